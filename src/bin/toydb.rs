@@ -4,6 +4,18 @@
 //! peers (default port 9705). The Raft log and SQL database are stored at
 //! data/raft and data/sql by default.
 //!
+//! A running server can be backed up via the toysql !backup command, and the
+//! backup restored with --restore DIR before the server starts.
+//!
+//! On SIGTERM or SIGINT, the server shuts down gracefully: it stops accepting
+//! requests, waits for in-flight requests, transfers Raft leadership to a peer,
+//! and flushes storage before exiting.
+//!
+//! Statements are traced via the tracing crate, with spans covering parsing,
+//! planning, execution, and Raft processing. Span durations can be logged via
+//! trace_spans, or exported to an OpenTelemetry collector via otlp_endpoint if
+//! built with the otlp feature.
+//!
 //! Use the toysql command-line client to connect to the server.
 
 #![warn(clippy::all)]
@@ -11,13 +23,16 @@
 use toydb::errinput;
 use toydb::error::Result;
 use toydb::raft;
-use toydb::sql;
-use toydb::storage;
+use toydb::server::{
+    AdmissionLimits, ChaosOptions, MaintenanceTask, ServerBuilder, TlsAcceptor, TlsConnector,
+};
+use toydb::storage::{self, Engine as _};
 use toydb::Server;
 
 use clap::Parser as _;
 use serde::Deserialize;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
 
 fn main() {
     if let Err(error) = Command::parse().run() {
@@ -33,27 +48,182 @@ struct Config {
     id: raft::NodeID,
     /// The other nodes in the cluster, and their Raft TCP addresses.
     peers: HashMap<raft::NodeID, String>,
+    /// The IDs of learners (non-voting nodes) in the cluster, possibly
+    /// including this node. Must be the same on all nodes.
+    learners: HashSet<raft::NodeID>,
     /// The Raft listen address.
     listen_raft: String,
+    /// The Raft peer transport: tcp or quic. QUIC requires the quic cargo
+    /// feature and TLS, and listens on a UDP address instead.
+    raft_transport: String,
     /// The SQL listen address.
     listen_sql: String,
+    /// The HTTP listen address. If empty, the HTTP interface is disabled.
+    listen_http: String,
+    /// The maximum number of committed but unapplied Raft log entries for the
+    /// HTTP readiness check to pass.
+    ready_max_apply_lag: raft::Index,
+    /// The Unix domain socket path to also listen for SQL clients on. If
+    /// empty, the socket is disabled.
+    listen_unix: String,
+    /// The Unix domain socket file permissions, as an octal mode.
+    listen_unix_mode: String,
     /// The log level.
     log_level: String,
+    /// If true, log the duration of traced spans (statements, Raft requests,
+    /// state machine applies, etc.) to stderr when they close.
+    trace_spans: bool,
+    /// The OpenTelemetry OTLP/HTTP endpoint to export traced spans to. If
+    /// empty, spans aren't exported. Requires the otlp build feature.
+    otlp_endpoint: String,
     /// The path to this node's data directory. The Raft log is stored in
     /// the file "raft", and the SQL state machine in "sql".
     data_dir: String,
-    /// The Raft storage engine: bitcask or memory.
+    /// The Raft storage engine: bitcask, lsm, or memory.
     storage_raft: String,
-    /// The SQL storage engine: bitcask or memory.
+    /// The SQL storage engine: bitcask, lsm, or memory.
     storage_sql: String,
-    /// If false, don't fsync Raft log writes to disk. Disabling this
-    /// will yield much better write performance, but may lose data on
-    /// host crashes which compromises Raft safety guarantees.
-    fsync: bool,
+    /// When to fsync Raft log writes to disk: always, interval(MS), or never
+    /// (true and false are accepted as always and never). Weaker policies
+    /// yield much better write performance, but may lose data on host crashes
+    /// which compromises Raft safety guarantees.
+    fsync: String,
     /// The garbage fraction threshold at which to trigger compaction.
     compact_threshold: f64,
     /// The minimum bytes of garbage before triggering compaction.
     compact_min_bytes: u64,
+    /// How often to check for compaction while running, in milliseconds. 0
+    /// only compacts on startup.
+    compact_interval_ms: u64,
+    /// The maximum size of BitCask log segment files, in bytes.
+    max_segment_size: u64,
+    /// The maximum estimated memory size of the SQL BitCask keydir, in bytes.
+    /// Writes that would add keys beyond it are rejected. 0 means unlimited.
+    max_keydir_size: u64,
+    /// The number of bytes to compact per step while running. 0 compacts
+    /// segments in one go.
+    compact_step_size: u64,
+    /// The base64-encoded 256-bit key to encrypt BitCask values with. If
+    /// empty, values aren't encrypted.
+    encryption_key: String,
+    /// Previous encryption keys, which can still decrypt values until they've
+    /// been re-encrypted with the current key during compaction.
+    encryption_old_keys: Vec<String>,
+    /// How often to emit changefeed changes to their sinks, in milliseconds.
+    /// 0 disables changefeed emission on this node.
+    changefeed_interval_ms: u64,
+    /// How often to purge expired rows from tables with a row TTL, in
+    /// milliseconds. 0 disables purging on this node.
+    ttl_interval_ms: u64,
+    /// If true, compress large Raft messages sent to peers that also have
+    /// compression enabled.
+    raft_compression: bool,
+    /// If true, compress large SQL responses to clients that request it.
+    sql_compression: bool,
+    /// The Raft tick interval in milliseconds. Other Raft timings are given
+    /// as a number of ticks.
+    raft_tick_interval_ms: u64,
+    /// The number of ticks between Raft leader heartbeats.
+    raft_heartbeat_interval: raft::Ticks,
+    /// The minimum number of ticks without hearing from a leader before a
+    /// follower calls an election.
+    raft_election_timeout: raft::Ticks,
+    /// The random jitter in ticks added to the election timeout, which avoids
+    /// repeated split votes when several nodes campaign at the same time.
+    raft_election_jitter: raft::Ticks,
+    /// The maximum number of Raft log entries to send in a single append
+    /// message.
+    raft_max_append_entries: usize,
+    /// The outbound Raft message queue capacity of each peer, and of the
+    /// inbound queue. Outbound messages are dropped when it's full (or half
+    /// full for heartbeats), and recovered via Raft retries.
+    raft_peer_channel_capacity: usize,
+    /// The maximum number of Raft messages and client requests to process
+    /// before syncing the Raft log to disk.
+    raft_group_commit_max_batch: usize,
+    /// The maximum rate of Raft log bytes sent to a lagging follower while
+    /// catching it up, in bytes per second. 0 means unlimited.
+    raft_catchup_bytes_per_sec: u64,
+    /// The maximum rate of Raft append messages sent to a lagging follower
+    /// while catching it up, in messages per second. 0 means unlimited.
+    raft_catchup_messages_per_sec: u64,
+    /// The maximum number of unacknowledged Raft append messages in flight to
+    /// a follower. Further log entries are batched until it acknowledges
+    /// earlier ones. 0 means unlimited.
+    raft_max_inflight_appends: usize,
+    /// The maximum number of unacknowledged Raft log bytes in flight to a
+    /// follower. 0 means unlimited.
+    raft_max_inflight_bytes: usize,
+    /// The maximum number of uncommitted Raft log entries on the leader,
+    /// beyond which writes are rejected as overloaded. 0 means unlimited.
+    raft_max_uncommitted_entries: usize,
+    /// The number of recent Raft events to retain for debugging, which can be
+    /// dumped via the toysql !trace command. 0 disables tracing.
+    raft_trace_capacity: usize,
+    /// The number of applied Raft log entries after which the log is
+    /// truncated, once the SQL state has been flushed to disk. 0 disables
+    /// truncation.
+    raft_snapshot_interval: raft::Index,
+    /// If true, Raft nodes hold a pre-vote before campaigning, which prevents
+    /// nodes rejoining after a partition from disrupting the leader.
+    raft_pre_vote: bool,
+    /// If true, a Raft leader that hasn't heard from a majority within an
+    /// election timeout steps down, aborting pending requests.
+    raft_check_quorum: bool,
+    /// If true, the Raft leader serves linearizable reads under a leader lease
+    /// without confirming them with a quorum. Requires bounded clock drift.
+    raft_lease_reads: bool,
+    /// If true, a single-node cluster executes requests directly instead of
+    /// going through the Raft message loop.
+    raft_fast_path: bool,
+    /// If true, read-only transactions are served by this node when it's a
+    /// follower, with bounded staleness.
+    replica_reads: bool,
+    /// The maximum number of ticks since the last leader heartbeat for a
+    /// follower to serve replica reads.
+    replica_read_staleness: raft::Ticks,
+    /// The TLS certificate chain PEM file. If set, SQL clients and Raft peers
+    /// must connect via TLS. Requires tls_key and tls_ca.
+    tls_cert: String,
+    /// The TLS private key PEM file.
+    tls_key: String,
+    /// The TLS CA certificate PEM file, used to verify Raft peers and, with
+    /// tls_client_auth, SQL clients.
+    tls_ca: String,
+    /// If true, SQL clients must present a TLS certificate signed by tls_ca.
+    /// Raft peers must always present one.
+    tls_client_auth: bool,
+    /// If true, SQL clients must authenticate as a user, once the first user
+    /// has been created.
+    auth: bool,
+    /// The maximum number of concurrent SQL and HTTP client connections. 0
+    /// means unlimited.
+    max_connections: usize,
+    /// The memory budget of each SQL statement in bytes, for rows buffered by
+    /// sorts, aggregates, and hash joins. 0 means unlimited.
+    session_memory_budget: usize,
+    /// The maximum number of cached query results, shared by all sessions. 0
+    /// disables the query cache.
+    query_cache_size: usize,
+    /// The maximum number of concurrently executing client requests. 0 means
+    /// unlimited.
+    max_concurrent_requests: usize,
+    /// The maximum number of client requests per second, across all clients
+    /// and per SQL connection respectively. 0 means unlimited.
+    max_qps: u32,
+    max_client_qps: u32,
+    /// How long a request can be queued for admission before it's rejected as
+    /// overloaded, in milliseconds.
+    admission_timeout_ms: u64,
+    /// How long to wait for in-flight requests to complete when shutting
+    /// down, in milliseconds.
+    drain_timeout_ms: u64,
+    /// The file to record client statements to as an operation history, for
+    /// consistency checking. If empty, no history is recorded.
+    history_file: String,
+    /// If set, injects network faults into outbound Raft messages, for
+    /// testing.
+    chaos: Option<ChaosOptions>,
 }
 
 impl Config {
@@ -63,13 +233,65 @@ impl Config {
             .set_default("id", "1")?
             .set_default("listen_sql", "localhost:9605")?
             .set_default("listen_raft", "localhost:9705")?
+            .set_default("raft_transport", "tcp")?
+            .set_default("listen_http", "")?
+            .set_default("ready_max_apply_lag", 1000)?
+            .set_default("listen_unix", "")?
+            .set_default("listen_unix_mode", "660")?
             .set_default("log_level", "info")?
+            .set_default("trace_spans", false)?
+            .set_default("otlp_endpoint", "")?
             .set_default("data_dir", "data")?
             .set_default("storage_raft", "bitcask")?
             .set_default("storage_sql", "bitcask")?
-            .set_default("fsync", true)?
+            .set_default("fsync", "always")?
             .set_default("compact_threshold", 0.2)?
             .set_default("compact_min_bytes", 1_000_000)?
+            .set_default("compact_interval_ms", 600_000)?
+            .set_default("max_segment_size", storage::BitCask::DEFAULT_MAX_SEGMENT_SIZE)?
+            .set_default("compact_step_size", 1_048_576)?
+            .set_default("max_keydir_size", 0)?
+            .set_default("encryption_key", "")?
+            .set_default("encryption_old_keys", Vec::<String>::new())?
+            .set_default("changefeed_interval_ms", 1000)?
+            .set_default("ttl_interval_ms", 60_000)?
+            .set_default("raft_compression", true)?
+            .set_default("sql_compression", true)?
+            .set_default("raft_tick_interval_ms", 100)?
+            .set_default("raft_heartbeat_interval", 4)?
+            .set_default("raft_election_timeout", 10)?
+            .set_default("raft_election_jitter", 10)?
+            .set_default("raft_max_append_entries", 100)?
+            .set_default("raft_peer_channel_capacity", 1000)?
+            .set_default("raft_group_commit_max_batch", 256)?
+            .set_default("raft_catchup_bytes_per_sec", 0)?
+            .set_default("raft_catchup_messages_per_sec", 0)?
+            .set_default("raft_max_inflight_appends", 64)?
+            .set_default("raft_max_inflight_bytes", 0)?
+            .set_default("raft_max_uncommitted_entries", 10000)?
+            .set_default("raft_trace_capacity", 1000)?
+            .set_default("raft_snapshot_interval", 10000)?
+            .set_default("learners", Vec::<raft::NodeID>::new())?
+            .set_default("raft_pre_vote", true)?
+            .set_default("raft_check_quorum", true)?
+            .set_default("raft_lease_reads", true)?
+            .set_default("raft_fast_path", true)?
+            .set_default("replica_reads", false)?
+            .set_default("replica_read_staleness", 8)?
+            .set_default("tls_cert", "")?
+            .set_default("tls_key", "")?
+            .set_default("tls_ca", "")?
+            .set_default("tls_client_auth", false)?
+            .set_default("auth", false)?
+            .set_default("max_connections", 0)?
+            .set_default("session_memory_budget", 0)?
+            .set_default("query_cache_size", 0)?
+            .set_default("max_concurrent_requests", 0)?
+            .set_default("max_qps", 0)?
+            .set_default("max_client_qps", 0)?
+            .set_default("admission_timeout_ms", 1000)?
+            .set_default("drain_timeout_ms", 10_000)?
+            .set_default("history_file", "")?
             .add_source(config::File::with_name(file))
             .add_source(config::Environment::with_prefix("TOYDB"))
             .build()?
@@ -84,6 +306,11 @@ struct Command {
     /// The configuration file path.
     #[arg(short = 'c', long, default_value = "config/toydb.yaml")]
     config: String,
+    /// Restores the Raft log and SQL storage from a backup directory written by
+    /// the toysql !backup command (the files raft and state), replacing any
+    /// existing data, before starting the server.
+    #[arg(long, value_name = "DIR")]
+    restore: Option<String>,
 }
 
 impl Command {
@@ -99,39 +326,341 @@ impl Command {
             logconfig.add_filter_allow_str("toydb");
         }
         simplelog::SimpleLogger::init(loglevel, logconfig.build())?;
+        Self::init_tracing(&cfg)?;
 
-        // Initialize the Raft log storage engine.
+        // Initialize the Raft log and SQL storage engines, optionally
+        // encrypting BitCask and LSM values.
+        type Encrypted = storage::Encrypted<storage::BitCask>;
+        let encryption = match cfg.encryption_key.as_str() {
+            "" if !cfg.encryption_old_keys.is_empty() => {
+                return errinput!("encryption_old_keys requires encryption_key")
+            }
+            "" => None,
+            key => Some((
+                Encrypted::parse_key(key)?,
+                cfg.encryption_old_keys
+                    .iter()
+                    .map(|k| Encrypted::parse_key(k))
+                    .collect::<Result<Vec<_>>>()?,
+            )),
+        };
         let datadir = std::path::Path::new(&cfg.data_dir);
-        let mut raft_log = match cfg.storage_raft.as_str() {
+        let durability: raft::Durability = cfg.fsync.parse()?;
+        let mut builder = ServerBuilder::new(cfg.id).peer_addrs(cfg.peers).durability(durability);
+        builder = match cfg.storage_raft.as_str() {
             "bitcask" | "" => {
-                let engine = storage::BitCask::new_compact(
+                let mut engine = storage::BitCask::new_compact(
                     datadir.join("raft"),
                     cfg.compact_threshold,
                     cfg.compact_min_bytes,
                 )?;
-                raft::Log::new(Box::new(engine))?
+                engine.set_max_segment_size(cfg.max_segment_size);
+                engine.set_compact_step_size(cfg.compact_step_size);
+                self.restore(&mut engine, "raft")?;
+                match &encryption {
+                    Some((key, old_keys)) => {
+                        builder.raft_storage(Encrypted::new(engine, *key, old_keys.clone())?)
+                    }
+                    None => builder.raft_storage(engine),
+                }
+            }
+            "lsm" => {
+                let mut engine = storage::Lsm::new(datadir.join("raft"))?;
+                engine.maybe_compact(cfg.compact_threshold, cfg.compact_min_bytes)?;
+                self.restore(&mut engine, "raft")?;
+                match &encryption {
+                    Some((key, old_keys)) => builder.raft_storage(storage::Encrypted::new(
+                        engine,
+                        *key,
+                        old_keys.clone(),
+                    )?),
+                    None => builder.raft_storage(engine),
+                }
+            }
+            "memory" => {
+                let mut engine = storage::Memory::new();
+                self.restore(&mut engine, "raft")?;
+                builder.raft_storage(engine)
             }
-            "memory" => raft::Log::new(Box::new(storage::Memory::new()))?,
             name => return errinput!("invalid Raft storage engine {name}"),
         };
-        raft_log.enable_fsync(cfg.fsync);
-
-        // Initialize the SQL storage engine.
-        let raft_state: Box<dyn raft::State> = match cfg.storage_sql.as_str() {
+        builder = match cfg.storage_sql.as_str() {
             "bitcask" | "" => {
-                let engine = storage::BitCask::new_compact(
+                let mut engine = storage::BitCask::new_compact(
                     datadir.join("sql"),
                     cfg.compact_threshold,
                     cfg.compact_min_bytes,
                 )?;
-                Box::new(sql::engine::Raft::new_state(engine)?)
+                engine.set_max_segment_size(cfg.max_segment_size);
+                engine.set_compact_step_size(cfg.compact_step_size);
+                engine.set_max_keydir_size(cfg.max_keydir_size);
+                self.restore(&mut engine, "state")?;
+                match &encryption {
+                    Some((key, old_keys)) => {
+                        builder.sql_storage(Encrypted::new(engine, *key, old_keys.clone())?)?
+                    }
+                    None => builder.sql_storage(engine)?,
+                }
+            }
+            "lsm" => {
+                let mut engine = storage::Lsm::new(datadir.join("sql"))?;
+                engine.maybe_compact(cfg.compact_threshold, cfg.compact_min_bytes)?;
+                self.restore(&mut engine, "state")?;
+                match &encryption {
+                    Some((key, old_keys)) => builder.sql_storage(storage::Encrypted::new(
+                        engine,
+                        *key,
+                        old_keys.clone(),
+                    )?)?,
+                    None => builder.sql_storage(engine)?,
+                }
+            }
+            "memory" => {
+                let mut engine = storage::Memory::new();
+                self.restore(&mut engine, "state")?;
+                builder.sql_storage(engine)?
             }
-            "memory" => Box::new(sql::engine::Raft::new_state(storage::Memory::new())?),
             name => return errinput!("invalid SQL storage engine {name}"),
         };
 
+        // Configure Raft timing. The election timeout is randomized in the
+        // range [timeout, timeout+jitter).
+        if cfg.raft_election_jitter == 0 {
+            return errinput!("Raft election jitter must be positive");
+        }
+        let Some(election_timeout_max) =
+            cfg.raft_election_timeout.checked_add(cfg.raft_election_jitter)
+        else {
+            return errinput!(
+                "Raft election timeout plus jitter exceeds {} ticks",
+                raft::Ticks::MAX
+            );
+        };
+        // Catch-up throttling is given per second, but Raft uses per-tick
+        // budgets. Round up to at least 1 per tick.
+        let per_tick = |per_sec: u64| match per_sec {
+            0 => None,
+            n => {
+                Some(std::cmp::max(1, n.saturating_mul(cfg.raft_tick_interval_ms) / 1000) as usize)
+            }
+        };
+        builder = builder.raft_options(raft::Options {
+            heartbeat_interval: cfg.raft_heartbeat_interval,
+            election_timeout_range: cfg.raft_election_timeout..election_timeout_max,
+            max_append_entries: cfg.raft_max_append_entries,
+            max_read_staleness: cfg.replica_read_staleness,
+            max_catchup_bytes: per_tick(cfg.raft_catchup_bytes_per_sec),
+            max_catchup_messages: per_tick(cfg.raft_catchup_messages_per_sec),
+            max_inflight_appends: Some(cfg.raft_max_inflight_appends).filter(|n| *n > 0),
+            max_inflight_bytes: Some(cfg.raft_max_inflight_bytes).filter(|n| *n > 0),
+            max_uncommitted_entries: Some(cfg.raft_max_uncommitted_entries).filter(|n| *n > 0),
+            trace_capacity: cfg.raft_trace_capacity,
+            // An in-memory SQL state is lost on restart, and must be rebuilt
+            // from the full log.
+            snapshot_interval: Some(cfg.raft_snapshot_interval)
+                .filter(|n| *n > 0 && cfg.storage_sql != "memory"),
+            pre_vote: cfg.raft_pre_vote,
+            check_quorum: cfg.raft_check_quorum,
+            learners: cfg.learners,
+            // The lease must expire before followers can time out and elect a
+            // new leader, so leave a heartbeat interval of margin.
+            lease_duration: Some(
+                cfg.raft_election_timeout.saturating_sub(cfg.raft_heartbeat_interval),
+            )
+            .filter(|n| cfg.raft_lease_reads && *n > 0),
+            ..raft::Options::default()
+        });
+
         // Start the server.
-        Server::new(cfg.id, cfg.peers, raft_log, raft_state)?
-            .serve(&cfg.listen_raft, &cfg.listen_sql)
+        let mut server = builder.build()?;
+        server.enable_raft_compression(cfg.raft_compression);
+        match cfg.raft_transport.as_str() {
+            "tcp" | "" => {}
+            #[cfg(feature = "quic")]
+            "quic" => server.enable_raft_quic(true),
+            #[cfg(not(feature = "quic"))]
+            "quic" => return errinput!("raft_transport quic requires the quic feature"),
+            name => return errinput!("invalid Raft transport {name}"),
+        }
+        server.enable_sql_compression(cfg.sql_compression);
+        server.enable_raft_fast_path(cfg.raft_fast_path);
+        server.enable_replica_reads(cfg.replica_reads);
+        server.enable_auth(cfg.auth);
+        server.set_max_connections(Some(cfg.max_connections).filter(|&max| max > 0));
+        server.set_session_memory_budget(Some(cfg.session_memory_budget).filter(|&b| b > 0));
+        server.set_query_cache(Some(cfg.query_cache_size).filter(|&size| size > 0));
+        server.set_admission_limits(AdmissionLimits {
+            max_concurrent: Some(cfg.max_concurrent_requests).filter(|&max| max > 0),
+            max_qps: Some(cfg.max_qps).filter(|&max| max > 0),
+            max_client_qps: Some(cfg.max_client_qps).filter(|&max| max > 0),
+            queue_timeout: std::time::Duration::from_millis(cfg.admission_timeout_ms),
+        });
+        server.set_drain_timeout(std::time::Duration::from_millis(cfg.drain_timeout_ms));
+        if !cfg.history_file.is_empty() {
+            server.enable_history(&cfg.history_file)?;
+        }
+        server
+            .set_raft_tick_interval(std::time::Duration::from_millis(cfg.raft_tick_interval_ms))?;
+        server.set_raft_peer_channel_capacity(cfg.raft_peer_channel_capacity)?;
+        server.set_raft_group_commit_max_batch(cfg.raft_group_commit_max_batch)?;
+        if !cfg.tls_cert.is_empty() {
+            if cfg.tls_key.is_empty() || cfg.tls_ca.is_empty() {
+                return errinput!("tls_cert requires tls_key and tls_ca");
+            }
+            let (cert, key, ca) =
+                (Path::new(&cfg.tls_cert), Path::new(&cfg.tls_key), Path::new(&cfg.tls_ca));
+            let client_ca = cfg.tls_client_auth.then_some(ca);
+            let acceptor = TlsAcceptor::new(cert, key, client_ca)?;
+            let connector = TlsConnector::new(ca, Some((cert, key)))?;
+            server.enable_tls(acceptor, connector);
+        }
+        if !cfg.listen_http.is_empty() {
+            server.enable_http(&cfg.listen_http)?;
+        }
+        if let Some(chaos) = cfg.chaos {
+            server.enable_raft_chaos(chaos)?;
+        }
+        server.set_ready_max_apply_lag(cfg.ready_max_apply_lag);
+        if cfg.compact_interval_ms > 0 {
+            server.add_maintenance_task(MaintenanceTask::compact(
+                std::time::Duration::from_millis(cfg.compact_interval_ms),
+                cfg.compact_threshold,
+                cfg.compact_min_bytes,
+            )?);
+        }
+        if cfg.changefeed_interval_ms > 0 {
+            server.add_maintenance_task(MaintenanceTask::changefeeds(
+                std::time::Duration::from_millis(cfg.changefeed_interval_ms),
+            )?);
+        }
+        if cfg.ttl_interval_ms > 0 {
+            server.add_maintenance_task(MaintenanceTask::ttl(std::time::Duration::from_millis(
+                cfg.ttl_interval_ms,
+            ))?);
+        }
+        if !cfg.listen_unix.is_empty() {
+            let Ok(mode) = u32::from_str_radix(&cfg.listen_unix_mode, 8) else {
+                return errinput!("invalid Unix socket mode {}", cfg.listen_unix_mode);
+            };
+            server.enable_unix_socket(&cfg.listen_unix, mode)?;
+        }
+        Self::shutdown_on_signal(&server)?;
+        server.serve(&cfg.listen_raft, &cfg.listen_sql)
     }
+
+    /// Restores a storage engine from the given backup file in the --restore
+    /// directory, if any, see storage::Engine::restore().
+    fn restore(&self, engine: &mut dyn storage::Engine, name: &str) -> Result<()> {
+        let Some(dir) = &self.restore else {
+            return Ok(());
+        };
+        engine.restore(&Path::new(dir).join(name))
+    }
+
+    /// Initializes tracing of statement spans, see the module documentation.
+    fn init_tracing(cfg: &Config) -> Result<()> {
+        use tracing_subscriber::filter::LevelFilter;
+        use tracing_subscriber::fmt::format::FmtSpan;
+        use tracing_subscriber::layer::SubscriberExt as _;
+        use tracing_subscriber::Layer as _;
+
+        let spans = cfg.trace_spans.then(|| {
+            tracing_subscriber::fmt::layer()
+                .with_writer(std::io::stderr)
+                .with_span_events(FmtSpan::CLOSE)
+                .with_filter(LevelFilter::INFO)
+        });
+        let otlp = match cfg.otlp_endpoint.as_str() {
+            "" => None,
+            endpoint => Some(otlp::layer(endpoint)?),
+        };
+        tracing::subscriber::set_global_default(
+            tracing_subscriber::registry().with(spans).with(otlp),
+        )?;
+        Ok(())
+    }
+
+    /// Shuts down the server gracefully and exits on SIGTERM or SIGINT.
+    fn shutdown_on_signal(server: &Server) -> Result<()> {
+        use signal_hook::consts::{SIGINT, SIGTERM};
+        let shutdown = server.shutdown_handle();
+        let mut signals = signal_hook::iterator::Signals::new([SIGTERM, SIGINT])?;
+        std::thread::spawn(move || {
+            if signals.forever().next().is_some() {
+                let result = shutdown.shutdown();
+                otlp::flush();
+                if let Err(error) = result {
+                    eprintln!("Error: shutdown failed: {error}");
+                    std::process::exit(1);
+                }
+                std::process::exit(0);
+            }
+        });
+        Ok(())
+    }
+}
+
+/// OpenTelemetry span export via OTLP/HTTP.
+#[cfg(feature = "otlp")]
+mod otlp {
+    use toydb::errinput;
+    use toydb::error::Result;
+
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_otlp::WithExportConfig as _;
+    use opentelemetry_sdk::trace::SdkTracerProvider;
+    use std::sync::OnceLock;
+    use tracing::Subscriber;
+    use tracing_subscriber::registry::LookupSpan;
+    use tracing_subscriber::Layer;
+
+    /// The tracer provider, which batches spans in a background thread.
+    static PROVIDER: OnceLock<SdkTracerProvider> = OnceLock::new();
+
+    /// Creates a tracing layer which exports spans to the given endpoint.
+    pub fn layer<S>(endpoint: &str) -> Result<impl Layer<S>>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        let exporter = match opentelemetry_otlp::SpanExporter::builder()
+            .with_http()
+            .with_endpoint(endpoint)
+            .build()
+        {
+            Ok(exporter) => exporter,
+            Err(err) => return errinput!("invalid OTLP endpoint {endpoint}: {err}"),
+        };
+        let resource = opentelemetry_sdk::Resource::builder().with_service_name("toydb").build();
+        let provider = SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(resource)
+            .build();
+        let tracer = provider.tracer("toydb");
+        PROVIDER.set(provider).ok();
+        Ok(tracing_opentelemetry::layer().with_tracer(tracer))
+    }
+
+    /// Flushes pending spans to the endpoint.
+    pub fn flush() {
+        if let Some(provider) = PROVIDER.get() {
+            provider.shutdown().ok();
+        }
+    }
+}
+
+/// Stub for builds without the otlp feature.
+#[cfg(not(feature = "otlp"))]
+mod otlp {
+    use toydb::errinput;
+    use toydb::error::Result;
+
+    use tracing_subscriber::layer::Identity;
+
+    /// Errors, since OTLP export requires the otlp feature.
+    pub fn layer(_: &str) -> Result<Identity> {
+        errinput!("otlp_endpoint requires toydb to be built with the otlp feature")
+    }
+
+    /// Does nothing.
+    pub fn flush() {}
 }
@@ -1,11 +1,29 @@
 //! toydump is a debug tool that prints a toyDB BitCask database in
 //! human-readable form. It can print both the SQL database and the Raft log
 //! (via --raft). It only outputs live BitCask data, not garbage entries.
+//!
+//! With --key, it instead decodes a single hex-encoded storage key, e.g. from
+//! a log message or raw dump, into its SQL meaning (or Raft meaning with
+//! --raft). For example:
+//!
+//! toydump --key 04027400ff00ff028000ff00ff00ff00ff00ff00ff0100000000000000000007
+//! mvcc:Version(sql:Row(t, 1), 7)
+//!
+//! With --sql, it instead dumps the latest committed contents of the SQL
+//! database as a SQL script of CREATE TABLE and INSERT statements. This can be
+//! loaded into a new database with toysql, e.g. to migrate data between toyDB
+//! versions or recover it from a node's data directory:
+//!
+//! toydump --sql data/sql/db > dump.sql
+//! toysql -i dump.sql
 
 #![warn(clippy::all)]
 
 use toydb::encoding::format::{self, Formatter as _};
+use toydb::encoding::keycode;
+use toydb::errinput;
 use toydb::error::Result;
+use toydb::sql::engine::{self, Engine as _, Local};
 use toydb::storage::{BitCask, Engine as _};
 
 use clap::Parser as _;
@@ -21,19 +39,39 @@ fn main() {
 #[command(about = "Prints toyDB file contents.", version, propagate_version = true)]
 struct Command {
     /// The BitCask file to dump (SQL database unless --raft).
-    file: String,
+    #[arg(required_unless_present = "key")]
+    file: Option<String>,
+    /// Decode a hex-encoded key instead of dumping a file.
+    #[arg(long, conflicts_with = "file")]
+    key: Option<String>,
     /// The file is a Raft log, not SQL database.
     #[arg(long)]
     raft: bool,
     /// Also show raw key and value.
     #[arg(long)]
     raw: bool,
+    /// Dump the SQL database as a SQL script, which can be loaded via toysql.
+    #[arg(long, conflicts_with_all = ["key", "raft", "raw"])]
+    sql: bool,
 }
 
 impl Command {
     /// Runs the command.
     fn run(self) -> Result<()> {
-        let mut engine = BitCask::new(self.file.into())?;
+        if let Some(key) = &self.key {
+            let key = decode_hex(key)?;
+            match self.raft {
+                true => println!("{}", format::Raft::<format::SQLCommand>::key(&key)),
+                false => println!("{}", keycode::describe(&key)),
+            }
+            return Ok(());
+        }
+        let file = self.file.expect("no file given");
+        let mut engine = BitCask::new(file.into())?;
+        if self.sql {
+            let local = Local::new(engine);
+            return engine::dump(&local.begin_read_only()?, &mut std::io::stdout().lock());
+        }
         let mut scan = engine.scan(..);
         while let Some((key, value)) = scan.next().transpose()? {
             let mut string = match self.raft {
@@ -48,3 +86,20 @@ impl Command {
         Ok(())
     }
 }
+
+/// Decodes a hex string, ignoring any whitespace.
+fn decode_hex(hex: &str) -> Result<Vec<u8>> {
+    let digits: Vec<u8> = hex.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    let pairs = digits.chunks_exact(2);
+    if !pairs.remainder().is_empty() {
+        return errinput!("hex key must have an even number of digits");
+    }
+    pairs
+        .map(|pair| {
+            std::str::from_utf8(pair)
+                .ok()
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .map_or_else(|| errinput!("invalid hex key {hex}"), Ok)
+        })
+        .collect()
+}
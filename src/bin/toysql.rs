@@ -1,26 +1,34 @@
 //! toySQL is a command-line client for toyDB. It connects to a toyDB node
-//! (default localhost:9605) and executes SQL statements against it via an
-//! interactive shell interface. Command history is stored in .toysql.history.
+//! (default localhost:9605), or a local Unix domain socket, and executes SQL
+//! statements against it via an interactive shell interface. Command history is
+//! stored in .toysql.history, and can be searched with Ctrl-R. Tab completes
+//! keywords, commands, and table and column names.
 
 #![warn(clippy::all)]
 
 use toydb::errinput;
-use toydb::error::Result;
+use toydb::error::{Error, Result, SyntaxError};
+use toydb::raft;
 use toydb::sql::engine::StatementResult;
-use toydb::sql::parser::{Lexer, Token};
-use toydb::Client;
+use toydb::sql::parser::{Keyword, Lexer, Token};
+use toydb::sql::types::{DataType, Label, Row, Value};
+use toydb::{Client, ConnectOptions, ConnectUrl};
 
 use clap::Parser as _;
 use itertools::Itertools as _;
+use rustyline::completion::Completer;
 use rustyline::error::ReadlineError;
 use rustyline::history::DefaultHistory;
 use rustyline::validate::{ValidationContext, ValidationResult, Validator};
 use rustyline::{Editor, Modifiers};
-use rustyline_derive::{Completer, Helper, Highlighter, Hinter};
+use rustyline_derive::{Helper, Highlighter, Hinter};
+use std::collections::BTreeMap;
+use std::io::Write as _;
 
 fn main() {
     if let Err(error) = Command::parse().run() {
-        eprintln!("Error: {error}");
+        print_error(&error);
+        std::process::exit(1);
     }
 }
 
@@ -28,26 +36,147 @@ fn main() {
 #[derive(clap::Parser)]
 #[command(about = "A toyDB client.", version, propagate_version = true)]
 struct Command {
-    /// A SQL statement to execute, then exit.
+    /// A SQL statement or ! command to execute, then exit.
     #[arg()]
     statement: Option<String>,
-    /// Host to connect to.
-    #[arg(short = 'H', long, default_value = "localhost")]
-    host: String,
-    /// Port number to connect to.
-    #[arg(short = 'p', long, default_value = "9605")]
-    port: u16,
+    /// A SQL statement or ! command to execute, then exit. Same as the
+    /// statement argument.
+    #[arg(short = 'e', long, conflicts_with = "statement")]
+    execute: Option<String>,
+    /// A script file of SQL statements and ! commands to execute, then exit.
+    /// Stops at the first error.
+    #[arg(short = 'i', long, conflicts_with_all = ["statement", "execute"])]
+    file: Option<std::path::PathBuf>,
+    /// Sets a variable as NAME=VALUE, for substitution in SQL statements (see
+    /// !set). Can be given multiple times.
+    #[arg(short = 'v', long = "set", value_name = "NAME=VALUE")]
+    variables: Vec<String>,
+    /// Connection URL, as toydb://[USER[:PASSWORD]@]HOST[:PORT][,...][?OPTIONS]
+    /// with options tls_ca, tls_cert, tls_key, and compression. Defaults to the TOYDB_URL
+    /// environment variable, if set. Can't be combined with other connection
+    /// options.
+    #[arg(long, conflicts_with_all = ["host", "port", "socket", "tls_ca", "tls_cert", "tls_key", "user"])]
+    url: Option<String>,
+    /// Host to connect to. Multiple comma-separated hosts can be given, e.g.
+    /// all cluster nodes, optionally as host:port, in which case the client
+    /// fails over to another host if the current one fails. [default:
+    /// localhost]
+    #[arg(short = 'H', long)]
+    host: Option<String>,
+    /// Port number to connect to, unless given with the host. [default: 9605]
+    #[arg(short = 'p', long)]
+    port: Option<u16>,
+    /// Connect via the Unix domain socket at this path instead of TCP.
+    #[arg(short = 'S', long, conflicts_with = "tls_ca")]
+    socket: Option<std::path::PathBuf>,
+    /// Connect via TLS, trusting server certificates signed by the CA
+    /// certificate(s) in this PEM file.
+    #[arg(long)]
+    tls_ca: Option<std::path::PathBuf>,
+    /// Client certificate PEM file, for servers that require TLS client
+    /// authentication. Requires --tls-ca and --tls-key.
+    #[arg(long, requires_all = ["tls_ca", "tls_key"])]
+    tls_cert: Option<std::path::PathBuf>,
+    /// Client private key PEM file. Requires --tls-cert.
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<std::path::PathBuf>,
+    /// User to authenticate as. The password is read from the TOYSQL_PASSWORD
+    /// environment variable if set, otherwise prompted for.
+    #[arg(short = 'u', long)]
+    user: Option<String>,
+    /// Request compression of large responses, to reduce network bandwidth
+    /// for wide rows and large result sets.
+    #[arg(long)]
+    compression: bool,
+    /// Output format for query results.
+    #[arg(short = 'f', long, value_enum, default_value_t = Format::Text)]
+    format: Format,
 }
 
 impl Command {
     /// Runs the command.
-    fn run(self) -> Result<()> {
-        let mut shell = Shell::new(&self.host, self.port)?;
-        match self.statement {
-            Some(statement) => shell.execute(&statement),
-            None => shell.run(),
+    fn run(mut self) -> Result<()> {
+        // Use the connection URL, if any, from --url or TOYDB_URL. The latter
+        // is ignored if other connection options are given.
+        let has_options = self.host.is_some()
+            || self.port.is_some()
+            || self.socket.is_some()
+            || self.tls_ca.is_some()
+            || self.user.is_some();
+        let url = match self.url.take() {
+            Some(url) => Some(url),
+            None if !has_options => std::env::var("TOYDB_URL").ok(),
+            None => None,
+        };
+        let mut password = None;
+        if let Some(url) = url {
+            let url = ConnectUrl::parse(&url)?;
+            self.host = Some(url.hosts.join(","));
+            (self.tls_ca, self.tls_cert, self.tls_key) = (url.tls_ca, url.tls_cert, url.tls_key);
+            (self.user, password) = (url.user, url.password);
+            self.compression |= url.compression;
+        }
+
+        let credentials = match &self.user {
+            Some(user) => {
+                let password = match password.or_else(|| std::env::var("TOYSQL_PASSWORD").ok()) {
+                    Some(password) => password,
+                    None => rpassword::prompt_password(format!("Password for {user}: "))?,
+                };
+                Some((user, password))
+            }
+            None => None,
+        };
+        let client = match &self.socket {
+            Some(socket) => {
+                let mut client = Client::connect_unix(socket)?;
+                if let Some((user, password)) = &credentials {
+                    client.authenticate(user, password)?;
+                }
+                client
+            }
+            None => {
+                let mut opts = ConnectOptions::new().compression(self.compression);
+                if let Some(ca) = &self.tls_ca {
+                    let identity = self.tls_cert.as_deref().zip(self.tls_key.as_deref());
+                    opts = opts.tls(ca, identity)?;
+                }
+                if let Some((user, password)) = &credentials {
+                    opts = opts.credentials(user, password);
+                }
+                Client::connect_with(self.addrs(), &opts)?
+            }
+        };
+        let mut shell = Shell::new(client, self.format)?;
+        for variable in &self.variables {
+            let Some((name, value)) = variable.split_once('=') else {
+                return errinput!("invalid variable {variable}, must be NAME=VALUE");
+            };
+            shell.set_variable(name, value)?;
+        }
+        shell.cancel_on_interrupt()?;
+        match (self.statement.or(self.execute), self.file) {
+            (Some(statement), _) => shell.execute(&statement),
+            (None, Some(file)) => shell.execute_file(&file),
+            (None, None) => shell.run(),
         }
     }
+
+    /// Returns the TCP addresses to connect to, as host:port.
+    fn addrs(&self) -> Vec<String> {
+        let with_port =
+            |host: &str| host.rsplit_once(':').is_some_and(|(_, p)| p.parse::<u16>().is_ok());
+        let port = self.port.unwrap_or(9605);
+        self.host
+            .as_deref()
+            .unwrap_or("localhost")
+            .split(',')
+            .map(|host| match with_port(host) {
+                true => host.to_string(),
+                false => format!("{host}:{port}"),
+            })
+            .collect()
+    }
 }
 
 /// An interactive toySQL shell.
@@ -55,27 +184,64 @@ struct Shell {
     /// The toyDB client.
     client: Client,
     /// The Rustyline command editor.
-    editor: Editor<InputValidator, DefaultHistory>,
+    editor: Editor<InputHelper, DefaultHistory>,
     /// The path to the history file, if any.
     history_path: Option<std::path::PathBuf>,
-    /// If true, SELECT column headers will be displayed.
+    /// If true, SELECT column headers will be displayed in text format.
     show_headers: bool,
+    /// The output format for query results.
+    format: Format,
+    /// If true, the execution time of each statement will be displayed.
+    show_timing: bool,
+    /// Variables for substitution in SQL statements.
+    variables: BTreeMap<String, String>,
 }
 
 impl Shell {
-    /// Creates a new shell connected to the given server.
-    fn new(host: &str, port: u16) -> Result<Self> {
-        let client = Client::connect((host, port))?;
+    /// Creates a new shell using the given client and output format.
+    fn new(client: Client, format: Format) -> Result<Self> {
         // Set up Rustyline. Make sure multiline pastes are handled normally.
-        let mut editor = Editor::new()?;
-        editor.set_helper(Some(InputValidator));
+        let config = rustyline::Config::builder()
+            .history_ignore_dups(true)?
+            .history_ignore_space(true)
+            .max_history_size(10_000)?
+            .completion_type(rustyline::CompletionType::List)
+            .build();
+        let mut editor = Editor::with_config(config)?;
+        editor.set_helper(Some(InputHelper::default()));
         editor.bind_sequence(
             rustyline::KeyEvent(rustyline::KeyCode::BracketedPasteStart, Modifiers::NONE),
             rustyline::Cmd::Noop,
         );
         let history_path = std::env::var_os("HOME")
             .map(|home| std::path::PathBuf::from(home).join(".toysql.history"));
-        Ok(Self { client, editor, history_path, show_headers: false })
+        Ok(Self {
+            client,
+            editor,
+            history_path,
+            show_headers: false,
+            format,
+            show_timing: false,
+            variables: BTreeMap::new(),
+        })
+    }
+
+    /// Cancels the executing statement when the user presses Ctrl-C. While
+    /// Rustyline reads input, the terminal is in raw mode and Ctrl-C is handled
+    /// as a key press instead. If the cancel request fails (e.g. because the
+    /// server is unavailable) the shell exits, so Ctrl-C can't get stuck.
+    fn cancel_on_interrupt(&mut self) -> Result<()> {
+        let canceller = self.client.canceller()?;
+        let mut signals = signal_hook::iterator::Signals::new([signal_hook::consts::SIGINT])?;
+        std::thread::spawn(move || {
+            for _ in signals.forever() {
+                if let Err(error) = canceller.cancel() {
+                    eprintln!("Error: {error}");
+                    std::process::exit(130);
+                }
+            }
+        });
+        Ok(())
     }
 
     /// Executes a SQL statement or ! command.
@@ -91,6 +257,12 @@ impl Shell {
 
     /// Executes a toySQL ! command (e.g. !help)
     fn execute_command(&mut self, input: &str) -> Result<()> {
+        // !copy takes a query argument, which can't be split on whitespace.
+        if let Some(args) = input.strip_prefix("!copy") {
+            if args.is_empty() || args.starts_with(char::is_whitespace) {
+                return self.copy(args.trim());
+            }
+        }
         let mut input = input.split_ascii_whitespace();
         let Some(command) = input.next() else {
             return errinput!("expected command");
@@ -98,6 +270,53 @@ impl Shell {
         let args = input.collect_vec();
 
         match (command, args.as_slice()) {
+            // Backs up the server's storage.
+            ("!backup", [path]) => {
+                let applied_index = self.client.backup(path)?;
+                println!("Backed up to {path} at applied index {applied_index}")
+            }
+            ("!backup", _) => return errinput!("!backup takes 1 argument"),
+
+            // Describes a table's columns and indexes.
+            ("!d", [name]) => {
+                let name = name.replace('\'', "''");
+                let (columns, rows) = self.query(&format!(
+                    "SELECT column_name, data_type, is_nullable, column_default, is_primary_key
+                     FROM information_schema.columns WHERE table_name = '{name}'
+                     ORDER BY ordinal_position"
+                ))?;
+                if rows.is_empty() {
+                    return errinput!("table {} does not exist", args[0]);
+                }
+                self.print_rows(columns, rows);
+                let (columns, rows) = self.query(&format!(
+                    "SELECT index_name, column_name, is_primary, is_unique
+                     FROM information_schema.indexes WHERE table_name = '{name}'
+                     ORDER BY is_primary DESC, index_name"
+                ))?;
+                self.print_rows(columns, rows);
+            }
+            ("!d", _) => return errinput!("!d takes 1 argument"),
+
+            // Lists tables.
+            ("!dt", []) => {
+                let (columns, rows) = self.query(
+                    "SELECT table_name, column_count FROM information_schema.tables
+                     ORDER BY table_name",
+                )?;
+                self.print_rows(columns, rows);
+            }
+            ("!dt", _) => return errinput!("!dt takes no arguments"),
+
+            // Displays or sets the output format.
+            ("!format", []) => println!("Format is {}", self.format),
+            ("!format", [format]) => {
+                self.format = clap::ValueEnum::from_str(format, true)
+                    .or_else(|_| errinput!("unknown format {format}"))?;
+                println!("Format set to {}", self.format);
+            }
+            ("!format", _) => return errinput!("!format takes 0 or 1 arguments"),
+
             // Toggles column headers.
             ("!headers", []) => {
                 self.show_headers = !self.show_headers;
@@ -112,13 +331,35 @@ impl Shell {
             ("!help", []) => println!(
                 r#"
 Enter a SQL statement terminated by a semicolon (;) to execute it, or Ctrl-D to
-exit. The following commands are also available:
+exit. Statements can span multiple lines. Press Ctrl-C to cancel a running
+statement, Ctrl-R to search the command history, and Tab to complete keywords,
+commands, and table and column names. The following commands are also
+available:
 
-    !headers           Toggles column headers
+    !backup DIR        Back up the server's storage to a directory on the server
+    !copy TABLE FROM 'FILE'         Import CSV rows into a table
+    !copy TABLE|(QUERY) TO 'FILE'   Export a table or query as CSV
+    !d NAME            Describe a table's columns and indexes
+    !dt                List tables and their column counts
+    !format [FORMAT]   Displays or sets the output format: text, table, csv, json
+    !headers           Toggles column headers in text format
     !help              This help message
+    !i FILE            Execute SQL statements and commands from a script file
+    !metrics           Display the server's Raft replication, election, and queue stats
+    !set [NAME VALUE]  Lists variables, or sets a variable: :NAME in SQL
+                       statements is replaced by VALUE, and :'NAME' by VALUE as
+                       a quoted string
+
     !status            Display server status
     !table NAME        Display a table schema
     !tables            List tables
+    !timing            Toggles display of statement execution times
+    !unset NAME        Unsets a variable
+    !trace             Display the server's recent Raft events
+    !transfer [NODE]   Transfer Raft leadership away from the server, to the
+                       given node ID or the most up-to-date node
+    !verify [state]    Verify Raft log consistency across nodes, or with
+                       state, SQL state machine consistency
 "#
             ),
             ("!help", _) => return errinput!("!help takes no arguments"),
@@ -128,13 +369,14 @@ exit. The following commands are also available:
                 let status = self.client.status()?;
                 println!(
                     r#"
-Server:       n{server} with Raft leader n{leader} in term {term} for {nodes} nodes
+Server:       n{server} ({role}) with Raft leader n{leader} in term {term} for {nodes} nodes
 Raft log:     {committed} committed, {applied} applied, {raft_size} MB, {raft_garbage}% garbage ({raft_storage} engine)
 Replication:  {raft_match}
 SQL storage:  {sql_keys} keys, {sql_size} MB logical, {nodes}x {sql_disk_size} MB disk, {sql_garbage}% garbage ({sql_storage} engine)
 Transactions: {active_txns} active, {versions} total
 "#,
                     server = status.server,
+                    role = status.role,
                     leader = status.raft.leader,
                     term = status.raft.term,
                     nodes = status.raft.match_index.len(),
@@ -165,6 +407,135 @@ Transactions: {active_txns} active, {versions} total
             ("!tables", []) => self.client.list_tables()?.iter().for_each(|t| println!("{t}")),
             ("!tables", _) => return errinput!("!tables takes no arguments"),
 
+            // Executes a script file.
+            ("!i", [path]) => self.execute_file(std::path::Path::new(path))?,
+            ("!i", _) => return errinput!("!i takes 1 argument"),
+
+            // Displays the server's Raft node metrics.
+            ("!metrics", []) => {
+                let metrics = self.client.metrics()?;
+                let leader = metrics.leader.map(|id| format!("n{id}")).unwrap_or("none".into());
+                println!(
+                    "Node:      n{} ({}) with leader {leader} in term {}",
+                    metrics.id, metrics.role, metrics.term
+                );
+                println!(
+                    "Raft log:  {} last, {} committed, {} applied",
+                    metrics.last_index, metrics.commit_index, metrics.applied_index
+                );
+                for (id, pr) in &metrics.progress {
+                    println!(
+                        "Peer n{id}:   {} matched, {} next, {} behind, {} appends ({} bytes) in flight",
+                        pr.match_index,
+                        pr.next_index,
+                        pr.lag(metrics.last_index),
+                        pr.inflight_appends,
+                        pr.inflight_bytes
+                    );
+                }
+                let counters = &metrics.counters;
+                println!(
+                    "Elections: {} pre-votes, {} campaigns, {} won, {} leader changes",
+                    counters.pre_votes,
+                    counters.elections,
+                    counters.elections_won,
+                    counters.leader_changes
+                );
+                let format_counts = |counts: &BTreeMap<String, u64>| {
+                    counts.iter().map(|(kind, count)| format!("{kind}={count}")).join(" ")
+                };
+                println!("Sent:      {}", format_counts(&counters.messages_sent));
+                println!("Received:  {}", format_counts(&counters.messages_received));
+                for (name, queue) in &metrics.queues {
+                    let capacity = queue.capacity.map(|c| c.to_string()).unwrap_or("∞".into());
+                    println!(
+                        "Queue {name}: {} of {capacity} queued, {} dropped",
+                        queue.depth, queue.dropped
+                    );
+                }
+            }
+            ("!metrics", _) => return errinput!("!metrics takes no arguments"),
+
+            // Lists or sets variables.
+            ("!set", []) => {
+                for (name, value) in &self.variables {
+                    println!("{name} = {value}");
+                }
+            }
+            ("!set", [name, value @ ..]) => self.set_variable(name, &value.join(" "))?,
+
+            // Unsets a variable.
+            ("!unset", [name]) => {
+                self.variables.remove(*name);
+            }
+            ("!unset", _) => return errinput!("!unset takes 1 argument"),
+
+            // Toggles statement timing.
+            ("!timing", []) => {
+                self.show_timing = !self.show_timing;
+                match self.show_timing {
+                    true => println!("Timing enabled"),
+                    false => println!("Timing disabled"),
+                }
+            }
+            ("!timing", _) => return errinput!("!timing takes no arguments"),
+
+            // Displays the server's Raft event trace.
+            ("!trace", []) => self.client.trace()?.iter().for_each(|e| println!("{e}")),
+            ("!trace", _) => return errinput!("!trace takes no arguments"),
+
+            // Transfers Raft leadership away from the server.
+            ("!transfer", args @ ([] | [_])) => {
+                let to = match args.first() {
+                    Some(id) => Some(id.parse().or_else(|_| errinput!("invalid node ID {id}"))?),
+                    None => None,
+                };
+                match self.client.transfer_leadership(to)? {
+                    Some(leader) => println!("Transferred leadership to n{leader}"),
+                    None => println!("Server is not the leader"),
+                }
+            }
+            ("!transfer", _) => return errinput!("!transfer takes 0 or 1 arguments"),
+
+            // Verifies Raft log consistency across nodes.
+            ("!verify", []) => {
+                let report = self.client.verify()?;
+                println!(
+                    "Verified Raft log with leader n{} in term {} at commit index {}",
+                    report.leader, report.term, report.commit_index
+                );
+                for (id, node) in &report.nodes {
+                    match node {
+                        raft::NodeReport::Consistent { index } => {
+                            println!("n{id}: consistent up to index {index}")
+                        }
+                        raft::NodeReport::Divergent { index, divergence } => println!(
+                            "n{id}: divergent at index {} ({}), compared up to index {index}",
+                            divergence.index, divergence.reason
+                        ),
+                        raft::NodeReport::Failed(err) => println!("n{id}: failed: {err}"),
+                    }
+                }
+            }
+            // Verifies SQL state machine consistency across nodes.
+            ("!verify", ["state"]) => {
+                let report = self.client.verify_state()?;
+                println!(
+                    "Verified {} state partitions with leader n{} in term {} at applied index {}",
+                    report.partitions, report.leader, report.term, report.applied_index
+                );
+                for (id, node) in &report.nodes {
+                    match node {
+                        raft::StateNodeReport::Consistent => println!("n{id}: consistent"),
+                        raft::StateNodeReport::Divergent { partitions } => {
+                            println!("n{id}: divergent in {}", partitions.join(", "))
+                        }
+                        raft::StateNodeReport::Failed(err) => println!("n{id}: failed: {err}"),
+                    }
+                }
+            }
+            ("!verify", _) => return errinput!("usage: !verify [state]"),
+
             (command, _) => return errinput!("unknown command {command}"),
         }
         Ok(())
@@ -173,23 +544,329 @@ Transactions: {active_txns} active, {versions} total
     /// Executes a SQL statement and displays the results.
     fn execute_sql(&mut self, statement: &str) -> Result<()> {
         use StatementResult::*;
-        match self.client.execute(statement)? {
+        let statement = self.substitute(statement)?;
+        let started = std::time::Instant::now();
+        let result = self.client.execute(&statement)?;
+        let elapsed = started.elapsed();
+        // Schema changes affect tab completion.
+        let refresh =
+            matches!(result, Use { .. } | CreateTable { .. } | DropTable { existed: true, .. });
+        match result {
             Begin(state) => match state.read_only {
                 true => println!("Began read-only transaction at version {}", state.version),
                 false => println!("Began transaction {}", state.version),
             },
             Commit { version } => println!("Committed transaction {version}"),
             Rollback { version } => println!("Rolled back transaction {version}"),
+            Savepoint { name } => println!("Created savepoint {name}"),
+            RollbackToSavepoint { name } => println!("Rolled back to savepoint {name}"),
+            ReleaseSavepoint { name } => println!("Released savepoint {name}"),
+            Set { name } => println!("Set {name}"),
+            Declare { name } => println!("Declared cursor {name}"),
+            Close { name } => println!("Closed cursor {name}"),
+            Use { database } => println!("Using database {database}"),
             Insert { count } => println!("Inserted {count} rows"),
             Delete { count } => println!("Deleted {count} rows"),
             Update { count } => println!("Updated {count} rows"),
             CreateTable { name } => println!("Created table {name}"),
+            CreateIndex { table, column } => println!("Created index on {table}.{column}"),
+            Analyze { tables } => println!("Analyzed {} tables", tables.len()),
+            CreateUser { name } => println!("Created user {name}"),
+            AlterUser { name } => println!("Altered user {name}"),
+            CreateRole { name } => println!("Created role {name}"),
+            DropRole { name } => println!("Dropped role {name}"),
+            CreateChangefeed { name } => println!("Created changefeed {name}"),
+            DropChangefeed { name } => println!("Dropped changefeed {name}"),
+            CreateTrigger { name } => println!("Created trigger {name}"),
+            DropTrigger { name } => println!("Dropped trigger {name}"),
+            CreateFunction { name } => println!("Created function {name}"),
+            DropFunction { name } => println!("Dropped function {name}"),
+            Grant { grantee } => println!("Granted to {grantee}"),
+            Revoke { grantee } => println!("Revoked from {grantee}"),
+            CreateDatabase { name } => println!("Created database {name}"),
+            DropDatabase { name, existed } => match existed {
+                true => println!("Dropped database {name}"),
+                false => println!("Database {name} does not exist"),
+            },
             DropTable { name, existed } => match existed {
                 true => println!("Dropped table {name}"),
                 false => println!("Table {name} does not exist"),
             },
-            Explain(plan) => println!("{plan}"),
-            Select { columns, rows } => {
+            Explain(plan, format, analysis) => {
+                println!("{}", plan.explain(format, analysis.as_ref()))
+            }
+            Select { columns, rows } => self.print_rows(columns, rows),
+        }
+        if refresh {
+            self.refresh_completions();
+        }
+        if self.show_timing {
+            println!("Time: {:.3} ms", elapsed.as_secs_f64() * 1000.0);
+        }
+        Ok(())
+    }
+
+    /// Executes a !copy command, importing or exporting CSV files with a header
+    /// line. The arguments are either TABLE FROM 'FILE', TABLE TO 'FILE', or
+    /// (QUERY) TO 'FILE'.
+    fn copy(&mut self, args: &str) -> Result<()> {
+        // Parse the table or parenthesized query.
+        let (source, rest) = if args.starts_with('(') {
+            let mut depth = 0;
+            let mut quote = None;
+            let end = args.char_indices().find_map(|(i, c)| {
+                match (c, quote) {
+                    ('\'' | '"', None) => quote = Some(c),
+                    (c, Some(q)) if c == q => quote = None,
+                    ('(', None) => depth += 1,
+                    (')', None) => depth -= 1,
+                    _ => {}
+                }
+                (depth == 0).then_some(i)
+            });
+            let Some(end) = end else {
+                return errinput!("unterminated query in !copy");
+            };
+            (&args[..=end], &args[end + 1..])
+        } else {
+            args.split_once(char::is_whitespace).unwrap_or((args, ""))
+        };
+        let Some((direction, path)) = rest.trim().split_once(char::is_whitespace) else {
+            return errinput!("usage: !copy TABLE FROM 'FILE' or !copy TABLE|(QUERY) TO 'FILE'");
+        };
+        let path = path.trim();
+        let path = path.strip_prefix('\'').and_then(|p| p.strip_suffix('\'')).unwrap_or(path);
+
+        let count = match direction.to_uppercase().as_str() {
+            "FROM" if source.starts_with('(') => return errinput!("can't copy into a query"),
+            "FROM" => self.copy_from(source, path)?,
+            "TO" => self.copy_to(source, path)?,
+            _ => return errinput!("expected FROM or TO, got {direction}"),
+        };
+        println!("Copied {count} rows");
+        Ok(())
+    }
+
+    /// Imports CSV rows from a file into a table, using batched INSERTs. The
+    /// header line names the columns. Unless a transaction is already open,
+    /// the import is atomic.
+    fn copy_from(&mut self, table: &str, path: &str) -> Result<u64> {
+        const BATCH_SIZE: usize = 100;
+        let mut reader = CsvReader::new(std::io::BufReader::new(std::fs::File::open(path)?));
+        let Some(header) = reader.next()? else {
+            return errinput!("no header in {path}");
+        };
+        let schema = self.client.get_table(table)?;
+        let mut columns = Vec::new();
+        for name in header {
+            let name = name.unwrap_or_default();
+            let Some(column) = schema.columns.iter().find(|c| c.name == name) else {
+                return errinput!("unknown column {name} in table {table}");
+            };
+            columns.push(column.clone());
+        }
+
+        // Prepares an INSERT statement for the given number of rows.
+        let insert = |rows: usize| {
+            let row = format!("({})", vec!["?"; columns.len()].join(", "));
+            let names = columns.iter().map(|c| &c.name).join(", ");
+            format!("INSERT INTO {table} ({names}) VALUES {}", vec![row; rows].join(", "))
+        };
+        let in_txn = self.client.txn().is_some();
+        if !in_txn {
+            self.client.execute("BEGIN")?;
+        }
+        let result = (|| -> Result<u64> {
+            let batch_insert = self.client.prepare(&insert(BATCH_SIZE))?;
+            let mut params = Vec::new();
+            let mut count = 0;
+            while let Some(record) = reader.next()? {
+                if record.len() != columns.len() {
+                    return errinput!(
+                        "line {}: expected {} fields, got {}",
+                        reader.line,
+                        columns.len(),
+                        record.len()
+                    );
+                }
+                for (field, column) in record.into_iter().zip(&columns) {
+                    let Some(field) = field else {
+                        params.push(Value::Null);
+                        continue;
+                    };
+                    let value = match column.datatype {
+                        DataType::Boolean => field.to_lowercase().parse().map(Value::Boolean).ok(),
+                        DataType::Integer => field.parse().map(Value::Integer).ok(),
+                        DataType::Float => field.parse().map(Value::Float).ok(),
+                        DataType::String => Some(Value::String(field.clone())),
+                        DataType::Point => Self::parse_point(&field),
+                    };
+                    let Some(value) = value else {
+                        return errinput!(
+                            "line {}: invalid {} value {field} for column {}",
+                            reader.line,
+                            column.datatype,
+                            column.name
+                        );
+                    };
+                    params.push(value);
+                }
+                count += 1;
+                if params.len() == BATCH_SIZE * columns.len() {
+                    self.client.execute_prepared(&batch_insert, &params)?;
+                    params.clear();
+                }
+            }
+            self.client.deallocate(&batch_insert)?;
+            if !params.is_empty() {
+                let last_insert = self.client.prepare(&insert(params.len() / columns.len()))?;
+                self.client.execute_prepared(&last_insert, &params)?;
+                self.client.deallocate(&last_insert)?;
+            }
+            Ok(count)
+        })();
+        match result {
+            Ok(count) => {
+                if !in_txn {
+                    self.client.execute("COMMIT")?;
+                }
+                Ok(count)
+            }
+            Err(error) => {
+                if !in_txn {
+                    self.client.execute("ROLLBACK").ok(); // ignore rollback error
+                }
+                Err(error)
+            }
+        }
+    }
+
+    /// Parses a CSV point field, formatted as POINT(x, y) like in exports.
+    fn parse_point(field: &str) -> Option<Value> {
+        let field = field.trim();
+        let prefix = field.get(..6).filter(|p| p.eq_ignore_ascii_case("point("))?;
+        let (x, y) = field[prefix.len()..].strip_suffix(')')?.split_once(',')?;
+        let (x, y) = (x.trim().parse::<f64>().ok()?, y.trim().parse::<f64>().ok()?);
+        (!x.is_nan() && !y.is_nan()).then_some(Value::Point(x, y))
+    }
+
+    /// Exports a table or parenthesized query to a CSV file with a header
+    /// line, streaming the rows from the server.
+    fn copy_to(&mut self, source: &str, path: &str) -> Result<u64> {
+        let query = match source.strip_prefix('(').and_then(|s| s.strip_suffix(')')) {
+            Some(query) => query.to_string(),
+            None => format!("SELECT * FROM {source}"),
+        };
+        let rows = self.client.query_iter(&query)?;
+        let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        let header = rows.columns().iter().map(|c| Some(c.as_header().to_string()));
+        file.write_all(Format::csv_record(header).as_bytes())?;
+        let mut count = 0;
+        for row in rows {
+            file.write_all(Format::csv_record(row?.iter().map(Format::csv_value)).as_bytes())?;
+            count += 1;
+        }
+        file.flush()?;
+        Ok(count)
+    }
+
+    /// Executes a script file of SQL statements and ! commands. Statements can
+    /// span multiple lines, and must be terminated by a semicolon. Blank lines
+    /// and lines starting with -- are ignored. Stops at the first error,
+    /// printing the file and line number.
+    fn execute_file(&mut self, path: &std::path::Path) -> Result<()> {
+        let script = std::fs::read_to_string(path)?;
+        let mut statement = String::new();
+        let mut start = 0;
+        for (number, line) in script.lines().enumerate().map(|(i, line)| (i + 1, line)) {
+            if statement.is_empty() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with("--") {
+                    continue;
+                }
+                if line.starts_with('!') {
+                    self.execute_command(line)
+                        .inspect_err(|_| eprint!("{}:{number}: ", path.display()))?;
+                    continue;
+                }
+                start = number;
+            }
+            statement.push_str(line);
+            statement.push('\n');
+            if is_complete(&statement) {
+                self.execute_sql(statement.trim())
+                    .inspect_err(|_| eprint!("{}:{start}: ", path.display()))?;
+                statement.clear();
+            }
+        }
+        if !statement.is_empty() {
+            eprint!("{}:{start}: ", path.display());
+            return errinput!("unterminated statement");
+        }
+        Ok(())
+    }
+
+    /// Sets a variable. Names can contain letters, digits, and underscores.
+    fn set_variable(&mut self, name: &str, value: &str) -> Result<()> {
+        if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            return errinput!("invalid variable name {name}");
+        }
+        self.variables.insert(name.to_string(), value.to_string());
+        Ok(())
+    }
+
+    /// Substitutes variables in a SQL statement: :NAME is replaced by the
+    /// variable's value, and :'NAME' by the value as a quoted string literal.
+    /// Variables aren't substituted in string literals or quoted identifiers.
+    fn substitute(&self, statement: &str) -> Result<String> {
+        let mut output = String::with_capacity(statement.len());
+        let mut chars = statement.chars().peekable();
+        let mut quote = None;
+        while let Some(c) = chars.next() {
+            match (c, quote) {
+                ('\'' | '"', None) => quote = Some(c),
+                (c, Some(q)) if c == q => quote = None,
+                (':', None) => {
+                    let quoted = chars.next_if_eq(&'\'').is_some();
+                    let mut name = String::new();
+                    while let Some(c) = chars.next_if(|c| c.is_alphanumeric() || *c == '_') {
+                        name.push(c);
+                    }
+                    if quoted && chars.next_if_eq(&'\'').is_none() {
+                        return errinput!("unterminated variable :'{name}");
+                    }
+                    if name.is_empty() {
+                        output.push(':');
+                        continue;
+                    }
+                    let Some(value) = self.variables.get(&name) else {
+                        return errinput!("unknown variable {name}");
+                    };
+                    match quoted {
+                        true => output.push_str(&format!("'{}'", value.replace('\'', "''"))),
+                        false => output.push_str(value),
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+            output.push(c);
+        }
+        Ok(output)
+    }
+
+    /// Executes a SQL query and returns its columns and rows.
+    fn query(&mut self, query: &str) -> Result<(Vec<Label>, Vec<Row>)> {
+        match self.client.execute(query)? {
+            StatementResult::Select { columns, rows } => Ok((columns, rows)),
+            _ => errinput!("not a query: {query}"),
+        }
+    }
+
+    /// Displays query results in the current output format.
+    fn print_rows(&self, columns: Vec<Label>, rows: Vec<Row>) {
+        match self.format {
+            Format::Text => {
                 if self.show_headers {
                     println!("{}", columns.iter().map(|c| c.as_header()).join(", "));
                 }
@@ -197,8 +874,10 @@ Transactions: {active_txns} active, {versions} total
                     println!("{}", row.iter().join(", "));
                 }
             }
+            Format::Table => print!("{}", Format::table(&columns, &rows)),
+            Format::Csv => print!("{}", Format::csv(&columns, &rows)),
+            Format::Json => println!("{}", Format::json(&columns, &rows)),
         }
-        Ok(())
     }
 
     /// Prompts the user for input. Returns None if the shell should close.
@@ -225,6 +904,7 @@ Transactions: {active_txns} active, {versions} total
         // Print welcome message.
         let server = self.client.status()?.server;
         println!("Connected to toyDB node n{server}. Enter !help for instructions.");
+        self.refresh_completions();
 
         // Prompt for commands and execute them.
         loop {
@@ -234,41 +914,333 @@ Transactions: {active_txns} active, {versions} total
                 Err(ReadlineError::Eof) => break,
                 Err(error) => return Err(error.into()),
             };
-            self.editor.add_history_entry(&input)?;
+            if input.is_empty() {
+                continue;
+            }
+            // Save the history after each entry, so it persists even if the
+            // shell is killed.
+            if self.editor.add_history_entry(&input)? {
+                if let Some(history_path) = &self.history_path {
+                    self.editor.save_history(history_path)?;
+                }
+            }
             if let Err(error) = self.execute(&input) {
-                eprintln!("Error: {error}");
+                print_error(&error);
             };
         }
+        Ok(())
+    }
 
-        // Save the history file.
-        if let Some(history_path) = &self.history_path {
-            self.editor.save_history(history_path)?;
+    /// Refreshes the table and column names used for tab completion from the
+    /// catalog. Errors are ignored, and leave the current names in place.
+    fn refresh_completions(&mut self) {
+        let query = "SELECT table_name, column_name FROM information_schema.columns";
+        let Ok(StatementResult::Select { rows, .. }) = self.client.execute(query) else {
+            return;
+        };
+        let mut tables: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for row in rows {
+            if let [Value::String(table), Value::String(column)] = row.as_slice() {
+                tables.entry(table.clone()).or_default().push(column.clone());
+            }
+        }
+        if let Some(helper) = self.editor.helper_mut() {
+            helper.tables = tables;
         }
-        Ok(())
     }
 }
 
-/// A Rustyline helper for multiline editing. After a new line is entered, it
-/// determines whether the input makes up a complete SQL statement that should
-/// be submitted to the server (i.e. it's terminated by ;), or wait for further
-/// input.
-#[derive(Completer, Helper, Highlighter, Hinter)]
-struct InputValidator;
+/// An output format for query results.
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum Format {
+    /// Comma-separated SQL values, with optional headers (see !headers).
+    Text,
+    /// An aligned table with column headers.
+    Table,
+    /// CSV with column headers. NULLs are empty fields.
+    Csv,
+    /// A JSON object with "columns" and "rows" arrays.
+    Json,
+}
 
-impl Validator for InputValidator {
+impl std::fmt::Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Text => f.write_str("text"),
+            Self::Table => f.write_str("table"),
+            Self::Csv => f.write_str("csv"),
+            Self::Json => f.write_str("json"),
+        }
+    }
+}
+
+impl Format {
+    /// Formats a value without SQL quoting, for table and CSV output.
+    fn raw(value: &Value) -> String {
+        match value {
+            Value::String(string) => string.clone(),
+            value => value.to_string(),
+        }
+    }
+
+    /// Formats query results as an aligned table, followed by the row count.
+    fn table(columns: &[Label], rows: &[Row]) -> String {
+        let headers = columns.iter().map(|c| c.as_header().to_string()).collect_vec();
+        let rows = rows.iter().map(|row| row.iter().map(Self::raw).collect_vec()).collect_vec();
+        let mut widths = headers.iter().map(|h| h.chars().count()).collect_vec();
+        for row in &rows {
+            for (width, value) in widths.iter_mut().zip(row) {
+                *width = std::cmp::max(*width, value.chars().count());
+            }
+        }
+        let line = |values: &[String]| {
+            let mut cells = values.iter().zip(&widths).map(|(v, w)| format!(" {v:<w$} "));
+            format!("{}\n", cells.join("|").trim_end())
+        };
+        let mut output = line(&headers);
+        output.push_str(&widths.iter().map(|w| "-".repeat(w + 2)).join("+"));
+        output.push('\n');
+        for row in &rows {
+            output.push_str(&line(row));
+        }
+        output.push_str(&match rows.len() {
+            1 => "(1 row)\n".to_string(),
+            n => format!("({n} rows)\n"),
+        });
+        output
+    }
+
+    /// Formats query results as CSV with a header line, see csv_record().
+    fn csv(columns: &[Label], rows: &[Row]) -> String {
+        let mut output = Self::csv_record(columns.iter().map(|c| Some(c.as_header().into())));
+        for row in rows {
+            output.push_str(&Self::csv_record(row.iter().map(Self::csv_value)));
+        }
+        output
+    }
+
+    /// Formats a CSV record (RFC 4180), terminated by a line break. None is
+    /// written as an empty field. Fields containing commas, quotes, or line
+    /// breaks are quoted, as are empty strings to distinguish them from None.
+    fn csv_record(fields: impl Iterator<Item = Option<String>>) -> String {
+        let mut record = fields
+            .map(|field| match field {
+                None => String::new(),
+                Some(f) if f.is_empty() || f.contains([',', '"', '\n', '\r']) => {
+                    format!("\"{}\"", f.replace('"', "\"\""))
+                }
+                Some(f) => f,
+            })
+            .join(",");
+        record.push('\n');
+        record
+    }
+
+    /// Converts a value to a CSV field, with NULL as None.
+    fn csv_value(value: &Value) -> Option<String> {
+        match value {
+            Value::Null => None,
+            value => Some(Self::raw(value)),
+        }
+    }
+
+    /// Formats query results as a JSON object with a "columns" array of column
+    /// names and a "rows" array of value arrays. Non-finite floats, which JSON
+    /// can't represent, are given as strings, and points as [x, y] arrays.
+    fn json(columns: &[Label], rows: &[Row]) -> serde_json::Value {
+        fn json_value(value: &Value) -> serde_json::Value {
+            match value {
+                Value::Null => serde_json::Value::Null,
+                Value::Boolean(b) => (*b).into(),
+                Value::Integer(i) => (*i).into(),
+                Value::Float(f) => serde_json::Number::from_f64(*f)
+                    .map(serde_json::Value::Number)
+                    .unwrap_or_else(|| f.to_string().into()),
+                Value::String(s) => s.as_str().into(),
+                Value::Point(x, y) => {
+                    [Value::Float(*x), Value::Float(*y)].iter().map(json_value).collect()
+                }
+            }
+        }
+        serde_json::json!({
+            "columns": columns.iter().map(|c| c.as_header()).collect_vec(),
+            "rows": rows.iter().map(|row| row.iter().map(json_value).collect_vec()).collect_vec(),
+        })
+    }
+}
+
+/// Reads CSV records (RFC 4180) from a reader, one at a time. Unquoted empty
+/// fields are read as None, i.e. NULL, and quoted fields may span lines.
+struct CsvReader<R: std::io::BufRead> {
+    reader: R,
+    /// The current line number, for errors.
+    line: usize,
+}
+
+impl<R: std::io::BufRead> CsvReader<R> {
+    fn new(reader: R) -> Self {
+        Self { reader, line: 0 }
+    }
+
+    /// Reads the next record, or None at the end of the input.
+    fn next(&mut self) -> Result<Option<Vec<Option<String>>>> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        self.line += 1;
+        let mut fields = Vec::new();
+        let mut field: Option<String> = None;
+        let mut quoted = false;
+        let mut chars = line.chars().peekable();
+        loop {
+            let Some(c) = chars.next() else {
+                // A line break in a quoted field continues on the next line.
+                if !quoted {
+                    break;
+                }
+                line.clear();
+                if self.reader.read_line(&mut line)? == 0 {
+                    return errinput!("line {}: unterminated quoted field", self.line);
+                }
+                self.line += 1;
+                chars = line.chars().peekable();
+                continue;
+            };
+            match c {
+                '"' if quoted && chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.get_or_insert_with(String::new).push('"');
+                }
+                '"' if quoted => quoted = false,
+                '"' if field.is_none() => {
+                    quoted = true;
+                    field = Some(String::new());
+                }
+                ',' if !quoted => fields.push(field.take()),
+                '\r' | '\n' if !quoted => {}
+                c => field.get_or_insert_with(String::new).push(c),
+            }
+        }
+        fields.push(field);
+        Ok(Some(fields))
+    }
+}
+
+/// Prints an error to stderr. For syntax errors with a location, the offending
+/// line is printed with a caret under the error location.
+fn print_error(error: &Error) {
+    eprintln!("Error: {error}");
+    if let Error::Syntax(SyntaxError { location: Some(location), .. }) = error.root() {
+        eprintln!("{}", location.render());
+    }
+}
+
+/// Returns true if the input is a complete SQL statement, i.e. if it's
+/// terminated by a semicolon. Otherwise, further input is needed. If the input
+/// can't be lexed (e.g. because it contains :variables), it's complete if it
+/// ends with a semicolon. The server handles further validation and errors.
+fn is_complete(input: &str) -> bool {
+    let mut terminated = false;
+    for token in Lexer::new(input) {
+        match token {
+            Ok(token) => terminated = token == Token::Semicolon,
+            Err(_) => return input.trim_end().ends_with(';'),
+        }
+    }
+    terminated
+}
+
+/// A Rustyline helper for multiline editing and tab completion.
+#[derive(Default, Helper, Highlighter, Hinter)]
+struct InputHelper {
+    /// Table names and their column names, for completion.
+    tables: BTreeMap<String, Vec<String>>,
+}
+
+/// The ! commands, for completion.
+const COMMANDS: &[&str] = &[
+    "!backup",
+    "!copy",
+    "!d",
+    "!dt",
+    "!format",
+    "!headers",
+    "!help",
+    "!i",
+    "!metrics",
+    "!set",
+    "!status",
+    "!table",
+    "!tables",
+    "!timing",
+    "!trace",
+    "!transfer",
+    "!unset",
+    "!verify",
+];
+
+impl Completer for InputHelper {
+    type Candidate = String;
+
+    /// Completes the word before the cursor. The first word of a ! command
+    /// completes command names, and the argument of !d and !table completes
+    /// table names. In SQL statements, a table.prefix word completes the
+    /// table's columns, and other words complete keywords and table and
+    /// column names. Keywords are completed in the case of the prefix.
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _: &rustyline::Context,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let is_word = |c: char| c.is_alphanumeric() || c == '_' || c == '.' || c == '!';
+        let start = line[..pos].rfind(|c| !is_word(c)).map(|i| i + 1).unwrap_or(0);
+        let word = &line[start..pos];
+        let matching = |candidates: &mut dyn Iterator<Item = String>| {
+            let mut candidates = candidates.filter(|c| c.starts_with(word)).collect_vec();
+            candidates.sort();
+            candidates.dedup();
+            candidates
+        };
+
+        let candidates = if line.starts_with('!') {
+            match line[..start].split_whitespace().collect_vec().as_slice() {
+                [] => matching(&mut COMMANDS.iter().map(|c| c.to_string())),
+                ["!d" | "!table"] => matching(&mut self.tables.keys().cloned()),
+                _ => Vec::new(),
+            }
+        } else if let Some((table, _)) = word.split_once('.') {
+            let columns = self.tables.get(table).into_iter().flatten();
+            matching(&mut columns.map(|column| format!("{table}.{column}")))
+        } else {
+            let lowercase = word.chars().next().is_some_and(|c| c.is_lowercase());
+            let keywords = Keyword::ALL.iter().map(|keyword| match lowercase {
+                true => keyword.to_string().to_lowercase(),
+                false => keyword.to_string(),
+            });
+            let tables = self.tables.keys().cloned();
+            let columns = self.tables.values().flatten().cloned();
+            matching(&mut keywords.chain(tables).chain(columns))
+        };
+        Ok((start, candidates))
+    }
+}
+
+impl Validator for InputHelper {
+    /// After a new line is entered, determines whether the input makes up a
+    /// complete SQL statement that should be submitted to the server (i.e. it's
+    /// terminated by ;), or wait for further input.
     fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
         let input = ctx.input();
         // Empty lines and ! commands are ready.
         if input.is_empty() || input.starts_with('!') || input == ";" {
             return Ok(ValidationResult::Valid(None));
         }
-        // For SQL statements, just look for any semicolon or lexer error, and
-        // rely on the server for further validation and error handling.
-        if Lexer::new(input).any(|r| matches!(r, Ok(Token::Semicolon) | Err(_))) {
-            return Ok(ValidationResult::Valid(None));
+        match is_complete(input) {
+            true => Ok(ValidationResult::Valid(None)),
+            false => Ok(ValidationResult::Incomplete),
         }
-        // Otherwise, wait for more input.
-        Ok(ValidationResult::Incomplete)
     }
 
     fn validate_while_typing(&self) -> bool {
@@ -9,6 +9,7 @@
 #![warn(clippy::all)]
 
 use hdrhistogram::Histogram;
+use toydb::errinput;
 use toydb::error::Result;
 use toydb::sql::types::{Row, Rows};
 use toydb::{Client, StatementResult};
@@ -18,9 +19,11 @@ use itertools::Itertools as _;
 use petname::Generator as _;
 use rand::distributions::Distribution as _;
 use rand::rngs::StdRng;
-use rand::SeedableRng as _;
+use rand::{Rng as _, SeedableRng as _};
 use std::collections::HashSet;
 use std::io::Write as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
 
 fn main() {
@@ -29,6 +32,7 @@ fn main() {
         Subcommand::Read(read) => runner.run(read),
         Subcommand::Write(write) => runner.run(write),
         Subcommand::Bank(bank) => runner.run(bank),
+        Subcommand::Ycsb(ycsb) => runner.run(ycsb),
     };
     if let Err(error) = result {
         eprintln!("Error: {error}")
@@ -51,6 +55,7 @@ enum Subcommand {
     Read(Read),
     Write(Write),
     Bank(Bank),
+    Ycsb(Ycsb),
 }
 
 /// Runs a workload benchmark.
@@ -515,3 +520,274 @@ impl Workload for Bank {
         Ok(())
     }
 }
+
+/// A YCSB-style workload. Creates an id,value table with the given row count
+/// and value size, like the read workload, and then runs a random mix of
+/// single-row reads, updates, and inserts, and short range scans. Keys are
+/// picked either uniformly or with a Zipfian distribution where low keys are
+/// hottest. Inserts use sequential keys past the existing rows, and later
+/// operations may pick inserted keys. For example, YCSB workload A (update
+/// heavy) is --read 0.5 --update 0.5, and workload E (short ranges) is
+/// --scan 0.95 --insert 0.05.
+#[derive(clap::Args, Clone)]
+#[command(about = "A YCSB-style mix of reads, updates, inserts, and scans")]
+struct Ycsb {
+    /// Initial number of rows in data set.
+    #[arg(short, long, default_value = "1000")]
+    rows: u64,
+
+    /// Row value size (excluding primary key).
+    #[arg(short, long, default_value = "64")]
+    size: usize,
+
+    /// Fraction of operations that are single-row reads.
+    #[arg(long, default_value = "0.5")]
+    read: f64,
+
+    /// Fraction of operations that are single-row updates.
+    #[arg(long, default_value = "0.5")]
+    update: f64,
+
+    /// Fraction of operations that are single-row inserts.
+    #[arg(long, default_value = "0")]
+    insert: f64,
+
+    /// Fraction of operations that are range scans.
+    #[arg(long, default_value = "0")]
+    scan: f64,
+
+    /// Maximum number of rows to fetch in a scan. The scan length is uniformly
+    /// random up to this.
+    #[arg(long, default_value = "100")]
+    scan_length: u64,
+
+    /// Key distribution.
+    #[arg(short, long, value_enum, default_value = "zipfian")]
+    distribution: KeyDistribution,
+
+    /// The number of inserts generated, for verification.
+    #[arg(skip)]
+    inserted: Arc<AtomicU64>,
+}
+
+/// A key distribution for the YCSB workload.
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum KeyDistribution {
+    /// All keys are equally likely.
+    Uniform,
+    /// Low keys are more likely, following a Zipfian distribution with the
+    /// YCSB default constant 0.99.
+    Zipfian,
+}
+
+impl std::fmt::Display for Ycsb {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "ycsb (rows={} size={} read={} update={} insert={} scan={} distribution={:?})",
+            self.rows, self.size, self.read, self.update, self.insert, self.scan, self.distribution
+        )
+    }
+}
+
+/// A YCSB operation.
+enum YcsbOp {
+    Read(u64),
+    Update(u64, String),
+    Insert(u64, String),
+    Scan(u64, u64),
+}
+
+impl Workload for Ycsb {
+    type Item = YcsbOp;
+
+    fn prepare(&self, client: &mut Client, rng: &mut StdRng) -> Result<()> {
+        let mix = [self.read, self.update, self.insert, self.scan];
+        if mix.iter().any(|f| !(0.0..=1.0).contains(f)) {
+            return errinput!("operation fractions must be between 0 and 1");
+        }
+        if (mix.iter().sum::<f64>() - 1.0).abs() > 1e-6 {
+            return errinput!("operation fractions must sum to 1");
+        }
+        if self.rows == 0 {
+            return errinput!("rows must be positive");
+        }
+        if self.scan > 0.0 && self.scan_length == 0 {
+            return errinput!("scan length must be positive");
+        }
+
+        client.execute("BEGIN")?;
+        client.execute("DROP TABLE IF EXISTS ycsb")?;
+        client.execute("CREATE TABLE ycsb (id INT PRIMARY KEY, value STRING NOT NULL)")?;
+
+        let chars = &mut rand::distributions::Alphanumeric.sample_iter(rng).map(|b| b as char);
+        let rows = (1..=self.rows).map(|id| (id, chars.take(self.size).collect::<String>()));
+        let chunks = rows.chunks(100);
+        let queries = chunks.into_iter().map(|chunk| {
+            format!(
+                "INSERT INTO ycsb (id, value) VALUES ({})",
+                chunk.map(|(id, value)| format!("{}, '{}'", id, value)).join("), (")
+            )
+        });
+        for query in queries {
+            client.execute(&query)?;
+        }
+        client.execute("COMMIT")?;
+        Ok(())
+    }
+
+    fn generate(&self, rng: StdRng) -> impl Iterator<Item = Self::Item> + 'static {
+        YcsbGenerator {
+            rng,
+            keys: self.rows,
+            zipfian: match self.distribution {
+                KeyDistribution::Uniform => None,
+                KeyDistribution::Zipfian => Some(Zipfian::new(self.rows, 0.99)),
+            },
+            mix: [self.read, self.update, self.insert],
+            size: self.size,
+            scan_length: self.scan_length,
+            inserted: self.inserted.clone(),
+        }
+    }
+
+    fn execute(client: &mut Client, item: &Self::Item) -> Result<()> {
+        match item {
+            YcsbOp::Read(id) => {
+                let rows: Rows =
+                    client.execute(&format!("SELECT * FROM ycsb WHERE id = {id}"))?.try_into()?;
+                assert!(rows.count() <= 1, "Unexpected row count");
+            }
+            YcsbOp::Update(id, value) => {
+                client.execute(&format!("UPDATE ycsb SET value = '{value}' WHERE id = {id}"))?;
+            }
+            YcsbOp::Insert(id, value) => {
+                client
+                    .execute(&format!("INSERT INTO ycsb (id, value) VALUES ({id}, '{value}')"))?;
+            }
+            YcsbOp::Scan(id, length) => {
+                let rows: Rows = client
+                    .execute(&format!(
+                        "SELECT * FROM ycsb WHERE id >= {id} ORDER BY id LIMIT {length}"
+                    ))?
+                    .try_into()?;
+                assert!(rows.count() as u64 <= *length, "Unexpected row count");
+            }
+        }
+        Ok(())
+    }
+
+    fn verify(&self, client: &mut Client, _: usize) -> Result<()> {
+        let count: i64 = client.execute("SELECT COUNT(*) FROM ycsb")?.try_into()?;
+        let expect = self.rows + self.inserted.load(Ordering::Relaxed);
+        assert_eq!(count as u64, expect, "Unexpected row count");
+        Ok(())
+    }
+}
+
+/// A YCSB workload generator, yielding random operations.
+struct YcsbGenerator {
+    rng: StdRng,
+    /// The current number of keys, including generated inserts.
+    keys: u64,
+    /// The Zipfian distribution, or None for uniform.
+    zipfian: Option<Zipfian>,
+    /// The read, update, and insert fractions. The rest are scans.
+    mix: [f64; 3],
+    size: usize,
+    scan_length: u64,
+    inserted: Arc<AtomicU64>,
+}
+
+impl YcsbGenerator {
+    /// Picks a random existing key.
+    fn key(&mut self) -> u64 {
+        match &mut self.zipfian {
+            Some(zipfian) => zipfian.sample(&mut self.rng) + 1,
+            None => self.rng.gen_range(1..=self.keys),
+        }
+    }
+
+    /// Generates a random value.
+    fn value(&mut self) -> String {
+        let chars = rand::distributions::Alphanumeric.sample_iter(&mut self.rng);
+        chars.take(self.size).map(|b| b as char).collect()
+    }
+}
+
+impl Iterator for YcsbGenerator {
+    type Item = <Ycsb as Workload>::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let [read, update, insert] = self.mix;
+        let op = self.rng.gen::<f64>();
+        Some(if op < read {
+            YcsbOp::Read(self.key())
+        } else if op < read + update {
+            YcsbOp::Update(self.key(), self.value())
+        } else if op < read + update + insert {
+            self.keys += 1;
+            if let Some(zipfian) = &mut self.zipfian {
+                zipfian.grow(self.keys);
+            }
+            self.inserted.fetch_add(1, Ordering::Relaxed);
+            YcsbOp::Insert(self.keys, self.value())
+        } else {
+            let length = self.rng.gen_range(1..=self.scan_length);
+            YcsbOp::Scan(self.key(), length)
+        })
+    }
+}
+
+/// A Zipfian distribution over [0,n), using the algorithm from "Quickly
+/// Generating Billion-Record Synthetic Databases" (Gray et al, 1994) like
+/// YCSB. The item count can grow, which updates the distribution
+/// incrementally.
+struct Zipfian {
+    n: u64,
+    theta: f64,
+    alpha: f64,
+    zeta2: f64,
+    zetan: f64,
+    eta: f64,
+}
+
+impl Zipfian {
+    /// Creates a new Zipfian distribution over n items with constant theta.
+    fn new(n: u64, theta: f64) -> Self {
+        let mut zipfian = Self {
+            n: 0,
+            theta,
+            alpha: 1.0 / (1.0 - theta),
+            zeta2: 1.0 + 0.5f64.powf(theta),
+            zetan: 0.0,
+            eta: 0.0,
+        };
+        zipfian.grow(n);
+        zipfian
+    }
+
+    /// Grows the distribution to n items.
+    fn grow(&mut self, n: u64) {
+        while self.n < n {
+            self.n += 1;
+            self.zetan += 1.0 / (self.n as f64).powf(self.theta);
+        }
+        self.eta =
+            (1.0 - (2.0 / self.n as f64).powf(1.0 - self.theta)) / (1.0 - self.zeta2 / self.zetan);
+    }
+
+    /// Samples an item.
+    fn sample(&self, rng: &mut StdRng) -> u64 {
+        let u = rng.gen::<f64>();
+        let uz = u * self.zetan;
+        if uz < 1.0 || self.n == 1 {
+            return 0;
+        }
+        if uz < 1.0 + 0.5f64.powf(self.theta) {
+            return 1;
+        }
+        let item = (self.n as f64 * (self.eta * u - self.eta + 1.0).powf(self.alpha)) as u64;
+        item.min(self.n - 1)
+    }
+}
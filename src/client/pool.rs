@@ -0,0 +1,387 @@
+//! A thread-safe client connection pool.
+//!
+//! A Client is a single connection to a server, and can only be used by one
+//! thread at a time. A Pool shares a set of client connections between
+//! threads: a thread checks out a connection via Pool::checkout(), uses it,
+//! and returns it to the pool when the PooledClient is dropped. The pool is
+//! cheap to clone, and clones share the same connections. For example:
+//!
+//! ```no_run
+//! # use toydb::{Pool, PoolOptions};
+//! # fn main() -> toydb::error::Result<()> {
+//! let pool = Pool::connect(["localhost:9601", "localhost:9602"], PoolOptions::default())?;
+//! let threads: Vec<_> = (0..8)
+//!     .map(|_| {
+//!         let pool = pool.clone();
+//!         std::thread::spawn(move || pool.checkout()?.execute("SELECT 1"))
+//!     })
+//!     .collect();
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Connections are opened lazily, up to a maximum pool size. If all
+//! connections are in use, checkouts wait for one to be returned, up to a
+//! timeout. Idle connections are closed after an idle timeout, and are health
+//! checked via Client::ping() before being handed out, such that connections
+//! to dead servers are replaced.
+//!
+//! Returned connections keep their session state, i.e. session settings and
+//! prepared statements, but an open transaction is rolled back. If the rollback
+//! fails, the connection is closed.
+
+use super::Client;
+use crate::errinput;
+use crate::error::{Error, Result};
+
+use log::debug;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// Connects a new client for the pool.
+type ConnectFn = Box<dyn Fn() -> Result<Client> + Send + Sync>;
+
+/// Connection pool options.
+#[derive(Clone, Debug)]
+pub struct PoolOptions {
+    /// The maximum number of open connections, including checked out ones.
+    pub max_size: usize,
+    /// How long a checkout waits for a connection before erroring.
+    pub checkout_timeout: Duration,
+    /// How long a connection can be idle in the pool before it's closed, if
+    /// ever.
+    pub idle_timeout: Option<Duration>,
+    /// Whether to ping idle connections before handing them out.
+    pub health_check: bool,
+}
+
+impl Default for PoolOptions {
+    fn default() -> Self {
+        Self {
+            max_size: 10,
+            checkout_timeout: Duration::from_secs(30),
+            idle_timeout: Some(Duration::from_secs(600)),
+            health_check: true,
+        }
+    }
+}
+
+/// A thread-safe client connection pool, see the module documentation.
+#[derive(Clone)]
+pub struct Pool {
+    inner: Arc<Inner>,
+}
+
+/// The shared pool state.
+struct Inner {
+    /// Connects new clients.
+    connect: ConnectFn,
+    /// The pool options.
+    opts: PoolOptions,
+    /// The pool connections.
+    state: Mutex<State>,
+    /// Notified when a connection is returned or closed.
+    available: Condvar,
+}
+
+/// The pool's connections.
+struct State {
+    /// Idle connections, with the time they were returned. The most recently
+    /// returned connection is last, and is used first.
+    idle: Vec<(Client, Instant)>,
+    /// The number of open connections, including idle and checked out ones,
+    /// as well as ones being connected.
+    open: usize,
+}
+
+impl Pool {
+    /// Creates a connection pool for the given toyDB servers, typically the
+    /// nodes of a cluster. Each connection fails over between the servers, see
+    /// Client::connect_cluster(). The addresses are resolved once, up front.
+    pub fn connect<A: ToSocketAddrs>(
+        addrs: impl IntoIterator<Item = A>,
+        opts: PoolOptions,
+    ) -> Result<Self> {
+        let mut socket_addrs: Vec<SocketAddr> = Vec::new();
+        for addr in addrs {
+            socket_addrs.extend(addr.to_socket_addrs()?);
+        }
+        Self::new(opts, move || Client::connect_cluster(&socket_addrs))
+    }
+
+    /// Creates a connection pool using the given function to connect new
+    /// clients, e.g. to connect via TLS or authenticate.
+    pub fn new(
+        opts: PoolOptions,
+        connect: impl Fn() -> Result<Client> + Send + Sync + 'static,
+    ) -> Result<Self> {
+        if opts.max_size == 0 {
+            return errinput!("pool size must be positive");
+        }
+        let state = Mutex::new(State { idle: Vec::new(), open: 0 });
+        let inner = Inner { connect: Box::new(connect), opts, state, available: Condvar::new() };
+        Ok(Self { inner: Arc::new(inner) })
+    }
+
+    /// Checks out a connection, reusing an idle one or opening a new one. If
+    /// the pool is full, waits for a connection to be returned, erroring after
+    /// the checkout timeout.
+    pub fn checkout(&self) -> Result<PooledClient> {
+        let inner = &self.inner;
+        let deadline = Instant::now() + inner.opts.checkout_timeout;
+        let mut state = inner.state.lock()?;
+        loop {
+            state.close_expired(inner.opts.idle_timeout);
+            if let Some((mut client, _)) = state.idle.pop() {
+                if !inner.opts.health_check {
+                    return Ok(PooledClient { client: Some(client), pool: self.clone() });
+                }
+                // Don't hold the lock while pinging the server.
+                drop(state);
+                match client.ping() {
+                    Ok(()) => return Ok(PooledClient { client: Some(client), pool: self.clone() }),
+                    Err(error) => debug!("Closing pooled connection failing health check: {error}"),
+                }
+                state = inner.state.lock()?;
+                state.open -= 1;
+                continue;
+            }
+            if state.open < inner.opts.max_size {
+                state.open += 1;
+                drop(state);
+                return match (inner.connect)() {
+                    Ok(client) => Ok(PooledClient { client: Some(client), pool: self.clone() }),
+                    Err(error) => {
+                        inner.state.lock()?.open -= 1;
+                        inner.available.notify_one();
+                        Err(error)
+                    }
+                };
+            }
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            if timeout.is_zero() {
+                return Err(Error::IO("timed out waiting for pooled connection".to_string()));
+            }
+            state = inner.available.wait_timeout(state, timeout)?.0;
+        }
+    }
+
+    /// Returns the number of open connections, including checked out ones.
+    pub fn size(&self) -> usize {
+        self.inner.state.lock().expect("mutex failed").open
+    }
+
+    /// Returns the number of idle connections.
+    pub fn idle(&self) -> usize {
+        self.inner.state.lock().expect("mutex failed").idle.len()
+    }
+
+    /// Returns a connection to the pool, rolling back any open transaction. If
+    /// the rollback fails, the connection is closed instead.
+    fn checkin(&self, mut client: Client) {
+        let healthy = match client.txn() {
+            Some(_) => client.execute("ROLLBACK").is_ok(),
+            None => true,
+        };
+        let Ok(mut state) = self.inner.state.lock() else {
+            return;
+        };
+        if healthy {
+            state.idle.push((client, Instant::now()));
+        } else {
+            state.open -= 1;
+        }
+        drop(state);
+        self.inner.available.notify_one();
+    }
+}
+
+impl State {
+    /// Closes idle connections that have exceeded the idle timeout.
+    fn close_expired(&mut self, idle_timeout: Option<Duration>) {
+        let Some(idle_timeout) = idle_timeout else {
+            return;
+        };
+        let len = self.idle.len();
+        self.idle.retain(|(_, since)| since.elapsed() < idle_timeout);
+        self.open -= len - self.idle.len();
+    }
+}
+
+/// A client connection checked out from a Pool. Dereferences to a Client, and
+/// returns the connection to the pool when dropped.
+pub struct PooledClient {
+    /// The client. Only None while being dropped or detached.
+    client: Option<Client>,
+    /// The pool to return the client to.
+    pool: Pool,
+}
+
+impl PooledClient {
+    /// Detaches the client from the pool, e.g. to keep a long-lived session.
+    /// The pool can then open a new connection in its place.
+    pub fn detach(mut self) -> Client {
+        let client = self.client.take().expect("no client");
+        if let Ok(mut state) = self.pool.inner.state.lock() {
+            state.open -= 1;
+        }
+        self.pool.inner.available.notify_one();
+        client
+    }
+}
+
+impl Deref for PooledClient {
+    type Target = Client;
+
+    fn deref(&self) -> &Client {
+        self.client.as_ref().expect("no client")
+    }
+}
+
+impl DerefMut for PooledClient {
+    fn deref_mut(&mut self) -> &mut Client {
+        self.client.as_mut().expect("no client")
+    }
+}
+
+impl Drop for PooledClient {
+    fn drop(&mut self) {
+        if let Some(client) = self.client.take() {
+            self.pool.checkin(client);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raft;
+    use crate::server::{MemoryNetwork, ServerBuilder, ServerHandle};
+    use crate::sql::types::Value;
+
+    /// Starts a single-node server.
+    fn server() -> Result<ServerHandle> {
+        let network = MemoryNetwork::new();
+        let mut server = ServerBuilder::new(1).durability(raft::Durability::Never).build()?;
+        server.set_raft_tick_interval(Duration::from_millis(10))?;
+        let handle = server.start(network.transport(1), "127.0.0.1:0")?;
+        handle.connect()?.with_retry(|c| c.execute("CREATE TABLE t (id INTEGER PRIMARY KEY)"))?;
+        Ok(handle)
+    }
+
+    /// Connections are opened lazily up to the maximum size and reused, and
+    /// checkouts from a full pool time out.
+    #[test]
+    fn checkout() -> Result<()> {
+        let handle = server()?;
+        let opts = PoolOptions {
+            max_size: 2,
+            checkout_timeout: Duration::from_millis(100),
+            ..PoolOptions::default()
+        };
+        let pool = Pool::connect([handle.sql_addr()], opts)?;
+        assert_eq!((pool.size(), pool.idle()), (0, 0));
+
+        let mut a = pool.checkout()?;
+        let b = pool.checkout()?;
+        assert_eq!((pool.size(), pool.idle()), (2, 0));
+        assert_eq!(
+            pool.checkout().err(),
+            Some(Error::IO("timed out waiting for pooled connection".into()))
+        );
+
+        // Returned connections are reused, with their session state.
+        a.execute("SET statement_timeout = 5000")?;
+        drop(a);
+        assert_eq!((pool.size(), pool.idle()), (2, 1));
+        let a = pool.checkout()?;
+        assert_eq!((pool.size(), pool.idle()), (2, 0));
+
+        // A detached connection frees up a slot.
+        let client = b.detach();
+        assert_eq!(pool.size(), 1);
+        drop(client);
+        drop(a);
+        let (_c, _d) = (pool.checkout()?, pool.checkout()?);
+        assert_eq!((pool.size(), pool.idle()), (2, 0));
+        Ok(())
+    }
+
+    /// Checkouts wait for connections to be returned by other threads.
+    #[test]
+    fn concurrent() -> Result<()> {
+        let handle = server()?;
+        let opts = PoolOptions { max_size: 2, ..PoolOptions::default() };
+        let pool = Pool::connect([handle.sql_addr()], opts)?;
+        let threads: Vec<_> = (1..=8)
+            .map(|id| {
+                let pool = pool.clone();
+                std::thread::spawn(move || {
+                    let mut client = pool.checkout()?;
+                    client.with_retry(|c| c.execute(&format!("INSERT INTO t VALUES ({id})")))
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().expect("thread panicked")?;
+        }
+        assert!(pool.size() <= 2);
+        let count = pool.checkout()?.execute("SELECT COUNT(*) FROM t")?;
+        assert_eq!(Value::try_from(count)?, Value::Integer(8));
+        Ok(())
+    }
+
+    /// Returned connections have their transaction rolled back.
+    #[test]
+    fn rollback() -> Result<()> {
+        let handle = server()?;
+        let opts = PoolOptions { max_size: 1, ..PoolOptions::default() };
+        let pool = Pool::connect([handle.sql_addr()], opts)?;
+
+        let mut client = pool.checkout()?;
+        client.execute("BEGIN")?;
+        client.execute("INSERT INTO t VALUES (1)")?;
+        drop(client);
+
+        let mut client = pool.checkout()?;
+        assert!(client.txn().is_none());
+        let count = client.execute("SELECT COUNT(*) FROM t")?;
+        assert_eq!(Value::try_from(count)?, Value::Integer(0));
+        Ok(())
+    }
+
+    /// Idle connections are closed after the idle timeout, and connections
+    /// failing health checks are replaced.
+    #[test]
+    fn idle_and_health() -> Result<()> {
+        let handle = server()?;
+        let connects = Arc::new(Mutex::new(0));
+        let opts =
+            PoolOptions { idle_timeout: Some(Duration::from_millis(50)), ..PoolOptions::default() };
+        let pool = Pool::new(opts, {
+            let (handle, connects) = (handle.clone(), connects.clone());
+            move || {
+                *connects.lock()? += 1;
+                handle.connect()
+            }
+        })?;
+
+        drop(pool.checkout()?);
+        drop(pool.checkout()?);
+        assert_eq!((pool.size(), pool.idle(), *connects.lock()?), (1, 1, 1));
+
+        std::thread::sleep(Duration::from_millis(100));
+        drop(pool.checkout()?);
+        assert_eq!((pool.size(), pool.idle(), *connects.lock()?), (1, 1, 2));
+
+        // Shut down the server. The idle connection fails its health check
+        // since the server is draining, and is replaced.
+        handle.shutdown()?;
+        let mut client = pool.checkout()?;
+        assert_eq!((pool.size(), pool.idle(), *connects.lock()?), (1, 0, 3));
+        assert!(client.ping().is_err());
+        Ok(())
+    }
+}
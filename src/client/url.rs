@@ -0,0 +1,236 @@
+//! Connection URLs, which specify all connection settings as a single string,
+//! e.g. from an environment variable. They have the form:
+//!
+//! ```text
+//! toydb://[USER[:PASSWORD]@]HOST[:PORT][,HOST[:PORT]...][/][?OPTION=VALUE[&...]]
+//! ```
+//!
+//! Multiple hosts are typically the nodes of a cluster, and the client fails
+//! over between them. The port defaults to 9605, and IPv6 addresses must be
+//! enclosed in brackets, e.g. `[::1]:9605`. The user and password can be
+//! percent-encoded, e.g. `%40` for `@`. The following options are supported:
+//!
+//! * `tls_ca`: connect via TLS, trusting server certificates signed by the CA
+//!   certificate(s) in this PEM file.
+//! * `tls_cert`, `tls_key`: a client certificate and private key PEM file, for
+//!   servers that require TLS client authentication. Requires `tls_ca`.
+//! * `compression`: `true` to request compression of large responses, or
+//!   `false` (the default).
+
+use super::ConnectOptions;
+use crate::errinput;
+use crate::error::Result;
+
+use std::path::PathBuf;
+
+/// The default SQL port.
+const DEFAULT_PORT: u16 = 9605;
+
+/// A parsed connection URL, see the module documentation.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ConnectUrl {
+    /// The server addresses, as host:port.
+    pub hosts: Vec<String>,
+    /// The user to authenticate as, if any.
+    pub user: Option<String>,
+    /// The user's password, if given.
+    pub password: Option<String>,
+    /// The TLS CA certificate PEM file, if any.
+    pub tls_ca: Option<PathBuf>,
+    /// The TLS client certificate PEM file, if any.
+    pub tls_cert: Option<PathBuf>,
+    /// The TLS client private key PEM file, if any.
+    pub tls_key: Option<PathBuf>,
+    /// Whether to request compression of large responses.
+    pub compression: bool,
+}
+
+impl ConnectUrl {
+    /// Parses a connection URL.
+    pub fn parse(url: &str) -> Result<Self> {
+        let Some(rest) = url.strip_prefix("toydb://") else {
+            return errinput!("connection URL must start with toydb://");
+        };
+        let (rest, query) = rest.split_once('?').unwrap_or((rest, ""));
+        let rest = rest.strip_suffix('/').unwrap_or(rest);
+        if rest.contains('/') {
+            return errinput!("connection URL can't have a path");
+        }
+
+        let mut result = Self::default();
+        let hosts = match rest.rsplit_once('@') {
+            Some((userinfo, hosts)) => {
+                let (user, password) = match userinfo.split_once(':') {
+                    Some((user, password)) => (user, Some(password)),
+                    None => (userinfo, None),
+                };
+                if user.is_empty() {
+                    return errinput!("connection URL has an empty user");
+                }
+                result.user = Some(percent_decode(user)?);
+                result.password = password.map(percent_decode).transpose()?;
+                hosts
+            }
+            None => rest,
+        };
+        for host in hosts.split(',') {
+            result.hosts.push(Self::parse_host(host)?);
+        }
+
+        for option in query.split('&').filter(|o| !o.is_empty()) {
+            let Some((key, value)) = option.split_once('=') else {
+                return errinput!("invalid connection URL option {option}");
+            };
+            let value = percent_decode(value)?;
+            match key {
+                "tls_ca" => result.tls_ca = Some(value.into()),
+                "tls_cert" => result.tls_cert = Some(value.into()),
+                "tls_key" => result.tls_key = Some(value.into()),
+                "compression" => {
+                    result.compression = match value.as_str() {
+                        "true" => true,
+                        "false" => false,
+                        value => return errinput!("invalid compression option {value}"),
+                    }
+                }
+                key => return errinput!("unknown connection URL option {key}"),
+            }
+        }
+        if result.tls_cert.is_some() != result.tls_key.is_some() {
+            return errinput!("tls_cert and tls_key must be given together");
+        }
+        if result.tls_cert.is_some() && result.tls_ca.is_none() {
+            return errinput!("tls_cert requires tls_ca");
+        }
+        Ok(result)
+    }
+
+    /// Parses a host with an optional port, returning it as host:port.
+    fn parse_host(host: &str) -> Result<String> {
+        // IPv6 addresses are bracketed, since they contain colons.
+        let (name, port) = match host.strip_prefix('[') {
+            Some(rest) => match rest.split_once(']') {
+                Some((addr, port)) => (&host[..addr.len() + 2], port),
+                None => return errinput!("invalid host {host}"),
+            },
+            None => match host.find(':') {
+                Some(i) => host.split_at(i),
+                None => (host, ""),
+            },
+        };
+        if name.is_empty() || name == "[]" {
+            return errinput!("connection URL has an empty host");
+        }
+        let port = match port.strip_prefix(':') {
+            Some(port) => match port.parse::<u16>() {
+                Ok(port) => port,
+                Err(_) => return errinput!("invalid port {port}"),
+            },
+            None if port.is_empty() => DEFAULT_PORT,
+            None => return errinput!("invalid host {host}"),
+        };
+        Ok(format!("{name}:{port}"))
+    }
+
+    /// Returns connect options for the URL, loading TLS certificates if any.
+    pub fn options(&self) -> Result<ConnectOptions> {
+        let mut opts = ConnectOptions::new().compression(self.compression);
+        if let Some(ca) = &self.tls_ca {
+            let identity = self.tls_cert.as_deref().zip(self.tls_key.as_deref());
+            opts = opts.tls(ca, identity)?;
+        }
+        if let Some(user) = &self.user {
+            opts = opts.credentials(user, self.password.as_deref().unwrap_or_default());
+        }
+        Ok(opts)
+    }
+}
+
+impl std::str::FromStr for ConnectUrl {
+    type Err = crate::error::Error;
+
+    fn from_str(url: &str) -> Result<Self> {
+        Self::parse(url)
+    }
+}
+
+/// Decodes a percent-encoded URL component.
+fn percent_decode(s: &str) -> Result<String> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut iter = s.bytes();
+    while let Some(b) = iter.next() {
+        if b != b'%' {
+            bytes.push(b);
+            continue;
+        }
+        let hex = [iter.next().unwrap_or(0), iter.next().unwrap_or(0)];
+        match std::str::from_utf8(&hex).ok().and_then(|h| u8::from_str_radix(h, 16).ok()) {
+            Some(b) => bytes.push(b),
+            None => return errinput!("invalid percent-encoding in {s}"),
+        }
+    }
+    String::from_utf8(bytes).or_else(|_| errinput!("invalid UTF-8 in {s}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Valid URLs are parsed, with default ports and decoded credentials.
+    #[test]
+    fn parse() -> Result<()> {
+        assert_eq!(
+            ConnectUrl::parse("toydb://localhost")?,
+            ConnectUrl { hosts: vec!["localhost:9605".into()], ..Default::default() }
+        );
+        assert_eq!(
+            ConnectUrl::parse("toydb://alice:p%40ss%3A@n1:9601,n2,[::1]:9603/")?,
+            ConnectUrl {
+                hosts: vec!["n1:9601".into(), "n2:9605".into(), "[::1]:9603".into()],
+                user: Some("alice".into()),
+                password: Some("p@ss:".into()),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            ConnectUrl::parse(
+                "toydb://bob@[::1]/?tls_ca=ca.pem&tls_cert=c.pem&tls_key=k.pem&compression=true"
+            )?,
+            ConnectUrl {
+                hosts: vec!["[::1]:9605".into()],
+                user: Some("bob".into()),
+                tls_ca: Some("ca.pem".into()),
+                tls_cert: Some("c.pem".into()),
+                tls_key: Some("k.pem".into()),
+                compression: true,
+                ..Default::default()
+            }
+        );
+        Ok(())
+    }
+
+    /// Invalid URLs error.
+    #[test]
+    fn parse_invalid() {
+        for url in [
+            "localhost:9605",
+            "http://localhost",
+            "toydb://",
+            "toydb://n1,",
+            "toydb://n1:port",
+            "toydb://n1:99999",
+            "toydb://[::1",
+            "toydb://[::1]x",
+            "toydb://@n1",
+            "toydb://n1/db",
+            "toydb://n1?foo=bar",
+            "toydb://n1?tls_ca",
+            "toydb://n1?tls_ca=ca.pem&tls_cert=c.pem",
+            "toydb://n1?tls_cert=c.pem&tls_key=k.pem",
+            "toydb://a%zz@n1",
+            "toydb://n1?compression=yes",
+        ] {
+            assert!(ConnectUrl::parse(url).is_err(), "{url} parsed");
+        }
+    }
+}
@@ -0,0 +1,127 @@
+//! An embedded toyDB database, which runs the SQL engine directly on local
+//! storage in the calling process, like SQLite. There is no server, Raft, or
+//! network involved, and only the local process can access the database.
+//!
+//! ```no_run
+//! # fn main() -> toydb::error::Result<()> {
+//! let db = toydb::Database::open("data/toydb.db")?;
+//! db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, value STRING)")?;
+//! db.execute("INSERT INTO t VALUES (1, 'a')")?;
+//!
+//! let mut session = db.session();
+//! session.execute("BEGIN")?;
+//! let value: String = session.execute("SELECT value FROM t WHERE id = 1")?.try_into()?;
+//! session.execute("COMMIT")?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The database file uses the same format as a server's SQL storage, but
+//! without Raft metadata, so it can't be shared with a server. It's locked
+//! while open, so only one process can use it at a time. The data itself is
+//! stored in log segment files next to it, see `storage::BitCask`.
+
+use crate::error::Result;
+use crate::sql::engine::{Local, Session, StatementResult};
+use crate::storage;
+
+use std::path::Path;
+
+/// The garbage fraction at which the database file is compacted when opened.
+const COMPACT_MIN_FRACTION: f64 = 0.2;
+
+/// The garbage size in bytes at which the database file is compacted when
+/// opened.
+const COMPACT_MIN_BYTES: u64 = 1_000_000;
+
+/// An embedded database, see the module documentation. Any storage engine can
+/// be used, e.g. `storage::Memory` for an in-memory database.
+pub struct Database<E: storage::Engine + 'static = storage::BitCask> {
+    /// The local SQL engine.
+    engine: Local<E>,
+}
+
+impl Database<storage::BitCask> {
+    /// Opens or creates a database in the given file, creating parent
+    /// directories as needed. The file is compacted if it has a lot of garbage.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let engine = storage::BitCask::new_compact(path, COMPACT_MIN_FRACTION, COMPACT_MIN_BYTES)?;
+        Ok(Self::new(engine))
+    }
+}
+
+impl<E: storage::Engine> Database<E> {
+    /// Creates a database using the given storage engine.
+    pub fn new(engine: E) -> Self {
+        Self { engine: Local::new(engine) }
+    }
+
+    /// Creates a new session. Sessions can run explicit transactions and
+    /// prepared statements, and several sessions can be used concurrently.
+    pub fn session(&self) -> Session<'_, Local<E>> {
+        Session::new(&self.engine)
+    }
+
+    /// Executes a single statement in a new session. An explicit transaction
+    /// begun by the statement is rolled back, use session() instead.
+    pub fn execute(&self, statement: &str) -> Result<StatementResult> {
+        self.session().execute(statement)
+    }
+
+    /// Flushes writes to durable storage. Writes are otherwise only flushed
+    /// by the operating system.
+    pub fn flush(&self) -> Result<()> {
+        self.engine.mvcc.engine.write()?.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::types::Value;
+
+    /// A database persists data across reopens.
+    #[test]
+    fn reopen() -> Result<()> {
+        let dir = tempfile::TempDir::with_prefix("toydb")?;
+        let path = dir.path().join("data").join("toydb.db");
+
+        let db = Database::open(&path)?;
+        db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, value STRING)")?;
+        let mut session = db.session();
+        session.execute("BEGIN")?;
+        session.execute("INSERT INTO t VALUES (1, 'a'), (2, 'b')")?;
+        session.execute("COMMIT")?;
+        db.flush()?;
+
+        // The file is locked while open.
+        assert!(Database::open(&path).is_err());
+        drop(session);
+        drop(db);
+
+        let db = Database::open(&path)?;
+        let count = db.execute("SELECT COUNT(*) FROM t")?;
+        assert_eq!(Value::try_from(count)?, Value::Integer(2));
+        Ok(())
+    }
+
+    /// Sessions are isolated, and an explicit transaction begun via execute()
+    /// is rolled back.
+    #[test]
+    fn sessions() -> Result<()> {
+        let db = Database::new(storage::Memory::new());
+        db.execute("CREATE TABLE t (id INTEGER PRIMARY KEY)")?;
+
+        let mut s1 = db.session();
+        s1.execute("BEGIN")?;
+        s1.execute("INSERT INTO t VALUES (1)")?;
+        assert_eq!(Value::try_from(db.execute("SELECT COUNT(*) FROM t")?)?, Value::Integer(0));
+        s1.execute("COMMIT")?;
+        assert_eq!(Value::try_from(db.execute("SELECT COUNT(*) FROM t")?)?, Value::Integer(1));
+
+        db.execute("BEGIN")?;
+        assert_eq!(Value::try_from(db.execute("SELECT COUNT(*) FROM t")?)?, Value::Integer(1));
+        Ok(())
+    }
+}
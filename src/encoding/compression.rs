@@ -0,0 +1,73 @@
+//! Zstandard compression, used to reduce the size of large network payloads
+//! such as Raft log entry batches sent to lagging followers. See:
+//! https://facebook.github.io/zstd/
+//!
+//! Compression only pays off for reasonably large payloads, so callers should
+//! only compress data above some size threshold.
+
+use crate::errdata;
+use crate::error::Result;
+
+use std::io::Read as _;
+
+/// The Zstandard compression level. Level 1 is the fastest, trading a bit of
+/// compression ratio for much lower CPU usage on the replication path.
+const LEVEL: i32 = 1;
+
+/// Compresses a byte slice.
+pub fn compress(bytes: &[u8]) -> Vec<u8> {
+    // Compressing an in-memory buffer can't fail, other than on allocation
+    // failure, so panic on errors.
+    zstd::bulk::compress(bytes, LEVEL).expect("zstd compression failed")
+}
+
+/// Decompresses a byte slice, erroring if the decompressed size exceeds the
+/// given limit. This guards against decompression bombs, where a small
+/// compressed payload expands into a huge allocation.
+pub fn decompress(bytes: &[u8], max_size: usize) -> Result<Vec<u8>> {
+    let mut decoder = match zstd::stream::Decoder::new(bytes) {
+        Ok(decoder) => decoder,
+        Err(err) => return errdata!("invalid compressed data: {err}"),
+    };
+    let mut output = Vec::new();
+    if let Err(err) = decoder.by_ref().take(max_size as u64 + 1).read_to_end(&mut output) {
+        return errdata!("invalid compressed data: {err}");
+    }
+    if output.len() > max_size {
+        return errdata!("decompressed size exceeds maximum {max_size}");
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Compressed data should roundtrip, and be smaller for repetitive data.
+    #[test]
+    fn roundtrip() -> Result<()> {
+        let data = b"toydb".repeat(1000);
+        let compressed = compress(&data);
+        assert!(compressed.len() < data.len());
+        assert_eq!(decompress(&compressed, data.len())?, data);
+
+        assert_eq!(decompress(&compress(&[]), 0)?, Vec::<u8>::new());
+        Ok(())
+    }
+
+    /// Decompressing garbage or truncated data should error.
+    #[test]
+    fn invalid() {
+        assert!(decompress(b"not zstd", 1024).is_err());
+        let compressed = compress(&b"toydb".repeat(1000));
+        assert!(decompress(&compressed[..compressed.len() - 1], 5000).is_err());
+    }
+
+    /// Decompressing beyond the size limit should error.
+    #[test]
+    fn max_size() {
+        let compressed = compress(&[0; 1_000_000]);
+        assert!(compressed.len() < 1000);
+        assert!(decompress(&compressed, 999_999).is_err());
+    }
+}
@@ -1,7 +1,7 @@
 //! Decodes and formats raw keys and values, recursively as needed. Handles both
 //! Raft, MVCC, SQL, and raw binary data.
 
-use super::{bincode, Key as _, Value as _};
+use super::{bincode, Key as _, Value as _, Versioned as _};
 use crate::raft;
 use crate::sql;
 use crate::storage::mvcc;
@@ -75,14 +75,14 @@ impl<I: Formatter> Formatter for Raft<I> {
             return Raw::value(key, value);
         };
         match key {
-            raft::Key::CommitIndex => {
-                match bincode::deserialize::<(raft::Index, raft::Term)>(value) {
+            raft::Key::CommitIndex | raft::Key::Snapshot => {
+                match <(raft::Index, raft::Term)>::decode_versioned(value) {
                     Ok((index, term)) => format!("{index}@{term}"),
                     Err(_) => Raw::bytes(value),
                 }
             }
             raft::Key::TermVote => {
-                match bincode::deserialize::<(raft::Term, Option<raft::NodeID>)>(value) {
+                match <(raft::Term, Option<raft::NodeID>)>::decode_versioned(value) {
                     Ok((term, vote)) => format!(
                         "term={term} vote={}",
                         vote.map(|v| v.to_string()).unwrap_or("None".to_string())
@@ -90,7 +90,7 @@ impl<I: Formatter> Formatter for Raft<I> {
                     Err(_) => Raw::bytes(value),
                 }
             }
-            raft::Key::Entry(_) => match bincode::deserialize::<raft::Entry>(value) {
+            raft::Key::Entry(_) => match raft::Entry::decode_versioned(value) {
                 Ok(entry) => Self::entry(&entry),
                 Err(_) => Raw::bytes(value),
             },
@@ -114,9 +114,20 @@ impl<I: Formatter> Formatter for MVCC<I> {
             mvcc::Key::Unversioned(innerkey) => {
                 format!("mvcc:Unversioned({})", I::key(&innerkey))
             }
-            mvcc::Key::NextVersion | mvcc::Key::TxnActive(_) | mvcc::Key::TxnActiveSnapshot(_) => {
-                format!("mvcc:{key:?}")
+            mvcc::Key::TxnUndo(version, depth, innerkey) => {
+                format!("mvcc:TxnUndo({version}, {depth}, {})", I::key(&innerkey))
             }
+            mvcc::Key::TxnRead(version, innerkey) => {
+                format!("mvcc:TxnRead({version}, {})", I::key(&innerkey))
+            }
+            mvcc::Key::TxnScan(version, start, end) => {
+                let fend = if end.is_empty() { String::new() } else { I::key(&end) };
+                format!("mvcc:TxnScan({version}, {}..{fend})", I::key(&start))
+            }
+            mvcc::Key::NextVersion
+            | mvcc::Key::TxnActive(_)
+            | mvcc::Key::TxnActiveSnapshot(_)
+            | mvcc::Key::TxnSavepoints(_) => format!("mvcc:{key:?}"),
         }
     }
 
@@ -135,13 +146,34 @@ impl<I: Formatter> Formatter for MVCC<I> {
                 };
                 format!("{{{}}}", active.iter().map(|v| v.to_string()).join(","))
             }
-            mvcc::Key::TxnActive(_) | mvcc::Key::TxnWrite(_, _) => Raw::bytes(value),
+            mvcc::Key::TxnActive(_)
+            | mvcc::Key::TxnWrite(_, _)
+            | mvcc::Key::TxnRead(_, _)
+            | mvcc::Key::TxnScan(_, _, _) => Raw::bytes(value),
             mvcc::Key::Version(userkey, _) => match bincode::deserialize(value) {
                 Ok(Some(value)) => I::value(&userkey, value),
                 Ok(None) => "None".to_string(),
                 Err(_) => Raw::bytes(value),
             },
             mvcc::Key::Unversioned(userkey) => I::value(&userkey, value),
+            mvcc::Key::TxnSavepoints(_) => {
+                let Ok(names) = bincode::deserialize::<Vec<String>>(value) else {
+                    return Raw::bytes(value);
+                };
+                format!("[{}]", names.join(","))
+            }
+            // The undo value is the raw version value, or None if unwritten.
+            mvcc::Key::TxnUndo(_, _, userkey) => {
+                match bincode::deserialize::<Option<Vec<u8>>>(value) {
+                    Ok(Some(value)) => match bincode::deserialize(&value) {
+                        Ok(Some(value)) => I::value(&userkey, value),
+                        Ok(None) => "None".to_string(),
+                        Err(_) => Raw::bytes(&value),
+                    },
+                    Ok(None) => "unwritten".to_string(),
+                    Err(_) => Raw::bytes(value),
+                }
+            }
         }
     }
 }
@@ -158,16 +190,8 @@ impl SQL {
         let re = regex::Regex::new(r#"\n\s*"#).expect("regex failed");
         re.replace_all(&table.to_string(), " ").into_owned()
     }
-}
 
-impl Formatter for SQL {
-    fn key(key: &[u8]) -> String {
-        // Special-case the applied_index key.
-        if key == sql::engine::Raft::APPLIED_INDEX_KEY {
-            return String::from_utf8_lossy(key).into_owned();
-        }
-
-        let Ok(key) = sql::engine::Key::decode(key) else { return Raw::key(key) };
+    fn format_key(key: sql::engine::Key) -> String {
         match key {
             sql::engine::Key::Table(name) => format!("sql:Table({name})"),
             sql::engine::Key::Index(table, column, value) => {
@@ -176,37 +200,109 @@ impl Formatter for SQL {
             sql::engine::Key::Row(table, id) => {
                 format!("sql:Row({table}, {id})")
             }
+            sql::engine::Key::User(name) => format!("sql:User({name})"),
+            sql::engine::Key::Role(name) => format!("sql:Role({name})"),
+            sql::engine::Key::Changefeed(name) => format!("sql:Changefeed({name})"),
+            sql::engine::Key::Trigger(table, name) => format!("sql:Trigger({table}, {name})"),
+            sql::engine::Key::Function(name) => format!("sql:Function({name})"),
+            sql::engine::Key::FullText(table, column, term) => {
+                format!("sql:FullText({table}.{column}, {term})")
+            }
+            sql::engine::Key::Database(name) => format!("sql:Database({name})"),
+            sql::engine::Key::InDatabase(database, key) => {
+                format!("sql:InDatabase({database}, {})", Self::format_key(*key))
+            }
+            sql::engine::Key::IndexBuild(table, column) => {
+                format!("sql:IndexBuild({table}.{column})")
+            }
+        }
+    }
+}
+
+impl Formatter for SQL {
+    fn key(key: &[u8]) -> String {
+        // Special-case the applied_index key.
+        if key == sql::engine::Raft::APPLIED_INDEX_KEY {
+            return String::from_utf8_lossy(key).into_owned();
         }
+
+        let Ok(key) = sql::engine::Key::decode(key) else { return Raw::key(key) };
+        Self::format_key(key)
     }
 
     fn value(key: &[u8], value: &[u8]) -> String {
         // Special-case the applied_index key.
         if key == sql::engine::Raft::APPLIED_INDEX_KEY {
-            if let Ok(applied_index) = bincode::deserialize::<raft::Index>(value) {
+            if let Ok(applied_index) = raft::Index::decode_versioned(value) {
                 return applied_index.to_string();
             }
         }
 
         let Ok(key) = sql::engine::Key::decode(key) else { return Raw::key(value) };
+        // Values in non-default databases are formatted like their inner key.
+        let key = match key {
+            sql::engine::Key::InDatabase(_, key) => *key,
+            key => key,
+        };
         match key {
             sql::engine::Key::Table(_) => {
-                let Ok(table) = bincode::deserialize::<sql::types::Table>(value) else {
+                let Ok(table) = sql::types::Table::decode_versioned(value) else {
                     return Raw::bytes(value);
                 };
                 Self::schema(table)
             }
             sql::engine::Key::Row(_, _) => {
-                let Ok(row) = bincode::deserialize::<sql::types::Row>(value) else {
+                let Ok(row) = sql::types::Row::decode_versioned(value) else {
                     return Raw::bytes(value);
                 };
                 Self::values(row)
             }
-            sql::engine::Key::Index(_, _, _) => {
-                let Ok(index) = bincode::deserialize::<BTreeSet<sql::types::Value>>(value) else {
+            sql::engine::Key::Index(_, _, _) | sql::engine::Key::FullText(_, _, _) => {
+                let Ok(index) = BTreeSet::<sql::types::Value>::decode_versioned(value) else {
                     return Raw::bytes(value);
                 };
                 Self::values(index)
             }
+            // Don't display password hashes.
+            sql::engine::Key::User(_) => {
+                let Ok(user) = sql::types::User::decode_versioned(value) else {
+                    return Raw::bytes(value);
+                };
+                user.name
+            }
+            sql::engine::Key::Role(_) => {
+                let Ok(role) = sql::types::Role::decode_versioned(value) else {
+                    return Raw::bytes(value);
+                };
+                role.name
+            }
+            sql::engine::Key::Changefeed(_) => {
+                let Ok(changefeed) = sql::types::Changefeed::decode_versioned(value) else {
+                    return Raw::bytes(value);
+                };
+                changefeed.to_string()
+            }
+            sql::engine::Key::Trigger(_, _) => {
+                let Ok(trigger) = sql::types::Trigger::decode_versioned(value) else {
+                    return Raw::bytes(value);
+                };
+                trigger.to_string()
+            }
+            sql::engine::Key::Function(_) => {
+                let Ok(function) = sql::types::Function::decode_versioned(value) else {
+                    return Raw::bytes(value);
+                };
+                function.to_string()
+            }
+            sql::engine::Key::Database(_) => {
+                let Ok(database) = sql::types::Database::decode_versioned(value) else {
+                    return Raw::bytes(value);
+                };
+                database.to_string()
+            }
+            sql::engine::Key::InDatabase(_, _) | sql::engine::Key::IndexBuild(_, _) => {
+                Raw::bytes(value)
+            }
         }
     }
 }
@@ -225,21 +321,54 @@ impl Formatter for SQLCommand {
             return Raw::bytes(value);
         };
 
+        // Writes in non-default databases are prefixed by the database name.
+        let (fdatabase, write) = match write {
+            sql::engine::Write::InDatabase { database, write } => {
+                (format!("[{database}] "), *write)
+            }
+            write => (String::new(), write),
+        };
+
         let txn = match &write {
-            sql::engine::Write::Begin => None,
+            sql::engine::Write::Begin
+            | sql::engine::Write::BeginSerializable
+            | sql::engine::Write::Read(_)
+            | sql::engine::Write::InDatabase { .. } => None,
             sql::engine::Write::Commit(txn)
             | sql::engine::Write::Rollback(txn)
             | sql::engine::Write::Delete { txn, .. }
             | sql::engine::Write::Insert { txn, .. }
             | sql::engine::Write::Update { txn, .. }
             | sql::engine::Write::CreateTable { txn, .. }
-            | sql::engine::Write::DropTable { txn, .. } => Some(txn),
+            | sql::engine::Write::DropTable { txn, .. }
+            | sql::engine::Write::CreateUser { txn, .. }
+            | sql::engine::Write::AlterUser { txn, .. }
+            | sql::engine::Write::CreateRole { txn, .. }
+            | sql::engine::Write::AlterRole { txn, .. }
+            | sql::engine::Write::DropRole { txn, .. }
+            | sql::engine::Write::CreateChangefeed { txn, .. }
+            | sql::engine::Write::DropChangefeed { txn, .. }
+            | sql::engine::Write::CreateTrigger { txn, .. }
+            | sql::engine::Write::DropTrigger { txn, .. }
+            | sql::engine::Write::CreateFunction { txn, .. }
+            | sql::engine::Write::DropFunction { txn, .. }
+            | sql::engine::Write::CreateDatabase { txn, .. }
+            | sql::engine::Write::DropDatabase { txn, .. }
+            | sql::engine::Write::CreateIndex { txn, .. }
+            | sql::engine::Write::BackfillIndex { txn, .. }
+            | sql::engine::Write::PublishIndex { txn, .. }
+            | sql::engine::Write::SetStatistics { txn, .. }
+            | sql::engine::Write::Savepoint { txn, .. }
+            | sql::engine::Write::RollbackToSavepoint { txn, .. }
+            | sql::engine::Write::ReleaseSavepoint { txn, .. } => Some(txn),
         };
         let ftxn =
             txn.filter(|t| !t.read_only).map(|t| format!("t{} ", t.version)).unwrap_or_default();
 
         let fcommand = match write {
             sql::engine::Write::Begin => "BEGIN".to_string(),
+            sql::engine::Write::BeginSerializable => "BEGIN SERIALIZABLE".to_string(),
+            sql::engine::Write::Read(read) => format!("READ {read:?}"),
             sql::engine::Write::Commit(_) => "COMMIT".to_string(),
             sql::engine::Write::Rollback(_) => "ROLLBACK".to_string(),
             sql::engine::Write::Delete { table, ids, .. } => {
@@ -257,7 +386,55 @@ impl Formatter for SQLCommand {
             ),
             sql::engine::Write::CreateTable { schema, .. } => SQL::schema(schema),
             sql::engine::Write::DropTable { table, .. } => format!("DROP TABLE {table}"),
+            sql::engine::Write::CreateUser { user, .. } => format!("CREATE USER {}", user.name),
+            sql::engine::Write::AlterUser { user, .. } => format!("ALTER USER {}", user.name),
+            sql::engine::Write::CreateRole { role, .. } => format!("CREATE ROLE {}", role.name),
+            sql::engine::Write::AlterRole { role, .. } => format!("ALTER ROLE {}", role.name),
+            sql::engine::Write::DropRole { role, .. } => format!("DROP ROLE {role}"),
+            sql::engine::Write::CreateChangefeed { changefeed, .. } => changefeed.to_string(),
+            sql::engine::Write::DropChangefeed { name, .. } => format!("DROP CHANGEFEED {name}"),
+            sql::engine::Write::CreateTrigger { trigger, .. } => trigger.to_string(),
+            sql::engine::Write::DropTrigger { table, name, .. } => {
+                format!("DROP TRIGGER {name} ON {table}")
+            }
+            sql::engine::Write::CreateFunction { function, .. } => function.to_string(),
+            sql::engine::Write::DropFunction { name, .. } => format!("DROP FUNCTION {name}"),
+            sql::engine::Write::CreateDatabase { database, .. } => database.to_string(),
+            sql::engine::Write::DropDatabase { database, .. } => {
+                format!("DROP DATABASE {database}")
+            }
+            sql::engine::Write::CreateIndex { table, column, .. } => {
+                format!("CREATE INDEX ON {table} ({column})")
+            }
+            sql::engine::Write::BackfillIndex { table, column, entries, .. } => {
+                let entry = |value: Option<sql::types::Value>| match value {
+                    Some(value) => value.to_string(),
+                    None => "∅".to_string(),
+                };
+                format!(
+                    "BACKFILL INDEX {table}.{column} {}",
+                    entries
+                        .into_iter()
+                        .map(|(id, old, new)| format!("{id}:{}→{}", entry(old), entry(new)))
+                        .join(" ")
+                )
+            }
+            sql::engine::Write::PublishIndex { table, column, .. } => {
+                format!("PUBLISH INDEX ON {table} ({column})")
+            }
+            sql::engine::Write::SetStatistics { table, statistics, .. } => {
+                format!("ANALYZE {table} ({} rows)", statistics.rows)
+            }
+            sql::engine::Write::Savepoint { name, .. } => format!("SAVEPOINT {name}"),
+            sql::engine::Write::RollbackToSavepoint { name, .. } => {
+                format!("ROLLBACK TO SAVEPOINT {name}")
+            }
+            sql::engine::Write::ReleaseSavepoint { name, .. } => {
+                format!("RELEASE SAVEPOINT {name}")
+            }
+            // Writes aren't nested in multiple databases.
+            sql::engine::Write::InDatabase { .. } => return Raw::bytes(value),
         };
-        format!("{ftxn}{fcommand}")
+        format!("{ftxn}{fdatabase}{fcommand}")
     }
 }
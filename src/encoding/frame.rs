@@ -0,0 +1,728 @@
+//! Framed network protocol, used both for client/server and Raft peer traffic.
+//! Each message is sent as a length-prefixed frame with an explicit message
+//! type, such that a reader can delimit and dispatch messages without knowing
+//! their payload encoding:
+//!
+//! ```text
+//! +----------------+------------------+-----------+---------------------+
+//! | length: u32 BE | checksum: u32 BE | kind: u8  | payload: length-1 B |
+//! +----------------+------------------+-----------+---------------------+
+//! ```
+//!
+//! The checksum is a CRC32 checksum of the kind and payload, which detects
+//! corrupted frames before they're passed on to the Raft or SQL layers. The
+//! length is limited to MAX_SIZE, and the payload buffer is grown as bytes
+//! arrive rather than allocated upfront, such that a corrupted or malicious
+//! length can't cause huge allocations. Decompressed payloads and chunked
+//! messages are limited to MAX_SIZE too.
+//!
+//! Connections begin with a handshake negotiating the protocol version. The
+//! connecting side sends a Hello frame with the payload "toyDB" followed by
+//! the lowest and highest protocol version it supports (u16 BE each), and a
+//! byte of optional feature flags (0x01: compression). The accepting side
+//! responds with a Hello frame for the highest common version (as both the
+//! lowest and highest version) and the features enabled by both sides, or an
+//! Error frame containing a UTF-8 error message if there is none. This allows
+//! nodes and clients of different versions to talk to each other, as long as
+//! they share a protocol version.
+//!
+//! If compression was negotiated, large responses may be sent as
+//! ResponseCompressed frames, containing a Zstandard-compressed Response
+//! payload. Requests are typically small, and aren't compressed.
+//!
+//! Requests and responses larger than CHUNK_SIZE (e.g. rows with large values,
+//! or buffered result sets) are split into chunks, such that they can be
+//! encoded and decoded incrementally without materializing the entire message
+//! payload on either end. The payload is sent as a sequence of Chunk frames
+//! (or ChunkCompressed frames with a compressed chunk), ending with a frame of
+//! the message's kind containing the final chunk:
+//!
+//! ```text
+//! Chunk(64 KB) → Chunk(64 KB) → ... → Response(≤ 64 KB)
+//! ```
+//!
+//! In protocol version 1, the payloads of Request, Response, and RaftMessage
+//! frames are Bincode-encoded server::Request, Result<server::Response>, and
+//! raft::Envelope respectively. RaftMessageCompressed frames contain a
+//! Zstandard-compressed RaftMessage payload, and RaftHandshake frames contain
+//! a single byte with flags (0x01: compression).
+
+use super::{compression, Value};
+use crate::errdata;
+use crate::error::{Error, Result};
+
+use std::io::{Read, Write};
+
+/// The magic bytes at the start of a Hello frame.
+const MAGIC: &[u8] = b"toyDB";
+
+/// The lowest supported protocol version.
+pub const MIN_VERSION: u16 = 1;
+
+/// The current (highest supported) protocol version.
+pub const VERSION: u16 = 1;
+
+/// The maximum frame size, and also the maximum size of a decompressed payload
+/// or chunked message. This guards against allocating huge buffers when
+/// reading garbage, e.g. from a client speaking a different protocol.
+pub const MAX_SIZE: u32 = 512 * 1024 * 1024;
+
+/// The minimum payload size before a frame is compressed, if compression is
+/// enabled. Compressing small messages isn't worth the CPU cost.
+pub const COMPRESSION_THRESHOLD: usize = 4096;
+
+/// The maximum payload size of a chunked message's frames.
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+/// A frame's message type. The numeric IDs are part of the protocol, and must
+/// never change.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Kind {
+    /// A protocol handshake.
+    Hello = 1,
+    /// A handshake error, with a UTF-8 error message.
+    Error = 2,
+    /// A SQL client request.
+    Request = 3,
+    /// A SQL server response.
+    Response = 4,
+    /// A Raft peer connection handshake, with connection flags.
+    RaftHandshake = 5,
+    /// A Raft message.
+    RaftMessage = 6,
+    /// A compressed Raft message.
+    RaftMessageCompressed = 7,
+    /// A compressed SQL server response.
+    ResponseCompressed = 8,
+    /// A chunk of a message, followed by further chunks.
+    Chunk = 9,
+    /// A compressed chunk of a message, followed by further chunks.
+    ChunkCompressed = 10,
+}
+
+impl Kind {
+    /// Returns the compressed variant of the kind, if it can be compressed.
+    fn compressed(self) -> Option<Self> {
+        match self {
+            Self::Response => Some(Self::ResponseCompressed),
+            Self::RaftMessage => Some(Self::RaftMessageCompressed),
+            Self::Chunk => Some(Self::ChunkCompressed),
+            _ => None,
+        }
+    }
+
+    /// Returns the uncompressed variant of a compressed kind.
+    fn decompressed(self) -> Option<Self> {
+        match self {
+            Self::ResponseCompressed => Some(Self::Response),
+            Self::RaftMessageCompressed => Some(Self::RaftMessage),
+            Self::ChunkCompressed => Some(Self::Chunk),
+            _ => None,
+        }
+    }
+}
+
+impl TryFrom<u8> for Kind {
+    type Error = Error;
+
+    fn try_from(id: u8) -> Result<Self> {
+        Ok(match id {
+            1 => Self::Hello,
+            2 => Self::Error,
+            3 => Self::Request,
+            4 => Self::Response,
+            5 => Self::RaftHandshake,
+            6 => Self::RaftMessage,
+            7 => Self::RaftMessageCompressed,
+            8 => Self::ResponseCompressed,
+            9 => Self::Chunk,
+            10 => Self::ChunkCompressed,
+            id => return errdata!("unknown frame kind {id}"),
+        })
+    }
+}
+
+/// A protocol frame.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    /// The message type.
+    pub kind: Kind,
+    /// The message payload.
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    /// Creates a new frame.
+    pub fn new(kind: Kind, payload: Vec<u8>) -> Self {
+        Self { kind, payload }
+    }
+
+    /// Creates a frame containing a Bincode-encoded value.
+    pub fn value(kind: Kind, value: &impl Value) -> Self {
+        Self::new(kind, value.encode())
+    }
+
+    /// Reads a frame, or returns None if the reader is closed.
+    pub fn read_from(mut reader: impl Read) -> Result<Option<Self>> {
+        let mut header = [0; 9];
+        match reader.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(err) => match err.kind() {
+                std::io::ErrorKind::UnexpectedEof => return Ok(None),
+                std::io::ErrorKind::ConnectionReset => return Ok(None),
+                _ => return Err(err.into()),
+            },
+        }
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+        if length == 0 || length > MAX_SIZE {
+            return errdata!("invalid frame length {length}");
+        }
+        let checksum = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+        // Grow the buffer as bytes arrive, rather than trusting the length.
+        let mut payload = Vec::new();
+        reader.by_ref().take(length as u64 - 1).read_to_end(&mut payload)?;
+        if payload.len() != length as usize - 1 {
+            return Err(Error::IO("unexpected end of frame".into()));
+        }
+        if Self::checksum(header[8], &payload) != checksum {
+            return errdata!("frame checksum mismatch");
+        }
+        let kind = Kind::try_from(header[8])?;
+        Ok(Some(Self { kind, payload }))
+    }
+
+    /// Computes the checksum of a frame's kind and payload.
+    fn checksum(kind: u8, payload: &[u8]) -> u32 {
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&[kind]);
+        hasher.update(payload);
+        hasher.finalize()
+    }
+
+    /// Compresses the frame if its kind can be compressed and its payload is
+    /// large, unless that doesn't make it smaller.
+    pub fn compress(self) -> Self {
+        let Some(kind) = self.kind.compressed() else {
+            return self;
+        };
+        if self.payload.len() < COMPRESSION_THRESHOLD {
+            return self;
+        }
+        let compressed = compression::compress(&self.payload);
+        if compressed.len() >= self.payload.len() {
+            return self;
+        }
+        Self::new(kind, compressed)
+    }
+
+    /// Decompresses the frame, if it's compressed.
+    pub fn decompress(self) -> Result<Self> {
+        match self.kind.decompressed() {
+            Some(kind) => {
+                let payload = compression::decompress(&self.payload, MAX_SIZE as usize)?;
+                Ok(Self::new(kind, payload))
+            }
+            None => Ok(self),
+        }
+    }
+
+    /// Reads a message of the given kind and decodes its Bincode payload, or
+    /// returns None if the reader is closed. The message may be chunked and
+    /// compressed, and is decoded incrementally as chunks are read.
+    pub fn read_value<V: Value>(mut reader: impl Read, kind: Kind) -> Result<Option<V>> {
+        let Some(frame) = Self::read_from(&mut reader)? else {
+            return Ok(None);
+        };
+        let mut chunks = ChunkReader::new(reader, kind, frame)?;
+        let result = V::decode_from(&mut chunks);
+        // Frame errors take precedence over the resulting decode errors.
+        if let Some(err) = chunks.error.take() {
+            return Err(err);
+        }
+        let value = result?;
+        chunks.finish()?;
+        Ok(Some(value))
+    }
+
+    /// Writes a message of the given kind containing a Bincode-encoded value.
+    /// The value is encoded incrementally, and chunked if it's large. If
+    /// compress is true, large frames are compressed.
+    pub fn write_value(
+        writer: impl Write,
+        kind: Kind,
+        value: &impl Value,
+        compress: bool,
+    ) -> Result<()> {
+        let mut chunks = ChunkWriter::new(writer, kind, compress);
+        value.encode_into(&mut chunks)?;
+        chunks.finish()?;
+        Ok(())
+    }
+
+    /// Writes the frame, using a single write call.
+    pub fn write_to(&self, mut writer: impl Write) -> Result<()> {
+        let length = self.payload.len() + 1;
+        if length > MAX_SIZE as usize {
+            return errdata!("frame size {length} exceeds maximum {MAX_SIZE}");
+        }
+        let mut bytes = Vec::with_capacity(length + 8);
+        bytes.extend((length as u32).to_be_bytes());
+        bytes.extend(Self::checksum(self.kind as u8, &self.payload).to_be_bytes());
+        bytes.push(self.kind as u8);
+        bytes.extend(&self.payload);
+        Ok(writer.write_all(&bytes)?)
+    }
+}
+
+/// Writes a message as a sequence of frames, see the module documentation.
+/// Buffers up to CHUNK_SIZE bytes, and writes a Chunk frame whenever the buffer
+/// is full. finish() must be called to write the final frame.
+struct ChunkWriter<W: Write> {
+    writer: W,
+    /// The message kind.
+    kind: Kind,
+    /// Whether to compress large frames.
+    compress: bool,
+    /// The buffered payload of the next frame.
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> ChunkWriter<W> {
+    /// Creates a new chunk writer for a message of the given kind.
+    fn new(writer: W, kind: Kind, compress: bool) -> Self {
+        Self { writer, kind, compress, buffer: Vec::new() }
+    }
+
+    /// Writes a frame with the buffered payload.
+    fn write_frame(&mut self, kind: Kind) -> Result<()> {
+        let mut frame = Frame::new(kind, std::mem::take(&mut self.buffer));
+        if self.compress {
+            frame = frame.compress();
+        }
+        frame.write_to(&mut self.writer)
+    }
+
+    /// Writes the final frame of the message, returning the inner writer.
+    fn finish(mut self) -> Result<W> {
+        self.write_frame(self.kind)?;
+        Ok(self.writer)
+    }
+}
+
+impl<W: Write> Write for ChunkWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.buffer.len() == CHUNK_SIZE {
+            self.write_frame(Kind::Chunk).map_err(std::io::Error::other)?;
+        }
+        let n = buf.len().min(CHUNK_SIZE - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Reads a message payload from a sequence of frames, see the module
+/// documentation. Frames are read as needed.
+struct ChunkReader<R: Read> {
+    reader: R,
+    /// The message kind.
+    kind: Kind,
+    /// The current frame's (decompressed) payload.
+    chunk: Vec<u8>,
+    /// The read position in the current chunk.
+    position: usize,
+    /// The total (decompressed) payload size read so far.
+    size: usize,
+    /// Whether the final frame has been read.
+    done: bool,
+    /// A frame error, which is returned to the caller as an IO error. The
+    /// original error is retained here for the caller.
+    error: Option<Error>,
+}
+
+impl<R: Read> ChunkReader<R> {
+    /// Creates a new chunk reader, starting with the given frame.
+    fn new(reader: R, kind: Kind, frame: Frame) -> Result<Self> {
+        let mut chunks = Self {
+            reader,
+            kind,
+            chunk: Vec::new(),
+            position: 0,
+            size: 0,
+            done: false,
+            error: None,
+        };
+        chunks.push(frame)?;
+        Ok(chunks)
+    }
+
+    /// Makes the given frame the current chunk.
+    fn push(&mut self, frame: Frame) -> Result<()> {
+        let frame = frame.decompress()?;
+        match frame.kind {
+            Kind::Chunk => {}
+            kind if kind == self.kind => self.done = true,
+            kind => return errdata!("expected {:?} frame, got {kind:?}", self.kind),
+        }
+        self.size += frame.payload.len();
+        if self.size > MAX_SIZE as usize {
+            return errdata!("chunked {:?} message exceeds maximum size {MAX_SIZE}", self.kind);
+        }
+        self.chunk = frame.payload;
+        self.position = 0;
+        Ok(())
+    }
+
+    /// Reads the next frame.
+    fn next(&mut self) -> Result<()> {
+        match Frame::read_from(&mut self.reader)? {
+            Some(frame) => self.push(frame),
+            None => errdata!("connection closed during chunked {:?} message", self.kind),
+        }
+    }
+
+    /// Reads the remaining frames of the message, which must be empty.
+    fn finish(mut self) -> Result<()> {
+        loop {
+            if self.position < self.chunk.len() {
+                return errdata!("unexpected trailing bytes in {:?} message", self.kind);
+            }
+            if self.done {
+                return Ok(());
+            }
+            self.next()?;
+        }
+    }
+}
+
+impl<R: Read> Read for ChunkReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.position == self.chunk.len() && !self.done {
+            if let Err(err) = self.next() {
+                let io_err = std::io::Error::other(err.to_string());
+                self.error = Some(err);
+                return Err(io_err);
+            }
+        }
+        let n = buf.len().min(self.chunk.len() - self.position);
+        buf[..n].copy_from_slice(&self.chunk[self.position..self.position + n]);
+        self.position += n;
+        Ok(n)
+    }
+}
+
+/// A Hello handshake frame, with the range of supported protocol versions and
+/// optional features.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Hello {
+    pub min_version: u16,
+    pub max_version: u16,
+    /// Whether compression is enabled.
+    pub compression: bool,
+}
+
+impl Hello {
+    /// The compression feature flag.
+    const COMPRESSION: u8 = 0x01;
+
+    /// Creates a Hello for the protocol versions supported by this build, with
+    /// the given features.
+    pub fn new(compression: bool) -> Self {
+        Self { min_version: MIN_VERSION, max_version: VERSION, compression }
+    }
+
+    /// Decodes a Hello from a frame.
+    pub fn from_frame(frame: &Frame) -> Result<Self> {
+        if frame.kind != Kind::Hello {
+            return errdata!("expected Hello frame, got {:?}", frame.kind);
+        }
+        // Unknown feature flags are ignored, to allow adding new features.
+        let (versions, flags) = match frame.payload.strip_prefix(MAGIC) {
+            Some(&[min1, min2, max1, max2]) => ([min1, min2, max1, max2], 0),
+            Some(&[min1, min2, max1, max2, flags]) => ([min1, min2, max1, max2], flags),
+            _ => return errdata!("invalid Hello frame"),
+        };
+        Ok(Self {
+            min_version: u16::from_be_bytes([versions[0], versions[1]]),
+            max_version: u16::from_be_bytes([versions[2], versions[3]]),
+            compression: flags & Self::COMPRESSION != 0,
+        })
+    }
+
+    /// Encodes the Hello as a frame.
+    pub fn to_frame(self) -> Frame {
+        let mut payload = MAGIC.to_vec();
+        payload.extend(self.min_version.to_be_bytes());
+        payload.extend(self.max_version.to_be_bytes());
+        payload.push(if self.compression { Self::COMPRESSION } else { 0 });
+        Frame::new(Kind::Hello, payload)
+    }
+
+    /// Negotiates the highest protocol version supported by both sides, and
+    /// the features enabled by both sides.
+    pub fn negotiate(&self, other: &Hello) -> Result<Hello> {
+        let version = std::cmp::min(self.max_version, other.max_version);
+        if version < self.min_version || version < other.min_version {
+            return errdata!(
+                "no common protocol version: supports {}-{}, peer supports {}-{}",
+                self.min_version,
+                self.max_version,
+                other.min_version,
+                other.max_version
+            );
+        }
+        Ok(Hello {
+            min_version: version,
+            max_version: version,
+            compression: self.compression && other.compression,
+        })
+    }
+}
+
+/// Performs the handshake as the connecting side, requesting the given
+/// features. Returns the negotiated protocol version and features.
+pub fn connect<S: Read + Write>(stream: &mut S, compression: bool) -> Result<Hello> {
+    let hello = Hello::new(compression);
+    hello.to_frame().write_to(&mut *stream)?;
+    stream.flush()?;
+    let Some(frame) = Frame::read_from(&mut *stream)? else {
+        return Err(Error::IO("connection closed during handshake".to_string()));
+    };
+    if frame.kind == Kind::Error {
+        return Err(Error::InvalidInput(String::from_utf8_lossy(&frame.payload).into_owned()));
+    }
+    let response = Hello::from_frame(&frame)?;
+    if hello.negotiate(&response)? != response {
+        return errdata!("invalid handshake response {response:?}");
+    }
+    Ok(response)
+}
+
+/// Performs the handshake as the accepting side, allowing the given features.
+/// Returns the negotiated protocol version and features, or None if the
+/// connection was closed before the handshake. Responds with an Error frame if
+/// there is no common version.
+pub fn accept<S: Read + Write>(
+    reader: &mut std::io::BufReader<S>,
+    compression: bool,
+) -> Result<Option<Hello>> {
+    let Some(frame) = Frame::read_from(&mut *reader)? else {
+        return Ok(None);
+    };
+    let hello = Hello::new(compression);
+    let result = Hello::from_frame(&frame).and_then(|peer| hello.negotiate(&peer));
+    let response = match &result {
+        Ok(negotiated) => negotiated.to_frame(),
+        Err(err) => Frame::new(Kind::Error, err.to_string().into_bytes()),
+    };
+    response.write_to(reader.get_mut())?;
+    reader.get_mut().flush()?;
+    result.map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Frames roundtrip, and a closed reader yields None.
+    #[test]
+    fn roundtrip() -> Result<()> {
+        let frame = Frame::new(Kind::Request, b"payload".to_vec());
+        let mut bytes = Vec::new();
+        frame.write_to(&mut bytes)?;
+        assert_eq!(bytes[..4], [0, 0, 0, 8]);
+        assert_eq!(bytes[4..8], Frame::checksum(3, b"payload").to_be_bytes());
+        assert_eq!(bytes[8..], *b"\x03payload");
+
+        let mut reader = bytes.as_slice();
+        assert_eq!(Frame::read_from(&mut reader)?, Some(frame));
+        assert_eq!(Frame::read_from(&mut reader)?, None);
+
+        let value = crate::sql::types::Value::from("foo");
+        let mut bytes = Vec::new();
+        Frame::value(Kind::Response, &value).write_to(&mut bytes)?;
+        assert_eq!(Frame::read_value(bytes.as_slice(), Kind::Response)?, Some(value.clone()));
+        assert!(
+            Frame::read_value::<crate::sql::types::Value>(bytes.as_slice(), Kind::Request).is_err()
+        );
+        Ok(())
+    }
+
+    /// Large compressible frames are compressed, and small or incompressible
+    /// frames and other kinds aren't. Compressed values are decompressed when
+    /// read.
+    #[test]
+    fn compress() -> Result<()> {
+        let small = Frame::new(Kind::Response, vec![0; COMPRESSION_THRESHOLD - 1]);
+        assert_eq!(small.clone().compress(), small);
+        let request = Frame::new(Kind::Request, vec![0; COMPRESSION_THRESHOLD]);
+        assert_eq!(request.clone().compress(), request);
+        let random: Vec<u8> = (0..COMPRESSION_THRESHOLD).map(|_| rand::random()).collect();
+        let random = Frame::new(Kind::Response, random);
+        assert_eq!(random.clone().compress(), random);
+
+        let large = Frame::new(Kind::RaftMessage, vec![0; COMPRESSION_THRESHOLD]);
+        let compressed = large.clone().compress();
+        assert_eq!(compressed.kind, Kind::RaftMessageCompressed);
+        assert!(compressed.payload.len() < large.payload.len());
+        assert_eq!(compressed.decompress()?, large);
+
+        let value = crate::sql::types::Value::from("x".repeat(COMPRESSION_THRESHOLD));
+        let mut bytes = Vec::new();
+        Frame::value(Kind::Response, &value).compress().write_to(&mut bytes)?;
+        assert_eq!(bytes[8], Kind::ResponseCompressed as u8);
+        assert_eq!(Frame::read_value(bytes.as_slice(), Kind::Response)?, Some(value));
+        Ok(())
+    }
+
+    /// Large values are written as chunks, and decoded incrementally. Small
+    /// values are written as a single frame.
+    #[test]
+    fn chunks() -> Result<()> {
+        use crate::sql::types::Value;
+
+        /// The written bytes, and the frame kinds and payload sizes.
+        type Written = (Vec<u8>, Vec<(Kind, usize)>);
+
+        // Writes the value, and returns the frame kinds and payload sizes.
+        let write = |value: &Value, compress| -> Result<Written> {
+            let mut bytes = Vec::new();
+            Frame::write_value(&mut bytes, Kind::Response, value, compress)?;
+            let mut reader = bytes.as_slice();
+            let mut frames = Vec::new();
+            while let Some(frame) = Frame::read_from(&mut reader)? {
+                frames.push((frame.kind, frame.payload.len()));
+            }
+            Ok((bytes, frames))
+        };
+
+        let small = Value::from("foo");
+        let (bytes, frames) = write(&small, false)?;
+        assert_eq!(frames, vec![(Kind::Response, small.encode().len())]);
+        assert_eq!(Frame::read_value(bytes.as_slice(), Kind::Response)?, Some(small));
+
+        // A large value is split into full chunks and a final frame. The value
+        // is followed by another message, which is read separately.
+        let large = Value::from("x".repeat(2 * CHUNK_SIZE + 10));
+        let length = large.encode().len();
+        let (mut bytes, frames) = write(&large, false)?;
+        assert_eq!(
+            frames,
+            vec![
+                (Kind::Chunk, CHUNK_SIZE),
+                (Kind::Chunk, CHUNK_SIZE),
+                (Kind::Response, length - 2 * CHUNK_SIZE)
+            ]
+        );
+        Frame::write_value(&mut bytes, Kind::Response, &Value::Null, false)?;
+        let mut reader = bytes.as_slice();
+        assert_eq!(Frame::read_value(&mut reader, Kind::Response)?, Some(large.clone()));
+        assert_eq!(Frame::read_value(&mut reader, Kind::Response)?, Some(Value::Null));
+        assert_eq!(Frame::read_value::<Value>(&mut reader, Kind::Response)?, None);
+
+        // Chunks are compressed individually.
+        let (bytes, frames) = write(&large, true)?;
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[0].0, Kind::ChunkCompressed);
+        assert!(frames[0].1 < CHUNK_SIZE);
+        assert_eq!(Frame::read_value(bytes.as_slice(), Kind::Response)?, Some(large.clone()));
+
+        // Truncated messages, unexpected kinds, and trailing bytes error.
+        let (bytes, _) = write(&large, false)?;
+        assert!(Frame::read_value::<Value>(&bytes[..CHUNK_SIZE + 20], Kind::Response).is_err());
+        assert!(Frame::read_value::<Value>(bytes.as_slice(), Kind::Request).is_err());
+        let mut bytes = Vec::new();
+        Frame::new(Kind::Chunk, Value::Null.encode()).write_to(&mut bytes)?;
+        Frame::new(Kind::Response, vec![0]).write_to(&mut bytes)?;
+        assert!(Frame::read_value::<Value>(bytes.as_slice(), Kind::Response).is_err());
+        Ok(())
+    }
+
+    /// Invalid lengths, checksums, and kinds error.
+    #[test]
+    fn invalid() -> Result<()> {
+        // Builds a raw frame with the given length, kind, and payload.
+        let raw = |length: u32, kind: u8, payload: &[u8]| -> Vec<u8> {
+            let mut bytes = length.to_be_bytes().to_vec();
+            bytes.extend(Frame::checksum(kind, payload).to_be_bytes());
+            bytes.push(kind);
+            bytes.extend(payload);
+            bytes
+        };
+        assert!(Frame::read_from(raw(4, 3, b"foo").as_slice())?.is_some());
+        assert!(Frame::read_from(raw(0, 3, b"").as_slice()).is_err());
+        assert!(Frame::read_from(raw(1, 0xff, b"").as_slice()).is_err());
+        assert!(Frame::read_from(raw(8, 3, b"foo").as_slice()).is_err());
+        assert!(Frame::read_from(raw(MAX_SIZE + 1, 3, b"foo").as_slice()).is_err());
+
+        // A huge length doesn't allocate a huge buffer upfront.
+        assert!(Frame::read_from(raw(MAX_SIZE, 3, b"foo").as_slice()).is_err());
+
+        // Corrupted frames fail the checksum.
+        let mut bytes = raw(4, 3, b"foo");
+        bytes[10] = b'x';
+        assert_eq!(Frame::read_from(bytes.as_slice()), errdata!("frame checksum mismatch"));
+        let mut bytes = raw(4, 3, b"foo");
+        bytes[8] = 4;
+        assert_eq!(Frame::read_from(bytes.as_slice()), errdata!("frame checksum mismatch"));
+        Ok(())
+    }
+
+    /// Version negotiation picks the highest common version, and features
+    /// enabled by both sides.
+    #[test]
+    fn negotiate() -> Result<()> {
+        let hello =
+            |min_version, max_version| Hello { min_version, max_version, compression: false };
+        assert_eq!(hello(1, 1).negotiate(&hello(1, 1))?, hello(1, 1));
+        assert_eq!(hello(1, 3).negotiate(&hello(2, 5))?, hello(3, 3));
+        assert_eq!(hello(2, 5).negotiate(&hello(1, 3))?, hello(3, 3));
+        assert!(hello(1, 2).negotiate(&hello(3, 4)).is_err());
+        assert!(hello(3, 4).negotiate(&hello(1, 2)).is_err());
+
+        assert!(Hello::new(true).negotiate(&Hello::new(true))?.compression);
+        assert!(!Hello::new(true).negotiate(&Hello::new(false))?.compression);
+        assert!(!Hello::new(false).negotiate(&Hello::new(true))?.compression);
+
+        let frame = hello(1, 2).to_frame();
+        assert_eq!(frame.payload, b"toyDB\x00\x01\x00\x02\x00");
+        assert_eq!(Hello::from_frame(&frame)?, hello(1, 2));
+        let frame = Hello { compression: true, ..hello(1, 2) }.to_frame();
+        assert_eq!(frame.payload, b"toyDB\x00\x01\x00\x02\x01");
+        assert!(Hello::from_frame(&frame)?.compression);
+
+        // Hellos without flags (from older versions) and with unknown flags
+        // are accepted.
+        let frame = Frame::new(Kind::Hello, b"toyDB\x00\x01\x00\x02".to_vec());
+        assert_eq!(Hello::from_frame(&frame)?, hello(1, 2));
+        let frame = Frame::new(Kind::Hello, b"toyDB\x00\x01\x00\x02\xfe".to_vec());
+        assert_eq!(Hello::from_frame(&frame)?, hello(1, 2));
+        assert!(Hello::from_frame(&Frame::new(Kind::Hello, b"hello".to_vec())).is_err());
+        Ok(())
+    }
+
+    /// The handshake negotiates a version over a connection, or responds with
+    /// an error frame.
+    #[test]
+    fn handshake() -> Result<()> {
+        let (mut client, server) = std::os::unix::net::UnixStream::pair()?;
+        let server = std::thread::spawn(move || accept(&mut std::io::BufReader::new(server), true));
+        let expect = Hello { min_version: VERSION, max_version: VERSION, compression: true };
+        assert_eq!(connect(&mut client, true)?, expect);
+        assert_eq!(server.join().expect("server panicked")?, Some(expect));
+
+        let (mut client, server) = std::os::unix::net::UnixStream::pair()?;
+        let server = std::thread::spawn(move || accept(&mut std::io::BufReader::new(server), true));
+        let hello =
+            Hello { min_version: VERSION + 1, max_version: VERSION + 1, compression: false };
+        hello.to_frame().write_to(&mut client)?;
+        let response = Frame::read_from(&mut client)?.expect("no response");
+        assert_eq!(response.kind, Kind::Error);
+        assert!(server.join().expect("server panicked").is_err());
+        Ok(())
+    }
+}
@@ -12,19 +12,36 @@
 //!
 //! KeyCode supports a subset of primitive data types, encoded as follows:
 //!
-//! bool:    0x00 for false, 0x01 for true.
-//! u64:     Big-endian binary representation.
-//! i64:     Big-endian binary representation, with sign bit flipped.
-//! f64:     Big-endian binary representation, with sign bit flipped, and rest if negative.
-//! Vec<u8>: 0x00 is escaped as 0x00ff, terminated with 0x0000.
-//! String:  Like Vec<u8>.
+//! bool:      0x00 for false, 0x01 for true.
+//! u32:       Big-endian binary representation.
+//! u64:       Like u32.
+//! u128:      Like u32.
+//! i64:       Big-endian binary representation, with sign bit flipped.
+//! i128:      Like i64.
+//! f64:       Big-endian binary representation, with sign bit flipped, and rest if negative.
+//! Vec<u8>:   0x00 is escaped as 0x00ff, terminated with 0x0000.
+//! String:    Like Vec<u8>.
 //!
 //! Additionally, several container types are supported:
 //!
-//! Tuple:  Concatenation of elements, with no surrounding structure.
-//! Array:  Like tuple.
-//! Vec:    Like tuple.
-//! Enum:   The variant's enum index as a single u8 byte.
+//! Tuple:     Concatenation of elements, with no surrounding structure.
+//! Array:     Like tuple.
+//! Vec:       Like tuple.
+//! Struct:    Like tuple, in field order.
+//! Newtype:   The inner value.
+//! Enum:      The variant's enum index as a single u8 byte.
+//!
+//! KeyCode is not human-readable, so types with a compact binary serde
+//! representation use it. This allows the following types to be used in keys,
+//! with byte order matching value order:
+//!
+//! SystemTime: A struct of seconds (u64) and nanoseconds (u32) since the Unix
+//!             epoch. Times before the epoch can't be serialized.
+//! Uuid:       The 16 bytes in big-endian order, like Vec<u8>.
+//! Decimals:   Fixed-scale decimals can use their i128 mantissa, e.g. via a
+//!             newtype. Decimals with varying scale must be normalized to a
+//!             common scale first, since e.g. 1.5 and 1.50 would otherwise
+//!             order by mantissa.
 //!
 //! SQL Value enums are encoded according to the above scheme, i.e. a single
 //! byte identifying the enum variant by index, then the primitive value.
@@ -43,12 +60,35 @@
 //! Unfortunately, byte vectors and slices such as Vec<u8> must be wrapped with
 //! serde_bytes::ByteBuf or use the #[serde(with="serde_bytes")] attribute. See
 //! https://github.com/serde-rs/bytes
+//!
+//! The encoding is part of the on-disk format, and is stable: changing the
+//! encoding of existing types, or the variant order of persisted key enums
+//! (e.g. raft::Key, mvcc::Key, and sql::engine::Key), would make existing data
+//! directories unreadable. New variants must be added at the end. The stable
+//! encodings are pinned by tests.
+//!
+//! For debugging, describe() decodes a raw SQL storage key into a
+//! human-readable form, e.g. mvcc:Version(sql:Row(movies, 1), 7) for version
+//! 7 of the row with primary key 1 in the movies table.
 
+use super::format::{self, Formatter as _};
 use crate::errdata;
 use crate::error::{Error, Result};
 
 use serde::{de, de::IntoDeserializer as _, ser};
 
+/// Describes a raw SQL storage key (an MVCC key wrapping a SQL key, or a bare
+/// SQL key) in human-readable form, for debugging. Keys that can't be decoded
+/// are formatted as escaped bytes. Raft log keys can be described via
+/// format::Raft::key().
+pub fn describe(key: &[u8]) -> String {
+    use super::Key as _;
+    match crate::storage::mvcc::Key::decode(key) {
+        Ok(_) => format::MVCC::<format::SQL>::key(key),
+        Err(_) => format::SQL::key(key),
+    }
+}
+
 /// Serializes a key to a binary KeyCode representation.
 ///
 /// In the common case, the encoded key is borrowed for a storage engine call
@@ -108,9 +148,15 @@ impl ser::Serializer for &mut Serializer {
     type SerializeTupleStruct = ser::Impossible<(), Error>;
     type SerializeTupleVariant = Self;
     type SerializeMap = ser::Impossible<(), Error>;
-    type SerializeStruct = ser::Impossible<(), Error>;
+    type SerializeStruct = Self;
     type SerializeStructVariant = ser::Impossible<(), Error>;
 
+    /// KeyCode is a binary format, so types should use their compact binary
+    /// representation (e.g. UUIDs as bytes rather than hyphenated strings).
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+
     /// bool simply uses 1 for true and 0 for false.
     fn serialize_bool(self, v: bool) -> Result<()> {
         self.output.push(if v { 1 } else { 0 });
@@ -143,6 +189,14 @@ impl ser::Serializer for &mut Serializer {
         Ok(())
     }
 
+    /// i128 is encoded like i64.
+    fn serialize_i128(self, v: i128) -> Result<()> {
+        let mut bytes = v.to_be_bytes();
+        bytes[0] ^= 1 << 7; // flip sign bit
+        self.output.extend(bytes);
+        Ok(())
+    }
+
     fn serialize_u8(self, _: u8) -> Result<()> {
         unimplemented!()
     }
@@ -151,8 +205,10 @@ impl ser::Serializer for &mut Serializer {
         unimplemented!()
     }
 
-    fn serialize_u32(self, _: u32) -> Result<()> {
-        unimplemented!()
+    /// u32 simply uses the big-endian encoding.
+    fn serialize_u32(self, v: u32) -> Result<()> {
+        self.output.extend(v.to_be_bytes());
+        Ok(())
     }
 
     /// u64 simply uses the big-endian encoding.
@@ -161,6 +217,12 @@ impl ser::Serializer for &mut Serializer {
         Ok(())
     }
 
+    /// u128 simply uses the big-endian encoding.
+    fn serialize_u128(self, v: u128) -> Result<()> {
+        self.output.extend(v.to_be_bytes());
+        Ok(())
+    }
+
     fn serialize_f32(self, _: f32) -> Result<()> {
         unimplemented!()
     }
@@ -225,12 +287,13 @@ impl ser::Serializer for &mut Serializer {
         Ok(())
     }
 
+    /// Newtype structs are serialized as the inner value.
     fn serialize_newtype_struct<T: ser::Serialize + ?Sized>(
         self,
         _: &'static str,
-        _: &T,
+        value: &T,
     ) -> Result<()> {
-        unimplemented!()
+        value.serialize(self)
     }
 
     /// Newtype variants are serialized using the variant index and inner type.
@@ -280,8 +343,9 @@ impl ser::Serializer for &mut Serializer {
         unimplemented!()
     }
 
+    /// Structs are serialized as the concatenation of the serialized fields.
     fn serialize_struct(self, _: &'static str, _: usize) -> Result<Self::SerializeStruct> {
-        unimplemented!()
+        Ok(self)
     }
 
     fn serialize_struct_variant(
@@ -337,6 +401,24 @@ impl ser::SerializeTupleVariant for &mut Serializer {
     }
 }
 
+/// Structs, like tuples, simply concatenate the serialized fields.
+impl ser::SerializeStruct for &mut Serializer {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: ser::Serialize + ?Sized>(
+        &mut self,
+        _: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
 /// Deserializes keys from byte slices into a given type. The format is not
 /// self-describing, so the caller must provide a concrete type to deserialize
 /// into.
@@ -392,6 +474,10 @@ impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
         panic!("must provide type, KeyCode is not self-describing")
     }
 
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+
     fn deserialize_bool<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
         visitor.visit_bool(match self.take_bytes(1)?[0] {
             0x00 => false,
@@ -418,6 +504,12 @@ impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
         visitor.visit_i64(i64::from_be_bytes(bytes.as_slice().try_into()?))
     }
 
+    fn deserialize_i128<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let mut bytes = self.take_bytes(16)?.to_vec();
+        bytes[0] ^= 1 << 7; // flip sign bit
+        visitor.visit_i128(i128::from_be_bytes(bytes.as_slice().try_into()?))
+    }
+
     fn deserialize_u8<V: de::Visitor<'de>>(self, _: V) -> Result<V::Value> {
         unimplemented!()
     }
@@ -426,14 +518,18 @@ impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
         unimplemented!()
     }
 
-    fn deserialize_u32<V: de::Visitor<'de>>(self, _: V) -> Result<V::Value> {
-        unimplemented!()
+    fn deserialize_u32<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_u32(u32::from_be_bytes(self.take_bytes(4)?.try_into()?))
     }
 
     fn deserialize_u64<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
         visitor.visit_u64(u64::from_be_bytes(self.take_bytes(8)?.try_into()?))
     }
 
+    fn deserialize_u128<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_u128(u128::from_be_bytes(self.take_bytes(16)?.try_into()?))
+    }
+
     fn deserialize_f32<V: de::Visitor<'de>>(self, _: V) -> Result<V::Value> {
         unimplemented!()
     }
@@ -491,9 +587,9 @@ impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
     fn deserialize_newtype_struct<V: de::Visitor<'de>>(
         self,
         _: &'static str,
-        _: V,
+        visitor: V,
     ) -> Result<V::Value> {
-        unimplemented!()
+        visitor.visit_newtype_struct(self)
     }
 
     fn deserialize_seq<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
@@ -521,9 +617,9 @@ impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
         self,
         _: &'static str,
         _: &'static [&'static str],
-        _: V,
+        visitor: V,
     ) -> Result<V::Value> {
-        unimplemented!()
+        visitor.visit_seq(self)
     }
 
     fn deserialize_enum<V: de::Visitor<'de>>(
@@ -604,7 +700,8 @@ mod tests {
     use super::*;
     use crate::sql::types::Value;
     use paste::paste;
-    use serde::{Deserialize, Serialize};
+    use rand::{rngs::StdRng, Rng as _, SeedableRng as _};
+    use serde::{de::DeserializeOwned, Deserialize, Serialize};
     use serde_bytes::ByteBuf;
     use std::borrow::Cow;
     use std::f64::consts::PI;
@@ -623,6 +720,59 @@ mod tests {
         ),
     }
 
+    /// The encoding of persisted keys is part of the on-disk format, and must
+    /// never change.
+    #[test]
+    fn stable() {
+        use crate::encoding::Key as _;
+        use crate::{raft, sql, storage::mvcc};
+
+        let row = sql::engine::Key::Row("t".into(), Cow::Owned(Value::Integer(1))).encode();
+        assert_eq!(hex::encode(&row), "02740000028000000000000001");
+        let index =
+            sql::engine::Key::Index("t".into(), "c".into(), Cow::Owned(Value::String("a".into())))
+                .encode();
+        assert_eq!(hex::encode(&index), "0174000063000004610000");
+        assert_eq!(hex::encode(sql::engine::Key::Table("t".into()).encode()), "00740000");
+
+        let version = mvcc::Key::Version(row.into(), 7).encode();
+        assert_eq!(
+            hex::encode(&version),
+            "04027400ff00ff028000ff00ff00ff00ff00ff00ff0100000000000000000007"
+        );
+        assert_eq!(hex::encode(mvcc::Key::NextVersion.encode()), "00");
+        assert_eq!(hex::encode(mvcc::Key::TxnActive(7).encode()), "010000000000000007");
+
+        assert_eq!(hex::encode(raft::Key::Entry(7).encode()), "000000000000000007");
+        assert_eq!(hex::encode(raft::Key::TermVote.encode()), "01");
+        assert_eq!(hex::encode(raft::Key::CommitIndex.encode()), "02");
+    }
+
+    /// describe() decodes SQL storage keys, and escapes invalid keys.
+    #[test]
+    fn describe() {
+        use crate::encoding::Key as _;
+        use crate::{sql, storage::mvcc};
+
+        let row = sql::engine::Key::Row("t".into(), Cow::Owned(Value::Integer(1))).encode();
+        let version = mvcc::Key::Version(row.into(), 7).encode();
+        assert_eq!(super::describe(&version), "mvcc:Version(sql:Row(t, 1), 7)");
+        assert_eq!(super::describe(&mvcc::Key::NextVersion.encode()), "mvcc:NextVersion");
+        let table = sql::engine::Key::Table("t".into()).encode();
+        assert_eq!(super::describe(&table), "sql:Table(t)");
+        assert_eq!(super::describe(b"\xff\x00"), "\"\\xff\\x00\"");
+    }
+
+    /// A fixed-scale decimal, represented by its i128 mantissa.
+    #[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, PartialOrd)]
+    struct Decimal(i128);
+
+    #[derive(Debug, Deserialize, Serialize, PartialEq)]
+    struct Struct {
+        a: bool,
+        b: String,
+    }
+
     /// Assert that serializing a value yields the expected byte sequence (as a
     /// hex-encoded string), and that deserializing it yields the original value.
     macro_rules! test_serialize_deserialize {
@@ -704,6 +854,23 @@ mod tests {
         u64_65535: 65535_u64 => "000000000000ffff",
         u64_max: u64::MAX => "ffffffffffffffff",
 
+        i128_min: i128::MIN => "00000000000000000000000000000000",
+        i128_neg_1: -1i128 => "7fffffffffffffffffffffffffffffff",
+        i128_0: 0i128 => "80000000000000000000000000000000",
+        i128_max: i128::MAX => "ffffffffffffffffffffffffffffffff",
+
+        u32_min: u32::MIN => "00000000",
+        u32_65535: 65535_u32 => "0000ffff",
+        u32_max: u32::MAX => "ffffffff",
+        u128_1: 1_u128 => "00000000000000000000000000000001",
+        u128_max: u128::MAX => "ffffffffffffffffffffffffffffffff",
+
+        system_time_epoch: std::time::UNIX_EPOCH => "000000000000000000000000",
+        system_time: std::time::UNIX_EPOCH + std::time::Duration::new(65535, 1) => "000000000000ffff00000001",
+        uuid: uuid::Uuid::from_u128(0x00112233_4455_6677_8899_aabbccddeeff) => "00ff112233445566778899aabbccddeeff0000",
+        newtype: Decimal(-1) => "7fffffffffffffffffffffffffffffff",
+        struct_fields: Struct { a: true, b: "foo".to_string() } => "01666f6f0000",
+
         bytes: ByteBuf::from(vec![0x01, 0xff]) => "01ff0000",
         bytes_empty: ByteBuf::new() => "0000",
         bytes_escape: ByteBuf::from(vec![0x00, 0x01, 0x02]) => "00ff01020000",
@@ -737,11 +904,8 @@ mod tests {
         i8: 0i8,
         i16: 0i16,
         i32: 0i32,
-        i128: 0i128,
         u8: 0u8,
         u16: 0u16,
-        u32: 0u32,
-        u128: 0u128,
         some: Some(true),
         none: Option::<bool>::None,
         vec_u8: vec![0u8],
@@ -755,14 +919,64 @@ mod tests {
         i8: "00" as i8,
         i16: "0000" as i16,
         i32: "00000000" as i32,
-        i128: "00000000000000000000000000000000" as i128,
+        i128_partial: "00000000" as i128,
         u16: "0000" as u16,
-        u32: "00000000" as u32,
+        u32_partial: "0000" as u32,
         u64_partial: "0000" as u64,
-        u128: "00000000000000000000000000000000" as u128,
+        u128_partial: "00000000" as u128,
+        uuid_partial: "00112233445566778899aabbccddeeff00" as uuid::Uuid,
         option: "00" as Option<bool>,
         string_utf8_invalid: "c0" as String,
         tuple_partial: "0001" as (bool, bool, bool),
         vec_u8: "0000" as Vec<u8>,
     }
+
+    /// Asserts that the KeyCode byte order matches the value order for random
+    /// values, and that they roundtrip. The random function is given a random
+    /// shift, which can be used to vary the magnitude of values.
+    fn assert_order<T>(random: impl Fn(&mut StdRng, u32) -> T) -> Result<()>
+    where
+        T: Serialize + DeserializeOwned + PartialOrd + std::fmt::Debug,
+    {
+        let mut rng = StdRng::seed_from_u64(0);
+        let random = |rng: &mut StdRng| {
+            let shift = rng.gen_range(0..128);
+            random(rng, shift)
+        };
+        for _ in 0..10_000 {
+            let (a, b) = (random(&mut rng), random(&mut rng));
+            let (a_key, b_key) = (serialize(&a), serialize(&b));
+            assert_eq!(a.partial_cmp(&b), Some(a_key.cmp(&b_key)), "{a:?} vs {b:?}");
+            assert_eq!(deserialize::<T>(&a_key)?, a);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn order_i128() -> Result<()> {
+        assert_order(|rng, shift| rng.gen::<i128>() >> shift)
+    }
+
+    #[test]
+    fn order_u128() -> Result<()> {
+        assert_order(|rng, shift| rng.gen::<u128>() >> shift)
+    }
+
+    #[test]
+    fn order_decimal() -> Result<()> {
+        assert_order(|rng, shift| Decimal(rng.gen::<i128>() >> shift))
+    }
+
+    #[test]
+    fn order_system_time() -> Result<()> {
+        assert_order(|rng, shift| {
+            let secs = rng.gen::<u64>() >> (shift % 32 + 30); // avoid overflow
+            std::time::UNIX_EPOCH + std::time::Duration::new(secs, rng.gen_range(0..1_000_000_000))
+        })
+    }
+
+    #[test]
+    fn order_uuid() -> Result<()> {
+        assert_order(|rng, shift| uuid::Uuid::from_u128(rng.gen::<u128>() >> shift))
+    }
 }
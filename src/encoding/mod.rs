@@ -2,10 +2,18 @@
 //!
 //! * keycode: used for keys in the key/value store.
 //! * bincode: used for values in the key/value store and network protocols.
+//! * versioned: adds a format version header to stored bincode values.
+//! * compression: used to compress large network payloads.
+//! * frame: used to frame network protocol messages.
 
 pub mod bincode;
+pub mod compression;
 pub mod format;
+pub mod frame;
 pub mod keycode;
+pub mod versioned;
+
+pub use versioned::Versioned;
 
 use crate::error::Result;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
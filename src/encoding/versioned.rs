@@ -0,0 +1,151 @@
+//! Versioned value encoding, used for values persisted in storage engines
+//! (e.g. SQL rows and schemas, and Raft log entries and metadata). This allows
+//! changing the on-disk encoding of a type without breaking existing data
+//! directories: old values are migrated to the current version when read.
+//!
+//! A versioned value is the Bincode-encoded value prefixed by a 2-byte header:
+//! the marker byte 0xff followed by the format version. Values written before
+//! versioning was introduced don't have a header, and are considered version 0.
+//! These can be recognized because a Bincode value never starts with 0xff:
+//! it's not a valid variable-length integer prefix, and structs, sequences,
+//! strings, and enums all start with an integer (or a 0/1 option tag).
+//!
+//! To change a type's encoding, bump Versioned::VERSION and implement
+//! Versioned::migrate() to decode the previous versions. Migrated values are
+//! rewritten with the current version the next time they're written, so
+//! migrations must be retained as long as old data directories may exist.
+//! Storage compaction operates on raw bytes, and doesn't upgrade values.
+
+use super::bincode;
+use crate::errdata;
+use crate::error::Result;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+/// The header marker byte, which can't begin a Bincode value.
+const MARKER: u8 = 0xff;
+
+/// Adds versioned Bincode encode/decode methods to stored value types.
+pub trait Versioned: Serialize + DeserializeOwned {
+    /// The current format version. Version 0 is used for legacy values without
+    /// a header, which use the version 1 encoding.
+    const VERSION: u8 = 1;
+
+    /// Decodes a value encoded with an older format version. By default, only
+    /// legacy version 0 values are supported. Types that bump VERSION must
+    /// override this to handle all previous versions (including 0).
+    fn migrate(version: u8, bytes: &[u8]) -> Result<Self> {
+        match version {
+            0 => bincode::deserialize(bytes),
+            version => errdata!("no migration from format version {version}"),
+        }
+    }
+
+    /// Decodes a versioned value, migrating it from older versions if needed.
+    fn decode_versioned(bytes: &[u8]) -> Result<Self> {
+        let (version, bytes) = split(bytes)?;
+        match version {
+            version if version == Self::VERSION => bincode::deserialize(bytes),
+            version if version < Self::VERSION => Self::migrate(version, bytes),
+            version => errdata!("unsupported format version {version}, max {}", Self::VERSION),
+        }
+    }
+
+    /// Encodes a value with the current format version.
+    fn encode_versioned(&self) -> Vec<u8> {
+        let mut bytes = vec![MARKER, Self::VERSION];
+        bytes.extend(bincode::serialize(self));
+        bytes
+    }
+}
+
+/// Splits a versioned value into its format version and Bincode payload,
+/// without decoding it. Legacy values without a header have version 0.
+pub fn split(bytes: &[u8]) -> Result<(u8, &[u8])> {
+    match bytes {
+        [MARKER, version, bytes @ ..] => Ok((*version, bytes)),
+        [MARKER] => errdata!("truncated format version header"),
+        bytes => Ok((0, bytes)),
+    }
+}
+
+/// Integers are used e.g. for Raft and SQL indexes.
+impl Versioned for u64 {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct V1 {
+        name: String,
+        count: u32,
+    }
+
+    impl Versioned for V1 {}
+
+    /// A later version of V1, which renamed count and added a field.
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct V2 {
+        name: String,
+        size: u64,
+        tags: Vec<String>,
+    }
+
+    impl Versioned for V2 {
+        const VERSION: u8 = 2;
+
+        fn migrate(version: u8, bytes: &[u8]) -> Result<Self> {
+            match version {
+                0 | 1 => {
+                    let V1 { name, count } = bincode::deserialize(bytes)?;
+                    Ok(Self { name, size: count as u64, tags: Vec::new() })
+                }
+                version => errdata!("no migration from format version {version}"),
+            }
+        }
+    }
+
+    /// Values roundtrip with a version header.
+    #[test]
+    fn roundtrip() -> Result<()> {
+        let v1 = V1 { name: "foo".into(), count: 7 };
+        let bytes = v1.encode_versioned();
+        assert_eq!(bytes[..2], [0xff, 1]);
+        assert_eq!(bytes[2..], bincode::serialize(&v1));
+        assert_eq!(V1::decode_versioned(&bytes)?, v1);
+        Ok(())
+    }
+
+    /// Legacy values without a header are decoded as version 0, including
+    /// values whose first byte is a large varint prefix.
+    #[test]
+    fn legacy() -> Result<()> {
+        let v1 = V1 { name: "x".repeat(300), count: 7 };
+        let bytes = bincode::serialize(&v1);
+        assert_eq!(bytes[0], 251); // u16 length prefix
+        assert_eq!(V1::decode_versioned(&bytes)?, v1);
+        assert_eq!(u64::decode_versioned(&bincode::serialize(&u64::MAX))?, u64::MAX);
+        Ok(())
+    }
+
+    /// Older versions are migrated, and newer versions error.
+    #[test]
+    fn migrate() -> Result<()> {
+        let v1 = V1 { name: "foo".into(), count: 7 };
+        let v2 = V2 { name: "foo".into(), size: 7, tags: Vec::new() };
+        assert_eq!(V2::decode_versioned(&bincode::serialize(&v1))?, v2);
+        assert_eq!(V2::decode_versioned(&v1.encode_versioned())?, v2);
+        assert_eq!(V2::decode_versioned(&v2.encode_versioned())?, v2);
+
+        assert_eq!(
+            V1::decode_versioned(&v2.encode_versioned()),
+            Err(Error::InvalidData("unsupported format version 2, max 1".into()))
+        );
+        assert!(V1::decode_versioned(&[0xff]).is_err());
+        Ok(())
+    }
+}
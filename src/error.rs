@@ -1,41 +1,364 @@
 use serde::{Deserialize, Serialize};
 
-/// toyDB errors.
+/// toyDB errors. These are sent to clients over the wire, so new variants must
+/// be added at the end.
+///
+/// Applications should use Error::is_retryable() to decide whether to retry a
+/// failed transaction (e.g. via Client::with_retry), rather than matching on
+/// specific variants. Other errors are permanent, and retrying them will fail
+/// the same way.
+///
+/// Errors may be wrapped in Error::Context, which says what operation failed
+/// (e.g. the statement, table, and key). Use Error::root() to match on the
+/// underlying error.
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Error {
     /// The operation was aborted and must be retried. This typically happens
     /// with e.g. Raft leader changes. This is used instead of implementing
     /// complex retry logic and replay protection in Raft.
     Abort,
+    /// The statement was cancelled by the client.
+    Cancelled,
     /// Invalid data, typically decoding errors or unexpected internal values.
     InvalidData(String),
     /// Invalid user input, typically parser or query errors.
     InvalidInput(String),
     /// An IO error.
     IO(String),
+    /// The server is overloaded and rejected the request, because it exceeded
+    /// a rate or concurrency limit. The request can be retried later.
+    Overloaded(String),
     /// A `write` was attempted in a read-only transaction.
     ReadOnly,
+    /// The statement exceeded the session's statement timeout.
+    Timeout,
     /// A write transaction conflicted with a different writer and lost. The
     /// transaction must be retried.
     Serialization,
+    /// A SQL syntax error. A kind of invalid input.
+    Syntax(SyntaxError),
+    /// A write violated a primary key or unique constraint. A kind of invalid
+    /// input.
+    UniqueViolation(String),
+    /// An error with context about the failed operation, e.g. the statement,
+    /// table, and key. The wrapped error is the source error, and determines
+    /// the error code and classification. Never nested.
+    Context(Box<Error>, Context),
 }
 
-impl std::error::Error for Error {}
+/// Context about a failed operation, see Error::with_context(). Built via
+/// e.g. Context::default().table("t").key(1).
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Context {
+    /// The SQL statement being executed.
+    pub statement: Option<String>,
+    /// The table being accessed.
+    pub table: Option<String>,
+    /// The key (e.g. row primary key) being accessed.
+    pub key: Option<String>,
+}
+
+impl Context {
+    /// Sets the statement.
+    pub fn statement(mut self, statement: impl Into<String>) -> Self {
+        self.statement = Some(statement.into());
+        self
+    }
+
+    /// Sets the table.
+    pub fn table(mut self, table: impl Into<String>) -> Self {
+        self.table = Some(table.into());
+        self
+    }
+
+    /// Sets the key.
+    pub fn key(mut self, key: impl ToString) -> Self {
+        self.key = Some(key.to_string());
+        self
+    }
+
+    /// Fills in any fields that aren't set from the given context.
+    fn merge(&mut self, other: Context) {
+        self.statement = self.statement.take().or(other.statement);
+        self.table = self.table.take().or(other.table);
+        self.key = self.key.take().or(other.key);
+    }
+}
+
+/// Displays the table and key, if any. The statement is only displayed in
+/// alternate mode ({:#}), since it's often long and known to the caller.
+impl std::fmt::Display for Context {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(table) = &self.table {
+            parts.push(format!("table {table}"));
+        }
+        if let Some(key) = &self.key {
+            parts.push(format!("key {key}"));
+        }
+        if let Some(statement) = self.statement.as_ref().filter(|_| f.alternate()) {
+            parts.push(format!("statement {statement:?}"));
+        }
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+/// A SQL syntax error, with the error location in the statement if known.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SyntaxError {
+    /// The error message, typically including the offending token.
+    pub message: String,
+    /// The error location, if known.
+    pub location: Option<Location>,
+}
+
+impl SyntaxError {
+    /// Creates a new syntax error without a location.
+    pub fn new(message: String) -> Self {
+        Self { message, location: None }
+    }
+}
+
+impl std::fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.message)?;
+        if let Some(Location { line, column, .. }) = &self.location {
+            write!(f, " at line {line}, column {column}")?;
+        }
+        Ok(())
+    }
+}
+
+/// A location in a SQL statement.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Location {
+    /// The 1-based line number.
+    pub line: usize,
+    /// The 1-based column number, in characters.
+    pub column: usize,
+    /// The text of the line, without the trailing newline. Allows rendering
+    /// the location without access to the original statement.
+    pub snippet: String,
+}
+
+impl Location {
+    /// Returns the location of a byte offset in the given input.
+    pub fn new(input: &str, offset: usize) -> Self {
+        let start = input[..offset].rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let end = input[offset..].find('\n').map(|i| offset + i).unwrap_or(input.len());
+        Self {
+            line: input[..offset].matches('\n').count() + 1,
+            column: input[start..offset].chars().count() + 1,
+            snippet: input[start..end].trim_end_matches('\r').to_string(),
+        }
+    }
+
+    /// Renders the snippet with a caret under the location, e.g.:
+    ///
+    /// SELECT * FORM t
+    ///          ^
+    pub fn render(&self) -> String {
+        // Preserve tabs, such that the caret lines up.
+        let indent: String = self
+            .snippet
+            .chars()
+            .take(self.column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        format!("{}\n{indent}^", self.snippet)
+    }
+}
+
+/// Stable error codes, which classify errors such that applications can branch
+/// on them instead of matching error messages. Each code maps to a 5-character
+/// SQLSTATE code, as used by e.g. PostgreSQL, via sqlstate(). Codes are stable:
+/// existing codes will not change meaning, although new codes may be added and
+/// errors may be classified more precisely over time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ErrorCode {
+    /// The operation was aborted, and it's unknown whether it was applied.
+    Aborted,
+    /// The statement was cancelled by the client.
+    Cancelled,
+    /// The connection failed, or another IO error happened.
+    ConnectionFailure,
+    /// Invalid or corrupted data was encountered.
+    DataCorrupted,
+    /// Invalid user input, e.g. unknown tables or type errors.
+    InvalidInput,
+    /// The server was overloaded and rejected the request.
+    Overloaded,
+    /// A write was attempted in a read-only transaction.
+    ReadOnlyTransaction,
+    /// The transaction conflicted with another transaction.
+    SerializationFailure,
+    /// The statement exceeded the statement timeout.
+    StatementTimeout,
+    /// A SQL syntax error.
+    SyntaxError,
+    /// A primary key or unique constraint violation.
+    UniqueViolation,
+}
+
+impl ErrorCode {
+    /// Returns the SQLSTATE code. Timeouts share the query_canceled code with
+    /// cancellation, like in PostgreSQL.
+    pub fn sqlstate(&self) -> &'static str {
+        match self {
+            Self::Aborted => "40003",
+            Self::Cancelled => "57014",
+            Self::ConnectionFailure => "08006",
+            Self::DataCorrupted => "XX001",
+            Self::InvalidInput => "42000",
+            Self::Overloaded => "53000",
+            Self::ReadOnlyTransaction => "25006",
+            Self::SerializationFailure => "40001",
+            Self::StatementTimeout => "57014",
+            Self::SyntaxError => "42601",
+            Self::UniqueViolation => "23505",
+        }
+    }
+}
+
+impl std::fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.sqlstate())
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Context(err, _) => Some(err.as_ref()),
+            _ => None,
+        }
+    }
+}
 
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
             Error::Abort => write!(f, "operation aborted"),
+            Error::Cancelled => write!(f, "statement cancelled"),
             Error::InvalidData(msg) => write!(f, "invalid data: {msg}"),
             Error::InvalidInput(msg) => write!(f, "invalid input: {msg}"),
             Error::IO(msg) => write!(f, "io error: {msg}"),
+            Error::Overloaded(msg) => write!(f, "server overloaded: {msg}"),
             Error::ReadOnly => write!(f, "read-only transaction"),
             Error::Serialization => write!(f, "serialization failure, retry transaction"),
+            Error::Timeout => write!(f, "statement timeout exceeded"),
+            // These are classified as input errors, and display as such.
+            Error::Syntax(err) => write!(f, "invalid input: {err}"),
+            Error::UniqueViolation(msg) => write!(f, "invalid input: {msg}"),
+            Error::Context(err, context) => {
+                err.fmt(f)?;
+                let context = match f.alternate() {
+                    true => format!("{context:#}"),
+                    false => context.to_string(),
+                };
+                if !context.is_empty() {
+                    write!(f, " ({context})")?;
+                }
+                Ok(())
+            }
         }
     }
 }
 
 impl Error {
+    /// Returns the error's code.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Error::Abort => ErrorCode::Aborted,
+            Error::Cancelled => ErrorCode::Cancelled,
+            Error::InvalidData(_) => ErrorCode::DataCorrupted,
+            Error::InvalidInput(_) => ErrorCode::InvalidInput,
+            Error::IO(_) => ErrorCode::ConnectionFailure,
+            Error::Overloaded(_) => ErrorCode::Overloaded,
+            Error::ReadOnly => ErrorCode::ReadOnlyTransaction,
+            Error::Serialization => ErrorCode::SerializationFailure,
+            Error::Syntax(_) => ErrorCode::SyntaxError,
+            Error::Timeout => ErrorCode::StatementTimeout,
+            Error::UniqueViolation(_) => ErrorCode::UniqueViolation,
+            Error::Context(err, _) => err.code(),
+        }
+    }
+
+    /// Adds context to the error. If the error already has context, only
+    /// fields that aren't already set are added, since the innermost context
+    /// is typically the most specific.
+    pub fn with_context(self, context: Context) -> Self {
+        match self {
+            Error::Context(err, mut existing) => {
+                existing.merge(context);
+                Error::Context(err, existing)
+            }
+            err => Error::Context(Box::new(err), context),
+        }
+    }
+
+    /// Returns the error's context, if any.
+    pub fn context(&self) -> Option<&Context> {
+        match self {
+            Error::Context(_, context) => Some(context),
+            _ => None,
+        }
+    }
+
+    /// Returns the source error without context. Use this to match on the
+    /// error kind.
+    pub fn root(&self) -> &Error {
+        match self {
+            Error::Context(err, _) => err,
+            err => err,
+        }
+    }
+
+    /// Sets the location of a syntax error, unless it already has one. Other
+    /// errors are returned unchanged.
+    pub fn with_location(self, location: impl FnOnce() -> Location) -> Self {
+        match self {
+            Error::Syntax(SyntaxError { message, location: None }) => {
+                Error::Syntax(SyntaxError { message, location: Some(location()) })
+            }
+            Error::Context(err, context) => {
+                Error::Context(Box::new(err.with_location(location)), context)
+            }
+            err => err,
+        }
+    }
+
+    /// Returns whether the error is caused by a temporary condition, rather
+    /// than by the request itself, such as a Raft leader change, a write
+    /// conflict, an overloaded server, or a network failure. The same request
+    /// may succeed later.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::Abort | Error::Overloaded(_) | Error::Serialization | Error::IO(_) => true,
+            Error::Cancelled
+            | Error::InvalidData(_)
+            | Error::InvalidInput(_)
+            | Error::ReadOnly
+            | Error::Syntax(_)
+            | Error::Timeout
+            | Error::UniqueViolation(_) => false,
+            Error::Context(err, _) => err.is_transient(),
+        }
+    }
+
+    /// Returns whether the failed request (or rather, its entire transaction)
+    /// can be retried as is, typically after a backoff. This is the case for
+    /// transient errors reported by a reachable server: aborts, serialization
+    /// failures, and overload rejections. IO errors are transient, but aren't
+    /// retryable since the server may be gone -- the client already fails over
+    /// internally, and reports an abort if the request may have been applied.
+    ///
+    /// Aborted writes may or may not have been applied, so retried writes
+    /// should be idempotent or run in a transaction.
+    pub fn is_retryable(&self) -> bool {
+        self.is_transient() && !matches!(self.root(), Error::IO(_))
+    }
+
     /// Returns whether the error is considered deterministic. Raft state
     /// machine application needs to know whether a command failure is
     /// deterministic on the input command -- if it is, the command can be
@@ -47,6 +370,9 @@ impl Error {
             // we consider them non-deterministic in case an abort should happen
             // unexpectedly below Raft.
             Error::Abort => false,
+            // Cancellation happens during session-side execution, never during
+            // application. Handle it like an abort for the same reason.
+            Error::Cancelled => false,
             // Possible data corruption local to this node.
             Error::InvalidData(_) => false,
             // Input errors are (likely) deterministic. They might not be in
@@ -54,10 +380,18 @@ impl Error {
             Error::InvalidInput(_) => true,
             // IO errors are typically local to the node (e.g. faulty disk).
             Error::IO(_) => false,
+            // Admission control happens before requests are submitted to Raft.
+            Error::Overloaded(_) => false,
             // Write commands in read-only transactions are deterministic.
             Error::ReadOnly => true,
             // Write conflicts are deterministic.
             Error::Serialization => true,
+            // Syntax errors and constraint violations are input errors.
+            Error::Syntax(_) | Error::UniqueViolation(_) => true,
+            // Timeouts depend on the wall clock, and are enforced session-side.
+            Error::Timeout => false,
+            // Context doesn't change the error.
+            Error::Context(err, _) => err.is_deterministic(),
         }
     }
 }
@@ -74,6 +408,14 @@ macro_rules! errinput {
     ($($args:tt)*) => { $crate::error::Error::InvalidInput(format!($($args)*)).into() };
 }
 
+/// Constructs an Error::Syntax for the given format string.
+#[macro_export]
+macro_rules! errsyntax {
+    ($($args:tt)*) => {
+        $crate::error::Error::Syntax($crate::error::SyntaxError::new(format!($($args)*))).into()
+    };
+}
+
 /// A toyDB Result returning Error.
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -143,6 +485,12 @@ impl From<hdrhistogram::RecordError> for Error {
     }
 }
 
+impl From<httparse::Error> for Error {
+    fn from(err: httparse::Error) -> Self {
+        Error::InvalidInput(err.to_string())
+    }
+}
+
 impl From<log::ParseLevelError> for Error {
     fn from(err: log::ParseLevelError) -> Self {
         Error::InvalidInput(err.to_string())
@@ -155,18 +503,75 @@ impl From<log::SetLoggerError> for Error {
     }
 }
 
+#[cfg(feature = "quic")]
+impl From<quinn::ClosedStream> for Error {
+    fn from(err: quinn::ClosedStream) -> Self {
+        Error::IO(err.to_string())
+    }
+}
+
+#[cfg(feature = "quic")]
+impl From<quinn::ConnectError> for Error {
+    fn from(err: quinn::ConnectError) -> Self {
+        Error::IO(err.to_string())
+    }
+}
+
+#[cfg(feature = "quic")]
+impl From<quinn::ConnectionError> for Error {
+    fn from(err: quinn::ConnectionError) -> Self {
+        Error::IO(err.to_string())
+    }
+}
+
+#[cfg(feature = "quic")]
+impl From<quinn::ReadToEndError> for Error {
+    fn from(err: quinn::ReadToEndError) -> Self {
+        Error::IO(err.to_string())
+    }
+}
+
+#[cfg(feature = "quic")]
+impl From<quinn::WriteError> for Error {
+    fn from(err: quinn::WriteError) -> Self {
+        Error::IO(err.to_string())
+    }
+}
+
+#[cfg(feature = "quic")]
+impl From<quinn::crypto::rustls::NoInitialCipherSuite> for Error {
+    fn from(err: quinn::crypto::rustls::NoInitialCipherSuite) -> Self {
+        // The TLS config doesn't support TLS 1.3, which QUIC requires.
+        Error::InvalidInput(err.to_string())
+    }
+}
+
 impl From<regex::Error> for Error {
     fn from(err: regex::Error) -> Self {
         panic!("{err}") // faulty code
     }
 }
 
+impl From<rustls::Error> for Error {
+    fn from(err: rustls::Error) -> Self {
+        // Runtime TLS errors are surfaced as IO errors by the stream, so these
+        // are typically configuration errors, e.g. invalid certificates.
+        Error::InvalidInput(err.to_string())
+    }
+}
+
 impl From<rustyline::error::ReadlineError> for Error {
     fn from(err: rustyline::error::ReadlineError) -> Self {
         Error::IO(err.to_string())
     }
 }
 
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::InvalidData(err.to_string())
+    }
+}
+
 impl From<std::array::TryFromSliceError> for Error {
     fn from(err: std::array::TryFromSliceError) -> Self {
         Error::InvalidData(err.to_string())
@@ -210,3 +615,90 @@ impl<T> From<std::sync::PoisonError<T>> for Error {
         panic!("{err}")
     }
 }
+
+impl From<tracing::subscriber::SetGlobalDefaultError> for Error {
+    fn from(err: tracing::subscriber::SetGlobalDefaultError) -> Self {
+        panic!("{err}") // faulty code
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::parser::Parser;
+
+    /// Only transient errors reported by the server are retryable.
+    #[test]
+    fn retryable() {
+        let retryable = [Error::Abort, Error::Overloaded("busy".into()), Error::Serialization];
+        for err in retryable {
+            assert!(err.is_transient() && err.is_retryable(), "{err:?}");
+        }
+        let io = Error::IO("connection refused".into());
+        assert!(io.is_transient() && !io.is_retryable());
+        let permanent = [
+            Error::Cancelled,
+            Error::InvalidData("corrupt".into()),
+            Error::InvalidInput("bad".into()),
+            Error::ReadOnly,
+            Error::Syntax(SyntaxError::new("bad".into())),
+            Error::Timeout,
+            Error::UniqueViolation("dup".into()),
+        ];
+        for err in permanent {
+            assert!(!err.is_transient() && !err.is_retryable(), "{err:?}");
+        }
+    }
+
+    /// Context is merged into a single wrapper, keeping the innermost fields,
+    /// and doesn't affect the error's classification.
+    #[test]
+    fn context() {
+        let err = Error::Serialization
+            .with_context(Context::default().table("t").key(1))
+            .with_context(Context::default().statement("UPDATE t SET v = 1").table("other"));
+        assert_eq!(err.root(), &Error::Serialization);
+        assert_eq!(
+            err.context(),
+            Some(&Context {
+                statement: Some("UPDATE t SET v = 1".into()),
+                table: Some("t".into()),
+                key: Some("1".into()),
+            })
+        );
+        assert!(err.is_retryable());
+        assert_eq!(err.code(), ErrorCode::SerializationFailure);
+        assert_eq!(err.to_string(), "serialization failure, retry transaction (table t, key 1)");
+        assert_eq!(
+            format!("{err:#}"),
+            r#"serialization failure, retry transaction (table t, key 1, statement "UPDATE t SET v = 1")"#
+        );
+        let source = std::error::Error::source(&err).expect("no source");
+        assert_eq!(source.to_string(), "serialization failure, retry transaction");
+
+        // The statement alone isn't displayed.
+        let err = Error::Abort.with_context(Context::default().statement("SELECT 1"));
+        assert_eq!(err.to_string(), "operation aborted");
+    }
+
+    /// Syntax errors are located at the offending token, and rendered with a
+    /// caret under it.
+    #[test]
+    fn syntax_location() {
+        let err = Parser::new("SELECT 1\n\tFROM t WHERE 👋").parse().unwrap_err();
+        let Error::Syntax(SyntaxError { message, location: Some(location) }) = &err else {
+            panic!("expected located syntax error, got {err:?}");
+        };
+        assert_eq!(message, "unexpected character `👋`");
+        assert_eq!((location.line, location.column), (2, 15));
+        assert_eq!(location.render(), "\tFROM t WHERE 👋\n\t             ^");
+        assert_eq!(
+            err.to_string(),
+            "invalid input: unexpected character `👋` at line 2, column 15"
+        );
+
+        // The end of input is located after the last character.
+        let err = Parser::new("SELECT 1 +").parse().unwrap_err();
+        assert_eq!(err.to_string(), "invalid input: unexpected end of input at line 1, column 11");
+    }
+}
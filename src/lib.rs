@@ -3,13 +3,22 @@
 #![allow(clippy::too_long_first_doc_paragraph)]
 
 pub mod client;
+pub mod database;
 pub mod encoding;
 pub mod error;
+#[cfg(any(test, feature = "fuzz"))]
+pub mod fuzz;
 pub mod raft;
 pub mod server;
 pub mod sql;
 pub mod storage;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 
-pub use client::Client;
-pub use server::Server;
+pub use client::{
+    Balance, BalancedClient, Canceller, ChangeStream, Client, ConnectOptions, ConnectUrl, Page,
+    Pool, PoolOptions, PooledClient, PreparedStatement, QueryIter,
+};
+pub use database::Database;
+pub use server::{Server, ServerBuilder};
 pub use sql::engine::StatementResult;
@@ -1,10 +1,12 @@
 use super::{NodeID, Term};
-use crate::encoding::{self, bincode, Key as _, Value as _};
-use crate::error::Result;
+use crate::encoding::{self, Key as _, Versioned as _};
+use crate::errinput;
+use crate::error::{Error, Result};
 use crate::storage;
 use crate::storage::ScanIterator;
 use serde::{Deserialize, Serialize};
 use std::iter::Iterator as StdIterator;
+use std::time::{Duration, Instant};
 
 /// A log index. Starts at 1, indicates no index if 0.
 pub type Index = u64;
@@ -22,6 +24,64 @@ pub struct Entry {
 }
 
 impl encoding::Value for Entry {}
+impl encoding::Versioned for Entry {}
+
+/// The stored term and vote, see Key::TermVote.
+impl encoding::Versioned for (Term, Option<NodeID>) {}
+
+/// The stored commit index and term, see Key::CommitIndex and Key::Snapshot.
+impl encoding::Versioned for (Index, Term) {}
+
+/// The durability policy for appended log entries, i.e. when they're fsynced
+/// to disk. Raft mandates that entries are durable before they're
+/// acknowledged, which comes with a hefty performance penalty. Weaker policies
+/// yield much better write performance, but may lose acknowledged entries on
+/// host crashes, which in some scenarios can cause log entries to become
+/// "uncommitted" and state machines to diverge.
+///
+/// Term and vote changes are always fsynced regardless of the policy, since
+/// they're rare and double voting could lead to split brain.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Durability {
+    /// Fsync appended entries before they're acknowledged.
+    Always,
+    /// Fsync appended entries at most once per interval, and on shutdown.
+    Interval(Duration),
+    /// Never fsync appended entries, except on shutdown.
+    Never,
+}
+
+impl std::fmt::Display for Durability {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Always => write!(f, "always"),
+            Self::Interval(interval) => write!(f, "interval({})", interval.as_millis()),
+            Self::Never => write!(f, "never"),
+        }
+    }
+}
+
+impl std::str::FromStr for Durability {
+    type Err = Error;
+
+    /// Parses a durability policy: always, interval(MS), or never. For
+    /// backwards compatibility, true and false mean always and never.
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "always" | "true" => Ok(Self::Always),
+            "never" | "false" => Ok(Self::Never),
+            s => {
+                let Some(ms) = s.strip_prefix("interval(").and_then(|s| s.strip_suffix(')')) else {
+                    return errinput!("invalid durability policy {s}");
+                };
+                match ms.trim().parse() {
+                    Ok(ms) if ms > 0 => Ok(Self::Interval(Duration::from_millis(ms))),
+                    _ => errinput!("invalid durability interval {ms}"),
+                }
+            }
+        }
+    }
+}
 
 /// A log storage key.
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
@@ -32,6 +92,9 @@ pub enum Key {
     TermVote,
     /// Stores the current commit index (if any).
     CommitIndex,
+    /// Stores the index and term of the last entry removed by log truncation
+    /// (if any), i.e. the last entry included in the state machine snapshot.
+    Snapshot,
 }
 
 impl encoding::Key<'_> for Key {}
@@ -73,14 +136,24 @@ impl encoding::Key<'_> for Key {}
 /// indexes, then the uncommitted entries will be replaced with entries from the
 /// new leader once the old leader (or a follower) discovers it.
 ///
+/// To bound its size, the log can be truncated up to an applied index via
+/// [`Log::truncate`], once the state machine has durably applied the entries.
+/// The state machine then serves as a snapshot of the truncated entries, and
+/// lagging followers must be caught up by installing a state machine snapshot
+/// from the leader, see [`Log::install_snapshot`]. The index and term of the
+/// last truncated entry is retained, such that the next entry can be appended
+/// and matched against the leader's log.
+///
 /// The Raft log has the following invariants:
 ///
-/// * Entry indexes are contiguous starting at 1 (no index gaps).
+/// * Entry indexes are contiguous starting after the snapshot index (no index
+///   gaps), or at 1 if the log hasn't been truncated.
 /// * Entry terms never decrease from the previous entry.
 /// * Entry terms are at or below the current term.
-/// * Appended entries are durable (flushed to disk).
+/// * Appended entries are durable (flushed to disk), with Durability::Always.
 /// * Appended entries use the current term.
-/// * Committed entries are never changed or removed (no log truncation).
+/// * Committed entries are never changed, and only removed by truncation
+///   once they're included in a durable state machine snapshot.
 /// * Committed entries will eventually be replicated to all nodes.
 /// * Entries with the same index/term contain the same command.
 /// * If two logs contain a matching index/term, all previous entries
@@ -102,13 +175,19 @@ pub struct Log {
     commit_index: Index,
     /// The term of the last committed entry.
     commit_term: Term,
-    /// If true, fsync entries to disk when appended. This is mandated by Raft,
-    /// but comes with a hefty performance penalty (especially since we don't
-    /// optimize for it by batching entries before fsyncing). Disabling it will
-    /// yield much better write performance, but may lose data on host crashes,
-    /// which in some scenarios can cause log entries to become "uncommitted"
-    /// and state machines diverging.
-    fsync: bool,
+    /// The index of the last truncated entry, or 0 if not truncated.
+    snapshot_index: Index,
+    /// The term of the last truncated entry, or 0 if not truncated.
+    snapshot_term: Term,
+    /// When to fsync appended entries to disk, see Durability.
+    durability: Durability,
+    /// If true, appends aren't synced immediately, but by the next sync()
+    /// call. This allows syncing a batch of appends with a single fsync.
+    group_commit: bool,
+    /// If true, there are appended entries that haven't been synced yet.
+    unsynced: bool,
+    /// The time of the last sync.
+    last_sync: Instant,
 }
 
 impl Log {
@@ -117,30 +196,60 @@ impl Log {
         use std::ops::Bound::Included;
         let (term, vote) = engine
             .get(&Key::TermVote.encode())?
-            .map(|v| bincode::deserialize(&v))
+            .map(|v| encoding::Versioned::decode_versioned(&v))
             .transpose()?
             .unwrap_or((0, None));
+        let (snapshot_index, snapshot_term) = engine
+            .get(&Key::Snapshot.encode())?
+            .map(|v| encoding::Versioned::decode_versioned(&v))
+            .transpose()?
+            .unwrap_or((0, 0));
         let (last_index, last_term) = engine
             .scan_dyn((Included(Key::Entry(0).encode()), Included(Key::Entry(u64::MAX).encode())))
             .last()
             .transpose()?
-            .map(|(_, v)| Entry::decode(&v))
+            .map(|(_, v)| Entry::decode_versioned(&v))
             .transpose()?
             .map(|e| (e.index, e.term))
-            .unwrap_or((0, 0));
+            .unwrap_or((snapshot_index, snapshot_term));
+        // The commit index isn't flushed, so it may be lost on crashes and
+        // fall behind the snapshot. Truncated entries are always committed.
         let (commit_index, commit_term) = engine
             .get(&Key::CommitIndex.encode())?
-            .map(|v| bincode::deserialize(&v))
+            .map(|v| encoding::Versioned::decode_versioned(&v))
             .transpose()?
-            .unwrap_or((0, 0));
-        let fsync = true; // fsync by default (NB: BitCask::flush() is a noop in tests)
-        Ok(Self { engine, term, vote, last_index, last_term, commit_index, commit_term, fsync })
+            .unwrap_or((0, 0))
+            .max((snapshot_index, snapshot_term));
+        Ok(Self {
+            engine,
+            term,
+            vote,
+            last_index,
+            last_term,
+            commit_index,
+            commit_term,
+            snapshot_index,
+            snapshot_term,
+            durability: Durability::Always, // NB: BitCask::flush() is a noop in tests
+            group_commit: false,
+            unsynced: false,
+            last_sync: Instant::now(),
+        })
+    }
+
+    /// Sets the durability policy for appended entries. Weaker policies than
+    /// Durability::Always may violate Raft guarantees, see Durability.
+    pub fn set_durability(&mut self, durability: Durability) {
+        self.durability = durability
     }
 
-    /// Controls whether to fsync writes. Disabling this may violate Raft
-    /// guarantees, see comment on fsync attribute.
-    pub fn enable_fsync(&mut self, fsync: bool) {
-        self.fsync = fsync
+    /// Enables or disables group commit. When enabled, appended entries aren't
+    /// synced until the next sync() call, such that appends from many
+    /// concurrent client requests can share a single fsync. The caller must
+    /// call sync() before sending any messages emitted since the appends (e.g.
+    /// append acknowledgements), otherwise Raft guarantees are violated.
+    pub fn enable_group_commit(&mut self, enabled: bool) {
+        self.group_commit = enabled
     }
 
     /// Returns the commit index and term.
@@ -153,6 +262,12 @@ impl Log {
         (self.last_index, self.last_term)
     }
 
+    /// Returns the index and term of the last truncated entry, i.e. the last
+    /// entry included in the state machine snapshot, or 0 if not truncated.
+    pub fn get_snapshot_index(&self) -> (Index, Term) {
+        (self.snapshot_index, self.snapshot_term)
+    }
+
     /// Returns the current term (0 if none) and vote.
     pub fn get_term(&self) -> (Term, Option<NodeID>) {
         (self.term, self.vote)
@@ -168,28 +283,28 @@ impl Log {
         if term == self.term && vote == self.vote {
             return Ok(());
         }
-        self.engine.set(&Key::TermVote.encode(), bincode::serialize(&(term, vote)))?;
-        // Always fsync, even with Log.fsync = false. Term changes are rare, so
-        // this doesn't materially affect performance, and double voting could
-        // lead to multiple leaders and split brain which is terrible.
-        self.engine.flush()?;
+        self.engine.set(&Key::TermVote.encode(), (term, vote).encode_versioned())?;
+        // Always fsync, regardless of the durability policy. Term changes are
+        // rare, so this doesn't materially affect performance, and double
+        // voting could lead to multiple leaders and split brain which is
+        // terrible. This also syncs any pending appends.
+        self.flush()?;
         self.term = term;
         self.vote = vote;
         Ok(())
     }
 
     /// Appends a command to the log at the current term, and flushes it to
-    /// disk, returning its index. None implies a noop command, typically after
-    /// Raft leader changes.
+    /// disk according to the durability policy (unless group commit is
+    /// enabled), returning its index. None implies a noop command, typically
+    /// after Raft leader changes.
     pub fn append(&mut self, command: Option<Vec<u8>>) -> Result<Index> {
         assert!(self.term > 0, "can't append entry in term 0");
         // We could omit the index in the encoded value, since it's also stored
         // in the key, but we keep it simple.
         let entry = Entry { index: self.last_index + 1, term: self.term, command };
-        self.engine.set(&Key::Entry(entry.index).encode(), entry.encode())?;
-        if self.fsync {
-            self.engine.flush()?;
-        }
+        self.engine.set(&Key::Entry(entry.index).encode(), entry.encode_versioned())?;
+        self.appended()?;
         self.last_index = entry.index;
         self.last_term = entry.term;
         Ok(entry.index)
@@ -198,15 +313,18 @@ impl Log {
     /// Commits entries up to and including the given index. The index must
     /// exist and be at or after the current commit index.
     pub fn commit(&mut self, index: Index) -> Result<Index> {
-        let term = match self.get(index)? {
-            Some(e) if e.index < self.commit_index => {
-                panic!("commit index regression {} → {}", self.commit_index, e.index);
+        // NB: the commit index may have been truncated, so check it first.
+        match index.cmp(&self.commit_index) {
+            std::cmp::Ordering::Less => {
+                panic!("commit index regression {} → {index}", self.commit_index)
             }
-            Some(e) if e.index == self.commit_index => return Ok(index),
-            Some(e) => e.term,
-            None => panic!("commit index {index} does not exist"),
+            std::cmp::Ordering::Equal if index > 0 => return Ok(index),
+            std::cmp::Ordering::Equal | std::cmp::Ordering::Greater => {}
+        }
+        let Some(term) = self.get(index)?.map(|e| e.term) else {
+            panic!("commit index {index} does not exist");
         };
-        self.engine.set(&Key::CommitIndex.encode(), bincode::serialize(&(index, term)))?;
+        self.engine.set(&Key::CommitIndex.encode(), (index, term).encode_versioned())?;
         // NB: the commit index doesn't need to be fsynced, since the entries
         // are fsynced and the commit index can be recovered from a log quorum.
         self.commit_index = index;
@@ -214,12 +332,30 @@ impl Log {
         Ok(index)
     }
 
-    /// Fetches an entry at an index, or None if it does not exist.
+    /// Fetches an entry at an index, or None if it does not exist (including
+    /// truncated entries).
     pub fn get(&mut self, index: Index) -> Result<Option<Entry>> {
-        self.engine.get(&Key::Entry(index).encode())?.map(|v| Entry::decode(&v)).transpose()
+        self.engine
+            .get(&Key::Entry(index).encode())?
+            .map(|v| Entry::decode_versioned(&v))
+            .transpose()
+    }
+
+    /// Returns the term of the entry at the given index, or None if it does
+    /// not exist. Unlike get(), this also returns the term of the snapshot
+    /// index, i.e. the last truncated entry.
+    pub fn get_entry_term(&mut self, index: Index) -> Result<Option<Term>> {
+        if index == self.snapshot_index && index > 0 {
+            return Ok(Some(self.snapshot_term));
+        }
+        Ok(self.get(index)?.map(|e| e.term))
     }
 
     /// Checks if the log contains an entry with the given index and term.
+    ///
+    /// Entries below the snapshot index have been truncated, and their terms
+    /// are unknown. However, they're committed, so they must match the log of
+    /// any current leader, and we assume that the log contains them.
     pub fn has(&mut self, index: Index, term: Term) -> Result<bool> {
         // Fast path: check against last_index. This is the common case when
         // followers process appends or heartbeats.
@@ -229,7 +365,10 @@ impl Log {
         if (index, term) == (self.last_index, self.last_term) {
             return Ok(true);
         }
-        Ok(self.get(index)?.map(|e| e.term == term).unwrap_or(false))
+        if index < self.snapshot_index {
+            return Ok(true);
+        }
+        Ok(self.get_entry_term(index)? == Some(term))
     }
 
     /// Returns an iterator over log entries in the given index range.
@@ -267,7 +406,8 @@ impl Log {
     /// New indexes will be appended. Overlapping indexes with the same term
     /// must be equal and will be ignored. Overlapping indexes with different
     /// terms will truncate the existing log at the first conflict and then
-    /// splice the new entries.
+    /// splice the new entries. Entries at or below the snapshot index are
+    /// committed, and will be ignored.
     pub fn splice(&mut self, entries: Vec<Entry>) -> Result<Index> {
         let (Some(first), Some(last)) = (entries.first(), entries.last()) else {
             return Ok(self.last_index); // empty input is noop
@@ -284,12 +424,21 @@ impl Log {
             panic!("spliced entries have term regression");
         }
 
+        // Skip truncated entries, which are already committed.
+        let skip = self.snapshot_index.saturating_sub(first.index - 1) as usize;
+        if let Some(entry) = skip.checked_sub(1).and_then(|i| entries.get(i)) {
+            assert_eq!(entry.term, self.snapshot_term, "term mismatch at snapshot {entry:?}");
+        }
+        let Some(first) = entries.get(skip) else {
+            return Ok(self.last_index);
+        };
+
         // Check that the entries connect to the existing log (if any), and that the
         // term doesn't regress.
         assert!(last.term <= self.term, "splice term {} beyond current {}", last.term, self.term);
-        match self.get(first.index - 1)? {
-            Some(base) if first.term < base.term => {
-                panic!("splice term regression {} → {}", base.term, first.term)
+        match self.get_entry_term(first.index - 1)? {
+            Some(base_term) if first.term < base_term => {
+                panic!("splice term regression {} → {}", base_term, first.term)
             }
             Some(_) => {}
             None if first.index == 1 => {}
@@ -297,7 +446,7 @@ impl Log {
         }
 
         // Skip entries that are already in the log.
-        let mut entries = entries.as_slice();
+        let mut entries = &entries[skip..];
         let mut scan = self.scan(first.index..=last.index);
         while let Some(entry) = scan.next().transpose()? {
             // [0] is ok, because the scan has the same size as entries.
@@ -320,21 +469,126 @@ impl Log {
         // since these entries must be immutable.
         assert!(first.index > self.commit_index, "spliced entries below commit index");
 
+        let mut batch = storage::WriteBatch::new();
         for entry in entries {
-            self.engine.set(&Key::Entry(entry.index).encode(), entry.encode())?;
+            batch.set(&Key::Entry(entry.index).encode(), entry.encode_versioned());
         }
         for index in last.index + 1..=self.last_index {
-            self.engine.delete(&Key::Entry(index).encode())?;
-        }
-        if self.fsync {
-            self.engine.flush()?;
+            batch.delete(&Key::Entry(index).encode());
         }
+        self.engine.write_batch(batch)?;
+        self.appended()?;
 
         self.last_index = last.index;
         self.last_term = last.term;
         Ok(self.last_index)
     }
 
+    /// Truncates the log up to and including the given index, which must be
+    /// committed. The state machine must have durably applied the truncated
+    /// entries, since they can't be replayed after a crash. Returns the new
+    /// snapshot index, which is unchanged if the index was already truncated.
+    pub fn truncate(&mut self, index: Index) -> Result<Index> {
+        assert!(index <= self.commit_index, "can't truncate uncommitted index {index}");
+        if index <= self.snapshot_index {
+            return Ok(self.snapshot_index);
+        }
+        let Some(term) = self.get(index)?.map(|e| e.term) else {
+            panic!("truncate index {index} does not exist");
+        };
+        let mut batch = storage::WriteBatch::new();
+        batch.set(&Key::Snapshot.encode(), (index, term).encode_versioned());
+        for index in self.snapshot_index + 1..=index {
+            batch.delete(&Key::Entry(index).encode());
+        }
+        // NB: this doesn't need to be fsynced, since the entries are committed
+        // and can be truncated again if the truncation is lost.
+        self.engine.write_batch(batch)?;
+        self.snapshot_index = index;
+        self.snapshot_term = term;
+        Ok(index)
+    }
+
+    /// Installs a state machine snapshot from the leader, which includes all
+    /// entries up to and including the given index/term. The index must be
+    /// beyond the commit index, and becomes the new commit and snapshot index.
+    /// If the log contains the index/term, subsequent entries are retained.
+    /// Otherwise, the entire log is discarded. The caller must restore the
+    /// state machine snapshot before calling this.
+    pub fn install_snapshot(&mut self, index: Index, term: Term) -> Result<()> {
+        assert!(index > self.commit_index, "snapshot index {index} at or below commit index");
+        assert!(term > 0 && term <= self.term, "invalid snapshot term {term}");
+        let retain = self.has(index, term)?;
+        let mut batch = storage::WriteBatch::new();
+        batch.set(&Key::Snapshot.encode(), (index, term).encode_versioned());
+        batch.set(&Key::CommitIndex.encode(), (index, term).encode_versioned());
+        let last = if retain { index } else { self.last_index };
+        for index in self.snapshot_index + 1..=last {
+            batch.delete(&Key::Entry(index).encode());
+        }
+        self.engine.write_batch(batch)?;
+        // Flush the snapshot index, since the log is otherwise missing the
+        // entries that were applied to the state machine.
+        self.flush()?;
+        if !retain {
+            self.last_index = index;
+            self.last_term = term;
+        }
+        self.snapshot_index = index;
+        self.snapshot_term = term;
+        self.commit_index = index;
+        self.commit_term = term;
+        Ok(())
+    }
+
+    /// Records that entries were appended, and syncs them unless group commit
+    /// is enabled.
+    fn appended(&mut self) -> Result<()> {
+        self.unsynced = true;
+        if !self.group_commit {
+            self.sync()?;
+        }
+        Ok(())
+    }
+
+    /// Syncs appended entries to durable storage according to the durability
+    /// policy, if any haven't been synced yet. With group commit, this must be
+    /// called after a batch of appends, see enable_group_commit().
+    pub fn sync(&mut self) -> Result<()> {
+        if !self.unsynced {
+            return Ok(());
+        }
+        match self.durability {
+            Durability::Always => self.flush(),
+            Durability::Interval(interval) if self.last_sync.elapsed() >= interval => self.flush(),
+            Durability::Interval(_) | Durability::Never => Ok(()),
+        }
+    }
+
+    /// Flushes the log to durable storage, regardless of the durability
+    /// policy. Appends may already be flushed, but the commit index isn't.
+    pub fn flush(&mut self) -> Result<()> {
+        self.engine.flush()?;
+        self.unsynced = false;
+        self.last_sync = Instant::now();
+        Ok(())
+    }
+
+    /// Compacts the log engine if its garbage exceeds the given thresholds,
+    /// see storage::Engine::maybe_compact(). Returns true if it was compacted.
+    pub fn maybe_compact(
+        &mut self,
+        garbage_min_fraction: f64,
+        garbage_min_bytes: u64,
+    ) -> Result<bool> {
+        self.engine.maybe_compact(garbage_min_fraction, garbage_min_bytes)
+    }
+
+    /// Backs up the log engine to a file, see storage::Engine::backup().
+    pub fn backup(&mut self, dest: &std::path::Path) -> Result<()> {
+        self.engine.backup(dest)
+    }
+
     /// Returns log engine status.
     pub fn status(&mut self) -> Result<storage::Status> {
         self.engine.status()
@@ -356,7 +610,7 @@ impl std::iter::Iterator for Iterator<'_> {
     type Item = Result<Entry>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.inner.next().map(|r| r.and_then(|(_, v)| Entry::decode(&v)))
+        self.inner.next().map(|r| r.and_then(|(_, v)| Entry::decode_versioned(&v)))
     }
 }
 
@@ -462,6 +716,14 @@ mod tests {
                     writeln!(output, "commit → {fmtentry}")?;
                 }
 
+                // durability POLICY
+                "durability" => {
+                    let mut args = command.consume_args();
+                    let durability = args.next_pos().ok_or("policy not given")?.parse()?;
+                    args.reject_rest()?;
+                    self.log.set_durability(durability);
+                }
+
                 // dump
                 "dump" => {
                     command.consume_args().reject_rest()?;
@@ -498,6 +760,14 @@ mod tests {
                     writeln!(output, "term={term} vote={vote}")?;
                 }
 
+                // group_commit BOOL
+                "group_commit" => {
+                    let mut args = command.consume_args();
+                    let enabled = args.next_pos().ok_or("bool not given")?.parse()?;
+                    args.reject_rest()?;
+                    self.log.enable_group_commit(enabled);
+                }
+
                 // has INDEX@TERM...
                 "has" => {
                     let mut args = command.consume_args();
@@ -513,6 +783,15 @@ mod tests {
                     }
                 }
 
+                // install_snapshot INDEX@TERM
+                "install_snapshot" => {
+                    let mut args = command.consume_args();
+                    let arg = args.next_pos().ok_or("index/term not given")?;
+                    let (index, term) = Self::parse_index_term(&arg.value)?;
+                    args.reject_rest()?;
+                    self.log.install_snapshot(index, term)?;
+                }
+
                 // reload
                 "reload" => {
                     command.consume_args().reject_rest()?;
@@ -591,12 +870,31 @@ mod tests {
                         output,
                         "term={term} last={last_index}@{last_term} commit={commit_index}@{commit_term} vote={vote}",
                     )?;
+                    let (snapshot_index, snapshot_term) = self.log.get_snapshot_index();
+                    if snapshot_index > 0 {
+                        write!(output, " snapshot={snapshot_index}@{snapshot_term}")?;
+                    }
                     if engine {
                         write!(output, " engine={:#?}", self.log.status()?)?;
                     }
                     writeln!(output)?;
                 }
 
+                // sync
+                "sync" => {
+                    command.consume_args().reject_rest()?;
+                    self.log.sync()?;
+                }
+
+                // truncate INDEX
+                "truncate" => {
+                    let mut args = command.consume_args();
+                    let index = args.next_pos().ok_or("index not given")?.parse()?;
+                    args.reject_rest()?;
+                    let index = self.log.truncate(index)?;
+                    writeln!(output, "truncate → {index}")?;
+                }
+
                 name => return Err(format!("unknown command {name}").into()),
             }
 
@@ -1,9 +1,11 @@
-use super::{Entry, Index, NodeID, Term};
+use super::{Digest, Entry, Index, Metrics, NodeID, Report, StateReport, Term, TraceEvent};
 use crate::encoding;
 use crate::error::Result;
 use crate::storage;
 
+use crossbeam::channel::Sender;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 /// A message envelope specifying the sender and receiver.
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
@@ -37,6 +39,10 @@ pub enum Message {
         last_index: Index,
         /// The term of the candidate's last log entry.
         last_term: Term,
+        /// If true, the leader asked the candidate to campaign via TimeoutNow.
+        /// With leader leases, nodes otherwise ignore campaigns while they
+        /// have a leader, see `Options::lease_duration`.
+        transfer: bool,
     },
 
     /// Followers may vote for a single candidate per term, but only if the
@@ -48,6 +54,26 @@ pub enum Message {
         vote: bool,
     },
 
+    /// Before campaigning, nodes ask peers whether they would grant a vote in
+    /// the next term, without changing anyone's term. See `Options::pre_vote`
+    /// and section 9.6 in the Raft thesis. The envelope carries the candidate's
+    /// prospective term (its current term + 1), which recipients don't adopt.
+    PreVote {
+        /// The index of the candidate's last log entry.
+        last_index: Index,
+        /// The term of the candidate's last log entry.
+        last_term: Term,
+    },
+
+    /// Nodes grant a pre-vote if they haven't heard from a leader within an
+    /// election timeout, and the candidate's log is at least as up-to-date as
+    /// theirs. Granted pre-votes are sent in the candidate's prospective term,
+    /// rejections in the voter's current term.
+    PreVoteResponse {
+        /// If true, the node would grant the candidate a vote.
+        vote: bool,
+    },
+
     /// Leaders send periodic heartbeats. This serves several purposes:
     ///
     /// * Inform nodes about the leader, and prevent elections.
@@ -120,6 +146,19 @@ pub enum Message {
         reject_index: Index,
     },
 
+    /// Leaders send a state machine snapshot to followers that lag behind the
+    /// leader's truncated log, since the missing entries can't be replicated.
+    /// The follower replaces its state machine with the snapshot, and responds
+    /// with an AppendResponse matching the snapshot index.
+    InstallSnapshot {
+        /// The index of the last entry included in the snapshot.
+        index: Index,
+        /// The term of the last entry included in the snapshot.
+        term: Term,
+        /// The state machine snapshot, see `State::snapshot`.
+        data: Vec<u8>,
+    },
+
     /// Leaders need to confirm they are still the leader before serving reads,
     /// to guarantee linearizability in case a different leader has been
     /// estalished elsewhere. Read requests are served once the sequence number
@@ -129,6 +168,66 @@ pub enum Message {
     /// Followers confirm leadership at the read sequence numbers.
     ReadResponse { seq: ReadSequence },
 
+    /// Leaders ask followers for a digest of their log, to verify that the
+    /// logs are consistent. See `Request::Verify`.
+    Verify {
+        /// The verification request ID.
+        id: RequestID,
+        /// The leader's commit index. Followers digest their log up to this
+        /// index or their own commit index, whichever is lower, since
+        /// uncommitted entries may legitimately differ from the leader.
+        commit_index: Index,
+    },
+
+    /// Followers respond with a digest of their log, or an error if they
+    /// failed to compute it (e.g. due to log corruption).
+    VerifyResponse {
+        /// The verification request ID.
+        id: RequestID,
+        /// The log digest, or an error.
+        digest: Result<Digest>,
+    },
+
+    /// Leaders ask followers for checksums of their state machine, to verify
+    /// that the state machines are consistent. See `Request::VerifyState`.
+    VerifyState {
+        /// The verification request ID.
+        id: RequestID,
+        /// The leader's applied index. Followers compute checksums once they
+        /// have applied this index, which they may not have done yet.
+        applied_index: Index,
+    },
+
+    /// Followers respond with their state machine checksums at the applied
+    /// index, or an error if they failed to compute them (e.g. because they
+    /// have already applied later entries).
+    VerifyStateResponse {
+        /// The verification request ID.
+        id: RequestID,
+        /// The state machine checksums by partition, or an error.
+        checksums: Result<BTreeMap<String, u64>>,
+    },
+
+    /// Leaders ask followers for their node status. See `Request::Nodes`.
+    NodeStatus {
+        /// The nodes request ID.
+        id: RequestID,
+    },
+
+    /// Followers respond with their node status.
+    NodeStatusResponse {
+        /// The nodes request ID.
+        id: RequestID,
+        /// The follower's node status.
+        status: NodeStatus,
+    },
+
+    /// Leaders transferring leadership tell the target follower to campaign
+    /// immediately, without waiting for an election timeout. This is only sent
+    /// once the follower's log has caught up with the leader's, such that it
+    /// can win the election. See section 3.10 in the Raft thesis.
+    TimeoutNow,
+
     /// A client request. This can be submitted to the leader, or to a follower
     /// which will forward it to its leader. If there is no leader, or the
     /// leader or term changes, the request is aborted with an Error::Abort
@@ -149,6 +248,41 @@ pub enum Message {
     },
 }
 
+impl Message {
+    /// Returns true if the message is a pre-vote or pre-vote grant, which
+    /// uses the candidate's prospective term rather than the sender's term.
+    /// Recipients must not adopt this term.
+    pub fn is_pre_vote(&self) -> bool {
+        matches!(self, Self::PreVote { .. } | Self::PreVoteResponse { vote: true })
+    }
+
+    /// Returns the message kind, for metrics.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Campaign { .. } => "campaign",
+            Self::CampaignResponse { .. } => "campaign_response",
+            Self::PreVote { .. } => "pre_vote",
+            Self::PreVoteResponse { .. } => "pre_vote_response",
+            Self::Heartbeat { .. } => "heartbeat",
+            Self::HeartbeatResponse { .. } => "heartbeat_response",
+            Self::Append { .. } => "append",
+            Self::AppendResponse { .. } => "append_response",
+            Self::InstallSnapshot { .. } => "install_snapshot",
+            Self::Read { .. } => "read",
+            Self::ReadResponse { .. } => "read_response",
+            Self::Verify { .. } => "verify",
+            Self::VerifyResponse { .. } => "verify_response",
+            Self::VerifyState { .. } => "verify_state",
+            Self::VerifyStateResponse { .. } => "verify_state_response",
+            Self::NodeStatus { .. } => "node_status",
+            Self::NodeStatusResponse { .. } => "node_status_response",
+            Self::TimeoutNow => "timeout_now",
+            Self::ClientRequest { .. } => "client_request",
+            Self::ClientResponse { .. } => "client_response",
+        }
+    }
+}
+
 /// A client request ID. Must be globally unique for the duration of the
 /// request. For simplicity, a random UUIDv4 is used -- the node ID and process
 /// ID could be incorporated for further collision avoidance, but it does not
@@ -158,21 +292,89 @@ pub type RequestID = uuid::Uuid;
 /// A read sequence number, used to confirm leadership for linearizable reads.
 pub type ReadSequence = u64;
 
+/// Submits client requests to the local Raft node, along with a response
+/// channel and the client's tracing span. The node's processing of the request
+/// (e.g. proposing and applying a write) is traced as part of this span.
+pub type RequestSender = Sender<(Request, Sender<Result<Response>>, tracing::Span)>;
+
 /// A client request, typically passed through to the state machine.
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Request {
     /// A state machine read command, executed via `State::read`. This is not
     /// replicated, and only evaluated on the leader.
     Read(Vec<u8>),
+    /// A state machine read command which may be evaluated on a follower with
+    /// bounded staleness, see `Options::max_read_staleness`. The client can
+    /// give a tighter bound via max_staleness. Falls back to a regular leader
+    /// read if the follower is too far behind.
+    ReadStale { command: Vec<u8>, max_staleness: Option<super::Ticks> },
     /// A state machine write command, executed via `State::apply`. This is
     /// replicated across all nodes, and must produce a deterministic result.
     Write(Vec<u8>),
     /// Requests Raft cluster status from the leader.
     Status,
+    /// Verifies that the committed log entries of all nodes are consistent
+    /// with the leader's log. Nodes that don't respond within an election
+    /// timeout are omitted from the report.
+    Verify,
+    /// Verifies that the state machines of all nodes are consistent with the
+    /// leader's state machine, by comparing checksums at the leader's applied
+    /// index. Nodes that don't respond within an election timeout are omitted
+    /// from the report.
+    VerifyState,
+    /// Returns the local node's recent event trace, for debugging. This is not
+    /// forwarded to the leader.
+    Trace,
+    /// Returns the status of every node in the cluster, assembled by the leader
+    /// by asking its peers. Nodes that don't respond within an election timeout
+    /// are reported as unreachable.
+    Nodes,
+    /// Transfers leadership away from the local node, if it's the leader, to
+    /// the given voting peer, or its most up-to-date voting peer if None. The
+    /// leader rejects writes until the transfer completes or times out. This
+    /// is not forwarded to the leader, and is used e.g. to drain the local node
+    /// before shutting it down.
+    TransferLeadership(Option<NodeID>),
+    /// Checks the local node's health: flushes its log storage to verify that
+    /// it's writable, and returns its status. This is not forwarded to the
+    /// leader, and is used e.g. by readiness checks.
+    Health,
+    /// Compacts the local node's Raft log and state machine storage if their
+    /// garbage exceeds the given fraction of the total size and the given
+    /// number of bytes, see `storage::Engine::maybe_compact`. This is not
+    /// forwarded to the leader, and is used by periodic maintenance.
+    Compact { garbage_min_fraction: f64, garbage_min_bytes: u64 },
+    /// Writes a consistent backup of the local node's Raft log and state
+    /// machine storage to the given directory, see `storage::Engine::backup`.
+    /// This is not forwarded to the leader.
+    Backup { path: String },
+    /// Fetches the local node's metrics. This is not forwarded to the leader.
+    Metrics,
 }
 
 impl encoding::Value for Request {}
 
+impl Request {
+    /// Returns the request kind, for tracing and logging.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Read(_) => "read",
+            Self::ReadStale { .. } => "read_stale",
+            Self::Write(_) => "write",
+            Self::Status => "status",
+            Self::Verify => "verify",
+            Self::VerifyState => "verify_state",
+            Self::Trace => "trace",
+            Self::Nodes => "nodes",
+            Self::TransferLeadership(_) => "transfer_leadership",
+            Self::Health => "health",
+            Self::Compact { .. } => "compact",
+            Self::Backup { .. } => "backup",
+            Self::Metrics => "metrics",
+        }
+    }
+}
+
 /// A client response. This will be wrapped in a Result for error handling.
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Response {
@@ -182,6 +384,27 @@ pub enum Response {
     Write(Vec<u8>),
     /// The current Raft leader status.
     Status(Status),
+    /// A log verification report.
+    Verify(Report),
+    /// A state machine verification report.
+    VerifyState(StateReport),
+    /// The local node's event trace, oldest first.
+    Trace(Vec<TraceEvent>),
+    /// The status of each node in the cluster, or None if it's unreachable.
+    /// Uses a BTreeMap for test determinism.
+    Nodes(BTreeMap<NodeID, Option<NodeStatus>>),
+    /// The peer that leadership was transferred to, or None if the local node
+    /// wasn't the leader or had no peers.
+    TransferLeadership(Option<NodeID>),
+    /// The local node's status, after a successful health check.
+    Health(NodeStatus),
+    /// Whether the local node's Raft log and state machine storage were
+    /// compacted.
+    Compact { log: bool, state: bool },
+    /// The state machine's applied index at the time of the backup.
+    Backup { applied_index: Index },
+    /// The local node's metrics.
+    Metrics(Metrics),
 }
 
 impl encoding::Value for Response {}
@@ -195,7 +418,7 @@ pub struct Status {
     pub term: Term,
     /// The match indexes of all nodes, indicating replication progress. Uses a
     /// BTreeMap for test determinism.
-    pub match_index: std::collections::BTreeMap<NodeID, Index>,
+    pub match_index: BTreeMap<NodeID, Index>,
     /// The current commit index.
     pub commit_index: Index,
     /// The current applied index.
@@ -203,3 +426,24 @@ pub struct Status {
     /// The log storage engine status.
     pub storage: storage::Status,
 }
+
+/// The status of an individual node, as reported by the node itself.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NodeStatus {
+    /// The node's Raft role: leader, follower, learner, or candidate.
+    pub role: String,
+    /// The node's current term.
+    pub term: Term,
+    /// The node's leader, if any.
+    pub leader: Option<NodeID>,
+    /// The node's last log index.
+    pub last_index: Index,
+    /// The node's commit index.
+    pub commit_index: Index,
+    /// The node's applied index.
+    pub applied_index: Index,
+    /// The node's log storage engine status.
+    pub storage: storage::Status,
+    /// The node's toyDB version.
+    pub version: String,
+}
@@ -0,0 +1,118 @@
+//! Raft node metrics.
+//!
+//! Operators (and tests) often need to know how far each replica lags behind
+//! the leader, or whether the cluster is suffering from election churn. To
+//! help with this, each node continuously maintains cumulative counters of
+//! elections, leader changes, and messages sent and received. These can be
+//! fetched via `Request::Metrics`, along with a snapshot of the node's current
+//! state and, on the leader, the replication progress of each peer.
+//!
+//! The node itself doesn't queue messages, but the server that hosts it does:
+//! inbound messages and client requests wait to be stepped, and outbound
+//! messages wait to be sent to each peer. When the server routes a metrics
+//! response to a client, it fills in the depth of these queues, which shows
+//! whether the node (or one of its peers) is falling behind.
+
+use super::{Index, Message, NodeID, Term};
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// A snapshot of a Raft node's metrics, as reported by the node itself.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Metrics {
+    /// The node ID.
+    pub id: NodeID,
+    /// The node's Raft role: leader, follower, learner, or candidate.
+    pub role: String,
+    /// The node's current term.
+    pub term: Term,
+    /// The node's leader, if any.
+    pub leader: Option<NodeID>,
+    /// The node's last log index.
+    pub last_index: Index,
+    /// The node's commit index.
+    pub commit_index: Index,
+    /// The node's applied index.
+    pub applied_index: Index,
+    /// The replication progress of each peer. Only reported by the leader, and
+    /// empty on other nodes. Uses a BTreeMap for test determinism.
+    pub progress: BTreeMap<NodeID, PeerProgress>,
+    /// Cumulative counters since the node was started.
+    pub counters: Counters,
+    /// The server's message queues, by name. Filled in by the server when
+    /// routing the response, and empty when reported by the node itself.
+    pub queues: BTreeMap<String, QueueDepth>,
+}
+
+/// The leader's view of a peer's replication progress.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PeerProgress {
+    /// The last log index known to be replicated to the peer.
+    pub match_index: Index,
+    /// The next log index to send to the peer.
+    pub next_index: Index,
+    /// The number of unacknowledged in-flight appends.
+    pub inflight_appends: usize,
+    /// The number of unacknowledged in-flight entry bytes.
+    pub inflight_bytes: usize,
+}
+
+impl PeerProgress {
+    /// Returns the number of log entries the peer lags behind the given last
+    /// log index.
+    pub fn lag(&self, last_index: Index) -> Index {
+        last_index.saturating_sub(self.match_index)
+    }
+}
+
+/// The depth of a server message queue.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct QueueDepth {
+    /// The number of queued messages.
+    pub depth: usize,
+    /// The queue capacity, or None if it's unbounded.
+    pub capacity: Option<usize>,
+    /// The number of messages dropped because the queue was full.
+    pub dropped: u64,
+}
+
+/// Cumulative Raft node counters. These are never reset while the node is
+/// running, but start from 0 when it's restarted.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Counters {
+    /// The number of pre-votes held, see `Options::pre_vote`.
+    pub pre_votes: u64,
+    /// The number of elections started, i.e. campaigns as a candidate.
+    pub elections: u64,
+    /// The number of elections won.
+    pub elections_won: u64,
+    /// The number of times the node learned of a new leader, including itself.
+    pub leader_changes: u64,
+    /// The number of messages sent, by message kind. Uses a BTreeMap for test
+    /// determinism.
+    pub messages_sent: BTreeMap<String, u64>,
+    /// The number of messages received, by message kind.
+    pub messages_received: BTreeMap<String, u64>,
+}
+
+impl Counters {
+    /// Records a sent message.
+    pub fn sent(&mut self, message: &Message) {
+        Self::increment(&mut self.messages_sent, message.kind())
+    }
+
+    /// Records a received message.
+    pub fn received(&mut self, message: &Message) {
+        Self::increment(&mut self.messages_received, message.kind())
+    }
+
+    /// Increments the counter for the given message kind. Avoids allocating
+    /// the key unless it's new, since this is called for every message.
+    fn increment(counts: &mut BTreeMap<String, u64>, kind: &str) {
+        match counts.get_mut(kind) {
+            Some(count) => *count += 1,
+            None => _ = counts.insert(kind.to_string(), 1),
+        }
+    }
+}
@@ -133,6 +133,24 @@
 //! hold new elections by itself until the network heals, at which point a new
 //! election will be held in its term (disrupting the current leader).
 //!
+//! To avoid this disruption, nodes can hold a pre-vote before campaigning if
+//! `Options::pre_vote` is enabled (Raft thesis section 9.6). The node sends
+//! `Message::PreVote` to all peers for its next term, without changing its own
+//! term. Peers respond with `Message::PreVoteResponse`, granting the pre-vote
+//! if they haven't heard from a leader either and the node's log is at least
+//! as up-to-date as theirs, without changing their term or vote. Only once a
+//! majority grants the pre-vote does the node become a candidate and campaign.
+//! A disconnected node thus remains in its current term, and rejoins the
+//! current leader when the network heals.
+//!
+//! Conversely, a leader that is partitioned away from the majority keeps
+//! believing it's the leader until it hears about a new term, and clients
+//! connected to it stall. If `Options::check_quorum` is enabled (Raft thesis
+//! section 6.2), the leader tracks which peers it has heard from, and steps
+//! down to a leaderless follower in its current term if it hasn't heard from
+//! a quorum within an election timeout. Pending client requests are aborted
+//! with `Error::Abort`, so clients can retry them against the new leader.
+//!
 //! REPLICATION AND CONSENSUS
 //! =========================
 //!
@@ -172,6 +190,42 @@
 //! index/term pair in their log, they'll say so in the `HeartbeatResponse` and
 //! the leader can begin probing their logs as with append rejections.
 //!
+//! Appends are pipelined: the leader doesn't wait for a follower's response
+//! before sending it further entries. The number of unacknowledged appends and
+//! bytes in flight to each follower can be limited via
+//! `Options::max_inflight_appends` and `Options::max_inflight_bytes`. Once
+//! reached, new entries are queued in the log, and sent to the follower as a
+//! single batched `Append` when it acknowledges earlier appends.
+//!
+//! If a quorum can't keep up with the write load, the leader's uncommitted
+//! log tail keeps growing. `Options::max_uncommitted_entries` bounds it: once
+//! reached, the leader rejects new writes with `Error::Overloaded`, which
+//! clients can retry once replication has caught up.
+//!
+//! Nodes listed in `Options::learners` are non-voting members (Raft thesis
+//! section 4.2.1). The leader replicates the log to them like any follower,
+//! and they apply it to their state machine, but they don't count towards
+//! quorums, never campaign, and don't vote. They can serve as read replicas,
+//! or catch up a new node before it's added as a voter.
+//!
+//! LOG TRUNCATION AND SNAPSHOTS
+//! ============================
+//!
+//! If `Options::snapshot_interval` is set, nodes periodically truncate their
+//! log to avoid retaining it forever (Raft paper section 7). Once that many
+//! entries have been applied since the last truncation, the node flushes the
+//! state machine to durable storage and removes all applied log entries via
+//! `Log.truncate()`, recording the index/term of the last truncated entry as
+//! the log's snapshot index. The state machine itself serves as the snapshot.
+//!
+//! When the leader needs to replicate entries that it has truncated, it
+//! instead sends a `Message::InstallSnapshot` containing a serialized copy of
+//! its state machine (see `State::snapshot`) at its applied index. The
+//! follower replaces its state machine with it via `State::restore`, and
+//! discards its log up to the snapshot index via `Log.install_snapshot()`
+//! (retaining any later entries if its log matches the snapshot). It then
+//! responds with an `AppendResponse`, and replication resumes from there.
+//!
 //! CLIENT REQUESTS
 //! ===============
 //!
@@ -201,6 +255,83 @@
 //! quorum have confirmed a sequence number the read is executed and the result
 //! returned to the client.
 //!
+//! With `Options::lease_duration`, the leader holds a lease while a quorum has
+//! confirmed a heartbeat or read sequence number that was sent within the lease
+//! duration, and serves reads immediately from its local state machine without
+//! confirming them (Raft thesis section 6.4.1). Followers ignore campaigns while
+//! they have a leader, so a new leader can't be elected until the lease has
+//! expired, except by a leadership transfer which revokes the lease first. The
+//! lease duration must be below the election timeout, and this relies on
+//! bounded clock drift between nodes; otherwise, reads may be stale.
+//!
+//! Stale read requests, `Request::ReadStale`, trade linearizability for
+//! scalability by executing reads on followers. A follower serves a stale read
+//! from its local state machine if it has heard a heartbeat from the leader
+//! within `Options::max_read_staleness` ticks and has applied all entries that
+//! were committed as of that heartbeat. The result may lag the leader by up to
+//! that many ticks (plus message delays), but is never older than the
+//! follower's previous reads. Otherwise, the read is forwarded to the leader
+//! which executes it as a regular read.
+//!
+//! Verify requests, `Request::Verify`, cross-check the committed log entries of
+//! all nodes against the leader's log. The leader sends a `Verify` message, and
+//! followers respond with a compact `Digest` of their committed log. These are
+//! compared against the leader's log, and any divergence is reported to the
+//! client. This is purely diagnostic, and helps detect bugs or corruption that
+//! violate the Log Matching Property.
+//!
+//! State verify requests, `Request::VerifyState`, similarly cross-check the
+//! state machines of all nodes, which can diverge despite identical logs, e.g.
+//! due to non-deterministic command application. The leader sends a
+//! `VerifyState` message with its applied index, and followers respond with
+//! `State::checksums` once they have applied exactly that index -- lagging
+//! followers defer their response until they catch up. The checksums are
+//! compared against the leader's, and divergent partitions (e.g. SQL tables)
+//! are reported to the client.
+//!
+//! Trace requests, `Request::Trace`, return the local node's recent events
+//! (role changes, votes, appends, commits, and snapshots) from an in-memory ring buffer.
+//! Unlike other requests, these are handled by the local node regardless of
+//! its role, and are useful for post-mortem debugging of consensus issues.
+//!
+//! Metrics requests, `Request::Metrics`, are similarly handled by the local
+//! node, and return its current term, role, leader, and log indexes along with
+//! cumulative counters of elections, leader changes, and messages sent and
+//! received by kind. The leader also reports the match and next index of each
+//! peer, from which operators can derive replication lag.
+//!
+//! Node status requests, `Request::Nodes`, report the status of every node in
+//! the cluster: its role, term, log indexes, storage, and version. The leader
+//! sends a `NodeStatus` message to its peers and assembles their responses,
+//! reporting nodes that don't respond within an election timeout as
+//! unreachable. This backs the SQL `SHOW NODES` command.
+//!
+//! Health requests, `Request::Health`, check the local node's health for
+//! readiness probes. The node flushes its log storage to check that it's
+//! writable, and returns its own status, including its leader (if any) and
+//! applied index. Like trace requests, they're handled by the local node.
+//!
+//! Compaction requests, `Request::Compact`, compact the local node's log and
+//! state machine storage engines if they contain enough garbage. BitCask
+//! storage otherwise only compacts when it's opened, so long-running nodes
+//! issue these periodically (see the server's maintenance scheduler).
+//!
+//! Backup requests, `Request::Backup`, write a backup of the local node's log
+//! and state machine storage to a directory (see `storage::Engine::backup`).
+//! The node doesn't process other messages while the backup is taken, so the
+//! state machine backup is consistent with the log, and the rest of the cluster
+//! keeps serving requests meanwhile. A restored node catches up with the
+//! cluster via the log like any node that was offline.
+//!
+//! Leadership transfer requests, `Request::TransferLeadership`, hand off
+//! leadership before the leader is shut down or drained for maintenance, to
+//! avoid waiting for an election timeout (Raft thesis section 3.10). The leader
+//! uses the requested voting peer or picks its most up-to-date one, replicates
+//! any missing entries to it, and then sends it a `TimeoutNow` message which
+//! makes it campaign immediately. Meanwhile, the leader rejects writes such
+//! that the target can catch up. Followers and candidates respond to these
+//! requests locally, since they have no leadership to transfer.
+//!
 //! IMPLEMENTATION CAVEATS
 //! ======================
 //!
@@ -208,28 +339,19 @@
 //! correct Raft protocol, and omits several advanced mechanisms that would be
 //! needed for a real production system. In particular:
 //!
-//! * No leases: for linearizability, every read request requires the leader to
-//!   confirm with followers that it's still the leader. This could be avoided
-//!   with a leader lease for a predefined time interval (Raft paper section 8,
-//!   Raft thesis section 6.3).
-//!
 //! * No cluster membership changes: to add or remove nodes, the entire cluster
 //!   must be stopped and restarted with the new configuration, otherwise it
-//!   risks multiple leaders (Raft paper section 6).
+//!   risks multiple leaders (Raft paper section 6). New nodes can first be
+//!   added as learners to catch up, but this also requires a restart.
 //!
-//! * No snapshots: new or lagging nodes must be caught up by replicating and
-//!   replaying the entire log, instead of sending a state machine snapshot
-//!   (Raft paper section 7).
+//! * Unchunked snapshots: a snapshot is sent to a follower as a single message
+//!   containing the entire state machine, which is held in memory. Large state
+//!   machines would need to stream snapshots in chunks (Raft paper section 7).
 //!
-//! * No log truncation: because snapshots aren't supported, the entire Raft
-//!   log must be retained forever in order to catch up new/lagging nodes,
-//!   leading to excessive storage use (Raft paper section 7).
-//!
-//! * No pre-vote or check-quorum: a node that's partially partitioned (can
-//!   reach some but not all nodes) can cause persistent unavailability with
-//!   spurious elections or heartbeats. A node rejoining after a partition can
-//!   also temporarily disrupt a leader. This requires additional pre-vote and
-//!   check-quorum protocol extensions (Raft thesis section 4.2.3 and 9.6).
+//! * No check-quorum: a leader that's partially partitioned (can reach some
+//!   but not all nodes) can cause persistent unavailability with spurious
+//!   heartbeats, since it won't step down when it loses its quorum. This
+//!   requires the check-quorum protocol extension (Raft thesis section 6.2).
 //!
 //! * No request retries: client requests will not be retried on leader changes
 //!   or message loss, and will be aggressively aborted, to ignore problems
@@ -241,23 +363,42 @@
 
 mod log;
 mod message;
+mod metrics;
 mod node;
+#[cfg(any(test, feature = "sim"))]
+pub mod sim;
 mod state;
+mod trace;
+mod verify;
 
-pub use log::{Entry, Index, Key, Log};
-pub use message::{Envelope, Message, ReadSequence, Request, RequestID, Response, Status};
+pub use log::{Durability, Entry, Index, Key, Log};
+pub use message::{
+    Envelope, Message, NodeStatus, ReadSequence, Request, RequestID, RequestSender, Response,
+    Status,
+};
+pub use metrics::{Counters, Metrics, PeerProgress, QueueDepth};
 pub use node::{Node, NodeID, Options, Term, Ticks};
 pub use state::State;
+pub use trace::{Event, Trace, TraceEvent};
+pub use verify::{Checksum, Digest, Divergence, NodeReport, Report, StateNodeReport, StateReport};
 
-/// The interval between Raft ticks, the unit of time.
+/// The default interval between Raft ticks, the unit of time.
 pub const TICK_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
 
-/// The interval between leader heartbeats in ticks.
+/// The default interval between leader heartbeats in ticks.
 const HEARTBEAT_INTERVAL: Ticks = 4;
 
 /// The default election timeout range in ticks. This is randomized in this
 /// interval, to avoid election ties.
 const ELECTION_TIMEOUT_RANGE: std::ops::Range<Ticks> = 10..20;
 
-/// The maximum number of entries to send in a single append message.
+/// The default maximum number of entries to send in a single append message.
 const MAX_APPEND_ENTRIES: usize = 100;
+
+/// The default maximum staleness of follower reads in ticks, i.e. the number of
+/// ticks since the leader's last heartbeat. Two heartbeat intervals tolerates a
+/// single lost heartbeat.
+const MAX_READ_STALENESS: Ticks = 2 * HEARTBEAT_INTERVAL;
+
+/// The default number of recent events retained in a node's event trace.
+const TRACE_CAPACITY: usize = 1000;
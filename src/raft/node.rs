@@ -1,14 +1,21 @@
 use super::log::{Index, Log};
-use super::message::{Envelope, Message, ReadSequence, Request, RequestID, Response, Status};
+use super::message::{
+    Envelope, Message, NodeStatus, ReadSequence, Request, RequestID, Response, Status,
+};
+use super::metrics::{Counters, Metrics, PeerProgress};
 use super::state::State;
-use crate::errinput;
+use super::trace::{Event, Trace};
+use super::verify::{Digest, NodeReport, Report, StateNodeReport, StateReport};
 use crate::error::{Error, Result};
+use crate::{errdata, errinput};
 
 use crossbeam::channel::Sender;
 use itertools::Itertools as _;
 use log::{debug, info};
 use rand::Rng as _;
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use tracing::info_span;
 
 /// A node ID. Unique within a cluster. Assigned manually when started.
 pub type NodeID = u8;
@@ -28,6 +35,68 @@ pub struct Options {
     pub election_timeout_range: std::ops::Range<Ticks>,
     /// Maximum number of entries to send in a single Append message.
     pub max_append_entries: usize,
+    /// The maximum number of ticks since the leader's last heartbeat for a
+    /// follower to serve stale reads (`Request::ReadStale`) locally.
+    pub max_read_staleness: Ticks,
+    /// The maximum number of catch-up Append messages to send to a lagging
+    /// follower per tick, or None for no limit. This prevents followers that
+    /// replay a large backlog from starving foreground replication.
+    pub max_catchup_messages: Option<usize>,
+    /// The maximum number of command bytes to send to a lagging follower per
+    /// tick in catch-up Append messages, or None for no limit. At least one
+    /// entry is sent per message, even if it exceeds the limit.
+    pub max_catchup_bytes: Option<usize>,
+    /// The maximum number of unacknowledged Append messages in flight to a
+    /// follower, or None for no limit. Once reached, new entries are queued
+    /// until the follower acknowledges earlier appends, and are then sent as
+    /// a single batch.
+    pub max_inflight_appends: Option<usize>,
+    /// The maximum number of unacknowledged command bytes in flight to a
+    /// follower, or None for no limit. As with max_inflight_appends, entries
+    /// are queued and batched once reached. At least one entry is sent per
+    /// message, even if it exceeds the limit.
+    pub max_inflight_bytes: Option<usize>,
+    /// The maximum number of uncommitted entries in the leader's log, or None
+    /// for no limit. Once reached, the leader rejects new writes with
+    /// `Error::Overloaded` until a quorum catches up and commits them, which
+    /// pushes back on clients when replication can't keep up with the write
+    /// load, instead of buffering an ever-growing backlog. The same limit
+    /// applies to committed entries awaiting application to the state machine.
+    pub max_uncommitted_entries: Option<usize>,
+    /// The number of recent events to retain in the event trace, see `Trace`.
+    /// 0 disables tracing.
+    pub trace_capacity: usize,
+    /// The number of applied entries after which the log is truncated up to
+    /// the applied index, or None to never truncate it. Followers that lag
+    /// behind the truncated log are caught up with a state machine snapshot,
+    /// so the state machine must support `State::snapshot`.
+    pub snapshot_interval: Option<Index>,
+    /// If true, nodes hold a pre-vote before campaigning, and only campaign
+    /// (incrementing their term) if a quorum would grant them a vote. This
+    /// prevents partitioned nodes from disrupting the cluster when they
+    /// rejoin with a higher term. See section 9.6 in the Raft thesis.
+    pub pre_vote: bool,
+    /// The duration of leader leases, or None to disable them. While a quorum
+    /// has confirmed a heartbeat sent within this many ticks, the leader
+    /// serves linearizable reads locally without confirming its leadership
+    /// with followers. To make this safe, nodes ignore campaigns while they
+    /// have a leader and for an election timeout after startup (except
+    /// leadership transfers), so it must be set on all nodes. Must be below
+    /// the minimum election timeout, with enough margin for clock rate
+    /// differences between nodes. See section 6.4.1 in the Raft thesis.
+    pub lease_duration: Option<Ticks>,
+    /// If true, the leader steps down if it hasn't heard from a quorum within
+    /// the minimum election timeout, e.g. because it's been partitioned away
+    /// from the majority. Pending requests are aborted, such that clients can
+    /// retry them against the new leader instead of stalling. See section
+    /// 6.2 in the Raft thesis.
+    pub check_quorum: bool,
+    /// The IDs of learners, i.e. non-voting nodes, which may include this
+    /// node. Learners replicate and apply the log, but don't vote, campaign,
+    /// or count towards quorums. They can be used as read replicas, or to
+    /// catch up a new node before it's added as a voter (by restarting the
+    /// cluster without it in learners). Must be the same on all nodes.
+    pub learners: HashSet<NodeID>,
 }
 
 impl Default for Options {
@@ -36,10 +105,80 @@ impl Default for Options {
             heartbeat_interval: super::HEARTBEAT_INTERVAL,
             election_timeout_range: super::ELECTION_TIMEOUT_RANGE,
             max_append_entries: super::MAX_APPEND_ENTRIES,
+            max_read_staleness: super::MAX_READ_STALENESS,
+            max_catchup_messages: None,
+            max_catchup_bytes: None,
+            max_inflight_appends: None,
+            max_inflight_bytes: None,
+            max_uncommitted_entries: None,
+            trace_capacity: super::TRACE_CAPACITY,
+            snapshot_interval: None,
+            pre_vote: false,
+            lease_duration: None,
+            check_quorum: false,
+            learners: HashSet::new(),
         }
     }
 }
 
+impl Options {
+    /// Validates the options, returning an error if they're unreasonable. The
+    /// node itself accepts any non-empty timeout range (tests rely on this),
+    /// but servers should validate user-provided options before use.
+    ///
+    /// The election timeout must be at least twice the heartbeat interval,
+    /// otherwise followers may call elections while a healthy leader is simply
+    /// waiting to send its next heartbeat.
+    pub fn validate(&self) -> Result<()> {
+        if self.heartbeat_interval == 0 {
+            return errinput!("heartbeat interval must be positive");
+        }
+        if self.election_timeout_range.is_empty() {
+            return errinput!("election timeout range {:?} is empty", self.election_timeout_range);
+        }
+        if (self.election_timeout_range.start as usize) < 2 * self.heartbeat_interval as usize {
+            return errinput!(
+                "election timeout {} must be at least twice the heartbeat interval {}",
+                self.election_timeout_range.start,
+                self.heartbeat_interval
+            );
+        }
+        if self.max_append_entries == 0 {
+            return errinput!("max append entries must be positive");
+        }
+        if self.max_catchup_messages == Some(0) {
+            return errinput!("max catch-up messages must be positive");
+        }
+        if self.max_catchup_bytes == Some(0) {
+            return errinput!("max catch-up bytes must be positive");
+        }
+        if self.max_inflight_appends == Some(0) {
+            return errinput!("max in-flight appends must be positive");
+        }
+        if self.max_inflight_bytes == Some(0) {
+            return errinput!("max in-flight bytes must be positive");
+        }
+        if self.max_uncommitted_entries == Some(0) {
+            return errinput!("max uncommitted entries must be positive");
+        }
+        if self.snapshot_interval == Some(0) {
+            return errinput!("snapshot interval must be positive");
+        }
+        if let Some(lease_duration) = self.lease_duration {
+            if lease_duration == 0 {
+                return errinput!("lease duration must be positive");
+            }
+            if lease_duration >= self.election_timeout_range.start {
+                return errinput!(
+                    "lease duration {lease_duration} must be below the election timeout {}",
+                    self.election_timeout_range.start
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
 /// A Raft node with a dynamic role. This implements the Raft distributed
 /// consensus protocol, see the `raft` module documentation for more info.
 ///
@@ -112,9 +251,9 @@ impl Node {
         opts: Options,
     ) -> Result<Self> {
         let node = RawNode::new(id, peers, log, state, tx, opts)?;
-        // If this is a single-node cluster, become leader immediately.
-        if node.cluster_size() == 1 {
-            return Ok(node.into_candidate()?.into_leader()?.into());
+        // If we're the only voter, become leader immediately.
+        if node.cluster_size() == 1 && !node.is_learner(node.id) {
+            return Ok(node.into_candidate(false)?.into_leader()?.into());
         }
         Ok(node.into())
     }
@@ -131,10 +270,11 @@ impl Node {
 
     /// Processes an inbound message.
     pub fn step(self, msg: Envelope) -> Result<Self> {
-        with_rawnode!(self, |n| {
+        with_rawnode!(self, |mut n| {
             assert_eq!(msg.to, n.id, "message to other node: {msg:?}");
             assert!(n.peers.contains(&msg.from) || msg.from == n.id, "unknown sender: {msg:?}");
             debug!("Stepping {msg:?}");
+            n.counters.get_mut().received(&msg.message);
             n.step(msg)
         })
     }
@@ -143,6 +283,44 @@ impl Node {
     pub fn tick(self) -> Result<Self> {
         with_rawnode!(self, |n| n.tick())
     }
+
+    /// Syncs appended log entries to durable storage according to the log's
+    /// durability policy. With group commit, this must be called after stepping
+    /// a batch of messages and before routing any outbound messages, see
+    /// Log::enable_group_commit().
+    pub fn sync(&mut self) -> Result<()> {
+        with_rawnode!(ref mut self, |n| n.log.sync())
+    }
+
+    /// Flushes the Raft log and state machine to durable storage, e.g. before
+    /// shutting down.
+    pub fn flush(&mut self) -> Result<()> {
+        with_rawnode!(ref mut self, |n| {
+            n.log.flush()?;
+            n.state.flush()
+        })
+    }
+
+    /// Executes a client request directly in a single-node cluster, bypassing
+    /// the message loop. A single node is always the leader and doesn't need
+    /// consensus, so this avoids the overhead of stepping messages and routing
+    /// responses. The Raft log is the same as with regular requests.
+    ///
+    /// Node errors are returned in the outer result, and should be handled
+    /// like step() errors. The inner result is the client response.
+    pub fn execute(&mut self, request: Request) -> Result<Result<Response>> {
+        match self {
+            Node::Leader(node) if node.peers.is_empty() => node.execute(request),
+            _ => errinput!("direct execution requires a single-node cluster"),
+        }
+    }
+
+    /// Dismantles the node into its log and state machine, e.g. to simulate a
+    /// crash and later restart the node with the same storage.
+    #[cfg(any(test, feature = "sim"))]
+    pub(crate) fn dismantle(self) -> (Log, Box<dyn State>) {
+        with_rawnode!(self, |n| (n.log, n.state))
+    }
 }
 
 impl From<RawNode<Candidate>> for Node {
@@ -185,6 +363,11 @@ pub struct RawNode<R: Role> {
     tx: Sender<Envelope>,
     /// Node options.
     opts: Options,
+    /// Recent node events, for debugging.
+    trace: Trace,
+    /// Cumulative node counters, for metrics. Uses a RefCell since messages
+    /// are counted when sent, which only borrows the node immutably.
+    counters: RefCell<Counters>,
     /// Role-specific state.
     role: R,
 }
@@ -199,18 +382,37 @@ impl<R: Role> RawNode<R> {
             state: self.state,
             tx: self.tx,
             opts: self.opts,
+            trace: self.trace,
+            counters: self.counters,
             role,
         }
     }
 
+    /// Records an event in the node's event trace.
+    fn trace(&mut self, event: Event) {
+        let term = self.term();
+        self.trace.record(term, event);
+    }
+
     /// Returns the node's current term. Convenience wrapper for Log.get_term().
     fn term(&self) -> Term {
         self.log.get_term().0
     }
 
-    /// Returns the cluster size as number of nodes.
+    /// Returns the cluster size as number of voting nodes. Learners don't
+    /// count towards quorums.
     fn cluster_size(&self) -> usize {
-        self.peers.len() + 1
+        self.peers.len() + 1 - self.opts.learners.len()
+    }
+
+    /// Returns true if the given node is a learner.
+    fn is_learner(&self, id: NodeID) -> bool {
+        self.opts.learners.contains(&id)
+    }
+
+    /// Returns the IDs of the voting peers, in increasing order.
+    fn voting_peers(&self) -> Vec<NodeID> {
+        self.peers.iter().copied().filter(|id| !self.is_learner(*id)).sorted().collect()
     }
 
     /// Returns the cluster quorum size (strict majority).
@@ -232,15 +434,42 @@ impl<R: Role> RawNode<R> {
 
     /// Sends a message to the given recipient.
     fn send(&self, to: NodeID, message: Message) -> Result<()> {
-        Self::send_with(&self.tx, Envelope { from: self.id, to, term: self.term(), message })
+        let msg = Envelope { from: self.id, to, term: self.term(), message };
+        Self::send_with(&self.tx, &self.counters, msg)
     }
 
     /// Sends a message without borrowing self, to allow partial borrows.
-    fn send_with(tx: &Sender<Envelope>, msg: Envelope) -> Result<()> {
+    fn send_with(tx: &Sender<Envelope>, counters: &RefCell<Counters>, msg: Envelope) -> Result<()> {
         debug!("Sending {msg:?}");
+        counters.borrow_mut().sent(&msg.message);
         Ok(tx.send(msg)?)
     }
 
+    /// Responds to a pre-vote from a candidate, for its prospective term. The
+    /// vote is granted if we don't have a leader, we're not a learner, and the
+    /// candidate's log is at least as up-to-date as ours. Our term and vote are
+    /// unaffected.
+    fn pre_vote(&self, msg: &Envelope, has_leader: bool) -> Result<()> {
+        let Message::PreVote { last_index, last_term } = msg.message else {
+            panic!("unexpected message {msg:?}")
+        };
+        let (log_index, log_term) = self.log.get_last_index();
+        let vote = !has_leader
+            && !self.is_learner(self.id)
+            && msg.term > self.term()
+            && (last_term > log_term || last_term == log_term && last_index >= log_index);
+        // Grants are sent in the candidate's prospective term, which it will
+        // recognize. Rejections are sent in our term, such that the candidate
+        // steps down if we're in a later term.
+        let term = if vote { msg.term } else { self.term() };
+        let message = Message::PreVoteResponse { vote };
+        Self::send_with(
+            &self.tx,
+            &self.counters,
+            Envelope { from: self.id, to: msg.from, term, message },
+        )
+    }
+
     /// Broadcasts a message to all peers.
     fn broadcast(&self, message: Message) -> Result<()> {
         // Send in increasing ID order for test determinism.
@@ -249,6 +478,92 @@ impl<R: Role> RawNode<R> {
         }
         Ok(())
     }
+
+    /// Returns the local node's status, given its role and leader.
+    fn node_status(&mut self, role: &str, leader: Option<NodeID>) -> Result<NodeStatus> {
+        Ok(NodeStatus {
+            role: role.to_string(),
+            term: self.term(),
+            leader,
+            last_index: self.log.get_last_index().0,
+            commit_index: self.log.get_commit_index().0,
+            applied_index: self.state.get_applied_index(),
+            storage: self.log.status()?,
+            version: env!("CARGO_PKG_VERSION").to_string(),
+        })
+    }
+
+    /// Returns the local node's metrics, given its role, leader, and peer
+    /// replication progress (if leader).
+    fn metrics(
+        &self,
+        role: &str,
+        leader: Option<NodeID>,
+        progress: BTreeMap<NodeID, PeerProgress>,
+    ) -> Metrics {
+        Metrics {
+            id: self.id,
+            role: role.to_string(),
+            term: self.term(),
+            leader,
+            last_index: self.log.get_last_index().0,
+            commit_index: self.log.get_commit_index().0,
+            applied_index: self.state.get_applied_index(),
+            progress,
+            counters: self.counters.borrow().clone(),
+            queues: BTreeMap::new(),
+        }
+    }
+
+    /// Checks the local node's health, given its role and leader. Flushes the
+    /// log storage to verify that it's writable, and returns the node status.
+    fn health(&mut self, role: &str, leader: Option<NodeID>) -> Result<NodeStatus> {
+        self.log.flush()?;
+        self.node_status(role, leader)
+    }
+
+    /// Compacts the log and state machine storage if their garbage exceeds the
+    /// given thresholds, returning which were compacted.
+    fn compact(&mut self, garbage_min_fraction: f64, garbage_min_bytes: u64) -> Result<Response> {
+        let log = self.log.maybe_compact(garbage_min_fraction, garbage_min_bytes)?;
+        let state = self.state.maybe_compact(garbage_min_fraction, garbage_min_bytes)?;
+        Ok(Response::Compact { log, state })
+    }
+
+    /// Truncates the log up to the applied index once it has advanced
+    /// snapshot_interval entries beyond the last truncation. The state machine
+    /// is flushed first, since the truncated entries can't be reapplied after
+    /// a crash -- it then serves as the snapshot of the truncated entries.
+    fn maybe_truncate_log(&mut self) -> Result<()> {
+        let Some(interval) = self.opts.snapshot_interval else {
+            return Ok(());
+        };
+        // The applied index may be beyond the commit index following a
+        // restart, since the commit index isn't flushed.
+        let (commit_index, _) = self.log.get_commit_index();
+        let index = std::cmp::min(self.state.get_applied_index(), commit_index);
+        let (snapshot_index, _) = self.log.get_snapshot_index();
+        if index < snapshot_index + interval {
+            return Ok(());
+        }
+        debug!("Truncating log to {index}");
+        self.state.flush()?;
+        self.log.truncate(index)?;
+        self.trace(Event::Truncate { index });
+        Ok(())
+    }
+
+    /// Backs up the log and state machine storage to the files "raft" and
+    /// "state" in the given directory, creating it if necessary. The node
+    /// doesn't process any messages while the backup is taken, so the state
+    /// machine is consistent with the log's applied entries.
+    fn backup(&mut self, path: &str) -> Result<Response> {
+        let path = std::path::Path::new(path);
+        std::fs::create_dir_all(path)?;
+        self.log.backup(&path.join("raft"))?;
+        self.state.backup(&path.join("state"))?;
+        Ok(Response::Backup { applied_index: self.state.get_applied_index() })
+    }
 }
 
 // A follower replicates log entries from a leader and forwards client requests.
@@ -261,15 +576,37 @@ pub struct Follower {
     leader_seen: Ticks,
     /// The leader_seen timeout before triggering an election.
     election_timeout: Ticks,
+    /// The leader's commit index as of its last heartbeat, and the number of
+    /// ticks since. Used to bound the staleness of follower reads.
+    heartbeat: Option<(Index, Ticks)>,
     // Local client requests that have been forwarded to the leader. These are
     // aborted on leader/term changes.
     forwarded: HashSet<RequestID>,
+    /// Pending state verification requests from the leader, as request IDs
+    /// and applied indexes. Responded to once the index is applied, and
+    /// dropped on leader/term changes.
+    state_verifications: Vec<(RequestID, Index)>,
+    /// Pre-votes received for the next term (including our own), if we're
+    /// holding a pre-vote. Empty otherwise.
+    pre_votes: HashSet<NodeID>,
+    /// The number of ticks remaining before we grant votes after startup, with
+    /// leader leases. See RawNode<Follower>::new().
+    vote_delay: Ticks,
 }
 
 impl Follower {
     /// Creates a new follower role.
     fn new(leader: Option<NodeID>, election_timeout: Ticks) -> Self {
-        Self { leader, leader_seen: 0, election_timeout, forwarded: HashSet::new() }
+        Self {
+            leader,
+            leader_seen: 0,
+            election_timeout,
+            heartbeat: None,
+            forwarded: HashSet::new(),
+            state_verifications: Vec::new(),
+            pre_votes: HashSet::new(),
+            vote_delay: 0,
+        }
     }
 }
 
@@ -288,10 +625,33 @@ impl RawNode<Follower> {
         if peers.contains(&id) {
             return errinput!("node ID {id} can't be in peers");
         }
+        if let Some(learner) = opts.learners.iter().find(|l| **l != id && !peers.contains(l)) {
+            return errinput!("learner {learner} is not a cluster node");
+        }
+        if opts.learners.len() > peers.len() {
+            return errinput!("cluster must have at least one voter");
+        }
+        let (snapshot_index, _) = log.get_snapshot_index();
+        if state.get_applied_index() < snapshot_index {
+            return errdata!(
+                "state machine applied index {} is below the log snapshot index {snapshot_index}",
+                state.get_applied_index()
+            );
+        }
         let role = Follower::new(None, 0);
-        let mut node = Self { id, peers, log, state, tx, opts, role };
+        let trace = Trace::new(opts.trace_capacity);
+        let counters = RefCell::default();
+        let mut node = Self { id, peers, log, state, tx, opts, trace, counters, role };
         node.role.election_timeout = node.random_election_timeout();
 
+        // With leader leases, we may have acknowledged a leader's heartbeat
+        // before restarting, and it may still be serving reads under its
+        // lease. We've forgotten about it, so refuse votes for an election
+        // timeout, by which time any such lease has expired.
+        if node.opts.lease_duration.is_some() {
+            node.role.vote_delay = node.opts.election_timeout_range.start;
+        }
+
         // Apply any pending entries following restart. Unlike the Raft log,
         // state machine writes are not flushed to durable storage, so a tail of
         // writes may be lost if the OS crashes or restarts.
@@ -300,8 +660,9 @@ impl RawNode<Follower> {
     }
 
     /// Transitions the follower into a candidate, by campaigning for
-    /// leadership in a new term.
-    fn into_candidate(mut self) -> Result<RawNode<Candidate>> {
+    /// leadership in a new term. If transfer is true, the leader asked us to
+    /// campaign via TimeoutNow.
+    fn into_candidate(mut self, transfer: bool) -> Result<RawNode<Candidate>> {
         // Abort any forwarded requests. These must be retried with new leader.
         self.abort_forwarded()?;
 
@@ -311,7 +672,7 @@ impl RawNode<Follower> {
         // Become candidate and campaign.
         let election_timeout = self.random_election_timeout();
         let mut node = self.into_role(Candidate::new(election_timeout));
-        node.campaign()?;
+        node.campaign(transfer)?;
 
         let (term, vote) = node.log.get_term();
         assert!(node.role.votes.contains(&node.id), "candidate did not vote for self");
@@ -335,14 +696,21 @@ impl RawNode<Follower> {
             assert_eq!(self.role.leader, None, "already have leader in term");
             assert_eq!(term, self.term(), "can't follow leader in different term");
             info!("Following leader {leader} in term {term}");
+            let vote_delay = self.role.vote_delay;
             self.role = Follower::new(Some(leader), self.role.election_timeout);
+            self.role.vote_delay = vote_delay;
+            self.trace(Event::Follower { leader: Some(leader) });
+            self.counters.get_mut().leader_changes += 1;
         } else {
             // We found a new term, but we don't know who the leader is yet.
             // We'll find out if we step a message from it.
             assert_ne!(term, self.term(), "can't become leaderless follower in current term");
             info!("Discovered new term {term}");
             self.log.set_term(term, None)?;
+            let vote_delay = self.role.vote_delay;
             self.role = Follower::new(None, self.random_election_timeout());
+            self.role.vote_delay = vote_delay;
+            self.trace(Event::Follower { leader: None });
         }
         Ok(self)
     }
@@ -354,8 +722,20 @@ impl RawNode<Follower> {
             debug!("Dropping message from past term: {msg:?}");
             return Ok(self.into());
         }
+        // With leader leases, ignore campaigns while we have a leader, since it
+        // may be serving reads under its lease, or shortly after startup, since
+        // we may have had one. Leadership transfers are exempt, since the
+        // leader gives up its lease.
+        if self.opts.lease_duration.is_some()
+            && (self.role.leader.is_some() || self.role.vote_delay > 0)
+            && matches!(msg.message, Message::Campaign { transfer: false, .. })
+        {
+            debug!("Ignoring campaign while we have or may have a leader: {msg:?}");
+            return Ok(self.into());
+        }
         // Future term: become leaderless follower and step the message.
-        if msg.term > self.term() {
+        // Pre-votes use the candidate's prospective term, which we don't adopt.
+        if msg.term > self.term() && !msg.message.is_pre_vote() {
             return self.into_follower(msg.term, None)?.step(msg);
         }
 
@@ -380,6 +760,7 @@ impl RawNode<Follower> {
                 // heartbeat. last_index always has the leader's term.
                 let match_index = if self.log.has(last_index, msg.term)? { last_index } else { 0 };
                 self.send(msg.from, Message::HeartbeatResponse { match_index, read_seq })?;
+                self.role.heartbeat = Some((commit_index, 0));
 
                 // Advance the commit index and apply entries. We can only do
                 // this if we matched the leader's last_index, which implies
@@ -387,6 +768,7 @@ impl RawNode<Follower> {
                 // implies that the commit_index is present in our log.
                 if match_index != 0 && commit_index > self.log.get_commit_index().0 {
                     self.log.commit(commit_index)?;
+                    self.trace(Event::Commit { index: commit_index });
                     self.maybe_apply()?;
                 }
             }
@@ -407,6 +789,9 @@ impl RawNode<Follower> {
                 let (mut reject_index, mut match_index) = (0, 0);
                 if base_index == 0 || self.log.has(base_index, base_term)? {
                     match_index = entries.last().map(|e| e.index).unwrap_or(base_index);
+                    if let Some(first) = entries.first().map(|e| e.index) {
+                        self.trace(Event::Append { first, last: match_index });
+                    }
                     self.log.splice(entries)?;
                 } else {
                     // Otherwise, reject the base index. If the local log is
@@ -417,6 +802,31 @@ impl RawNode<Follower> {
                 self.send(msg.from, Message::AppendResponse { reject_index, match_index })?;
             }
 
+            // Install a state machine snapshot from the leader, unless we've
+            // already committed its index, and confirm that the log matches
+            // the leader's up to the snapshot index.
+            Message::InstallSnapshot { index, term, data } => {
+                // Make sure the snapshot is from our leader, or follow it.
+                match self.role.leader {
+                    Some(leader) => assert_eq!(msg.from, leader, "multiple leaders in term"),
+                    None => self = self.into_follower(msg.term, Some(msg.from))?,
+                }
+
+                if index > self.log.get_commit_index().0 {
+                    info!("Installing snapshot at {index}@{term} from leader {}", msg.from);
+                    self.state.restore(data)?;
+                    let applied_index = self.state.get_applied_index();
+                    assert_eq!(applied_index, index, "snapshot applied index mismatch");
+                    self.log.install_snapshot(index, term)?;
+                    self.trace(Event::Snapshot { index });
+                    // Pending state verifications at or below the snapshot
+                    // index can't be answered, let them time out.
+                    self.role.state_verifications.retain(|(_, i)| *i > index);
+                }
+                let message = Message::AppendResponse { match_index: index, reject_index: 0 };
+                self.send(msg.from, message)?;
+            }
+
             // Confirm the leader's read sequence number.
             Message::Read { seq } => {
                 // Make sure the read is from our leader, or follow it.
@@ -429,8 +839,61 @@ impl RawNode<Follower> {
                 self.send(msg.from, Message::ReadResponse { seq })?;
             }
 
+            // Send a digest of our committed log to the leader for
+            // verification. Uncommitted entries may legitimately differ from
+            // the leader's log, so only digest up to our commit index.
+            Message::Verify { id, commit_index } => {
+                // Make sure the verify is from our leader, or follow it.
+                match self.role.leader {
+                    Some(leader) => assert_eq!(msg.from, leader, "multiple leaders in term"),
+                    None => self = self.into_follower(msg.term, Some(msg.from))?,
+                }
+
+                let index = std::cmp::min(commit_index, self.log.get_commit_index().0);
+                let digest = Digest::new(&mut self.log, index);
+                self.send(msg.from, Message::VerifyResponse { id, digest })?;
+            }
+
+            // Send our state machine checksums at the leader's applied index.
+            // If we haven't applied it yet, respond once we have.
+            Message::VerifyState { id, applied_index } => {
+                // Make sure the verify is from our leader, or follow it.
+                match self.role.leader {
+                    Some(leader) => assert_eq!(msg.from, leader, "multiple leaders in term"),
+                    None => self = self.into_follower(msg.term, Some(msg.from))?,
+                }
+
+                let local_index = self.state.get_applied_index();
+                if local_index < applied_index {
+                    self.role.state_verifications.push((id, applied_index));
+                } else {
+                    let checksums = match local_index == applied_index {
+                        true => self.state.checksums(),
+                        false => errinput!("applied index {local_index} beyond {applied_index}"),
+                    };
+                    self.send(msg.from, Message::VerifyStateResponse { id, checksums })?;
+                }
+            }
+
+            // Send our node status to the leader.
+            Message::NodeStatus { id } => {
+                // Make sure the request is from our leader, or follow it.
+                match self.role.leader {
+                    Some(leader) => assert_eq!(msg.from, leader, "multiple leaders in term"),
+                    None => self = self.into_follower(msg.term, Some(msg.from))?,
+                }
+                let status = self.node_status(self.role_name(), self.role.leader)?;
+                self.send(msg.from, Message::NodeStatusResponse { id, status })?;
+            }
+
             // A candidate is requesting our vote. We'll only grant one.
-            Message::Campaign { last_index, last_term } => {
+            Message::Campaign { last_index, last_term, .. } => {
+                // Learners don't vote.
+                if self.is_learner(self.id) {
+                    self.send(msg.from, Message::CampaignResponse { vote: false })?;
+                    return Ok(self.into());
+                }
+
                 // Don't vote if we already voted for someone else in this term.
                 // We can repeat our vote though.
                 if let (_, Some(vote)) = self.log.get_term() {
@@ -452,15 +915,110 @@ impl RawNode<Follower> {
                 // Grant the vote.
                 info!("Voting for {} in term {} election", msg.from, msg.term);
                 self.log.set_term(msg.term, Some(msg.from))?;
+                self.trace(Event::Vote { candidate: msg.from });
                 self.send(msg.from, Message::CampaignResponse { vote: true })?;
             }
 
+            // A node is holding a pre-vote. We only grant it if we've lost
+            // our leader too.
+            Message::PreVote { .. } => {
+                self.pre_vote(&msg, self.role.leader.is_some() || self.role.vote_delay > 0)?
+            }
+
+            // If we're holding a pre-vote and it's granted by a quorum, hold a
+            // real election.
+            Message::PreVoteResponse { vote: true }
+                if msg.term == self.term() + 1 && !self.role.pre_votes.is_empty() =>
+            {
+                self.role.pre_votes.insert(msg.from);
+                if self.role.pre_votes.len() >= self.quorum_size() {
+                    return Ok(self.into_candidate(false)?.into());
+                }
+            }
+
+            // We may receive a pre-vote after it's no longer needed, or a
+            // rejection. Ignore it.
+            Message::PreVoteResponse { .. } => {}
+
+            // The leader is transferring leadership to us. Campaign now.
+            Message::TimeoutNow => {
+                assert!(!self.is_learner(self.id), "leadership transferred to learner");
+                match self.role.leader {
+                    Some(leader) => assert_eq!(msg.from, leader, "multiple leaders in term"),
+                    None => self = self.into_follower(msg.term, Some(msg.from))?,
+                }
+                info!("Leader {} transferred leadership to us", msg.from);
+                return Ok(self.into_candidate(true)?.into());
+            }
+
+            // Event traces are always node-local.
+            Message::ClientRequest { id, request: Request::Trace } => {
+                assert_eq!(msg.from, self.id, "client request from other node");
+                let response = Ok(Response::Trace(self.trace.events()));
+                self.send(msg.from, Message::ClientResponse { id, response })?;
+            }
+
+            // As are metrics.
+            Message::ClientRequest { id, request: Request::Metrics } => {
+                assert_eq!(msg.from, self.id, "client request from other node");
+                let metrics = self.metrics(self.role_name(), self.role.leader, BTreeMap::new());
+                let response = Ok(Response::Metrics(metrics));
+                self.send(msg.from, Message::ClientResponse { id, response })?;
+            }
+
+            // We're not the leader, so there's no leadership to transfer.
+            Message::ClientRequest { id, request: Request::TransferLeadership(_) } => {
+                assert_eq!(msg.from, self.id, "client request from other node");
+                let response = Ok(Response::TransferLeadership(None));
+                self.send(msg.from, Message::ClientResponse { id, response })?;
+            }
+
+            // Health checks are always node-local.
+            Message::ClientRequest { id, request: Request::Health } => {
+                assert_eq!(msg.from, self.id, "client request from other node");
+                let response =
+                    self.health(self.role_name(), self.role.leader).map(Response::Health);
+                self.send(msg.from, Message::ClientResponse { id, response })?;
+            }
+
+            // Compaction is node-local too.
+            Message::ClientRequest {
+                id,
+                request: Request::Compact { garbage_min_fraction, garbage_min_bytes },
+            } => {
+                assert_eq!(msg.from, self.id, "client request from other node");
+                let response = self.compact(garbage_min_fraction, garbage_min_bytes);
+                self.send(msg.from, Message::ClientResponse { id, response })?;
+            }
+
+            // As are backups.
+            Message::ClientRequest { id, request: Request::Backup { path } } => {
+                assert_eq!(msg.from, self.id, "client request from other node");
+                let response = self.backup(&path);
+                self.send(msg.from, Message::ClientResponse { id, response })?;
+            }
+
+            // Serve stale reads locally if we're sufficiently caught up.
+            Message::ClientRequest {
+                id,
+                request: Request::ReadStale { command, max_staleness },
+            } if self.can_read_stale(max_staleness) => {
+                assert_eq!(msg.from, self.id, "client request from other node");
+                let response = self.state.read(command).map(Response::Read);
+                self.send(msg.from, Message::ClientResponse { id, response })?;
+            }
+
+            // We may have been the leader in this term before restarting, and
+            // peers may still forward client requests to us. Abort them.
+            Message::ClientRequest { id, request: _ } if msg.from != self.id => {
+                let response = Err(Error::Abort);
+                self.send(msg.from, Message::ClientResponse { id, response })?
+            }
+
             // Forward client requests to the leader, or abort them if there is
             // none. These will not be retried, the client should use timeouts.
             // Local client requests use our node ID as the sender.
             Message::ClientRequest { id, request: _ } => {
-                assert_eq!(msg.from, self.id, "client request from other node");
-
                 if let Some(leader) = self.role.leader {
                     debug!("Forwarding request to leader {leader}: {msg:?}");
                     self.role.forwarded.insert(id);
@@ -472,10 +1030,12 @@ impl RawNode<Follower> {
             }
 
             // Client responses from the leader are passed on to the client.
+            // A previous leader may respond in a later term after we've
+            // already aborted the request, e.g. if it stepped down before
+            // receiving it (see Options::check_quorum). Ignore these.
             Message::ClientResponse { id, response } => {
-                assert_eq!(Some(msg.from), self.role.leader, "client response from non-leader");
-
                 if self.role.forwarded.remove(&id) {
+                    assert_eq!(Some(msg.from), self.role.leader, "client response from non-leader");
                     self.send(self.id, Message::ClientResponse { id, response })?;
                 }
             }
@@ -483,10 +1043,24 @@ impl RawNode<Follower> {
             // We may receive a vote after we lost an election, ignore it.
             Message::CampaignResponse { .. } => {}
 
-            // We're not leader this term, so we shouldn't see these.
+            // We may have been the leader in this term before stepping down
+            // (see Options::check_quorum) or restarting, so followers may
+            // still respond to us. Ignore them.
+            Message::HeartbeatResponse { .. }
+            | Message::AppendResponse { .. }
+            | Message::ReadResponse { .. }
+            | Message::VerifyResponse { .. }
+            | Message::VerifyStateResponse { .. }
+            | Message::NodeStatusResponse { .. }
+                if self.log.get_term().1 == Some(self.id) => {}
+
+            // Otherwise, we're not leader this term, so we shouldn't see these.
             Message::HeartbeatResponse { .. }
             | Message::AppendResponse { .. }
-            | Message::ReadResponse { .. } => {
+            | Message::ReadResponse { .. }
+            | Message::VerifyResponse { .. }
+            | Message::VerifyStateResponse { .. }
+            | Message::NodeStatusResponse { .. } => {
                 panic!("unexpected message {msg:?}")
             }
         };
@@ -496,12 +1070,72 @@ impl RawNode<Follower> {
     /// Processes a logical clock tick.
     fn tick(mut self) -> Result<Node> {
         self.role.leader_seen += 1;
-        if self.role.leader_seen >= self.role.election_timeout {
-            return Ok(self.into_candidate()?.into());
+        self.role.vote_delay = self.role.vote_delay.saturating_sub(1);
+        if let Some((_, ticks)) = self.role.heartbeat.as_mut() {
+            *ticks += 1;
+        }
+        // Learners never campaign, they wait for a leader to contact them.
+        if self.role.leader_seen >= self.role.election_timeout && !self.is_learner(self.id) {
+            if !self.opts.pre_vote {
+                return Ok(self.into_candidate(false)?.into());
+            }
+            self.pre_campaign()?;
+            if self.role.pre_votes.len() >= self.quorum_size() {
+                return Ok(self.into_candidate(false)?.into());
+            }
         }
         Ok(self.into())
     }
 
+    /// Returns the follower's role name for status reports.
+    fn role_name(&self) -> &'static str {
+        if self.is_learner(self.id) {
+            "learner"
+        } else {
+            "follower"
+        }
+    }
+
+    /// Holds a pre-vote for the next term, asking peers whether they would
+    /// grant us a vote without changing any terms. We remain a leaderless
+    /// follower until a quorum grants it, and hold a new pre-vote on the next
+    /// election timeout.
+    fn pre_campaign(&mut self) -> Result<()> {
+        // Abort any forwarded requests, since we've lost the leader.
+        self.abort_forwarded()?;
+
+        let term = self.term() + 1;
+        info!("Starting pre-vote for term {term}");
+        self.role = Follower::new(None, self.random_election_timeout());
+        self.role.pre_votes.insert(self.id); // vote for ourself
+        self.trace(Event::PreCampaign);
+        self.counters.get_mut().pre_votes += 1;
+
+        let (last_index, last_term) = self.log.get_last_index();
+        let message = Message::PreVote { last_index, last_term };
+        for to in self.voting_peers() {
+            let msg = Envelope { from: self.id, to, term, message: message.clone() };
+            Self::send_with(&self.tx, &self.counters, msg)?;
+        }
+        Ok(())
+    }
+
+    /// Returns true if the follower can serve stale reads: it must have heard
+    /// a heartbeat from the leader within max_read_staleness ticks (or the
+    /// client's tighter bound, if given), and must have applied all entries
+    /// that the leader had committed at the time.
+    fn can_read_stale(&self, max_staleness: Option<Ticks>) -> bool {
+        let max_staleness = max_staleness.map_or(self.opts.max_read_staleness, |max| {
+            std::cmp::min(max, self.opts.max_read_staleness)
+        });
+        match self.role.heartbeat {
+            Some((commit_index, ticks)) => {
+                ticks <= max_staleness && self.state.get_applied_index() >= commit_index
+            }
+            None => false,
+        }
+    }
+
     /// Aborts all forwarded requests (e.g. on term/leader changes).
     fn abort_forwarded(&mut self) -> Result<()> {
         // Sort by ID for test determinism.
@@ -512,17 +1146,37 @@ impl RawNode<Follower> {
         Ok(())
     }
 
-    /// Applies any pending log entries.
+    /// Applies any pending log entries. Computes state machine checksums for
+    /// pending state verification requests as their applied index is reached,
+    /// and sends them to the leader.
     fn maybe_apply(&mut self) -> Result<()> {
+        let mut responses = Vec::new();
         let mut iter = self.log.scan_apply(self.state.get_applied_index());
         while let Some(entry) = iter.next().transpose()? {
             debug!("Applying {entry:?}");
+            let index = entry.index;
             // Throw away the result, since only the leader responds to clients.
             // This includes errors -- any non-deterministic errors (e.g. IO
             // errors) must panic instead to avoid replica divergence.
             _ = self.state.apply(entry);
+
+            if self.role.state_verifications.iter().any(|(_, i)| *i == index) {
+                let checksums = self.state.checksums();
+                let (done, pending): (Vec<_>, Vec<_>) =
+                    std::mem::take(&mut self.role.state_verifications)
+                        .into_iter()
+                        .partition(|(_, i)| *i == index);
+                self.role.state_verifications = pending;
+                responses.extend(done.into_iter().map(|(id, _)| (id, checksums.clone())));
+            }
         }
-        Ok(())
+        drop(iter);
+        if let Some(leader) = self.role.leader {
+            for (id, checksums) in responses {
+                self.send(leader, Message::VerifyStateResponse { id, checksums })?;
+            }
+        }
+        self.maybe_truncate_log()
     }
 }
 
@@ -555,6 +1209,8 @@ impl RawNode<Candidate> {
             // We lost the election, follow the winner.
             assert_eq!(term, self.term(), "can't follow leader in different term");
             info!("Lost election, following leader {leader} in term {term}");
+            self.trace(Event::Follower { leader: Some(leader) });
+            self.counters.get_mut().leader_changes += 1;
             Ok(self.into_role(Follower::new(Some(leader), election_timeout)))
         } else {
             // We found a new term, but we don't necessarily know who the leader
@@ -562,6 +1218,7 @@ impl RawNode<Candidate> {
             assert_ne!(term, self.term(), "can't become leaderless follower in current term");
             info!("Discovered new term {term}");
             self.log.set_term(term, None)?;
+            self.trace(Event::Follower { leader: None });
             Ok(self.into_role(Follower::new(None, election_timeout)))
         }
     }
@@ -575,7 +1232,12 @@ impl RawNode<Candidate> {
         info!("Won election for term {term}, becoming leader");
         let peers = self.peers.clone();
         let (last_index, _) = self.log.get_last_index();
-        let mut node = self.into_role(Leader::new(peers, last_index));
+        let role = Leader::new(peers, last_index, &self.opts);
+        let mut node = self.into_role(role);
+        node.trace(Event::Leader);
+        let counters = node.counters.get_mut();
+        counters.elections_won += 1;
+        counters.leader_changes += 1;
 
         // Propose an empty command when assuming leadership, to disambiguate
         // previous entries in the log. See section 5.4.2 in the Raft paper.
@@ -596,7 +1258,8 @@ impl RawNode<Candidate> {
             return Ok(self.into());
         }
         // Future term: become leaderless follower and step the message.
-        if msg.term > self.term() {
+        // Pre-votes use the candidate's prospective term, which we don't adopt.
+        if msg.term > self.term() && !msg.message.is_pre_vote() {
             return self.into_follower(msg.term, None)?.step(msg);
         }
 
@@ -618,12 +1281,65 @@ impl RawNode<Candidate> {
                 self.send(msg.from, Message::CampaignResponse { vote: false })?
             }
 
+            // We don't have a leader either, so grant pre-votes for later
+            // terms if the candidate's log is up-to-date.
+            Message::PreVote { .. } => self.pre_vote(&msg, false)?,
+
+            // We've already passed the pre-vote, ignore late responses.
+            Message::PreVoteResponse { .. } => {}
+
             // If we hear from a leader in this term, we lost the election.
             // Follow it and step the message.
-            Message::Heartbeat { .. } | Message::Append { .. } | Message::Read { .. } => {
+            Message::Heartbeat { .. }
+            | Message::Append { .. }
+            | Message::InstallSnapshot { .. }
+            | Message::Read { .. }
+            | Message::Verify { .. }
+            | Message::VerifyState { .. }
+            | Message::NodeStatus { .. }
+            | Message::TimeoutNow => {
                 return self.into_follower(msg.term, Some(msg.from))?.step(msg);
             }
 
+            // Event traces are node-local, so we can serve them.
+            Message::ClientRequest { id, request: Request::Trace } => {
+                let response = Ok(Response::Trace(self.trace.events()));
+                self.send(msg.from, Message::ClientResponse { id, response })?;
+            }
+
+            // As are metrics.
+            Message::ClientRequest { id, request: Request::Metrics } => {
+                let metrics = self.metrics("candidate", None, BTreeMap::new());
+                let response = Ok(Response::Metrics(metrics));
+                self.send(msg.from, Message::ClientResponse { id, response })?;
+            }
+
+            // We're not the leader, so there's no leadership to transfer.
+            Message::ClientRequest { id, request: Request::TransferLeadership(_) } => {
+                let response = Ok(Response::TransferLeadership(None));
+                self.send(msg.from, Message::ClientResponse { id, response })?;
+            }
+
+            // Health checks are node-local, so we can serve them.
+            Message::ClientRequest { id, request: Request::Health } => {
+                let response = self.health("candidate", None).map(Response::Health);
+                self.send(msg.from, Message::ClientResponse { id, response })?;
+            }
+
+            // As are compaction and backups.
+            Message::ClientRequest {
+                id,
+                request: Request::Compact { garbage_min_fraction, garbage_min_bytes },
+            } => {
+                let response = self.compact(garbage_min_fraction, garbage_min_bytes);
+                self.send(msg.from, Message::ClientResponse { id, response })?;
+            }
+
+            Message::ClientRequest { id, request: Request::Backup { path } } => {
+                let response = self.backup(&path);
+                self.send(msg.from, Message::ClientResponse { id, response })?;
+            }
+
             // Abort client requests while campaigning. The client must retry.
             Message::ClientRequest { id, request: _ } => {
                 self.send(msg.from, Message::ClientResponse { id, response: Err(Error::Abort) })?;
@@ -634,6 +1350,9 @@ impl RawNode<Candidate> {
             Message::HeartbeatResponse { .. }
             | Message::AppendResponse { .. }
             | Message::ReadResponse { .. }
+            | Message::VerifyResponse { .. }
+            | Message::VerifyStateResponse { .. }
+            | Message::NodeStatusResponse { .. }
             | Message::ClientResponse { .. } => panic!("unexpected message {msg:?}"),
         }
         Ok(self.into())
@@ -643,22 +1362,41 @@ impl RawNode<Candidate> {
     fn tick(mut self) -> Result<Node> {
         self.role.election_duration += 1;
         if self.role.election_duration >= self.role.election_timeout {
-            self.campaign()?;
+            // With pre-votes, fall back to a pre-vote in our current term
+            // rather than bumping the term again. Otherwise, a candidate that
+            // can't reach a quorum would keep increasing its term.
+            if self.opts.pre_vote {
+                info!("Election timed out, holding pre-vote");
+                let election_timeout = self.random_election_timeout();
+                let mut node = self.into_role(Follower::new(None, election_timeout));
+                node.trace(Event::Follower { leader: None });
+                node.pre_campaign()?;
+                return Ok(node.into());
+            }
+            self.campaign(false)?;
         }
         Ok(self.into())
     }
 
     /// Hold a new election by increasing the term, voting for ourself, and
-    /// soliciting votes from all peers.
-    fn campaign(&mut self) -> Result<()> {
+    /// soliciting votes from all voting peers. If transfer is true, the leader
+    /// asked us to campaign, and peers should vote for us even if they have a
+    /// leader.
+    fn campaign(&mut self, transfer: bool) -> Result<()> {
         let term = self.term() + 1;
         info!("Starting new election for term {term}");
         self.role = Candidate::new(self.random_election_timeout());
         self.role.votes.insert(self.id); // vote for ourself
         self.log.set_term(term, Some(self.id))?;
+        self.trace(Event::Campaign);
+        self.counters.get_mut().elections += 1;
 
         let (last_index, last_term) = self.log.get_last_index();
-        self.broadcast(Message::Campaign { last_index, last_term })
+        let message = Message::Campaign { last_index, last_term, transfer };
+        for to in self.voting_peers() {
+            self.send(to, message.clone())?;
+        }
+        Ok(())
     }
 }
 
@@ -679,8 +1417,31 @@ pub struct Leader {
     /// The read sequence number used for the last read. Initialized to 0 in
     /// this term, and incremented for every read command.
     read_seq: ReadSequence,
+    /// Tracks pending verify requests, until all peers have responded with
+    /// log digests or the request times out.
+    verifications: HashMap<RequestID, Verification>,
+    /// Tracks pending state verify requests, until all peers have responded
+    /// with state machine checksums or the request times out.
+    state_verifications: HashMap<RequestID, StateVerification>,
+    /// Tracks pending node status requests, until all peers have responded
+    /// or the request times out.
+    node_statuses: HashMap<RequestID, NodeStatuses>,
     /// Number of ticks since last heartbeat.
     since_heartbeat: Ticks,
+    /// A pending leadership transfer, if any.
+    transfer: Option<Transfer>,
+    /// The number of ticks since we became leader, used as the lease clock.
+    ticks: u64,
+    /// The tick at which each read sequence number was sent to followers, if
+    /// leases are enabled. Removed once confirmed by a quorum.
+    read_seq_sent: VecDeque<(ReadSequence, u64)>,
+    /// The tick at which our lease expires. We hold a lease while ticks is
+    /// below it, see Options::lease_duration.
+    lease_expiry: u64,
+    /// If true, we've transferred leadership and given up our lease.
+    lease_revoked: bool,
+    /// Number of ticks since the last quorum check, see Options::check_quorum.
+    since_quorum_check: Ticks,
 }
 
 /// Follower replication progress (in this term).
@@ -699,17 +1460,55 @@ struct Progress {
     /// reads on leader changes, a read is only served once its sequence number
     /// is confirmed by a quorum.
     read_seq: ReadSequence,
+    /// The remaining catch-up Append messages that can be sent in this tick.
+    catchup_messages: usize,
+    /// The remaining catch-up command bytes that can be sent in this tick.
+    catchup_bytes: usize,
+    /// If true, catch-up replication exhausted the budget for this tick, and
+    /// should resume on the next tick.
+    throttled: bool,
+    /// The last index and command bytes of each unacknowledged Append message
+    /// sent to the follower, oldest first. Limited by max_inflight_appends and
+    /// max_inflight_bytes.
+    inflight: VecDeque<(Index, usize)>,
+    /// The total command bytes of inflight appends.
+    inflight_bytes: usize,
+    /// If true, we've heard from the follower since the last quorum check,
+    /// see Options::check_quorum.
+    recent_active: bool,
 }
 
 impl Progress {
+    /// Creates a new progress tracker with the given next_index.
+    fn new(next_index: Index, opts: &Options) -> Self {
+        let mut progress = Self {
+            next_index,
+            match_index: 0,
+            read_seq: 0,
+            catchup_messages: 0,
+            catchup_bytes: 0,
+            throttled: false,
+            inflight: VecDeque::new(),
+            inflight_bytes: 0,
+            recent_active: false,
+        };
+        progress.refill(opts);
+        progress
+    }
+
     /// Attempts to advance a follower's match index, returning true if it did.
     /// If next_index is below it, it is advanced to the following index.
+    /// In-flight appends up to the match index are acknowledged.
     fn advance(&mut self, match_index: Index) -> bool {
         if match_index <= self.match_index {
             return false;
         }
         self.match_index = match_index;
         self.next_index = std::cmp::max(self.next_index, match_index + 1);
+        while let Some((_, size)) = self.inflight.front().filter(|(last, _)| *last <= match_index) {
+            self.inflight_bytes -= size;
+            self.inflight.pop_front();
+        }
         true
     }
 
@@ -722,15 +1521,39 @@ impl Progress {
         true
     }
 
+    /// Refills the catch-up replication budget, on every tick.
+    fn refill(&mut self, opts: &Options) {
+        self.catchup_messages = opts.max_catchup_messages.unwrap_or(usize::MAX);
+        self.catchup_bytes = opts.max_catchup_bytes.unwrap_or(usize::MAX);
+    }
+
     /// Attempts to regress a follower's next index to the given index, returning
-    /// true if it did. Won't regress below match_index + 1.
+    /// true if it did. Won't regress below match_index + 1. The in-flight
+    /// appends are discarded, since they'll be rejected or resent.
     fn regress_next(&mut self, next_index: Index) -> bool {
         if next_index >= self.next_index || self.next_index <= self.match_index + 1 {
             return false;
         }
         self.next_index = std::cmp::max(next_index, self.match_index + 1);
+        self.clear_inflight();
         true
     }
+
+    /// Discards all in-flight appends, e.g. when they were lost.
+    fn clear_inflight(&mut self) {
+        self.inflight.clear();
+        self.inflight_bytes = 0;
+    }
+
+    /// Returns the number of command bytes that can be sent in the next
+    /// append, or None if the in-flight window is full.
+    fn inflight_room(&self, opts: &Options) -> Option<usize> {
+        if self.inflight.len() >= opts.max_inflight_appends.unwrap_or(usize::MAX) {
+            return None;
+        }
+        let max_bytes = opts.max_inflight_bytes.unwrap_or(usize::MAX);
+        max_bytes.checked_sub(self.inflight_bytes).filter(|room| *room > 0)
+    }
 }
 
 /// A pending client write request.
@@ -739,6 +1562,9 @@ struct Write {
     from: NodeID,
     /// The write request ID.
     id: RequestID,
+    /// The tracing span that proposed the write, used as the parent of its
+    /// apply span. Only set for requests submitted by the local node.
+    span: tracing::Span,
 }
 
 /// A pending client read request.
@@ -751,22 +1577,76 @@ struct Read {
     id: RequestID,
     /// The read command.
     command: Vec<u8>,
+    /// The tracing span that submitted the read, see Write::span.
+    span: tracing::Span,
+}
+
+/// A pending client verify request.
+struct Verification {
+    /// The node which submitted the request.
+    from: NodeID,
+    /// The leader's log digest at the commit index, to compare peers against.
+    digest: Digest,
+    /// The verification report, populated as peers respond.
+    report: Report,
+    /// The number of ticks since the request was submitted.
+    ticks: Ticks,
+}
+
+/// A pending client state verify request.
+struct StateVerification {
+    /// The node which submitted the request.
+    from: NodeID,
+    /// The leader's state machine checksums, to compare peers against.
+    checksums: BTreeMap<String, u64>,
+    /// The verification report, populated as peers respond.
+    report: StateReport,
+    /// The number of ticks since the request was submitted.
+    ticks: Ticks,
+}
+
+/// A pending client node status request.
+struct NodeStatuses {
+    /// The node which submitted the request.
+    from: NodeID,
+    /// The node statuses, populated as peers respond.
+    nodes: BTreeMap<NodeID, NodeStatus>,
+    /// The number of ticks since the request was submitted.
+    ticks: Ticks,
+}
+
+/// A pending leadership transfer, waiting for the target to catch up.
+struct Transfer {
+    /// The peer to transfer leadership to.
+    to: NodeID,
+    /// The node which submitted the transfer request.
+    from: NodeID,
+    /// The transfer request ID.
+    id: RequestID,
+    /// The number of ticks since the request was submitted.
+    ticks: Ticks,
 }
 
 impl Leader {
     /// Creates a new leader role.
-    fn new(peers: HashSet<NodeID>, last_index: Index) -> Self {
+    fn new(peers: HashSet<NodeID>, last_index: Index, opts: &Options) -> Self {
         let next_index = last_index + 1;
-        let progress = peers
-            .into_iter()
-            .map(|p| (p, Progress { next_index, match_index: 0, read_seq: 0 }))
-            .collect();
+        let progress = peers.into_iter().map(|p| (p, Progress::new(next_index, opts))).collect();
         Self {
             progress,
             writes: HashMap::new(),
             reads: VecDeque::new(),
             read_seq: 0,
+            verifications: HashMap::new(),
+            state_verifications: HashMap::new(),
+            node_statuses: HashMap::new(),
             since_heartbeat: 0,
+            transfer: None,
+            ticks: 0,
+            read_seq_sent: VecDeque::new(),
+            lease_expiry: 0,
+            lease_revoked: false,
+            since_quorum_check: 0,
         }
     }
 }
@@ -780,9 +1660,29 @@ impl RawNode<Leader> {
     fn into_follower(mut self, term: Term) -> Result<RawNode<Follower>> {
         assert!(term > self.term(), "leader can only become follower in later term");
         info!("Discovered new term {term}");
+        self.abort_requests()?;
+        self.log.set_term(term, None)?;
+        self.trace(Event::Follower { leader: None });
+        let election_timeout = self.random_election_timeout();
+        Ok(self.into_role(Follower::new(None, election_timeout)))
+    }
+
+    /// Steps down to a leaderless follower in the current term, because we
+    /// haven't heard from a quorum, see Options::check_quorum. We've already
+    /// voted for ourself in this term, and will campaign in the next term if
+    /// we don't hear from a new leader.
+    fn step_down(mut self) -> Result<RawNode<Follower>> {
+        info!("Lost contact with quorum, stepping down in term {}", self.term());
+        self.abort_requests()?;
+        self.trace(Event::Follower { leader: None });
+        let election_timeout = self.random_election_timeout();
+        Ok(self.into_role(Follower::new(None, election_timeout)))
+    }
 
-        // Abort in-flight requests. The client must retry. Sort the requests
-        // by ID for test determinism.
+    /// Aborts all in-flight requests when losing leadership. The client must
+    /// retry.
+    fn abort_requests(&mut self) -> Result<()> {
+        // Sort the requests by ID for test determinism.
         for write in std::mem::take(&mut self.role.writes).into_values().sorted_by_key(|w| w.id) {
             let response = Err(Error::Abort);
             self.send(write.from, Message::ClientResponse { id: write.id, response })?;
@@ -791,10 +1691,29 @@ impl RawNode<Leader> {
             let response = Err(Error::Abort);
             self.send(read.from, Message::ClientResponse { id: read.id, response })?;
         }
-
-        self.log.set_term(term, None)?;
-        let election_timeout = self.random_election_timeout();
-        Ok(self.into_role(Follower::new(None, election_timeout)))
+        for (id, verification) in
+            std::mem::take(&mut self.role.verifications).into_iter().sorted_by_key(|(id, _)| *id)
+        {
+            let response = Err(Error::Abort);
+            self.send(verification.from, Message::ClientResponse { id, response })?;
+        }
+        for (id, verification) in std::mem::take(&mut self.role.state_verifications)
+            .into_iter()
+            .sorted_by_key(|(id, _)| *id)
+        {
+            let response = Err(Error::Abort);
+            self.send(verification.from, Message::ClientResponse { id, response })?;
+        }
+        for (id, statuses) in
+            std::mem::take(&mut self.role.node_statuses).into_iter().sorted_by_key(|(id, _)| *id)
+        {
+            let response = Err(Error::Abort);
+            self.send(statuses.from, Message::ClientResponse { id, response })?;
+        }
+        if let Some(Transfer { from, id, .. }) = self.role.transfer.take() {
+            self.send(from, Message::ClientResponse { id, response: Err(Error::Abort) })?;
+        }
+        Ok(())
     }
 
     /// Processes an inbound message.
@@ -804,10 +1723,25 @@ impl RawNode<Leader> {
             debug!("Dropping message from past term: {msg:?}");
             return Ok(self.into());
         }
+        // With leader leases, ignore campaigns other than leadership
+        // transfers, since we may be serving reads under our lease.
+        if self.opts.lease_duration.is_some()
+            && matches!(msg.message, Message::Campaign { transfer: false, .. })
+        {
+            debug!("Ignoring campaign while we're the leader: {msg:?}");
+            return Ok(self.into());
+        }
         // Future term: become leaderless follower and step the message.
-        if msg.term > self.term() {
+        // Pre-votes use the candidate's prospective term, which we don't adopt.
+        if msg.term > self.term() && !msg.message.is_pre_vote() {
             return self.into_follower(msg.term)?.step(msg);
         }
+        // Record that the peer is active in our term, for quorum checks.
+        if msg.term == self.term() {
+            if let Some(progress) = self.role.progress.get_mut(&msg.from) {
+                progress.recent_active = true;
+            }
+        }
 
         match msg.message {
             // A follower received our heartbeat and confirms our leadership.
@@ -828,7 +1762,10 @@ impl RawNode<Leader> {
                 // a matching entry and start replicating. Move next_index back
                 // to last_index since the follower just told us it doesn't have
                 // it (or a previous last_index).
+                //
+                // Any in-flight appends were lost, or will be rejected.
                 if match_index == 0 {
+                    self.progress(msg.from).clear_inflight();
                     self.progress(msg.from).regress_next(last_index);
                     self.maybe_send_append(msg.from, true)?;
                 }
@@ -842,6 +1779,7 @@ impl RawNode<Leader> {
                 // heartbeat will trigger a probe above.
                 if self.progress(msg.from).advance(match_index) {
                     self.maybe_commit_and_apply()?;
+                    self.maybe_transfer()?;
                 }
             }
 
@@ -853,6 +1791,7 @@ impl RawNode<Leader> {
 
                 if self.progress(msg.from).advance(match_index) {
                     self.maybe_commit_and_apply()?;
+                    self.maybe_transfer()?;
                 }
 
                 // Eagerly send any further pending entries. This may be a
@@ -869,6 +1808,59 @@ impl RawNode<Leader> {
                 }
             }
 
+            // A follower responded with a log digest. Compare it against our
+            // own digest, and respond to the client once all nodes have
+            // reported.
+            Message::VerifyResponse { id, digest } => {
+                // The request may have timed out, ignore the response.
+                let Some(verification) = self.role.verifications.get(&id) else {
+                    return Ok(self.into());
+                };
+                // Compare against our stored digest, truncated to the peer's
+                // index if it lags.
+                let report = match digest {
+                    Ok(digest) => match verification.digest.truncate(&mut self.log, digest.index) {
+                        Ok(local) => match local.compare(&digest) {
+                            None => NodeReport::Consistent { index: digest.index },
+                            Some(divergence) => {
+                                NodeReport::Divergent { index: digest.index, divergence }
+                            }
+                        },
+                        Err(err) => NodeReport::Failed(err),
+                    },
+                    Err(err) => NodeReport::Failed(err),
+                };
+                if let Some(verification) = self.role.verifications.get_mut(&id) {
+                    verification.report.nodes.insert(msg.from, report);
+                }
+                self.maybe_verify()?;
+            }
+
+            // A follower responded with state machine checksums. Compare them
+            // against our own, and respond to the client once all nodes have
+            // reported. The request may have timed out.
+            Message::VerifyStateResponse { id, checksums } => {
+                if let Some(verification) = self.role.state_verifications.get_mut(&id) {
+                    let report = match checksums {
+                        Ok(checksums) => {
+                            StateNodeReport::compare(&verification.checksums, &checksums)
+                        }
+                        Err(err) => StateNodeReport::Failed(err),
+                    };
+                    verification.report.nodes.insert(msg.from, report);
+                    self.maybe_verify_state()?;
+                }
+            }
+
+            // A follower responded with its node status. Respond to the client
+            // once all nodes have reported. The request may have timed out.
+            Message::NodeStatusResponse { id, status } => {
+                if let Some(statuses) = self.role.node_statuses.get_mut(&id) {
+                    statuses.nodes.insert(msg.from, status);
+                    self.maybe_node_statuses()?;
+                }
+            }
+
             // A follower rejected an append because the base entry in
             // reject_index did not match its log. Probe the previous entry by
             // sending an empty append until we find a common base.
@@ -896,34 +1888,172 @@ impl RawNode<Leader> {
             // AppendResponses must set either match_index or reject_index.
             Message::AppendResponse { .. } => panic!("invalid message {msg:?}"),
 
+            // A client submitted a write request during a leadership transfer.
+            // Reject it such that the target can catch up with our log. The
+            // client must retry, typically with the new leader.
+            Message::ClientRequest { id, request: Request::Write(_) }
+                if self.role.transfer.is_some() =>
+            {
+                self.send(msg.from, Message::ClientResponse { id, response: Err(Error::Abort) })?;
+            }
+
             // A client submitted a write request. Propose it, and wait until
             // it's replicated and applied to the state machine before returning
             // the response to the client.
+            //
+            // If too many entries are already awaiting commit, reject the
+            // write instead, such that clients back off until replication
+            // catches up (see Options::max_uncommitted_entries).
             Message::ClientRequest { id, request: Request::Write(command) } => {
-                let index = self.propose(Some(command))?;
-                self.role.writes.insert(index, Write { from: msg.from, id });
+                if let Some(reason) = self.overloaded() {
+                    let response = Err(Error::Overloaded(reason));
+                    self.send(msg.from, Message::ClientResponse { id, response })?;
+                    return Ok(self.into());
+                }
+                let index = info_span!("propose").in_scope(|| self.propose(Some(command)))?;
+                let span = tracing::Span::current();
+                self.role.writes.insert(index, Write { from: msg.from, id, span });
                 if self.cluster_size() == 1 {
                     self.maybe_commit_and_apply()?;
                 }
             }
 
-            // A client submitted a read request. To ensure linearizability, we
-            // must confirm that we are still the leader by sending the read's
-            // sequence number and wait for quorum confirmation.
-            Message::ClientRequest { id, request: Request::Read(command) } => {
-                self.role.read_seq += 1;
-                let read = Read { seq: self.role.read_seq, from: msg.from, id, command };
-                self.role.reads.push_back(read);
-                self.broadcast(Message::Read { seq: self.role.read_seq })?;
-                if self.cluster_size() == 1 {
-                    self.maybe_read()?;
-                }
+            // A client submitted a read request. To ensure linearizability, we
+            // must confirm that we are still the leader by sending the read's
+            // sequence number and wait for quorum confirmation. If we hold a
+            // lease, we know we're still the leader and can read immediately.
+            //
+            // Stale reads are executed as regular reads on the leader.
+            Message::ClientRequest {
+                id,
+                request: Request::Read(command) | Request::ReadStale { command, .. },
+            } => {
+                if self.has_lease() && self.can_read() {
+                    let response = info_span!("read")
+                        .in_scope(|| self.state.read(command).map(Response::Read));
+                    self.send(msg.from, Message::ClientResponse { id, response })?;
+                    return Ok(self.into());
+                }
+                let seq = self.next_read_seq();
+                let span = tracing::Span::current();
+                let read = Read { seq, from: msg.from, id, command, span };
+                self.role.reads.push_back(read);
+                self.broadcast(Message::Read { seq })?;
+                if self.cluster_size() == 1 {
+                    self.maybe_read()?;
+                }
+            }
+
+            // A client submitted a status command.
+            Message::ClientRequest { id, request: Request::Status } => {
+                let response = self.status().map(Response::Status);
+                self.send(msg.from, Message::ClientResponse { id, response })?;
+            }
+
+            // A client requested our event trace.
+            Message::ClientRequest { id, request: Request::Trace } => {
+                let response = Ok(Response::Trace(self.trace.events()));
+                self.send(msg.from, Message::ClientResponse { id, response })?;
+            }
+
+            // A client requested our metrics.
+            Message::ClientRequest { id, request: Request::Metrics } => {
+                let response = Ok(Response::Metrics(self.leader_metrics()));
+                self.send(msg.from, Message::ClientResponse { id, response })?;
+            }
+
+            // A client requested a health check of the local node.
+            Message::ClientRequest { id, request: Request::Health } => {
+                let response = self.health("leader", Some(self.id)).map(Response::Health);
+                self.send(msg.from, Message::ClientResponse { id, response })?;
+            }
+
+            // A client requested compaction of the local node's storage.
+            Message::ClientRequest {
+                id,
+                request: Request::Compact { garbage_min_fraction, garbage_min_bytes },
+            } => {
+                let response = self.compact(garbage_min_fraction, garbage_min_bytes);
+                self.send(msg.from, Message::ClientResponse { id, response })?;
+            }
+
+            // A client requested a backup of the local node's storage.
+            Message::ClientRequest { id, request: Request::Backup { path } } => {
+                let response = self.backup(&path);
+                self.send(msg.from, Message::ClientResponse { id, response })?;
+            }
+
+            // A client submitted a verify request. Check our own committed log,
+            // and ask followers for digests of theirs to compare against.
+            Message::ClientRequest { id, request: Request::Verify } => {
+                let (report, digest) = match self.verify_report() {
+                    Ok(result) => result,
+                    Err(err) => {
+                        self.send(msg.from, Message::ClientResponse { id, response: Err(err) })?;
+                        return Ok(self.into());
+                    }
+                };
+                let commit_index = report.commit_index;
+                self.role
+                    .verifications
+                    .insert(id, Verification { from: msg.from, digest, report, ticks: 0 });
+                self.broadcast(Message::Verify { id, commit_index })?;
+                self.maybe_verify()?;
+            }
+
+            // A client submitted a state verify request. Compute checksums of
+            // our own state machine, and ask followers for theirs at our
+            // applied index to compare against.
+            Message::ClientRequest { id, request: Request::VerifyState } => {
+                let (report, checksums) = match self.verify_state_report() {
+                    Ok(result) => result,
+                    Err(err) => {
+                        self.send(msg.from, Message::ClientResponse { id, response: Err(err) })?;
+                        return Ok(self.into());
+                    }
+                };
+                let applied_index = report.applied_index;
+                let verification =
+                    StateVerification { from: msg.from, checksums, report, ticks: 0 };
+                self.role.state_verifications.insert(id, verification);
+                self.broadcast(Message::VerifyState { id, applied_index })?;
+                self.maybe_verify_state()?;
+            }
+
+            // A client requested the status of all nodes. Ask the followers
+            // for theirs, and respond once they have.
+            Message::ClientRequest { id, request: Request::Nodes } => {
+                let status = self.node_status("leader", Some(self.id))?;
+                let nodes = BTreeMap::from([(self.id, status)]);
+                self.role
+                    .node_statuses
+                    .insert(id, NodeStatuses { from: msg.from, nodes, ticks: 0 });
+                self.broadcast(Message::NodeStatus { id })?;
+                self.maybe_node_statuses()?;
             }
 
-            // A client submitted a status command.
-            Message::ClientRequest { id, request: Request::Status } => {
-                let response = self.status().map(Response::Status);
-                self.send(msg.from, Message::ClientResponse { id, response })?;
+            // A client asked us to transfer leadership, e.g. because we're
+            // shutting down. Use the given voting peer or pick the most
+            // up-to-date one, and tell it to campaign once it has caught up
+            // with our log. A previous pending transfer is aborted.
+            Message::ClientRequest { id, request: Request::TransferLeadership(to) } => {
+                if let Some(to) = to.filter(|to| !self.peers.contains(to) || self.is_learner(*to)) {
+                    let response = errinput!("node {to} is not a voting peer");
+                    self.send(msg.from, Message::ClientResponse { id, response })?;
+                    return Ok(self.into());
+                }
+                if let Some(Transfer { from, id, .. }) = self.role.transfer.take() {
+                    self.send(from, Message::ClientResponse { id, response: Err(Error::Abort) })?;
+                }
+                let Some(to) = to.or_else(|| self.transfer_target()) else {
+                    let response = Ok(Response::TransferLeadership(None));
+                    self.send(msg.from, Message::ClientResponse { id, response })?;
+                    return Ok(self.into());
+                };
+                info!("Transferring leadership to {to}");
+                self.role.transfer = Some(Transfer { to, from: msg.from, id, ticks: 0 });
+                self.maybe_send_append(to, false)?;
+                self.maybe_transfer()?;
             }
 
             // Don't grant any votes (we've already voted for ourself).
@@ -934,8 +2064,20 @@ impl RawNode<Leader> {
             // Votes can come in after we won the election, ignore them.
             Message::CampaignResponse { .. } => {}
 
+            // We're the leader, so don't grant pre-votes. Ignore late
+            // responses to our own pre-vote.
+            Message::PreVote { .. } => self.pre_vote(&msg, true)?,
+            Message::PreVoteResponse { .. } => {}
+
             // There can't be another leader in this term.
-            Message::Heartbeat { .. } | Message::Append { .. } | Message::Read { .. } => {
+            Message::Heartbeat { .. }
+            | Message::Append { .. }
+            | Message::InstallSnapshot { .. }
+            | Message::Read { .. }
+            | Message::Verify { .. }
+            | Message::VerifyState { .. }
+            | Message::NodeStatus { .. }
+            | Message::TimeoutNow => {
                 panic!("saw other leader {} in term {}", msg.from, msg.term);
             }
 
@@ -948,18 +2090,104 @@ impl RawNode<Leader> {
 
     /// Processes a logical clock tick.
     fn tick(mut self) -> Result<Node> {
+        self.role.ticks += 1;
+        if self.opts.check_quorum {
+            self.role.since_quorum_check += 1;
+            if self.role.since_quorum_check >= self.opts.election_timeout_range.start {
+                self.role.since_quorum_check = 0;
+                if !self.check_quorum() {
+                    return Ok(self.step_down()?.into());
+                }
+            }
+        }
+        // Read sequence numbers sent more than a lease duration ago can't
+        // extend the lease, so don't track them.
+        if let Some(duration) = self.opts.lease_duration {
+            while let Some((_, sent)) = self.role.read_seq_sent.front() {
+                if sent + duration as u64 > self.role.ticks {
+                    break;
+                }
+                self.role.read_seq_sent.pop_front();
+            }
+        }
         self.role.since_heartbeat += 1;
         if self.role.since_heartbeat >= self.opts.heartbeat_interval {
             self.heartbeat()?;
         }
+        for verification in self.role.verifications.values_mut() {
+            verification.ticks = verification.ticks.saturating_add(1);
+        }
+        self.maybe_verify()?;
+        for verification in self.role.state_verifications.values_mut() {
+            verification.ticks = verification.ticks.saturating_add(1);
+        }
+        self.maybe_verify_state()?;
+        for statuses in self.role.node_statuses.values_mut() {
+            statuses.ticks = statuses.ticks.saturating_add(1);
+        }
+        self.maybe_node_statuses()?;
+
+        // Abort a leadership transfer if the target hasn't caught up within
+        // an election timeout, e.g. because it's unreachable.
+        if let Some(transfer) = self.role.transfer.as_mut() {
+            transfer.ticks = transfer.ticks.saturating_add(1);
+            if transfer.ticks >= self.opts.election_timeout_range.start {
+                let Transfer { to, from, id, .. } = self.role.transfer.take().unwrap();
+                info!("Leadership transfer to {to} timed out");
+                self.send(from, Message::ClientResponse { id, response: Err(Error::Abort) })?;
+            }
+        }
+
+        // Refill the catch-up budgets, and resume throttled replication.
+        for peer in self.peers.iter().copied().sorted() {
+            let progress = self.role.progress.get_mut(&peer).expect("unknown node");
+            progress.refill(&self.opts);
+            if std::mem::take(&mut progress.throttled) {
+                self.maybe_send_append(peer, false)?;
+            }
+        }
         Ok(self.into())
     }
 
-    /// Broadcasts a heartbeat to all peers.
+    /// Returns true if we've heard from a quorum (including ourself) since the
+    /// last quorum check, and resets the peers' activity for the next check.
+    fn check_quorum(&mut self) -> bool {
+        let active = 1 + self.voter_progress().filter(|p| p.recent_active).count();
+        for progress in self.role.progress.values_mut() {
+            progress.recent_active = false;
+        }
+        active >= self.quorum_size()
+    }
+
+    /// Returns a reason if the leader is overloaded and should reject new
+    /// writes, i.e. if either the uncommitted log tail or the apply backlog
+    /// has reached Options::max_uncommitted_entries.
+    fn overloaded(&self) -> Option<String> {
+        let max = self.opts.max_uncommitted_entries?;
+        let (last_index, _) = self.log.get_last_index();
+        let (commit_index, _) = self.log.get_commit_index();
+        let uncommitted = last_index - commit_index;
+        if uncommitted >= max as Index {
+            return Some(format!("{uncommitted} uncommitted Raft log entries, limit is {max}"));
+        }
+        // The applied index may exceed the commit index after a restart, since
+        // the commit index isn't flushed.
+        let unapplied = commit_index.saturating_sub(self.state.get_applied_index());
+        if unapplied >= max as Index {
+            return Some(format!("{unapplied} unapplied Raft log entries, limit is {max}"));
+        }
+        None
+    }
+
+    /// Broadcasts a heartbeat to all peers. With leases, each heartbeat uses
+    /// a new read sequence number, such that its confirmation renews the lease.
     fn heartbeat(&mut self) -> Result<()> {
         let (last_index, last_term) = self.log.get_last_index();
         let (commit_index, _) = self.log.get_commit_index();
-        let read_seq = self.role.read_seq;
+        let read_seq = match self.opts.lease_duration {
+            Some(_) => self.next_read_seq(),
+            None => self.role.read_seq,
+        };
         assert_eq!(last_term, self.term(), "leader's last_term not in current term");
 
         self.role.since_heartbeat = 0;
@@ -971,6 +2199,7 @@ impl RawNode<Leader> {
     /// and applied to the state machine.
     fn propose(&mut self, command: Option<Vec<u8>>) -> Result<Index> {
         let index = self.log.append(command)?;
+        self.trace(Event::Append { first: index, last: index });
         for peer in self.peers.iter().copied().sorted() {
             // Eagerly send the entry to the peer if it's in steady state and
             // we've sent all previous entries. Otherwise, the peer is lagging
@@ -988,7 +2217,7 @@ impl RawNode<Leader> {
         // Determine the new commit index by quorum.
         let (last_index, _) = self.log.get_last_index();
         let quorum_index = self.quorum_value(
-            self.role.progress.values().map(|p| p.match_index).chain([last_index]).collect(),
+            self.voter_progress().map(|p| p.match_index).chain([last_index]).collect(),
         );
 
         // If the commit index doesn't advance, do nothing. We don't assert on
@@ -1009,6 +2238,7 @@ impl RawNode<Leader> {
 
         // Commit entries.
         self.log.commit(quorum_index)?;
+        self.trace(Event::Commit { index: quorum_index });
 
         // Apply entries and respond to clients.
         let term = self.term();
@@ -1016,14 +2246,21 @@ impl RawNode<Leader> {
         while let Some(entry) = iter.next().transpose()? {
             debug!("Applying {entry:?}");
             let write = self.role.writes.remove(&entry.index);
-            let result = self.state.apply(entry);
+            let parent = write.as_ref().and_then(|write| write.span.id());
+            let span = info_span!(parent: parent, "apply", index = entry.index);
+            let result = span.in_scope(|| self.state.apply(entry));
 
-            if let Some(Write { id, from: to }) = write {
+            if let Some(Write { id, from: to, .. }) = write {
                 let message = Message::ClientResponse { id, response: result.map(Response::Write) };
-                Self::send_with(&self.tx, Envelope { from: self.id, term, to, message })?;
+                Self::send_with(
+                    &self.tx,
+                    &self.counters,
+                    Envelope { from: self.id, term, to, message },
+                )?;
             }
         }
         drop(iter);
+        self.maybe_truncate_log()?;
 
         // If the commit term changed, there may be pending reads waiting for us
         // to commit and apply an entry from our own term. Execute them.
@@ -1034,25 +2271,74 @@ impl RawNode<Leader> {
         Ok(quorum_index)
     }
 
-    /// Executes any ready read requests (with confirmed sequence numbers).
-    fn maybe_read(&mut self) -> Result<()> {
-        if self.role.reads.is_empty() {
-            return Ok(());
+    /// Returns the next read sequence number, recording when it was sent if
+    /// leases are enabled. The caller must send it to all peers.
+    fn next_read_seq(&mut self) -> ReadSequence {
+        self.role.read_seq += 1;
+        if self.opts.lease_duration.is_some() && self.cluster_size() > 1 {
+            self.role.read_seq_sent.push_back((self.role.read_seq, self.role.ticks));
+        }
+        self.role.read_seq
+    }
+
+    /// Returns the progress of voting peers, i.e. excluding learners.
+    fn voter_progress(&self) -> impl Iterator<Item = &Progress> {
+        self.role.progress.iter().filter(|(id, _)| !self.is_learner(**id)).map(|(_, p)| p)
+    }
+
+    /// Returns the maximum read sequence number confirmed by a quorum.
+    fn quorum_read_seq(&self) -> ReadSequence {
+        self.quorum_value(
+            self.voter_progress().map(|p| p.read_seq).chain([self.role.read_seq]).collect(),
+        )
+    }
+
+    /// Returns true if we hold a leader lease.
+    fn has_lease(&self) -> bool {
+        self.role.ticks < self.role.lease_expiry
+    }
+
+    /// Renews our lease when a quorum has confirmed a read sequence number,
+    /// until lease_duration ticks after it was sent. The followers that
+    /// confirmed it won't vote for another candidate until they've gone an
+    /// election timeout without hearing from us, and the lease duration is
+    /// shorter than that.
+    fn maybe_renew_lease(&mut self) {
+        let Some(duration) = self.opts.lease_duration else {
+            return;
+        };
+        let quorum_read_seq = self.quorum_read_seq();
+        while let Some(&(seq, sent)) = self.role.read_seq_sent.front() {
+            if seq > quorum_read_seq {
+                break;
+            }
+            self.role.read_seq_sent.pop_front();
+            if !self.role.lease_revoked {
+                let expiry = sent + duration as u64;
+                self.role.lease_expiry = std::cmp::max(self.role.lease_expiry, expiry);
+            }
         }
+    }
 
-        // It's only safe to read if we've committed and applied an entry from
-        // our own term (the leader appends an entry when elected). Otherwise we
-        // may be behind on application and serve stale reads.
+    /// Returns true if it's safe to execute reads, once our leadership is
+    /// confirmed. We must have committed and applied an entry from our own
+    /// term (the leader appends an entry when elected). Otherwise we may be
+    /// behind on application and serve stale reads.
+    fn can_read(&self) -> bool {
         let (commit_index, commit_term) = self.log.get_commit_index();
-        let applied_index = self.state.get_applied_index();
-        if commit_term < self.term() || applied_index < commit_index {
+        commit_term == self.term() && self.state.get_applied_index() >= commit_index
+    }
+
+    /// Executes any ready read requests (with confirmed sequence numbers), and
+    /// renews our lease.
+    fn maybe_read(&mut self) -> Result<()> {
+        self.maybe_renew_lease();
+        if self.role.reads.is_empty() || !self.can_read() {
             return Ok(());
         }
 
         // Determine the maximum read sequence confirmed by quorum.
-        let quorum_read_seq = self.quorum_value(
-            self.role.progress.values().map(|p| p.read_seq).chain([self.role.read_seq]).collect(),
-        );
+        let quorum_read_seq = self.quorum_read_seq();
 
         // Execute ready reads. The VecDeque is ordered by read_seq, so we
         // can keep pulling until we hit quorum_read_seq.
@@ -1061,12 +2347,115 @@ impl RawNode<Leader> {
                 break;
             }
             let read = self.role.reads.pop_front().unwrap();
-            let response = self.state.read(read.command).map(Response::Read);
+            let span = info_span!(parent: read.span.id(), "read");
+            let response = span.in_scope(|| self.state.read(read.command).map(Response::Read));
             self.send(read.from, Message::ClientResponse { id: read.id, response })?;
         }
         Ok(())
     }
 
+    /// Responds to completed verify requests, where all nodes have reported.
+    /// Requests time out after the minimum election timeout, in which case the
+    /// partial report is returned, omitting unresponsive nodes.
+    fn maybe_verify(&mut self) -> Result<()> {
+        let cluster_size = self.peers.len() + 1;
+        let timeout = self.opts.election_timeout_range.start;
+        let done: Vec<RequestID> = self
+            .role
+            .verifications
+            .iter()
+            .filter(|(_, v)| v.report.nodes.len() >= cluster_size || v.ticks >= timeout)
+            .map(|(id, _)| *id)
+            .sorted()
+            .collect();
+        for id in done {
+            let verification = self.role.verifications.remove(&id).expect("missing verification");
+            let response = Ok(Response::Verify(verification.report));
+            self.send(verification.from, Message::ClientResponse { id, response })?;
+        }
+        Ok(())
+    }
+
+    /// Responds to completed state verify requests, where all nodes have
+    /// reported. Requests time out after the minimum election timeout, in which
+    /// case the partial report is returned, omitting unresponsive nodes.
+    fn maybe_verify_state(&mut self) -> Result<()> {
+        let cluster_size = self.peers.len() + 1;
+        let timeout = self.opts.election_timeout_range.start;
+        let done: Vec<RequestID> = self
+            .role
+            .state_verifications
+            .iter()
+            .filter(|(_, v)| v.report.nodes.len() >= cluster_size || v.ticks >= timeout)
+            .map(|(id, _)| *id)
+            .sorted()
+            .collect();
+        for id in done {
+            let verification =
+                self.role.state_verifications.remove(&id).expect("missing state verification");
+            let response = Ok(Response::VerifyState(verification.report));
+            self.send(verification.from, Message::ClientResponse { id, response })?;
+        }
+        Ok(())
+    }
+
+    /// Responds to completed node status requests, where all nodes have
+    /// reported. Requests time out after the minimum election timeout, in which
+    /// case unresponsive nodes are reported as unreachable (None).
+    fn maybe_node_statuses(&mut self) -> Result<()> {
+        let cluster_size = self.peers.len() + 1;
+        let timeout = self.opts.election_timeout_range.start;
+        let done: Vec<RequestID> = self
+            .role
+            .node_statuses
+            .iter()
+            .filter(|(_, s)| s.nodes.len() >= cluster_size || s.ticks >= timeout)
+            .map(|(id, _)| *id)
+            .sorted()
+            .collect();
+        for id in done {
+            let statuses = self.role.node_statuses.remove(&id).expect("missing node statuses");
+            let mut nodes: BTreeMap<NodeID, Option<NodeStatus>> =
+                self.peers.iter().map(|peer| (*peer, None)).collect();
+            nodes.extend(statuses.nodes.into_iter().map(|(id, status)| (id, Some(status))));
+            let response = Ok(Response::Nodes(nodes));
+            self.send(statuses.from, Message::ClientResponse { id, response })?;
+        }
+        Ok(())
+    }
+
+    /// Returns the peer to transfer leadership to: the voter with the highest
+    /// match index, or the lowest ID for ties. None if there are no voters.
+    fn transfer_target(&self) -> Option<NodeID> {
+        self.role
+            .progress
+            .iter()
+            .filter(|(id, _)| !self.is_learner(**id))
+            .max_by_key(|(id, progress)| (progress.match_index, std::cmp::Reverse(**id)))
+            .map(|(id, _)| *id)
+    }
+
+    /// Completes a pending leadership transfer once the target has caught up
+    /// with our log, by telling it to campaign immediately. It will then win
+    /// the election in the next term, and we'll step down when we see it.
+    fn maybe_transfer(&mut self) -> Result<()> {
+        let Some(to) = self.role.transfer.as_ref().map(|t| t.to) else {
+            return Ok(());
+        };
+        let (last_index, _) = self.log.get_last_index();
+        if self.progress(to).match_index < last_index {
+            return Ok(());
+        }
+        let Transfer { from, id, .. } = self.role.transfer.take().unwrap();
+        // Give up our lease, since peers will vote for the target even though
+        // they have a leader.
+        self.role.lease_revoked = true;
+        self.role.lease_expiry = 0;
+        self.send(to, Message::TimeoutNow)?;
+        let response = Ok(Response::TransferLeadership(Some(to)));
+        self.send(from, Message::ClientResponse { id, response })
+    }
+
     // Sends a batch of pending log entries to a follower in the
     // [next_index,last_index] range, limited by max_append_entries.
     //
@@ -1079,6 +2468,21 @@ impl RawNode<Leader> {
     // The probe is skipped if the follower is up-to-date (according to
     // match_index and last_index). If the probe's base_index has already been
     // confirmed via match_index, an actual append is sent instead.
+    //
+    // Appends to lagging followers (i.e. anything but the latest entry) are
+    // catch-up replication, limited by the peer's catch-up budget. If it's
+    // exhausted, the append is deferred until the budget is refilled on the
+    // next tick.
+    //
+    // Appends are pipelined: we don't wait for a response before sending the
+    // next one, up to the in-flight window given by max_inflight_appends and
+    // max_inflight_bytes. Once it's full, entries are queued until the
+    // follower acknowledges earlier appends, at which point the queued
+    // entries are sent in a single batch.
+    //
+    // If the entries at next_index have been truncated, a state machine
+    // snapshot is sent instead (also as catch-up replication). It's taken at
+    // the applied index, and replication resumes after it.
     fn maybe_send_append(&mut self, peer: NodeID, mut probe: bool) -> Result<()> {
         let (last_index, _) = self.log.get_last_index();
         let progress = self.role.progress.get_mut(&peer).expect("unknown node");
@@ -1092,6 +2496,24 @@ impl RawNode<Leader> {
             return Ok(());
         }
 
+        // If the base entry has been truncated, send a snapshot instead.
+        let (snapshot_index, _) = self.log.get_snapshot_index();
+        if progress.next_index <= snapshot_index {
+            if progress.catchup_messages == 0 || progress.catchup_bytes == 0 {
+                debug!("Throttling catch-up replication to {peer}");
+                progress.throttled = true;
+                return Ok(());
+            }
+            let index = self.state.get_applied_index();
+            let term = self.log.get_entry_term(index)?.expect("missing applied entry");
+            let data = self.state.snapshot()?;
+            progress.catchup_messages -= 1;
+            progress.catchup_bytes = progress.catchup_bytes.saturating_sub(data.len());
+            progress.next_index = index + 1;
+            debug!("Sending snapshot at {index}@{term} to {peer}");
+            return self.send(peer, Message::InstallSnapshot { index, term, data });
+        }
+
         // If a probe was requested, but the base_index has already been
         // confirmed via match_index, there is no point in probing. Just send
         // the entries instead.
@@ -1103,31 +2525,165 @@ impl RawNode<Leader> {
             return Ok(());
         }
 
-        // Fetch the base and entries.
+        // Throttle catch-up replication if the budget is exhausted.
+        let catchup = !probe && progress.next_index < last_index;
+        if catchup && (progress.catchup_messages == 0 || progress.catchup_bytes == 0) {
+            debug!("Throttling catch-up replication to {peer}");
+            progress.throttled = true;
+            return Ok(());
+        }
+
+        // Pause replication if the in-flight window is full. Probes are always
+        // sent, since they don't carry entries.
+        let inflight_room = progress.inflight_room(&self.opts);
+        if !probe && inflight_room.is_none() {
+            debug!("Pausing replication to {peer} with full in-flight window");
+            return Ok(());
+        }
+
+        // Fetch the base and entries. Appends are limited by the catch-up
+        // byte budget and the in-flight window, but always include at least
+        // one entry.
+        let max_bytes = match catchup {
+            true => std::cmp::min(progress.catchup_bytes, inflight_room.unwrap_or(usize::MAX)),
+            false => inflight_room.unwrap_or(usize::MAX),
+        };
         let (base_index, base_term) = match progress.next_index {
             0 => panic!("next_index=0 for node {peer}"),
             1 => (0, 0),
-            next => self.log.get(next - 1)?.map(|e| (e.index, e.term)).expect("missing base entry"),
-        };
-        let entries = match probe {
-            false => self
-                .log
-                .scan(progress.next_index..)
-                .take(self.opts.max_append_entries)
-                .try_collect()?,
-            true => Vec::new(),
+            next => (next - 1, self.log.get_entry_term(next - 1)?.expect("missing base entry")),
         };
+        let mut entries = Vec::new();
+        if !probe {
+            let mut size = 0;
+            let mut scan = self.log.scan(progress.next_index..).take(self.opts.max_append_entries);
+            while let Some(entry) = scan.next().transpose()? {
+                let entry_size = entry.command.as_ref().map_or(0, |c| c.len());
+                if !entries.is_empty() && size + entry_size > max_bytes {
+                    break;
+                }
+                size += entry_size;
+                entries.push(entry);
+            }
+            if catchup {
+                progress.catchup_messages -= 1;
+                progress.catchup_bytes = progress.catchup_bytes.saturating_sub(size);
+            }
+        }
 
         // Optimistically assume the entries will be accepted by the follower,
         // and bump next_index to avoid resending them until a response.
+        // Track the append as in flight until then.
         if let Some(last) = entries.last() {
             progress.next_index = last.index + 1;
+            let size = entries.iter().map(|e| e.command.as_ref().map_or(0, |c| c.len())).sum();
+            progress.inflight.push_back((last.index, size));
+            progress.inflight_bytes += size;
         }
 
         debug!("Replicating {} entries with base {base_index} to {peer}", entries.len());
         self.send(peer, Message::Append { base_index, base_term, entries })
     }
 
+    /// Executes a client request directly, without going through the message
+    /// loop. This is only valid in a single-node cluster, where there is no
+    /// need for consensus: writes are appended, committed, and applied in one
+    /// go, and reads are executed immediately. The log is identical to the one
+    /// produced by the message loop.
+    ///
+    /// The outer result contains node errors, which are fatal as with step().
+    /// The inner result is the client response.
+    fn execute(&mut self, request: Request) -> Result<Result<Response>> {
+        assert!(self.peers.is_empty(), "direct execution requires a single-node cluster");
+        match request {
+            Request::Write(command) => {
+                let index = info_span!("propose").in_scope(|| self.propose(Some(command)))?;
+                self.log.commit(index)?;
+                self.trace(Event::Commit { index });
+                let mut result = None;
+                let mut iter = self.log.scan_apply(self.state.get_applied_index());
+                while let Some(entry) = iter.next().transpose()? {
+                    debug!("Applying {entry:?}");
+                    let entry_index = entry.index;
+                    let span = info_span!("apply", index = entry_index);
+                    let entry_result = span.in_scope(|| self.state.apply(entry));
+                    if entry_index == index {
+                        result = Some(entry_result);
+                    }
+                }
+                drop(iter);
+                self.maybe_truncate_log()?;
+                Ok(result.expect("write not applied").map(Response::Write))
+            }
+            Request::Read(command) | Request::ReadStale { command, .. } => {
+                Ok(info_span!("read").in_scope(|| self.state.read(command).map(Response::Read)))
+            }
+            Request::Status => Ok(self.status().map(Response::Status)),
+            Request::Verify => Ok(self.verify_report().map(|(report, _)| Response::Verify(report))),
+            Request::VerifyState => {
+                Ok(self.verify_state_report().map(|(report, _)| Response::VerifyState(report)))
+            }
+            Request::Trace => Ok(Ok(Response::Trace(self.trace.events()))),
+            Request::Metrics => Ok(Ok(Response::Metrics(self.leader_metrics()))),
+            Request::Nodes => {
+                let status = self.node_status("leader", Some(self.id))?;
+                Ok(Ok(Response::Nodes(BTreeMap::from([(self.id, Some(status))]))))
+            }
+            Request::TransferLeadership(_) => Ok(Ok(Response::TransferLeadership(None))),
+            Request::Health => Ok(self.health("leader", Some(self.id)).map(Response::Health)),
+            Request::Compact { garbage_min_fraction, garbage_min_bytes } => {
+                Ok(self.compact(garbage_min_fraction, garbage_min_bytes))
+            }
+            Request::Backup { path } => Ok(self.backup(&path)),
+        }
+    }
+
+    /// Returns the leader's metrics, including peer replication progress.
+    fn leader_metrics(&self) -> Metrics {
+        let progress = self
+            .role
+            .progress
+            .iter()
+            .map(|(id, pr)| {
+                let progress = PeerProgress {
+                    match_index: pr.match_index,
+                    next_index: pr.next_index,
+                    inflight_appends: pr.inflight.len(),
+                    inflight_bytes: pr.inflight_bytes,
+                };
+                (*id, progress)
+            })
+            .collect();
+        self.metrics("leader", Some(self.id), progress)
+    }
+
+    /// Creates a verification report for our own log at the commit index,
+    /// along with its digest. Peer results must be added as they respond.
+    fn verify_report(&mut self) -> Result<(Report, Digest)> {
+        let (commit_index, _) = self.log.get_commit_index();
+        let digest = Digest::new(&mut self.log, commit_index)?;
+        let mut report =
+            Report { leader: self.id, term: self.term(), commit_index, nodes: BTreeMap::new() };
+        report.nodes.insert(self.id, NodeReport::Consistent { index: commit_index });
+        Ok((report, digest))
+    }
+
+    /// Creates a state verification report for our own state machine at the
+    /// applied index, along with its checksums. Peer results must be added as
+    /// they respond.
+    fn verify_state_report(&mut self) -> Result<(StateReport, BTreeMap<String, u64>)> {
+        let checksums = self.state.checksums()?;
+        let mut report = StateReport {
+            leader: self.id,
+            term: self.term(),
+            applied_index: self.state.get_applied_index(),
+            partitions: checksums.len(),
+            nodes: BTreeMap::new(),
+        };
+        report.nodes.insert(self.id, StateNodeReport::Consistent);
+        Ok((report, checksums))
+    }
+
     /// Generates cluster status.
     fn status(&mut self) -> Result<Status> {
         Ok(Status {
@@ -1205,6 +2761,77 @@ mod tests {
         node.quorum_value(values)
     }
 
+    /// Tests that RawNode::new() validates learners: they must be cluster
+    /// nodes, and there must be at least one voter.
+    #[test_case(&[] => true; "none")]
+    #[test_case(&[1] => true; "self")]
+    #[test_case(&[2, 3] => true; "peers")]
+    #[test_case(&[4] => false; "unknown")]
+    #[test_case(&[1, 2, 3] => false; "no voters")]
+    fn new_learners(learners: &[NodeID]) -> bool {
+        let log = Log::new(Box::new(storage::Memory::new())).expect("log failed");
+        let state = teststate::Noop::new();
+        let (tx, _) = crossbeam::channel::unbounded();
+        let opts = Options { learners: learners.iter().copied().collect(), ..Options::default() };
+        RawNode::new(1, HashSet::from([2, 3]), log, state, tx, opts).is_ok()
+    }
+
+    /// Tests Options.validate().
+    #[test_case(4, 10..20, 100 => true; "default")]
+    #[test_case(1, 2..3, 1 => true; "minimal")]
+    #[test_case(0, 10..20, 100 => false; "zero heartbeat")]
+    #[test_case(4, 10..10, 100 => false; "empty election range")]
+    #[test_case(4, 7..20, 100 => false; "election timeout too short")]
+    #[test_case(4, 10..20, 0 => false; "zero max append")]
+    fn options_validate(
+        heartbeat_interval: Ticks,
+        election_timeout_range: std::ops::Range<Ticks>,
+        max_append_entries: usize,
+    ) -> bool {
+        let opts = Options {
+            heartbeat_interval,
+            election_timeout_range,
+            max_append_entries,
+            ..Options::default()
+        };
+        opts.validate().is_ok()
+    }
+
+    /// Tests that RawNode<Leader>.overloaded() checks both the uncommitted
+    /// and unapplied entries against max_uncommitted_entries.
+    #[test]
+    fn leader_overloaded() -> Result<(), Box<dyn Error>> {
+        let log = Log::new(Box::new(storage::Memory::new()))?;
+        let state = teststate::Noop::new();
+        let (tx, _rx) = crossbeam::channel::unbounded();
+        let opts = Options { max_uncommitted_entries: Some(2), ..Options::default() };
+        let node = RawNode::new(1, HashSet::from([2, 3]), log, state, tx, opts)?;
+        let mut node = node.into_candidate(false)?.into_leader()?;
+
+        // The leader's initial entry is uncommitted, but below the limit.
+        assert_eq!(node.overloaded(), None);
+
+        // Appending another entry reaches the uncommitted limit.
+        let index = node.propose(None)?;
+        assert_eq!(
+            node.overloaded().as_deref(),
+            Some("2 uncommitted Raft log entries, limit is 2")
+        );
+
+        // Committing the entries without applying them reaches the apply limit.
+        node.log.commit(index)?;
+        assert_eq!(node.overloaded().as_deref(), Some("2 unapplied Raft log entries, limit is 2"));
+
+        // Applying them clears it.
+        let mut iter = node.log.scan_apply(node.state.get_applied_index());
+        while let Some(entry) = iter.next().transpose()? {
+            node.state.apply(entry)?;
+        }
+        drop(iter);
+        assert_eq!(node.overloaded(), None);
+        Ok(())
+    }
+
     /// Test helpers for RawNode.
     impl RawNode<Follower> {
         /// Creates a noop node, with a noop state machine and transport.
@@ -1218,10 +2845,6 @@ mod tests {
 
     /// Test helpers for Node.
     impl Node {
-        fn dismantle(self) -> (Log, Box<dyn State>) {
-            with_rawnode!(self, |n| (n.log, n.state))
-        }
-
         fn get_applied_index(&self) -> Index {
             with_rawnode!(ref self, |n| n.state.get_applied_index())
         }
@@ -1234,6 +2857,10 @@ mod tests {
             with_rawnode!(ref self, |n| n.log.get_last_index())
         }
 
+        fn get_snapshot_index(&self) -> (Index, Term) {
+            with_rawnode!(ref self, |n| n.log.get_snapshot_index())
+        }
+
         fn get_term_vote(&self) -> (Term, Option<NodeID>) {
             with_rawnode!(ref self, |n| n.log.get_term())
         }
@@ -1291,7 +2918,7 @@ mod tests {
                     self.campaign(&ids, &mut output)?;
                 }
 
-                // cluster nodes=N [leader=ID] [heartbeat_interval=N] [election_timeout=N] [max_append_entries=N]
+                // cluster nodes=N [leader=ID] [heartbeat_interval=N] [election_timeout=N] [max_append_entries=N] [max_read_staleness=N] [max_catchup_messages=N] [max_catchup_bytes=N] [max_inflight_appends=N] [max_inflight_bytes=N] [max_uncommitted_entries=N] [snapshot_interval=N] [pre_vote=BOOL] [lease_duration=N] [check_quorum=BOOL] [learners=ID,...]
                 // Creates a new Raft cluster.
                 "cluster" => {
                     let mut opts = Options::default();
@@ -1307,6 +2934,41 @@ mod tests {
                     if let Some(max_append_entries) = args.lookup_parse("max_append_entries")? {
                         opts.max_append_entries = max_append_entries;
                     }
+                    if let Some(max_read_staleness) = args.lookup_parse("max_read_staleness")? {
+                        opts.max_read_staleness = max_read_staleness;
+                    }
+                    if let Some(max_catchup_messages) = args.lookup_parse("max_catchup_messages")? {
+                        opts.max_catchup_messages = Some(max_catchup_messages);
+                    }
+                    if let Some(max_catchup_bytes) = args.lookup_parse("max_catchup_bytes")? {
+                        opts.max_catchup_bytes = Some(max_catchup_bytes);
+                    }
+                    if let Some(max_inflight) = args.lookup_parse("max_inflight_appends")? {
+                        opts.max_inflight_appends = Some(max_inflight);
+                    }
+                    if let Some(max_inflight_bytes) = args.lookup_parse("max_inflight_bytes")? {
+                        opts.max_inflight_bytes = Some(max_inflight_bytes);
+                    }
+                    if let Some(max_uncommitted) = args.lookup_parse("max_uncommitted_entries")? {
+                        opts.max_uncommitted_entries = Some(max_uncommitted);
+                    }
+                    if let Some(snapshot_interval) = args.lookup_parse("snapshot_interval")? {
+                        opts.snapshot_interval = Some(snapshot_interval);
+                    }
+                    if let Some(pre_vote) = args.lookup_parse("pre_vote")? {
+                        opts.pre_vote = pre_vote;
+                    }
+                    if let Some(lease_duration) = args.lookup_parse("lease_duration")? {
+                        opts.lease_duration = Some(lease_duration);
+                    }
+                    if let Some(check_quorum) = args.lookup_parse("check_quorum")? {
+                        opts.check_quorum = check_quorum;
+                    }
+                    if let Some(learners) = args.lookup("learners") {
+                        for id in learners.value.split(',') {
+                            opts.learners.insert(id.parse()?);
+                        }
+                    }
                     args.reject_rest()?;
                     self.cluster(nodes, leader, opts, &mut output)?;
                 }
@@ -1322,16 +2984,29 @@ mod tests {
                     self.deliver(&ids, from, &mut output)?;
                 }
 
-                // get ID KEY
+                // get [direct=BOOL] [stale=BOOL] [max_staleness=N] ID KEY
                 // Sends a client request to the given node to read the given
-                // key from the state machine (key/value store).
+                // key from the state machine (key/value store). If direct is
+                // true, executes it directly on a single-node cluster. If stale
+                // is true, sends a stale read which followers may serve, with
+                // the given maximum staleness in ticks if any.
                 "get" => {
                     let mut args = command.consume_args();
+                    let direct = args.lookup_parse("direct")?.unwrap_or(false);
+                    let stale = args.lookup_parse("stale")?.unwrap_or(false);
+                    let max_staleness = args.lookup_parse("max_staleness")?;
                     let id = args.next_pos().ok_or("must specify node ID")?.parse()?;
                     let key = args.next_pos().ok_or("must specify key")?.value.clone();
                     args.reject_rest()?;
-                    let request = Request::Read(KVCommand::Get { key }.encode());
-                    self.request(id, request, &mut output)?;
+                    let command = KVCommand::Get { key }.encode();
+                    let request = match stale {
+                        true => Request::ReadStale { command, max_staleness },
+                        false => Request::Read(command),
+                    };
+                    match direct {
+                        true => self.execute(id, request, &mut output)?,
+                        false => self.request(id, request, &mut output)?,
+                    }
                 }
 
                 // heal [ID...]
@@ -1364,17 +3039,22 @@ mod tests {
                     self.partition(&ids, &mut output)?;
                 }
 
-                // put ID KEY=VALUE
+                // put [direct=BOOL] ID KEY=VALUE
                 // Sends a client request to the given node to write a key/value
-                // pair to the state machine (key/value store).
+                // pair to the state machine (key/value store). If direct is
+                // true, executes it directly on a single-node cluster.
                 "put" => {
                     let mut args = command.consume_args();
+                    let direct = args.lookup_parse("direct")?.unwrap_or(false);
                     let id = args.next_pos().ok_or("must specify node ID")?.parse()?;
                     let kv = args.next_key().ok_or("must specify key/value pair")?.clone();
                     let (key, value) = (kv.key.unwrap(), kv.value);
                     args.reject_rest()?;
                     let request = Request::Write(KVCommand::Put { key, value }.encode());
-                    self.request(id, request, &mut output)?;
+                    match direct {
+                        true => self.execute(id, request, &mut output)?,
+                        false => self.request(id, request, &mut output)?,
+                    }
                 }
 
                 // restart [commit_index=INDEX] [applied_index=INDEX] [ID...]
@@ -1428,6 +3108,16 @@ mod tests {
                     }
                 }
 
+                // nodes ID
+                // Sends a client request to the given node for the status of
+                // all nodes.
+                "nodes" => {
+                    let mut args = command.consume_args();
+                    let id = args.next_pos().ok_or("must specify node ID")?.parse()?;
+                    args.reject_rest()?;
+                    self.request(id, Request::Nodes, &mut output)?;
+                }
+
                 // step ID JSON
                 // Steps a manually generated JSON message on the given node.
                 "step" => {
@@ -1448,6 +3138,64 @@ mod tests {
                     }
                 }
 
+                // health ID
+                // Sends a client request to the given node for a health check.
+                "health" => {
+                    let mut args = command.consume_args();
+                    let id = args.next_pos().ok_or("must specify node ID")?.parse()?;
+                    args.reject_rest()?;
+                    self.request(id, Request::Health, &mut output)?;
+                }
+
+                // trace ID
+                // Sends a client request to the given node for its event trace.
+                "trace" => {
+                    let mut args = command.consume_args();
+                    let id = args.next_pos().ok_or("must specify node ID")?.parse()?;
+                    args.reject_rest()?;
+                    self.request(id, Request::Trace, &mut output)?;
+                }
+
+                // metrics ID
+                // Sends a client request to the given node for its metrics.
+                "metrics" => {
+                    let mut args = command.consume_args();
+                    let id = args.next_pos().ok_or("must specify node ID")?.parse()?;
+                    args.reject_rest()?;
+                    self.request(id, Request::Metrics, &mut output)?;
+                }
+
+                // transfer ID [to=ID]
+                // Sends a client request to the given node to transfer its
+                // leadership to the given peer, or the most up-to-date peer.
+                "transfer" => {
+                    let mut args = command.consume_args();
+                    let id = args.next_pos().ok_or("must specify node ID")?.parse()?;
+                    let to = args.lookup_parse("to")?;
+                    args.reject_rest()?;
+                    self.request(id, Request::TransferLeadership(to), &mut output)?;
+                }
+
+                // verify ID
+                // Sends a client request to the given node to verify that the
+                // Raft logs of all nodes are consistent.
+                "verify" => {
+                    let mut args = command.consume_args();
+                    let id = args.next_pos().ok_or("must specify node ID")?.parse()?;
+                    args.reject_rest()?;
+                    self.request(id, Request::Verify, &mut output)?;
+                }
+
+                // verify_state ID
+                // Sends a client request to the given node to verify that the
+                // state machines of all nodes are consistent.
+                "verify_state" => {
+                    let mut args = command.consume_args();
+                    let id = args.next_pos().ok_or("must specify node ID")?.parse()?;
+                    args.reject_rest()?;
+                    self.request(id, Request::VerifyState, &mut output)?;
+                }
+
                 name => return Err(format!("unknown command {name}").into()),
             }
             Ok(output)
@@ -1511,13 +3259,13 @@ mod tests {
         fn campaign(&mut self, ids: &[NodeID], output: &mut String) -> Result<(), Box<dyn Error>> {
             let campaign = |node| match node {
                 Node::Candidate(mut node) => {
-                    node.campaign()?;
+                    node.campaign(false)?;
                     Ok(node.into())
                 }
-                Node::Follower(node) => Ok(node.into_candidate()?.into()),
+                Node::Follower(node) => Ok(node.into_candidate(false)?.into()),
                 Node::Leader(node) => {
                     let term = node.term();
-                    Ok(node.into_follower(term + 1)?.into_candidate()?.into())
+                    Ok(node.into_follower(term + 1)?.into_candidate(false)?.into())
                 }
             };
             for id in ids.iter().copied() {
@@ -1554,7 +3302,7 @@ mod tests {
                 let Some(Node::Follower(node)) = self.nodes.remove(&id) else {
                     return Err(format!("invalid leader {id}").into());
                 };
-                self.nodes.insert(id, node.into_candidate()?.into_leader()?.into());
+                self.nodes.insert(id, node.into_candidate(false)?.into_leader()?.into());
                 self.receive(id, quiet)?;
                 self.stabilize(&self.ids.clone(), true, quiet)?;
             }
@@ -1631,11 +3379,16 @@ mod tests {
                 let nodefmt = Self::format_node(node);
                 let (last_index, last_term) = node.get_last_index();
                 let (commit_index, commit_term) = node.get_commit_index();
+                let (snapshot_index, snapshot_term) = node.get_snapshot_index();
                 let (term, vote) = node.get_term_vote();
-                writeln!(
+                write!(
                     output,
                     "{nodefmt} term={term} last={last_index}@{last_term} commit={commit_index}@{commit_term} vote={vote:?}",
                 )?;
+                if snapshot_index > 0 {
+                    write!(output, " snapshot={snapshot_index}@{snapshot_term}")?;
+                }
+                output.push('\n');
                 for entry in node.scan_log()? {
                     writeln!(output, "{nodefmt} entry {}", Self::format_entry(&entry))?;
                 }
@@ -1723,6 +3476,34 @@ mod tests {
             self.transition(id, |n| n.step(msg), output)
         }
 
+        /// Executes a client request directly on the given node, bypassing the
+        /// message loop. Only valid for single-node clusters.
+        fn execute(
+            &mut self,
+            id: NodeID,
+            request: Request,
+            output: &mut String,
+        ) -> Result<(), Box<dyn Error>> {
+            let term = self.nodes.get(&id).ok_or(format!("unknown node {id}"))?.term();
+            let mut response = None;
+            self.transition(
+                id,
+                |mut n| {
+                    response = Some(n.execute(request.clone())?);
+                    Ok(n)
+                },
+                output,
+            )?;
+            let response = response.expect("no response");
+            writeln!(
+                output,
+                "c{id}@{term} {} ⇒ {}",
+                Self::format_request(&request),
+                Self::format_response(&response),
+            )?;
+            Ok(())
+        }
+
         /// Restarts the given nodes. If commit_index or applied_index are
         /// given, the log commit index or state machine will regress.
         fn restart(
@@ -1763,6 +3544,9 @@ mod tests {
                     if applied_index > log.get_commit_index().0 {
                         return Err(format!("applied_index={applied_index} beyond commit").into());
                     }
+                    if log.get_snapshot_index().0 > 0 {
+                        return Err("can't reapply truncated log".into());
+                    }
                     state = teststate::KV::new();
                     let mut scan = log.scan(..=applied_index);
                     while let Some(entry) = scan.next().transpose()? {
@@ -1865,7 +3649,9 @@ mod tests {
             // Fetch pre-transition info.
             let old_noderole = Self::format_node_role(&node);
             let (old_commit_index, _) = node.get_commit_index();
-            let mut old_entries = node.scan_log()?.into_iter();
+            let (old_snapshot_index, _) = node.get_snapshot_index();
+            let old_entries: HashMap<Index, Term> =
+                node.scan_log()?.into_iter().map(|e| (e.index, e.term)).collect();
 
             // Apply the transition.
             node = f(node)?;
@@ -1874,10 +3660,14 @@ mod tests {
             let nodefmt = Self::format_node(&node);
             let noderole = Self::format_node_role(&node);
             let (commit_index, commit_term) = node.get_commit_index();
+            let (snapshot_index, snapshot_term) = node.get_snapshot_index();
 
-            let entries = node.scan_log()?.into_iter();
-            let appended: Vec<Entry> = entries
-                .skip_while(|e| Some(e.term) == old_entries.next().map(|e| e.term))
+            // Entries that were truncated or installed via a snapshot aren't
+            // output as appended.
+            let appended: Vec<Entry> = node
+                .scan_log()?
+                .into_iter()
+                .filter(|e| old_entries.get(&e.index) != Some(&e.term))
                 .collect();
 
             self.nodes.insert(id, node);
@@ -1895,6 +3685,9 @@ mod tests {
             for entry in self.applied_rx[&id].try_iter() {
                 writeln!(output, "{nodefmt} apply {}", Self::format_entry(&entry))?
             }
+            if old_snapshot_index != snapshot_index {
+                writeln!(output, "{nodefmt} snapshot {snapshot_index}@{snapshot_term}")?;
+            }
 
             // Receive any outbound messages.
             self.receive(id, output)?;
@@ -1973,8 +3766,8 @@ mod tests {
 
             // Anchor the symmetric partitions at the node with the largest number
             // of disconnects, otherwise the smallest (first) ID.
-            for (id, peers) in &symmetric.clone() {
-                for peer in peers {
+            for (id, peers) in symmetric.clone().iter().sorted_by_key(|(id, _)| **id) {
+                for peer in peers.iter().sorted() {
                     // Recompute the peer set sizes for each iteration, since we
                     // modify the peer set below.
                     let len = symmetric.get(id).map(|p| p.len()).unwrap_or(0);
@@ -2034,12 +3827,19 @@ mod tests {
         /// Formats a message.
         fn format_message(msg: &Message) -> String {
             match msg {
-                Message::Campaign { last_index, last_term } => {
-                    format!("Campaign last={last_index}@{last_term}")
+                Message::Campaign { last_index, last_term, transfer } => {
+                    let transfer = if *transfer { " transfer" } else { "" };
+                    format!("Campaign last={last_index}@{last_term}{transfer}")
                 }
                 Message::CampaignResponse { vote } => {
                     format!("CampaignResponse vote={vote}")
                 }
+                Message::PreVote { last_index, last_term } => {
+                    format!("PreVote last={last_index}@{last_term}")
+                }
+                Message::PreVoteResponse { vote } => {
+                    format!("PreVoteResponse vote={vote}")
+                }
                 Message::Heartbeat { last_index, commit_index, read_seq } => {
                     format!("Heartbeat last_index={last_index} commit_index={commit_index} read_seq={read_seq}")
                 }
@@ -2058,20 +3858,90 @@ mod tests {
                         (_, _) => panic!("match_index and reject_index both set"),
                     }
                 }
+                Message::InstallSnapshot { index, term, data } => {
+                    format!("InstallSnapshot {index}@{term} size={}", data.len())
+                }
                 Message::Read { seq } => {
                     format!("Read seq={seq}")
                 }
                 Message::ReadResponse { seq } => {
                     format!("ReadResponse seq={seq}")
                 }
+                Message::Verify { id, commit_index } => {
+                    let id = hex::encode(id);
+                    format!(
+                        "Verify id=0x{} commit_index={commit_index}",
+                        id.trim_start_matches("00")
+                    )
+                }
+                Message::VerifyResponse { id, digest } => {
+                    format!(
+                        "VerifyResponse id=0x{} {}",
+                        hex::encode(id).trim_start_matches("00"),
+                        match digest {
+                            Ok(digest) =>
+                                format!("index={} terms={:?}", digest.index, digest.terms),
+                            Err(error) => format!("Error::{error:#?}"),
+                        }
+                    )
+                }
+                Message::VerifyState { id, applied_index } => {
+                    format!(
+                        "VerifyState id=0x{} applied_index={applied_index}",
+                        hex::encode(id).trim_start_matches("00")
+                    )
+                }
+                Message::VerifyStateResponse { id, checksums } => {
+                    format!(
+                        "VerifyStateResponse id=0x{} {}",
+                        hex::encode(id).trim_start_matches("00"),
+                        match checksums {
+                            Ok(checksums) => format!("checksums={checksums:x?}"),
+                            Err(error) => format!("Error::{error:#?}"),
+                        }
+                    )
+                }
+                Message::NodeStatus { id } => {
+                    format!("NodeStatus id=0x{}", hex::encode(id).trim_start_matches("00"))
+                }
+                Message::NodeStatusResponse { id, status } => {
+                    format!(
+                        "NodeStatusResponse id=0x{} {}",
+                        hex::encode(id).trim_start_matches("00"),
+                        Self::format_node_status(status)
+                    )
+                }
+                Message::TimeoutNow => "TimeoutNow".to_string(),
                 Message::ClientRequest { id, request } => {
                     format!(
                         "ClientRequest id=0x{} {}",
                         hex::encode(id).trim_start_matches("00"),
                         match request {
                             Request::Read(v) => format!("read 0x{}", hex::encode(v)),
+                            Request::ReadStale { command, max_staleness: None } => {
+                                format!("read_stale 0x{}", hex::encode(command))
+                            }
+                            Request::ReadStale { command, max_staleness: Some(max) } => {
+                                format!("read_stale 0x{} max_staleness={max}", hex::encode(command))
+                            }
                             Request::Write(v) => format!("write 0x{}", hex::encode(v)),
                             Request::Status => "status".to_string(),
+                            Request::Verify => "verify".to_string(),
+                            Request::VerifyState => "verify_state".to_string(),
+                            Request::Trace => "trace".to_string(),
+                            Request::Nodes => "nodes".to_string(),
+                            Request::TransferLeadership(None) => {
+                                "transfer_leadership".to_string()
+                            }
+                            Request::TransferLeadership(Some(to)) => {
+                                format!("transfer_leadership to={to}")
+                            }
+                            Request::Health => "health".to_string(),
+                            Request::Compact { garbage_min_fraction, garbage_min_bytes } => {
+                                format!("compact {garbage_min_fraction} {garbage_min_bytes}")
+                            }
+                            Request::Backup { path } => format!("backup {path}"),
+                            Request::Metrics => "metrics".to_string(),
                         }
                     )
                 }
@@ -2083,6 +3953,23 @@ mod tests {
                             Ok(Response::Read(v)) => format!("read 0x{}", hex::encode(v)),
                             Ok(Response::Write(v)) => format!("write 0x{}", hex::encode(v)),
                             Ok(Response::Status(v)) => format!("status {v:?}"),
+                            Ok(Response::Verify(v)) => format!("verify {v:?}"),
+                            Ok(Response::VerifyState(v)) => format!("verify_state {v:?}"),
+                            Ok(Response::Trace(v)) => format!("trace {} events", v.len()),
+                            Ok(Response::Nodes(v)) => format!("nodes {:?}", v.keys()),
+                            Ok(Response::TransferLeadership(v)) => {
+                                format!("transfer_leadership {v:?}")
+                            }
+                            Ok(Response::Health(v)) => {
+                                format!("health {}", Self::format_node_status(v))
+                            }
+                            Ok(Response::Compact { log, state }) => {
+                                format!("compact log={log} state={state}")
+                            }
+                            Ok(Response::Backup { applied_index }) => {
+                                format!("backup applied_index={applied_index}")
+                            }
+                            Ok(Response::Metrics(_)) => "metrics".to_string(),
                             Err(error) => format!("Error::{error:#?}"),
                         }
                     )
@@ -2101,7 +3988,7 @@ mod tests {
                 Node::Candidate(_) => "candidate".to_string(),
                 Node::Follower(node) => {
                     let leader = node.role.leader.map(|id| format!("n{id}")).unwrap_or_default();
-                    format!("follower({leader})")
+                    format!("{}({leader})", node.role_name())
                 }
                 Node::Leader(_) => "leader".to_string(),
             };
@@ -2111,8 +3998,22 @@ mod tests {
         /// Formats a request.
         fn format_request(request: &Request) -> String {
             match request {
-                Request::Read(c) | Request::Write(c) => KVCommand::decode(c).unwrap().to_string(),
+                Request::Read(c) | Request::ReadStale { command: c, .. } | Request::Write(c) => {
+                    KVCommand::decode(c).unwrap().to_string()
+                }
                 Request::Status => "status".to_string(),
+                Request::Verify => "verify".to_string(),
+                Request::VerifyState => "verify state".to_string(),
+                Request::Trace => "trace".to_string(),
+                Request::Nodes => "nodes".to_string(),
+                Request::TransferLeadership(None) => "transfer leadership".to_string(),
+                Request::TransferLeadership(Some(to)) => format!("transfer leadership to n{to}"),
+                Request::Health => "health".to_string(),
+                Request::Compact { garbage_min_fraction, garbage_min_bytes } => {
+                    format!("compact {garbage_min_fraction} {garbage_min_bytes}")
+                }
+                Request::Backup { path } => format!("backup {path}"),
+                Request::Metrics => "metrics".to_string(),
             }
         }
 
@@ -2123,10 +4024,78 @@ mod tests {
                     KVResponse::decode(r).unwrap().to_string()
                 }
                 Ok(Response::Status(status)) => format!("{status:#?}"),
+                Ok(Response::Verify(report)) => format!("{report:#?}"),
+                Ok(Response::VerifyState(report)) => format!("{report:#?}"),
+                Ok(Response::Trace(events)) => events.iter().join("\n"),
+                Ok(Response::Nodes(nodes)) => nodes
+                    .iter()
+                    .map(|(id, status)| match status {
+                        Some(status) => format!("n{id}: {}", Self::format_node_status(status)),
+                        None => format!("n{id}: unreachable"),
+                    })
+                    .join("\n"),
+                Ok(Response::TransferLeadership(Some(id))) => format!("n{id}"),
+                Ok(Response::TransferLeadership(None)) => "none".to_string(),
+                Ok(Response::Health(status)) => Self::format_node_status(status),
+                Ok(Response::Compact { log, state }) => format!("log={log} state={state}"),
+                Ok(Response::Backup { applied_index }) => format!("applied_index={applied_index}"),
+                Ok(Response::Metrics(metrics)) => Self::format_metrics(metrics),
                 Err(error) => format!("Error::{error:?} ({error})"),
             }
         }
 
+        /// Formats a node status. Omits the version, which changes with releases.
+        fn format_node_status(status: &NodeStatus) -> String {
+            let leader = status.leader.map(|id| format!("n{id}")).unwrap_or_default();
+            format!(
+                "{role}({leader}) term={term} last={last} commit={commit} applied={applied} storage={storage}",
+                role = status.role,
+                term = status.term,
+                last = status.last_index,
+                commit = status.commit_index,
+                applied = status.applied_index,
+                storage = status.storage.name,
+            )
+        }
+
+        /// Formats node metrics, one line per section.
+        fn format_metrics(metrics: &Metrics) -> String {
+            let leader = metrics.leader.map(|id| format!("n{id}")).unwrap_or_default();
+            let counters = &metrics.counters;
+            let mut lines = vec![format!(
+                "n{id} {role}({leader}) term={term} last={last} commit={commit} applied={applied}",
+                id = metrics.id,
+                role = metrics.role,
+                term = metrics.term,
+                last = metrics.last_index,
+                commit = metrics.commit_index,
+                applied = metrics.applied_index,
+            )];
+            for (id, pr) in &metrics.progress {
+                lines.push(format!(
+                    "progress n{id}: match={} next={} lag={} inflight={}/{}b",
+                    pr.match_index,
+                    pr.next_index,
+                    pr.lag(metrics.last_index),
+                    pr.inflight_appends,
+                    pr.inflight_bytes,
+                ));
+            }
+            lines.push(format!(
+                "elections: pre_votes={} elections={} won={} leader_changes={}",
+                counters.pre_votes,
+                counters.elections,
+                counters.elections_won,
+                counters.leader_changes,
+            ));
+            let format_counts = |counts: &BTreeMap<String, u64>| {
+                counts.iter().map(|(kind, count)| format!("{kind}={count}")).join(" ")
+            };
+            lines.push(format!("sent: {}", format_counts(&counters.messages_sent)));
+            lines.push(format!("received: {}", format_counts(&counters.messages_received)));
+            lines.join("\n")
+        }
+
         /// Strike-through formats the given string using a Unicode combining stroke.
         fn format_strikethrough(s: &str) -> String {
             s.chars().flat_map(|c| [c, '\u{0336}']).collect()
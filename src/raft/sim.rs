@@ -0,0 +1,741 @@
+//! Deterministic simulation of a Raft cluster, for testing. Only built for
+//! tests or with the `sim` feature.
+//!
+//! The simulation runs a cluster of Raft nodes in a single thread, on a virtual
+//! clock measured in ticks. Messages are passed via an in-memory network that
+//! delivers them after a random delay, and faults are injected at random:
+//! network partitions, node crashes (and later restarts), and clock skew
+//! (nodes randomly missing ticks). A simulated client submits random reads and
+//! writes to random nodes. All randomness comes from a single seeded RNG, so a
+//! given seed always produces the same execution, and a failing seed can be
+//! replayed to debug it.
+//!
+//! Each node runs an MVCC-based key/value state machine, and the simulation
+//! checks the following invariants as it runs:
+//!
+//! * Election safety: there is at most one leader per term.
+//! * State machine safety: all nodes apply the same entry at a given index,
+//!   in order.
+//! * Replica consistency: nodes at the same applied index have identical MVCC
+//!   states, including the MVCC version.
+//! * Linearizability of reads: a read never returns a value older than the
+//!   last write to the key acknowledged before the read was submitted, nor a
+//!   value that was never written.
+//!
+//! At the end of the run, faults are healed and the cluster is left to
+//! stabilize, after which all nodes must have converged on the same state.
+//!
+//! For determinism, the election timeout range must contain a single value,
+//! since nodes pick random timeouts internally. Clock skew breaks election
+//! ties instead.
+
+use super::{Entry, Envelope, Index, Log, Message, Node, NodeID, Request, RequestID, Response};
+use super::{State, Term};
+use crate::encoding::{self, bincode, Value as _};
+use crate::error::{Error, Result};
+use crate::storage::{self, mvcc};
+use crate::{errdata, errinput};
+
+use crossbeam::channel::{Receiver, Sender};
+use rand::rngs::StdRng;
+use rand::{Rng as _, SeedableRng as _};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::ops::RangeInclusive;
+
+/// Simulation options.
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// The RNG seed.
+    pub seed: u64,
+    /// The number of nodes.
+    pub nodes: u8,
+    /// The number of ticks to run with fault injection.
+    pub ticks: u64,
+    /// The number of ticks to let the cluster stabilize after healing faults.
+    pub stabilize_ticks: u64,
+    /// Raft node options. The election timeout range must be a single value.
+    pub raft: super::Options,
+    /// The message delivery delay range, in ticks. 0 delivers the message in
+    /// the same tick.
+    pub delay: RangeInclusive<u64>,
+    /// The probability that a node misses a tick, simulating clock skew.
+    pub skew: f64,
+    /// The probability of submitting a client request in a tick.
+    pub request_probability: f64,
+    /// The fraction of client requests that are reads, the rest are writes.
+    pub read_fraction: f64,
+    /// The number of distinct keys to read and write.
+    pub keys: u64,
+    /// The probability of partitioning the network in a tick, if it isn't
+    /// already partitioned.
+    pub partition_probability: f64,
+    /// The probability of crashing a node in a tick. At most a minority of
+    /// nodes are crashed at a time.
+    pub crash_probability: f64,
+    /// The duration of partitions and crashes, in ticks.
+    pub fault_duration: RangeInclusive<u64>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            nodes: 3,
+            ticks: 1000,
+            stabilize_ticks: 200,
+            raft: super::Options { election_timeout_range: 10..11, ..Default::default() },
+            delay: 0..=2,
+            skew: 0.1,
+            request_probability: 0.5,
+            read_fraction: 0.2,
+            keys: 5,
+            partition_probability: 0.01,
+            crash_probability: 0.005,
+            fault_duration: 10..=50,
+        }
+    }
+}
+
+/// Simulation statistics. Identical for identical options.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Stats {
+    /// The number of ticks run.
+    pub ticks: u64,
+    /// The number of messages delivered between nodes.
+    pub delivered: u64,
+    /// The number of messages dropped due to partitions or crashes.
+    pub dropped: u64,
+    /// The number of client requests submitted.
+    pub requests: u64,
+    /// The number of successful writes.
+    pub writes: u64,
+    /// The number of successful reads.
+    pub reads: u64,
+    /// The number of requests that errored, e.g. due to leader changes.
+    pub errors: u64,
+    /// The number of requests lost due to crashes.
+    pub lost: u64,
+    /// The number of node crashes.
+    pub crashes: u64,
+    /// The number of network partitions.
+    pub partitions: u64,
+    /// The final Raft term.
+    pub term: Term,
+    /// The final applied index.
+    pub applied_index: Index,
+}
+
+/// A simulated Raft cluster. See the module documentation for details.
+pub struct Simulation {
+    /// Simulation options.
+    opts: Options,
+    /// The seeded RNG, used for all random decisions.
+    rng: StdRng,
+    /// The current time, in ticks.
+    now: u64,
+    /// Live nodes.
+    nodes: BTreeMap<NodeID, Node>,
+    /// Outbound message queues from live nodes.
+    nodes_rx: BTreeMap<NodeID, Receiver<Envelope>>,
+    /// Crashed nodes, with their durable log and state, and restart time.
+    crashed: BTreeMap<NodeID, (Log, Box<dyn State>, u64)>,
+    /// Each node's MVCC store, shared with its state machine for inspection.
+    stores: BTreeMap<NodeID, mvcc::MVCC<storage::Memory>>,
+    /// The current partition, if any: one side of it, and the heal time.
+    partition: Option<(BTreeSet<NodeID>, u64)>,
+    /// In-flight messages, keyed by delivery time and sequence number.
+    network: BTreeMap<(u64, u64), Envelope>,
+    /// The sequence number of the next in-flight message.
+    next_seq: u64,
+    /// Applied entries from all state machines, with the node ID.
+    applied_rx: Receiver<(NodeID, Entry)>,
+    /// The sender for applied entries, cloned into each state machine.
+    applied_tx: Sender<(NodeID, Entry)>,
+    /// The last applied index of each node, as seen via applied_rx.
+    applied: BTreeMap<NodeID, Index>,
+    /// The latest MVCC snapshot of each node, with the applied index it was
+    /// taken at. Only retaken when the node has applied entries since, as
+    /// scanning every store on every tick is expensive.
+    snapshots: BTreeMap<NodeID, (Index, Snapshot)>,
+    /// All applied entries, by index.
+    history: BTreeMap<Index, Entry>,
+    /// The leader of each term.
+    leaders: BTreeMap<Term, NodeID>,
+    /// Pending client requests, with the receiving node.
+    requests: BTreeMap<RequestID, (NodeID, Pending)>,
+    /// The number of the next client request.
+    next_request: u64,
+    /// The log index of each written value, once applied.
+    writes: BTreeMap<u64, Index>,
+    /// The highest log index of an acknowledged write to each key.
+    acked: BTreeMap<u64, Index>,
+    /// Statistics.
+    stats: Stats,
+}
+
+/// A pending client request.
+enum Pending {
+    /// A write of a unique value to a key.
+    Write { key: u64, value: u64 },
+    /// A read of a key, which must see at least the given write index.
+    Read { key: u64, min_index: Index },
+}
+
+impl Simulation {
+    /// Creates a new simulated cluster.
+    pub fn new(opts: Options) -> Result<Self> {
+        opts.raft.validate()?;
+        if opts.raft.election_timeout_range.len() != 1 {
+            return errinput!("simulation election timeout range must contain a single value");
+        }
+        if opts.nodes == 0 {
+            return errinput!("simulation needs at least one node");
+        }
+        if opts.keys == 0 {
+            return errinput!("simulation needs at least one key");
+        }
+        let (applied_tx, applied_rx) = crossbeam::channel::unbounded();
+        let mut sim = Self {
+            rng: StdRng::seed_from_u64(opts.seed),
+            opts,
+            now: 0,
+            nodes: BTreeMap::new(),
+            nodes_rx: BTreeMap::new(),
+            crashed: BTreeMap::new(),
+            stores: BTreeMap::new(),
+            partition: None,
+            network: BTreeMap::new(),
+            next_seq: 0,
+            applied_rx,
+            applied_tx,
+            applied: BTreeMap::new(),
+            snapshots: BTreeMap::new(),
+            history: BTreeMap::new(),
+            leaders: BTreeMap::new(),
+            requests: BTreeMap::new(),
+            next_request: 1,
+            writes: BTreeMap::new(),
+            acked: BTreeMap::new(),
+            stats: Stats::default(),
+        };
+        for id in 1..=sim.opts.nodes {
+            let log = Log::new(Box::new(storage::Memory::new()))?;
+            let store = mvcc::MVCC::new(storage::Memory::new());
+            let state = KV::new(id, store.clone(), &sim.applied_tx);
+            sim.stores.insert(id, store);
+            sim.applied.insert(id, 0);
+            sim.start(id, log, state)?;
+        }
+        Ok(sim)
+    }
+
+    /// Runs the simulation: first with fault injection for the configured
+    /// number of ticks, then without faults to let the cluster stabilize.
+    /// Errors if an invariant is violated, or if the cluster doesn't converge.
+    pub fn run(&mut self) -> Result<Stats> {
+        while self.now < self.opts.ticks {
+            self.tick(true).map_err(|err| self.annotate(err))?;
+        }
+        self.heal()?;
+        for _ in 0..self.opts.stabilize_ticks {
+            self.tick(false).map_err(|err| self.annotate(err))?;
+        }
+        self.check_converged().map_err(|err| self.annotate(err))?;
+        Ok(self.stats())
+    }
+
+    /// Returns the current statistics.
+    pub fn stats(&self) -> Stats {
+        let mut stats = self.stats.clone();
+        stats.ticks = self.now;
+        stats.term = self.nodes.values().map(|n| n.term()).max().unwrap_or_default();
+        stats.applied_index = self.history.keys().last().copied().unwrap_or_default();
+        stats
+    }
+
+    /// Advances the simulation by a single tick. Faults and client requests
+    /// are only injected if active is true.
+    pub fn tick(&mut self, active: bool) -> Result<()> {
+        self.now += 1;
+
+        // Heal faults that have expired, and maybe inject new ones.
+        if self.partition.as_ref().is_some_and(|(_, heal)| *heal <= self.now) {
+            self.partition = None;
+        }
+        let restart = self.crashed.iter().filter(|(_, (_, _, at))| *at <= self.now);
+        for id in restart.map(|(id, _)| *id).collect::<Vec<_>>() {
+            let (log, state, _) = self.crashed.remove(&id).expect("crashed node");
+            self.start(id, log, state)?;
+        }
+        if active {
+            self.inject_faults()?;
+            if self.rng.gen_bool(self.opts.request_probability) {
+                self.submit()?;
+            }
+        }
+
+        // Deliver due messages, in delivery order.
+        while let Some(entry) = self.network.first_entry() {
+            if entry.key().0 > self.now {
+                break;
+            }
+            let msg = entry.remove();
+            if !self.nodes.contains_key(&msg.to) || self.is_partitioned(msg.from, msg.to) {
+                self.stats.dropped += 1;
+                continue;
+            }
+            self.stats.delivered += 1;
+            self.transition(msg.to, |node| node.step(msg))?;
+        }
+
+        // Tick the nodes, unless they miss the tick due to clock skew.
+        for id in self.nodes.keys().copied().collect::<Vec<_>>() {
+            if !self.rng.gen_bool(self.opts.skew) {
+                self.transition(id, |node| node.tick())?;
+            }
+        }
+
+        self.check_replicas()
+    }
+
+    /// Heals all faults: restarts crashed nodes and removes partitions.
+    fn heal(&mut self) -> Result<()> {
+        self.partition = None;
+        for (id, (log, state, _)) in std::mem::take(&mut self.crashed) {
+            self.start(id, log, state)?;
+        }
+        Ok(())
+    }
+
+    /// Randomly injects partitions and crashes.
+    fn inject_faults(&mut self) -> Result<()> {
+        if self.partition.is_none() && self.rng.gen_bool(self.opts.partition_probability) {
+            let side: BTreeSet<NodeID> =
+                (1..=self.opts.nodes).filter(|_| self.rng.gen_bool(0.5)).collect();
+            if !side.is_empty() && side.len() < self.opts.nodes as usize {
+                let heal = self.now + self.rng.gen_range(self.opts.fault_duration.clone());
+                self.partition = Some((side, heal));
+                self.stats.partitions += 1;
+            }
+        }
+
+        let max_crashed = (self.opts.nodes as usize - 1) / 2;
+        if self.crashed.len() < max_crashed && self.rng.gen_bool(self.opts.crash_probability) {
+            let ids = self.nodes.keys().copied().collect::<Vec<_>>();
+            let id = ids[self.rng.gen_range(0..ids.len())];
+            let node = self.nodes.remove(&id).expect("live node");
+            self.nodes_rx.remove(&id);
+            let (log, state) = node.dismantle();
+            let restart = self.now + self.rng.gen_range(self.opts.fault_duration.clone());
+            self.crashed.insert(id, (log, state, restart));
+            self.stats.crashes += 1;
+
+            // Requests submitted to the node are lost.
+            let lost = self.requests.iter().filter(|(_, (to, _))| *to == id);
+            for request_id in lost.map(|(request_id, _)| *request_id).collect::<Vec<_>>() {
+                self.requests.remove(&request_id);
+                self.stats.lost += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Submits a random client request to a random live node.
+    fn submit(&mut self) -> Result<()> {
+        let ids = self.nodes.keys().copied().collect::<Vec<_>>();
+        let Some(id) = ids.get(self.rng.gen_range(0..ids.len().max(1))).copied() else {
+            return Ok(());
+        };
+        let key = self.rng.gen_range(0..self.opts.keys);
+        let (request, pending) = if self.rng.gen_bool(self.opts.read_fraction) {
+            let min_index = self.acked.get(&key).copied().unwrap_or_default();
+            (Request::Read(Command::Get { key }.encode()), Pending::Read { key, min_index })
+        } else {
+            // Use the request number as a unique value.
+            let value = self.next_request;
+            (Request::Write(Command::Put { key, value }.encode()), Pending::Write { key, value })
+        };
+        let request_id = uuid::Uuid::from_u64_pair(0, self.next_request);
+        self.next_request += 1;
+        self.requests.insert(request_id, (id, pending));
+        self.stats.requests += 1;
+
+        let node = self.nodes.get(&id).expect("live node");
+        let message = Message::ClientRequest { id: request_id, request };
+        let msg = Envelope { from: id, to: id, term: node.term(), message };
+        self.transition(id, |node| node.step(msg))
+    }
+
+    /// Starts a node with the given log and state.
+    fn start(&mut self, id: NodeID, log: Log, state: Box<dyn State>) -> Result<()> {
+        let peers: HashSet<NodeID> = (1..=self.opts.nodes).filter(|p| *p != id).collect();
+        let (tx, rx) = crossbeam::channel::unbounded();
+        let node = Node::new(id, peers, log, state, tx, self.opts.raft.clone())?;
+        self.nodes.insert(id, node);
+        self.nodes_rx.insert(id, rx);
+        self.observe(id)
+    }
+
+    /// Transitions a live node, and processes its output.
+    fn transition(&mut self, id: NodeID, f: impl FnOnce(Node) -> Result<Node>) -> Result<()> {
+        let node = self.nodes.remove(&id).expect("live node");
+        self.nodes.insert(id, f(node)?);
+        self.observe(id)
+    }
+
+    /// Processes a node's applied entries and outbound messages, and checks
+    /// its leadership.
+    fn observe(&mut self, id: NodeID) -> Result<()> {
+        // Check election safety.
+        if let Some(Node::Leader(_)) = self.nodes.get(&id) {
+            let term = self.nodes[&id].term();
+            match self.leaders.get(&term) {
+                Some(leader) if *leader != id => {
+                    return errdata!("nodes {leader} and {id} both leaders in term {term}");
+                }
+                Some(_) => {}
+                None => _ = self.leaders.insert(term, id),
+            }
+        }
+
+        // Check state machine safety, and record the index of written values.
+        while let Ok((id, entry)) = self.applied_rx.try_recv() {
+            let applied = self.applied.get_mut(&id).expect("unknown node");
+            if entry.index != *applied + 1 {
+                return errdata!("node {id} applied {} after {applied}", entry.index);
+            }
+            *applied = entry.index;
+            match self.history.get(&entry.index) {
+                Some(existing) if existing != &entry => {
+                    return errdata!("node {id} applied {entry:?}, others applied {existing:?}");
+                }
+                Some(_) => {}
+                None => {
+                    if let Some(Command::Put { value, .. }) =
+                        entry.command.as_deref().map(Command::decode).transpose()?
+                    {
+                        self.writes.insert(value, entry.index);
+                    }
+                    self.history.insert(entry.index, entry);
+                }
+            }
+        }
+
+        // Process outbound messages. Sort them by recipient for determinism,
+        // in case the node emits them in hashmap order.
+        let Some(rx) = self.nodes_rx.get(&id) else {
+            return Ok(());
+        };
+        let mut msgs = rx.try_iter().collect::<Vec<_>>();
+        msgs.sort_by_key(|msg| msg.to);
+        for msg in msgs {
+            if msg.from == msg.to {
+                let Message::ClientResponse { id, response } = msg.message else {
+                    return errdata!("invalid self-addressed message {msg:?}");
+                };
+                self.respond(id, response)?;
+                continue;
+            }
+            let delay = self.rng.gen_range(self.opts.delay.clone());
+            self.network.insert((self.now + delay, self.next_seq), msg);
+            self.next_seq += 1;
+        }
+        Ok(())
+    }
+
+    /// Processes a client response, checking read linearizability.
+    fn respond(&mut self, id: RequestID, response: Result<Response>) -> Result<()> {
+        let Some((_, pending)) = self.requests.remove(&id) else {
+            return errdata!("response to unknown request {id}");
+        };
+        match (pending, response) {
+            (Pending::Write { key, value }, Ok(Response::Write(_))) => {
+                let Some(index) = self.writes.get(&value).copied() else {
+                    return errdata!("acknowledged write {key}={value} not applied");
+                };
+                let acked = self.acked.entry(key).or_default();
+                *acked = index.max(*acked);
+                self.stats.writes += 1;
+            }
+            (Pending::Read { key, min_index }, Ok(Response::Read(result))) => {
+                let value: Option<u64> = bincode::deserialize(&result)?;
+                let index = match value {
+                    Some(value) => match self.writes.get(&value) {
+                        Some(index) => *index,
+                        None => return errdata!("read {key} returned unwritten value {value}"),
+                    },
+                    None => 0,
+                };
+                if index < min_index {
+                    return errdata!(
+                        "stale read of {key}: saw write at {index}, acknowledged at {min_index}"
+                    );
+                }
+                self.stats.reads += 1;
+            }
+            (_, Err(_)) => self.stats.errors += 1,
+            (_, Ok(response)) => return errdata!("unexpected response {response:?}"),
+        }
+        Ok(())
+    }
+
+    /// Checks that nodes at the same applied index have identical states.
+    /// Nodes that are alone at their applied index aren't snapshotted.
+    fn check_replicas(&mut self) -> Result<()> {
+        let mut by_index: BTreeMap<Index, Vec<NodeID>> = BTreeMap::new();
+        for (id, index) in &self.applied {
+            by_index.entry(*index).or_default().push(*id);
+        }
+        for (index, ids) in by_index.into_iter().filter(|(_, ids)| ids.len() > 1) {
+            for id in &ids {
+                if self.snapshots.get(id).is_none_or(|(taken, _)| *taken != index) {
+                    self.snapshots.insert(*id, (index, Snapshot::new(&self.stores[id])?));
+                }
+            }
+            let (first, rest) = ids.split_first().expect("no nodes");
+            let (_, expect) = &self.snapshots[first];
+            for id in rest {
+                let (_, snapshot) = &self.snapshots[id];
+                if snapshot != expect {
+                    return errdata!(
+                        "nodes {first} and {id} diverged at applied index {index}: \
+                        {expect:?} vs {snapshot:?}"
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that all nodes have converged on the same applied state, and
+    /// that there is a leader.
+    fn check_converged(&mut self) -> Result<()> {
+        let indexes: BTreeSet<Index> = self.applied.values().copied().collect();
+        if indexes.len() != 1 {
+            return errdata!("nodes didn't converge, applied indexes {:?}", self.applied);
+        }
+        if !self.nodes.values().any(|n| matches!(n, Node::Leader(_))) {
+            return errdata!("no leader after stabilizing");
+        }
+        self.check_replicas()
+    }
+
+    /// Returns true if the nodes are on different sides of a partition.
+    fn is_partitioned(&self, a: NodeID, b: NodeID) -> bool {
+        self.partition.as_ref().is_some_and(|(side, _)| side.contains(&a) != side.contains(&b))
+    }
+
+    /// Annotates an invariant violation with the seed and time, for replay.
+    fn annotate(&self, err: Error) -> Error {
+        match err {
+            Error::InvalidData(msg) => {
+                Error::InvalidData(format!("seed {} tick {}: {msg}", self.opts.seed, self.now))
+            }
+            err => err,
+        }
+    }
+}
+
+/// A key/value state machine command.
+#[derive(Debug, Serialize, Deserialize)]
+enum Command {
+    /// Writes a value to a key, returning the MVCC version.
+    Put { key: u64, value: u64 },
+    /// Reads a key's value, if any.
+    Get { key: u64 },
+}
+
+impl encoding::Value for Command {}
+
+/// A key/value state machine, storing keys in MVCC. Applied entries are sent
+/// to the simulation via the given channel.
+struct KV {
+    /// The node ID.
+    id: NodeID,
+    /// The MVCC store.
+    mvcc: mvcc::MVCC<storage::Memory>,
+    /// The last applied index. The state survives simulated crashes, so this
+    /// doesn't need to be stored.
+    applied_index: Index,
+    /// Emits applied entries.
+    applied_tx: Sender<(NodeID, Entry)>,
+}
+
+impl KV {
+    fn new(
+        id: NodeID,
+        mvcc: mvcc::MVCC<storage::Memory>,
+        applied_tx: &Sender<(NodeID, Entry)>,
+    ) -> Box<Self> {
+        Box::new(Self { id, mvcc, applied_index: 0, applied_tx: applied_tx.clone() })
+    }
+}
+
+impl State for KV {
+    fn get_applied_index(&self) -> Index {
+        self.applied_index
+    }
+
+    fn apply(&mut self, entry: Entry) -> Result<Vec<u8>> {
+        let result = match entry.command.as_deref().map(Command::decode).transpose()? {
+            Some(Command::Put { key, value }) => {
+                let txn = self.mvcc.begin()?;
+                let version = txn.version();
+                txn.set(&key.to_be_bytes(), bincode::serialize(&value))?;
+                txn.commit()?;
+                bincode::serialize(&version)
+            }
+            Some(command) => return errdata!("invalid write command {command:?}"),
+            None => Vec::new(),
+        };
+        self.applied_index = entry.index;
+        _ = self.applied_tx.send((self.id, entry));
+        Ok(result)
+    }
+
+    fn read(&self, command: Vec<u8>) -> Result<Vec<u8>> {
+        match Command::decode(&command)? {
+            Command::Get { key } => {
+                let txn = self.mvcc.begin_read_only()?;
+                let value: Option<u64> =
+                    txn.get(&key.to_be_bytes())?.map(|v| bincode::deserialize(&v)).transpose()?;
+                Ok(bincode::serialize(&value))
+            }
+            command => errdata!("invalid read command {command:?}"),
+        }
+    }
+}
+
+/// A snapshot of a node's MVCC state: the latest version and key/values.
+#[derive(Debug, PartialEq)]
+struct Snapshot {
+    versions: u64,
+    data: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl Snapshot {
+    fn new(mvcc: &mvcc::MVCC<storage::Memory>) -> Result<Self> {
+        let versions = mvcc.status()?.versions;
+        let txn = mvcc.begin_read_only()?;
+        let data = txn.scan(..).collect::<Result<_>>()?;
+        Ok(Self { versions, data })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The seeds to simulate in regular test runs. simulate_sweep() runs a
+    /// larger sweep, which is too slow to run by default.
+    const SEEDS: std::ops::Range<u64> = 0..2;
+
+    /// The seeds to simulate in simulate_sweep().
+    const SWEEP_SEEDS: std::ops::Range<u64> = 0..50;
+
+    /// Runs a simulation with the given options, asserting that it wrote.
+    fn run(opts: Options) -> Result<()> {
+        let seed = opts.seed;
+        let stats = Simulation::new(opts)?.run()?;
+        assert!(stats.writes > 0, "no writes for seed {seed}: {stats:?}");
+        Ok(())
+    }
+
+    /// Runs simulations with fault injection across cluster sizes and
+    /// election configurations for the given seed.
+    fn simulate_seed(seed: u64) -> Result<()> {
+        for nodes in [1, 3, 5] {
+            for (pre_vote, lease_duration) in [(false, None), (true, None), (true, Some(5))] {
+                let mut opts = Options { seed, nodes, ..Default::default() };
+                opts.raft.pre_vote = pre_vote;
+                opts.raft.lease_duration = lease_duration;
+                run(opts)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs simulations where some nodes are learners.
+    fn simulate_learners_seed(seed: u64) -> Result<()> {
+        let mut opts = Options { seed, nodes: 5, ..Default::default() };
+        opts.raft.learners = HashSet::from([4, 5]);
+        run(opts)
+    }
+
+    /// Runs simulations with small in-flight append windows.
+    fn simulate_inflight_seed(seed: u64) -> Result<()> {
+        let mut opts = Options { seed, nodes: 3, ..Default::default() };
+        opts.raft.max_inflight_appends = Some(2);
+        opts.raft.max_inflight_bytes = Some(64);
+        run(opts)
+    }
+
+    /// Runs simulations with check-quorum, where partitioned leaders step
+    /// down.
+    fn simulate_check_quorum_seed(seed: u64) -> Result<()> {
+        for nodes in [3, 5] {
+            let mut opts = Options { seed, nodes, ..Default::default() };
+            opts.raft.pre_vote = true;
+            opts.raft.check_quorum = true;
+            run(opts)?;
+        }
+        Ok(())
+    }
+
+    /// Runs simulations with fault injection across a few seeds, cluster
+    /// sizes, and election configurations, checking invariants.
+    #[test]
+    fn simulate() -> Result<()> {
+        SEEDS.into_iter().try_for_each(simulate_seed)
+    }
+
+    #[test]
+    fn simulate_learners() -> Result<()> {
+        SEEDS.into_iter().try_for_each(simulate_learners_seed)
+    }
+
+    #[test]
+    fn simulate_inflight() -> Result<()> {
+        SEEDS.into_iter().try_for_each(simulate_inflight_seed)
+    }
+
+    #[test]
+    fn simulate_check_quorum() -> Result<()> {
+        SEEDS.into_iter().try_for_each(simulate_check_quorum_seed)
+    }
+
+    /// Runs all of the above simulations across a large range of seeds. Slow,
+    /// so it's ignored by default. Run it with:
+    ///
+    /// cargo test --release --lib raft::sim -- --ignored
+    #[test]
+    #[ignore = "slow, run explicitly"]
+    fn simulate_sweep() -> Result<()> {
+        for seed in SWEEP_SEEDS {
+            simulate_seed(seed)?;
+            simulate_learners_seed(seed)?;
+            simulate_inflight_seed(seed)?;
+            simulate_check_quorum_seed(seed)?;
+        }
+        Ok(())
+    }
+
+    /// The same seed yields the same execution.
+    #[test]
+    fn deterministic() -> Result<()> {
+        let opts = Options { seed: 7, nodes: 5, ..Default::default() };
+        let stats = Simulation::new(opts.clone())?.run()?;
+        assert_eq!(Simulation::new(opts)?.run()?, stats);
+        assert!(stats.crashes > 0 && stats.partitions > 0, "{stats:?}");
+        Ok(())
+    }
+
+    /// Invalid options error.
+    #[test]
+    fn invalid_options() {
+        let raft = super::super::Options { election_timeout_range: 10..20, ..Default::default() };
+        assert!(Simulation::new(Options { raft, ..Default::default() }).is_err());
+        assert!(Simulation::new(Options { nodes: 0, ..Default::default() }).is_err());
+    }
+}
@@ -1,6 +1,9 @@
 use super::{Entry, Index};
+use crate::errinput;
 use crate::error::Result;
 
+use std::collections::BTreeMap;
+
 /// A Raft-managed state machine. Raft itself does not care what the state
 /// machine is, nor what the commands and results do -- it will simply apply
 /// arbitrary binary commands sequentially from the Raft log, returning an
@@ -48,18 +51,74 @@ pub trait State: Send {
     /// This is only executed on a single replica/node, so it must not result in
     /// any state changes (i.e. it must not write).
     fn read(&self, command: Vec<u8>) -> Result<Vec<u8>>;
+
+    /// Returns checksums of the state machine contents at the applied index,
+    /// keyed by partition (e.g. SQL table). These are compared across replicas
+    /// to detect divergence (see `Request::VerifyState`), so they must be
+    /// identical on all replicas at the same applied index. Defaults to no
+    /// checksums, i.e. nothing to verify.
+    fn checksums(&self) -> Result<BTreeMap<String, u64>> {
+        Ok(BTreeMap::new())
+    }
+
+    /// Flushes applied state to durable storage. Applied entries aren't
+    /// usually flushed, since Raft can reapply them from the log after a
+    /// crash, but this avoids the replay after a clean shutdown. Defaults to a
+    /// noop, e.g. for in-memory state machines.
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Compacts the state machine's storage if its garbage exceeds the given
+    /// fraction of the total size and the given number of bytes, see
+    /// storage::Engine::maybe_compact(). Returns true if it was compacted.
+    /// This is only done on the local node, and must not change the state.
+    /// Defaults to a noop, e.g. for in-memory state machines.
+    fn maybe_compact(
+        &mut self,
+        _garbage_min_fraction: f64,
+        _garbage_min_bytes: u64,
+    ) -> Result<bool> {
+        Ok(false)
+    }
+
+    /// Backs up the state machine's storage to a file at dest, which can be
+    /// restored via storage::Engine::restore() before the node is started.
+    /// The backup must be consistent with the applied index. This is only done
+    /// on the local node. Defaults to an error, e.g. for in-memory state
+    /// machines that don't use a storage engine.
+    fn backup(&mut self, _dest: &std::path::Path) -> Result<()> {
+        errinput!("state machine does not support backups")
+    }
+
+    /// Returns a snapshot of the state machine at the applied index, which can
+    /// be restored on a different replica via restore(). Used to catch up
+    /// followers that lag behind the leader's truncated log. Defaults to an
+    /// error, in which case the log must not be truncated (see
+    /// `Options::snapshot_interval`).
+    fn snapshot(&self) -> Result<Vec<u8>> {
+        errinput!("state machine does not support snapshots")
+    }
+
+    /// Replaces the state machine contents with a snapshot from snapshot(),
+    /// including its applied index, and flushes it to durable storage. If
+    /// this fails partway, the state may be partially restored, and the node
+    /// must not proceed (the error is fatal).
+    fn restore(&mut self, _snapshot: Vec<u8>) -> Result<()> {
+        errinput!("state machine does not support snapshots")
+    }
 }
 
 /// Test helper state machines.
 #[cfg(test)]
 pub mod test {
     use super::*;
-    use crate::encoding::{self, Value as _};
+    use crate::encoding::{self, bincode, Value as _};
+    use crate::raft::Checksum;
 
     use crossbeam::channel::Sender;
     use itertools::Itertools as _;
     use serde::{Deserialize, Serialize};
-    use std::collections::BTreeMap;
 
     /// Wraps a state machine and emits applied entries to the provided channel.
     pub struct Emit {
@@ -87,6 +146,22 @@ pub mod test {
         fn read(&self, command: Vec<u8>) -> Result<Vec<u8>> {
             self.inner.read(command)
         }
+
+        fn checksums(&self) -> Result<BTreeMap<String, u64>> {
+            self.inner.checksums()
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            self.inner.flush()
+        }
+
+        fn snapshot(&self) -> Result<Vec<u8>> {
+            self.inner.snapshot()
+        }
+
+        fn restore(&mut self, snapshot: Vec<u8>) -> Result<()> {
+            self.inner.restore(snapshot)
+        }
     }
 
     /// A simple string key/value store. Takes KVCommands.
@@ -131,6 +206,28 @@ pub mod test {
                 c @ KVCommand::Put { .. } => panic!("{c} submitted as read command"),
             }
         }
+
+        /// Checksums each key's value separately.
+        fn checksums(&self) -> Result<BTreeMap<String, u64>> {
+            Ok(self
+                .data
+                .iter()
+                .map(|(key, value)| {
+                    let mut checksum = Checksum::new();
+                    checksum.write(value.as_bytes());
+                    (key.clone(), checksum.finish())
+                })
+                .collect())
+        }
+
+        fn snapshot(&self) -> Result<Vec<u8>> {
+            Ok(bincode::serialize(&(self.applied_index, &self.data)))
+        }
+
+        fn restore(&mut self, snapshot: Vec<u8>) -> Result<()> {
+            (self.applied_index, self.data) = bincode::deserialize(&snapshot)?;
+            Ok(())
+        }
     }
 
     /// A KV command. Returns the corresponding KVResponse.
@@ -206,5 +303,14 @@ pub mod test {
         fn read(&self, _: Vec<u8>) -> Result<Vec<u8>> {
             Ok(Vec::new())
         }
+
+        fn snapshot(&self) -> Result<Vec<u8>> {
+            Ok(bincode::serialize(&self.applied_index))
+        }
+
+        fn restore(&mut self, snapshot: Vec<u8>) -> Result<()> {
+            self.applied_index = bincode::deserialize(&snapshot)?;
+            Ok(())
+        }
     }
 }
@@ -0,0 +1,136 @@
+//! Raft event tracing.
+//!
+//! Consensus incidents (e.g. election storms or replicas that fail to catch up)
+//! are hard to debug after the fact, since the node's debug logging is usually
+//! disabled and the relevant messages are long gone. To help with this, each
+//! node records significant events (role changes, votes, appends, commits, and
+//! snapshots) in a fixed-size in-memory ring buffer, which can be dumped on
+//! request via `Request::Trace`. Older events are discarded when the buffer is
+//! full.
+
+use super::{Index, NodeID, Term};
+
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+/// A ring buffer of the most recent Raft events on a node.
+pub struct Trace {
+    /// The maximum number of events to retain. 0 disables tracing.
+    capacity: usize,
+    /// The sequence number of the next event.
+    next_seq: u64,
+    /// The retained events, in order.
+    events: VecDeque<TraceEvent>,
+}
+
+impl Trace {
+    /// Creates a new trace which retains up to capacity events.
+    pub fn new(capacity: usize) -> Self {
+        Self { capacity, next_seq: 1, events: VecDeque::new() }
+    }
+
+    /// Records an event in the given term, discarding the oldest event if the
+    /// buffer is full.
+    pub fn record(&mut self, term: Term, event: Event) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.events.len() >= self.capacity {
+            self.events.pop_front();
+        }
+        self.events.push_back(TraceEvent { seq: self.next_seq, term, event });
+        self.next_seq += 1;
+    }
+
+    /// Returns the retained events, oldest first.
+    pub fn events(&self) -> Vec<TraceEvent> {
+        self.events.iter().cloned().collect()
+    }
+}
+
+/// A traced Raft event.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TraceEvent {
+    /// The event sequence number, starting at 1 when the node starts. Gaps in
+    /// the sequence indicate discarded events.
+    pub seq: u64,
+    /// The node's term when the event happened.
+    pub term: Term,
+    /// The event.
+    pub event: Event,
+}
+
+impl std::fmt::Display for TraceEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#{} term {}: {}", self.seq, self.term, self.event)
+    }
+}
+
+/// A Raft event.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Event {
+    /// The node became a follower of the given leader, or a leaderless
+    /// follower if None.
+    Follower { leader: Option<NodeID> },
+    /// The node held a pre-vote for the next term, see `Options::pre_vote`.
+    PreCampaign,
+    /// The node campaigned for leadership as a candidate.
+    Campaign,
+    /// The node won an election and became leader.
+    Leader,
+    /// The node voted for the given candidate.
+    Vote { candidate: NodeID },
+    /// The node appended the given range of entries to its log, either as a
+    /// leader proposal or by replicating them from the leader.
+    Append { first: Index, last: Index },
+    /// The node advanced its commit index.
+    Commit { index: Index },
+    /// The node truncated its log up to the given index.
+    Truncate { index: Index },
+    /// The node installed a state machine snapshot from the leader at the
+    /// given index.
+    Snapshot { index: Index },
+}
+
+impl std::fmt::Display for Event {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Follower { leader: Some(leader) } => write!(f, "follow leader n{leader}"),
+            Self::Follower { leader: None } => write!(f, "become leaderless follower"),
+            Self::PreCampaign => write!(f, "pre-vote campaign"),
+            Self::Campaign => write!(f, "campaign"),
+            Self::Leader => write!(f, "become leader"),
+            Self::Vote { candidate } => write!(f, "vote for n{candidate}"),
+            Self::Append { first, last } if first == last => write!(f, "append {first}"),
+            Self::Append { first, last } => write!(f, "append {first}-{last}"),
+            Self::Commit { index } => write!(f, "commit {index}"),
+            Self::Truncate { index } => write!(f, "truncate log to {index}"),
+            Self::Snapshot { index } => write!(f, "install snapshot {index}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The trace retains the most recent events up to its capacity.
+    #[test]
+    fn ring_buffer() {
+        let mut trace = Trace::new(2);
+        trace.record(1, Event::Campaign);
+        trace.record(1, Event::Leader);
+        trace.record(1, Event::Commit { index: 1 });
+        let events = trace.events();
+        assert_eq!(events.iter().map(|e| e.seq).collect::<Vec<_>>(), vec![2, 3]);
+        assert_eq!(events[1].event, Event::Commit { index: 1 });
+    }
+
+    /// A trace with 0 capacity is disabled.
+    #[test]
+    fn disabled() {
+        let mut trace = Trace::new(0);
+        trace.record(1, Event::Campaign);
+        assert!(trace.events().is_empty());
+    }
+}
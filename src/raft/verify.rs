@@ -0,0 +1,468 @@
+//! Raft log consistency verification.
+//!
+//! Raft guarantees that committed log entries are identical on all nodes (the
+//! Log Matching Property). Bugs or storage corruption can violate this without
+//! anyone noticing until replicas return different results. To detect this,
+//! the leader can cross-check its log against its peers' logs on request.
+//!
+//! Rather than shipping the entire log across the network, nodes exchange a
+//! compact `Digest` of their log up to some index: the term boundaries (where
+//! each term begins) and checksums of fixed-size chunks of entries. The leader
+//! compares each peer's digest against a digest of its own log up to the same
+//! index, and reports the first divergence in a `Report`.
+//!
+//! Computing a digest also checks the local log's structure: entries must be
+//! contiguous and terms must not decrease. Truncated entries are unknown, so
+//! digests of truncated logs start at the first chunk after the snapshot
+//! index, and only the range covered by both digests is compared.
+//!
+//! Identical logs don't guarantee identical state machines, e.g. if a state
+//! machine applies commands non-deterministically. The leader can also
+//! cross-check the state machines: each node computes checksums of its state
+//! machine (e.g. one per SQL table) once it has applied the leader's current
+//! applied index, and the leader reports partitions that differ from its own
+//! in a `StateReport`.
+
+use super::{Index, Log, NodeID, Term};
+use crate::error::{Error, Result};
+use crate::{errdata, errinput};
+
+use itertools::Itertools as _;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// The number of log entries covered by each digest checksum. Smaller chunks
+/// narrow down the location of a divergence, at the expense of larger digests.
+const CHUNK_SIZE: Index = 1000;
+
+/// A digest of a Raft log prefix, used to compare logs across nodes.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Digest {
+    /// The first index covered by the digest: 1, or the start of the first
+    /// chunk after the log's snapshot index if it has been truncated.
+    pub start: Index,
+    /// The last index covered by the digest. Entries [start,index] are
+    /// included.
+    pub index: Index,
+    /// Term boundaries as (index, term) pairs: the first index of each term,
+    /// starting at the start index.
+    pub terms: Vec<(Index, Term)>,
+    /// Checksums of consecutive CHUNK_SIZE entry chunks, starting at the start
+    /// index. The last chunk may be partial.
+    pub checksums: Vec<u64>,
+}
+
+impl Digest {
+    /// Computes a digest of the log entries [start,index], checking the log
+    /// structure. Errors if the log structure is invalid.
+    pub fn new(log: &mut Log, index: Index) -> Result<Self> {
+        let (last_index, _) = log.get_last_index();
+        if index > last_index {
+            return errinput!("digest index {index} beyond last index {last_index}");
+        }
+
+        // Start at a chunk boundary, such that chunks line up across nodes
+        // with different snapshot indexes.
+        let (snapshot_index, _) = log.get_snapshot_index();
+        let start = snapshot_index.div_ceil(CHUNK_SIZE) * CHUNK_SIZE + 1;
+        Self::scan(log, start, index)
+    }
+
+    /// Computes a digest of the log entries [start,index], where start is a
+    /// chunk boundary.
+    fn scan(log: &mut Log, start: Index, index: Index) -> Result<Self> {
+        let mut digest = Self { start, index, terms: Vec::new(), checksums: Vec::new() };
+        if start > index {
+            return Ok(digest);
+        }
+        let mut checksum = Checksum::new();
+        let mut chunk_len = 0;
+        let mut expect_index = start;
+        let mut last_term = 0;
+        let mut scan = log.scan(start..=index);
+        while let Some(entry) = scan.next().transpose()? {
+            if entry.index != expect_index {
+                return errdata!("expected entry {expect_index}, found {}", entry.index);
+            }
+            if entry.term < last_term {
+                return errdata!(
+                    "entry {} has term {} below previous term {last_term}",
+                    entry.index,
+                    entry.term
+                );
+            }
+            if entry.term > last_term {
+                digest.terms.push((entry.index, entry.term));
+                last_term = entry.term;
+            }
+
+            checksum.write(&entry.index.to_be_bytes());
+            checksum.write(&entry.term.to_be_bytes());
+            match &entry.command {
+                Some(command) => {
+                    checksum.write(&[1]);
+                    checksum.write(&(command.len() as u64).to_be_bytes());
+                    checksum.write(command);
+                }
+                None => checksum.write(&[0]),
+            }
+            chunk_len += 1;
+            if chunk_len == CHUNK_SIZE {
+                digest.checksums.push(checksum.finish());
+                checksum = Checksum::new();
+                chunk_len = 0;
+            }
+            expect_index += 1;
+        }
+        if expect_index <= index {
+            return errdata!("missing entry {expect_index}");
+        }
+        if chunk_len > 0 {
+            digest.checksums.push(checksum.finish());
+        }
+        Ok(digest)
+    }
+
+    /// Returns the digest truncated to end at the given index, e.g. to compare
+    /// it with a lagging node's digest. Complete chunks are reused, and only
+    /// the final partial chunk (if any) is recomputed from the log.
+    pub fn truncate(&self, log: &mut Log, index: Index) -> Result<Self> {
+        if index > self.index {
+            return errinput!("can't extend digest index {} to {index}", self.index);
+        }
+        if index == self.index {
+            return Ok(self.clone());
+        }
+        if index < self.start {
+            return Ok(Self { start: self.start, index, terms: Vec::new(), checksums: Vec::new() });
+        }
+        // If the log has since been truncated past the final chunk, compute a
+        // new digest instead.
+        let chunks = (index - self.start + 1) / CHUNK_SIZE;
+        let chunk_start = self.start + chunks * CHUNK_SIZE;
+        if chunk_start <= log.get_snapshot_index().0 {
+            return Self::new(log, index);
+        }
+
+        let mut terms: Vec<_> =
+            self.terms.iter().filter(|(i, _)| *i < chunk_start).copied().collect();
+        let mut checksums: Vec<_> = self.checksums[..chunks as usize].to_vec();
+        if chunk_start <= index {
+            let tail = Self::scan(log, chunk_start, index)?;
+            let last_term = terms.last().map(|(_, term)| *term);
+            terms.extend(tail.terms.into_iter().filter(|(_, term)| Some(*term) != last_term));
+            checksums.extend(tail.checksums);
+        }
+        Ok(Self { start: self.start, index, terms, checksums })
+    }
+
+    /// Compares the digest with another digest of the same index, returning the
+    /// first divergence found, if any. Only the range covered by both digests
+    /// is compared.
+    pub fn compare(&self, other: &Digest) -> Option<Divergence> {
+        assert_eq!(self.index, other.index, "can't compare digests at different indexes");
+        let start = std::cmp::max(self.start, other.start);
+        let (this, other) = (self.trim(start), other.trim(start));
+
+        // Find the first term boundary that differs. Log entries with the same
+        // index and term should be identical, so the divergence starts at the
+        // lowest index of the differing boundaries.
+        let terms = this.terms.iter().map(Some).chain(std::iter::repeat(None));
+        let other_terms = other.terms.iter().map(Some).chain(std::iter::repeat(None));
+        for (a, b) in terms.zip(other_terms) {
+            let index = match (a, b) {
+                (Some(a), Some(b)) if a == b => continue,
+                (Some((a, _)), Some((b, _))) => std::cmp::min(*a, *b),
+                (Some((index, _)), None) | (None, Some((index, _))) => *index,
+                (None, None) => break,
+            };
+            return Some(Divergence { index, reason: format!("term mismatch at index {index}") });
+        }
+
+        // The terms match, so compare the entry checksums.
+        let (index, _) =
+            this.checksums.iter().zip(&other.checksums).enumerate().find(|(_, (a, b))| a != b)?;
+        let start = this.start + index as Index * CHUNK_SIZE;
+        let end = std::cmp::min(start + CHUNK_SIZE - 1, this.index);
+        Some(Divergence { index: start, reason: format!("entry mismatch in range {start}-{end}") })
+    }
+
+    /// Returns the digest trimmed to begin at the given start index, which
+    /// must be a chunk boundary at or after the current start index.
+    fn trim(&self, start: Index) -> Digest {
+        assert!(start >= self.start, "can't extend digest start");
+        assert_eq!((start - self.start) % CHUNK_SIZE, 0, "start must be a chunk boundary");
+        let mut terms = Vec::new();
+        if start <= self.index {
+            // The entry at start has the term of the preceding boundary.
+            if let Some((_, term)) = self.terms.iter().rev().find(|(index, _)| *index <= start) {
+                terms.push((start, *term));
+            }
+            terms.extend(self.terms.iter().filter(|(index, _)| *index > start));
+        }
+        let skip = ((start - self.start) / CHUNK_SIZE) as usize;
+        let checksums = self.checksums.iter().skip(skip).copied().collect();
+        Digest { start, index: self.index, terms, checksums }
+    }
+}
+
+/// A divergence between two node logs.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Divergence {
+    /// The lowest index where the logs may diverge. For entry checksum
+    /// mismatches, this is the start of the mismatched chunk.
+    pub index: Index,
+    /// A human-readable description of the divergence.
+    pub reason: String,
+}
+
+/// The verification result for a single node's log.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum NodeReport {
+    /// The node's log matches the leader's log up to the given index.
+    Consistent { index: Index },
+    /// The node's log diverges from the leader's log. The logs were compared
+    /// up to the given index.
+    Divergent { index: Index, divergence: Divergence },
+    /// The node failed to compute a digest, e.g. due to a corrupt log.
+    Failed(Error),
+}
+
+/// A log verification report, generated by the leader. Nodes that didn't
+/// respond in time are omitted.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Report {
+    /// The leader which generated the report.
+    pub leader: NodeID,
+    /// The leader's term.
+    pub term: Term,
+    /// The leader's commit index when verification started. Nodes are
+    /// verified up to this index, or their last index if they're lagging.
+    pub commit_index: Index,
+    /// Verification results by node ID, including the leader.
+    pub nodes: BTreeMap<NodeID, NodeReport>,
+}
+
+impl Report {
+    /// Returns true if all reported nodes are consistent with the leader.
+    pub fn is_consistent(&self) -> bool {
+        self.nodes.values().all(|r| matches!(r, NodeReport::Consistent { .. }))
+    }
+}
+
+/// The state machine verification result for a single node.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum StateNodeReport {
+    /// The node's state machine checksums match the leader's.
+    Consistent,
+    /// The node's state machine checksums differ from the leader's in the
+    /// given partitions, including partitions that only exist on one of them.
+    Divergent { partitions: Vec<String> },
+    /// The node failed to compute checksums, e.g. because it had already
+    /// applied entries beyond the applied index.
+    Failed(Error),
+}
+
+impl StateNodeReport {
+    /// Compares a node's state machine checksums against the leader's.
+    pub fn compare(leader: &BTreeMap<String, u64>, node: &BTreeMap<String, u64>) -> Self {
+        let partitions: Vec<String> = leader
+            .keys()
+            .chain(node.keys().filter(|p| !leader.contains_key(*p)))
+            .filter(|p| leader.get(*p) != node.get(*p))
+            .cloned()
+            .sorted()
+            .collect();
+        match partitions.is_empty() {
+            true => Self::Consistent,
+            false => Self::Divergent { partitions },
+        }
+    }
+}
+
+/// A state machine verification report, generated by the leader. Nodes that
+/// didn't respond in time are omitted.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StateReport {
+    /// The leader which generated the report.
+    pub leader: NodeID,
+    /// The leader's term.
+    pub term: Term,
+    /// The leader's applied index when verification started. All nodes
+    /// compute checksums at this applied index.
+    pub applied_index: Index,
+    /// The number of checksummed partitions on the leader.
+    pub partitions: usize,
+    /// Verification results by node ID, including the leader.
+    pub nodes: BTreeMap<NodeID, StateNodeReport>,
+}
+
+impl StateReport {
+    /// Returns true if all reported nodes are consistent with the leader.
+    pub fn is_consistent(&self) -> bool {
+        self.nodes.values().all(|r| matches!(r, StateNodeReport::Consistent))
+    }
+}
+
+/// A 64-bit FNV-1a checksum. Unlike the standard library hasher, the algorithm
+/// is stable across Rust versions, which matters when comparing checksums
+/// computed by different binaries. It is not cryptographically secure, but
+/// it's only used to detect accidental divergence. Also used by state machines
+/// to compute their checksums, see `State::checksums`.
+#[derive(Clone, Copy)]
+pub struct Checksum(u64);
+
+impl Checksum {
+    /// Creates a new, empty checksum.
+    pub fn new() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+
+    /// Adds the given bytes to the checksum.
+    pub fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    /// Returns the checksum value.
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+impl Default for Checksum {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::{Key as _, Value as _};
+    use crate::raft::{Entry, Key};
+    use crate::storage;
+
+    /// Creates a log with the given entry terms, using the term as the command.
+    fn log(terms: &[Term]) -> Log {
+        let mut log = Log::new(Box::new(storage::Memory::new())).expect("log failed");
+        for (i, term) in terms.iter().copied().enumerate() {
+            if term > log.get_term().0 {
+                log.set_term(term, None).expect("set_term failed");
+            }
+            log.append(Some(vec![i as u8])).expect("append failed");
+        }
+        log
+    }
+
+    /// Identical logs have identical digests.
+    #[test]
+    fn digest_identical() -> Result<()> {
+        let mut a = log(&[1, 1, 2, 3, 3]);
+        let mut b = log(&[1, 1, 2, 3, 3]);
+        let digest = Digest::new(&mut a, 5)?;
+        assert_eq!(digest.terms, vec![(1, 1), (3, 2), (4, 3)]);
+        assert_eq!(digest.checksums.len(), 1);
+        assert_eq!(digest.compare(&Digest::new(&mut b, 5)?), None);
+
+        // A log prefix also matches.
+        let mut c = log(&[1, 1, 2]);
+        assert_eq!(Digest::new(&mut a, 3)?.compare(&Digest::new(&mut c, 3)?), None);
+        Ok(())
+    }
+
+    /// Divergent terms are reported at the first divergent index.
+    #[test]
+    fn digest_term_mismatch() -> Result<()> {
+        let mut a = log(&[1, 1, 2, 2, 2]);
+        let mut b = log(&[1, 1, 1, 3, 3]);
+        let divergence = Digest::new(&mut a, 5)?.compare(&Digest::new(&mut b, 5)?);
+        assert_eq!(divergence.map(|d| d.index), Some(3));
+        Ok(())
+    }
+
+    /// Divergent commands with matching terms are reported by chunk.
+    #[test]
+    fn digest_entry_mismatch() -> Result<()> {
+        let mut a = log(&[1, 1, 2]);
+        let mut b = log(&[1, 1, 2]);
+        // Corrupt the entry directly in storage, since splice() rejects it.
+        let entry = Entry { index: 3, term: 2, command: Some(vec![7]) };
+        b.engine.set(&Key::Entry(3).encode(), entry.encode())?;
+        let divergence = Digest::new(&mut a, 3)?.compare(&Digest::new(&mut b, 3)?);
+        assert_eq!(divergence.map(|d| d.index), Some(1));
+        Ok(())
+    }
+
+    /// Truncated logs are only compared after their snapshot index, from the
+    /// first chunk boundary.
+    #[test]
+    fn digest_truncated() -> Result<()> {
+        let terms: Vec<Term> = (0..CHUNK_SIZE + 5).map(|i| if i < 10 { 1 } else { 2 }).collect();
+        let mut a = log(&terms);
+        let mut b = log(&terms);
+        a.commit(CHUNK_SIZE + 2)?;
+        a.truncate(3)?;
+        let digest = Digest::new(&mut a, CHUNK_SIZE + 5)?;
+        assert_eq!(digest.start, CHUNK_SIZE + 1);
+        assert_eq!(digest.terms, vec![(CHUNK_SIZE + 1, 2)]);
+        assert_eq!(digest.compare(&Digest::new(&mut b, CHUNK_SIZE + 5)?), None);
+
+        // A divergence below the truncated log isn't detected, but above it is.
+        let mut c = log(&terms);
+        let entry = Entry { index: 5, term: 1, command: Some(vec![7]) };
+        c.engine.set(&Key::Entry(5).encode(), entry.encode())?;
+        let entry = Entry { index: CHUNK_SIZE + 3, term: 2, command: Some(vec![7]) };
+        c.engine.set(&Key::Entry(CHUNK_SIZE + 3).encode(), entry.encode())?;
+        let divergence = digest.compare(&Digest::new(&mut c, CHUNK_SIZE + 5)?);
+        assert_eq!(divergence.map(|d| d.index), Some(CHUNK_SIZE + 1));
+
+        // Digests below the start are empty.
+        let digest = Digest::new(&mut a, 5)?;
+        assert_eq!((digest.terms.len(), digest.checksums.len()), (0, 0));
+        assert_eq!(digest.compare(&Digest::new(&mut b, 5)?), None);
+        Ok(())
+    }
+
+    /// Truncated digests match digests computed at the truncated index.
+    #[test]
+    fn digest_truncate() -> Result<()> {
+        let terms: Vec<Term> =
+            (0..2 * CHUNK_SIZE + 5).map(|i| if i < 10 { 1 } else { 2 }).collect();
+        let mut a = log(&terms);
+        let digest = Digest::new(&mut a, 2 * CHUNK_SIZE + 5)?;
+        for index in [0, 5, 10, 11, CHUNK_SIZE, CHUNK_SIZE + 1, 2 * CHUNK_SIZE + 5] {
+            assert_eq!(digest.truncate(&mut a, index)?, Digest::new(&mut a, index)?);
+        }
+        assert!(digest.truncate(&mut a, 2 * CHUNK_SIZE + 6).is_err());
+        Ok(())
+    }
+
+    /// Digests beyond the last index error.
+    #[test]
+    fn digest_beyond_last_index() {
+        let mut a = log(&[1, 1]);
+        assert!(Digest::new(&mut a, 3).is_err());
+    }
+
+    /// State checksums are compared by partition, including partitions that
+    /// only exist on one node.
+    #[test]
+    fn state_compare() {
+        let leader = BTreeMap::from([("a".to_string(), 1), ("b".to_string(), 2)]);
+        assert_eq!(StateNodeReport::compare(&leader, &leader.clone()), StateNodeReport::Consistent);
+
+        let node = BTreeMap::from([("a".to_string(), 1), ("b".to_string(), 3), ("c".into(), 4)]);
+        assert_eq!(
+            StateNodeReport::compare(&leader, &node),
+            StateNodeReport::Divergent { partitions: vec!["b".into(), "c".into()] }
+        );
+        let node = BTreeMap::from([("b".to_string(), 2)]);
+        assert_eq!(
+            StateNodeReport::compare(&leader, &node),
+            StateNodeReport::Divergent { partitions: vec!["a".into()] }
+        );
+    }
+}
@@ -0,0 +1,232 @@
+//! Admission control for client requests.
+//!
+//! Requests are subject to a per-connection rate limit, a global rate limit,
+//! and a global concurrency limit, such that a single noisy client can't starve
+//! the others. Rate limits use token buckets which allow bursts of up to one
+//! second's worth of requests.
+//!
+//! Requests beyond a limit are queued until they can be admitted, but only for
+//! a bounded time. If a request can't be admitted within the queue timeout, it
+//! is rejected with Error::Overloaded, which the client can retry later.
+
+use crate::error::{Error, Result};
+
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// The default admission queue timeout.
+const QUEUE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Admission limits, see Server::set_admission_limits(). All limits are
+/// disabled by default.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AdmissionLimits {
+    /// The maximum number of concurrently executing requests across all
+    /// clients, if any.
+    pub max_concurrent: Option<usize>,
+    /// The maximum number of requests per second across all clients, if any.
+    pub max_qps: Option<u32>,
+    /// The maximum number of requests per second for each SQL connection, if
+    /// any. Doesn't apply to HTTP requests, which use a connection each.
+    pub max_client_qps: Option<u32>,
+    /// How long a request can be queued waiting for admission before it is
+    /// rejected.
+    pub queue_timeout: Duration,
+}
+
+impl Default for AdmissionLimits {
+    fn default() -> Self {
+        Self {
+            max_concurrent: None,
+            max_qps: None,
+            max_client_qps: None,
+            queue_timeout: QUEUE_TIMEOUT,
+        }
+    }
+}
+
+/// Admits client requests according to the admission limits.
+pub(super) struct Admission {
+    limits: AdmissionLimits,
+    state: Mutex<AdmissionState>,
+    /// Notified when an executing request completes.
+    released: Condvar,
+}
+
+struct AdmissionState {
+    /// The number of executing requests.
+    active: usize,
+    /// The global rate limiter, if any.
+    bucket: Option<TokenBucket>,
+}
+
+impl Admission {
+    /// Creates a new admission controller with the given limits.
+    pub(super) fn new(limits: AdmissionLimits) -> Self {
+        let bucket = limits.max_qps.map(TokenBucket::new);
+        Self {
+            limits,
+            state: Mutex::new(AdmissionState { active: 0, bucket }),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Creates a rate limiter for a new SQL connection, if enabled.
+    pub(super) fn client_bucket(&self) -> Option<TokenBucket> {
+        self.limits.max_client_qps.map(TokenBucket::new)
+    }
+
+    /// Admits a request, waiting for up to the queue timeout. The client's
+    /// rate limiter is given for SQL connections. Returns a guard which
+    /// releases the request's concurrency slot when dropped.
+    pub(super) fn admit(&self, client: Option<&mut TokenBucket>) -> Result<AdmissionGuard<'_>> {
+        let deadline = Instant::now() + self.limits.queue_timeout;
+
+        if let Some(bucket) = client {
+            let wait = bucket.reserve(Instant::now(), deadline).ok_or_else(|| {
+                Error::Overloaded(format!("client exceeded {} requests per second", bucket.rate))
+            })?;
+            std::thread::sleep(wait);
+        }
+
+        let mut state = self.state.lock()?;
+        if let Some(bucket) = state.bucket.as_mut() {
+            let wait = bucket.reserve(Instant::now(), deadline).ok_or_else(|| {
+                Error::Overloaded(format!("server exceeded {} requests per second", bucket.rate))
+            })?;
+            if !wait.is_zero() {
+                drop(state);
+                std::thread::sleep(wait);
+                state = self.state.lock()?;
+            }
+        }
+
+        if let Some(max) = self.limits.max_concurrent {
+            while state.active >= max {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    return Err(Error::Overloaded(format!(
+                        "too many concurrent requests (limit {max})"
+                    )));
+                }
+                state = self.released.wait_timeout(state, remaining)?.0;
+            }
+        }
+        state.active += 1;
+        Ok(AdmissionGuard(self))
+    }
+}
+
+/// An admitted request, see Admission::admit().
+pub(super) struct AdmissionGuard<'a>(&'a Admission);
+
+impl Drop for AdmissionGuard<'_> {
+    fn drop(&mut self) {
+        self.0.state.lock().expect("mutex failed").active -= 1;
+        self.0.released.notify_one();
+    }
+}
+
+/// A token bucket rate limiter. Tokens are replenished at the given rate, up
+/// to one second's worth of tokens, and each request takes a token.
+pub(super) struct TokenBucket {
+    /// The number of tokens per second.
+    rate: u32,
+    /// The number of available tokens. Negative if tokens have been reserved
+    /// by queued requests.
+    tokens: f64,
+    /// When the tokens were last replenished.
+    updated: Instant,
+}
+
+impl TokenBucket {
+    /// Creates a new, full token bucket with the given rate.
+    fn new(rate: u32) -> Self {
+        Self { rate, tokens: rate as f64, updated: Instant::now() }
+    }
+
+    /// Reserves a token, returning how long the caller must wait before using
+    /// it. Returns None without reserving a token if it isn't available by the
+    /// given deadline.
+    fn reserve(&mut self, now: Instant, deadline: Instant) -> Option<Duration> {
+        let rate = self.rate as f64;
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(rate);
+        self.updated = now;
+
+        let wait = Duration::from_secs_f64((1.0 - self.tokens).max(0.0) / rate);
+        if !wait.is_zero() && now + wait > deadline {
+            return None;
+        }
+        self.tokens -= 1.0;
+        Some(wait)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Token buckets allow a burst of requests, then queue requests at the
+    /// given rate until the deadline.
+    #[test]
+    fn token_bucket() {
+        let mut bucket = TokenBucket::new(10);
+        let now = bucket.updated;
+        let deadline = now + Duration::from_millis(250);
+        for _ in 0..10 {
+            assert_eq!(bucket.reserve(now, deadline), Some(Duration::ZERO));
+        }
+        assert_eq!(bucket.reserve(now, deadline), Some(Duration::from_millis(100)));
+        assert_eq!(bucket.reserve(now, deadline), Some(Duration::from_millis(200)));
+        assert_eq!(bucket.reserve(now, deadline), None);
+
+        // Tokens are replenished over time.
+        let later = now + Duration::from_millis(300);
+        assert_eq!(bucket.reserve(later, later), Some(Duration::ZERO));
+        assert_eq!(bucket.reserve(later, later), None);
+    }
+
+    /// The concurrency limit queues requests until others complete, and
+    /// rejects them after the queue timeout.
+    #[test]
+    fn max_concurrent() {
+        let limits = AdmissionLimits {
+            max_concurrent: Some(1),
+            queue_timeout: Duration::from_millis(10),
+            ..Default::default()
+        };
+        let admission = Admission::new(limits);
+        let _guard = admission.admit(None).expect("admit failed");
+        assert!(matches!(admission.admit(None), Err(Error::Overloaded(_))));
+
+        let admission =
+            Admission::new(AdmissionLimits { queue_timeout: Duration::from_secs(10), ..limits });
+        let guard = admission.admit(None).expect("admit failed");
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                std::thread::sleep(Duration::from_millis(10));
+                drop(guard);
+            });
+            admission.admit(None).expect("admit failed");
+        });
+    }
+
+    /// The per-client rate limit rejects requests beyond the queue timeout.
+    #[test]
+    fn max_client_qps() {
+        let admission = Admission::new(AdmissionLimits {
+            max_client_qps: Some(2),
+            queue_timeout: Duration::ZERO,
+            ..Default::default()
+        });
+        let mut client = admission.client_bucket().expect("no client bucket");
+        admission.admit(Some(&mut client)).expect("admit failed");
+        admission.admit(Some(&mut client)).expect("admit failed");
+        assert!(matches!(admission.admit(Some(&mut client)), Err(Error::Overloaded(_))));
+
+        // Other clients are unaffected.
+        let mut other = admission.client_bucket().expect("no client bucket");
+        admission.admit(Some(&mut other)).expect("admit failed");
+    }
+}
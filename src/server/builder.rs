@@ -0,0 +1,188 @@
+//! An embeddable server API, for running toyDB nodes inside another Rust
+//! program instead of via the toydb binary and its config file.
+//!
+//! A `ServerBuilder` constructs a `Server` from programmatic configuration:
+//! the node ID and peers, the Raft options, and the storage engines for the
+//! Raft log and SQL state machine (in-memory by default, but any
+//! `storage::Engine` can be used). Other server options are set on the built
+//! server as usual, e.g. `Server::enable_auth()`.
+//!
+//! The server can then be run in the foreground via `Server::serve()`, or in a
+//! background thread via `Server::start()`, which returns a `ServerHandle`.
+//! Nodes in the same process can communicate via a `MemoryTransport` instead
+//! of TCP. For example, a three-node in-process cluster:
+//!
+//! ```no_run
+//! # use toydb::server::{MemoryNetwork, ServerBuilder};
+//! # fn main() -> toydb::error::Result<()> {
+//! let network = MemoryNetwork::new();
+//! let mut handles = Vec::new();
+//! for id in 1..=3 {
+//!     let peers = (1..=3).filter(|peer| *peer != id);
+//!     let server = ServerBuilder::new(id).peers(peers).build()?;
+//!     handles.push(server.start(network.transport(id), "localhost:0")?);
+//! }
+//! let mut client = handles[0].connect()?;
+//! client.with_retry(|client| client.execute("SELECT 1"))?;
+//! # Ok(())
+//! # }
+//! ```
+
+use super::{Server, ShutdownHandle};
+use crate::error::Result;
+use crate::raft;
+use crate::sql;
+use crate::storage;
+use crate::Client;
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+/// Builds a toyDB server, see the module documentation.
+pub struct ServerBuilder {
+    /// The node ID.
+    id: raft::NodeID,
+    /// Raft peer IDs and addresses.
+    peers: HashMap<raft::NodeID, String>,
+    /// The Raft log storage engine. Defaults to in-memory storage.
+    raft_storage: Option<Box<dyn storage::Engine>>,
+    /// The Raft state machine. Defaults to the SQL state machine with
+    /// in-memory storage.
+    state: Option<Box<dyn raft::State>>,
+    /// The Raft log durability policy.
+    durability: raft::Durability,
+    /// The Raft options.
+    raft_opts: raft::Options,
+}
+
+impl ServerBuilder {
+    /// Creates a new server builder for the given node ID. By default, the
+    /// node has no peers and uses in-memory storage.
+    pub fn new(id: raft::NodeID) -> Self {
+        Self {
+            id,
+            peers: HashMap::new(),
+            raft_storage: None,
+            state: None,
+            durability: raft::Durability::Always,
+            raft_opts: raft::Options::default(),
+        }
+    }
+
+    /// Sets the Raft peer IDs. Use peer_addrs() instead with a TCP transport.
+    pub fn peers(self, peers: impl IntoIterator<Item = raft::NodeID>) -> Self {
+        self.peer_addrs(peers.into_iter().map(|id| (id, String::new())))
+    }
+
+    /// Sets the Raft peer IDs and their Raft addresses, for a TCP transport.
+    pub fn peer_addrs(mut self, peers: impl IntoIterator<Item = (raft::NodeID, String)>) -> Self {
+        self.peers = peers.into_iter().collect();
+        self
+    }
+
+    /// Sets the Raft log storage engine.
+    pub fn raft_storage(mut self, engine: impl storage::Engine + 'static) -> Self {
+        self.raft_storage = Some(Box::new(engine));
+        self
+    }
+
+    /// Sets the SQL storage engine.
+    pub fn sql_storage(self, engine: impl storage::Engine + 'static) -> Result<Self> {
+        Ok(self.state_machine(Box::new(sql::engine::Raft::new_state(engine)?)))
+    }
+
+    /// Sets a custom Raft state machine instead of the SQL state machine. SQL
+    /// clients won't work, but other Raft state machines can be embedded.
+    pub fn state_machine(mut self, state: Box<dyn raft::State>) -> Self {
+        self.state = Some(state);
+        self
+    }
+
+    /// Sets the durability policy for Raft log writes, i.e. when to fsync them.
+    /// Defaults to Durability::Always. Weaker policies yield better write
+    /// performance, but may lose data on host crashes which compromises Raft
+    /// safety guarantees.
+    pub fn durability(mut self, durability: raft::Durability) -> Self {
+        self.durability = durability;
+        self
+    }
+
+    /// Sets the Raft options.
+    pub fn raft_options(mut self, opts: raft::Options) -> Self {
+        self.raft_opts = opts;
+        self
+    }
+
+    /// Builds the server.
+    pub fn build(self) -> Result<Server> {
+        let raft_storage = self.raft_storage.unwrap_or_else(|| Box::new(storage::Memory::new()));
+        let mut raft_log = raft::Log::new(raft_storage)?;
+        raft_log.set_durability(self.durability);
+        let state = match self.state {
+            Some(state) => state,
+            None => Box::new(sql::engine::Raft::new_state(storage::Memory::new())?),
+        };
+        Server::new(self.id, self.peers, raft_log, state, self.raft_opts)
+    }
+}
+
+/// A handle to a server running in a background thread, see Server::start().
+#[derive(Clone)]
+pub struct ServerHandle {
+    /// The local SQL listen address.
+    pub(super) sql_addr: SocketAddr,
+    /// Shuts down the server.
+    pub(super) shutdown: ShutdownHandle,
+}
+
+impl ServerHandle {
+    /// Returns the server's SQL listen address.
+    pub fn sql_addr(&self) -> SocketAddr {
+        self.sql_addr
+    }
+
+    /// Connects a new client to the server.
+    pub fn connect(&self) -> Result<Client> {
+        Client::connect(self.sql_addr)
+    }
+
+    /// Shuts down the server gracefully, see ShutdownHandle::shutdown(). The
+    /// server no longer serves requests afterwards, but its background threads
+    /// keep running until the process exits.
+    pub fn shutdown(&self) -> Result<()> {
+        self.shutdown.shutdown()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::MemoryNetwork;
+    use super::*;
+    use crate::sql::types::Value;
+
+    use std::time::Duration;
+
+    /// A three-node cluster runs in-process via a memory transport.
+    #[test]
+    fn memory_cluster() -> Result<()> {
+        let network = MemoryNetwork::new();
+        let mut handles = Vec::new();
+        for id in 1..=3 {
+            let peers = (1..=3).filter(|peer| *peer != id);
+            let mut server =
+                ServerBuilder::new(id).peers(peers).durability(raft::Durability::Never).build()?;
+            server.set_raft_tick_interval(Duration::from_millis(10))?;
+            handles.push(server.start(network.transport(id), "127.0.0.1:0")?);
+        }
+
+        // Writes via one node are visible via the others.
+        let mut c1 = handles[0].connect()?;
+        c1.with_retry(|c| c.execute("CREATE TABLE t (id INTEGER PRIMARY KEY)"))?;
+        c1.with_retry(|c| c.execute("INSERT INTO t VALUES (1), (2), (3)"))?;
+        for handle in &handles {
+            let count = handle.connect()?.execute("SELECT COUNT(*) FROM t")?;
+            assert_eq!(Value::try_from(count)?, Value::Integer(3));
+        }
+        Ok(())
+    }
+}
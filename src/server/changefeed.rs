@@ -0,0 +1,209 @@
+//! Changefeed sinks, which emit row changes to external consumers such as
+//! caches and search indexes. Changefeeds are created with `CREATE CHANGEFEED
+//! name FOR table, ... INTO 'file://PATH'` and stored in the catalog. The
+//! changefeeds maintenance task (see MaintenanceTask::changefeeds()) appends
+//! their changes to the sink files as JSON Lines, for example:
+//!
+//! ```text
+//! {"version":3,"table":"movies","id":1,"row":[1,"Sicario",2015]}
+//! {"version":3,"table":"movies","id":2,"row":[2,"Arrival",2016]}
+//! {"version":4,"table":"movies","id":1,"row":null}
+//! {"resolved":5}
+//! ```
+//!
+//! * `version`: the MVCC version (i.e. transaction) that wrote the change.
+//!   Changes are ordered by version, table, and primary key. Consumers should
+//!   apply all changes at a version together, since transactions are atomic.
+//!
+//! * `row`: the new row values in column order, or `null` if the row was
+//!   deleted (including by DROP TABLE). Values are converted like in the HTTP
+//!   interface: infinite and NaN floats are given as strings.
+//!
+//! * `resolved`: written after each batch of changes. All changes below this
+//!   version have been emitted, and later changes will have a higher version.
+//!
+//! A new changefeed first emits the tables' entire history, which gives
+//! consumers the initial state. After a restart, the task resumes from the
+//! last `resolved` event in the file, truncating any partial batch after it,
+//! so each change is emitted exactly once.
+//!
+//! Sinks are files on the local node. Every node running the task reads the
+//! changes via Raft and appends them to its own copy of the sink file, so the
+//! files on different nodes have the same contents, although they may lag.
+//!
+//! Clients can also stream changes directly via Client::changes().
+
+use super::Server;
+use crate::error::Result;
+use crate::sql::engine::{Catalog, Change, Transaction};
+use crate::storage::mvcc;
+
+use serde::Serialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::io::{BufRead as _, BufReader, Write as _};
+use std::path::{Path, PathBuf};
+
+/// Emits changefeed changes to their sink files, tracking the resolved
+/// version of each sink.
+#[derive(Default)]
+pub struct Emitter {
+    /// The resolved version of each sink file that has been emitted to.
+    resolved: HashMap<PathBuf, mvcc::Version>,
+}
+
+impl Emitter {
+    /// Creates a new emitter.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Emits pending changes of all changefeeds to their sinks, using the
+    /// given read-only transaction. Returns the number of emitted changes.
+    pub fn emit(&mut self, txn: &(impl Transaction + Catalog)) -> Result<usize> {
+        let changefeeds = txn.list_changefeeds()?;
+        let mut count = 0;
+        let mut sinks = Vec::with_capacity(changefeeds.len());
+        for changefeed in changefeeds {
+            let path = changefeed.sink_path()?.to_path_buf();
+            let from = match self.resolved.get(&path) {
+                Some(resolved) => *resolved,
+                None => Self::recover(&path)?,
+            };
+            let changes = txn.changes(&changefeed.tables, from)?;
+            if !changes.changes.is_empty() {
+                count += changes.changes.len();
+                Self::write(&path, changes.changes, changes.resolved)?;
+            }
+            self.resolved.insert(path.clone(), from.max(changes.resolved));
+            sinks.push(path);
+        }
+        // Forget dropped changefeeds, in case they're recreated.
+        self.resolved.retain(|path, _| sinks.contains(path));
+        Ok(count)
+    }
+
+    /// Recovers a sink's resolved version from the last resolved event in the
+    /// file, or 0 if none. Truncates a partial batch after it, if any.
+    fn recover(path: &Path) -> Result<mvcc::Version> {
+        let file = match std::fs::File::open(path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(err) => return Err(err.into()),
+        };
+        let (mut resolved, mut end, mut offset) = (0, 0, 0);
+        let mut reader = BufReader::new(file);
+        let mut line = String::new();
+        while reader.read_line(&mut line)? > 0 {
+            offset += line.len() as u64;
+            if line.ends_with('\n') {
+                if let Ok(event) = serde_json::from_str::<serde_json::Value>(&line) {
+                    if let Some(version) = event.get("resolved").and_then(|v| v.as_u64()) {
+                        (resolved, end) = (version, offset);
+                    }
+                }
+            }
+            line.clear();
+        }
+        if offset > end {
+            std::fs::OpenOptions::new().write(true).open(path)?.set_len(end)?;
+        }
+        Ok(resolved)
+    }
+
+    /// Appends a batch of changes to a sink file, followed by a resolved
+    /// event, creating the file and its parent directories if needed.
+    fn write(path: &Path, changes: Vec<Change>, resolved: mvcc::Version) -> Result<()> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+        let file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+        let mut writer = std::io::BufWriter::new(file);
+        for change in changes {
+            serde_json::to_writer(&mut writer, &Self::event(change))?;
+            writer.write_all(b"\n")?;
+        }
+        serde_json::to_writer(&mut writer, &json!({ "resolved": resolved }))?;
+        writer.write_all(b"\n")?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Converts a change to a JSON change event.
+    fn event(change: Change) -> Event {
+        Event {
+            version: change.version,
+            table: change.table,
+            id: Server::http_value(change.id),
+            row: change.row.map(|row| row.into_iter().map(Server::http_value).collect()),
+        }
+    }
+}
+
+/// A JSON change event, see the module documentation. Uses a struct rather
+/// than a JSON object to retain the field order.
+#[derive(Serialize)]
+struct Event {
+    version: mvcc::Version,
+    table: String,
+    id: serde_json::Value,
+    row: Option<Vec<serde_json::Value>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::engine::{Engine as _, Local};
+    use crate::storage::Memory;
+
+    /// Changes are emitted to sink files, and resumed from the last resolved
+    /// event after a restart.
+    #[test]
+    fn emit() -> Result<()> {
+        let dir = tempfile::TempDir::with_prefix("toydb")?;
+        let path = dir.path().join("sink").join("changes.jsonl");
+        let engine = Local::new(Memory::new());
+        let mut session = engine.session();
+        session.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, value FLOAT)")?;
+        session.execute("CREATE TABLE other (id INTEGER PRIMARY KEY)")?;
+        session.execute("INSERT INTO t VALUES (1, 1.5), (2, INFINITY)")?;
+        session.execute("INSERT INTO other VALUES (1)")?;
+        session.execute(&format!("CREATE CHANGEFEED f FOR t INTO 'file://{}'", path.display()))?;
+        session.execute("DELETE FROM t WHERE id = 1")?;
+
+        let mut emitter = Emitter::new();
+        assert_eq!(emitter.emit(&engine.begin_read_only()?)?, 3);
+        assert_eq!(emitter.emit(&engine.begin_read_only()?)?, 0);
+        let contents = std::fs::read_to_string(&path)?;
+        assert_eq!(
+            contents,
+            [
+                r#"{"version":3,"table":"t","id":1,"row":[1,1.5]}"#,
+                r#"{"version":3,"table":"t","id":2,"row":[2,"inf"]}"#,
+                r#"{"version":6,"table":"t","id":1,"row":null}"#,
+                r#"{"resolved":7}"#,
+                "",
+            ]
+            .join("\n")
+        );
+
+        // A new emitter resumes from the last resolved event, and truncates
+        // a partial batch.
+        session.execute("UPDATE t SET value = 0.0 WHERE id = 2")?;
+        std::fs::OpenOptions::new().append(true).open(&path)?.write_all(b"{\"id\":2,\"ro")?;
+        let mut emitter = Emitter::new();
+        assert_eq!(emitter.emit(&engine.begin_read_only()?)?, 1);
+        let contents = std::fs::read_to_string(&path)?;
+        let lines: Vec<&str> = contents.lines().skip(4).collect();
+        assert_eq!(
+            lines,
+            vec![r#"{"version":7,"table":"t","id":2,"row":[2,0.0]}"#, r#"{"resolved":8}"#]
+        );
+
+        // Tables used by changefeeds can't be dropped.
+        assert!(session.execute("DROP TABLE t").is_err());
+        session.execute("DROP CHANGEFEED f")?;
+        session.execute("DROP TABLE t")?;
+        Ok(())
+    }
+}
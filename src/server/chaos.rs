@@ -0,0 +1,306 @@
+//! A chaos transport, which wraps another Raft peer transport and injects
+//! network faults into outbound messages: drops, duplicates, reordering, and
+//! partitions between specific node pairs. It's intended for testing and
+//! staging clusters, to exercise Raft's fault tolerance over a real network.
+//!
+//! Random faults are decided by a seeded RNG for each link between two nodes,
+//! so a link makes the same sequence of fault decisions for a given seed and
+//! message sequence. Message timing and thread scheduling still vary between
+//! runs, so a cluster won't replay exactly -- see raft::sim for deterministic
+//! simulation.
+//!
+//! Partitions follow a schedule of time windows since the transport was
+//! created. A partition drops messages in both directions, so all nodes must
+//! use the same schedule.
+
+use super::Transport;
+use crate::errinput;
+use crate::error::Result;
+use crate::raft;
+
+use crossbeam::channel::{Receiver, RecvTimeoutError, Sender};
+use log::warn;
+use rand::rngs::StdRng;
+use rand::{Rng as _, SeedableRng as _};
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+
+/// How long a reordered message is held back, waiting for the next message on
+/// the link to overtake it, before it's sent anyway.
+const REORDER_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// Fault injection options for a `ChaosTransport`. Defaults to no faults.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct ChaosOptions {
+    /// The random seed.
+    pub seed: u64,
+    /// The probability of dropping a message.
+    pub drop: f64,
+    /// The probability of sending a message twice.
+    pub duplicate: f64,
+    /// The probability of holding back a message until after the next message
+    /// on the same link.
+    pub reorder: f64,
+    /// Partitions between node pairs.
+    pub partitions: Vec<ChaosPartition>,
+}
+
+impl ChaosOptions {
+    /// Validates the options.
+    pub fn validate(&self) -> Result<()> {
+        for (name, p) in
+            [("drop", self.drop), ("duplicate", self.duplicate), ("reorder", self.reorder)]
+        {
+            if !(0.0..=1.0).contains(&p) {
+                return errinput!("chaos {name} probability must be between 0 and 1");
+            }
+        }
+        for partition in &self.partitions {
+            if partition.start_ms > partition.end_ms {
+                return errinput!("chaos partition {:?} ends before it starts", partition.nodes);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A scheduled partition between two nodes. Messages between them are dropped
+/// in both directions during the time window [start_ms, end_ms), measured since
+/// the transport was created.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct ChaosPartition {
+    /// The partitioned node pair.
+    pub nodes: (raft::NodeID, raft::NodeID),
+    /// When the partition starts, in milliseconds.
+    pub start_ms: u64,
+    /// When the partition heals, in milliseconds.
+    pub end_ms: u64,
+}
+
+impl ChaosPartition {
+    /// Returns true if the partition separates the given nodes at the given
+    /// time since the transport was created.
+    fn separates(&self, a: raft::NodeID, b: raft::NodeID, elapsed: Duration) -> bool {
+        let elapsed = elapsed.as_millis();
+        (self.nodes == (a, b) || self.nodes == (b, a))
+            && (self.start_ms as u128..self.end_ms as u128).contains(&elapsed)
+    }
+}
+
+/// A transport decorator which injects faults into the inner transport's
+/// outbound messages, according to the given options. See module docs.
+pub struct ChaosTransport<T: Transport> {
+    /// The local node ID.
+    id: raft::NodeID,
+    /// The inner transport.
+    inner: T,
+    /// The fault injection options.
+    opts: ChaosOptions,
+    /// The time the transport was created, for the partition schedule.
+    started: Instant,
+}
+
+impl<T: Transport> ChaosTransport<T> {
+    /// Wraps the given transport for the local node.
+    pub fn new(id: raft::NodeID, inner: T, opts: ChaosOptions) -> Result<Self> {
+        opts.validate()?;
+        warn!("Injecting Raft network faults: {opts:?}");
+        Ok(Self { id, inner, opts, started: Instant::now() })
+    }
+}
+
+impl<T: Transport> Transport for ChaosTransport<T> {
+    fn receive(&self, step_tx: Sender<raft::Envelope>) -> Result<()> {
+        self.inner.receive(step_tx)
+    }
+
+    /// Passes outbound messages through a link, which injects faults, and
+    /// sends the remaining messages via the inner transport.
+    fn send(&self, peer: raft::NodeID, rx: Receiver<raft::Envelope>) {
+        let (inner_tx, inner_rx) = crossbeam::channel::unbounded();
+        std::thread::scope(|s| {
+            s.spawn(|| self.inner.send(peer, inner_rx));
+            let mut link = Link::new(self.id, peer, &self.opts);
+            let mut held = None;
+            loop {
+                // Wait for the next message. If a message is held back, only
+                // wait for a while before sending it anyway.
+                let message = match held {
+                    Some(_) => match rx.recv_timeout(REORDER_TIMEOUT) {
+                        Ok(message) => Some(message),
+                        Err(RecvTimeoutError::Timeout) => None,
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    },
+                    None => match rx.recv() {
+                        Ok(message) => Some(message),
+                        Err(_) => break,
+                    },
+                };
+                let Some(message) = message else {
+                    _ = inner_tx.send(held.take().expect("no held message"));
+                    continue;
+                };
+                for message in link.step(message, &mut held, self.started.elapsed()) {
+                    _ = inner_tx.send(message);
+                }
+            }
+            // Flush a held message and disconnect the inner transport.
+            if let Some(message) = held {
+                _ = inner_tx.send(message);
+            }
+            drop(inner_tx);
+        });
+    }
+}
+
+/// A link from the local node to a peer, which decides the faults to inject
+/// into its messages.
+struct Link<'a> {
+    from: raft::NodeID,
+    to: raft::NodeID,
+    opts: &'a ChaosOptions,
+    /// The link's RNG, seeded by the seed and node pair.
+    rng: StdRng,
+}
+
+impl<'a> Link<'a> {
+    /// Creates a new link between the given nodes.
+    fn new(from: raft::NodeID, to: raft::NodeID, opts: &'a ChaosOptions) -> Self {
+        let seed = opts.seed ^ ((from as u64) << 8 | to as u64);
+        Self { from, to, opts, rng: StdRng::seed_from_u64(seed) }
+    }
+
+    /// Steps a message through the link at the given time, returning the
+    /// messages to send. A held back message is sent after the next one.
+    fn step(
+        &mut self,
+        message: raft::Envelope,
+        held: &mut Option<raft::Envelope>,
+        elapsed: Duration,
+    ) -> Vec<raft::Envelope> {
+        if self.opts.partitions.iter().any(|p| p.separates(self.from, self.to, elapsed)) {
+            return held.take().into_iter().collect();
+        }
+        if self.rng.gen_bool(self.opts.drop) {
+            return held.take().into_iter().collect();
+        }
+        let mut messages = Vec::with_capacity(3);
+        if self.rng.gen_bool(self.opts.duplicate) {
+            messages.push(message.clone());
+        }
+        messages.push(message);
+        // Hold back the message, unless it's overtaking a held message which is
+        // then sent after it.
+        if held.is_none() && self.rng.gen_bool(self.opts.reorder) {
+            *held = messages.pop();
+            return messages;
+        }
+        messages.extend(held.take());
+        messages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::MemoryNetwork;
+
+    use itertools::Itertools as _;
+
+    /// Creates a heartbeat message from node 1 to node 2, using the last index
+    /// as a sequence number.
+    fn message(seq: raft::Index) -> raft::Envelope {
+        let message = raft::Message::Heartbeat { last_index: seq, commit_index: 0, read_seq: 0 };
+        raft::Envelope { from: 1, to: 2, term: 1, message }
+    }
+
+    /// Steps the messages 1..=count through a link, and returns the sequence
+    /// numbers of the sent messages.
+    fn run(opts: &ChaosOptions, count: raft::Index) -> Vec<raft::Index> {
+        let mut link = Link::new(1, 2, opts);
+        let mut held = None;
+        let mut sent: Vec<raft::Envelope> = Vec::new();
+        for seq in 1..=count {
+            sent.extend(link.step(message(seq), &mut held, Duration::ZERO));
+        }
+        sent.extend(held);
+        sent.into_iter()
+            .map(|e| match e.message {
+                raft::Message::Heartbeat { last_index, .. } => last_index,
+                message => panic!("unexpected message {message:?}"),
+            })
+            .collect()
+    }
+
+    /// Faults are injected deterministically for a given seed.
+    #[test]
+    fn link_faults() {
+        let opts = ChaosOptions { drop: 0.2, duplicate: 0.2, reorder: 0.2, ..Default::default() };
+        let sent = run(&opts, 100);
+        assert_eq!(sent, run(&opts, 100));
+        assert_ne!(sent, run(&ChaosOptions { seed: 1, ..opts.clone() }, 100));
+
+        // Some messages are dropped, duplicated, and reordered.
+        assert!((1..=100).any(|seq| !sent.contains(&seq)));
+        assert!(sent.iter().duplicates().next().is_some());
+        assert!(sent.windows(2).any(|w| w[0] > w[1]));
+
+        // Without faults, all messages are sent in order.
+        assert_eq!(run(&ChaosOptions::default(), 100), (1..=100).collect::<Vec<_>>());
+
+        // Always reordering swaps pairs of messages.
+        let opts = ChaosOptions { reorder: 1.0, ..Default::default() };
+        assert_eq!(run(&opts, 5), vec![2, 1, 4, 3, 5]);
+    }
+
+    /// Partitions drop messages in both directions during their time window.
+    #[test]
+    fn partition() {
+        let partition = ChaosPartition { nodes: (2, 1), start_ms: 100, end_ms: 200 };
+        for (from, to) in [(1, 2), (2, 1)] {
+            assert!(!partition.separates(from, to, Duration::from_millis(99)));
+            assert!(partition.separates(from, to, Duration::from_millis(100)));
+            assert!(partition.separates(from, to, Duration::from_millis(199)));
+            assert!(!partition.separates(from, to, Duration::from_millis(200)));
+        }
+        assert!(!partition.separates(1, 3, Duration::from_millis(150)));
+    }
+
+    /// Messages pass through the chaos transport to the inner transport,
+    /// except across a partition.
+    #[test]
+    fn transport() -> Result<()> {
+        let network = MemoryNetwork::new();
+        let opts = ChaosOptions {
+            partitions: vec![ChaosPartition { nodes: (1, 3), start_ms: 0, end_ms: u64::MAX }],
+            ..Default::default()
+        };
+        let t1 = ChaosTransport::new(1, network.transport(1), opts)?;
+        let (step2_tx, step2_rx) = crossbeam::channel::unbounded();
+        let (step3_tx, step3_rx) = crossbeam::channel::unbounded();
+        network.transport(2).receive(step2_tx)?;
+        network.transport(3).receive(step3_tx)?;
+
+        let (peer2_tx, peer2_rx) = crossbeam::channel::unbounded();
+        let (peer3_tx, peer3_rx) = crossbeam::channel::unbounded();
+        std::thread::scope(|s| {
+            s.spawn(|| t1.send(2, peer2_rx));
+            s.spawn(|| t1.send(3, peer3_rx));
+            peer2_tx.send(message(1)).expect("send failed");
+            peer3_tx.send(raft::Envelope { to: 3, ..message(1) }).expect("send failed");
+            drop((peer2_tx, peer3_tx));
+        });
+        assert_eq!(step2_rx.try_iter().collect::<Vec<_>>(), vec![message(1)]);
+        assert_eq!(step3_rx.try_iter().count(), 0);
+        Ok(())
+    }
+
+    /// Invalid options are rejected.
+    #[test]
+    fn validate() {
+        assert!(ChaosOptions { drop: 1.5, ..Default::default() }.validate().is_err());
+        let partitions = vec![ChaosPartition { nodes: (1, 2), start_ms: 2, end_ms: 1 }];
+        assert!(ChaosOptions { partitions, ..Default::default() }.validate().is_err());
+    }
+}
@@ -0,0 +1,249 @@
+//! Operation history recording, for black-box consistency checking with tools
+//! like Elle or Porcupine. When enabled via Server::enable_history(), the
+//! server appends an event to a JSON Lines file when a client SQL statement is
+//! invoked, and another when it completes. The format follows Jepsen histories,
+//! for example:
+//!
+//! ```text
+//! {"type":"invoke","process":257,"node":1,"time":1718000000000000000,"f":"execute","value":{"statement":"INSERT INTO t VALUES (1)"}}
+//! {"type":"ok","process":257,"node":1,"time":1718000000001000000,"f":"execute","value":{"Insert":{"count":1}}}
+//! ```
+//!
+//! * `type`: `invoke` when the statement is received, and `ok` when it
+//!   succeeds, `fail` when it errors without taking effect, or `info` when the
+//!   outcome is unknown, e.g. a Raft leader change aborted a write that may
+//!   still commit.
+//!
+//! * `process`: the client session. Sessions execute one statement at a time.
+//!   After an `info` event the session is given a new process ID, since the
+//!   unknown operation may take effect concurrently with later ones. Process
+//!   IDs are unique across nodes, so histories from several nodes can be
+//!   merged.
+//!
+//! * `time`: the wall-clock time in Unix nanoseconds. Merged histories from
+//!   several nodes are only as accurate as the nodes' clock synchronization.
+//!
+//! * `f`: `execute` for executed statements, including prepared statements and
+//!   HTTP queries, or `query` for streamed queries.
+//!
+//! * `value`: for `invoke`, the statement and any prepared statement
+//!   parameters. For `ok`, the statement result as returned by the HTTP
+//!   interface. For `fail` and `info`, the error and its SQLSTATE code.
+//!
+//! Only SQL statements are recorded, not other client requests such as status
+//! requests. Recording flushes every event to the file, which is slow, so it's
+//! intended for testing.
+
+use crate::error::{Error, Result};
+use crate::raft::NodeID;
+use crate::sql;
+
+use log::error;
+use serde::Serialize;
+use serde_json::json;
+use std::io::Write as _;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+/// An operation history file, see the module documentation.
+pub struct History {
+    /// The local node ID.
+    node: NodeID,
+    /// The history file.
+    file: Mutex<std::io::BufWriter<std::fs::File>>,
+    /// The sequence number of the next process on this node.
+    next_process: AtomicU64,
+}
+
+impl History {
+    /// Opens a history file for the given node, appending to it if it exists.
+    pub fn open(path: impl AsRef<Path>, node: NodeID) -> Result<Self> {
+        let file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            node,
+            file: Mutex::new(std::io::BufWriter::new(file)),
+            next_process: AtomicU64::new(1),
+        })
+    }
+
+    /// Returns a new process for a client session.
+    pub(super) fn process(&self) -> Process<'_> {
+        Process { history: self, id: self.next_process_id() }
+    }
+
+    /// Allocates a process ID. The node ID is used as the low byte, to make
+    /// the ID unique across nodes.
+    fn next_process_id(&self) -> u64 {
+        self.next_process.fetch_add(1, Ordering::SeqCst) << 8 | self.node as u64
+    }
+
+    /// Appends an event to the file and flushes it.
+    fn write(&self, event: &Event) -> Result<()> {
+        let mut file = self.file.lock()?;
+        serde_json::to_writer(&mut *file, event)?;
+        file.write_all(b"\n")?;
+        file.flush()?;
+        Ok(())
+    }
+}
+
+/// A client process, which records the invocations and completions of a
+/// session's statements.
+pub(super) struct Process<'a> {
+    history: &'a History,
+    id: u64,
+}
+
+impl Process<'_> {
+    /// Records an invoked statement, with optional prepared statement
+    /// parameters. Passwords are redacted, including all parameters if one of
+    /// them is a password.
+    pub(super) fn invoke(&self, f: Function, statement: &str, params: Option<serde_json::Value>) {
+        let (statement, password_param) = sql::parser::redact(statement);
+        let mut value = json!({ "statement": statement });
+        if let Some(params) = params {
+            value["params"] = if password_param { json!("***") } else { params };
+        }
+        self.record(EventType::Invoke, f, value);
+    }
+
+    /// Records the result of the last invoked statement.
+    pub(super) fn complete(&mut self, f: Function, result: &Result<serde_json::Value>) {
+        match result {
+            Ok(value) => self.record(EventType::Ok, f, value.clone()),
+            Err(err) => {
+                let value = json!({ "error": err.to_string(), "code": err.code().sqlstate() });
+                if Self::is_indeterminate(err) {
+                    self.record(EventType::Info, f, value);
+                    self.id = self.history.next_process_id();
+                } else {
+                    self.record(EventType::Fail, f, value);
+                }
+            }
+        }
+    }
+
+    /// Returns whether a statement error leaves its outcome unknown. A Raft
+    /// abort or timeout may happen after a write was proposed, and an IO error
+    /// may happen after it was applied.
+    fn is_indeterminate(err: &Error) -> bool {
+        matches!(err.root(), Error::Abort | Error::Timeout | Error::IO(_))
+    }
+
+    /// Records an event. Errors are logged rather than failing the statement.
+    fn record(&self, kind: EventType, f: Function, value: serde_json::Value) {
+        let time = UNIX_EPOCH.elapsed().unwrap_or_default().as_nanos() as u64;
+        let event = Event { kind, process: self.id, node: self.history.node, time, f, value };
+        if let Err(err) = self.history.write(&event) {
+            error!("Failed to record history event: {err}");
+        }
+    }
+}
+
+/// A recorded operation kind.
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(super) enum Function {
+    /// An executed statement.
+    Execute,
+    /// A streamed query.
+    Query,
+}
+
+/// A history event type.
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+enum EventType {
+    Invoke,
+    Ok,
+    Fail,
+    Info,
+}
+
+/// A history event, see the module documentation.
+#[derive(Serialize)]
+struct Event {
+    #[serde(rename = "type")]
+    kind: EventType,
+    process: u64,
+    node: NodeID,
+    time: u64,
+    f: Function,
+    value: serde_json::Value,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Events are appended as JSON lines, and indeterminate errors allocate a
+    /// new process ID.
+    #[test]
+    fn record() -> Result<()> {
+        let dir = tempfile::TempDir::with_prefix("toydb")?;
+        let path = dir.path().join("history");
+        let history = History::open(&path, 3)?;
+        let mut process = history.process();
+        process.invoke(Function::Execute, "SELECT 1", None);
+        process.complete(Function::Execute, &Ok(json!({ "rows": [[1]] })));
+        process.invoke(Function::Query, "SELECT ?", Some(json!([1])));
+        process.complete(Function::Query, &Err(Error::Abort));
+        process.invoke(Function::Execute, "SELECT x", None);
+        process.complete(Function::Execute, &Err(Error::InvalidInput("unknown column".into())));
+        drop(history);
+
+        let events: Vec<serde_json::Value> = std::fs::read_to_string(&path)?
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<std::result::Result<_, _>>()?;
+        let summary: Vec<_> = events
+            .iter()
+            .map(|e| {
+                (e["type"].as_str().unwrap(), e["process"].as_u64().unwrap(), e["node"].clone())
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("invoke", 259, json!(3)),
+                ("ok", 259, json!(3)),
+                ("invoke", 259, json!(3)),
+                ("info", 259, json!(3)),
+                ("invoke", 515, json!(3)),
+                ("fail", 515, json!(3)),
+            ]
+        );
+        assert_eq!(events[2]["value"], json!({ "statement": "SELECT ?", "params": [1] }));
+        assert_eq!(
+            events[5]["value"]["code"],
+            json!(Error::InvalidInput(String::new()).code().sqlstate())
+        );
+        Ok(())
+    }
+
+    /// Passwords are redacted, as are the parameters of statements with a
+    /// password parameter.
+    #[test]
+    fn redact() -> Result<()> {
+        let dir = tempfile::TempDir::with_prefix("toydb")?;
+        let path = dir.path().join("history");
+        let history = History::open(&path, 1)?;
+        let process = history.process();
+        process.invoke(Function::Execute, "CREATE USER alice PASSWORD 'secret'", None);
+        process.invoke(Function::Execute, "ALTER USER ? PASSWORD ?", Some(json!(["a", "s"])));
+        drop(history);
+
+        let events: Vec<serde_json::Value> = std::fs::read_to_string(&path)?
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<std::result::Result<_, _>>()?;
+        assert_eq!(events[0]["value"], json!({ "statement": "CREATE USER alice PASSWORD '***'" }));
+        assert_eq!(
+            events[1]["value"],
+            json!({ "statement": "ALTER USER ? PASSWORD ?", "params": "***" })
+        );
+        Ok(())
+    }
+}
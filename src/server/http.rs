@@ -0,0 +1,488 @@
+//! A minimal HTTP interface, for scripts and dashboards that can't use the Rust
+//! client. It exposes the following endpoints, returning JSON:
+//!
+//! * `POST /query`: executes the SQL statement given as the request body.
+//!   Queries return `{"columns": [...], "rows": [[...], ...]}`, EXPLAIN returns
+//!   `{"plan": "..."}`, and other statements return their statement result,
+//!   e.g. `{"Insert": {"count": 1}}`.
+//!
+//! * `GET /status`: returns the server status.
+//!
+//! * `GET /maintenance`: returns the status of each maintenance task, see the
+//!   maintenance module. Times are in milliseconds, and `last_run` is a Unix
+//!   timestamp.
+//!
+//! * `GET /healthz`: a liveness check, for orchestrators like Kubernetes.
+//!   Returns `{"status": "ok"}` if the server is running.
+//!
+//! * `GET /readyz`: a readiness check. Returns `{"ready": true, "checks":
+//!   {...}}` if the server isn't shutting down, the local Raft node has a
+//!   leader and writable storage, and its apply lag (committed but unapplied
+//!   log entries) is within the limit. Otherwise, returns 503 Service
+//!   Unavailable with `"ready": false` and the reason for each failed check.
+//!
+//! Errors return `{"error": "..."}` with an appropriate status code, and the
+//! SQLSTATE error code as `"code"` for toyDB errors (see ErrorCode). If client
+//! authentication is enabled, requests must use HTTP basic authentication,
+//! except for the health checks. While the server is shutting down, requests
+//! return 503 Service Unavailable. Health checks bypass admission control,
+//! such that an overloaded server isn't considered dead.
+//!
+//! Only a small subset of HTTP/1.1 is supported: a single request per
+//! connection, with a Content-Length body (no chunked encoding). Each request
+//! uses a separate session, so explicit transactions can't span requests.
+
+use super::{Admission, Drain, Server, SessionOptions, Status, Stream, TaskStatus, TlsAcceptor};
+use super::{Function, History, Process};
+use crate::errinput;
+use crate::error::{Error, Result};
+use crate::sql;
+use crate::sql::engine::{Engine as _, QueryCache, StatementResult};
+use crate::sql::types::Value;
+
+use base64::Engine as _;
+use log::{debug, error, info};
+use serde_json::json;
+use std::io::{BufRead as _, BufReader, Read as _, Write as _};
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::UNIX_EPOCH;
+
+/// The maximum size of the request line and headers.
+const MAX_HEADER_SIZE: usize = 8 * 1024;
+
+/// The maximum size of a request body.
+const MAX_BODY_SIZE: usize = 1024 * 1024;
+
+/// The maximum number of request headers.
+const MAX_HEADERS: usize = 32;
+
+/// The read and write timeout of HTTP connections, such that slow or idle
+/// clients can't hold on to a connection thread indefinitely. Doesn't limit
+/// statement execution.
+const IO_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// An HTTP request.
+struct Request {
+    method: String,
+    path: String,
+    /// The Authorization header, if any.
+    authorization: Option<String>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    /// Reads a request from the stream.
+    fn read(reader: &mut BufReader<Stream>) -> Result<Self> {
+        // Read the request line and headers, up to the empty line.
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            let limit = (MAX_HEADER_SIZE - head.len()) as u64;
+            if limit == 0 {
+                return errinput!("request headers too large");
+            }
+            if reader.by_ref().take(limit).read_until(b'\n', &mut head)? == 0 {
+                return errinput!("unexpected end of request");
+            }
+        }
+
+        let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+        let mut request = httparse::Request::new(&mut headers);
+        if request.parse(&head)?.is_partial() {
+            return errinput!("incomplete request");
+        }
+        let header = |name: &str| {
+            request
+                .headers
+                .iter()
+                .find(|h| h.name.eq_ignore_ascii_case(name))
+                .map(|h| String::from_utf8_lossy(h.value).into_owned())
+        };
+
+        let length = match header("Content-Length") {
+            Some(length) => {
+                length.trim().parse().or_else(|_| errinput!("invalid Content-Length"))?
+            }
+            None => 0,
+        };
+        if length > MAX_BODY_SIZE {
+            return errinput!("request body too large");
+        }
+        let authorization = header("Authorization");
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body)?;
+
+        Ok(Self {
+            method: request.method.unwrap_or_default().to_string(),
+            path: request.path.unwrap_or_default().to_string(),
+            authorization,
+            body,
+        })
+    }
+
+    /// Decodes HTTP basic authentication credentials, if given.
+    fn credentials(&self) -> Result<Option<(String, String)>> {
+        let Some(authorization) = &self.authorization else {
+            return Ok(None);
+        };
+        let Some(encoded) = authorization.strip_prefix("Basic ") else {
+            return errinput!("unsupported authorization scheme");
+        };
+        let decoded = base64::engine::general_purpose::STANDARD
+            .decode(encoded.trim())
+            .or_else(|_| errinput!("invalid basic authorization"))?;
+        let decoded = String::from_utf8(decoded)?;
+        let Some((user, password)) = decoded.split_once(':') else {
+            return errinput!("invalid basic authorization");
+        };
+        Ok(Some((user.to_string(), password.to_string())))
+    }
+}
+
+/// An HTTP response.
+struct Response {
+    status: u16,
+    body: serde_json::Value,
+}
+
+impl Response {
+    /// Creates a 200 OK response.
+    fn ok(body: serde_json::Value) -> Self {
+        Self { status: 200, body }
+    }
+
+    /// Creates an error response with the given status code.
+    fn error(status: u16, message: impl std::fmt::Display) -> Self {
+        Self { status, body: json!({ "error": message.to_string() }) }
+    }
+
+    /// Creates an error response for a toyDB error. Input errors are the
+    /// client's fault, aborted or conflicting transactions can be retried, and
+    /// overloaded servers can be retried later.
+    fn from_error(err: Error) -> Self {
+        let status = match err.root() {
+            Error::InvalidInput(_)
+            | Error::Syntax(_)
+            | Error::UniqueViolation(_)
+            | Error::ReadOnly
+            | Error::Cancelled
+            | Error::Timeout => 400,
+            Error::Abort | Error::Serialization => 409,
+            Error::InvalidData(_) | Error::IO(_) => 500,
+            Error::Overloaded(_) => 503,
+            Error::Context(..) => unreachable!("root error has no context"),
+        };
+        let body = json!({ "error": err.to_string(), "code": err.code().sqlstate() });
+        Self { status, body }
+    }
+
+    /// Writes the response to the stream.
+    fn write(&self, stream: &mut Stream) -> Result<()> {
+        let reason = match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            409 => "Conflict",
+            503 => "Service Unavailable",
+            _ => "Internal Server Error",
+        };
+        let body = serde_json::to_vec(&self.body)?;
+        let mut head = format!("HTTP/1.1 {} {reason}\r\n", self.status);
+        head.push_str("Content-Type: application/json\r\n");
+        head.push_str(&format!("Content-Length: {}\r\n", body.len()));
+        if self.status == 401 {
+            head.push_str("WWW-Authenticate: Basic realm=\"toydb\"\r\n");
+        }
+        head.push_str("Connection: close\r\n\r\n");
+        stream.write_all(head.as_bytes())?;
+        stream.write_all(&body)?;
+        Ok(stream.flush()?)
+    }
+}
+
+impl Server {
+    /// Accepts new HTTP connections and spawns threads to handle them. HTTP
+    /// connections count towards the SQL connection limit, and are rejected
+    /// with 503 Service Unavailable beyond it.
+    #[allow(clippy::too_many_arguments)]
+    pub(super) fn http_accept(
+        opts: SessionOptions,
+        listener: TcpListener,
+        tls: Option<TlsAcceptor>,
+        sql_engine: sql::engine::Raft,
+        query_cache: Option<Arc<QueryCache>>,
+        connections: &AtomicUsize,
+        drain: &Drain,
+        admission: &Admission,
+        maintenance: &Mutex<Vec<TaskStatus>>,
+        history: Option<&History>,
+    ) {
+        std::thread::scope(|s| loop {
+            let (socket, peer) = match listener.accept() {
+                Ok((socket, peer)) => (socket, peer),
+                Err(err) => {
+                    error!("HTTP accept failed: {err}");
+                    continue;
+                }
+            };
+            if let Err(err) = socket
+                .set_read_timeout(Some(IO_TIMEOUT))
+                .and_then(|()| socket.set_write_timeout(Some(IO_TIMEOUT)))
+            {
+                error!("HTTP client {peer} setup failed: {err}");
+                continue;
+            }
+            let socket = match &tls {
+                Some(acceptor) => match acceptor.accept(socket) {
+                    Ok(stream) => stream,
+                    Err(err) => {
+                        error!("HTTP client {peer} TLS setup failed: {err}");
+                        continue;
+                    }
+                },
+                None => Stream::Tcp(socket),
+            };
+            // Reject clients beyond the connection limit. The request is read
+            // before responding, since closing a socket with unread data
+            // resets the connection. The IO timeouts bound the thread.
+            if let Some(max) =
+                opts.max_connections.filter(|max| connections.load(Ordering::SeqCst) >= *max)
+            {
+                info!("Rejecting HTTP client {peer}: too many connections (limit {max})");
+                s.spawn(move || {
+                    let mut reader = BufReader::new(socket);
+                    _ = Request::read(&mut reader);
+                    let response =
+                        Response::error(503, format!("too many connections (limit {max})"));
+                    if let Err(err) = response.write(reader.get_mut()) {
+                        error!("HTTP client {peer} error: {err}");
+                    }
+                });
+                continue;
+            }
+            connections.fetch_add(1, Ordering::SeqCst);
+            let sql_engine = &sql_engine;
+            let query_cache = query_cache.clone();
+            s.spawn(move || {
+                let mut session = sql_engine.session();
+                session.set_memory_budget(opts.memory_budget);
+                session.set_query_cache(query_cache);
+                let result = Self::http_session(
+                    opts,
+                    socket,
+                    session,
+                    drain,
+                    admission,
+                    maintenance,
+                    history,
+                );
+                connections.fetch_sub(1, Ordering::SeqCst);
+                if let Err(err) = result {
+                    error!("HTTP client {peer} error: {err}");
+                }
+            });
+        })
+    }
+
+    /// Handles a single HTTP request. The request is registered as in-flight
+    /// with the drain, which rejects it if the server is shutting down, and is
+    /// subject to admission control. Queries are recorded to the history, if
+    /// any, with a separate process per request.
+    fn http_session(
+        opts: SessionOptions,
+        socket: Stream,
+        mut session: sql::engine::Session<sql::engine::Raft>,
+        drain: &Drain,
+        admission: &Admission,
+        maintenance: &Mutex<Vec<TaskStatus>>,
+        history: Option<&History>,
+    ) -> Result<()> {
+        let mut reader = BufReader::new(socket);
+        let response = match Request::read(&mut reader) {
+            Ok(request) => {
+                debug!("Received HTTP request {} {}", request.method, request.path);
+                match request.path.as_str() {
+                    "/healthz" | "/readyz" => Self::http_health(opts, request, &session, drain),
+                    _ => match drain.begin() {
+                        Ok(_guard) => match admission.admit(None) {
+                            Ok(_admitted) => {
+                                let process = history.map(History::process);
+                                Self::http_handle(opts, request, &mut session, maintenance, process)
+                            }
+                            Err(err) => Response::from_error(err),
+                        },
+                        Err(err) => Response::error(503, err),
+                    },
+                }
+            }
+            Err(err) => Response::from_error(err),
+        };
+        response.write(reader.get_mut())
+    }
+
+    /// Authenticates and routes an HTTP request.
+    fn http_handle(
+        opts: SessionOptions,
+        request: Request,
+        session: &mut sql::engine::Session<sql::engine::Raft>,
+        maintenance: &Mutex<Vec<TaskStatus>>,
+        mut process: Option<Process>,
+    ) -> Response {
+        let credentials = match request.credentials() {
+            Ok(credentials) => credentials,
+            Err(err) => return Response::error(401, err),
+        };
+        let authorized = match credentials {
+            Some((user, password)) => session.authenticate(&user, &password),
+            None if opts.auth => Self::sql_bootstrap(session),
+            None => Ok(()),
+        };
+        if let Err(err) = authorized {
+            return Response::error(401, err);
+        }
+
+        let result = match (request.method.as_str(), request.path.as_str()) {
+            ("POST", "/query") => match String::from_utf8(request.body) {
+                Ok(query) => {
+                    if let Some(process) = &process {
+                        process.invoke(Function::Execute, &query, None);
+                    }
+                    let result = session.execute(&query).and_then(Self::http_result);
+                    if let Some(process) = &mut process {
+                        process.complete(Function::Execute, &result);
+                    }
+                    result
+                }
+                Err(err) => Err(err.into()),
+            },
+            ("GET", "/status") => Self::sql_status(opts, session)
+                .and_then(|status| Ok(serde_json::to_value::<Status>(status)?)),
+            ("GET", "/maintenance") => {
+                let tasks = maintenance.lock().map_err(Error::from);
+                tasks.map(|tasks| tasks.iter().map(Self::http_task).collect())
+            }
+            (_, "/query" | "/status" | "/maintenance") => {
+                return Response::error(405, format!("method {} not allowed", request.method))
+            }
+            (_, path) => return Response::error(404, format!("unknown path {path}")),
+        };
+        match result {
+            Ok(body) => Response::ok(body),
+            Err(err) => Response::from_error(err),
+        }
+    }
+
+    /// Handles liveness and readiness checks, see the module documentation.
+    fn http_health(
+        opts: SessionOptions,
+        request: Request,
+        session: &sql::engine::Session<sql::engine::Raft>,
+        drain: &Drain,
+    ) -> Response {
+        if request.method != "GET" {
+            return Response::error(405, format!("method {} not allowed", request.method));
+        }
+        if request.path == "/healthz" {
+            return Response::ok(json!({ "status": "ok" }));
+        }
+
+        // A failed health request fails the storage check, and the checks
+        // that depend on its status are skipped.
+        let health = session.health();
+        let status = health.as_ref().ok();
+        let shutdown = match drain.is_draining() {
+            true => Err("server is shutting down".to_string()),
+            false => Ok(()),
+        };
+        let leader = match status {
+            Some(status) if status.leader.is_none() => Err("no Raft leader".to_string()),
+            Some(_) | None => Ok(()),
+        };
+        let storage = health.as_ref().map(|_| ()).map_err(|err| err.to_string());
+        let lag = status.map(|s| s.commit_index.saturating_sub(s.applied_index)).unwrap_or(0);
+        let apply_lag = match lag > opts.max_apply_lag {
+            true => Err(format!("apply lag {lag} exceeds {}", opts.max_apply_lag)),
+            false => Ok(()),
+        };
+
+        let checks = [
+            ("shutdown", shutdown),
+            ("leader", leader),
+            ("storage", storage),
+            ("apply_lag", apply_lag),
+        ];
+        let ready = checks.iter().all(|(_, result)| result.is_ok());
+        let checks: serde_json::Map<_, _> = checks
+            .into_iter()
+            .map(|(name, result)| (name.to_string(), result.err().unwrap_or("ok".into()).into()))
+            .collect();
+        let body = json!({ "ready": ready, "checks": checks });
+        match ready {
+            true => Response::ok(body),
+            false => Response { status: 503, body },
+        }
+    }
+
+    /// Converts a maintenance task status to JSON.
+    fn http_task(status: &TaskStatus) -> serde_json::Value {
+        let last_run = status
+            .last_run
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_millis() as u64);
+        let (last_result, last_error) = match &status.last_result {
+            Some(Ok(result)) => (Some(result.as_str()), None),
+            Some(Err(error)) => (None, Some(error.as_str())),
+            None => (None, None),
+        };
+        json!({
+            "name": status.name,
+            "interval_ms": status.interval.as_millis() as u64,
+            "runs": status.runs,
+            "failures": status.failures,
+            "last_run": last_run,
+            "last_duration_ms": status.last_duration.as_millis() as u64,
+            "last_result": last_result,
+            "last_error": last_error,
+        })
+    }
+
+    /// Converts a statement result to JSON.
+    pub(super) fn http_result(result: StatementResult) -> Result<serde_json::Value> {
+        Ok(match result {
+            StatementResult::Select { columns, rows } => json!({
+                "columns": columns.iter().map(|c| c.as_header()).collect::<Vec<_>>(),
+                "rows": rows
+                    .into_iter()
+                    .map(|row| row.into_iter().map(Self::http_value).collect())
+                    .collect::<Vec<Vec<_>>>(),
+            }),
+            StatementResult::Explain(plan, format, analysis) => {
+                json!({ "plan": plan.explain(format, analysis.as_ref()) })
+            }
+            result => serde_json::to_value(result)?,
+        })
+    }
+
+    /// Converts a SQL value to JSON. Infinite and NaN floats can't be
+    /// represented as JSON numbers, so they're returned as strings. Points
+    /// are returned as [x, y] arrays.
+    pub(super) fn http_value(value: Value) -> serde_json::Value {
+        match value {
+            Value::Null => serde_json::Value::Null,
+            Value::Boolean(b) => b.into(),
+            Value::Integer(i) => i.into(),
+            Value::Float(f) => match serde_json::Number::from_f64(f) {
+                Some(number) => number.into(),
+                None => f.to_string().into(),
+            },
+            Value::String(s) => s.into(),
+            Value::Point(x, y) => serde_json::Value::Array(vec![
+                Self::http_value(x.into()),
+                Self::http_value(y.into()),
+            ]),
+        }
+    }
+}
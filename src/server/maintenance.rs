@@ -0,0 +1,317 @@
+//! Periodic background maintenance.
+//!
+//! The server runs maintenance tasks in a background thread, each at its own
+//! interval. Tasks are executed one at a time, and a task that runs late (e.g.
+//! because a previous task took a long time) is run as soon as possible and
+//! then rescheduled one interval later. Tasks aren't run while the server is
+//! shutting down.
+//!
+//! The built-in tasks are storage compaction, see MaintenanceTask::compact(),
+//! changefeed emission, see MaintenanceTask::changefeeds(), and purging of
+//! expired rows, see MaintenanceTask::ttl(). BitCask storage otherwise only
+//! compacts when it's opened, so long-running nodes would accumulate garbage
+//! indefinitely. Embedders can add their own tasks via
+//! Server::add_maintenance_task().
+//!
+//! There are no tasks for MVCC garbage collection, statistics, or snapshots,
+//! since toyDB doesn't have them: MVCC retains all versions for time-travel
+//! queries, the planner doesn't use statistics, and the Raft log is never
+//! truncated.
+//!
+//! The status of each task (runs, failures, and the time, duration, and result
+//! of its last run) is reported via the HTTP `GET /maintenance` endpoint.
+//! Failures are also logged, and the task is retried at its next interval.
+
+use super::changefeed::Emitter;
+use super::Drain;
+use crate::errinput;
+use crate::error::Result;
+use crate::sql;
+use crate::sql::engine::{Engine as _, Transaction as _};
+use crate::sql::execution::purge_expired;
+
+use log::{debug, error};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+/// The maximum number of expired rows to purge per transaction.
+const TTL_PURGE_BATCH_SIZE: usize = 1000;
+
+/// A maintenance task function. It's given a Raft SQL engine for the local
+/// node, and returns a human-readable result.
+type TaskFn = Box<dyn FnMut(&sql::engine::Raft) -> Result<String> + Send>;
+
+/// A periodic maintenance task, see the module documentation.
+pub struct MaintenanceTask {
+    /// The task name, for status reporting.
+    name: String,
+    /// The interval between task runs.
+    interval: Duration,
+    /// The task function.
+    run: TaskFn,
+}
+
+impl MaintenanceTask {
+    /// Creates a new maintenance task which runs the given function at the
+    /// given interval, first after one interval has passed.
+    pub fn new(
+        name: impl Into<String>,
+        interval: Duration,
+        run: impl FnMut(&sql::engine::Raft) -> Result<String> + Send + 'static,
+    ) -> Result<Self> {
+        if interval.is_zero() {
+            return errinput!("maintenance task interval must be positive");
+        }
+        Ok(Self { name: name.into(), interval, run: Box::new(run) })
+    }
+
+    /// Creates a storage compaction task named "compact", which compacts the
+    /// local node's Raft log and SQL storage if their garbage exceeds both the
+    /// given fraction of the total size and the given number of bytes.
+    pub fn compact(
+        interval: Duration,
+        garbage_min_fraction: f64,
+        garbage_min_bytes: u64,
+    ) -> Result<Self> {
+        Self::new("compact", interval, move |engine| {
+            let (log, state) = engine.compact(garbage_min_fraction, garbage_min_bytes)?;
+            Ok(match (log, state) {
+                (false, false) => "no compaction needed",
+                (true, false) => "compacted Raft log",
+                (false, true) => "compacted SQL storage",
+                (true, true) => "compacted Raft log and SQL storage",
+            }
+            .to_string())
+        })
+    }
+
+    /// Creates a changefeed task named "changefeeds", which emits pending
+    /// changes of all changefeeds to their sinks. See the changefeed module.
+    pub fn changefeeds(interval: Duration) -> Result<Self> {
+        let mut emitter = Emitter::new();
+        Self::new("changefeeds", interval, move |engine| {
+            let count = emitter.emit(&engine.begin_read_only()?)?;
+            Ok(format!("emitted {count} changes"))
+        })
+    }
+
+    /// Creates a TTL task named "ttl", which deletes expired rows from tables
+    /// with a row TTL (see sql::types::Ttl), in transactions of up to
+    /// TTL_PURGE_BATCH_SIZE rows. Only the Raft leader purges rows, to avoid
+    /// conflicting deletes from multiple nodes; the deletes are replicated.
+    pub fn ttl(interval: Duration) -> Result<Self> {
+        Self::new("ttl", interval, move |engine| {
+            if engine.health()?.role != "leader" {
+                return Ok("skipped on non-leader".to_string());
+            }
+            let mut count = 0;
+            loop {
+                let mut txn = engine.begin()?;
+                let now = SystemTime::now();
+                let purged = match purge_expired(&mut txn, now, TTL_PURGE_BATCH_SIZE) {
+                    Ok(purged) => purged,
+                    Err(err) => {
+                        txn.rollback()?;
+                        return Err(err);
+                    }
+                };
+                txn.commit()?;
+                count += purged;
+                if purged < TTL_PURGE_BATCH_SIZE as u64 {
+                    return Ok(format!("purged {count} expired rows"));
+                }
+            }
+        })
+    }
+}
+
+/// The status of a maintenance task.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TaskStatus {
+    /// The task name.
+    pub name: String,
+    /// The interval between task runs.
+    pub interval: Duration,
+    /// The number of times the task has run.
+    pub runs: u64,
+    /// The number of failed task runs.
+    pub failures: u64,
+    /// When the task last started, if it has run.
+    pub last_run: Option<SystemTime>,
+    /// How long the last run took.
+    pub last_duration: Duration,
+    /// The result of the last run, or its error.
+    pub last_result: Option<std::result::Result<String, String>>,
+}
+
+/// Runs maintenance tasks at their intervals, recording their status.
+pub(super) struct Scheduler {
+    /// The tasks, with the time of their next run.
+    tasks: Vec<(MaintenanceTask, Instant)>,
+}
+
+impl Scheduler {
+    /// Creates a new scheduler for the given tasks, starting now.
+    pub(super) fn new(tasks: Vec<MaintenanceTask>, now: Instant) -> Self {
+        let tasks = tasks
+            .into_iter()
+            .map(|task| {
+                let next = now + task.interval;
+                (task, next)
+            })
+            .collect();
+        Self { tasks }
+    }
+
+    /// Returns the initial status of the tasks, before they have run.
+    pub(super) fn status(&self) -> Vec<TaskStatus> {
+        self.tasks
+            .iter()
+            .map(|(task, _)| TaskStatus {
+                name: task.name.clone(),
+                interval: task.interval,
+                runs: 0,
+                failures: 0,
+                last_run: None,
+                last_duration: Duration::ZERO,
+                last_result: None,
+            })
+            .collect()
+    }
+
+    /// Returns the index of the next task to run and when to run it, if any.
+    /// Ties are broken by task order.
+    fn next(&self) -> Option<(usize, Instant)> {
+        self.tasks.iter().enumerate().map(|(i, (_, next))| (i, *next)).min_by_key(|(_, next)| *next)
+    }
+
+    /// Runs the given task, recording its result in the status and scheduling
+    /// its next run one interval after it was due, or after now if it's late.
+    fn run_task(
+        &mut self,
+        index: usize,
+        engine: &sql::engine::Raft,
+        status: &mut TaskStatus,
+        now: Instant,
+    ) {
+        let (task, next) = &mut self.tasks[index];
+        debug!("Running maintenance task {}", task.name);
+        let started = Instant::now();
+        let result = (task.run)(engine);
+        status.runs += 1;
+        status.last_run = Some(SystemTime::now());
+        status.last_duration = started.elapsed();
+        status.last_result = Some(match result {
+            Ok(result) => {
+                debug!("Maintenance task {} succeeded: {result}", task.name);
+                Ok(result)
+            }
+            Err(err) => {
+                error!("Maintenance task {} failed: {err}", task.name);
+                status.failures += 1;
+                Err(err.to_string())
+            }
+        });
+        *next = std::cmp::max(*next + task.interval, now);
+    }
+
+    /// Runs the tasks indefinitely, updating the shared status. Tasks that
+    /// come due while the server is draining are skipped.
+    pub(super) fn run(
+        mut self,
+        engine: sql::engine::Raft,
+        status: &Mutex<Vec<TaskStatus>>,
+        drain: &Drain,
+    ) {
+        let Some((mut index, mut next)) = self.next() else {
+            return;
+        };
+        loop {
+            std::thread::sleep(next.saturating_duration_since(Instant::now()));
+            if drain.is_draining() {
+                let (task, next) = &mut self.tasks[index];
+                *next += task.interval;
+            } else {
+                let mut task_status = status.lock().expect("mutex failed")[index].clone();
+                self.run_task(index, &engine, &mut task_status, Instant::now());
+                status.lock().expect("mutex failed")[index] = task_status;
+            }
+            (index, next) = self.next().expect("no maintenance tasks");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+
+    use std::sync::Arc;
+
+    const SEC: Duration = Duration::from_secs(1);
+
+    /// Tasks run at their own intervals, in order of their due time, and
+    /// record their results and failures.
+    #[test]
+    fn schedule() -> Result<()> {
+        let runs = Arc::new(Mutex::new(Vec::new()));
+        let (a_runs, b_runs) = (runs.clone(), runs.clone());
+        let a = MaintenanceTask::new("a", Duration::from_secs(2), move |_| {
+            a_runs.lock()?.push("a");
+            Ok("ok".to_string())
+        })?;
+        let b = MaintenanceTask::new("b", Duration::from_secs(3), move |_| {
+            b_runs.lock()?.push("b");
+            Err(Error::IO("disk full".to_string()))
+        })?;
+
+        let start = Instant::now();
+        let mut scheduler = Scheduler::new(vec![a, b], start);
+        let mut status = scheduler.status();
+        let (tx, _) = crossbeam::channel::unbounded();
+        let engine = sql::engine::Raft::new(tx);
+
+        // Run tasks as they come due, until 6 seconds have passed. The
+        // scheduler is never late.
+        let mut times = Vec::new();
+        while let Some((index, next)) =
+            scheduler.next().filter(|(_, next)| *next <= start + 6 * SEC)
+        {
+            times.push((next - start).as_secs());
+            scheduler.run_task(index, &engine, &mut status[index], next);
+        }
+        assert_eq!(times, vec![2, 3, 4, 6, 6]);
+        assert_eq!(*runs.lock()?, vec!["a", "b", "a", "a", "b"]);
+
+        assert_eq!((status[0].runs, status[0].failures), (3, 0));
+        assert_eq!(status[0].last_result, Some(Ok("ok".to_string())));
+        assert_eq!((status[1].runs, status[1].failures), (2, 2));
+        assert_eq!(status[1].last_result, Some(Err("io error: disk full".to_string())));
+        assert!(status[1].last_run.is_some());
+        Ok(())
+    }
+
+    /// A late task runs once when it's due, then reschedules from the time it
+    /// ran instead of catching up on missed runs.
+    #[test]
+    fn late() -> Result<()> {
+        let task = MaintenanceTask::new("a", SEC, |_| Ok(String::new()))?;
+        let start = Instant::now();
+        let mut scheduler = Scheduler::new(vec![task], start);
+        let mut status = scheduler.status();
+        let (tx, _) = crossbeam::channel::unbounded();
+        let engine = sql::engine::Raft::new(tx);
+
+        scheduler.run_task(0, &engine, &mut status[0], start + 10 * SEC);
+        assert_eq!(scheduler.next(), Some((0, start + 10 * SEC)));
+        scheduler.run_task(0, &engine, &mut status[0], start + 10 * SEC);
+        assert_eq!(scheduler.next(), Some((0, start + 11 * SEC)));
+        Ok(())
+    }
+
+    /// Task intervals must be positive.
+    #[test]
+    fn zero_interval() {
+        assert!(MaintenanceTask::new("a", Duration::ZERO, |_| Ok(String::new())).is_err());
+    }
+}
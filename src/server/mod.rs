@@ -0,0 +1,1392 @@
+mod admission;
+mod builder;
+mod changefeed;
+mod chaos;
+mod history;
+mod http;
+mod maintenance;
+mod outbox;
+#[cfg(feature = "quic")]
+mod quic;
+mod shutdown;
+mod tls;
+mod transport;
+
+pub use admission::AdmissionLimits;
+pub use builder::{ServerBuilder, ServerHandle};
+pub use chaos::{ChaosOptions, ChaosPartition, ChaosTransport};
+pub use history::History;
+pub use maintenance::{MaintenanceTask, TaskStatus};
+#[cfg(feature = "quic")]
+pub use quic::QuicTransport;
+pub use shutdown::ShutdownHandle;
+pub use tls::{Stream, TlsAcceptor, TlsConnector};
+pub use transport::{MemoryNetwork, MemoryTransport, TcpTransport, Transport};
+
+use crate::encoding;
+use crate::encoding::frame::{self, Frame, Kind};
+use crate::errinput;
+use crate::error::{Error, Result};
+use crate::raft;
+use crate::sql;
+use crate::sql::engine::{Catalog as _, Engine as _, QueryCache, StatementResult};
+use crate::sql::execution::CancelFlag;
+use crate::sql::types::{Label, Row, Table};
+use crate::storage;
+use admission::Admission;
+use history::{Function, Process};
+use maintenance::Scheduler;
+use outbox::Outbox;
+use shutdown::Drain;
+
+use crossbeam::channel::{Receiver, Sender};
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write as _;
+use std::net::{TcpListener, ToSocketAddrs};
+use std::os::unix::fs::{FileTypeExt as _, PermissionsExt as _};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+/// The default outbound Raft peer channel capacity, see
+/// Server::set_raft_peer_channel_capacity().
+const RAFT_PEER_CHANNEL_CAPACITY: usize = 1000;
+
+/// The default group commit batch size, see
+/// Server::set_raft_group_commit_max_batch().
+const RAFT_GROUP_COMMIT_MAX_BATCH: usize = 256;
+
+/// How long to wait for the first request from a client that is rejected
+/// because of the connection limit.
+const REJECT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// The default drain timeout during shutdown, see Server::set_drain_timeout().
+const DRAIN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// The default maximum apply lag for readiness, see
+/// Server::set_ready_max_apply_lag().
+const READY_MAX_APPLY_LAG: raft::Index = 1000;
+
+/// How long to wait for a new leader to be elected after transferring
+/// leadership during shutdown.
+const TRANSFER_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Cancellation flags for the statements of active SQL sessions, keyed by the
+/// session's random cancel key.
+type Cancellers = Arc<Mutex<HashMap<Uuid, CancelFlag>>>;
+
+/// A listener for SQL client connections.
+enum SqlListener {
+    /// A TCP listener, using TLS if given an acceptor.
+    Tcp(TcpListener, Option<TlsAcceptor>),
+    /// A Unix domain socket listener. Access is controlled by the socket file
+    /// permissions, so it doesn't use TLS.
+    Unix(UnixListener),
+}
+
+impl SqlListener {
+    /// Accepts a client connection, returning its stream and a peer name for
+    /// logging. Unix domain socket peers are unnamed, so the socket path is
+    /// used instead.
+    fn accept(&self) -> Result<(Stream, String)> {
+        match self {
+            Self::Tcp(listener, tls) => {
+                let (socket, peer) = listener.accept()?;
+                let stream = match tls {
+                    Some(acceptor) => acceptor.accept(socket)?,
+                    None => Stream::Tcp(socket),
+                };
+                Ok((stream, peer.to_string()))
+            }
+            Self::Unix(listener) => {
+                let (socket, _) = listener.accept()?;
+                let addr = listener.local_addr()?;
+                let path = addr.as_pathname().unwrap_or(Path::new("")).display();
+                Ok((Stream::Unix(socket), format!("unix:{path}")))
+            }
+        }
+    }
+}
+
+/// Options for SQL and HTTP client sessions.
+#[derive(Clone, Copy)]
+struct SessionOptions {
+    /// The local node ID.
+    id: raft::NodeID,
+    /// Whether replica reads are enabled.
+    replica_reads: bool,
+    /// Whether clients must authenticate.
+    auth: bool,
+    /// The maximum number of concurrent SQL client connections, if any.
+    max_connections: Option<usize>,
+    /// The memory budget of each statement in bytes, if any.
+    memory_budget: Option<usize>,
+    /// The maximum apply lag for HTTP readiness checks, see the http module.
+    max_apply_lag: raft::Index,
+    /// Whether to compress large responses, if the client requests it.
+    compression: bool,
+}
+
+/// Options for the Raft message loop, see raft_route() and raft_execute().
+#[derive(Clone, Copy)]
+struct RaftLoopOptions {
+    /// The interval between Raft node ticks.
+    tick_interval: std::time::Duration,
+    /// The maximum number of queued Raft messages and client requests to step
+    /// before syncing the Raft log.
+    group_commit_max_batch: usize,
+}
+
+/// A toyDB server. Routes messages to/from an inner Raft node.
+///
+/// * Listens for inbound SQL connections from clients via TCP, and optionally
+///   a Unix domain socket, and passes requests to the local Raft node.
+///
+/// * Optionally listens for inbound HTTP requests, see the http module.
+///
+/// * Receives inbound Raft messages from other toyDB nodes via a Transport
+///   (TCP by default) and passes them to the local Raft node.
+///
+/// * Sends outbound Raft messages from the local Raft node to other toyDB
+///   nodes via the Transport.
+pub struct Server {
+    /// The inner Raft node.
+    node: raft::Node,
+    /// Outbound messages from the Raft node.
+    node_rx: Receiver<raft::Envelope>,
+    /// Raft peer IDs and addresses.
+    peers: HashMap<raft::NodeID, String>,
+    /// Whether to compress large Raft messages sent to peers. Only used if the
+    /// peer also has compression enabled, as negotiated during the handshake.
+    raft_compression: bool,
+    /// Whether Raft peers communicate via QUIC instead of TCP.
+    #[cfg(feature = "quic")]
+    raft_quic: bool,
+    /// Whether to compress large SQL responses to clients. Only used if the
+    /// client requests compression during the handshake.
+    sql_compression: bool,
+    /// The interval between Raft node ticks.
+    raft_tick_interval: std::time::Duration,
+    /// The outbound message channel capacity of each Raft peer.
+    raft_peer_channel_capacity: usize,
+    /// The maximum number of Raft messages and requests to step per log sync.
+    raft_group_commit_max_batch: usize,
+    /// Whether a single-node cluster executes requests directly, bypassing
+    /// the Raft message loop.
+    raft_fast_path: bool,
+    /// Whether to serve read-only transactions from the local node when it's
+    /// a follower, with bounded staleness.
+    replica_reads: bool,
+    /// If set, SQL and Raft connections use TLS.
+    tls: Option<(TlsAcceptor, TlsConnector)>,
+    /// If set, injects faults into outbound Raft messages, for testing.
+    raft_chaos: Option<ChaosOptions>,
+    /// Whether SQL clients must authenticate as a user.
+    auth: bool,
+    /// The maximum number of concurrent SQL client connections, if any.
+    max_connections: Option<usize>,
+    /// The memory budget of each SQL statement in bytes, if any.
+    session_memory_budget: Option<usize>,
+    /// If set, caches query results for SQL and HTTP sessions.
+    query_cache: Option<Arc<QueryCache>>,
+    /// Rate and concurrency limits for client requests.
+    admission_limits: AdmissionLimits,
+    /// If set, serves HTTP requests on this listener.
+    http: Option<TcpListener>,
+    /// The maximum number of committed but unapplied Raft log entries for the
+    /// HTTP readiness check to pass.
+    ready_max_apply_lag: raft::Index,
+    /// If set, also serves SQL clients on this Unix domain socket listener.
+    unix_socket: Option<UnixListener>,
+    /// Periodic maintenance tasks.
+    maintenance_tasks: Vec<MaintenanceTask>,
+    /// How long to wait for in-flight requests to complete during shutdown.
+    drain_timeout: std::time::Duration,
+    /// If set, records client statements to an operation history.
+    history: Option<History>,
+    /// Shutdown requests from ShutdownHandles, with a completion channel. The
+    /// server retains the sender, so the receiver never disconnects.
+    shutdown_tx: Sender<Sender<Result<()>>>,
+    shutdown_rx: Receiver<Sender<Result<()>>>,
+}
+
+impl Server {
+    /// Creates a new toyDB server. The Raft options are validated first.
+    pub fn new(
+        id: raft::NodeID,
+        peers: HashMap<raft::NodeID, String>,
+        mut raft_log: raft::Log,
+        raft_state: Box<dyn raft::State>,
+        raft_opts: raft::Options,
+    ) -> Result<Self> {
+        raft_opts.validate()?;
+        // The Raft loops sync the log after each batch of messages, see
+        // raft_route() and raft_execute().
+        raft_log.enable_group_commit(true);
+        let (node_tx, node_rx) = crossbeam::channel::unbounded();
+        let (shutdown_tx, shutdown_rx) = crossbeam::channel::unbounded();
+        let node = raft::Node::new(
+            id,
+            peers.keys().copied().collect(),
+            raft_log,
+            raft_state,
+            node_tx,
+            raft_opts,
+        )?;
+        Ok(Self {
+            node,
+            peers,
+            node_rx,
+            raft_compression: true,
+            #[cfg(feature = "quic")]
+            raft_quic: false,
+            sql_compression: true,
+            raft_tick_interval: raft::TICK_INTERVAL,
+            raft_peer_channel_capacity: RAFT_PEER_CHANNEL_CAPACITY,
+            raft_group_commit_max_batch: RAFT_GROUP_COMMIT_MAX_BATCH,
+            raft_fast_path: true,
+            replica_reads: false,
+            tls: None,
+            raft_chaos: None,
+            auth: false,
+            max_connections: None,
+            session_memory_budget: None,
+            query_cache: None,
+            admission_limits: AdmissionLimits::default(),
+            http: None,
+            ready_max_apply_lag: READY_MAX_APPLY_LAG,
+            unix_socket: None,
+            maintenance_tasks: Vec::new(),
+            drain_timeout: DRAIN_TIMEOUT,
+            history: None,
+            shutdown_tx,
+            shutdown_rx,
+        })
+    }
+
+    /// Enables or disables compression of large Raft messages between peers.
+    /// Enabled by default. Compression is only used on a connection if both
+    /// peers have it enabled.
+    pub fn enable_raft_compression(&mut self, enable: bool) {
+        self.raft_compression = enable
+    }
+
+    /// Enables or disables QUIC for Raft peer connections, using QuicTransport
+    /// instead of TcpTransport in serve(). Disabled by default. QUIC requires
+    /// TLS, see enable_tls(), and must be enabled on all nodes.
+    #[cfg(feature = "quic")]
+    pub fn enable_raft_quic(&mut self, enable: bool) {
+        self.raft_quic = enable
+    }
+
+    /// Enables or disables compression of large SQL responses (e.g. result
+    /// sets with wide rows). Enabled by default. Compression is only used on a
+    /// connection if the client requests it.
+    pub fn enable_sql_compression(&mut self, enable: bool) {
+        self.sql_compression = enable
+    }
+
+    /// Sets the Raft tick interval, i.e. the duration of a logical Raft tick.
+    /// The Raft heartbeat interval and election timeout are given in ticks.
+    /// Defaults to raft::TICK_INTERVAL.
+    pub fn set_raft_tick_interval(&mut self, interval: std::time::Duration) -> Result<()> {
+        if interval.is_zero() {
+            return errinput!("Raft tick interval must be positive");
+        }
+        self.raft_tick_interval = interval;
+        Ok(())
+    }
+
+    /// Sets the outbound message channel capacity of each Raft peer, which
+    /// buffers messages when the peer is slow or unavailable. Beyond this,
+    /// messages are dropped, and Raft recovers them via retries (heartbeats
+    /// are dropped once it's half full, see the outbox module). It also bounds
+    /// the inbound message queue, such that the transport stops receiving from
+    /// peers while the local node falls behind. Defaults to 1000 messages.
+    pub fn set_raft_peer_channel_capacity(&mut self, capacity: usize) -> Result<()> {
+        if capacity == 0 {
+            return errinput!("Raft peer channel capacity must be positive");
+        }
+        self.raft_peer_channel_capacity = capacity;
+        Ok(())
+    }
+
+    /// Sets the maximum number of queued Raft messages and client requests to
+    /// step before syncing the Raft log to disk, i.e. the group commit batch
+    /// size. Larger batches amortize syncs on slow disks, at the cost of
+    /// latency. Defaults to 256.
+    pub fn set_raft_group_commit_max_batch(&mut self, max_batch: usize) -> Result<()> {
+        if max_batch == 0 {
+            return errinput!("Raft group commit batch size must be positive");
+        }
+        self.raft_group_commit_max_batch = max_batch;
+        Ok(())
+    }
+
+    /// Enables or disables the single-node fast path. Enabled by default. If
+    /// the cluster has a single node, client requests are executed directly
+    /// against the Raft node instead of being routed through the message loop,
+    /// which reduces write latency. The Raft log is the same either way. Has no
+    /// effect for multi-node clusters.
+    pub fn enable_raft_fast_path(&mut self, enable: bool) {
+        self.raft_fast_path = enable
+    }
+
+    /// Enables or disables replica reads. Disabled by default. If enabled, and
+    /// this node is a follower, read-only transactions (including implicit
+    /// transactions for individual SELECT statements) are executed against the
+    /// local state machine if it's up to date with the leader as of its last
+    /// heartbeat, see raft::Options::max_read_staleness. This offloads reads
+    /// from the leader, but they may not see the latest writes. Writes, and
+    /// reads when the follower is lagging, are forwarded to the leader.
+    pub fn enable_replica_reads(&mut self, enable: bool) {
+        self.replica_reads = enable
+    }
+
+    /// Enables TLS for SQL clients and Raft peers. Disabled by default. The
+    /// acceptor is used for inbound connections, and determines whether
+    /// clients and peers must present a trusted certificate. The connector is
+    /// used for outbound Raft connections, and should present this node's
+    /// certificate in case peers require it. Applies to the TCP transport in
+    /// serve(), other transports handle their own security.
+    pub fn enable_tls(&mut self, acceptor: TlsAcceptor, connector: TlsConnector) {
+        self.tls = Some((acceptor, connector))
+    }
+
+    /// Injects network faults into outbound Raft messages, for testing. Applies
+    /// to the TCP transport in serve(), other transports can be wrapped in a
+    /// ChaosTransport directly. All nodes should use the same options.
+    pub fn enable_raft_chaos(&mut self, opts: ChaosOptions) -> Result<()> {
+        opts.validate()?;
+        self.raft_chaos = Some(opts);
+        Ok(())
+    }
+
+    /// Enables or disables SQL client authentication. Disabled by default. If
+    /// enabled, clients must authenticate as a user (see CREATE USER) before
+    /// sending any other requests. As long as no users exist, clients can
+    /// connect without authenticating, such that the first user can be created.
+    pub fn enable_auth(&mut self, enable: bool) {
+        self.auth = enable
+    }
+
+    /// Sets the maximum number of concurrent SQL client connections, or None
+    /// for no limit (the default). Further connections are rejected with an
+    /// error until existing clients disconnect. HTTP connections count towards
+    /// the limit too.
+    pub fn set_max_connections(&mut self, max: Option<usize>) {
+        self.max_connections = max
+    }
+
+    /// Sets the memory budget of each statement executed by SQL and HTTP
+    /// clients in bytes, or None for no limit (the default). Statements that
+    /// buffer more rows than this in sorts, aggregates, or hash joins error
+    /// instead of exhausting the node's memory. See sql::execution::MemoryBudget.
+    pub fn set_session_memory_budget(&mut self, bytes: Option<usize>) {
+        self.session_memory_budget = bytes
+    }
+
+    /// Enables a query result cache shared by SQL and HTTP sessions, holding
+    /// up to the given number of results, or None to disable it (the default).
+    /// See sql::engine::QueryCache.
+    pub fn set_query_cache(&mut self, capacity: Option<usize>) {
+        self.query_cache = capacity.map(|capacity| Arc::new(QueryCache::new(capacity)))
+    }
+
+    /// Sets rate and concurrency limits for SQL and HTTP client requests, see
+    /// the admission module. Requests beyond the limits are queued, and
+    /// rejected if they can't be admitted in time. Disabled by default.
+    pub fn set_admission_limits(&mut self, limits: AdmissionLimits) {
+        self.admission_limits = limits
+    }
+
+    /// Sets how long a shutdown waits for in-flight requests to complete before
+    /// abandoning them. Defaults to 10 seconds.
+    pub fn set_drain_timeout(&mut self, timeout: std::time::Duration) {
+        self.drain_timeout = timeout
+    }
+
+    /// Records the invocations and results of client SQL statements to an
+    /// operation history file at the given path, for consistency checking with
+    /// tools like Elle or Porcupine, see the history module. Disabled by
+    /// default. The file is appended to if it exists.
+    pub fn enable_history(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        self.history = Some(History::open(path, self.node.id())?);
+        Ok(())
+    }
+
+    /// Returns a handle which can be used to shut down the server gracefully
+    /// once it's serving requests, see the shutdown module.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle { tx: self.shutdown_tx.clone() }
+    }
+
+    /// Enables the HTTP interface on the given address, see the http module.
+    /// Disabled by default. Binds the listener immediately, such that address
+    /// errors are returned here. Uses TLS and authentication like SQL clients.
+    pub fn enable_http(&mut self, addr: impl ToSocketAddrs) -> Result<()> {
+        self.http = Some(TcpListener::bind(addr)?);
+        Ok(())
+    }
+
+    /// Sets the maximum number of committed but unapplied Raft log entries
+    /// for the HTTP readiness check (GET /readyz) to pass. Defaults to 1000.
+    pub fn set_ready_max_apply_lag(&mut self, lag: raft::Index) {
+        self.ready_max_apply_lag = lag
+    }
+
+    /// Also serves SQL clients on a Unix domain socket at the given path, which
+    /// avoids TCP overhead and port management for clients on the same host.
+    /// Access is controlled by the socket file's permissions, which are set to
+    /// the given mode (e.g. 0o660), so these connections don't use TLS. A
+    /// stale socket file left behind by a previous server is replaced.
+    pub fn enable_unix_socket(&mut self, path: impl AsRef<Path>, mode: u32) -> Result<()> {
+        let path = path.as_ref();
+        if let Ok(metadata) = std::fs::symlink_metadata(path) {
+            if !metadata.file_type().is_socket() {
+                return errinput!("{} exists and is not a socket", path.display());
+            }
+            if UnixStream::connect(path).is_ok() {
+                return errinput!("{} is in use by another server", path.display());
+            }
+            std::fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+        self.unix_socket = Some(listener);
+        Ok(())
+    }
+
+    /// Adds a periodic maintenance task, see the maintenance module. There are
+    /// no tasks by default, e.g. MaintenanceTask::compact() must be added to
+    /// compact storage while the server is running.
+    pub fn add_maintenance_task(&mut self, task: MaintenanceTask) {
+        self.maintenance_tasks.push(task)
+    }
+
+    /// Serves Raft and SQL requests indefinitely, using a TCP transport (or a
+    /// QUIC transport, see enable_raft_quic()) for Raft peers. Consumes the
+    /// server. Doesn't return after a shutdown, the caller should exit the
+    /// process instead, see ShutdownHandle.
+    pub fn serve(self, raft_addr: impl ToSocketAddrs, sql_addr: impl ToSocketAddrs) -> Result<()> {
+        #[cfg(feature = "quic")]
+        if self.raft_quic {
+            let Some((acceptor, connector)) = &self.tls else {
+                return errinput!("Raft QUIC transport requires TLS");
+            };
+            let mut transport =
+                QuicTransport::bind(raft_addr, self.peers.clone(), acceptor, connector)?;
+            transport.enable_compression(self.raft_compression);
+            return self.serve_with_chaos(transport, sql_addr);
+        }
+        let mut transport = TcpTransport::bind(raft_addr, self.peers.clone())?;
+        transport.enable_compression(self.raft_compression);
+        if let Some((acceptor, connector)) = &self.tls {
+            transport.enable_tls(acceptor.clone(), connector.clone());
+        }
+        self.serve_with_chaos(transport, sql_addr)
+    }
+
+    /// Serves via serve_with_transport(), wrapping the transport in a
+    /// ChaosTransport if enabled.
+    fn serve_with_chaos(
+        self,
+        transport: impl Transport,
+        sql_addr: impl ToSocketAddrs,
+    ) -> Result<()> {
+        if let Some(opts) = self.raft_chaos.clone() {
+            let transport = ChaosTransport::new(self.node.id(), transport, opts)?;
+            return self.serve_with_transport(transport, sql_addr);
+        }
+        self.serve_with_transport(transport, sql_addr)
+    }
+
+    /// Serves Raft and SQL requests indefinitely, using the given transport
+    /// for Raft peers. Consumes the server.
+    pub fn serve_with_transport(
+        self,
+        transport: impl Transport,
+        sql_addr: impl ToSocketAddrs,
+    ) -> Result<()> {
+        self.serve_listener(transport, TcpListener::bind(sql_addr)?)
+    }
+
+    /// Serves Raft and SQL requests in a background thread, using the given
+    /// transport for Raft peers, e.g. to embed the server in another program.
+    /// Consumes the server. The SQL listener is bound before returning, so the
+    /// address can use port 0 and the returned handle reports the actual
+    /// address.
+    pub fn start(
+        self,
+        transport: impl Transport + 'static,
+        sql_addr: impl ToSocketAddrs,
+    ) -> Result<ServerHandle> {
+        let sql_listener = TcpListener::bind(sql_addr)?;
+        let handle =
+            ServerHandle { sql_addr: sql_listener.local_addr()?, shutdown: self.shutdown_handle() };
+        std::thread::spawn(move || {
+            if let Err(err) = self.serve_listener(transport, sql_listener) {
+                error!("Server failed: {err}");
+            }
+        });
+        Ok(handle)
+    }
+
+    /// Serves Raft and SQL requests indefinitely on the given SQL listener.
+    fn serve_listener(self, transport: impl Transport, sql_listener: TcpListener) -> Result<()> {
+        info!("Listening on {} (SQL)", sql_listener.local_addr()?);
+        if let Some(unix_listener) = &self.unix_socket {
+            let addr = unix_listener.local_addr()?;
+            let path = addr.as_pathname().unwrap_or(Path::new("")).display();
+            info!("Listening on {path} (SQL)");
+        }
+        if let Some(http_listener) = &self.http {
+            info!("Listening on {} (HTTP)", http_listener.local_addr()?);
+        }
+
+        let transport = &transport;
+        let drain = &Drain::default();
+        let admission = &Admission::new(self.admission_limits);
+        let connections = &AtomicUsize::new(0);
+        let scheduler = Scheduler::new(self.maintenance_tasks, std::time::Instant::now());
+        let maintenance = &Mutex::new(scheduler.status());
+        let history = self.history.as_ref();
+        std::thread::scope(move |s| {
+            let raft_opts = RaftLoopOptions {
+                tick_interval: self.raft_tick_interval,
+                group_commit_max_batch: self.raft_group_commit_max_batch,
+            };
+            let raft_peer_channel_capacity = self.raft_peer_channel_capacity;
+            let fast_path = self.raft_fast_path && self.peers.is_empty();
+            let opts = SessionOptions {
+                id: self.node.id(),
+                replica_reads: self.replica_reads,
+                auth: self.auth,
+                max_connections: self.max_connections,
+                memory_budget: self.session_memory_budget,
+                max_apply_lag: self.ready_max_apply_lag,
+                compression: self.sql_compression,
+            };
+            let sql_tls = self.tls.map(|(acceptor, _)| acceptor);
+            let (raft_request_tx, raft_request_rx) = crossbeam::channel::unbounded();
+            let (raft_step_tx, raft_step_rx) =
+                crossbeam::channel::bounded(raft_peer_channel_capacity);
+            let (raft_stop_tx, raft_stop_rx) = crossbeam::channel::bounded(1);
+
+            // Receive inbound Raft messages from peers.
+            s.spawn(move || {
+                if let Err(err) = transport.receive(raft_step_tx) {
+                    panic!("Raft transport failed: {err}");
+                }
+            });
+
+            // Send outbound Raft messages to peers.
+            let mut raft_outboxes = HashMap::new();
+            for peer in self.peers.into_keys() {
+                let (outbox, raft_peer_rx) = Outbox::new(peer, raft_peer_channel_capacity);
+                raft_outboxes.insert(peer, outbox);
+                s.spawn(move || transport.send(peer, raft_peer_rx));
+            }
+
+            // Route Raft messages between the local node, peers, and clients.
+            // Single-node clusters can execute client requests directly.
+            if fast_path {
+                info!("Single-node cluster, executing Raft requests directly");
+                s.spawn(move || {
+                    Self::raft_execute(self.node, raft_opts, raft_request_rx, raft_stop_rx)
+                });
+            } else {
+                s.spawn(move || {
+                    Self::raft_route(
+                        self.node,
+                        raft_opts,
+                        self.node_rx,
+                        raft_step_rx,
+                        raft_outboxes,
+                        raft_request_rx,
+                        raft_stop_rx,
+                    )
+                });
+            }
+
+            // Shut down gracefully when requested by a ShutdownHandle.
+            let shutdown_timeout = self.drain_timeout;
+            let shutdown_request_tx = raft_request_tx.clone();
+            s.spawn(move || {
+                let done_tx = self.shutdown_rx.recv().expect("shutdown_rx disconnected");
+                let result = Self::shutdown(
+                    opts.id,
+                    drain,
+                    shutdown_timeout,
+                    &shutdown_request_tx,
+                    raft_stop_tx,
+                );
+                done_tx.send(result).ok();
+            });
+
+            // Run periodic maintenance tasks, if any.
+            let maintenance_engine = sql::engine::Raft::new(raft_request_tx.clone());
+            s.spawn(move || scheduler.run(maintenance_engine, maintenance, drain));
+
+            // Serve inbound HTTP requests, if enabled. These share the
+            // connection limit with SQL clients.
+            if let Some(http_listener) = self.http {
+                let mut http_engine = sql::engine::Raft::new(raft_request_tx.clone());
+                http_engine.enable_replica_reads(opts.replica_reads);
+                let http_tls = sql_tls.clone();
+                let query_cache = self.query_cache.clone();
+                s.spawn(move || {
+                    Self::http_accept(
+                        opts,
+                        http_listener,
+                        http_tls,
+                        http_engine,
+                        query_cache,
+                        connections,
+                        drain,
+                        admission,
+                        maintenance,
+                        history,
+                    )
+                });
+            }
+
+            // Serve inbound SQL connections. Unix socket clients share the
+            // connection limit and cancellers with TCP clients.
+            let cancellers = Cancellers::default();
+            if let Some(unix_listener) = self.unix_socket {
+                let mut unix_engine = sql::engine::Raft::new(raft_request_tx.clone());
+                unix_engine.enable_replica_reads(opts.replica_reads);
+                let listener = SqlListener::Unix(unix_listener);
+                let cancellers = cancellers.clone();
+                let query_cache = self.query_cache.clone();
+                s.spawn(move || {
+                    Self::sql_accept(
+                        opts,
+                        listener,
+                        unix_engine,
+                        query_cache,
+                        cancellers,
+                        connections,
+                        drain,
+                        admission,
+                        history,
+                    )
+                });
+            }
+            let mut sql_engine = sql::engine::Raft::new(raft_request_tx);
+            sql_engine.enable_replica_reads(opts.replica_reads);
+            let listener = SqlListener::Tcp(sql_listener, sql_tls);
+            let query_cache = self.query_cache;
+            s.spawn(move || {
+                Self::sql_accept(
+                    opts,
+                    listener,
+                    sql_engine,
+                    query_cache,
+                    cancellers,
+                    connections,
+                    drain,
+                    admission,
+                    history,
+                )
+            });
+        });
+
+        Ok(())
+    }
+
+    /// Routes Raft messages:
+    ///
+    /// * node_rx: outbound messages from the local Raft node. Routed to peers
+    ///   via the transport, or to local clients via a response channel.
+    ///
+    /// * request_rx: inbound requests from local SQL clients. Stepped into
+    ///   the local Raft node as ClientRequest messages. Responses are returned
+    ///   via the provided response channel.
+    ///
+    /// * peers_rx: inbound messages from remote Raft peers. Stepped into the
+    ///   local Raft node. This is bounded, such that the transport blocks when
+    ///   the node falls behind, pushing back on peers.
+    ///
+    /// * outboxes: outbound per-peer queues sent via the transport. Messages
+    ///   from the local node's node_rx are sent here, and dropped if the peer
+    ///   is congested, see the outbox module.
+    ///
+    /// * stop_rx: shutdown requests. Flushes the node and returns the result
+    ///   via the provided channel, then stops routing.
+    ///
+    /// Log appends use group commit: after stepping a message, any other queued
+    /// inbound messages and requests are stepped too, and the log is then
+    /// synced once for the whole batch, before routing the node's outbound
+    /// messages (which may acknowledge the appended entries). Concurrent client
+    /// writes thus share a single fsync.
+    ///
+    /// Panics on any errors, since the Raft node can't recover from failed
+    /// state transitions.
+    fn raft_route(
+        mut node: raft::Node,
+        opts: RaftLoopOptions,
+        node_rx: Receiver<raft::Envelope>,
+        peers_rx: Receiver<raft::Envelope>,
+        mut outboxes: HashMap<raft::NodeID, Outbox>,
+        request_rx: Receiver<(raft::Request, Sender<Result<raft::Response>>, tracing::Span)>,
+        stop_rx: Receiver<Sender<Result<()>>>,
+    ) {
+        // Track response channels by request ID. The Raft node will emit
+        // ClientResponse messages that we forward to the response channel.
+        let mut response_txs = HashMap::<raft::RequestID, Sender<Result<raft::Response>>>::new();
+
+        let ticker = crossbeam::channel::tick(opts.tick_interval);
+        loop {
+            crossbeam::select! {
+                // Periodically tick the node.
+                recv(ticker) -> _ => node = node.tick().expect("tick failed"),
+
+                // Step messages from peers into the node.
+                recv(peers_rx) -> result => {
+                    let msg = result.expect("peers_rx disconnected");
+                    node = node.step(msg).expect("step failed");
+                },
+
+                // Send outbound messages from the node to the appropriate peer.
+                // If we receive a client response addressed to the local node,
+                // forward it to the waiting client via the response channel.
+                // Metrics responses include the server's queue depths.
+                recv(node_rx) -> result => {
+                    let msg = result.expect("node_rx disconnected");
+                    if msg.to == node.id() {
+                        if let raft::Message::ClientResponse{ id, mut response } = msg.message {
+                            if let Ok(raft::Response::Metrics(metrics)) = &mut response {
+                                metrics.queues =
+                                    Self::raft_queue_depths(&peers_rx, &request_rx, &outboxes);
+                            }
+                            if let Some(response_tx) = response_txs.remove(&id) {
+                                response_tx.send(response).expect("response_tx disconnected");
+                            }
+                            continue
+                        }
+                    }
+                    outboxes.get_mut(&msg.to).expect("unknown peer").send(msg);
+                }
+
+                // Track inbound client requests and step them into the node.
+                recv(request_rx) -> result => {
+                    let request = result.expect("request_rx disconnected");
+                    node = Self::raft_step_request(node, request, &mut response_txs);
+                }
+
+                // Flush the node and stop on shutdown.
+                recv(stop_rx) -> result => {
+                    let done_tx = result.expect("stop_rx disconnected");
+                    done_tx.send(node.flush()).ok();
+                    return;
+                }
+            }
+
+            // Step any other queued messages and requests, then sync the log
+            // for the whole batch before routing outbound messages.
+            for _ in 0..opts.group_commit_max_batch {
+                if let Ok(msg) = peers_rx.try_recv() {
+                    node = node.step(msg).expect("step failed");
+                } else if let Ok(request) = request_rx.try_recv() {
+                    node = Self::raft_step_request(node, request, &mut response_txs);
+                } else {
+                    break;
+                }
+            }
+            node.sync().expect("sync failed");
+        }
+    }
+
+    /// Returns the depths of the Raft message queues, for metrics responses.
+    fn raft_queue_depths(
+        peers_rx: &Receiver<raft::Envelope>,
+        request_rx: &Receiver<(raft::Request, Sender<Result<raft::Response>>, tracing::Span)>,
+        outboxes: &HashMap<raft::NodeID, Outbox>,
+    ) -> BTreeMap<String, raft::QueueDepth> {
+        let mut queues = BTreeMap::new();
+        let inbound =
+            raft::QueueDepth { depth: peers_rx.len(), capacity: peers_rx.capacity(), dropped: 0 };
+        queues.insert("inbound".to_string(), inbound);
+        let requests = raft::QueueDepth {
+            depth: request_rx.len(),
+            capacity: request_rx.capacity(),
+            dropped: 0,
+        };
+        queues.insert("requests".to_string(), requests);
+        for (peer, outbox) in outboxes {
+            queues.insert(format!("outbound n{peer}"), outbox.depth());
+        }
+        queues
+    }
+
+    /// Steps a client request into the Raft node in the client's tracing span,
+    /// tracking its response channel by request ID.
+    fn raft_step_request(
+        node: raft::Node,
+        (request, response_tx, span): (
+            raft::Request,
+            Sender<Result<raft::Response>>,
+            tracing::Span,
+        ),
+        response_txs: &mut HashMap<raft::RequestID, Sender<Result<raft::Response>>>,
+    ) -> raft::Node {
+        let id = uuid::Uuid::new_v4();
+        let msg = raft::Envelope {
+            from: node.id(),
+            to: node.id(),
+            term: node.term(),
+            message: raft::Message::ClientRequest { id, request },
+        };
+        let node = span.in_scope(|| node.step(msg)).expect("step failed");
+        response_txs.insert(id, response_tx);
+        node
+    }
+
+    /// Executes client requests directly against a single-node Raft cluster,
+    /// bypassing the message loop in raft_route(). There are no peers to
+    /// communicate with, and a single node doesn't need ticks since it's
+    /// always the leader.
+    ///
+    /// Like raft_route(), this uses group commit: queued requests are executed
+    /// as a batch, and the log is synced once before responding to them. The
+    /// log is also synced at the tick interval, for Durability::Interval.
+    ///
+    /// Panics on node errors, like raft_route(). Flushes the node and stops on
+    /// shutdown requests via stop_rx.
+    fn raft_execute(
+        mut node: raft::Node,
+        opts: RaftLoopOptions,
+        request_rx: Receiver<(raft::Request, Sender<Result<raft::Response>>, tracing::Span)>,
+        stop_rx: Receiver<Sender<Result<()>>>,
+    ) {
+        let ticker = crossbeam::channel::tick(opts.tick_interval);
+        loop {
+            crossbeam::select! {
+                recv(request_rx) -> result => {
+                    let Ok(request) = result else {
+                        return;
+                    };
+                    let mut responses = Vec::new();
+                    let mut next = Some(request);
+                    while let Some((request, response_tx, span)) = next {
+                        let response =
+                            span.in_scope(|| node.execute(request)).expect("execute failed");
+                        responses.push((response_tx, response));
+                        if responses.len() >= opts.group_commit_max_batch {
+                            break;
+                        }
+                        next = request_rx.try_recv().ok();
+                    }
+                    node.sync().expect("sync failed");
+                    for (response_tx, response) in responses {
+                        response_tx.send(response).expect("response_tx disconnected");
+                    }
+                }
+                recv(ticker) -> _ => node.sync().expect("sync failed"),
+                recv(stop_rx) -> result => {
+                    let done_tx = result.expect("stop_rx disconnected");
+                    done_tx.send(node.flush()).ok();
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Shuts down the server gracefully, see the shutdown module. New SQL
+    /// connections and requests are rejected while draining, but the Raft node
+    /// keeps running until leadership has been transferred.
+    fn shutdown(
+        id: raft::NodeID,
+        drain: &Drain,
+        drain_timeout: std::time::Duration,
+        raft_request_tx: &raft::RequestSender,
+        raft_stop_tx: Sender<Sender<Result<()>>>,
+    ) -> Result<()> {
+        info!("Shutting down, draining in-flight requests");
+        let active = drain.drain(drain_timeout)?;
+        if active > 0 {
+            warn!("Drain timeout expired, abandoning {active} in-flight requests");
+        }
+
+        // If we're the leader, transfer leadership and wait for the new leader
+        // to be elected, since it may need our vote. Errors aren't fatal, the
+        // cluster will hold an election once we're gone.
+        let request = |request| -> Result<raft::Response> {
+            let (response_tx, response_rx) = crossbeam::channel::bounded(1);
+            raft_request_tx.send((request, response_tx, tracing::Span::current()))?;
+            response_rx.recv()?
+        };
+        match request(raft::Request::TransferLeadership(None)) {
+            Ok(raft::Response::TransferLeadership(Some(leader))) => {
+                info!("Transferred leadership to {leader}");
+                let started = std::time::Instant::now();
+                while started.elapsed() < TRANSFER_TIMEOUT {
+                    match request(raft::Request::Status) {
+                        Ok(raft::Response::Status(status)) if status.leader != id => break,
+                        _ => std::thread::sleep(std::time::Duration::from_millis(50)),
+                    }
+                }
+            }
+            Ok(raft::Response::TransferLeadership(None)) => {}
+            Ok(response) => warn!("Unexpected leadership transfer response {response:?}"),
+            Err(err) => warn!("Leadership transfer failed: {err}"),
+        }
+
+        // Stop the Raft node and flush storage.
+        let (done_tx, done_rx) = crossbeam::channel::bounded(1);
+        raft_stop_tx.send(done_tx)?;
+        done_rx.recv()??;
+        info!("Shutdown complete");
+        Ok(())
+    }
+
+    /// Accepts new SQL client connections and spawns session threads for them.
+    /// Rejects clients beyond the maximum number of connections.
+    #[allow(clippy::too_many_arguments)]
+    fn sql_accept(
+        opts: SessionOptions,
+        listener: SqlListener,
+        sql_engine: sql::engine::Raft,
+        query_cache: Option<Arc<QueryCache>>,
+        cancellers: Cancellers,
+        connections: &AtomicUsize,
+        drain: &Drain,
+        admission: &Admission,
+        history: Option<&History>,
+    ) {
+        std::thread::scope(|s| loop {
+            let (socket, peer) = match listener.accept() {
+                Ok((socket, peer)) => (socket, peer),
+                Err(err) => {
+                    error!("Client accept failed: {err}");
+                    continue;
+                }
+            };
+            // Reject clients while shutting down or beyond the connection limit.
+            // Don't let rejected clients hold on to a thread indefinitely.
+            let reject = if drain.is_draining() {
+                Some(Error::InvalidInput("server is shutting down".into()))
+            } else {
+                opts.max_connections
+                    .filter(|max| connections.load(Ordering::SeqCst) >= *max)
+                    .map(|max| Error::InvalidInput(format!("too many connections (limit {max})")))
+            };
+            if reject.is_some() {
+                socket.set_read_timeout(Some(REJECT_TIMEOUT)).ok();
+            }
+            if let Some(err) = reject {
+                info!("Rejecting client {peer}: {err}");
+                let cancellers = cancellers.clone();
+                s.spawn(move || Self::sql_reject(socket, err, &cancellers));
+                continue;
+            }
+            connections.fetch_add(1, Ordering::SeqCst);
+            let cancellers = cancellers.clone();
+            let sql_engine = &sql_engine;
+            let query_cache = query_cache.clone();
+            s.spawn(move || {
+                debug!("Client {peer} connected");
+                // Sessions hold cursors which aren't Send, so they're created
+                // on the session thread.
+                let mut session = sql_engine.session();
+                session.set_memory_budget(opts.memory_budget);
+                session.set_query_cache(query_cache);
+                // Register the session's cancellation flag, such that other
+                // connections can cancel its statements given the key.
+                let cancel_key = Uuid::new_v4();
+                cancellers.lock().expect("mutex failed").insert(cancel_key, session.canceller());
+                let result = Self::sql_session(
+                    opts,
+                    socket,
+                    session,
+                    cancel_key,
+                    &cancellers,
+                    drain,
+                    admission,
+                    history.map(History::process),
+                );
+                cancellers.lock().expect("mutex failed").remove(&cancel_key);
+                connections.fetch_sub(1, Ordering::SeqCst);
+                match result {
+                    Ok(()) => debug!("Client {peer} disconnected"),
+                    Err(err) => error!("Client {peer} error: {err}"),
+                }
+            });
+        })
+    }
+
+    /// Processes a client SQL session, executing SQL statements against the
+    /// Raft node. The cancel key identifies the session in cancel requests,
+    /// which are sent on a separate connection while a statement executes.
+    ///
+    /// Requests are registered as in-flight with the drain, which rejects them
+    /// once the server is shutting down. The session is then closed, rolling
+    /// back any open transaction. Requests are then subject to admission
+    /// control, and rejected if the server is overloaded.
+    ///
+    /// If given a history process, SQL statements are recorded to it.
+    #[allow(clippy::too_many_arguments)]
+    fn sql_session(
+        opts: SessionOptions,
+        socket: Stream,
+        mut session: sql::engine::Session<sql::engine::Raft>,
+        cancel_key: Uuid,
+        cancellers: &Cancellers,
+        drain: &Drain,
+        admission: &Admission,
+        mut process: Option<Process>,
+    ) -> Result<()> {
+        // A TLS stream can't be split into separate reader and writer halves,
+        // so responses are written to the underlying stream in one go.
+        let mut reader = std::io::BufReader::new(socket);
+        let mut rate_limiter = admission.client_bucket();
+        let Some(hello) = frame::accept(&mut reader, opts.compression)? else {
+            return Ok(());
+        };
+        let compress = hello.compression;
+        // Prepared statements by ID, for recording their invocations.
+        let mut prepared = HashMap::new();
+
+        while let Some(request) = Frame::read_value::<Request>(&mut reader, Kind::Request)? {
+            // Execute request. Don't log passwords or cancel keys. Prepared
+            // statement parameters may be passwords too, so only count them.
+            match &request {
+                Request::Authenticate { user, .. } => debug!("Received authentication for {user}"),
+                Request::Cancel(_) => debug!("Received cancel request"),
+                Request::Execute(statement)
+                | Request::Prepare(statement)
+                | Request::Query(statement) => {
+                    debug!("Received statement {}", sql::parser::redact(statement).0)
+                }
+                Request::ExecutePrepared { id, params } => {
+                    debug!("Received prepared statement {id} with {} parameters", params.len())
+                }
+                request => debug!("Received request {request:?}"),
+            }
+            // Cancel requests are served while draining, since they can speed
+            // up the drain.
+            let guard = match request {
+                Request::Cancel(_) => None,
+                _ => match drain.begin() {
+                    Ok(guard) => Some(guard),
+                    Err(err) => {
+                        let response: Result<Response> = Err(err);
+                        Frame::write_value(reader.get_mut(), Kind::Response, &response, compress)?;
+                        reader.get_mut().flush()?;
+                        return Ok(());
+                    }
+                },
+            };
+            // Cancel requests bypass admission control, since they free up
+            // resources, and are authorized by the cancel key, since they come
+            // from a separate connection.
+            let admitted = match request {
+                Request::Cancel(_) => Ok(None),
+                _ => admission.admit(rate_limiter.as_mut()).map(Some),
+            };
+            let authorized = match (&admitted, &request) {
+                (Err(err), _) => Err(err.clone()),
+                (_, Request::Authenticate { .. } | Request::Cancel(_)) => Ok(()),
+                _ if !opts.auth || session.user().is_some() => Ok(()),
+                _ => Self::sql_bootstrap(&mut session),
+            };
+            // Record the invocation of SQL statements, if enabled.
+            let recorded = process.as_ref().and_then(|process| {
+                let (f, statement, params) = match &request {
+                    Request::Execute(query) => (Function::Execute, query, None),
+                    Request::ExecutePrepared { id, params } => {
+                        (Function::Execute, prepared.get(id)?, serde_json::to_value(params).ok())
+                    }
+                    Request::Query(query) => (Function::Query, query, None),
+                    _ => return None,
+                };
+                process.invoke(f, statement, params);
+                Some(f)
+            });
+            let preparing = match &request {
+                Request::Prepare(query) if process.is_some() => Some(query.clone()),
+                _ => None,
+            };
+            let mut stream = None;
+            let response = authorized.and_then(|()| match request {
+                Request::Authenticate { user, password } => {
+                    session.authenticate(&user, &password).map(|()| Response::Authenticate)
+                }
+                Request::Cancel(key) => Self::sql_cancel(key, cancellers),
+                Request::CancelKey => Ok(Response::CancelKey(cancel_key)),
+                Request::Execute(query) => session.execute(&query).map(Response::Execute),
+                Request::Prepare(query) => session
+                    .prepare(&query)
+                    .map(|(id, parameters)| Response::Prepare { id, parameters }),
+                Request::ExecutePrepared { id, params } => {
+                    session.execute_prepared(id, params).map(Response::Execute)
+                }
+                Request::Deallocate(id) => session.deallocate(id).map(|()| Response::Deallocate),
+                Request::Query(query) => session.query(&query).map(|(columns, rows)| {
+                    stream = Some(rows);
+                    Response::Query(columns)
+                }),
+                Request::GetTable(table) => {
+                    session.with_txn(true, |txn| txn.must_get_table(&table)).map(Response::GetTable)
+                }
+                Request::ListTables => session
+                    .with_txn(true, |txn| {
+                        Ok(txn.list_tables()?.into_iter().map(|t| t.name).collect())
+                    })
+                    .map(Response::ListTables),
+                Request::Status => Self::sql_status(opts, &session).map(Response::Status),
+                Request::Ping => Ok(Response::Ping),
+                Request::Verify => session.verify().map(Response::Verify),
+                Request::Trace => session.trace().map(Response::Trace),
+                Request::Metrics => session.metrics().map(Response::Metrics),
+                Request::VerifyState => session.verify_state().map(Response::VerifyState),
+                Request::Changes { tables, from } => {
+                    session.changes(&tables, from).map(Response::Changes)
+                }
+                Request::Backup(path) => session.backup(&path).map(Response::Backup),
+                Request::TransferLeadership(to) => {
+                    session.transfer_leadership(to).map(Response::TransferLeadership)
+                }
+            });
+
+            // Record the statement result. Query results are recorded once
+            // their rows have been streamed.
+            if let (Some(process), Some(f)) = (&mut process, recorded) {
+                match &response {
+                    Ok(Response::Execute(result)) => {
+                        process.complete(f, &Self::http_result(result.clone()))
+                    }
+                    Ok(_) => {}
+                    Err(err) => process.complete(f, &Err(err.clone())),
+                }
+            }
+            if let (Some(query), Ok(Response::Prepare { id, .. })) = (preparing, &response) {
+                prepared.insert(*id, query);
+            }
+
+            // Process response.
+            debug!("Returning response {response:?}");
+            Frame::write_value(reader.get_mut(), Kind::Response, &response, compress)?;
+            reader.get_mut().flush()?;
+
+            // Stream query rows, if any, ending with either Row(None) or an
+            // error. Buffer the writes, since rows are usually small.
+            if let Some(rows) = stream {
+                let mut writer = std::io::BufWriter::new(reader.get_mut());
+                let mut end = Ok(Response::Row(None));
+                let mut streamed = Vec::new();
+                for row in rows {
+                    match row {
+                        Ok(row) => {
+                            if process.is_some() {
+                                streamed.push(row.clone());
+                            }
+                            let response = Ok(Response::Row(Some(row)));
+                            Frame::write_value(&mut writer, Kind::Response, &response, compress)?
+                        }
+                        Err(err) => {
+                            end = Err(err);
+                            break;
+                        }
+                    }
+                }
+                Frame::write_value(&mut writer, Kind::Response, &end, compress)?;
+                writer.flush()?;
+                if let (Some(process), Ok(Response::Query(columns))) = (&mut process, response) {
+                    let result = end.and_then(|_| {
+                        Self::http_result(StatementResult::Select { columns, rows: streamed })
+                    });
+                    process.complete(Function::Query, &result);
+                }
+            }
+            drop(admitted);
+            drop(guard);
+        }
+        Ok(())
+    }
+
+    /// Cancels the executing statement of the session with the given cancel
+    /// key, if any.
+    fn sql_cancel(key: Uuid, cancellers: &Cancellers) -> Result<Response> {
+        if let Some(cancel) = cancellers.lock()?.get(&key) {
+            cancel.cancel();
+        }
+        Ok(Response::Cancel)
+    }
+
+    /// Rejects a client connection beyond the maximum number of connections,
+    /// or during shutdown. Waits for the client's first request and responds
+    /// with the given error, since the client may not read the error until it
+    /// has sent a request. Cancel requests are still served, since they're
+    /// needed to free up sessions.
+    fn sql_reject(socket: Stream, err: Error, cancellers: &Cancellers) {
+        let mut reader = std::io::BufReader::new(socket);
+        if !matches!(frame::accept(&mut reader, false), Ok(Some(_))) {
+            return;
+        }
+        let response = match Frame::read_value::<Request>(&mut reader, Kind::Request) {
+            Ok(Some(Request::Cancel(key))) => Self::sql_cancel(key, cancellers),
+            Ok(Some(_)) => Err(err),
+            Ok(None) | Err(_) => return,
+        };
+        Frame::write_value(reader.get_mut(), Kind::Response, &response, false).ok();
+        reader.get_mut().flush().ok();
+    }
+
+    /// Fetches the server status.
+    fn sql_status(
+        opts: SessionOptions,
+        session: &sql::engine::Session<sql::engine::Raft>,
+    ) -> Result<Status> {
+        let status = session.status()?;
+        Ok(Status {
+            server: opts.id,
+            role: Role::new(opts.id, status.raft.leader, opts.replica_reads),
+            raft: status.raft,
+            mvcc: status.mvcc,
+        })
+    }
+
+    /// Checks whether an unauthenticated client may send requests, which is
+    /// only the case until the first user is created. This allows bootstrapping
+    /// a cluster with authentication enabled.
+    fn sql_bootstrap(session: &mut sql::engine::Session<sql::engine::Raft>) -> Result<()> {
+        if !session.with_txn(true, |txn| txn.list_users())?.is_empty() {
+            return errinput!("authentication required");
+        }
+        Ok(())
+    }
+}
+
+/// A SQL client request.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Request {
+    /// Authenticates the session as the given user. Must be sent before any
+    /// other requests if the server requires authentication.
+    Authenticate { user: String, password: String },
+    /// Cancels the currently executing statement of the session with the given
+    /// cancel key, if any. Sent on a separate connection, since the session's
+    /// own connection is busy executing the statement. Unknown keys are
+    /// ignored.
+    Cancel(Uuid),
+    /// Returns the session's cancel key, for use with Cancel.
+    CancelKey,
+    /// Executes a SQL statement.
+    Execute(String),
+    /// Prepares a SQL statement with positional parameters (i.e. ?), for
+    /// execution via ExecutePrepared. Prepared statements are session-local.
+    Prepare(String),
+    /// Executes a prepared statement with the given parameter values.
+    ExecutePrepared { id: u64, params: Vec<sql::types::Value> },
+    /// Deallocates a prepared statement.
+    Deallocate(u64),
+    /// Executes a SELECT query, streaming the result rows. The server responds
+    /// with Response::Query containing the column labels, followed by a
+    /// Response::Row for each row and a final Response::Row(None). An error
+    /// response ends the stream early.
+    Query(String),
+    /// Fetches the given table schema.
+    GetTable(String),
+    /// Lists all tables.
+    ListTables,
+    /// Returns server status.
+    Status,
+    /// Checks that the connection is alive, without involving Raft.
+    Ping,
+    /// Verifies the consistency of the Raft logs across the cluster.
+    Verify,
+    /// Returns the server's Raft event trace.
+    Trace,
+    /// Returns the server's Raft node metrics, e.g. replication progress and
+    /// election counts.
+    Metrics,
+    /// Verifies the consistency of the SQL state machines across the cluster.
+    VerifyState,
+    /// Returns the row changes of the given tables from the given version, up
+    /// to the resolved version. See Session::changes().
+    Changes { tables: Vec<String>, from: storage::mvcc::Version },
+    /// Backs up the server's Raft log and SQL storage to the given directory
+    /// on the server, without stopping it. See Session::backup().
+    Backup(String),
+    /// Transfers Raft leadership away from the server, if it's the leader, to
+    /// the given node or the most up-to-date one, e.g. to drain it for
+    /// maintenance. See Session::transfer_leadership().
+    TransferLeadership(Option<raft::NodeID>),
+}
+
+impl encoding::Value for Request {}
+
+/// A SQL server response.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Response {
+    Authenticate,
+    Cancel,
+    CancelKey(Uuid),
+    Execute(StatementResult),
+    Prepare { id: u64, parameters: usize },
+    Deallocate,
+    Query(Vec<Label>),
+    Row(Option<Row>),
+    GetTable(Table),
+    ListTables(Vec<String>),
+    Status(Status),
+    Ping,
+    Verify(raft::Report),
+    Trace(Vec<raft::TraceEvent>),
+    Metrics(raft::Metrics),
+    VerifyState(raft::StateReport),
+    Changes(sql::engine::Changes),
+    Backup(raft::Index),
+    TransferLeadership(Option<raft::NodeID>),
+}
+
+impl encoding::Value for Response {}
+
+/// SQL server status.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Status {
+    pub server: raft::NodeID,
+    pub role: Role,
+    pub raft: raft::Status,
+    pub mvcc: storage::mvcc::Status,
+}
+
+/// A server's replica role, as advertised in its status. Clients can use this
+/// to direct read-only sessions to read replicas.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Role {
+    /// The Raft leader, which executes all reads and writes.
+    Leader,
+    /// A Raft follower, which forwards all requests to the leader.
+    Follower,
+    /// A Raft follower which executes read-only transactions locally with
+    /// bounded staleness, and forwards other requests to the leader.
+    ReadReplica,
+}
+
+impl Role {
+    /// Determines the role of the given server, given the current leader.
+    fn new(server: raft::NodeID, leader: raft::NodeID, replica_reads: bool) -> Self {
+        match (server == leader, replica_reads) {
+            (true, _) => Self::Leader,
+            (false, true) => Self::ReadReplica,
+            (false, false) => Self::Follower,
+        }
+    }
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Leader => write!(f, "leader"),
+            Self::Follower => write!(f, "follower"),
+            Self::ReadReplica => write!(f, "read replica"),
+        }
+    }
+}
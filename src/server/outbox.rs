@@ -0,0 +1,109 @@
+//! Outboxes for outbound Raft messages.
+//!
+//! Each Raft peer has an outbox, a bounded queue drained by the transport.
+//! This prevents a slow or unreachable peer from making the node buffer
+//! messages without bound. When an outbox is full, new messages are dropped.
+//! Raft tolerates message loss, and recovers via retries: heartbeats are
+//! resent every heartbeat interval, and the leader detects lost appends via
+//! heartbeat responses and resends them.
+//!
+//! Once an outbox is half full, heartbeats are dropped too. A congested peer
+//! doesn't need a backlog of heartbeats, since the next one supersedes them,
+//! and this leaves room for appends and responses that carry new entries.
+
+use crate::raft;
+
+use crossbeam::channel::{Receiver, Sender, TrySendError};
+use log::{debug, error};
+
+/// A bounded outbox for outbound messages to a single Raft peer.
+pub struct Outbox {
+    /// The peer ID.
+    peer: raft::NodeID,
+    /// The outbound channel, drained by the transport.
+    tx: Sender<raft::Envelope>,
+    /// The channel capacity.
+    capacity: usize,
+    /// The number of messages dropped since the outbox was created.
+    dropped: u64,
+}
+
+impl Outbox {
+    /// Creates a new outbox for the given peer with the given capacity,
+    /// returning it along with the receiver to pass to the transport.
+    pub fn new(peer: raft::NodeID, capacity: usize) -> (Self, Receiver<raft::Envelope>) {
+        assert!(capacity > 0, "outbox capacity must be positive");
+        let (tx, rx) = crossbeam::channel::bounded(capacity);
+        (Self { peer, tx, capacity, dropped: 0 }, rx)
+    }
+
+    /// Queues a message for the peer, or drops it if the outbox is full (or
+    /// half full for heartbeats).
+    pub fn send(&mut self, msg: raft::Envelope) {
+        if matches!(msg.message, raft::Message::Heartbeat { .. })
+            && self.tx.len() >= self.capacity.div_ceil(2)
+        {
+            debug!("Raft peer {} outbox congested, dropping heartbeat", self.peer);
+            self.dropped += 1;
+            return;
+        }
+        match self.tx.try_send(msg) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                error!("Raft peer {} outbox full, dropping message", self.peer);
+                self.dropped += 1;
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                panic!("outbox for peer {} disconnected", self.peer)
+            }
+        }
+    }
+
+    /// Returns the outbox queue depth.
+    pub fn depth(&self) -> raft::QueueDepth {
+        raft::QueueDepth {
+            depth: self.tx.len(),
+            capacity: Some(self.capacity),
+            dropped: self.dropped,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn envelope(message: raft::Message) -> raft::Envelope {
+        raft::Envelope { from: 1, to: 2, term: 1, message }
+    }
+
+    fn heartbeat() -> raft::Envelope {
+        envelope(raft::Message::Heartbeat { last_index: 1, commit_index: 1, read_seq: 0 })
+    }
+
+    fn append() -> raft::Envelope {
+        envelope(raft::Message::Append { base_index: 1, base_term: 1, entries: Vec::new() })
+    }
+
+    /// Heartbeats are dropped once the outbox is half full, other messages
+    /// once it's full.
+    #[test]
+    fn drops_when_congested() {
+        let (mut outbox, rx) = Outbox::new(2, 4);
+
+        outbox.send(heartbeat());
+        outbox.send(append());
+        outbox.send(heartbeat());
+        assert_eq!(outbox.depth(), raft::QueueDepth { depth: 2, capacity: Some(4), dropped: 1 });
+
+        outbox.send(append());
+        outbox.send(append());
+        outbox.send(append());
+        assert_eq!(outbox.depth(), raft::QueueDepth { depth: 4, capacity: Some(4), dropped: 2 });
+
+        // Draining the outbox makes room for heartbeats again.
+        rx.try_iter().for_each(drop);
+        outbox.send(heartbeat());
+        assert_eq!(outbox.depth(), raft::QueueDepth { depth: 1, capacity: Some(4), dropped: 2 });
+    }
+}
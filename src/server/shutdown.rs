@@ -0,0 +1,127 @@
+//! Graceful server shutdown.
+//!
+//! When a server is asked to shut down (e.g. on SIGTERM), it stops accepting new
+//! connections and requests, waits for in-flight requests to complete (bounded
+//! by a drain timeout), transfers Raft leadership to a peer if it's the leader,
+//! and flushes the Raft log and state machine to durable storage. The caller
+//! should then exit the process.
+
+use crate::errinput;
+use crate::error::Result;
+
+use crossbeam::channel::Sender;
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// Tracks in-flight client requests, such that a shutdown can wait for them to
+/// complete. Once draining, new requests are rejected.
+#[derive(Default)]
+pub(super) struct Drain {
+    state: Mutex<DrainState>,
+    /// Notified when the last in-flight request completes.
+    idle: Condvar,
+}
+
+#[derive(Default)]
+struct DrainState {
+    /// If true, the server is shutting down and rejects new requests.
+    draining: bool,
+    /// The number of in-flight requests.
+    active: usize,
+}
+
+impl Drain {
+    /// Returns true if the server is draining.
+    pub(super) fn is_draining(&self) -> bool {
+        self.state.lock().expect("mutex failed").draining
+    }
+
+    /// Registers an in-flight request, returning a guard which deregisters it
+    /// when dropped. Errors if the server is draining.
+    pub(super) fn begin(&self) -> Result<DrainGuard<'_>> {
+        let mut state = self.state.lock()?;
+        if state.draining {
+            return errinput!("server is shutting down");
+        }
+        state.active += 1;
+        Ok(DrainGuard(self))
+    }
+
+    /// Starts draining, and waits for in-flight requests to complete or the
+    /// timeout to expire. Returns the number of requests still in flight.
+    pub(super) fn drain(&self, timeout: Duration) -> Result<usize> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.state.lock()?;
+        state.draining = true;
+        while state.active > 0 {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break;
+            }
+            state = self.idle.wait_timeout(state, remaining)?.0;
+        }
+        Ok(state.active)
+    }
+}
+
+/// An in-flight request, see Drain::begin().
+pub(super) struct DrainGuard<'a>(&'a Drain);
+
+impl Drop for DrainGuard<'_> {
+    fn drop(&mut self) {
+        let mut state = self.0.state.lock().expect("mutex failed");
+        state.active -= 1;
+        if state.active == 0 {
+            self.0.idle.notify_all();
+        }
+    }
+}
+
+/// A handle for shutting down a running server, see Server::shutdown_handle().
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    pub(super) tx: Sender<Sender<Result<()>>>,
+}
+
+impl ShutdownHandle {
+    /// Shuts down the server gracefully, blocking until in-flight requests have
+    /// been drained, leadership has been transferred, and storage has been
+    /// flushed. The server no longer serves requests afterwards, and the
+    /// process should exit.
+    pub fn shutdown(&self) -> Result<()> {
+        let (done_tx, done_rx) = crossbeam::channel::bounded(1);
+        self.tx.send(done_tx)?;
+        done_rx.recv()?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Draining rejects new requests and waits for in-flight ones.
+    #[test]
+    fn drain() {
+        let drain = Drain::default();
+        let guard = drain.begin().expect("begin failed");
+        assert!(!drain.is_draining());
+
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                std::thread::sleep(Duration::from_millis(50));
+                drop(guard);
+            });
+            assert_eq!(drain.drain(Duration::from_secs(10)), Ok(0));
+        });
+        assert!(drain.is_draining());
+        assert!(drain.begin().is_err());
+    }
+
+    /// Draining gives up after the timeout, returning the in-flight requests.
+    #[test]
+    fn drain_timeout() {
+        let drain = Drain::default();
+        let _guard = drain.begin().expect("begin failed");
+        assert_eq!(drain.drain(Duration::from_millis(10)), Ok(1));
+    }
+}
@@ -0,0 +1,289 @@
+//! TLS support for SQL client and Raft peer connections, using rustls.
+//!
+//! A `TlsAcceptor` wraps inbound TCP connections, presenting the node's
+//! certificate and optionally requiring clients to present a certificate
+//! signed by a trusted CA (mutual authentication). A `TlsConnector` wraps
+//! outbound TCP connections, verifying the server's certificate against a
+//! trusted CA and optionally presenting a client certificate. Certificates and
+//! keys are loaded from PEM files.
+//!
+//! Both return a `Stream`, which is either a plain TCP stream or a TLS stream.
+//! Unlike a TcpStream, a TLS stream can't be cloned into separate reader and
+//! writer halves, so callers use a single stream for both. Local clients can
+//! also use a Unix domain socket stream, which doesn't use TLS.
+
+use crate::errinput;
+use crate::error::Result;
+
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::{ClientConnection, RootCertStore, ServerConnection, StreamOwned};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Accepts inbound TLS connections.
+#[derive(Clone)]
+pub struct TlsAcceptor(Arc<rustls::ServerConfig>);
+
+impl TlsAcceptor {
+    /// Creates a TLS acceptor with the given certificate chain and private key
+    /// PEM files. If client_ca is given, clients must present a certificate
+    /// signed by one of the CA certificates in this PEM file.
+    pub fn new(cert: &Path, key: &Path, client_ca: Option<&Path>) -> Result<Self> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let builder = rustls::ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()?;
+        let builder = match client_ca {
+            Some(ca) => {
+                let roots = Arc::new(load_roots(ca)?);
+                let verifier =
+                    rustls::server::WebPkiClientVerifier::builder_with_provider(roots, provider)
+                        .build()
+                        .or_else(|err| errinput!("invalid TLS client CA: {err}"))?;
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => builder.with_no_client_auth(),
+        };
+        let config = builder.with_single_cert(load_certs(cert)?, load_key(key)?)?;
+        Ok(Self(Arc::new(config)))
+    }
+
+    /// Wraps an inbound TCP connection in TLS. The handshake happens lazily on
+    /// the first read or write.
+    pub fn accept(&self, socket: TcpStream) -> Result<Stream> {
+        let conn = ServerConnection::new(self.0.clone())?;
+        Ok(Stream::TlsServer(Box::new(StreamOwned::new(conn, socket))))
+    }
+
+    /// Returns the rustls server config, e.g. for QUIC.
+    #[cfg(feature = "quic")]
+    pub(super) fn config(&self) -> Arc<rustls::ServerConfig> {
+        self.0.clone()
+    }
+}
+
+/// Establishes outbound TLS connections.
+#[derive(Clone)]
+pub struct TlsConnector(Arc<rustls::ClientConfig>);
+
+impl TlsConnector {
+    /// Creates a TLS connector which trusts server certificates signed by one
+    /// of the CA certificates in the given PEM file. If identity is given, it
+    /// contains the certificate chain and private key PEM files to present to
+    /// servers that require client authentication.
+    pub fn new(ca: &Path, identity: Option<(&Path, &Path)>) -> Result<Self> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let builder = rustls::ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(load_roots(ca)?);
+        let config = match identity {
+            Some((cert, key)) => {
+                builder.with_client_auth_cert(load_certs(cert)?, load_key(key)?)?
+            }
+            None => builder.with_no_client_auth(),
+        };
+        Ok(Self(Arc::new(config)))
+    }
+
+    /// Wraps an outbound TCP connection in TLS, verifying that the server's
+    /// certificate is valid for the given host name or IP address. The
+    /// handshake happens lazily on the first read or write.
+    pub fn connect(&self, socket: TcpStream, host: &str) -> Result<Stream> {
+        let Ok(name) = ServerName::try_from(host.to_string()) else {
+            return errinput!("invalid TLS server name {host}");
+        };
+        let conn = ClientConnection::new(self.0.clone(), name)?;
+        Ok(Stream::TlsClient(Box::new(StreamOwned::new(conn, socket))))
+    }
+
+    /// Returns the rustls client config, e.g. for QUIC.
+    #[cfg(feature = "quic")]
+    pub(super) fn config(&self) -> Arc<rustls::ClientConfig> {
+        self.0.clone()
+    }
+}
+
+/// A network stream, with or without TLS.
+pub enum Stream {
+    /// A plain TCP stream.
+    Tcp(TcpStream),
+    /// An inbound TLS stream.
+    TlsServer(Box<StreamOwned<ServerConnection, TcpStream>>),
+    /// An outbound TLS stream.
+    TlsClient(Box<StreamOwned<ClientConnection, TcpStream>>),
+    /// A Unix domain socket stream.
+    Unix(UnixStream),
+}
+
+impl Stream {
+    /// Sets the read timeout of the underlying socket.
+    pub fn set_read_timeout(&self, timeout: Option<std::time::Duration>) -> Result<()> {
+        match self {
+            Self::Tcp(stream) => stream.set_read_timeout(timeout)?,
+            Self::TlsServer(stream) => stream.sock.set_read_timeout(timeout)?,
+            Self::TlsClient(stream) => stream.sock.set_read_timeout(timeout)?,
+            Self::Unix(stream) => stream.set_read_timeout(timeout)?,
+        }
+        Ok(())
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Self::Tcp(stream) => stream.read(buf),
+            Self::TlsServer(stream) => stream.read(buf),
+            Self::TlsClient(stream) => stream.read(buf),
+            Self::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Self::Tcp(stream) => stream.write(buf),
+            Self::TlsServer(stream) => stream.write(buf),
+            Self::TlsClient(stream) => stream.write(buf),
+            Self::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Self::Tcp(stream) => stream.flush(),
+            Self::TlsServer(stream) => stream.flush(),
+            Self::TlsClient(stream) => stream.flush(),
+            Self::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+/// Loads a certificate chain from a PEM file.
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let mut reader = std::io::BufReader::new(std::fs::File::open(path)?);
+    let certs = rustls_pemfile::certs(&mut reader).collect::<std::io::Result<Vec<_>>>()?;
+    if certs.is_empty() {
+        return errinput!("no certificates found in {}", path.display());
+    }
+    Ok(certs)
+}
+
+/// Loads a private key from a PEM file.
+fn load_key(path: &Path) -> Result<PrivateKeyDer<'static>> {
+    let mut reader = std::io::BufReader::new(std::fs::File::open(path)?);
+    match rustls_pemfile::private_key(&mut reader)? {
+        Some(key) => Ok(key),
+        None => errinput!("no private key found in {}", path.display()),
+    }
+}
+
+/// Loads trusted CA certificates from a PEM file.
+fn load_roots(path: &Path) -> Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(path)? {
+        roots.add(cert)?;
+    }
+    Ok(roots)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    /// A temporary directory with a self-signed certificate and key for
+    /// localhost, which also acts as its own CA.
+    struct Certs {
+        dir: tempfile::TempDir,
+    }
+
+    impl Certs {
+        fn new() -> Self {
+            let dir = tempfile::TempDir::with_prefix("toydb").expect("tempdir failed");
+            let rcgen::CertifiedKey { cert, key_pair } =
+                rcgen::generate_simple_self_signed(vec!["localhost".to_string()])
+                    .expect("cert generation failed");
+            std::fs::write(dir.path().join("cert.pem"), cert.pem()).expect("write failed");
+            std::fs::write(dir.path().join("key.pem"), key_pair.serialize_pem())
+                .expect("write failed");
+            Self { dir }
+        }
+
+        fn cert(&self) -> std::path::PathBuf {
+            self.dir.path().join("cert.pem")
+        }
+
+        fn key(&self) -> std::path::PathBuf {
+            self.dir.path().join("key.pem")
+        }
+    }
+
+    /// Sends a ping from the connector to the acceptor and back. Returns the
+    /// connector's result.
+    fn ping(acceptor: TlsAcceptor, connector: TlsConnector, host: &str) -> Result<Vec<u8>> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        std::thread::spawn(move || -> Result<()> {
+            let (socket, _) = listener.accept()?;
+            let mut stream = acceptor.accept(socket)?;
+            let mut buf = [0; 4];
+            stream.read_exact(&mut buf)?;
+            stream.write_all(&buf)?;
+            Ok(stream.flush()?)
+        });
+        let mut stream = connector.connect(TcpStream::connect(addr)?, host)?;
+        stream.write_all(b"ping")?;
+        stream.flush()?;
+        let mut buf = vec![0; 4];
+        stream.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// A client can connect to a server with a trusted certificate.
+    #[test]
+    fn connect() -> Result<()> {
+        let certs = Certs::new();
+        let acceptor = TlsAcceptor::new(&certs.cert(), &certs.key(), None)?;
+        let connector = TlsConnector::new(&certs.cert(), None)?;
+        assert_eq!(ping(acceptor, connector, "localhost")?, b"ping");
+        Ok(())
+    }
+
+    /// The server certificate must match the host name.
+    #[test]
+    fn connect_wrong_host() -> Result<()> {
+        let certs = Certs::new();
+        let acceptor = TlsAcceptor::new(&certs.cert(), &certs.key(), None)?;
+        let connector = TlsConnector::new(&certs.cert(), None)?;
+        assert!(ping(acceptor, connector, "example.com").is_err());
+        Ok(())
+    }
+
+    /// The server certificate must be signed by a trusted CA.
+    #[test]
+    fn connect_untrusted() -> Result<()> {
+        let (server, client) = (Certs::new(), Certs::new());
+        let acceptor = TlsAcceptor::new(&server.cert(), &server.key(), None)?;
+        let connector = TlsConnector::new(&client.cert(), None)?;
+        assert!(ping(acceptor, connector, "localhost").is_err());
+        Ok(())
+    }
+
+    /// With mutual authentication, the client must present a trusted
+    /// certificate.
+    #[test]
+    fn mutual_auth() -> Result<()> {
+        let certs = Certs::new();
+        let acceptor = TlsAcceptor::new(&certs.cert(), &certs.key(), Some(&certs.cert()))?;
+
+        let connector = TlsConnector::new(&certs.cert(), Some((&certs.cert(), &certs.key())))?;
+        assert_eq!(ping(acceptor.clone(), connector, "localhost")?, b"ping");
+
+        let connector = TlsConnector::new(&certs.cert(), None)?;
+        assert!(ping(acceptor, connector, "localhost").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,359 @@
+//! Raft peer transports, which carry Raft messages between toyDB nodes.
+//!
+//! The server exchanges Raft messages with its peers via a `Transport`. By
+//! default, it uses `TcpTransport`, which sends framed messages across TCP
+//! connections (see `encoding::frame`). With the quic feature, `QuicTransport`
+//! can be used instead, see the quic module. `MemoryTransport` delivers
+//! messages between nodes in the same process via channels, which is
+//! convenient for tests.
+//!
+//! TcpTransport can optionally use TLS with mutual authentication, such that
+//! only nodes with a certificate signed by the cluster CA can join.
+//!
+//! Raft tolerates message loss, reordering, and duplication, so transports
+//! don't need to guarantee delivery. They should simply make a best effort,
+//! and reconnect if a connection fails.
+
+use super::{Stream, TlsAcceptor, TlsConnector};
+use crate::encoding::frame::{self, Frame, Kind};
+use crate::encoding::Value as _;
+use crate::errdata;
+use crate::error::Result;
+use crate::raft;
+
+use crossbeam::channel::{Receiver, Sender};
+use log::{debug, error, info};
+use std::collections::HashMap;
+use std::io::Write as _;
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+
+/// The retry interval when connecting to a Raft peer.
+const RAFT_PEER_RETRY_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// A Raft peer transport. The server calls `receive()` and `send()` on separate
+/// threads, so implementations must be thread-safe.
+pub trait Transport: Send + Sync {
+    /// Receives inbound messages from peers and passes them to step_tx, which
+    /// steps them into the local Raft node. This may block indefinitely, or
+    /// return once delivery to step_tx has been set up. Errors are fatal.
+    fn receive(&self, step_tx: Sender<raft::Envelope>) -> Result<()>;
+
+    /// Sends outbound messages from rx to the given peer, until rx is
+    /// disconnected. Failures should be logged and retried.
+    fn send(&self, peer: raft::NodeID, rx: Receiver<raft::Envelope>);
+}
+
+/// A TCP transport. Each node listens for inbound connections from its peers,
+/// and establishes an outbound connection to each peer, such that every pair
+/// of nodes has two unidirectional connections.
+///
+/// When connecting, the peers perform the protocol handshake to negotiate the
+/// protocol version, and then exchange a `RaftHandshake` to negotiate the
+/// connection settings. The connecting peer then sends a stream of RaftMessage
+/// frames, each containing a single `raft::Envelope`.
+pub struct TcpTransport {
+    /// The listener for inbound peer connections.
+    listener: TcpListener,
+    /// Peer addresses by node ID.
+    peers: HashMap<raft::NodeID, String>,
+    /// Whether to compress large messages. Only used if the peer also has
+    /// compression enabled, as negotiated during the handshake.
+    compression: bool,
+    /// If set, connections use TLS. The acceptor wraps inbound connections,
+    /// and the connector wraps outbound connections.
+    tls: Option<(TlsAcceptor, TlsConnector)>,
+}
+
+impl TcpTransport {
+    /// Creates a new TCP transport listening on the given address, connecting
+    /// to the given peer addresses. Compression is enabled by default.
+    pub fn bind(addr: impl ToSocketAddrs, peers: HashMap<raft::NodeID, String>) -> Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        info!("Listening on {} (Raft)", listener.local_addr()?);
+        Ok(Self { listener, peers, compression: true, tls: None })
+    }
+
+    /// Enables or disables compression of large messages.
+    pub fn enable_compression(&mut self, enable: bool) {
+        self.compression = enable
+    }
+
+    /// Enables TLS for peer connections. Peers must use TLS too. The acceptor
+    /// should require client certificates, such that peers are mutually
+    /// authenticated. The connector should present this node's certificate.
+    pub fn enable_tls(&mut self, acceptor: TlsAcceptor, connector: TlsConnector) {
+        self.tls = Some((acceptor, connector))
+    }
+
+    /// Returns the local listen address.
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Receives inbound messages from a peer via TCP, and queues them for
+    /// stepping into the Raft node. The peer initiates the connection with the
+    /// handshakes, and we respond with the negotiated settings.
+    fn receive_peer(&self, socket: TcpStream, step_tx: Sender<raft::Envelope>) -> Result<()> {
+        let stream = match &self.tls {
+            Some((acceptor, _)) => acceptor.accept(socket)?,
+            None => Stream::Tcp(socket),
+        };
+        let mut reader = std::io::BufReader::new(stream);
+
+        // Raft compression is negotiated via the RaftHandshake below.
+        if frame::accept(&mut reader, false)?.is_none() {
+            return Ok(());
+        }
+        let Some(frame) = Frame::read_from(&mut reader)? else {
+            return Ok(());
+        };
+        let handshake = RaftHandshake::from_frame(&frame)?;
+        let compression = self.compression && handshake.compression;
+        RaftHandshake { compression }.to_frame().write_to(reader.get_mut())?;
+        reader.get_mut().flush()?;
+
+        while let Some(frame) = Frame::read_from(&mut reader)? {
+            let envelope = match frame.kind {
+                Kind::RaftMessage => raft::Envelope::decode(&frame.payload)?,
+                Kind::RaftMessageCompressed if compression => {
+                    raft::Envelope::decode(&frame.decompress()?.payload)?
+                }
+                Kind::RaftMessageCompressed => {
+                    return errdata!("received compressed Raft message without negotiation")
+                }
+                kind => return errdata!("unexpected {kind:?} frame from Raft peer"),
+            };
+            step_tx.send(envelope)?;
+        }
+        Ok(())
+    }
+
+    /// Connects to a Raft peer and performs the connection handshake. Returns
+    /// the socket and whether compression was negotiated.
+    fn connect_peer(&self, addr: &str) -> Result<(Stream, bool)> {
+        let socket = TcpStream::connect(addr)?;
+        let mut stream = match &self.tls {
+            Some((_, connector)) => connector.connect(socket, host(addr))?,
+            None => Stream::Tcp(socket),
+        };
+        frame::connect(&mut stream, false)?;
+        RaftHandshake { compression: self.compression }.to_frame().write_to(&mut stream)?;
+        stream.flush()?;
+        let Some(frame) = Frame::read_from(&mut stream)? else {
+            return errdata!("Raft peer {addr} closed the connection during handshake");
+        };
+        let response = RaftHandshake::from_frame(&frame)?;
+        debug!("Connected to Raft peer {addr} (compression={})", response.compression);
+        Ok((stream, self.compression && response.compression))
+    }
+}
+
+impl Transport for TcpTransport {
+    /// Accepts new inbound connections from peers, and spawns threads routing
+    /// inbound messages to the local Raft node. Never returns.
+    fn receive(&self, step_tx: Sender<raft::Envelope>) -> Result<()> {
+        std::thread::scope(|s| loop {
+            let (socket, peer) = match self.listener.accept() {
+                Ok((socket, peer)) => (socket, peer),
+                Err(err) => {
+                    error!("Raft peer accept failed: {err}");
+                    continue;
+                }
+            };
+            let step_tx = step_tx.clone();
+            s.spawn(move || {
+                debug!("Raft peer {peer} connected");
+                match self.receive_peer(socket, step_tx) {
+                    Ok(()) => debug!("Raft peer {peer} disconnected"),
+                    Err(err) => error!("Raft peer {peer} error: {err}"),
+                }
+            });
+        })
+    }
+
+    /// Sends outbound messages to a peer via TCP. Retries indefinitely if the
+    /// connection fails.
+    fn send(&self, peer: raft::NodeID, rx: Receiver<raft::Envelope>) {
+        let Some(addr) = self.peers.get(&peer) else {
+            error!("Unknown Raft peer {peer}, dropping messages");
+            while rx.recv().is_ok() {}
+            return;
+        };
+        loop {
+            let (mut socket, compression) = match self.connect_peer(addr) {
+                Ok((socket, compression)) => (std::io::BufWriter::new(socket), compression),
+                Err(err) => {
+                    error!("Failed connecting to Raft peer {addr}: {err}");
+                    std::thread::sleep(RAFT_PEER_RETRY_INTERVAL);
+                    continue;
+                }
+            };
+            loop {
+                let Ok(message) = rx.recv() else {
+                    return; // the node shut down
+                };
+                let frame = message_frame(&message, compression);
+                if let Err(err) = frame.write_to(&mut socket).and_then(|_| Ok(socket.flush()?)) {
+                    error!("Failed sending to Raft peer {addr}: {err}");
+                    break;
+                }
+            }
+            debug!("Disconnected from Raft peer {addr}");
+        }
+    }
+}
+
+/// Returns the host part of a host:port address, used as the TLS server name.
+/// Removes brackets around IPv6 addresses.
+pub(super) fn host(addr: &str) -> &str {
+    let host = addr.rsplit_once(':').map_or(addr, |(host, _)| host);
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
+/// A Raft peer connection handshake. Sent by the connecting peer when it
+/// establishes an outbound connection, and echoed back by the accepting peer
+/// with the negotiated settings.
+#[derive(Debug, PartialEq)]
+struct RaftHandshake {
+    /// Whether the peer supports (and has enabled) message compression. The
+    /// response contains true if both peers enabled it.
+    compression: bool,
+}
+
+impl RaftHandshake {
+    /// The compression flag.
+    const COMPRESSION: u8 = 0x01;
+
+    /// Decodes a handshake from a RaftHandshake frame. Unknown flags are
+    /// ignored, to allow adding new settings.
+    fn from_frame(frame: &Frame) -> Result<Self> {
+        match (frame.kind, frame.payload.as_slice()) {
+            (Kind::RaftHandshake, [flags]) => {
+                Ok(Self { compression: flags & Self::COMPRESSION != 0 })
+            }
+            (kind, _) => errdata!("invalid Raft handshake {kind:?} frame"),
+        }
+    }
+
+    /// Encodes the handshake as a RaftHandshake frame.
+    fn to_frame(&self) -> Frame {
+        let flags = if self.compression { Self::COMPRESSION } else { 0 };
+        Frame::new(Kind::RaftHandshake, vec![flags])
+    }
+}
+
+/// Creates a RaftMessage frame for the given envelope. If compression is
+/// enabled and the message is large, it is compressed if that makes it smaller.
+/// In practice, this mostly applies to Append messages with large entry
+/// batches, not heartbeats and votes.
+pub(super) fn message_frame(envelope: &raft::Envelope, compress: bool) -> Frame {
+    let frame = Frame::new(Kind::RaftMessage, envelope.encode());
+    if compress {
+        return frame.compress();
+    }
+    frame
+}
+
+/// An in-memory network connecting `MemoryTransport`s in the same process.
+/// Cloning it yields a handle to the same network.
+#[derive(Clone, Default)]
+pub struct MemoryNetwork {
+    /// Inbound message channels by node ID, registered via receive().
+    nodes: Arc<Mutex<HashMap<raft::NodeID, Sender<raft::Envelope>>>>,
+}
+
+impl MemoryNetwork {
+    /// Creates a new, empty network.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a transport for the given node on this network.
+    pub fn transport(&self, id: raft::NodeID) -> MemoryTransport {
+        MemoryTransport { id, network: self.clone() }
+    }
+}
+
+/// An in-memory transport, which delivers messages to other transports on the
+/// same `MemoryNetwork` via channels. Messages to nodes that haven't started
+/// receiving yet are dropped.
+pub struct MemoryTransport {
+    /// The local node ID.
+    id: raft::NodeID,
+    /// The network.
+    network: MemoryNetwork,
+}
+
+impl Transport for MemoryTransport {
+    /// Registers the node with the network, which delivers messages directly
+    /// to step_tx. Returns immediately.
+    fn receive(&self, step_tx: Sender<raft::Envelope>) -> Result<()> {
+        self.network.nodes.lock()?.insert(self.id, step_tx);
+        Ok(())
+    }
+
+    fn send(&self, peer: raft::NodeID, rx: Receiver<raft::Envelope>) {
+        while let Ok(message) = rx.recv() {
+            // Clone the sender out of the map, so we don't hold the lock while
+            // sending (which may block on a bounded channel).
+            let Ok(nodes) = self.network.nodes.lock() else {
+                return; // poisoned
+            };
+            let tx = nodes.get(&peer).cloned();
+            drop(nodes);
+            // The receiver may have shut down, ignore send errors.
+            if let Some(tx) = tx {
+                _ = tx.send(message);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sends a heartbeat from node 1 to node 2 via the given transports, and
+    /// asserts that it's received.
+    fn assert_delivery(t1: impl Transport + 'static, t2: impl Transport + 'static) {
+        let (step_tx, step_rx) = crossbeam::channel::unbounded();
+        let (peer_tx, peer_rx) = crossbeam::channel::unbounded();
+        std::thread::spawn(move || t2.receive(step_tx));
+        std::thread::spawn(move || t1.send(2, peer_rx));
+
+        let message = raft::Message::Heartbeat { last_index: 1, commit_index: 1, read_seq: 0 };
+        let envelope = raft::Envelope { from: 1, to: 2, term: 1, message };
+        peer_tx.send(envelope.clone()).expect("send failed");
+        let received = step_rx.recv_timeout(std::time::Duration::from_secs(10));
+        assert_eq!(received, Ok(envelope));
+    }
+
+    /// Messages are delivered via TCP.
+    #[test]
+    fn tcp() -> Result<()> {
+        let t2 = TcpTransport::bind("127.0.0.1:0", HashMap::new())?;
+        let peers = HashMap::from([(2, t2.local_addr()?.to_string())]);
+        let t1 = TcpTransport::bind("127.0.0.1:0", peers)?;
+        assert_delivery(t1, t2);
+        Ok(())
+    }
+
+    /// Messages are delivered in memory.
+    #[test]
+    fn memory() {
+        let network = MemoryNetwork::new();
+        let (t1, t2) = (network.transport(1), network.transport(2));
+        // Register node 2 before sending, since messages to unregistered nodes
+        // are dropped.
+        let (step_tx, step_rx) = crossbeam::channel::unbounded();
+        t2.receive(step_tx).expect("receive failed");
+        let (peer_tx, peer_rx) = crossbeam::channel::unbounded();
+        std::thread::spawn(move || t1.send(2, peer_rx));
+
+        let message = raft::Message::Heartbeat { last_index: 1, commit_index: 1, read_seq: 0 };
+        let envelope = raft::Envelope { from: 1, to: 2, term: 1, message };
+        peer_tx.send(envelope.clone()).expect("send failed");
+        assert_eq!(step_rx.recv(), Ok(envelope));
+    }
+}
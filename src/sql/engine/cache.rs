@@ -0,0 +1,152 @@
+use crate::error::Result;
+use crate::sql::planner::Plan;
+use crate::sql::types::{Label, Row};
+use crate::storage::mvcc;
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+/// Results with more rows than this aren't cached, to avoid evicting many
+/// small results (e.g. dashboard aggregates) for a single large one.
+const QUERY_CACHE_MAX_ROWS: usize = 1000;
+
+/// A cache of SELECT query results, shared by the sessions on a node (see
+/// Session::set_query_cache). This speeds up queries that are run repeatedly
+/// against tables that rarely change, e.g. dashboard aggregates.
+///
+/// Entries are keyed by database and statement text, and record the query
+/// plan and the versions of the latest transactions that wrote to the queried
+/// tables (see Transaction::table_versions). A cached result is only used if a
+/// new snapshot yields the same plan and table versions, i.e. if the schema
+/// hasn't changed and the tables haven't been written since. Writes thus
+/// invalidate cached results for the tables they write, whichever node and
+/// session they're made through.
+///
+/// The cache holds up to a fixed number of entries, evicting the least
+/// recently used entry when full.
+pub struct QueryCache {
+    /// The maximum number of entries.
+    capacity: usize,
+    /// The cache state.
+    state: Mutex<CacheState>,
+}
+
+/// The query cache state.
+#[derive(Default)]
+struct CacheState {
+    /// Cached results, by database and statement text.
+    entries: HashMap<(String, String), CacheEntry>,
+    /// Entry keys by last use, for LRU eviction.
+    lru: BTreeMap<u64, (String, String)>,
+    /// The logical clock used to order entries by last use.
+    clock: u64,
+    /// The number of lookups that used a cached result.
+    hits: u64,
+    /// The number of lookups that didn't.
+    misses: u64,
+}
+
+/// A cached query result.
+struct CacheEntry {
+    plan: Plan,
+    versions: Vec<mvcc::Version>,
+    columns: Vec<Label>,
+    rows: Vec<Row>,
+    /// The logical time of the entry's last use.
+    used: u64,
+}
+
+/// Query cache status.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct QueryCacheStatus {
+    /// The number of cached results.
+    pub entries: usize,
+    /// The number of lookups that used a cached result.
+    pub hits: u64,
+    /// The number of lookups that didn't.
+    pub misses: u64,
+}
+
+impl QueryCache {
+    /// Creates a new query cache holding up to the given number of entries.
+    pub fn new(capacity: usize) -> Self {
+        Self { capacity, state: Mutex::default() }
+    }
+
+    /// Looks up a cached result for the given statement, if its plan and table
+    /// versions match. Stale entries are removed.
+    pub fn get(
+        &self,
+        database: &str,
+        statement: &str,
+        plan: &Plan,
+        versions: &[mvcc::Version],
+    ) -> Result<Option<(Vec<Label>, Vec<Row>)>> {
+        let mut state = self.state.lock()?;
+        let key = (database.to_string(), statement.to_string());
+        let Some(entry) = state.entries.get(&key) else {
+            state.misses += 1;
+            return Ok(None);
+        };
+        if entry.plan != *plan || entry.versions != versions {
+            let used = entry.used;
+            state.entries.remove(&key);
+            state.lru.remove(&used);
+            state.misses += 1;
+            return Ok(None);
+        }
+        state.hits += 1;
+        state.clock += 1;
+        let clock = state.clock;
+        let state = &mut *state;
+        let entry = state.entries.get_mut(&key).expect("entry not found");
+        state.lru.remove(&entry.used);
+        state.lru.insert(clock, key);
+        entry.used = clock;
+        Ok(Some((entry.columns.clone(), entry.rows.clone())))
+    }
+
+    /// Caches the result of a statement with the given plan and table
+    /// versions, replacing any existing entry. Evicts the least recently used
+    /// entry if the cache is full.
+    pub fn insert(
+        &self,
+        database: &str,
+        statement: &str,
+        plan: Plan,
+        versions: Vec<mvcc::Version>,
+        columns: Vec<Label>,
+        rows: Vec<Row>,
+    ) -> Result<()> {
+        if self.capacity == 0 || rows.len() > QUERY_CACHE_MAX_ROWS {
+            return Ok(());
+        }
+        let mut state = self.state.lock()?;
+        let key = (database.to_string(), statement.to_string());
+        if let Some(entry) = state.entries.remove(&key) {
+            state.lru.remove(&entry.used);
+        }
+        while state.entries.len() >= self.capacity {
+            let Some((_, evict)) = state.lru.pop_first() else {
+                break;
+            };
+            state.entries.remove(&evict);
+        }
+        state.clock += 1;
+        let used = state.clock;
+        state.lru.insert(used, key.clone());
+        state.entries.insert(key, CacheEntry { plan, versions, columns, rows, used });
+        Ok(())
+    }
+
+    /// Returns the cache status.
+    pub fn status(&self) -> Result<QueryCacheStatus> {
+        let state = self.state.lock()?;
+        Ok(QueryCacheStatus {
+            entries: state.entries.len(),
+            hits: state.hits,
+            misses: state.misses,
+        })
+    }
+}
@@ -0,0 +1,203 @@
+use super::{Catalog, Transaction};
+use crate::error::Result;
+use crate::sql::types::{format_ident, Row, Table, Value};
+
+use itertools::Itertools as _;
+use std::collections::HashMap;
+use std::io::Write;
+
+/// The number of rows per INSERT statement in a dump.
+const DUMP_INSERT_BATCH: usize = 100;
+
+/// Dumps the database as seen by the given transaction as a SQL script of
+/// CREATE TABLE and INSERT statements, wrapped in a transaction. The script
+/// recreates the database when executed, e.g. via `toysql -i`, and doesn't
+/// depend on the storage format, so it can be used to migrate data between
+/// toyDB versions or to recover data from an unusable cluster.
+///
+/// Tables are created after the tables they reference, and rows are inserted
+/// after the rows they reference in the same table, such that foreign key
+/// constraints hold while loading. Rows that reference each other in a cycle
+/// (created via UPDATE) can't be inserted in any order, and will fail to load.
+/// Functions are created first, and triggers are created after all rows are
+/// inserted, so loading doesn't fire them.
+///
+/// Users and roles are not dumped, since passwords are only stored as hashes.
+pub fn dump(txn: &(impl Transaction + Catalog), w: &mut impl Write) -> Result<()> {
+    writeln!(w, "BEGIN;")?;
+
+    let functions = txn.list_functions()?;
+    if !functions.is_empty() {
+        writeln!(w)?;
+    }
+    for function in functions {
+        writeln!(w, "{function};")?;
+    }
+
+    let tables = txn.list_tables()?;
+    let names: HashMap<&str, usize> =
+        tables.iter().enumerate().map(|(i, t)| (t.name.as_str(), i)).collect();
+    let order = sort_dependencies(tables.len(), |i| {
+        tables[i]
+            .columns
+            .iter()
+            .filter_map(|c| c.references.as_deref())
+            .filter_map(|reference| names.get(reference).copied())
+            .collect()
+    });
+
+    for table in order.into_iter().map(|i| &tables[i]) {
+        writeln!(w)?;
+        writeln!(w, "{table};")?;
+        let name = format_ident(&table.name);
+        let rows = sort_rows(table, txn.scan(&table.name, None)?.collect::<Result<_>>()?);
+        for batch in &rows.into_iter().chunks(DUMP_INSERT_BATCH) {
+            let values = batch
+                .map(|row| format!("({})", row.iter().map(Value::to_sql).join(", ")))
+                .join(", ");
+            writeln!(w, "INSERT INTO {name} VALUES {values};")?;
+        }
+    }
+
+    let triggers: Vec<_> =
+        tables.iter().map(|t| txn.list_triggers(&t.name)).flatten_ok().try_collect()?;
+    if !triggers.is_empty() {
+        writeln!(w)?;
+    }
+    for trigger in triggers {
+        writeln!(w, "{trigger};")?;
+    }
+
+    writeln!(w)?;
+    writeln!(w, "COMMIT;")?;
+    Ok(())
+}
+
+/// Orders a table's rows such that rows referenced by other rows in the same
+/// table come first. Rows are otherwise kept in primary key order.
+fn sort_rows(table: &Table, mut rows: Vec<Row>) -> Vec<Row> {
+    let references = (0..table.columns.len())
+        .filter(|&i| table.columns[i].references.as_deref() == Some(&table.name))
+        .collect_vec();
+    if references.is_empty() {
+        return rows;
+    }
+    let pk = table.primary_key;
+    let ids: HashMap<&Value, usize> = rows.iter().enumerate().map(|(i, r)| (&r[pk], i)).collect();
+    let order = sort_dependencies(rows.len(), |i| {
+        references.iter().filter_map(|&c| ids.get(&rows[i][c]).copied()).collect()
+    });
+    // Move the rows out in order, leaving empty rows behind.
+    order.into_iter().map(|i| std::mem::take(&mut rows[i])).collect()
+}
+
+/// Orders the items 0..count such that each item comes after its dependencies,
+/// and items are otherwise kept in order. Dependencies that form a cycle are
+/// ignored, and self-dependencies are allowed.
+fn sort_dependencies(count: usize, dependencies: impl Fn(usize) -> Vec<usize>) -> Vec<usize> {
+    #[derive(Clone, Copy, PartialEq)]
+    enum Mark {
+        New,
+        Visiting,
+        Done,
+    }
+    let mut marks = vec![Mark::New; count];
+    let mut order = Vec::with_capacity(count);
+    // Use a depth-first search with an explicit stack, since dependency chains
+    // can be as long as the table (e.g. a linked list).
+    for start in 0..count {
+        let mut stack = vec![start];
+        while let Some(&i) = stack.last() {
+            if marks[i] == Mark::Done {
+                stack.pop();
+                continue;
+            }
+            marks[i] = Mark::Visiting;
+            match dependencies(i).into_iter().find(|&dep| marks[dep] == Mark::New) {
+                Some(dep) => stack.push(dep),
+                None => {
+                    marks[i] = Mark::Done;
+                    order.push(i);
+                    stack.pop();
+                }
+            }
+        }
+    }
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::engine::{Engine as _, Local};
+    use crate::storage::Memory;
+
+    /// Dumps a database and loads it into an empty database, checking that
+    /// the contents are the same.
+    #[test]
+    fn dump_load() -> Result<()> {
+        let engine = Local::new(Memory::new());
+        let mut session = engine.session();
+        for statement in [
+            r#"CREATE TABLE "Ref" (id INTEGER PRIMARY KEY, "Name" STRING DEFAULT 'it''s')"#,
+            "CREATE TABLE node (
+                id INTEGER PRIMARY KEY,
+                parent INTEGER REFERENCES node,
+                ref INTEGER REFERENCES \"Ref\",
+                value FLOAT DEFAULT -INFINITY,
+                flag BOOLEAN UNIQUE INDEX
+            )",
+            "CREATE TABLE empty (id STRING PRIMARY KEY)",
+            r#"CREATE FUNCTION "Half"(x FLOAT) RETURNS FLOAT AS x / 2.0"#,
+            "INSERT INTO \"Ref\" VALUES (1, 'a''b\nc'), (2, NULL)",
+            "INSERT INTO node VALUES (1, NULL, 1, NAN, TRUE), (2, 1, NULL, -0.0, FALSE)",
+            "INSERT INTO node (id, parent, flag) VALUES (3, NULL, NULL)",
+            // Make node 1 reference node 3, which must then be inserted first.
+            "UPDATE node SET parent = 3 WHERE id = 1",
+            // Fires for the rows inserted below. The dump creates it after the
+            // rows, so it doesn't fire again (and conflict) when loading.
+            "CREATE TRIGGER \"Log\" AFTER INSERT ON \"Ref\" INSERT INTO empty VALUES (NEW.\"Name\")",
+        ] {
+            session.execute(statement)?;
+        }
+        for i in 4..=250 {
+            session.execute(&format!("INSERT INTO \"Ref\" VALUES ({i}, '{i}')"))?;
+        }
+
+        let mut script = Vec::new();
+        dump(&engine.begin_read_only()?, &mut script)?;
+        let script = String::from_utf8(script)?;
+        let position = |s: &str| script.find(s).expect("not found");
+        assert!(position("CREATE TABLE \"Ref\"") < position("CREATE TABLE node"));
+        assert!(position("(3, NULL") < position("(1, 3"));
+
+        let loaded = Local::new(Memory::new());
+        let mut session = loaded.session();
+        for statement in script.split_terminator(";\n") {
+            session.execute(statement)?;
+        }
+
+        let (txn, loaded_txn) = (engine.begin_read_only()?, loaded.begin_read_only()?);
+        assert_eq!(txn.list_tables()?, loaded_txn.list_tables()?);
+        assert_eq!(txn.list_functions()?, loaded_txn.list_functions()?);
+        for table in txn.list_tables()? {
+            assert_eq!(txn.list_triggers(&table.name)?, loaded_txn.list_triggers(&table.name)?);
+            let rows: Vec<Row> = txn.scan(&table.name, None)?.collect::<Result<_>>()?;
+            let loaded_rows: Vec<Row> =
+                loaded_txn.scan(&table.name, None)?.collect::<Result<_>>()?;
+            assert_eq!(rows, loaded_rows, "table {}", table.name);
+        }
+
+        let mut reloaded = Vec::new();
+        dump(&loaded_txn, &mut reloaded)?;
+        assert_eq!(String::from_utf8(reloaded)?, script);
+        Ok(())
+    }
+
+    /// Dependencies are ordered first, ignoring cycles.
+    #[test]
+    fn sort_dependencies() {
+        let deps = [vec![2], vec![], vec![3, 2], vec![], vec![5], vec![4]];
+        assert_eq!(super::sort_dependencies(6, |i| deps[i].clone()), vec![3, 2, 0, 1, 5, 4]);
+    }
+}
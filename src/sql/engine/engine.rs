@@ -1,11 +1,16 @@
 #![allow(clippy::module_inception)]
 
 use super::Session;
+use crate::encoding;
 use crate::errinput;
 use crate::error::Result;
-use crate::sql::types::{Expression, Row, Rows, Table, Value};
+use crate::sql::types::{
+    Changefeed, Database, Expression, Function, Role, Row, Rows, Statistics, Table, Trigger, User,
+    Value, ValueRange,
+};
 use crate::storage::mvcc;
 
+use serde::{Deserialize, Serialize};
 use std::collections::{BTreeMap, BTreeSet};
 
 /// A SQL engine. This provides low-level CRUD (create, read, update, delete)
@@ -20,8 +25,16 @@ pub trait Engine<'a>: Sized {
 
     /// Begins a read-write transaction.
     fn begin(&'a self) -> Result<Self::Transaction>;
+    /// Begins a read-write transaction with serializable isolation.
+    fn begin_serializable(&'a self) -> Result<Self::Transaction>;
     /// Begins a read-only transaction.
     fn begin_read_only(&'a self) -> Result<Self::Transaction>;
+    /// Begins a read-only transaction which may read from a local replica at
+    /// most the given number of Raft ticks behind the leader, if the engine
+    /// serves replica reads. Otherwise, this is the same as begin_read_only().
+    fn begin_read_only_stale(&'a self, _max_staleness: u8) -> Result<Self::Transaction> {
+        self.begin_read_only()
+    }
     /// Begins a read-only transaction as of a historical version.
     fn begin_as_of(&'a self, version: mvcc::Version) -> Result<Self::Transaction>;
 
@@ -53,6 +66,14 @@ pub trait Transaction {
     fn commit(self) -> Result<()>;
     /// Rolls back the transaction.
     fn rollback(self) -> Result<()>;
+    /// Creates a savepoint with the given name.
+    fn savepoint(&self, name: &str) -> Result<()>;
+    /// Rolls back the transaction's writes since the given savepoint, keeping
+    /// the savepoint itself.
+    fn rollback_to_savepoint(&self, name: &str) -> Result<()>;
+    /// Releases the given savepoint and any savepoints created after it,
+    /// keeping their writes.
+    fn release_savepoint(&self, name: &str) -> Result<()>;
 
     /// Deletes table rows by primary key, if they exist.
     fn delete(&self, table: &str, ids: &[Value]) -> Result<()>;
@@ -62,22 +83,109 @@ pub trait Transaction {
     fn insert(&self, table: &str, rows: Vec<Row>) -> Result<()>;
     /// Looks up a set of primary keys by index values. BTreeSet for testing.
     fn lookup_index(&self, table: &str, column: &str, values: &[Value]) -> Result<BTreeSet<Value>>;
+    /// Looks up the primary keys of rows whose index values are in the given
+    /// range, ordered by index value and then primary key. NULLs are never in
+    /// the range.
+    fn lookup_index_range(
+        &self,
+        table: &str,
+        column: &str,
+        range: ValueRange,
+    ) -> Result<Vec<Value>>;
+    /// Looks up the primary keys of rows whose full-text indexed column matches
+    /// the given search query. BTreeSet for testing.
+    fn lookup_fulltext(&self, table: &str, column: &str, query: &str) -> Result<BTreeSet<Value>>;
     /// Scans a table's rows, optionally applying the given filter.
     fn scan(&self, table: &str, filter: Option<Expression>) -> Result<Rows>;
+    /// Counts a table's rows, like scan() without a filter, but without
+    /// reading the rows themselves where possible. Ignores row TTLs.
+    fn count(&self, table: &str) -> Result<u64>;
+    /// Scans a table's rows with primary keys in the given range, in primary
+    /// key order.
+    fn scan_range(&self, table: &str, range: ValueRange) -> Result<Rows>;
     /// Updates table rows by primary key. Uses BTreeMap for testing.
     fn update(&self, table: &str, rows: BTreeMap<Value, Row>) -> Result<()>;
+    /// Moves primary keys between the entries of a secondary index that is
+    /// being built (see Catalog::create_index), given as (id, old, new) where
+    /// old and new are the indexed values and None means no entry. Used to
+    /// backfill the index and catch up with concurrent writes.
+    fn backfill_index(
+        &self,
+        table: &str,
+        column: &str,
+        entries: Vec<(Value, Option<Value>, Option<Value>)>,
+    ) -> Result<()>;
+
+    /// Returns the version of the latest transaction that wrote to each of the
+    /// given tables (including their schema), whether or not it has committed.
+    /// The versions may be larger than necessary, e.g. for tables that haven't
+    /// been written since the node started, but they change on every write.
+    /// Used to validate cached query results, see QueryCache.
+    fn table_versions(&self, tables: &[String]) -> Result<Vec<mvcc::Version>>;
+
+    /// Returns the row changes in the given tables that were written at or
+    /// after the given version, up to the transaction's resolved version (see
+    /// `mvcc::TransactionState::resolved`). Changes are returned even if the
+    /// table has since been dropped.
+    fn changes(&self, tables: &[String], from: mvcc::Version) -> Result<Changes>;
+}
+
+/// A row change, i.e. a row version written by a committed transaction.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Change {
+    /// The version that wrote the change.
+    pub version: mvcc::Version,
+    /// The table name.
+    pub table: String,
+    /// The row's primary key.
+    pub id: Value,
+    /// The new row, or None if the row was deleted.
+    pub row: Option<Row>,
 }
 
-/// The catalog stores table schema information. It must be implemented for
-/// Engine::Transaction, and is thus fully transactional. For simplicity, it
-/// only supports creating and dropping tables. There are no ALTER TABLE schema
-/// changes, nor CREATE INDEX -- everything has to be specified when the table
-/// is initially created.
+/// A batch of row changes, returned by Transaction::changes().
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Changes {
+    /// The changes, ordered by version, table, and primary key.
+    pub changes: Vec<Change>,
+    /// The resolved version. All changes below it have been returned, and it
+    /// should be used as the from version of the next batch.
+    pub resolved: mvcc::Version,
+}
+
+impl encoding::Value for Changes {}
+
+/// The catalog stores table schema information, database users, and roles. It
+/// must be implemented for Engine::Transaction, and is thus fully
+/// transactional. For simplicity, it only supports creating and dropping
+/// tables, and adding secondary indexes. There are no other ALTER TABLE schema
+/// changes -- everything else has to be specified when the table is initially
+/// created.
 ///
 /// This type is separate from Transaction, even though Engine::Transaction
 /// requires transactions to implement it. This allows better control of when
 /// catalog access should be used (i.e. during planning, not execution).
+///
+/// Tables, changefeeds, triggers, and functions belong to the catalog's current
+/// database (initially the default database), while users, roles, and the
+/// databases themselves are shared by all databases.
 pub trait Catalog {
+    /// Returns the current database.
+    fn database(&self) -> &str;
+    /// Switches to the given database. Does not check that it exists.
+    fn use_database(&mut self, database: &str);
+
+    /// Creates a new database. Errors if it already exists.
+    fn create_database(&self, database: Database) -> Result<()>;
+    /// Drops a database and everything in it. Errors if it does not exist,
+    /// unless if_exists is true, or if it's the default or current database.
+    /// Returns true if the database existed and was deleted.
+    fn drop_database(&self, database: &str, if_exists: bool) -> Result<bool>;
+    /// Fetches a database, or None if it doesn't exist.
+    fn get_database(&self, database: &str) -> Result<Option<Database>>;
+    /// Returns a list of all databases, including the default database.
+    fn list_databases(&self) -> Result<Vec<Database>>;
+
     /// Creates a new table. Errors if it already exists.
     fn create_table(&self, table: Table) -> Result<()>;
     /// Drops a table. Errors if it does not exist, unless if_exists is true.
@@ -92,4 +200,65 @@ pub trait Catalog {
     fn must_get_table(&self, table: &str) -> Result<Table> {
         self.get_table(table)?.ok_or_else(|| errinput!("table {table} does not exist"))
     }
+
+    /// Creates a secondary index on a table column, in the building state:
+    /// writes maintain it from now on, but queries don't use it until it's
+    /// published via publish_index(), once existing rows have been backfilled.
+    /// Errors if the column is already indexed, and does nothing if the index
+    /// is already being built.
+    fn create_index(&self, table: &str, column: &str) -> Result<()>;
+    /// Publishes a secondary index that is being built, marking the column as
+    /// indexed in the table schema. Errors if the index isn't being built.
+    fn publish_index(&self, table: &str, column: &str) -> Result<()>;
+    /// Stores statistics for a table, replacing any existing statistics.
+    /// Errors if the table does not exist.
+    fn set_statistics(&self, table: &str, statistics: Statistics) -> Result<()>;
+
+    /// Creates a new user. Errors if it already exists.
+    fn create_user(&self, user: User) -> Result<()>;
+    /// Replaces an existing user, e.g. to change its password. Errors if it
+    /// does not exist.
+    fn alter_user(&self, user: User) -> Result<()>;
+    /// Fetches a user, or None if it doesn't exist.
+    fn get_user(&self, name: &str) -> Result<Option<User>>;
+    /// Returns a list of all users.
+    fn list_users(&self) -> Result<Vec<User>>;
+
+    /// Creates a new role. Errors if it already exists.
+    fn create_role(&self, role: Role) -> Result<()>;
+    /// Replaces an existing role, e.g. to change its privileges. Errors if it
+    /// does not exist.
+    fn alter_role(&self, role: Role) -> Result<()>;
+    /// Deletes a role. Errors if it does not exist. Does not revoke the role
+    /// from users.
+    fn drop_role(&self, role: &str) -> Result<()>;
+    /// Fetches a role, or None if it doesn't exist.
+    fn get_role(&self, name: &str) -> Result<Option<Role>>;
+    /// Returns a list of all roles.
+    fn list_roles(&self) -> Result<Vec<Role>>;
+
+    /// Creates a new changefeed. Errors if it already exists.
+    fn create_changefeed(&self, changefeed: Changefeed) -> Result<()>;
+    /// Drops a changefeed. Errors if it does not exist.
+    fn drop_changefeed(&self, name: &str) -> Result<()>;
+    /// Fetches a changefeed, or None if it doesn't exist.
+    fn get_changefeed(&self, name: &str) -> Result<Option<Changefeed>>;
+    /// Returns a list of all changefeeds.
+    fn list_changefeeds(&self) -> Result<Vec<Changefeed>>;
+
+    /// Creates a new trigger. Errors if it already exists on the table.
+    fn create_trigger(&self, trigger: Trigger) -> Result<()>;
+    /// Drops a table's trigger. Errors if it does not exist.
+    fn drop_trigger(&self, table: &str, name: &str) -> Result<()>;
+    /// Returns a list of a table's triggers, ordered by name.
+    fn list_triggers(&self, table: &str) -> Result<Vec<Trigger>>;
+
+    /// Creates a new function. Errors if it already exists.
+    fn create_function(&self, function: Function) -> Result<()>;
+    /// Drops a function. Errors if it does not exist.
+    fn drop_function(&self, name: &str) -> Result<()>;
+    /// Fetches a function, or None if it doesn't exist.
+    fn get_function(&self, name: &str) -> Result<Option<Function>>;
+    /// Returns a list of all functions.
+    fn list_functions(&self) -> Result<Vec<Function>>;
 }
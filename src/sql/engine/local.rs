@@ -1,26 +1,63 @@
-use super::Catalog;
-use crate::encoding::{self, Key as _, Value as _};
-use crate::errinput;
-use crate::error::Result;
-use crate::sql::types::{Expression, Row, Rows, Table, Value};
+use super::{Catalog, Change, Changes, InformationSchema};
+use crate::encoding::{self, keycode, Key as _, Versioned as _};
+use crate::error::{Context, Result};
+use crate::sql::types::{
+    terms, Changefeed, Column, Database, Expression, Function, Role, Row, Rows, SearchQuery,
+    SearchTerm, Statistics, Table, Trigger, User, Value, ValueRange, ValueRef,
+};
 use crate::storage::{self, mvcc};
+use crate::{errdata, errinput};
 
 use itertools::Itertools as _;
 use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
-use std::collections::{BTreeMap, BTreeSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ops::{Bound, RangeBounds};
+use std::sync::{Arc, Mutex};
 
 /// A SQL engine using local storage. This provides the main SQL storage logic,
 /// and the Raft SQL engine just dispatches to this for node-local SQL storage.
 pub struct Local<E: storage::Engine + 'static> {
     /// The local MVCC storage engine.
     pub mvcc: mvcc::MVCC<E>,
+    /// The latest versions that wrote to each table, shared by transactions.
+    table_versions: Arc<Mutex<TableVersions>>,
+}
+
+/// Tracks the version of the latest transaction that wrote to each table,
+/// whether or not it has committed, see Transaction::table_versions(). This is
+/// kept in memory, so tables that haven't been written since the engine was
+/// first used are attributed to the latest version at the time.
+#[derive(Default)]
+struct TableVersions {
+    /// The latest version when the engine was first used, if it has been.
+    initial: Option<mvcc::Version>,
+    /// The latest writer version of each table, by database and table name.
+    written: HashMap<(String, String), mvcc::Version>,
 }
 
 impl<E: storage::Engine> Local<E> {
     /// Creates a new local SQL engine using the given storage engine.
     pub fn new(engine: E) -> Self {
-        Self { mvcc: mvcc::MVCC::new(engine) }
+        Self { mvcc: mvcc::MVCC::new(engine), table_versions: Arc::default() }
+    }
+
+    /// Forgets the table versions, e.g. after the storage contents have been
+    /// replaced by a Raft snapshot. They're reinitialized on next use.
+    pub fn reset_table_versions(&self) -> Result<()> {
+        *self.table_versions.lock()? = TableVersions::default();
+        Ok(())
+    }
+
+    /// Creates a SQL transaction for the given MVCC transaction. Records the
+    /// initial table version on first use, which is at least as large as any
+    /// transaction that may have written before (including this one).
+    fn transaction(&self, txn: mvcc::Transaction<E>) -> Result<Transaction<E>> {
+        let mut table_versions = self.table_versions.lock()?;
+        if table_versions.initial.is_none() {
+            table_versions.initial = Some(self.mvcc.status()?.versions);
+        }
+        Ok(Transaction::new(txn, self.table_versions.clone()))
     }
 
     /// Resumes a transaction from the given state. This is usually encapsulated
@@ -28,7 +65,7 @@ impl<E: storage::Engine> Local<E> {
     /// transaction between each request since it may be executed across
     /// different leader nodes, so it instead keeps the state in the session.
     pub fn resume(&self, state: mvcc::TransactionState) -> Result<Transaction<E>> {
-        Ok(Transaction::new(self.mvcc.resume(state)?))
+        self.transaction(self.mvcc.resume(state)?)
     }
 
     /// Gets an unversioned key, or None if it doesn't exist.
@@ -46,27 +83,80 @@ impl<E: storage::Engine> super::Engine<'_> for Local<E> {
     type Transaction = Transaction<E>;
 
     fn begin(&self) -> Result<Self::Transaction> {
-        Ok(Self::Transaction::new(self.mvcc.begin()?))
+        self.transaction(self.mvcc.begin()?)
+    }
+
+    fn begin_serializable(&self) -> Result<Self::Transaction> {
+        self.transaction(self.mvcc.begin_serializable()?)
     }
 
     fn begin_read_only(&self) -> Result<Self::Transaction> {
-        Ok(Self::Transaction::new(self.mvcc.begin_read_only()?))
+        self.transaction(self.mvcc.begin_read_only()?)
     }
 
     fn begin_as_of(&self, version: mvcc::Version) -> Result<Self::Transaction> {
-        Ok(Self::Transaction::new(self.mvcc.begin_as_of(version)?))
+        self.transaction(self.mvcc.begin_as_of(version)?)
     }
 }
 
 /// A SQL transaction, wrapping an MVCC transaction.
 pub struct Transaction<E: storage::Engine + 'static> {
     txn: mvcc::Transaction<E>,
+    /// The current database. Table, changefeed, trigger, and function keys are
+    /// scoped to it, unless it's the default database.
+    database: String,
+    /// The latest versions that wrote to each table, shared with the engine.
+    table_versions: Arc<Mutex<TableVersions>>,
 }
 
 impl<E: storage::Engine> Transaction<E> {
     /// Creates a new SQL transaction using the given MVCC transaction.
-    fn new(txn: mvcc::Transaction<E>) -> Self {
-        Self { txn }
+    fn new(txn: mvcc::Transaction<E>, table_versions: Arc<Mutex<TableVersions>>) -> Self {
+        Self { txn, database: Database::DEFAULT.to_string(), table_versions }
+    }
+
+    /// Records a write to the given table in the current database, for
+    /// table_versions(). This is done before writing, and is retained even if
+    /// the write fails or the transaction rolls back, which is harmless.
+    fn record_write(&self, table: &str) -> Result<()> {
+        let mut table_versions = self.table_versions.lock()?;
+        let initial = table_versions.initial.unwrap_or(mvcc::Version::MAX);
+        let key = (self.database.clone(), table.to_string());
+        let version = table_versions.written.entry(key).or_insert(initial);
+        *version = (*version).max(self.txn.state().version);
+        Ok(())
+    }
+
+    /// Encodes a key in the current database. Keys in the default database
+    /// are stored as is, for compatibility with existing data.
+    fn key(&self, key: Key) -> Vec<u8> {
+        self.scoped(key.encode())
+    }
+
+    /// Encodes a key prefix in the current database.
+    fn prefix(&self, prefix: KeyPrefix) -> Vec<u8> {
+        self.scoped(prefix.encode())
+    }
+
+    /// Scopes an encoded key or key prefix to the current database. The
+    /// KeyCode encoding simply concatenates enum fields, so this is the same
+    /// as encoding Key::InDatabase.
+    fn scoped(&self, key: Vec<u8>) -> Vec<u8> {
+        if self.database == Database::DEFAULT {
+            return key;
+        }
+        let mut scoped = KeyPrefix::InDatabase((&self.database).into()).encode();
+        scoped.extend(key);
+        scoped
+    }
+
+    /// Errors if the current database does not exist, e.g. if it was dropped
+    /// by a different session.
+    fn check_database(&self) -> Result<()> {
+        if self.get_database(&self.database)?.is_none() {
+            return errinput!("database {} does not exist", self.database);
+        }
+        Ok(())
     }
 
     /// Returns the transaction's internal state.
@@ -74,6 +164,14 @@ impl<E: storage::Engine> Transaction<E> {
         self.txn.state()
     }
 
+    /// Errors if the given table is a read-only information_schema table.
+    fn check_writable(table: &str) -> Result<()> {
+        match InformationSchema::lookup(table) {
+            Some(_) => errinput!("system table {table} is read-only"),
+            None => Ok(()),
+        }
+    }
+
     /// Fetches the matching primary keys for the given secondary index value,
     /// or an empty set if there is none. The value must already be normalized.
     fn get_index(&self, table: &str, column: &str, value: &Value) -> Result<BTreeSet<Value>> {
@@ -81,28 +179,169 @@ impl<E: storage::Engine> Transaction<E> {
         debug_assert!(value.is_normalized(), "value not normalized");
         Ok(self
             .txn
-            .get(&Key::Index(table.into(), column.into(), value.into()).encode())?
-            .map(|v| BTreeSet::decode(&v))
+            .get(&self.key(Key::Index(table.into(), column.into(), value.into())))?
+            .map(|v| BTreeSet::decode_versioned(&v))
+            .transpose()?
+            .unwrap_or_default())
+    }
+
+    /// Fetches the primary keys of rows containing the given full-text term, or
+    /// an empty set if there are none.
+    fn get_fulltext(&self, table: &str, column: &str, term: &str) -> Result<BTreeSet<Value>> {
+        Ok(self
+            .txn
+            .get(&self.key(Key::FullText(table.into(), column.into(), term.into())))?
+            .map(|v| BTreeSet::decode_versioned(&v))
             .transpose()?
             .unwrap_or_default())
     }
 
+    /// Fetches the primary keys of rows matching the given full-text search
+    /// term. Prefix terms are looked up via a prefix scan of the index terms.
+    fn search_fulltext(
+        &self,
+        table: &str,
+        column: &str,
+        term: &SearchTerm,
+    ) -> Result<BTreeSet<Value>> {
+        let prefix = match term {
+            SearchTerm::Exact(term) => return self.get_fulltext(table, column, term),
+            SearchTerm::Prefix(prefix) => prefix,
+        };
+        // Strip the 0x0000 string terminator to get a prefix of the encoded
+        // term. Terms are alphanumeric, so they never contain escaped 0x00.
+        let mut key = self.key(Key::FullText(table.into(), column.into(), prefix.into()));
+        key.truncate(key.len() - 2);
+        let mut ids = BTreeSet::new();
+        for result in self.txn.scan_prefix(&key) {
+            let (_, value) = result?;
+            ids.extend(BTreeSet::<Value>::decode_versioned(&value)?);
+        }
+        Ok(ids)
+    }
+
+    /// Updates a full-text index for a row's column value changing from old to
+    /// new, removing the row from terms no longer present and adding it to new
+    /// terms. Use NULL for a missing old or new value.
+    fn update_fulltext(
+        &self,
+        table: &str,
+        column: &str,
+        id: &Value,
+        old: &Value,
+        new: &Value,
+    ) -> Result<()> {
+        let text_terms = |value: &Value| match value {
+            Value::String(text) => terms(text),
+            _ => BTreeSet::new(),
+        };
+        let (old, new) = (text_terms(old), text_terms(new));
+        for term in old.symmetric_difference(&new) {
+            let key = self.key(Key::FullText(table.into(), column.into(), term.into()));
+            let mut ids = self.get_fulltext(table, column, term)?;
+            if new.contains(term) {
+                ids.insert(id.clone());
+            } else {
+                ids.remove(id);
+            }
+            if ids.is_empty() {
+                self.txn.delete(&key)?;
+            } else {
+                self.txn.set(&key, ids.encode_versioned())?;
+            }
+        }
+        Ok(())
+    }
+
     /// Fetches a single row by primary key, or None if it doesn't exist. The key
     /// must already be normalized.
     fn get_row(&self, table: &str, id: &Value) -> Result<Option<Row>> {
         debug_assert!(id.is_normalized(), "value not normalized");
         self.txn
-            .get(&Key::Row(table.into(), id.into()).encode())?
-            .map(|v| Row::decode(&v))
-            .transpose()
+            .get(&self.key(Key::Row(table.into(), id.into())))
+            .and_then(|value| value.map(|v| Row::decode_versioned(&v)).transpose())
+            .map_err(|err| err.with_context(Self::row_context(table, Some(id))))
+    }
+
+    /// Returns an error context for the given table row.
+    fn row_context(table: &str, id: Option<&Value>) -> Context {
+        let context = Context::default().table(table);
+        match id {
+            Some(id) => context.key(id),
+            None => context,
+        }
+    }
+
+    /// Encodes a value range as a key range in the current database, using the
+    /// given key encoder for bound values and the given prefix for unbounded
+    /// ends. Returns None if the range is empty.
+    fn key_range(
+        &self,
+        range: ValueRange,
+        prefix: KeyPrefix,
+        key: impl Fn(&Value) -> Vec<u8>,
+    ) -> Option<impl RangeBounds<Vec<u8>>> {
+        use Bound::*;
+        if let (Included(start) | Excluded(start), Included(end) | Excluded(end)) = &range {
+            if start > end || start == end && !matches!(range, (Included(_), Included(_))) {
+                return None;
+            }
+        }
+        let (prefix_start, prefix_end) = keycode::prefix_range(&self.prefix(prefix));
+        let start = match range.0 {
+            Included(value) => Included(key(&value)),
+            Excluded(value) => Excluded(key(&value)),
+            Unbounded => prefix_start,
+        };
+        let end = match range.1 {
+            Included(value) => Included(key(&value)),
+            Excluded(value) => Excluded(key(&value)),
+            Unbounded => prefix_end,
+        };
+        Some((start, end))
     }
 
-    /// Returns true if the given secondary index exists.
+    /// Returns true if the given secondary index exists, or is being built.
     fn has_index(&self, table: &str, column: &str) -> Result<bool> {
-        let table = self.must_get_table(table)?;
+        let table = self.must_get_write_table(table)?;
         Ok(table.columns.iter().find(|c| c.name == column).map(|c| c.index).unwrap_or(false))
     }
 
+    /// Returns the names of a table's columns whose secondary index is being
+    /// built.
+    fn index_builds(&self, table: &str) -> Result<BTreeSet<String>> {
+        self.txn
+            .scan_prefix(&self.prefix(KeyPrefix::IndexBuild(table.into())))
+            .map(|r| {
+                let (key, _) = r?;
+                let key = match Key::decode(&key)? {
+                    Key::InDatabase(_, key) => *key,
+                    key => key,
+                };
+                match key {
+                    Key::IndexBuild(_, column) => Ok(column.into_owned()),
+                    key => errdata!("expected index build key, got {key:?}"),
+                }
+            })
+            .collect()
+    }
+
+    /// Fetches a table schema for writing rows, or errors if it does not exist.
+    /// Secondary indexes that are being built are marked as indexed, such that
+    /// writes maintain them.
+    fn must_get_write_table(&self, table: &str) -> Result<Table> {
+        let mut table = self.must_get_table(table)?;
+        if InformationSchema::lookup(&table.name).is_some() {
+            return Ok(table);
+        }
+        for column in self.index_builds(&table.name)? {
+            if let Some(column) = table.columns.iter_mut().find(|c| c.name == column) {
+                column.index = true;
+            }
+        }
+        Ok(table)
+    }
+
     /// Stores a secondary index entry for the given column value, replacing the
     /// existing entry. The value and ids must already be normalized.
     fn set_index(
@@ -115,12 +354,109 @@ impl<E: storage::Engine> Transaction<E> {
         debug_assert!(self.has_index(table, column)?, "no index on {table}.{column}");
         debug_assert!(value.is_normalized(), "value not normalized");
         debug_assert!(ids.iter().all(|v| v.is_normalized()), "value not normalized");
-        let key = Key::Index(table.into(), column.into(), value.into()).encode();
+        let key = self.key(Key::Index(table.into(), column.into(), value.into()));
         if ids.is_empty() {
             self.txn.delete(&key)
         } else {
-            self.txn.set(&key, ids.encode())
+            self.txn.set(&key, ids.encode_versioned())
+        }
+    }
+
+    /// Deletes a single row by primary key, along with its secondary and
+    /// full-text index entries, given as (column index, column) pairs.
+    fn delete_row(
+        &self,
+        table: &Table,
+        indexes: &[(usize, &Column)],
+        id: Cow<Value>,
+    ) -> Result<()> {
+        // Update any index entries.
+        if !indexes.is_empty() {
+            if let Some(row) = self.get_row(&table.name, &id)? {
+                for (i, column) in indexes.iter().copied() {
+                    if column.fulltext {
+                        self.update_fulltext(
+                            &table.name,
+                            &column.name,
+                            &id,
+                            &row[i],
+                            &Value::Null,
+                        )?;
+                    }
+                    if !column.index {
+                        continue;
+                    }
+                    let mut index = self.get_index(&table.name, &column.name, &row[i])?;
+                    index.remove(&id);
+                    self.set_index(&table.name, &column.name, &row[i], index)?;
+                }
+            }
+        }
+        self.txn.delete(&self.key(Key::Row((&table.name).into(), id)))
+    }
+
+    /// Inserts a single normalized row, updating any secondary and full-text
+    /// indexes.
+    fn insert_row(&self, table: &Table, row: Row) -> Result<()> {
+        table.validate_row(&row, false, self)?;
+        let id = &row[table.primary_key];
+        self.txn
+            .set(&self.key(Key::Row((&table.name).into(), id.into())), row.encode_versioned())?;
+
+        // Update any secondary indexes.
+        for (i, column) in table.columns.iter().enumerate().filter(|(_, c)| c.index) {
+            let mut index = self.get_index(&table.name, &column.name, &row[i])?;
+            index.insert(id.clone());
+            self.set_index(&table.name, &column.name, &row[i], index)?;
+        }
+
+        // Update any full-text indexes.
+        for (i, column) in table.columns.iter().enumerate().filter(|(_, c)| c.fulltext) {
+            self.update_fulltext(&table.name, &column.name, id, &Value::Null, &row[i])?;
+        }
+        Ok(())
+    }
+
+    /// Updates a single normalized row whose primary key hasn't changed,
+    /// updating any secondary and full-text indexes.
+    fn update_row(&self, table: &Table, id: Value, row: Row) -> Result<()> {
+        // Validate the row, but don't write it yet since we may need to
+        // read the existing value to update secondary indexes.
+        table.validate_row(&row, true, self)?;
+
+        // Update indexes, knowing that the primary key has not changed.
+        let indexes =
+            table.columns.iter().enumerate().filter(|(_, c)| c.index || c.fulltext).collect_vec();
+        if !indexes.is_empty() {
+            let old = self.get_row(&table.name, &id)?.expect("updated row not found");
+            for (i, column) in indexes {
+                // If the value didn't change, we don't have to do anything.
+                if old[i] == row[i] {
+                    continue;
+                }
+
+                if column.fulltext {
+                    self.update_fulltext(&table.name, &column.name, &id, &old[i], &row[i])?;
+                }
+                if !column.index {
+                    continue;
+                }
+
+                // Remove the old value from the index entry.
+                let mut index = self.get_index(&table.name, &column.name, &old[i])?;
+                index.remove(&id);
+                self.set_index(&table.name, &column.name, &old[i], index)?;
+
+                // Insert the new value into the index entry.
+                let mut index = self.get_index(&table.name, &column.name, &row[i])?;
+                index.insert(id.clone());
+                self.set_index(&table.name, &column.name, &row[i], index)?;
+            }
         }
+
+        // Update the row.
+        self.txn
+            .set(&self.key(Key::Row((&table.name).into(), (&id).into())), row.encode_versioned())
     }
 
     /// Returns all tables referencing a table, as (table, column index) pairs.
@@ -157,9 +493,24 @@ impl<E: storage::Engine> super::Transaction for Transaction<E> {
         self.txn.rollback()
     }
 
+    fn savepoint(&self, name: &str) -> Result<()> {
+        self.txn.savepoint(name)
+    }
+
+    fn rollback_to_savepoint(&self, name: &str) -> Result<()> {
+        self.txn.rollback_to_savepoint(name)
+    }
+
+    fn release_savepoint(&self, name: &str) -> Result<()> {
+        self.txn.release_savepoint(name)
+    }
+
     fn delete(&self, table: &str, ids: &[Value]) -> Result<()> {
-        let table = self.must_get_table(table)?;
-        let indexes = table.columns.iter().enumerate().filter(|(_, c)| c.index).collect_vec();
+        Self::check_writable(table)?;
+        self.record_write(table)?;
+        let table = self.must_get_write_table(table)?;
+        let indexes =
+            table.columns.iter().enumerate().filter(|(_, c)| c.index || c.fulltext).collect_vec();
 
         // Check for foreign key references to the deleted rows.
         for (source, refs) in self.table_references(&table.name)? {
@@ -196,44 +547,28 @@ impl<E: storage::Engine> super::Transaction for Transaction<E> {
         // Delete the rows.
         for id in ids {
             let id = id.normalize_ref();
-
-            // Update any index entries.
-            if !indexes.is_empty() {
-                if let Some(row) = self.get_row(&table.name, &id)? {
-                    for (i, column) in indexes.iter().copied() {
-                        let mut index = self.get_index(&table.name, &column.name, &row[i])?;
-                        index.remove(&id);
-                        self.set_index(&table.name, &column.name, &row[i], index)?;
-                    }
-                }
-            }
-
-            self.txn.delete(&Key::Row((&table.name).into(), id).encode())?;
+            let context = Self::row_context(&table.name, Some(&id));
+            self.delete_row(&table, &indexes, id).map_err(|err| err.with_context(context))?;
         }
         Ok(())
     }
 
     fn get(&self, table: &str, ids: &[Value]) -> Result<Vec<Row>> {
+        if let Some(system) = InformationSchema::lookup(table) {
+            return system.get(self, ids);
+        }
         ids.iter().filter_map(|id| self.get_row(table, &id.normalize_ref()).transpose()).collect()
     }
 
     fn insert(&self, table: &str, rows: Vec<Row>) -> Result<()> {
-        let table = self.must_get_table(table)?;
+        Self::check_writable(table)?;
+        self.record_write(table)?;
+        let table = self.must_get_write_table(table)?;
         for mut row in rows {
             // Normalize the row.
             row.iter_mut().for_each(|v| v.normalize());
-
-            // Insert the row.
-            table.validate_row(&row, false, self)?;
-            let id = &row[table.primary_key];
-            self.txn.set(&Key::Row((&table.name).into(), id.into()).encode(), row.encode())?;
-
-            // Update any secondary indexes.
-            for (i, column) in table.columns.iter().enumerate().filter(|(_, c)| c.index) {
-                let mut index = self.get_index(&table.name, &column.name, &row[i])?;
-                index.insert(id.clone());
-                self.set_index(&table.name, &column.name, &row[i], index)?;
-            }
+            let context = Self::row_context(&table.name, row.get(table.primary_key));
+            self.insert_row(&table, row).map_err(|err| err.with_context(context))?;
         }
         Ok(())
     }
@@ -247,30 +582,99 @@ impl<E: storage::Engine> super::Transaction for Transaction<E> {
             .collect()
     }
 
+    fn lookup_index_range(
+        &self,
+        table: &str,
+        column: &str,
+        range: ValueRange,
+    ) -> Result<Vec<Value>> {
+        debug_assert!(self.has_index(table, column)?, "no index on {table}.{column}");
+        // NULLs sort first in the index, so skip them if there's no start.
+        let range = match range {
+            (Bound::Unbounded, end) => (Bound::Excluded(Value::Null), end),
+            range => range,
+        };
+        let prefix = KeyPrefix::Index(table.into(), column.into());
+        let key = |value: &Value| {
+            self.key(Key::Index(table.into(), column.into(), value.normalize_ref()))
+        };
+        let Some(range) = self.key_range(range, prefix, key) else {
+            return Ok(Vec::new());
+        };
+        self.txn
+            .scan(range)
+            .map(|result| result.and_then(|(_, ids)| BTreeSet::<Value>::decode_versioned(&ids)))
+            .flatten_ok()
+            .collect()
+    }
+
+    fn lookup_fulltext(&self, table: &str, column: &str, query: &str) -> Result<BTreeSet<Value>> {
+        let query = SearchQuery::parse(query)?;
+        // Intersect the matches for each term, since all terms must match.
+        let mut terms = query.terms().iter();
+        let first = terms.next().expect("empty query");
+        let mut ids = self.search_fulltext(table, column, first)?;
+        for term in terms {
+            if ids.is_empty() {
+                break;
+            }
+            let matches = self.search_fulltext(table, column, term)?;
+            ids.retain(|id| matches.contains(id));
+        }
+        Ok(ids)
+    }
+
     // noinspection DuplicatedCode
     fn scan(&self, table: &str, filter: Option<Expression>) -> Result<Rows> {
+        if let Some(system) = InformationSchema::lookup(table) {
+            return Ok(Box::new(system.scan(self, filter)?.into_iter().map(Ok)));
+        }
         // TODO: this could be simpler if process_results() implemented Clone.
-        let rows = self
-            .txn
-            .scan_prefix(&KeyPrefix::Row(table.into()).encode())
-            .map(|result| result.and_then(|(_, value)| Row::decode(&value)));
+        let rows = self.txn.scan_prefix(&self.prefix(KeyPrefix::Row(table.into())));
         let Some(filter) = filter else {
-            return Ok(Box::new(rows));
+            return Ok(Box::new(
+                rows.map(|result| result.and_then(|(_, value)| Row::decode_versioned(&value))),
+            ));
         };
+        // Evaluate the filter on borrowed rows, and only copy matching rows.
         let rows = rows.filter_map(move |result| {
             result
-                .and_then(|row| match filter.evaluate(Some(&row))? {
-                    Value::Boolean(true) => Ok(Some(row)),
-                    Value::Boolean(false) | Value::Null => Ok(None),
-                    value => errinput!("filter returned {value}, expected boolean"),
+                .and_then(|(_, value)| {
+                    let row = ValueRef::decode_row(&value)?;
+                    match filter.evaluate_ref(&row)? {
+                        Value::Boolean(true) => {
+                            Ok(Some(row.into_iter().map(|v| v.into_owned()).collect()))
+                        }
+                        Value::Boolean(false) | Value::Null => Ok(None),
+                        value => errinput!("filter returned {value}, expected boolean"),
+                    }
                 })
                 .transpose()
         });
         Ok(Box::new(rows))
     }
 
+    fn count(&self, table: &str) -> Result<u64> {
+        if let Some(system) = InformationSchema::lookup(table) {
+            return Ok(system.scan(self, None)?.len() as u64);
+        }
+        self.txn.count_prefix(&self.prefix(KeyPrefix::Row(table.into())))
+    }
+
+    fn scan_range(&self, table: &str, range: ValueRange) -> Result<Rows> {
+        let prefix = KeyPrefix::Row(table.into());
+        let key = |value: &Value| self.key(Key::Row(table.into(), value.normalize_ref()));
+        let Some(range) = self.key_range(range, prefix, key) else {
+            return Ok(Box::new(std::iter::empty()));
+        };
+        let rows = self.txn.scan(range);
+        Ok(Box::new(rows.map(|result| result.and_then(|(_, value)| Row::decode_versioned(&value)))))
+    }
+
     fn update(&self, table: &str, rows: BTreeMap<Value, Row>) -> Result<()> {
-        let table = self.must_get_table(table)?;
+        Self::check_writable(table)?;
+        self.record_write(table)?;
+        let table = self.must_get_write_table(table)?;
         for (mut id, mut row) in rows {
             // Normalize the ID and row.
             id.normalize();
@@ -284,54 +688,174 @@ impl<E: storage::Engine> super::Transaction for Transaction<E> {
                 continue;
             }
 
-            // Validate the row, but don't write it yet since we may need to
-            // read the existing value to update secondary indexes.
-            table.validate_row(&row, true, self)?;
-
-            // Update indexes, knowing that the primary key has not changed.
-            let indexes = table.columns.iter().enumerate().filter(|(_, c)| c.index).collect_vec();
-            if !indexes.is_empty() {
-                let old = self.get(&table.name, &[id.clone()])?.remove(0);
-                for (i, column) in indexes {
-                    // If the value didn't change, we don't have to do anything.
-                    if old[i] == row[i] {
-                        continue;
-                    }
-
-                    // Remove the old value from the index entry.
-                    let mut index = self.get_index(&table.name, &column.name, &old[i])?;
-                    index.remove(&id);
-                    self.set_index(&table.name, &column.name, &old[i], index)?;
+            let context = Self::row_context(&table.name, Some(&id));
+            self.update_row(&table, id, row).map_err(|err| err.with_context(context))?;
+        }
+        Ok(())
+    }
 
-                    // Insert the new value into the index entry.
-                    let mut index = self.get_index(&table.name, &column.name, &row[i])?;
-                    index.insert(id.clone());
-                    self.set_index(&table.name, &column.name, &row[i], index)?;
-                }
+    fn backfill_index(
+        &self,
+        table: &str,
+        column: &str,
+        entries: Vec<(Value, Option<Value>, Option<Value>)>,
+    ) -> Result<()> {
+        Self::check_writable(table)?;
+        self.record_write(table)?;
+        if !self.index_builds(table)?.contains(column) {
+            return errinput!("index on {table}.{column} is not being built");
+        }
+        for (mut id, mut old, mut new) in entries {
+            id.normalize();
+            old.iter_mut().chain(new.iter_mut()).for_each(|v| v.normalize());
+            if old == new {
+                continue;
+            }
+            if let Some(old) = old {
+                let mut index = self.get_index(table, column, &old)?;
+                index.remove(&id);
+                self.set_index(table, column, &old, index)?;
+            }
+            if let Some(new) = new {
+                let mut index = self.get_index(table, column, &new)?;
+                index.insert(id);
+                self.set_index(table, column, &new, index)?;
             }
-
-            // Update the row.
-            self.txn.set(&Key::Row((&table.name).into(), (&id).into()).encode(), row.encode())?;
         }
         Ok(())
     }
+
+    fn table_versions(&self, tables: &[String]) -> Result<Vec<mvcc::Version>> {
+        let table_versions = self.table_versions.lock()?;
+        let initial = table_versions.initial.unwrap_or(mvcc::Version::MAX);
+        Ok(tables
+            .iter()
+            .map(|table| {
+                let key = (self.database.clone(), table.clone());
+                table_versions.written.get(&key).copied().unwrap_or(initial)
+            })
+            .collect())
+    }
+
+    fn changes(&self, tables: &[String], from: mvcc::Version) -> Result<Changes> {
+        let mut changes = Vec::new();
+        for table in tables.iter().collect::<BTreeSet<_>>() {
+            if InformationSchema::lookup(table).is_some() {
+                return errinput!("can't emit changes for system table {table}");
+            }
+            let prefix = self.prefix(KeyPrefix::Row(table.into()));
+            for (key, version, value) in self.txn.scan_changes(&prefix, from)? {
+                let key = match Key::decode(&key)? {
+                    Key::InDatabase(_, key) => *key,
+                    key => key,
+                };
+                let Key::Row(_, id) = key else {
+                    return errdata!("expected row key, got {key:?}");
+                };
+                let row = value.map(|v| Row::decode_versioned(&v)).transpose()?;
+                changes.push(Change { version, table: table.clone(), id: id.into_owned(), row });
+            }
+        }
+        // Each table's changes are ordered by version and primary key, and the
+        // tables by name, so a stable sort by version retains the table and
+        // primary key order.
+        changes.sort_by_key(|change| change.version);
+        Ok(Changes { changes, resolved: self.txn.state().resolved() })
+    }
 }
 
 impl<E: storage::Engine> Catalog for Transaction<E> {
+    fn database(&self) -> &str {
+        &self.database
+    }
+
+    fn use_database(&mut self, database: &str) {
+        self.database = database.to_string();
+    }
+
+    fn create_database(&self, database: Database) -> Result<()> {
+        if self.get_database(&database.name)?.is_some() {
+            return errinput!("database {} already exists", database.name);
+        }
+        database.validate()?;
+        let key = Key::Database((&database.name).into()).encode();
+        self.txn.set(&key, database.encode_versioned())
+    }
+
+    fn drop_database(&self, database: &str, if_exists: bool) -> Result<bool> {
+        if database == Database::DEFAULT {
+            return errinput!("can't drop default database {database}");
+        }
+        if database == self.database {
+            return errinput!("can't drop current database {database}");
+        }
+        match self.get_database(database)? {
+            Some(_) => {}
+            None if if_exists => return Ok(false),
+            None => return errinput!("database {database} does not exist"),
+        }
+
+        // Record writes to all of the database's tables. Tables that aren't
+        // recorded can't have been written since the initial version.
+        let version = self.txn.state().version;
+        let mut table_versions = self.table_versions.lock()?;
+        for ((db, _), written) in table_versions.written.iter_mut() {
+            if db == database {
+                *written = (*written).max(version);
+            }
+        }
+        drop(table_versions);
+
+        // Delete all of the database's keys, buffering them since we can't
+        // write while scanning (see drop_table).
+        let prefix = &KeyPrefix::InDatabase(database.into()).encode();
+        let keys: Vec<_> = self.txn.scan_prefix(prefix).map_ok(|(key, _)| key).try_collect()?;
+        for key in keys {
+            self.txn.delete(&key)?;
+        }
+        self.txn.delete(&Key::Database(database.into()).encode())?;
+        Ok(true)
+    }
+
+    fn get_database(&self, database: &str) -> Result<Option<Database>> {
+        if database == Database::DEFAULT {
+            return Ok(Some(Database { name: database.to_string() }));
+        }
+        self.txn
+            .get(&Key::Database(database.into()).encode())?
+            .map(|v| Database::decode_versioned(&v))
+            .transpose()
+    }
+
+    fn list_databases(&self) -> Result<Vec<Database>> {
+        let mut databases: Vec<_> = self
+            .txn
+            .scan_prefix(&KeyPrefix::Database.encode())
+            .map(|r| r.and_then(|(_, v)| Database::decode_versioned(&v)))
+            .try_collect()?;
+        databases.push(Database { name: Database::DEFAULT.to_string() });
+        databases.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(databases)
+    }
+
     fn create_table(&self, table: Table) -> Result<()> {
+        self.check_database()?;
         if self.get_table(&table.name)?.is_some() {
             return errinput!("table {} already exists", table.name);
         }
         table.validate(self)?;
-        self.txn.set(&Key::Table((&table.name).into()).encode(), table.encode())
+        self.record_write(&table.name)?;
+        self.txn.set(&self.key(Key::Table((&table.name).into())), table.encode_versioned())
     }
 
     fn drop_table(&self, table: &str, if_exists: bool) -> Result<bool> {
+        Self::check_writable(table)?;
         let table = match self.get_table(table)? {
             Some(table) => table,
             None if if_exists => return Ok(false),
             None => return errinput!("table {table} does not exist"),
         };
+        self.record_write(&table.name)?;
 
         // Check for foreign key references.
         if let Some((source, refs)) =
@@ -345,23 +869,63 @@ impl<E: storage::Engine> Catalog for Transaction<E> {
             );
         }
 
+        // Check for changefeeds emitting the table's changes.
+        if let Some(changefeed) =
+            self.list_changefeeds()?.into_iter().find(|c| c.tables.contains(&table.name))
+        {
+            return errinput!("table {} is used by changefeed {}", table.name, changefeed.name);
+        }
+
+        // Check for other tables' triggers writing to the table.
+        for source in self.list_tables()?.into_iter().filter(|t| t.name != table.name) {
+            for trigger in self.list_triggers(&source.name)? {
+                if trigger.target()?.0 == table.name {
+                    return errinput!(
+                        "table {} is used by trigger {} on {}",
+                        table.name,
+                        trigger.name,
+                        source.name
+                    );
+                }
+            }
+        }
+
+        // Delete the table's triggers.
+        for trigger in self.list_triggers(&table.name)? {
+            self.txn.delete(&self.key(Key::Trigger((&table.name).into(), trigger.name.into())))?;
+        }
+
         // Delete the table schema entry.
-        self.txn.delete(&Key::Table((&table.name).into()).encode())?;
+        self.txn.delete(&self.key(Key::Table((&table.name).into())))?;
 
         // Delete the table rows. storage::Engine doesn't support writing while
         // scanning, so we buffer all keys in a vector. We could also do this in
         // batches, although we'd want to do the batching above Raft to avoid
         // blocking Raft processing for the duration of the drop.
-        let prefix = &KeyPrefix::Row((&table.name).into()).encode();
+        let prefix = &self.prefix(KeyPrefix::Row((&table.name).into()));
         let keys: Vec<Vec<u8>> =
             self.txn.scan_prefix(prefix).map_ok(|(key, _)| key).try_collect()?;
         for key in keys {
             self.txn.delete(&key)?;
         }
 
-        // Delete any secondary indexes.
-        for column in table.columns.iter().filter(|c| c.index) {
-            let prefix = &KeyPrefix::Index((&table.name).into(), (&column.name).into()).encode();
+        // Delete any secondary and full-text indexes, including indexes that
+        // are being built.
+        let builds = self.index_builds(&table.name)?;
+        for column in &builds {
+            self.txn.delete(&self.key(Key::IndexBuild((&table.name).into(), column.into())))?;
+        }
+        for column in table.columns.iter().filter(|c| c.index || builds.contains(&c.name)) {
+            let prefix =
+                &self.prefix(KeyPrefix::Index((&table.name).into(), (&column.name).into()));
+            let keys: Vec<_> = self.txn.scan_prefix(prefix).map_ok(|(key, _)| key).try_collect()?;
+            for key in keys {
+                self.txn.delete(&key)?;
+            }
+        }
+        for column in table.columns.iter().filter(|c| c.fulltext) {
+            let prefix =
+                &self.prefix(KeyPrefix::FullText((&table.name).into(), (&column.name).into()));
             let keys: Vec<_> = self.txn.scan_prefix(prefix).map_ok(|(key, _)| key).try_collect()?;
             for key in keys {
                 self.txn.delete(&key)?;
@@ -371,13 +935,205 @@ impl<E: storage::Engine> Catalog for Transaction<E> {
     }
 
     fn get_table(&self, table: &str) -> Result<Option<Table>> {
-        self.txn.get(&Key::Table(table.into()).encode())?.map(|v| Table::decode(&v)).transpose()
+        if let Some(system) = InformationSchema::lookup(table) {
+            return Ok(Some(system.schema()));
+        }
+        self.txn
+            .get(&self.key(Key::Table(table.into())))?
+            .map(|v| Table::decode_versioned(&v))
+            .transpose()
     }
 
     fn list_tables(&self) -> Result<Vec<Table>> {
         self.txn
-            .scan_prefix(&KeyPrefix::Table.encode())
-            .map(|r| r.and_then(|(_, v)| Table::decode(&v)))
+            .scan_prefix(&self.prefix(KeyPrefix::Table))
+            .map(|r| r.and_then(|(_, v)| Table::decode_versioned(&v)))
+            .collect()
+    }
+
+    fn create_index(&self, table: &str, column: &str) -> Result<()> {
+        Self::check_writable(table)?;
+        let table = self.must_get_table(table)?;
+        let Some(i) = table.columns.iter().position(|c| c.name == column) else {
+            return errinput!("unknown column {column} in table {}", table.name);
+        };
+        if i == table.primary_key {
+            return errinput!("primary key {column} can't have an index");
+        }
+        if table.columns[i].index {
+            return errinput!("column {column} already has an index");
+        }
+        let key = self.key(Key::IndexBuild((&table.name).into(), column.into()));
+        self.txn.set(&key, Vec::new())
+    }
+
+    fn publish_index(&self, table: &str, column: &str) -> Result<()> {
+        let key = self.key(Key::IndexBuild(table.into(), column.into()));
+        if self.txn.get(&key)?.is_none() {
+            return errinput!("index on {table}.{column} is not being built");
+        }
+        let mut table = self.must_get_table(table)?;
+        self.record_write(&table.name)?;
+        for c in table.columns.iter_mut().filter(|c| c.name == column) {
+            c.index = true;
+        }
+        self.txn.delete(&key)?;
+        self.txn.set(&self.key(Key::Table((&table.name).into())), table.encode_versioned())
+    }
+
+    fn set_statistics(&self, table: &str, statistics: Statistics) -> Result<()> {
+        Self::check_writable(table)?;
+        let mut table = self.must_get_table(table)?;
+        self.record_write(&table.name)?;
+        table.statistics = Some(statistics);
+        self.txn.set(&self.key(Key::Table((&table.name).into())), table.encode_versioned())
+    }
+
+    fn create_user(&self, user: User) -> Result<()> {
+        if self.get_user(&user.name)?.is_some() {
+            return errinput!("user {} already exists", user.name);
+        }
+        self.txn.set(&Key::User((&user.name).into()).encode(), user.encode_versioned())
+    }
+
+    fn alter_user(&self, user: User) -> Result<()> {
+        if self.get_user(&user.name)?.is_none() {
+            return errinput!("user {} does not exist", user.name);
+        }
+        self.txn.set(&Key::User((&user.name).into()).encode(), user.encode_versioned())
+    }
+
+    fn get_user(&self, name: &str) -> Result<Option<User>> {
+        self.txn
+            .get(&Key::User(name.into()).encode())?
+            .map(|v| User::decode_versioned(&v))
+            .transpose()
+    }
+
+    fn list_users(&self) -> Result<Vec<User>> {
+        self.txn
+            .scan_prefix(&KeyPrefix::User.encode())
+            .map(|r| r.and_then(|(_, v)| User::decode_versioned(&v)))
+            .collect()
+    }
+
+    fn create_role(&self, role: Role) -> Result<()> {
+        if self.get_role(&role.name)?.is_some() {
+            return errinput!("role {} already exists", role.name);
+        }
+        self.txn.set(&Key::Role((&role.name).into()).encode(), role.encode_versioned())
+    }
+
+    fn alter_role(&self, role: Role) -> Result<()> {
+        if self.get_role(&role.name)?.is_none() {
+            return errinput!("role {} does not exist", role.name);
+        }
+        self.txn.set(&Key::Role((&role.name).into()).encode(), role.encode_versioned())
+    }
+
+    fn drop_role(&self, role: &str) -> Result<()> {
+        if self.get_role(role)?.is_none() {
+            return errinput!("role {role} does not exist");
+        }
+        self.txn.delete(&Key::Role(role.into()).encode())
+    }
+
+    fn get_role(&self, name: &str) -> Result<Option<Role>> {
+        self.txn
+            .get(&Key::Role(name.into()).encode())?
+            .map(|v| Role::decode_versioned(&v))
+            .transpose()
+    }
+
+    fn list_roles(&self) -> Result<Vec<Role>> {
+        self.txn
+            .scan_prefix(&KeyPrefix::Role.encode())
+            .map(|r| r.and_then(|(_, v)| Role::decode_versioned(&v)))
+            .collect()
+    }
+
+    fn create_changefeed(&self, changefeed: Changefeed) -> Result<()> {
+        if self.get_changefeed(&changefeed.name)?.is_some() {
+            return errinput!("changefeed {} already exists", changefeed.name);
+        }
+        changefeed.validate(self)?;
+        let key = self.key(Key::Changefeed((&changefeed.name).into()));
+        self.txn.set(&key, changefeed.encode_versioned())
+    }
+
+    fn drop_changefeed(&self, name: &str) -> Result<()> {
+        if self.get_changefeed(name)?.is_none() {
+            return errinput!("changefeed {name} does not exist");
+        }
+        self.txn.delete(&self.key(Key::Changefeed(name.into())))
+    }
+
+    fn get_changefeed(&self, name: &str) -> Result<Option<Changefeed>> {
+        self.txn
+            .get(&self.key(Key::Changefeed(name.into())))?
+            .map(|v| Changefeed::decode_versioned(&v))
+            .transpose()
+    }
+
+    fn list_changefeeds(&self) -> Result<Vec<Changefeed>> {
+        self.txn
+            .scan_prefix(&self.prefix(KeyPrefix::Changefeed))
+            .map(|r| r.and_then(|(_, v)| Changefeed::decode_versioned(&v)))
+            .collect()
+    }
+
+    fn create_trigger(&self, trigger: Trigger) -> Result<()> {
+        let key = self.key(Key::Trigger((&trigger.table).into(), (&trigger.name).into()));
+        if self.txn.get(&key)?.is_some() {
+            return errinput!("trigger {} already exists on table {}", trigger.name, trigger.table);
+        }
+        trigger.validate(self)?;
+        self.txn.set(&key, trigger.encode_versioned())
+    }
+
+    fn drop_trigger(&self, table: &str, name: &str) -> Result<()> {
+        let key = self.key(Key::Trigger(table.into(), name.into()));
+        if self.txn.get(&key)?.is_none() {
+            return errinput!("trigger {name} does not exist on table {table}");
+        }
+        self.txn.delete(&key)
+    }
+
+    fn list_triggers(&self, table: &str) -> Result<Vec<Trigger>> {
+        self.txn
+            .scan_prefix(&self.prefix(KeyPrefix::Trigger(table.into())))
+            .map(|r| r.and_then(|(_, v)| Trigger::decode_versioned(&v)))
+            .collect()
+    }
+
+    fn create_function(&self, function: Function) -> Result<()> {
+        if self.get_function(&function.name)?.is_some() {
+            return errinput!("function {} already exists", function.name);
+        }
+        self.check_database()?;
+        function.validate()?;
+        let key = self.key(Key::Function((&function.name).into()));
+        self.txn.set(&key, function.encode_versioned())
+    }
+
+    fn drop_function(&self, name: &str) -> Result<()> {
+        if self.get_function(name)?.is_none() {
+            return errinput!("function {name} does not exist");
+        }
+        self.txn.delete(&self.key(Key::Function(name.into())))
+    }
+
+    fn get_function(&self, name: &str) -> Result<Option<Function>> {
+        self.txn
+            .get(&self.key(Key::Function(name.into())))?
+            .map(|v| Function::decode_versioned(&v))
+            .transpose()
+    }
+
+    fn list_functions(&self) -> Result<Vec<Function>> {
+        self.txn
+            .scan_prefix(&self.prefix(KeyPrefix::Function))
+            .map(|r| r.and_then(|(_, v)| Function::decode_versioned(&v)))
             .collect()
     }
 }
@@ -396,6 +1152,25 @@ pub enum Key<'a> {
     Index(Cow<'a, str>, Cow<'a, str>, Cow<'a, Value>),
     /// A table row, by table name and primary key value.
     Row(Cow<'a, str>, Cow<'a, Value>),
+    /// A user, by user name.
+    User(Cow<'a, str>),
+    /// A role, by role name.
+    Role(Cow<'a, str>),
+    /// A changefeed, by changefeed name.
+    Changefeed(Cow<'a, str>),
+    /// A trigger, by table name and trigger name.
+    Trigger(Cow<'a, str>, Cow<'a, str>),
+    /// A function, by function name.
+    Function(Cow<'a, str>),
+    /// A full-text index entry, by table name, column name, and term.
+    FullText(Cow<'a, str>, Cow<'a, str>, Cow<'a, str>),
+    /// A database, by database name.
+    Database(Cow<'a, str>),
+    /// A key in a database other than the default database, by database name.
+    /// Tables, changefeeds, triggers, and functions are scoped to a database.
+    InDatabase(Cow<'a, str>, Box<Key<'a>>),
+    /// A secondary index that is being built, by table name and column name.
+    IndexBuild(Cow<'a, str>, Cow<'a, str>),
 }
 
 impl<'a> encoding::Key<'a> for Key<'a> {}
@@ -410,6 +1185,25 @@ enum KeyPrefix<'a> {
     Index(Cow<'a, str>, Cow<'a, str>),
     /// An entire table's rows, by table name.
     Row(Cow<'a, str>),
+    /// All users.
+    User,
+    /// All roles.
+    Role,
+    /// All changefeeds.
+    Changefeed,
+    /// All of a table's triggers, by table name.
+    Trigger(Cow<'a, str>),
+    /// All functions.
+    Function,
+    /// An entire full-text index, by table and column name.
+    FullText(Cow<'a, str>, Cow<'a, str>),
+    /// All databases.
+    Database,
+    /// An entire database, by database name. Followed by a key or key prefix
+    /// in the database.
+    InDatabase(Cow<'a, str>),
+    /// All of a table's secondary indexes that are being built, by table name.
+    IndexBuild(Cow<'a, str>),
 }
 
 impl<'a> encoding::Key<'a> for KeyPrefix<'a> {}
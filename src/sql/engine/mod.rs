@@ -1,14 +1,23 @@
 //! The SQL engine provides SQL data storage and access, as well as session and
 //! transaction management. The [`Local`] engine provides node-local on-disk
 //! storage, while the [`Raft`] engine submits commands through Raft consensus
-//! before dispatching to the [`Local`] engine on each node.
+//! before dispatching to the [`Local`] engine on each node. The [`Local`] engine
+//! serves read-only [`InformationSchema`] tables from the catalog, and the
+//! [`Raft`] engine also serves read-only [`SystemTable`]s with the cluster
+//! status. Sessions can share a [`QueryCache`] of query results.
 
+mod cache;
+mod dump;
 mod engine;
 mod local;
 mod raft;
 mod session;
+mod system;
 
-pub use engine::{Catalog, Engine, Transaction};
+pub use cache::{QueryCache, QueryCacheStatus};
+pub use dump::dump;
+pub use engine::{Catalog, Change, Changes, Engine, Transaction};
 pub use local::{Key, Local};
 pub use raft::{Raft, Status, Write};
 pub use session::{Session, StatementResult};
+pub use system::{InformationSchema, SystemTable};
@@ -1,15 +1,18 @@
-use super::{Catalog, Engine as _, Transaction as _};
-use crate::encoding::{self, bincode, Value as _};
-use crate::errdata;
+use super::{Catalog, Changes, Engine as _, InformationSchema, SystemTable, Transaction as _};
+use crate::encoding::{self, bincode, Key as _, Value as _, Versioned as _};
 use crate::error::Result;
 use crate::raft;
-use crate::sql::types::{Expression, Row, Rows, Table, Value};
+use crate::sql::types::{
+    Changefeed, Database, Expression, Function, Role, Row, Rows, Statistics, Table, Trigger, User,
+    Value, ValueRange,
+};
 use crate::storage::{self, mvcc};
+use crate::{errdata, errinput};
 
-use crossbeam::channel::Sender;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::borrow::Cow;
 use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Bound;
 
 /// A Raft-based SQL engine. This dispatches to the `Local` engine for local
 /// storage and processing on each node, but plumbs read/write commands through
@@ -23,9 +26,17 @@ use std::collections::{BTreeMap, BTreeSet};
 ///
 /// For more details on how SQL statements flow through the engine, see the
 /// `sql` module documentation.
+///
+/// If replica reads are enabled, read-only transactions are executed against
+/// the local node's state machine via `raft::Request::ReadStale` when the node
+/// is a sufficiently up-to-date follower, instead of going to the leader. Such
+/// transactions may not see the latest writes, but their snapshot is still
+/// consistent. Read-write transactions always use the leader.
 pub struct Raft {
     /// Sends requests to the local Raft node, along with a response channel.
-    tx: Sender<(raft::Request, Sender<Result<raft::Response>>)>,
+    tx: raft::RequestSender,
+    /// If true, read-only transactions may read from the local follower.
+    replica_reads: bool,
 }
 
 impl Raft {
@@ -35,8 +46,14 @@ impl Raft {
 
     /// Creates a new Raft-based SQL engine, given a Raft request channel to the
     /// local Raft node.
-    pub fn new(tx: Sender<(raft::Request, Sender<Result<raft::Response>>)>) -> Self {
-        Self { tx }
+    pub fn new(tx: raft::RequestSender) -> Self {
+        Self { tx, replica_reads: false }
+    }
+
+    /// Enables or disables replica reads for read-only transactions. Disabled
+    /// by default.
+    pub fn enable_replica_reads(&mut self, enable: bool) {
+        self.replica_reads = enable
     }
 
     /// Creates the Raft-managed state machine for the Raft engine. Receives
@@ -46,10 +63,13 @@ impl Raft {
     }
 
     /// Executes a request against the Raft cluster, waiting for the response.
+    /// The request is traced in a span, which is passed along to the Raft node
+    /// such that its processing is traced as part of the request.
     fn execute(&self, request: raft::Request) -> Result<raft::Response> {
+        let span = tracing::info_span!("raft_request", request = request.kind());
         let (response_tx, response_rx) = crossbeam::channel::bounded(1);
-        self.tx.send((request, response_tx))?;
-        response_rx.recv()?
+        self.tx.send((request, response_tx, span.clone()))?;
+        span.in_scope(|| response_rx.recv())?
     }
 
     /// Writes through Raft, deserializing the response into the return type.
@@ -68,6 +88,20 @@ impl Raft {
         }
     }
 
+    /// Reads from the local follower with bounded staleness, falling back to
+    /// the leader if the follower is lagging. See `raft::Request::ReadStale`.
+    fn read_stale<V: DeserializeOwned>(
+        &self,
+        read: Read,
+        max_staleness: Option<raft::Ticks>,
+    ) -> Result<V> {
+        let request = raft::Request::ReadStale { command: read.encode(), max_staleness };
+        match self.execute(request)? {
+            raft::Response::Read(response) => bincode::deserialize(&response),
+            response => errdata!("unexpected Raft read response {response:?}"),
+        }
+    }
+
     /// Raft SQL engine status.
     pub fn status(&self) -> Result<Status> {
         let raft = match self.execute(raft::Request::Status)? {
@@ -77,21 +111,111 @@ impl Raft {
         let mvcc = self.read(Read::Status)?;
         Ok(Status { raft, mvcc })
     }
+
+    /// Verifies that the Raft logs of all nodes are consistent.
+    pub fn verify(&self) -> Result<raft::Report> {
+        match self.execute(raft::Request::Verify)? {
+            raft::Response::Verify(report) => Ok(report),
+            response => errdata!("unexpected Raft verify response {response:?}"),
+        }
+    }
+
+    /// Verifies that the SQL state machines of all nodes are consistent, by
+    /// comparing per-table checksums at the leader's applied index.
+    pub fn verify_state(&self) -> Result<raft::StateReport> {
+        match self.execute(raft::Request::VerifyState)? {
+            raft::Response::VerifyState(report) => Ok(report),
+            response => errdata!("unexpected Raft state verify response {response:?}"),
+        }
+    }
+
+    /// Returns the status of each node in the cluster, or None if unreachable.
+    pub fn nodes(&self) -> Result<BTreeMap<raft::NodeID, Option<raft::NodeStatus>>> {
+        match self.execute(raft::Request::Nodes)? {
+            raft::Response::Nodes(nodes) => Ok(nodes),
+            response => errdata!("unexpected Raft nodes response {response:?}"),
+        }
+    }
+
+    /// Returns the local Raft node's event trace.
+    pub fn trace(&self) -> Result<Vec<raft::TraceEvent>> {
+        match self.execute(raft::Request::Trace)? {
+            raft::Response::Trace(events) => Ok(events),
+            response => errdata!("unexpected Raft trace response {response:?}"),
+        }
+    }
+
+    /// Returns the local Raft node's metrics.
+    pub fn metrics(&self) -> Result<raft::Metrics> {
+        match self.execute(raft::Request::Metrics)? {
+            raft::Response::Metrics(metrics) => Ok(metrics),
+            response => errdata!("unexpected Raft metrics response {response:?}"),
+        }
+    }
+
+    /// Checks the local Raft node's health, returning its status.
+    pub fn health(&self) -> Result<raft::NodeStatus> {
+        match self.execute(raft::Request::Health)? {
+            raft::Response::Health(status) => Ok(status),
+            response => errdata!("unexpected Raft health response {response:?}"),
+        }
+    }
+
+    /// Compacts the local Raft node's log and SQL storage if their garbage
+    /// exceeds the given thresholds, returning whether each was compacted.
+    pub fn compact(
+        &self,
+        garbage_min_fraction: f64,
+        garbage_min_bytes: u64,
+    ) -> Result<(bool, bool)> {
+        let request = raft::Request::Compact { garbage_min_fraction, garbage_min_bytes };
+        match self.execute(request)? {
+            raft::Response::Compact { log, state } => Ok((log, state)),
+            response => errdata!("unexpected Raft compact response {response:?}"),
+        }
+    }
+
+    /// Backs up the local Raft node's log and SQL storage to the given
+    /// directory on the node, returning the applied index of the backup.
+    pub fn backup(&self, path: &str) -> Result<raft::Index> {
+        match self.execute(raft::Request::Backup { path: path.to_string() })? {
+            raft::Response::Backup { applied_index } => Ok(applied_index),
+            response => errdata!("unexpected Raft backup response {response:?}"),
+        }
+    }
+
+    /// Transfers leadership away from the local Raft node, if it's the leader,
+    /// to the given peer or the most up-to-date one. Returns the new leader, or
+    /// None if the local node wasn't the leader.
+    pub fn transfer_leadership(&self, to: Option<raft::NodeID>) -> Result<Option<raft::NodeID>> {
+        match self.execute(raft::Request::TransferLeadership(to))? {
+            raft::Response::TransferLeadership(leader) => Ok(leader),
+            response => errdata!("unexpected Raft transfer response {response:?}"),
+        }
+    }
 }
 
 impl<'a> super::Engine<'a> for Raft {
     type Transaction = Transaction<'a>;
 
     fn begin(&'a self) -> Result<Self::Transaction> {
-        Transaction::begin(self, false, None)
+        Transaction::begin(self, false, false, None, None)
+    }
+
+    fn begin_serializable(&'a self) -> Result<Self::Transaction> {
+        Transaction::begin(self, false, true, None, None)
     }
 
     fn begin_read_only(&'a self) -> Result<Self::Transaction> {
-        Transaction::begin(self, true, None)
+        Transaction::begin(self, true, false, None, None)
+    }
+
+    fn begin_read_only_stale(&'a self, max_staleness: raft::Ticks) -> Result<Self::Transaction> {
+        Transaction::begin(self, true, false, None, Some(max_staleness))
     }
 
     fn begin_as_of(&'a self, version: mvcc::Version) -> Result<Self::Transaction> {
-        Transaction::begin(self, true, Some(version))
+        Transaction::begin(self, true, false, Some(version), None)
     }
 }
 
@@ -108,21 +232,84 @@ pub struct Transaction<'a> {
     engine: &'a Raft,
     /// The MVCC transaction state.
     state: mvcc::TransactionState,
+    /// If true, the transaction reads from the local follower replica.
+    replica: bool,
+    /// The maximum replica staleness in ticks, if tighter than the node's.
+    max_staleness: Option<raft::Ticks>,
+    /// The current database. Commands are wrapped in InDatabase for databases
+    /// other than the default database.
+    database: String,
 }
 
 impl<'a> Transaction<'a> {
-    /// Starts a transaction in the given mode.
-    fn begin(engine: &'a Raft, read_only: bool, as_of: Option<mvcc::Version>) -> Result<Self> {
+    /// Starts a transaction in the given mode. Replica reads use the given
+    /// maximum staleness, if any. Serializable only applies to read-write
+    /// transactions.
+    fn begin(
+        engine: &'a Raft,
+        read_only: bool,
+        serializable: bool,
+        as_of: Option<mvcc::Version>,
+        max_staleness: Option<raft::Ticks>,
+    ) -> Result<Self> {
         assert!(as_of.is_none() || read_only, "can't use as_of without read_only");
+        // Time travel queries use the leader, since a lagging follower may not
+        // have the requested version yet.
+        let replica = engine.replica_reads && read_only && as_of.is_none();
         // Read-only transactions don't need to persist anything, they just need
         // to grab the current transaction state, so submit them as reads to
         // avoid a replication roundtrip.
-        let state = if read_only || as_of.is_some() {
+        let state = if replica {
+            engine.read_stale(Read::BeginReadOnly { as_of }, max_staleness)?
+        } else if read_only || as_of.is_some() {
             engine.read(Read::BeginReadOnly { as_of })?
+        } else if serializable {
+            engine.write(Write::BeginSerializable)?
         } else {
             engine.write(Write::Begin)?
         };
-        Ok(Self { engine, state })
+        let database = Database::DEFAULT.to_string();
+        Ok(Self { engine, state, replica, max_staleness, database })
+    }
+
+    /// Errors if the given table is a system table, which are read-only. The
+    /// Local engine also checks information_schema tables, but checking them
+    /// here avoids a Raft roundtrip.
+    fn check_writable(table: &str) -> Result<()> {
+        if SystemTable::lookup(table).is_some() || InformationSchema::lookup(table).is_some() {
+            return errinput!("system table {table} is read-only");
+        }
+        Ok(())
+    }
+
+    /// Reads from Raft, using the local follower for replica transactions. The
+    /// follower's snapshot is consistent, since its state machine is a prefix
+    /// of the leader's, and the transaction may also safely fall back to the
+    /// leader.
+    fn read<V: DeserializeOwned>(&self, read: Read) -> Result<V> {
+        let read = match self.database.as_str() {
+            Database::DEFAULT => read,
+            database => Read::InDatabase { database: database.into(), read: Box::new(read) },
+        };
+        // Serializable read-write transactions record their reads in the MVCC
+        // engine for conflict detection, so their reads must be applied via
+        // the Raft log like writes, to record them on all replicas.
+        if self.state.serializable && !self.state.read_only {
+            return self.engine.write(Write::Read(read));
+        }
+        match self.replica {
+            true => self.engine.read_stale(read, self.max_staleness),
+            false => self.engine.read(read),
+        }
+    }
+
+    /// Writes through Raft in the current database.
+    fn write<V: DeserializeOwned>(&self, write: Write) -> Result<V> {
+        let write = match self.database.as_str() {
+            Database::DEFAULT => write,
+            database => Write::InDatabase { database: database.into(), write: Box::new(write) },
+        };
+        self.engine.write(write)
     }
 }
 
@@ -145,8 +332,21 @@ impl super::Transaction for Transaction<'_> {
         self.engine.write(Write::Rollback(self.state.into()))
     }
 
+    fn savepoint(&self, name: &str) -> Result<()> {
+        self.write(Write::Savepoint { txn: (&self.state).into(), name: name.into() })
+    }
+
+    fn rollback_to_savepoint(&self, name: &str) -> Result<()> {
+        self.write(Write::RollbackToSavepoint { txn: (&self.state).into(), name: name.into() })
+    }
+
+    fn release_savepoint(&self, name: &str) -> Result<()> {
+        self.write(Write::ReleaseSavepoint { txn: (&self.state).into(), name: name.into() })
+    }
+
     fn delete(&self, table: &str, ids: &[Value]) -> Result<()> {
-        self.engine.write(Write::Delete {
+        Self::check_writable(table)?;
+        self.write(Write::Delete {
             txn: (&self.state).into(),
             table: table.into(),
             ids: ids.into(),
@@ -154,19 +354,19 @@ impl super::Transaction for Transaction<'_> {
     }
 
     fn get(&self, table: &str, ids: &[Value]) -> Result<Vec<Row>> {
-        self.engine.read(Read::Get {
-            txn: (&self.state).into(),
-            table: table.into(),
-            ids: ids.into(),
-        })
+        if let Some(system) = SystemTable::lookup(table) {
+            return system.get(self.engine, ids);
+        }
+        self.read(Read::Get { txn: (&self.state).into(), table: table.into(), ids: ids.into() })
     }
 
     fn insert(&self, table: &str, rows: Vec<Row>) -> Result<()> {
-        self.engine.write(Write::Insert { txn: (&self.state).into(), table: table.into(), rows })
+        Self::check_writable(table)?;
+        self.write(Write::Insert { txn: (&self.state).into(), table: table.into(), rows })
     }
 
     fn lookup_index(&self, table: &str, column: &str, values: &[Value]) -> Result<BTreeSet<Value>> {
-        self.engine.read(Read::LookupIndex {
+        self.read(Read::LookupIndex {
             txn: (&self.state).into(),
             table: table.into(),
             column: column.into(),
@@ -174,39 +374,244 @@ impl super::Transaction for Transaction<'_> {
         })
     }
 
-    fn scan(&self, table: &str, filter: Option<Expression>) -> Result<Rows> {
-        let scan: Vec<Row> = self.engine.read(Read::Scan {
+    fn lookup_index_range(
+        &self,
+        table: &str,
+        column: &str,
+        range: ValueRange,
+    ) -> Result<Vec<Value>> {
+        self.read(Read::LookupIndexRange {
+            txn: (&self.state).into(),
+            table: table.into(),
+            column: column.into(),
+            range,
+        })
+    }
+
+    fn lookup_fulltext(&self, table: &str, column: &str, query: &str) -> Result<BTreeSet<Value>> {
+        self.read(Read::LookupFullText {
             txn: (&self.state).into(),
             table: table.into(),
-            filter,
-        })?;
+            column: column.into(),
+            query: query.into(),
+        })
+    }
+
+    fn scan(&self, table: &str, filter: Option<Expression>) -> Result<Rows> {
+        if let Some(system) = SystemTable::lookup(table) {
+            return Ok(Box::new(system.scan(self.engine, filter)?.into_iter().map(Ok)));
+        }
+        let scan: Vec<Row> =
+            self.read(Read::Scan { txn: (&self.state).into(), table: table.into(), filter })?;
+        Ok(Box::new(scan.into_iter().map(Ok)))
+    }
+
+    fn count(&self, table: &str) -> Result<u64> {
+        if let Some(system) = SystemTable::lookup(table) {
+            return Ok(system.scan(self.engine, None)?.len() as u64);
+        }
+        self.read(Read::Count { txn: (&self.state).into(), table: table.into() })
+    }
+
+    fn scan_range(&self, table: &str, range: ValueRange) -> Result<Rows> {
+        let scan: Vec<Row> =
+            self.read(Read::ScanRange { txn: (&self.state).into(), table: table.into(), range })?;
         Ok(Box::new(scan.into_iter().map(Ok)))
     }
 
     fn update(&self, table: &str, rows: BTreeMap<Value, Row>) -> Result<()> {
-        self.engine.write(Write::Update { txn: (&self.state).into(), table: table.into(), rows })
+        Self::check_writable(table)?;
+        self.write(Write::Update { txn: (&self.state).into(), table: table.into(), rows })
+    }
+
+    fn backfill_index(
+        &self,
+        table: &str,
+        column: &str,
+        entries: Vec<(Value, Option<Value>, Option<Value>)>,
+    ) -> Result<()> {
+        Self::check_writable(table)?;
+        self.write(Write::BackfillIndex {
+            txn: (&self.state).into(),
+            table: table.into(),
+            column: column.into(),
+            entries,
+        })
+    }
+
+    fn table_versions(&self, tables: &[String]) -> Result<Vec<mvcc::Version>> {
+        self.read(Read::TableVersions { txn: (&self.state).into(), tables: tables.into() })
+    }
+
+    fn changes(&self, tables: &[String], from: mvcc::Version) -> Result<Changes> {
+        if let Some(table) = tables.iter().find(|t| SystemTable::lookup(t).is_some()) {
+            return errinput!("can't emit changes for system table {table}");
+        }
+        self.read(Read::Changes { txn: (&self.state).into(), tables: tables.into(), from })
     }
 }
 
 impl Catalog for Transaction<'_> {
-    fn create_table(&self, schema: Table) -> Result<()> {
-        self.engine.write(Write::CreateTable { txn: (&self.state).into(), schema })
+    fn database(&self) -> &str {
+        &self.database
     }
 
-    fn drop_table(&self, table: &str, if_exists: bool) -> Result<bool> {
-        self.engine.write(Write::DropTable {
+    fn use_database(&mut self, database: &str) {
+        self.database = database.to_string();
+    }
+
+    fn create_database(&self, database: Database) -> Result<()> {
+        self.write(Write::CreateDatabase { txn: (&self.state).into(), database })
+    }
+
+    fn drop_database(&self, database: &str, if_exists: bool) -> Result<bool> {
+        self.write(Write::DropDatabase {
             txn: (&self.state).into(),
-            table: table.into(),
+            database: database.into(),
             if_exists,
         })
     }
 
+    fn get_database(&self, database: &str) -> Result<Option<Database>> {
+        self.read(Read::GetDatabase { txn: (&self.state).into(), database: database.into() })
+    }
+
+    fn list_databases(&self) -> Result<Vec<Database>> {
+        self.read(Read::ListDatabases { txn: (&self.state).into() })
+    }
+
+    fn create_table(&self, schema: Table) -> Result<()> {
+        if SystemTable::lookup(&schema.name).is_some() {
+            return errinput!("table {} already exists", schema.name);
+        }
+        self.write(Write::CreateTable { txn: (&self.state).into(), schema })
+    }
+
+    fn drop_table(&self, table: &str, if_exists: bool) -> Result<bool> {
+        Self::check_writable(table)?;
+        self.write(Write::DropTable { txn: (&self.state).into(), table: table.into(), if_exists })
+    }
+
     fn get_table(&self, table: &str) -> Result<Option<Table>> {
-        self.engine.read(Read::GetTable { txn: (&self.state).into(), table: table.into() })
+        if let Some(system) = SystemTable::lookup(table) {
+            return Ok(Some(system.schema()));
+        }
+        self.read(Read::GetTable { txn: (&self.state).into(), table: table.into() })
     }
 
     fn list_tables(&self) -> Result<Vec<Table>> {
-        self.engine.read(Read::ListTables { txn: (&self.state).into() })
+        self.read(Read::ListTables { txn: (&self.state).into() })
+    }
+
+    fn create_index(&self, table: &str, column: &str) -> Result<()> {
+        Self::check_writable(table)?;
+        self.write(Write::CreateIndex {
+            txn: (&self.state).into(),
+            table: table.into(),
+            column: column.into(),
+        })
+    }
+
+    fn publish_index(&self, table: &str, column: &str) -> Result<()> {
+        Self::check_writable(table)?;
+        self.write(Write::PublishIndex {
+            txn: (&self.state).into(),
+            table: table.into(),
+            column: column.into(),
+        })
+    }
+
+    fn set_statistics(&self, table: &str, statistics: Statistics) -> Result<()> {
+        Self::check_writable(table)?;
+        self.write(Write::SetStatistics {
+            txn: (&self.state).into(),
+            table: table.into(),
+            statistics,
+        })
+    }
+
+    fn create_user(&self, user: User) -> Result<()> {
+        self.write(Write::CreateUser { txn: (&self.state).into(), user })
+    }
+
+    fn alter_user(&self, user: User) -> Result<()> {
+        self.write(Write::AlterUser { txn: (&self.state).into(), user })
+    }
+
+    fn get_user(&self, name: &str) -> Result<Option<User>> {
+        self.read(Read::GetUser { txn: (&self.state).into(), name: name.into() })
+    }
+
+    fn list_users(&self) -> Result<Vec<User>> {
+        self.read(Read::ListUsers { txn: (&self.state).into() })
+    }
+
+    fn create_role(&self, role: Role) -> Result<()> {
+        self.write(Write::CreateRole { txn: (&self.state).into(), role })
+    }
+
+    fn alter_role(&self, role: Role) -> Result<()> {
+        self.write(Write::AlterRole { txn: (&self.state).into(), role })
+    }
+
+    fn drop_role(&self, role: &str) -> Result<()> {
+        self.write(Write::DropRole { txn: (&self.state).into(), role: role.into() })
+    }
+
+    fn get_role(&self, name: &str) -> Result<Option<Role>> {
+        self.read(Read::GetRole { txn: (&self.state).into(), name: name.into() })
+    }
+
+    fn list_roles(&self) -> Result<Vec<Role>> {
+        self.read(Read::ListRoles { txn: (&self.state).into() })
+    }
+
+    fn create_changefeed(&self, changefeed: Changefeed) -> Result<()> {
+        self.write(Write::CreateChangefeed { txn: (&self.state).into(), changefeed })
+    }
+
+    fn drop_changefeed(&self, name: &str) -> Result<()> {
+        self.write(Write::DropChangefeed { txn: (&self.state).into(), name: name.into() })
+    }
+
+    fn get_changefeed(&self, name: &str) -> Result<Option<Changefeed>> {
+        self.read(Read::GetChangefeed { txn: (&self.state).into(), name: name.into() })
+    }
+
+    fn list_changefeeds(&self) -> Result<Vec<Changefeed>> {
+        self.read(Read::ListChangefeeds { txn: (&self.state).into() })
+    }
+
+    fn create_trigger(&self, trigger: Trigger) -> Result<()> {
+        self.write(Write::CreateTrigger { txn: (&self.state).into(), trigger })
+    }
+
+    fn drop_trigger(&self, table: &str, name: &str) -> Result<()> {
+        self.write(Write::DropTrigger {
+            txn: (&self.state).into(),
+            table: table.into(),
+            name: name.into(),
+        })
+    }
+
+    fn list_triggers(&self, table: &str) -> Result<Vec<Trigger>> {
+        self.read(Read::ListTriggers { txn: (&self.state).into(), table: table.into() })
+    }
+
+    fn create_function(&self, function: Function) -> Result<()> {
+        self.write(Write::CreateFunction { txn: (&self.state).into(), function })
+    }
+
+    fn drop_function(&self, name: &str) -> Result<()> {
+        self.write(Write::DropFunction { txn: (&self.state).into(), name: name.into() })
+    }
+
+    fn get_function(&self, name: &str) -> Result<Option<Function>> {
+        self.read(Read::GetFunction { txn: (&self.state).into(), name: name.into() })
+    }
+
+    fn list_functions(&self) -> Result<Vec<Function>> {
+        self.read(Read::ListFunctions { txn: (&self.state).into() })
     }
 }
 
@@ -231,41 +636,202 @@ impl<E: storage::Engine> State<E> {
     /// local storage.
     pub fn new(engine: E) -> Result<Self> {
         let local = super::Local::new(engine);
-        let applied_index = local
+        let applied_index = Self::load_applied_index(&local)?;
+        Ok(State { local, applied_index })
+    }
+
+    /// Loads the applied index from local storage, or 0 if none.
+    fn load_applied_index(local: &super::Local<E>) -> Result<raft::Index> {
+        Ok(local
             .get_unversioned(Raft::APPLIED_INDEX_KEY)?
-            .map(|b| bincode::deserialize(&b))
+            .map(|b| raft::Index::decode_versioned(&b))
             .transpose()?
-            .unwrap_or(0);
-        Ok(State { local, applied_index })
+            .unwrap_or(0))
+    }
+
+    /// Resumes a local transaction in the given database.
+    fn resume(
+        &self,
+        txn: Cow<'_, mvcc::TransactionState>,
+        database: &str,
+    ) -> Result<super::local::Transaction<E>> {
+        let mut txn = self.local.resume(txn.into_owned())?;
+        txn.use_database(database);
+        Ok(txn)
     }
 
-    /// Executes a write command.
-    fn write(&self, command: Write) -> Result<Vec<u8>> {
+    /// Executes a write command in the given database.
+    fn write_in(&self, command: Write, database: &str) -> Result<Vec<u8>> {
         Ok(match command {
             Write::Begin => self.local.begin()?.state().encode(),
-            Write::Commit(txn) => {
-                bincode::serialize(&self.local.resume(txn.into_owned())?.commit()?)
+            Write::BeginSerializable => self.local.begin_serializable()?.state().encode(),
+            Write::Read(read) => self.read_in(read, database)?,
+            Write::InDatabase { database, write } => self.write_in(*write, &database)?,
+
+            Write::CreateDatabase { txn, database: db } => {
+                bincode::serialize(&self.resume(txn, database)?.create_database(db)?)
+            }
+            Write::DropDatabase { txn, database: db, if_exists } => {
+                bincode::serialize(&self.resume(txn, database)?.drop_database(&db, if_exists)?)
             }
-            Write::Rollback(txn) => {
-                bincode::serialize(&self.local.resume(txn.into_owned())?.rollback()?)
+
+            Write::Commit(txn) => bincode::serialize(&self.resume(txn, database)?.commit()?),
+            Write::Rollback(txn) => bincode::serialize(&self.resume(txn, database)?.rollback()?),
+            Write::Savepoint { txn, name } => {
+                bincode::serialize(&self.resume(txn, database)?.savepoint(&name)?)
+            }
+            Write::RollbackToSavepoint { txn, name } => {
+                bincode::serialize(&self.resume(txn, database)?.rollback_to_savepoint(&name)?)
+            }
+            Write::ReleaseSavepoint { txn, name } => {
+                bincode::serialize(&self.resume(txn, database)?.release_savepoint(&name)?)
             }
 
             Write::Delete { txn, table, ids } => {
-                bincode::serialize(&self.local.resume(txn.into_owned())?.delete(&table, &ids)?)
+                bincode::serialize(&self.resume(txn, database)?.delete(&table, &ids)?)
             }
             Write::Insert { txn, table, rows } => {
-                bincode::serialize(&self.local.resume(txn.into_owned())?.insert(&table, rows)?)
+                bincode::serialize(&self.resume(txn, database)?.insert(&table, rows)?)
             }
             Write::Update { txn, table, rows } => {
-                bincode::serialize(&self.local.resume(txn.into_owned())?.update(&table, rows)?)
+                bincode::serialize(&self.resume(txn, database)?.update(&table, rows)?)
             }
 
             Write::CreateTable { txn, schema } => {
-                bincode::serialize(&self.local.resume(txn.into_owned())?.create_table(schema)?)
+                bincode::serialize(&self.resume(txn, database)?.create_table(schema)?)
             }
-            Write::DropTable { txn, table, if_exists } => bincode::serialize(
-                &self.local.resume(txn.into_owned())?.drop_table(&table, if_exists)?,
+            Write::DropTable { txn, table, if_exists } => {
+                bincode::serialize(&self.resume(txn, database)?.drop_table(&table, if_exists)?)
+            }
+            Write::CreateIndex { txn, table, column } => {
+                bincode::serialize(&self.resume(txn, database)?.create_index(&table, &column)?)
+            }
+            Write::BackfillIndex { txn, table, column, entries } => bincode::serialize(
+                &self.resume(txn, database)?.backfill_index(&table, &column, entries)?,
             ),
+            Write::PublishIndex { txn, table, column } => {
+                bincode::serialize(&self.resume(txn, database)?.publish_index(&table, &column)?)
+            }
+            Write::SetStatistics { txn, table, statistics } => {
+                bincode::serialize(&self.resume(txn, database)?.set_statistics(&table, statistics)?)
+            }
+
+            Write::CreateUser { txn, user } => {
+                bincode::serialize(&self.resume(txn, database)?.create_user(user)?)
+            }
+            Write::AlterUser { txn, user } => {
+                bincode::serialize(&self.resume(txn, database)?.alter_user(user)?)
+            }
+
+            Write::CreateRole { txn, role } => {
+                bincode::serialize(&self.resume(txn, database)?.create_role(role)?)
+            }
+            Write::AlterRole { txn, role } => {
+                bincode::serialize(&self.resume(txn, database)?.alter_role(role)?)
+            }
+            Write::DropRole { txn, role } => {
+                bincode::serialize(&self.resume(txn, database)?.drop_role(&role)?)
+            }
+
+            Write::CreateChangefeed { txn, changefeed } => {
+                bincode::serialize(&self.resume(txn, database)?.create_changefeed(changefeed)?)
+            }
+            Write::DropChangefeed { txn, name } => {
+                bincode::serialize(&self.resume(txn, database)?.drop_changefeed(&name)?)
+            }
+
+            Write::CreateTrigger { txn, trigger } => {
+                bincode::serialize(&self.resume(txn, database)?.create_trigger(trigger)?)
+            }
+            Write::DropTrigger { txn, table, name } => {
+                bincode::serialize(&self.resume(txn, database)?.drop_trigger(&table, &name)?)
+            }
+
+            Write::CreateFunction { txn, function } => {
+                bincode::serialize(&self.resume(txn, database)?.create_function(function)?)
+            }
+            Write::DropFunction { txn, name } => {
+                bincode::serialize(&self.resume(txn, database)?.drop_function(&name)?)
+            }
+        })
+    }
+
+    /// Executes a read command in the given database.
+    fn read_in(&self, command: Read, database: &str) -> Result<Vec<u8>> {
+        Ok(match command {
+            Read::BeginReadOnly { as_of } => {
+                let txn = match as_of {
+                    Some(version) => self.local.begin_as_of(version)?,
+                    None => self.local.begin_read_only()?,
+                };
+                txn.state().encode()
+            }
+            Read::Status => self.local.mvcc.status()?.encode(),
+            Read::InDatabase { database, read } => self.read_in(*read, &database)?,
+
+            Read::GetDatabase { txn, database: db } => {
+                self.resume(txn, database)?.get_database(&db)?.encode()
+            }
+            Read::ListDatabases { txn } => self.resume(txn, database)?.list_databases()?.encode(),
+
+            Read::Get { txn, table, ids } => {
+                self.resume(txn, database)?.get(&table, &ids)?.encode()
+            }
+            Read::LookupIndex { txn, table, column, values } => {
+                self.resume(txn, database)?.lookup_index(&table, &column, &values)?.encode()
+            }
+            Read::Scan { txn, table, filter } => {
+                // For simplicity, buffer the entire scan. See `State` comment.
+                self.resume(txn, database)?
+                    .scan(&table, filter)?
+                    .collect::<Result<Vec<Row>>>()?
+                    .encode()
+            }
+            Read::Count { txn, table } => self.resume(txn, database)?.count(&table)?.encode(),
+            Read::ScanRange { txn, table, range } => self
+                .resume(txn, database)?
+                .scan_range(&table, range)?
+                .collect::<Result<Vec<Row>>>()?
+                .encode(),
+            Read::LookupIndexRange { txn, table, column, range } => {
+                self.resume(txn, database)?.lookup_index_range(&table, &column, range)?.encode()
+            }
+
+            Read::GetTable { txn, table } => {
+                self.resume(txn, database)?.get_table(&table)?.encode()
+            }
+            Read::ListTables { txn } => self.resume(txn, database)?.list_tables()?.encode(),
+
+            Read::GetUser { txn, name } => self.resume(txn, database)?.get_user(&name)?.encode(),
+            Read::ListUsers { txn } => self.resume(txn, database)?.list_users()?.encode(),
+
+            Read::GetRole { txn, name } => self.resume(txn, database)?.get_role(&name)?.encode(),
+            Read::ListRoles { txn } => self.resume(txn, database)?.list_roles()?.encode(),
+
+            Read::TableVersions { txn, tables } => {
+                self.resume(txn, database)?.table_versions(&tables)?.encode()
+            }
+            Read::Changes { txn, tables, from } => {
+                self.resume(txn, database)?.changes(&tables, from)?.encode()
+            }
+            Read::GetChangefeed { txn, name } => {
+                self.resume(txn, database)?.get_changefeed(&name)?.encode()
+            }
+            Read::ListChangefeeds { txn } => {
+                self.resume(txn, database)?.list_changefeeds()?.encode()
+            }
+
+            Read::ListTriggers { txn, table } => {
+                self.resume(txn, database)?.list_triggers(&table)?.encode()
+            }
+
+            Read::GetFunction { txn, name } => {
+                self.resume(txn, database)?.get_function(&name)?.encode()
+            }
+            Read::ListFunctions { txn } => self.resume(txn, database)?.list_functions()?.encode(),
+            Read::LookupFullText { txn, table, column, query } => {
+                self.resume(txn, database)?.lookup_fulltext(&table, &column, &query)?.encode()
+            }
         })
     }
 }
@@ -279,7 +845,7 @@ impl<E: storage::Engine> raft::State for State<E> {
         assert_eq!(entry.index, self.applied_index + 1, "entry index not after applied index");
 
         let result = match &entry.command {
-            Some(command) => match self.write(Write::decode(command)?) {
+            Some(command) => match self.write_in(Write::decode(command)?, Database::DEFAULT) {
                 // Panic on non-deterministic apply failures, to prevent replica
                 // divergence. See [`raft::State`] docs for details.
                 Err(e) if !e.is_deterministic() => panic!("non-deterministic apply failure: {e}"),
@@ -294,45 +860,100 @@ impl<E: storage::Engine> raft::State for State<E> {
         // lose a tail of the state machine writes (e.g. if the machine
         // crashes). Raft will replay the log from the last known applied index.
         self.applied_index = entry.index;
-        self.local.set_unversioned(Raft::APPLIED_INDEX_KEY, bincode::serialize(&entry.index))?;
+        self.local.set_unversioned(Raft::APPLIED_INDEX_KEY, entry.index.encode_versioned())?;
         result
     }
 
     fn read(&self, command: Vec<u8>) -> Result<Vec<u8>> {
-        Ok(match Read::decode(&command)? {
-            Read::BeginReadOnly { as_of } => {
-                let txn = match as_of {
-                    Some(version) => self.local.begin_as_of(version)?,
-                    None => self.local.begin_read_only()?,
-                };
-                txn.state().encode()
-            }
-            Read::Status => self.local.mvcc.status()?.encode(),
+        self.read_in(Read::decode(&command)?, Database::DEFAULT)
+    }
 
-            Read::Get { txn, table, ids } => {
-                self.local.resume(txn.into_owned())?.get(&table, &ids)?.encode()
-            }
-            Read::LookupIndex { txn, table, column, values } => self
-                .local
-                .resume(txn.into_owned())?
-                .lookup_index(&table, &column, &values)?
-                .encode(),
-            Read::Scan { txn, table, filter } => {
-                // For simplicity, buffer the entire scan. See `State` comment.
-                self.local
-                    .resume(txn.into_owned())?
-                    .scan(&table, filter)?
-                    .collect::<Result<Vec<Row>>>()?
-                    .encode()
-            }
+    /// Checksums the raw MVCC storage, including all versions and transaction
+    /// metadata, partitioned by the SQL table a key belongs to (including its
+    /// schema, rows, secondary and full-text indexes, index builds, and
+    /// triggers). Users, roles, changefeeds, and functions are checksummed in
+    /// the partitions "users", "roles", "changefeeds", and "functions",
+    /// database entries in "databases", all keys in non-default databases in
+    /// "database:{name}", and other MVCC keys in "mvcc".
+    fn checksums(&self) -> Result<BTreeMap<String, u64>> {
+        let mut checksums: BTreeMap<String, raft::Checksum> = BTreeMap::new();
+        let engine = self.local.mvcc.engine.read()?;
+        let mut scan = engine.scan_shared((Bound::Unbounded, Bound::Unbounded));
+        while let Some((key, value)) = scan.next().transpose()? {
+            let partition = match mvcc::Key::decode(&key)? {
+                mvcc::Key::Version(key, _)
+                | mvcc::Key::TxnWrite(_, key)
+                | mvcc::Key::Unversioned(key) => match super::Key::decode(&key) {
+                    Ok(super::Key::Table(table))
+                    | Ok(super::Key::Row(table, _))
+                    | Ok(super::Key::Index(table, _, _))
+                    | Ok(super::Key::FullText(table, _, _))
+                    | Ok(super::Key::IndexBuild(table, _))
+                    | Ok(super::Key::Trigger(table, _)) => format!("table:{table}"),
+                    Ok(super::Key::User(_)) => "users".to_string(),
+                    Ok(super::Key::Role(_)) => "roles".to_string(),
+                    Ok(super::Key::Changefeed(_)) => "changefeeds".to_string(),
+                    Ok(super::Key::Function(_)) => "functions".to_string(),
+                    Ok(super::Key::Database(_)) => "databases".to_string(),
+                    Ok(super::Key::InDatabase(database, _)) => format!("database:{database}"),
+                    // Unversioned metadata, e.g. the applied index.
+                    Err(_) => "mvcc".to_string(),
+                },
+                _ => "mvcc".to_string(),
+            };
+            let checksum = checksums.entry(partition).or_default();
+            checksum.write(&(key.len() as u64).to_be_bytes());
+            checksum.write(&key);
+            checksum.write(&(value.len() as u64).to_be_bytes());
+            checksum.write(&value);
+        }
+        Ok(checksums
+            .into_iter()
+            .map(|(partition, checksum)| (partition, checksum.finish()))
+            .collect())
+    }
 
-            Read::GetTable { txn, table } => {
-                self.local.resume(txn.into_owned())?.get_table(&table)?.encode()
-            }
-            Read::ListTables { txn } => {
-                self.local.resume(txn.into_owned())?.list_tables()?.encode()
-            }
-        })
+    fn flush(&mut self) -> Result<()> {
+        self.local.mvcc.engine.write()?.flush()
+    }
+
+    fn maybe_compact(&mut self, garbage_min_fraction: f64, garbage_min_bytes: u64) -> Result<bool> {
+        self.local.mvcc.engine.write()?.maybe_compact(garbage_min_fraction, garbage_min_bytes)
+    }
+
+    fn backup(&mut self, dest: &std::path::Path) -> Result<()> {
+        self.local.mvcc.engine.write()?.backup(dest)
+    }
+
+    /// Snapshots the raw MVCC storage as key/value pairs, including all
+    /// versions, transaction metadata, and the applied index.
+    fn snapshot(&self) -> Result<Vec<u8>> {
+        let engine = self.local.mvcc.engine.read()?;
+        let data: Vec<(Vec<u8>, Vec<u8>)> =
+            engine.scan_shared((Bound::Unbounded, Bound::Unbounded)).collect::<Result<_>>()?;
+        Ok(bincode::serialize(&data))
+    }
+
+    /// Replaces the raw MVCC storage with the snapshot in a single write
+    /// batch. MVCC watchers aren't notified of the changes.
+    fn restore(&mut self, snapshot: Vec<u8>) -> Result<()> {
+        let data: Vec<(Vec<u8>, Vec<u8>)> = bincode::deserialize(&snapshot)?;
+        let mut engine = self.local.mvcc.engine.write()?;
+        let mut batch = storage::WriteBatch::new();
+        let mut scan = engine.scan_keys((Bound::Unbounded, Bound::Unbounded));
+        while let Some((key, _)) = scan.next().transpose()? {
+            batch.delete(&key);
+        }
+        drop(scan);
+        for (key, value) in data {
+            batch.set(&key, value);
+        }
+        engine.write_batch(batch)?;
+        engine.flush()?;
+        drop(engine);
+        self.local.reset_table_versions()?;
+        self.applied_index = Self::load_applied_index(&self.local)?;
+        Ok(())
     }
 }
 
@@ -369,6 +990,86 @@ pub enum Read<'a> {
     ListTables {
         txn: Cow<'a, mvcc::TransactionState>,
     },
+
+    GetUser {
+        txn: Cow<'a, mvcc::TransactionState>,
+        name: Cow<'a, str>,
+    },
+    ListUsers {
+        txn: Cow<'a, mvcc::TransactionState>,
+    },
+
+    GetRole {
+        txn: Cow<'a, mvcc::TransactionState>,
+        name: Cow<'a, str>,
+    },
+    ListRoles {
+        txn: Cow<'a, mvcc::TransactionState>,
+    },
+
+    Changes {
+        txn: Cow<'a, mvcc::TransactionState>,
+        tables: Cow<'a, [String]>,
+        from: mvcc::Version,
+    },
+    GetChangefeed {
+        txn: Cow<'a, mvcc::TransactionState>,
+        name: Cow<'a, str>,
+    },
+    ListChangefeeds {
+        txn: Cow<'a, mvcc::TransactionState>,
+    },
+
+    ListTriggers {
+        txn: Cow<'a, mvcc::TransactionState>,
+        table: Cow<'a, str>,
+    },
+
+    GetFunction {
+        txn: Cow<'a, mvcc::TransactionState>,
+        name: Cow<'a, str>,
+    },
+    ListFunctions {
+        txn: Cow<'a, mvcc::TransactionState>,
+    },
+    LookupFullText {
+        txn: Cow<'a, mvcc::TransactionState>,
+        table: Cow<'a, str>,
+        column: Cow<'a, str>,
+        query: Cow<'a, str>,
+    },
+
+    /// Executes a read in a database other than the default database.
+    InDatabase {
+        database: Cow<'a, str>,
+        read: Box<Read<'a>>,
+    },
+    GetDatabase {
+        txn: Cow<'a, mvcc::TransactionState>,
+        database: Cow<'a, str>,
+    },
+    ListDatabases {
+        txn: Cow<'a, mvcc::TransactionState>,
+    },
+    TableVersions {
+        txn: Cow<'a, mvcc::TransactionState>,
+        tables: Cow<'a, [String]>,
+    },
+    ScanRange {
+        txn: Cow<'a, mvcc::TransactionState>,
+        table: Cow<'a, str>,
+        range: ValueRange,
+    },
+    LookupIndexRange {
+        txn: Cow<'a, mvcc::TransactionState>,
+        table: Cow<'a, str>,
+        column: Cow<'a, str>,
+        range: ValueRange,
+    },
+    Count {
+        txn: Cow<'a, mvcc::TransactionState>,
+        table: Cow<'a, str>,
+    },
 }
 
 impl encoding::Value for Read<'_> {}
@@ -381,12 +1082,133 @@ pub enum Write<'a> {
     Commit(Cow<'a, mvcc::TransactionState>),
     Rollback(Cow<'a, mvcc::TransactionState>),
 
-    Delete { txn: Cow<'a, mvcc::TransactionState>, table: Cow<'a, str>, ids: Cow<'a, [Value]> },
-    Insert { txn: Cow<'a, mvcc::TransactionState>, table: Cow<'a, str>, rows: Vec<Row> },
-    Update { txn: Cow<'a, mvcc::TransactionState>, table: Cow<'a, str>, rows: BTreeMap<Value, Row> },
+    Delete {
+        txn: Cow<'a, mvcc::TransactionState>,
+        table: Cow<'a, str>,
+        ids: Cow<'a, [Value]>,
+    },
+    Insert {
+        txn: Cow<'a, mvcc::TransactionState>,
+        table: Cow<'a, str>,
+        rows: Vec<Row>,
+    },
+    Update {
+        txn: Cow<'a, mvcc::TransactionState>,
+        table: Cow<'a, str>,
+        rows: BTreeMap<Value, Row>,
+    },
 
-    CreateTable { txn: Cow<'a, mvcc::TransactionState>, schema: Table },
-    DropTable { txn: Cow<'a, mvcc::TransactionState>, table: Cow<'a, str>, if_exists: bool },
+    CreateTable {
+        txn: Cow<'a, mvcc::TransactionState>,
+        schema: Table,
+    },
+    DropTable {
+        txn: Cow<'a, mvcc::TransactionState>,
+        table: Cow<'a, str>,
+        if_exists: bool,
+    },
+
+    CreateUser {
+        txn: Cow<'a, mvcc::TransactionState>,
+        user: User,
+    },
+    AlterUser {
+        txn: Cow<'a, mvcc::TransactionState>,
+        user: User,
+    },
+
+    CreateRole {
+        txn: Cow<'a, mvcc::TransactionState>,
+        role: Role,
+    },
+    AlterRole {
+        txn: Cow<'a, mvcc::TransactionState>,
+        role: Role,
+    },
+    DropRole {
+        txn: Cow<'a, mvcc::TransactionState>,
+        role: Cow<'a, str>,
+    },
+
+    CreateChangefeed {
+        txn: Cow<'a, mvcc::TransactionState>,
+        changefeed: Changefeed,
+    },
+    DropChangefeed {
+        txn: Cow<'a, mvcc::TransactionState>,
+        name: Cow<'a, str>,
+    },
+
+    CreateTrigger {
+        txn: Cow<'a, mvcc::TransactionState>,
+        trigger: Trigger,
+    },
+    DropTrigger {
+        txn: Cow<'a, mvcc::TransactionState>,
+        table: Cow<'a, str>,
+        name: Cow<'a, str>,
+    },
+
+    CreateFunction {
+        txn: Cow<'a, mvcc::TransactionState>,
+        function: Function,
+    },
+    DropFunction {
+        txn: Cow<'a, mvcc::TransactionState>,
+        name: Cow<'a, str>,
+    },
+
+    /// Executes a write in a database other than the default database.
+    InDatabase {
+        database: Cow<'a, str>,
+        write: Box<Write<'a>>,
+    },
+    CreateDatabase {
+        txn: Cow<'a, mvcc::TransactionState>,
+        database: Database,
+    },
+    DropDatabase {
+        txn: Cow<'a, mvcc::TransactionState>,
+        database: Cow<'a, str>,
+        if_exists: bool,
+    },
+
+    CreateIndex {
+        txn: Cow<'a, mvcc::TransactionState>,
+        table: Cow<'a, str>,
+        column: Cow<'a, str>,
+    },
+    BackfillIndex {
+        txn: Cow<'a, mvcc::TransactionState>,
+        table: Cow<'a, str>,
+        column: Cow<'a, str>,
+        entries: Vec<(Value, Option<Value>, Option<Value>)>,
+    },
+    PublishIndex {
+        txn: Cow<'a, mvcc::TransactionState>,
+        table: Cow<'a, str>,
+        column: Cow<'a, str>,
+    },
+    SetStatistics {
+        txn: Cow<'a, mvcc::TransactionState>,
+        table: Cow<'a, str>,
+        statistics: Statistics,
+    },
+    Savepoint {
+        txn: Cow<'a, mvcc::TransactionState>,
+        name: Cow<'a, str>,
+    },
+    RollbackToSavepoint {
+        txn: Cow<'a, mvcc::TransactionState>,
+        name: Cow<'a, str>,
+    },
+    ReleaseSavepoint {
+        txn: Cow<'a, mvcc::TransactionState>,
+        name: Cow<'a, str>,
+    },
+    BeginSerializable,
+    /// A read in a serializable read-write transaction, see Transaction::read.
+    Read(Read<'a>),
 }
 
 impl encoding::Value for Write<'_> {}
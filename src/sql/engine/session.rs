@@ -1,49 +1,256 @@
 use super::raft::{Raft, Status};
-use super::{Engine, Transaction as _};
-use crate::error::{Error, Result};
-use crate::sql::execution::ExecutionResult;
-use crate::sql::parser::{ast, Parser};
-use crate::sql::planner::Plan;
-use crate::sql::types::{Label, Row, Rows, Value};
+use super::{
+    Catalog as _, Changes, Engine, InformationSchema, QueryCache, SystemTable, Transaction as _,
+};
+use crate::error::{Context, Error, Result};
+use crate::raft;
+use crate::sql::execution::{CancelFlag, ExecutionResult, MemoryBudget};
+use crate::sql::parser::{ast, redact, Parser};
+use crate::sql::planner::{Analysis, Plan};
+use crate::sql::types::{Database, Label, Privilege, Row, Rows, User, Value};
 use crate::storage::mvcc;
 use crate::{errdata, errinput};
 
 use itertools::Itertools as _;
-use log::error;
+use log::{debug, error};
+use rand::Rng as _;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::info_span;
+
+/// The number of rows backfilled per transaction when building an index.
+const INDEX_BUILD_BATCH_SIZE: usize = 1000;
+
+/// How often an index build polls for older transactions to complete.
+const INDEX_BUILD_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The maximum number of times an implicit read-write transaction is retried
+/// after a serialization failure, before returning the error to the client.
+const IMPLICIT_TXN_MAX_RETRIES: u32 = 5;
+
+/// The minimum and maximum backoff between implicit transaction retries.
+const IMPLICIT_TXN_MIN_BACKOFF: Duration = Duration::from_millis(10);
+const IMPLICIT_TXN_MAX_BACKOFF: Duration = Duration::from_secs(1);
 
 /// A SQL client session. Executes raw SQL statements against a SQL engine and
 /// handles transaction control.
+///
+/// Sessions also manage cursors, which allow clients to fetch large query
+/// results in batches (DECLARE, FETCH, and CLOSE). A cursor keeps the query's
+/// row iterator open, and rows are only pulled through the plan as they're
+/// fetched, so they aren't all buffered by the session at once. However,
+/// operators like sorts still buffer their input, and Raft scans are buffered
+/// as they're read from the Raft state machine. Cursors can only be declared
+/// in explicit transactions, and are closed when the transaction ends.
+///
+/// Statements run in the session's current database, which is initially the
+/// default database and can be changed with USE.
+///
+/// If the session has a query cache, the results of SELECT statements run
+/// outside of explicit transactions are cached, see QueryCache.
 pub struct Session<'a, E: Engine<'a>> {
     /// The SQL engine.
     engine: &'a E,
     /// The current transaction, if any.
     txn: Option<E::Transaction>,
+    /// The authenticated user, if any.
+    user: Option<String>,
+    /// The current database, set via USE.
+    database: String,
+    /// The cancellation flag for the currently executing statement.
+    cancel: CancelFlag,
+    /// The maximum execution time of a statement, if any. Set via SET
+    /// statement_timeout.
+    statement_timeout: Option<Duration>,
+    /// The memory budget of each statement in bytes, if any. See MemoryBudget.
+    memory_budget: Option<usize>,
+    /// The memory limit of each statement in bytes, if tighter than the
+    /// memory budget. Set via SET work_mem.
+    work_mem: Option<usize>,
+    /// The maximum staleness of replica reads in Raft ticks, if tighter than
+    /// the node's. Set via SET replica_read_staleness.
+    replica_read_staleness: Option<u8>,
+    /// If true, read-write transactions are serializable rather than snapshot
+    /// isolated. Set via SET TRANSACTION ISOLATION LEVEL.
+    serializable: bool,
+    /// Open cursors, by name.
+    cursors: HashMap<String, Cursor>,
+    /// Prepared statements, by ID.
+    prepared: HashMap<u64, Prepared>,
+    /// The next prepared statement ID.
+    next_prepared: u64,
+    /// The query result cache, if any. Usually shared with other sessions.
+    query_cache: Option<Arc<QueryCache>>,
+}
+
+/// A prepared statement. The statement is parsed once when prepared, but
+/// planned on every execution, since the plan depends on the bound parameter
+/// values and the schema as of the executing transaction.
+struct Prepared {
+    /// The raw SQL statement, for tracing.
+    statement: String,
+    /// The parsed statement, with unbound parameters.
+    ast: ast::Statement,
+    /// The number of parameters.
+    parameters: usize,
+}
+
+/// An open cursor, with the query's remaining rows.
+struct Cursor {
+    columns: Vec<Label>,
+    rows: Rows,
 }
 
 impl<'a, E: Engine<'a>> Session<'a, E> {
     /// Creates a new session using the given SQL engine.
     pub fn new(engine: &'a E) -> Self {
-        Self { engine, txn: None }
+        Self {
+            engine,
+            txn: None,
+            user: None,
+            database: Database::DEFAULT.to_string(),
+            cancel: CancelFlag::new(),
+            statement_timeout: None,
+            memory_budget: None,
+            work_mem: None,
+            replica_read_staleness: None,
+            serializable: false,
+            cursors: HashMap::new(),
+            prepared: HashMap::new(),
+            next_prepared: 1,
+            query_cache: None,
+        }
     }
 
     /// Executes a client statement.
+    ///
+    /// The statement can be cancelled via the flag returned by canceller().
+    /// The flag is cleared before each statement, so a cancellation that
+    /// arrives between statements is ignored.
+    ///
+    /// Outside of an explicit transaction, the statement runs in an implicit
+    /// transaction. Read-write implicit transactions are transparently retried
+    /// with backoff on serialization failures, up to IMPLICIT_TXN_MAX_RETRIES
+    /// times, since the statement is the entire transaction.
+    ///
+    /// The statement is traced in a span, with child spans for parsing,
+    /// planning, and execution, and their Raft requests. Errors carry the
+    /// statement as context.
     pub fn execute(&mut self, statement: &str) -> Result<StatementResult> {
-        // Parse and execute the statement. Transaction control is done here,
-        // other statements are executed by the SQL engine.
-        Ok(match Parser::new(statement).parse()? {
+        let _span = info_span!("statement", statement = %redact(statement).0).entered();
+        self.cancel.reset();
+        info_span!("parse")
+            .in_scope(|| Parser::new(statement).parse())
+            .and_then(|parsed| match parsed {
+                ast::Statement::Select { as_of: None, .. }
+                    if self.query_cache.is_some() && self.txn.is_none() =>
+                {
+                    self.execute_cached(statement, parsed)
+                }
+                parsed => self.execute_statement(parsed),
+            })
+            .map_err(|err| err.with_context(Context::default().statement(statement)))
+    }
+
+    /// Prepares a statement for later execution via execute_prepared(). The
+    /// statement may contain positional parameters (i.e. ?), which are bound
+    /// to values on execution. Returns the prepared statement ID and the
+    /// number of parameters.
+    pub fn prepare(&mut self, statement: &str) -> Result<(u64, usize)> {
+        let _span = info_span!("prepare", statement = %redact(statement).0).entered();
+        let mut parser = Parser::new(statement);
+        let ast = parser
+            .parse()
+            .map_err(|err| err.with_context(Context::default().statement(statement)))?;
+        let parameters = parser.parameters();
+        let id = self.next_prepared;
+        self.next_prepared += 1;
+        self.prepared.insert(id, Prepared { statement: statement.to_string(), ast, parameters });
+        Ok((id, parameters))
+    }
+
+    /// Executes a prepared statement with the given parameter values, which
+    /// must match the statement's number of parameters. See execute().
+    pub fn execute_prepared(&mut self, id: u64, params: Vec<Value>) -> Result<StatementResult> {
+        let Some(prepared) = self.prepared.get(&id) else {
+            return errinput!("prepared statement {id} does not exist");
+        };
+        let _span = info_span!("statement", statement = %redact(&prepared.statement).0).entered();
+        self.cancel.reset();
+        if params.len() != prepared.parameters {
+            return errinput!("expected {} parameters, got {}", prepared.parameters, params.len());
+        }
+        let mut ast = prepared.ast.clone();
+        ast.bind(&params.into_iter().map(ast::Literal::from).collect_vec());
+        let context = Context::default().statement(&prepared.statement);
+        self.execute_statement(ast).map_err(|err| err.with_context(context))
+    }
+
+    /// Deallocates a prepared statement.
+    pub fn deallocate(&mut self, id: u64) -> Result<()> {
+        if self.prepared.remove(&id).is_none() {
+            return errinput!("prepared statement {id} does not exist");
+        }
+        Ok(())
+    }
+
+    /// Executes a SELECT query, returning its column labels and a row
+    /// iterator instead of buffering the rows, e.g. to stream them to a
+    /// client. Uses the session's transaction if any, otherwise an implicit
+    /// read-only transaction.
+    ///
+    /// The rows are pulled through the plan as the iterator is consumed, but
+    /// note that Raft scans are still buffered by the Raft state machine. The
+    /// statement timeout and cancellation apply until the iterator is done.
+    pub fn query(&mut self, statement: &str) -> Result<(Vec<Label>, Rows)> {
+        let _span = info_span!("statement", statement = %redact(statement).0).entered();
+        self.cancel.reset();
+        let context = || Context::default().statement(statement);
+        let parsed = info_span!("parse")
+            .in_scope(|| Parser::new(statement).parse())
+            .map_err(|err| err.with_context(context()))?;
+        if !matches!(parsed, ast::Statement::Select { .. }) {
+            return errinput!("not a query: {statement}");
+        }
+        let user = self.user.clone();
+        let cancel = self.statement_cancel();
+        let memory = self.statement_memory();
+        let as_of = Self::as_of(&parsed);
+        let (columns, rows) = self
+            .with_txn_as_of(true, as_of, |txn| {
+                let plan = Self::plan(parsed, user.as_deref(), txn)?;
+                match info_span!("execute").in_scope(|| plan.execute(txn, &cancel, &memory))? {
+                    ExecutionResult::Select { columns, rows } => Ok((columns, rows)),
+                    _ => errdata!("query didn't return rows"),
+                }
+            })
+            .map_err(|err| err.with_context(context()))?;
+        let rows = rows.map(move |row| {
+            cancel.check()?;
+            row
+        });
+        Ok((columns, Box::new(rows)))
+    }
+
+    /// Executes a parsed statement. Transaction control is done here, other
+    /// statements are executed by the SQL engine.
+    fn execute_statement(&mut self, parsed: ast::Statement) -> Result<StatementResult> {
+        Ok(match parsed {
             ast::Statement::Begin { read_only, as_of } => {
                 if self.txn.is_some() {
                     return errinput!("already in a transaction");
                 }
-                let txn = match (read_only, as_of) {
-                    (false, None) => self.engine.begin()?,
-                    (true, None) => self.engine.begin_read_only()?,
+                let mut txn = match (read_only, as_of) {
+                    (false, None) => self.begin_read_write()?,
+                    (true, None) => self.begin_read_only()?,
                     (true, Some(as_of)) => self.engine.begin_as_of(as_of)?,
                     (false, Some(_)) => {
                         return errinput!("can't start read-write transaction in a given version")
                     }
                 };
+                txn.use_database(&self.database);
                 let state = txn.state().clone();
                 self.txn = Some(txn);
                 StatementResult::Begin(state)
@@ -53,6 +260,7 @@ impl<'a, E: Engine<'a>> Session<'a, E> {
                     return errinput!("not in a transaction");
                 };
                 let version = txn.version();
+                self.cursors.clear();
                 txn.commit()?;
                 StatementResult::Commit { version }
             }
@@ -61,40 +269,608 @@ impl<'a, E: Engine<'a>> Session<'a, E> {
                     return errinput!("not in a transaction");
                 };
                 let version = txn.version();
+                self.cursors.clear();
                 txn.rollback()?;
                 StatementResult::Rollback { version }
             }
-            ast::Statement::Explain(statement) => self.with_txn(true, |txn| {
-                Ok(StatementResult::Explain(Plan::build(*statement, txn)?.optimize()?))
-            })?,
+            ast::Statement::Savepoint { name } => {
+                let Some(txn) = self.txn.as_ref() else {
+                    return errinput!("not in a transaction");
+                };
+                txn.savepoint(&name)?;
+                StatementResult::Savepoint { name }
+            }
+            ast::Statement::RollbackToSavepoint { name } => {
+                let Some(txn) = self.txn.as_ref() else {
+                    return errinput!("not in a transaction");
+                };
+                txn.rollback_to_savepoint(&name)?;
+                StatementResult::RollbackToSavepoint { name }
+            }
+            ast::Statement::ReleaseSavepoint { name } => {
+                let Some(txn) = self.txn.as_ref() else {
+                    return errinput!("not in a transaction");
+                };
+                txn.release_savepoint(&name)?;
+                StatementResult::ReleaseSavepoint { name }
+            }
+            ast::Statement::Explain { statement, format, analyze: false } => {
+                let user = self.user.clone();
+                self.with_txn_as_of(true, Self::as_of(&statement), |txn| {
+                    Ok(StatementResult::Explain(
+                        Self::plan(*statement, user.as_deref(), txn)?.into(),
+                        format,
+                        None,
+                    ))
+                })?
+            }
+            ast::Statement::Explain { statement, format, analyze: true } => {
+                self.explain_analyze(*statement, format)?
+            }
+            ast::Statement::Set { name, value } => {
+                match name.as_str() {
+                    "statement_timeout" => {
+                        let timeout = value.as_ref().map(ast::Literal::to_duration).transpose()?;
+                        self.statement_timeout = timeout.filter(|timeout| !timeout.is_zero())
+                    }
+                    "work_mem" => {
+                        let bytes = value.map(|value| self.parse_work_mem(value)).transpose()?;
+                        self.work_mem = bytes.filter(|&bytes| bytes > 0)
+                    }
+                    "replica_read_staleness" => {
+                        self.replica_read_staleness =
+                            value.map(Self::parse_staleness).transpose()?
+                    }
+                    "transaction_isolation" => {
+                        self.serializable =
+                            value.map(Self::parse_isolation).transpose()?.unwrap_or(false)
+                    }
+                    name => return errinput!("unknown setting {name}"),
+                }
+                StatementResult::Set { name }
+            }
+            ast::Statement::Use { name } => {
+                if self.with_txn(true, |txn| txn.get_database(&name))?.is_none() {
+                    return errinput!("database {name} does not exist");
+                }
+                if let Some(txn) = &mut self.txn {
+                    txn.use_database(&name);
+                }
+                self.database = name.clone();
+                StatementResult::Use { database: name }
+            }
+            ast::Statement::CreateIndex { table, column } => {
+                if self.txn.is_some() {
+                    return errinput!("CREATE INDEX can't run in a transaction");
+                }
+                self.create_index(&table, &column)?;
+                StatementResult::CreateIndex { table, column }
+            }
+            ast::Statement::Declare { name, query } => {
+                let memory = self.statement_memory();
+                let Some(txn) = &mut self.txn else {
+                    return errinput!("cursors can only be declared in a transaction");
+                };
+                if Self::as_of(&query).is_some() {
+                    return errinput!("AS OF SYSTEM TIME can't be used in a transaction");
+                }
+                if self.cursors.contains_key(&name) {
+                    return errinput!("cursor {name} already exists");
+                }
+                // Fetches apply the statement timeout, so don't set a deadline
+                // here. The cancel flag is shared, so fetches can be cancelled.
+                let plan = Self::plan(*query, self.user.as_deref(), txn)?;
+                let ExecutionResult::Select { columns, rows } =
+                    plan.execute(txn, &self.cancel, &memory)?
+                else {
+                    return errdata!("cursor query didn't return rows");
+                };
+                self.cursors.insert(name.clone(), Cursor { columns, rows });
+                StatementResult::Declare { name }
+            }
+            ast::Statement::Fetch { name, count } => {
+                let cancel = self.statement_cancel();
+                let Some(cursor) = self.cursors.get_mut(&name) else {
+                    return errinput!("cursor {name} does not exist");
+                };
+                let count = count.map_or(usize::MAX, |count| count as usize);
+                let mut rows = Vec::new();
+                while rows.len() < count {
+                    cancel.check()?;
+                    match cursor.rows.next().transpose()? {
+                        Some(row) => rows.push(row),
+                        None => break,
+                    }
+                }
+                StatementResult::Select { columns: cursor.columns.clone(), rows }
+            }
+            ast::Statement::Close { name } => {
+                if self.cursors.remove(&name).is_none() {
+                    return errinput!("cursor {name} does not exist");
+                }
+                StatementResult::Close { name }
+            }
             statement => {
+                let cancel = self.statement_cancel();
                 let read_only = matches!(statement, ast::Statement::Select { .. });
-                self.with_txn(read_only, |txn| {
-                    Plan::build(statement, txn)?.optimize()?.execute(txn)?.try_into()
-                })?
+                // Implicit read-write transactions are retried on
+                // serialization failures, since the statement is the entire
+                // transaction. Read-only transactions can't conflict.
+                if self.txn.is_some() || read_only {
+                    return self.execute_planned(statement, read_only, &cancel);
+                }
+                let mut retries = 0;
+                loop {
+                    match self.execute_planned(statement.clone(), read_only, &cancel) {
+                        Err(err)
+                            if err.root() == &Error::Serialization
+                                && retries < IMPLICIT_TXN_MAX_RETRIES =>
+                        {
+                            debug!("Retrying implicit transaction: {err}");
+                            cancel.check()?;
+                            std::thread::sleep(Self::retry_backoff(retries));
+                            retries += 1;
+                        }
+                        result => break result?,
+                    }
+                }
+            }
+        })
+    }
+
+    /// Plans and executes a statement in the session's transaction, or an
+    /// implicit transaction.
+    fn execute_planned(
+        &mut self,
+        statement: ast::Statement,
+        read_only: bool,
+        cancel: &CancelFlag,
+    ) -> Result<StatementResult> {
+        let user = self.user.clone();
+        let memory = self.statement_memory();
+        self.with_txn_as_of(read_only, Self::as_of(&statement), |txn| {
+            let plan = Self::plan(statement, user.as_deref(), txn)?;
+            info_span!("execute").in_scope(|| plan.execute(txn, cancel, &memory)?.try_into())
+        })
+    }
+
+    /// Executes a statement for EXPLAIN ANALYZE, returning its plan along with
+    /// execution statistics. SELECT results are discarded, and writes are
+    /// applied as usual.
+    fn explain_analyze(
+        &mut self,
+        statement: ast::Statement,
+        format: ast::ExplainFormat,
+    ) -> Result<StatementResult> {
+        let read_only = match statement {
+            ast::Statement::Select { .. } => true,
+            ast::Statement::Delete { .. }
+            | ast::Statement::Insert { .. }
+            | ast::Statement::Update { .. } => false,
+            _ => {
+                return errinput!(
+                    "EXPLAIN ANALYZE only supports SELECT, INSERT, UPDATE, and DELETE"
+                )
+            }
+        };
+        let user = self.user.clone();
+        let cancel = self.statement_cancel();
+        let memory = self.statement_memory();
+        self.with_txn_as_of(read_only, Self::as_of(&statement), |txn| {
+            let plan = Self::plan(statement, user.as_deref(), txn)?;
+            let result =
+                info_span!("execute").in_scope(|| plan.clone().execute(txn, &cancel, &memory))?;
+            let rows = match result {
+                ExecutionResult::Select { rows, .. } => {
+                    rows.process_results(|rows| rows.count())? as u64
+                }
+                ExecutionResult::Delete { count }
+                | ExecutionResult::Insert { count }
+                | ExecutionResult::Update { count } => count,
+                _ => return errdata!("unexpected EXPLAIN ANALYZE result"),
+            };
+            let analysis =
+                Analysis { rows, peak_memory: memory.used(), memory_limit: memory.limit() };
+            Ok(StatementResult::Explain(plan.into(), format, Some(analysis)))
+        })
+    }
+
+    /// Executes a SELECT statement in an implicit read-only transaction, using
+    /// the session's query cache. The statement is always planned, which also
+    /// checks the user's privileges, and the cached result is used if the plan
+    /// and the versions of the queried tables match.
+    ///
+    /// The result is only cached if the snapshot sees all writes up to the
+    /// table versions. Otherwise, a transaction that was active in the snapshot
+    /// may have written to the tables, and could later commit without changing
+    /// the versions. Queries that read system tables or tables with a TTL
+    /// aren't cached, since their results can change without writes.
+    fn execute_cached(
+        &mut self,
+        statement: &str,
+        parsed: ast::Statement,
+    ) -> Result<StatementResult> {
+        let Some(cache) = self.query_cache.clone() else {
+            return self.execute_statement(parsed);
+        };
+        let user = self.user.clone();
+        let database = self.database.clone();
+        let cancel = self.statement_cancel();
+        let memory = self.statement_memory();
+        self.with_txn(true, |txn| {
+            let plan = Self::plan(parsed, user.as_deref(), txn)?;
+            let Some(tables) = Self::cacheable_tables(&plan) else {
+                return info_span!("execute")
+                    .in_scope(|| plan.execute(txn, &cancel, &memory)?.try_into());
+            };
+            let versions = txn.table_versions(&tables)?;
+            if let Some((columns, rows)) = cache.get(&database, statement, &plan, &versions)? {
+                return Ok(StatementResult::Select { columns, rows });
+            }
+            let state = txn.state();
+            let cacheable = versions
+                .iter()
+                .all(|&v| v < state.version && state.active.iter().all(|&active| active > v));
+            let cached_plan = cacheable.then(|| plan.clone());
+            let result: StatementResult = info_span!("execute")
+                .in_scope(|| plan.execute(txn, &cancel, &memory)?.try_into())?;
+            if let (Some(plan), StatementResult::Select { columns, rows }) = (cached_plan, &result)
+            {
+                cache.insert(
+                    &database,
+                    statement,
+                    plan,
+                    versions,
+                    columns.clone(),
+                    rows.clone(),
+                )?;
             }
+            Ok(result)
         })
     }
 
+    /// Returns the tables read by a plan, if its result can be cached.
+    fn cacheable_tables(plan: &Plan) -> Option<Vec<String>> {
+        let Plan::Select(root) = plan else {
+            return None;
+        };
+        let schemas = root.schemas();
+        if schemas.is_empty()
+            || schemas.iter().any(|table| {
+                table.ttl.is_some()
+                    || SystemTable::lookup(&table.name).is_some()
+                    || InformationSchema::lookup(&table.name).is_some()
+            })
+        {
+            return None;
+        }
+        Some(root.tables().into_iter().map(String::from).collect())
+    }
+
+    /// Builds a secondary index online, without blocking writes to the table
+    /// for the duration of the build. Each phase runs in separate, short
+    /// transactions:
+    ///
+    /// 1. Create the index in the building state. Transactions that begin
+    ///    after this maintain the index on writes, but queries don't use it.
+    /// 2. Wait for older transactions to complete, since they don't maintain
+    ///    the index, then backfill it in batches from a read-only snapshot.
+    /// 3. Catch up from the change log. Rows written after the snapshot may
+    ///    have been backfilled with stale values, so move them to the entries
+    ///    of their current values. This waits for all transactions up to the
+    ///    last backfill batch to complete, since they may not have seen it.
+    /// 4. Publish the index, atomically making it visible to queries.
+    ///
+    /// Write conflicts with concurrent transactions are retried. If the build
+    /// is interrupted, e.g. by the statement timeout, the index remains in the
+    /// building state, and running CREATE INDEX again resumes it.
+    fn create_index(&self, table: &str, column: &str) -> Result<()> {
+        let cancel = self.statement_cancel();
+        let user = self.user.as_deref();
+
+        // Phase 1: create the index in the building state.
+        let mut version = 0;
+        self.with_index_build_txn(&cancel, |txn| {
+            if let Some(user) = user {
+                Plan::authorize_tables(user, vec![(table, Privilege::Ddl)], txn)?;
+            }
+            version = txn.version();
+            txn.create_index(table, column)
+        })?;
+
+        // Phase 2: backfill the index from a snapshot, once all transactions
+        // that began before the build have completed.
+        let snapshot = loop {
+            let mut snapshot = self.engine.begin_read_only()?;
+            if snapshot.state().resolved() > version {
+                snapshot.use_database(&self.database);
+                break snapshot;
+            }
+            cancel.check()?;
+            std::thread::sleep(INDEX_BUILD_POLL_INTERVAL);
+        };
+        let schema = snapshot.must_get_table(table)?;
+        let primary_key = schema.primary_key;
+        let Some(index) = schema.columns.iter().position(|c| c.name == column) else {
+            return errinput!("unknown column {column} in table {table}");
+        };
+        for batch in &snapshot.scan(table, None)?.chunks(INDEX_BUILD_BATCH_SIZE) {
+            let rows: Vec<Row> = batch.try_collect()?;
+            self.with_index_build_txn(&cancel, |txn| {
+                version = txn.version();
+                let entries = rows
+                    .iter()
+                    .map(|row| (row[primary_key].clone(), None, Some(row[index].clone())))
+                    .collect();
+                txn.backfill_index(table, column, entries)
+            })?;
+        }
+
+        // Phase 3: catch up with rows changed after the snapshot, until all
+        // transactions up to the last backfill batch have completed.
+        let mut from = snapshot.state().resolved();
+        while from <= version {
+            self.with_index_build_txn(&cancel, |txn| {
+                let changes = txn.changes(&[table.to_string()], from)?;
+                from = changes.resolved;
+                let ids =
+                    changes.changes.into_iter().map(|change| change.id).unique().collect_vec();
+                if ids.is_empty() {
+                    return Ok(());
+                }
+                let values = |rows: Vec<Row>| -> HashMap<Value, Value> {
+                    rows.into_iter()
+                        .map(|row| (row[primary_key].clone(), row[index].clone()))
+                        .collect()
+                };
+                let mut old = values(snapshot.get(table, &ids)?);
+                let mut new = values(txn.get(table, &ids)?);
+                let entries = ids
+                    .into_iter()
+                    .map(|id| {
+                        let (old, new) = (old.remove(&id), new.remove(&id));
+                        (id, old, new)
+                    })
+                    .collect();
+                txn.backfill_index(table, column, entries)
+            })?;
+            if from <= version {
+                cancel.check()?;
+                std::thread::sleep(INDEX_BUILD_POLL_INTERVAL);
+            }
+        }
+
+        // Phase 4: publish the index.
+        self.with_index_build_txn(&cancel, |txn| txn.publish_index(table, column))
+    }
+
+    /// Runs a step of an online index build in a read-write transaction in the
+    /// current database, retrying it on transient errors such as write
+    /// conflicts with concurrent transactions.
+    fn with_index_build_txn(
+        &self,
+        cancel: &CancelFlag,
+        mut f: impl FnMut(&E::Transaction) -> Result<()>,
+    ) -> Result<()> {
+        loop {
+            cancel.check()?;
+            let mut txn = self.engine.begin()?;
+            txn.use_database(&self.database);
+            match f(&txn) {
+                Ok(()) => return txn.commit(),
+                Err(err) if err.is_retryable() => txn.rollback()?,
+                Err(err) => {
+                    txn.rollback()?;
+                    return Err(err);
+                }
+            }
+            std::thread::sleep(INDEX_BUILD_POLL_INTERVAL);
+        }
+    }
+
+    /// Returns the backoff before retrying an implicit transaction after the
+    /// given number of retries. Uses exponential backoff starting at
+    /// IMPLICIT_TXN_MIN_BACKOFF doubling up to IMPLICIT_TXN_MAX_BACKOFF, but
+    /// randomizes the backoff in this interval to reduce the chance of
+    /// repeated conflicts. Like Client::with_retry().
+    fn retry_backoff(retries: u32) -> Duration {
+        let min = IMPLICIT_TXN_MIN_BACKOFF;
+        let max = std::cmp::min(
+            min.saturating_mul(2_u32.saturating_pow(retries)),
+            IMPLICIT_TXN_MAX_BACKOFF,
+        );
+        rand::thread_rng().gen_range(min..=max)
+    }
+
+    /// Returns the cancellation flag for a statement, with the statement
+    /// timeout deadline if any.
+    fn statement_cancel(&self) -> CancelFlag {
+        match self.statement_timeout {
+            Some(timeout) => self.cancel.with_deadline(Instant::now() + timeout),
+            None => self.cancel.clone(),
+        }
+    }
+
+    /// Builds and optimizes a plan for the statement. If the session is
+    /// authenticated, checks that the user is allowed to execute it.
+    fn plan(statement: ast::Statement, user: Option<&str>, txn: &E::Transaction) -> Result<Plan> {
+        let _span = info_span!("plan").entered();
+        let plan = Plan::build(statement, txn)?;
+        if let Some(user) = user {
+            plan.authorize(user, txn)?;
+        }
+        plan.optimize()
+    }
+
+    /// Returns a memory budget for a statement, limited by the session's
+    /// memory budget and work_mem setting.
+    fn statement_memory(&self) -> MemoryBudget {
+        let limit = match (self.work_mem, self.memory_budget) {
+            (Some(work_mem), Some(budget)) => Some(work_mem.min(budget)),
+            (work_mem, budget) => work_mem.or(budget),
+        };
+        MemoryBudget::new(limit)
+    }
+
+    /// Parses a work_mem setting, as an integer number of bytes or a string
+    /// with a unit. It can't exceed the session's memory budget.
+    fn parse_work_mem(&self, value: ast::Literal) -> Result<usize> {
+        let bytes = value.to_bytes()?;
+        if let Some(budget) = self.memory_budget.filter(|&budget| bytes > budget) {
+            return errinput!("work_mem can't exceed the session memory budget of {budget} bytes");
+        }
+        Ok(bytes)
+    }
+
+    /// Parses a replica read staleness setting, as an integer number of Raft
+    /// ticks.
+    fn parse_staleness(value: ast::Literal) -> Result<u8> {
+        let ast::Literal::Integer(ticks) = value else {
+            return errinput!("replica read staleness must be an integer");
+        };
+        u8::try_from(ticks).or_else(|_| errinput!("invalid replica read staleness {ticks}"))
+    }
+
+    /// Parses a transaction isolation level setting, returning true for
+    /// serializable and false for snapshot isolation.
+    fn parse_isolation(value: ast::Literal) -> Result<bool> {
+        let ast::Literal::String(level) = value else {
+            return errinput!("transaction isolation level must be a string");
+        };
+        match level.to_lowercase().as_str() {
+            "serializable" => Ok(true),
+            "snapshot" => Ok(false),
+            _ => errinput!("invalid transaction isolation level {level}"),
+        }
+    }
+
+    /// Authenticates the session as the given user, by verifying the password
+    /// against the user's password hash in the catalog. Neither the error nor
+    /// the response time reveal whether the user exists: unknown users are
+    /// verified against a dummy hash.
+    pub fn authenticate(&mut self, name: &str, password: &str) -> Result<()> {
+        let user = self.with_txn(true, |txn| txn.get_user(name))?;
+        let verified = match user {
+            Some(user) => user.verify_password(password),
+            None => User::verify_dummy_password(password),
+        };
+        if !verified {
+            return errinput!("authentication failed for user {name}");
+        }
+        self.user = Some(name.to_string());
+        Ok(())
+    }
+
+    /// Returns the session's current database.
+    pub fn database(&self) -> &str {
+        &self.database
+    }
+
+    /// Returns the authenticated user, if any. Unauthenticated sessions are not
+    /// subject to privilege checks.
+    pub fn user(&self) -> Option<&str> {
+        self.user.as_deref()
+    }
+
+    /// Sets the memory budget of each statement in bytes, or None for no
+    /// limit. Statements that buffer more rows than this in sorts, aggregates,
+    /// or hash joins error instead of exhausting the node's memory.
+    pub fn set_memory_budget(&mut self, bytes: Option<usize>) {
+        self.memory_budget = bytes
+    }
+
+    /// Sets the query result cache, or None to disable caching (the default).
+    /// The cache is usually shared by all sessions on a node.
+    pub fn set_query_cache(&mut self, cache: Option<Arc<QueryCache>>) {
+        self.query_cache = cache
+    }
+
+    /// Returns the session's cancellation flag. Setting it cancels the
+    /// currently executing statement, if any, from a different thread.
+    pub fn canceller(&self) -> CancelFlag {
+        self.cancel.clone()
+    }
+
+    /// Returns the row changes of the given tables with versions at or above
+    /// from, up to the resolved version. Clients stream changes by calling
+    /// this repeatedly with the previous resolved version. Authenticated users
+    /// need the SELECT privilege on the tables.
+    pub fn changes(&mut self, tables: &[String], from: mvcc::Version) -> Result<Changes> {
+        let user = self.user.clone();
+        self.with_txn(true, |txn| {
+            if let Some(user) = &user {
+                let required = tables.iter().map(|t| (t.as_str(), Privilege::Select)).collect();
+                Plan::authorize_tables(user, required, txn)?;
+            }
+            txn.changes(tables, from)
+        })
+    }
+
+    /// Returns the AS OF SYSTEM TIME version of a SELECT statement, if any.
+    fn as_of(statement: &ast::Statement) -> Option<mvcc::Version> {
+        match statement {
+            ast::Statement::Select { as_of, .. } => *as_of,
+            _ => None,
+        }
+    }
+
+    /// Begins a read-write transaction, using the session's isolation level.
+    fn begin_read_write(&self) -> Result<E::Transaction> {
+        match self.serializable {
+            true => self.engine.begin_serializable(),
+            false => self.engine.begin(),
+        }
+    }
+
+    /// Begins a read-only transaction, using the session's replica read
+    /// staleness bound if set.
+    fn begin_read_only(&self) -> Result<E::Transaction> {
+        match self.replica_read_staleness {
+            Some(ticks) => self.engine.begin_read_only_stale(ticks),
+            None => self.engine.begin_read_only(),
+        }
+    }
+
     /// Runs a closure in the session's explicit transaction, if there is one,
     /// otherwise a temporary implicit transaction. If read_only is true, uses a
     /// read-only implicit transaction. Does not retry errors.
     pub fn with_txn<F, T>(&mut self, read_only: bool, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut E::Transaction) -> Result<T>,
+    {
+        self.with_txn_as_of(read_only, None, f)
+    }
+
+    /// Like with_txn(), but if as_of is given, uses an implicit read-only
+    /// transaction in that historical version, i.e. a time-travel query. This
+    /// errors in an explicit transaction, which has its own version.
+    fn with_txn_as_of<F, T>(
+        &mut self,
+        read_only: bool,
+        as_of: Option<mvcc::Version>,
+        f: F,
+    ) -> Result<T>
     where
         F: FnOnce(&mut E::Transaction) -> Result<T>,
     {
         // Use the current explicit transaction, if there is one.
         if let Some(ref mut txn) = self.txn {
+            if as_of.is_some() {
+                return errinput!("AS OF SYSTEM TIME can't be used in a transaction");
+            }
             return f(txn);
         }
         // Otherwise, use an implicit transaction. Doing this session-side
         // results in additional Raft roundtrips to begin and complete the
         // transaction -- we could avoid this if the below-Raft engine supported
         // implicit transactions, but we keep it simple.
-        let mut txn = match read_only {
-            true => self.engine.begin_read_only()?,
-            false => self.engine.begin()?,
+        let mut txn = match (read_only, as_of) {
+            (true, Some(version)) => self.engine.begin_as_of(version)?,
+            (true, None) => self.begin_read_only()?,
+            (false, None) => self.begin_read_write()?,
+            (false, Some(_)) => return errinput!("AS OF SYSTEM TIME requires a read-only query"),
         };
+        txn.use_database(&self.database);
         let result = f(&mut txn);
         match result {
             Ok(_) => txn.commit()?,
@@ -109,6 +885,64 @@ impl Session<'_, Raft> {
     pub fn status(&self) -> Result<Status> {
         self.engine.status()
     }
+
+    /// Verifies that the Raft logs of all nodes are consistent.
+    pub fn verify(&self) -> Result<raft::Report> {
+        self.engine.verify()
+    }
+
+    /// Verifies that the SQL state machines of all nodes are consistent.
+    pub fn verify_state(&self) -> Result<raft::StateReport> {
+        self.engine.verify_state()
+    }
+
+    /// Returns the local Raft node's event trace.
+    pub fn trace(&self) -> Result<Vec<raft::TraceEvent>> {
+        self.engine.trace()
+    }
+
+    /// Returns the local Raft node's metrics.
+    pub fn metrics(&self) -> Result<raft::Metrics> {
+        self.engine.metrics()
+    }
+
+    /// Checks the local Raft node's health, returning its status.
+    pub fn health(&self) -> Result<raft::NodeStatus> {
+        self.engine.health()
+    }
+
+    /// Backs up the local Raft node's storage to the given directory on the
+    /// node, returning the applied index of the backup. Authenticated users
+    /// must be superusers, since this writes server files.
+    pub fn backup(&mut self, path: &str) -> Result<raft::Index> {
+        self.require_superuser()?;
+        self.engine.backup(path)
+    }
+
+    /// Transfers Raft leadership away from the local node, if it's the leader,
+    /// to the given node or the most up-to-date one. Returns the new leader, or
+    /// None if the local node isn't the leader. Authenticated users must be
+    /// superusers.
+    pub fn transfer_leadership(
+        &mut self,
+        to: Option<raft::NodeID>,
+    ) -> Result<Option<raft::NodeID>> {
+        self.require_superuser()?;
+        self.engine.transfer_leadership(to)
+    }
+
+    /// Errors unless the session is unauthenticated or its user is a
+    /// superuser, for server administration requests.
+    fn require_superuser(&mut self) -> Result<()> {
+        if let Some(user) = self.user.clone() {
+            let user = self.with_txn(true, |txn| txn.get_user(&user))?;
+            if !user.as_ref().is_some_and(|user| user.superuser) {
+                let name = user.map(|user| user.name).unwrap_or_default();
+                return errinput!("permission denied: user {name} is not a superuser");
+            }
+        }
+        Ok(())
+    }
 }
 
 /// If the session has an open transaction when dropped, roll it back.
@@ -123,14 +957,37 @@ impl<'a, E: Engine<'a>> Drop for Session<'a, E> {
 }
 
 /// A session statement result. Sent across the wire to SQL clients.
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum StatementResult {
     Begin(mvcc::TransactionState),
     Commit { version: mvcc::Version },
     Rollback { version: mvcc::Version },
-    Explain(Plan),
+    Savepoint { name: String },
+    RollbackToSavepoint { name: String },
+    ReleaseSavepoint { name: String },
+    Explain(Box<Plan>, ast::ExplainFormat, Option<Analysis>),
+    Set { name: String },
+    Declare { name: String },
+    Close { name: String },
+    Use { database: String },
+    CreateDatabase { name: String },
+    DropDatabase { name: String, existed: bool },
     CreateTable { name: String },
     DropTable { name: String, existed: bool },
+    CreateIndex { table: String, column: String },
+    Analyze { tables: Vec<String> },
+    CreateUser { name: String },
+    AlterUser { name: String },
+    CreateRole { name: String },
+    DropRole { name: String },
+    CreateChangefeed { name: String },
+    DropChangefeed { name: String },
+    CreateTrigger { name: String },
+    DropTrigger { name: String },
+    CreateFunction { name: String },
+    DropFunction { name: String },
+    Grant { grantee: String },
+    Revoke { grantee: String },
     Delete { count: u64 },
     Insert { count: u64 },
     Update { count: u64 },
@@ -146,8 +1003,23 @@ impl TryFrom<ExecutionResult> for StatementResult {
 
     fn try_from(result: ExecutionResult) -> Result<Self> {
         Ok(match result {
+            ExecutionResult::CreateDatabase { name } => Self::CreateDatabase { name },
+            ExecutionResult::DropDatabase { name, existed } => Self::DropDatabase { name, existed },
             ExecutionResult::CreateTable { name } => Self::CreateTable { name },
             ExecutionResult::DropTable { name, existed } => Self::DropTable { name, existed },
+            ExecutionResult::Analyze { tables } => Self::Analyze { tables },
+            ExecutionResult::CreateUser { name } => Self::CreateUser { name },
+            ExecutionResult::AlterUser { name } => Self::AlterUser { name },
+            ExecutionResult::CreateRole { name } => Self::CreateRole { name },
+            ExecutionResult::DropRole { name } => Self::DropRole { name },
+            ExecutionResult::CreateChangefeed { name } => Self::CreateChangefeed { name },
+            ExecutionResult::DropChangefeed { name } => Self::DropChangefeed { name },
+            ExecutionResult::CreateTrigger { name } => Self::CreateTrigger { name },
+            ExecutionResult::DropTrigger { name } => Self::DropTrigger { name },
+            ExecutionResult::CreateFunction { name } => Self::CreateFunction { name },
+            ExecutionResult::DropFunction { name } => Self::DropFunction { name },
+            ExecutionResult::Grant { grantee } => Self::Grant { grantee },
+            ExecutionResult::Revoke { grantee } => Self::Revoke { grantee },
             ExecutionResult::Delete { count } => Self::Delete { count },
             ExecutionResult::Insert { count } => Self::Insert { count },
             ExecutionResult::Update { count } => Self::Update { count },
@@ -0,0 +1,339 @@
+//! System tables. These are virtual, read-only tables which aren't stored
+//! anywhere, but are generated on demand when scanned.
+//!
+//! The `system` schema exposes the Raft cluster status via SQL, and backs the
+//! SHOW CLUSTER and SHOW NODES statements. It is served by the Raft engine.
+//!
+//! * `system.cluster`: a single row with the cluster-wide status, as seen by
+//!   the Raft leader.
+//! * `system.nodes`: a row per node, with its Raft role, log indexes, storage
+//!   status, and version. Assembled by the leader by querying its peers.
+//!
+//! The `information_schema` schema exposes the table schemas in the catalog,
+//! roughly following the SQL standard such that tools and ORMs can introspect
+//! them. It is served by the Local engine, and thus also by the Raft engine.
+//!
+//! * `information_schema.tables`: a row per table.
+//! * `information_schema.columns`: a row per table column.
+//! * `information_schema.indexes`: a row per primary or secondary index.
+//! * `information_schema.table_constraints`: a row per primary key, unique,
+//!   and foreign key constraint.
+//!
+//! The information_schema tables are keyed by table_name, which isn't unique
+//! except in the tables table. Primary key lookups return all matching rows.
+
+use super::{Catalog, Raft};
+use crate::errinput;
+use crate::error::Result;
+use crate::sql::types::{Column, DataType, Expression, Row, Table, Value};
+
+/// A system table.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SystemTable {
+    /// system.cluster: the cluster status.
+    Cluster,
+    /// system.nodes: the status of each node.
+    Nodes,
+}
+
+impl SystemTable {
+    /// Looks up a system table by name.
+    pub fn lookup(name: &str) -> Option<Self> {
+        match name {
+            "system.cluster" => Some(Self::Cluster),
+            "system.nodes" => Some(Self::Nodes),
+            _ => None,
+        }
+    }
+
+    /// Returns the table name.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Cluster => "system.cluster",
+            Self::Nodes => "system.nodes",
+        }
+    }
+
+    /// Returns the table schema. The first column is the primary key.
+    pub fn schema(&self) -> Table {
+        use DataType::{Integer, String};
+        let columns: &[(&str, DataType, bool)] = match self {
+            Self::Cluster => &[
+                ("leader", Integer, false),
+                ("term", Integer, false),
+                ("nodes", Integer, false),
+                ("commit_index", Integer, false),
+                ("applied_index", Integer, false),
+                ("mvcc_versions", Integer, false),
+                ("active_txns", Integer, false),
+            ],
+            // Unreachable nodes only have an ID and role. ROLE is a keyword, so the
+            // column is named raft_role.
+            Self::Nodes => &[
+                ("id", Integer, false),
+                ("raft_role", String, false),
+                ("term", Integer, true),
+                ("leader", Integer, true),
+                ("last_index", Integer, true),
+                ("commit_index", Integer, true),
+                ("applied_index", Integer, true),
+                ("storage", String, true),
+                ("storage_keys", Integer, true),
+                ("storage_size", Integer, true),
+                ("version", String, true),
+            ],
+        };
+        schema(self.name(), columns)
+    }
+
+    /// Generates the table rows, optionally applying the given filter.
+    pub fn scan(&self, engine: &Raft, filter: Option<Expression>) -> Result<Vec<Row>> {
+        let rows = match self {
+            Self::Cluster => {
+                let status = engine.status()?;
+                vec![vec![
+                    Value::Integer(status.raft.leader as i64),
+                    Value::Integer(status.raft.term as i64),
+                    Value::Integer(status.raft.match_index.len() as i64),
+                    Value::Integer(status.raft.commit_index as i64),
+                    Value::Integer(status.raft.applied_index as i64),
+                    Value::Integer(status.mvcc.versions as i64),
+                    Value::Integer(status.mvcc.active_txns as i64),
+                ]]
+            }
+            Self::Nodes => engine
+                .nodes()?
+                .into_iter()
+                .map(|(id, status)| {
+                    let Some(status) = status else {
+                        let mut row = vec![Value::Integer(id as i64), "unreachable".into()];
+                        row.resize(self.schema().columns.len(), Value::Null);
+                        return row;
+                    };
+                    vec![
+                        Value::Integer(id as i64),
+                        status.role.into(),
+                        Value::Integer(status.term as i64),
+                        status.leader.map(|id| Value::Integer(id as i64)).unwrap_or(Value::Null),
+                        Value::Integer(status.last_index as i64),
+                        Value::Integer(status.commit_index as i64),
+                        Value::Integer(status.applied_index as i64),
+                        status.storage.name.into(),
+                        Value::Integer(status.storage.keys as i64),
+                        Value::Integer(status.storage.size as i64),
+                        status.version.into(),
+                    ]
+                })
+                .collect(),
+        };
+        filter_rows(rows, filter)
+    }
+
+    /// Fetches table rows by primary key.
+    pub fn get(&self, engine: &Raft, ids: &[Value]) -> Result<Vec<Row>> {
+        Ok(self.scan(engine, None)?.into_iter().filter(|row| ids.contains(&row[0])).collect())
+    }
+}
+
+/// An information_schema table.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InformationSchema {
+    /// information_schema.tables: the tables.
+    Tables,
+    /// information_schema.columns: the table columns.
+    Columns,
+    /// information_schema.indexes: the primary and secondary indexes.
+    Indexes,
+    /// information_schema.table_constraints: the table constraints.
+    TableConstraints,
+}
+
+impl InformationSchema {
+    /// The schema of user tables. toyDB only has a single schema.
+    const TABLE_SCHEMA: &'static str = "public";
+
+    /// Looks up an information_schema table by name.
+    pub fn lookup(name: &str) -> Option<Self> {
+        match name {
+            "information_schema.tables" => Some(Self::Tables),
+            "information_schema.columns" => Some(Self::Columns),
+            "information_schema.indexes" => Some(Self::Indexes),
+            "information_schema.table_constraints" => Some(Self::TableConstraints),
+            _ => None,
+        }
+    }
+
+    /// Returns the table name.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Tables => "information_schema.tables",
+            Self::Columns => "information_schema.columns",
+            Self::Indexes => "information_schema.indexes",
+            Self::TableConstraints => "information_schema.table_constraints",
+        }
+    }
+
+    /// Returns the table schema. The first column is the primary key.
+    pub fn schema(&self) -> Table {
+        use DataType::{Boolean, Integer, String};
+        let columns: &[(&str, DataType, bool)] = match self {
+            Self::Tables => &[
+                ("table_name", String, false),
+                ("table_schema", String, false),
+                ("table_type", String, false),
+                ("column_count", Integer, false),
+            ],
+            Self::Columns => &[
+                ("table_name", String, false),
+                ("table_schema", String, false),
+                ("column_name", String, false),
+                ("ordinal_position", Integer, false),
+                ("data_type", String, false),
+                ("is_nullable", String, false),
+                ("column_default", String, true),
+                ("is_primary_key", Boolean, false),
+            ],
+            Self::Indexes => &[
+                ("table_name", String, false),
+                ("table_schema", String, false),
+                ("index_name", String, false),
+                ("column_name", String, false),
+                ("is_primary", Boolean, false),
+                ("is_unique", Boolean, false),
+            ],
+            Self::TableConstraints => &[
+                ("table_name", String, false),
+                ("table_schema", String, false),
+                ("constraint_name", String, false),
+                ("constraint_type", String, false),
+                ("column_name", String, false),
+                ("referenced_table", String, true),
+            ],
+        };
+        schema(self.name(), columns)
+    }
+
+    /// Generates the table rows from the catalog, optionally applying the
+    /// given filter.
+    pub fn scan(&self, catalog: &impl Catalog, filter: Option<Expression>) -> Result<Vec<Row>> {
+        let schema = || Value::from(Self::TABLE_SCHEMA);
+        let mut rows = Vec::new();
+        for table in catalog.list_tables()? {
+            let name = || Value::from(table.name.as_str());
+            let primary_key = &table.columns[table.primary_key];
+            match self {
+                Self::Tables => rows.push(vec![
+                    name(),
+                    schema(),
+                    "BASE TABLE".into(),
+                    Value::Integer(table.columns.len() as i64),
+                ]),
+
+                Self::Columns => {
+                    for (i, column) in table.columns.iter().enumerate() {
+                        rows.push(vec![
+                            name(),
+                            schema(),
+                            column.name.as_str().into(),
+                            Value::Integer(i as i64 + 1),
+                            column.datatype.to_string().into(),
+                            if column.nullable { "YES" } else { "NO" }.into(),
+                            match &column.default {
+                                Some(Value::Null) | None => Value::Null,
+                                Some(default) => default.to_string().into(),
+                            },
+                            Value::Boolean(i == table.primary_key),
+                        ])
+                    }
+                }
+
+                Self::Indexes => {
+                    rows.push(vec![
+                        name(),
+                        schema(),
+                        format!("{}_pkey", table.name).into(),
+                        primary_key.name.as_str().into(),
+                        Value::Boolean(true),
+                        Value::Boolean(true),
+                    ]);
+                    for column in table.columns.iter().filter(|c| c.index) {
+                        rows.push(vec![
+                            name(),
+                            schema(),
+                            format!("{}_{}_idx", table.name, column.name).into(),
+                            column.name.as_str().into(),
+                            Value::Boolean(false),
+                            Value::Boolean(column.unique),
+                        ])
+                    }
+                }
+
+                Self::TableConstraints => {
+                    let constraint = |constraint: String, r#type: &str, column: &Column| {
+                        vec![
+                            name(),
+                            schema(),
+                            constraint.into(),
+                            r#type.into(),
+                            column.name.as_str().into(),
+                            column.references.as_deref().map(Value::from).unwrap_or(Value::Null),
+                        ]
+                    };
+                    let (table_name, pkey) = (&table.name, primary_key);
+                    rows.push(constraint(format!("{table_name}_pkey"), "PRIMARY KEY", pkey));
+                    for (i, column) in table.columns.iter().enumerate() {
+                        let prefix = format!("{table_name}_{}", column.name);
+                        if column.unique && i != table.primary_key {
+                            rows.push(constraint(format!("{prefix}_key"), "UNIQUE", column));
+                        }
+                        if column.references.is_some() {
+                            rows.push(constraint(format!("{prefix}_fkey"), "FOREIGN KEY", column));
+                        }
+                    }
+                }
+            }
+        }
+        filter_rows(rows, filter)
+    }
+
+    /// Fetches table rows by primary key, i.e. table name.
+    pub fn get(&self, catalog: &impl Catalog, ids: &[Value]) -> Result<Vec<Row>> {
+        Ok(self.scan(catalog, None)?.into_iter().filter(|row| ids.contains(&row[0])).collect())
+    }
+}
+
+/// Builds a system table schema from (name, datatype, nullable) columns. The
+/// first column is the primary key.
+fn schema(name: &str, columns: &[(&str, DataType, bool)]) -> Table {
+    let columns = columns
+        .iter()
+        .enumerate()
+        .map(|(i, (name, datatype, nullable))| Column {
+            name: name.to_string(),
+            datatype: *datatype,
+            nullable: *nullable,
+            default: nullable.then_some(Value::Null),
+            unique: i == 0,
+            index: false,
+            references: None,
+            fulltext: false,
+        })
+        .collect();
+    Table { name: name.to_string(), primary_key: 0, columns, ttl: None, statistics: None }
+}
+
+/// Applies an optional filter to generated rows.
+fn filter_rows(rows: Vec<Row>, filter: Option<Expression>) -> Result<Vec<Row>> {
+    let Some(filter) = filter else {
+        return Ok(rows);
+    };
+    let mut filtered = Vec::new();
+    for row in rows {
+        match filter.evaluate(Some(&row))? {
+            Value::Boolean(true) => filtered.push(row),
+            Value::Boolean(false) | Value::Null => {}
+            value => return errinput!("filter returned {value}, expected boolean"),
+        }
+    }
+    Ok(filtered)
+}
@@ -0,0 +1,99 @@
+use crate::errinput;
+use crate::error::Result;
+use crate::sql::engine::Catalog;
+use crate::sql::types::{Grant, Privileges, Role, User};
+
+/// Creates a user. Errors if a user or role with the same name exists.
+pub fn create_user(catalog: &impl Catalog, user: User) -> Result<()> {
+    if catalog.get_role(&user.name)?.is_some() {
+        return errinput!("role {} already exists", user.name);
+    }
+    catalog.create_user(user)
+}
+
+/// Replaces a user's password hash.
+pub fn alter_user(catalog: &impl Catalog, name: &str, password_hash: String) -> Result<()> {
+    let Some(mut user) = catalog.get_user(name)? else {
+        return errinput!("user {name} does not exist");
+    };
+    user.password_hash = password_hash;
+    catalog.alter_user(user)
+}
+
+/// Creates a role. Errors if a user or role with the same name exists, since
+/// GRANT and REVOKE don't otherwise distinguish between them.
+pub fn create_role(catalog: &impl Catalog, role: Role) -> Result<()> {
+    if catalog.get_user(&role.name)?.is_some() {
+        return errinput!("user {} already exists", role.name);
+    }
+    catalog.create_role(role)
+}
+
+/// Drops a role, and revokes it from all users such that a later role with
+/// the same name isn't implicitly granted to them.
+pub fn drop_role(catalog: &impl Catalog, name: &str) -> Result<()> {
+    catalog.drop_role(name)?;
+    for mut user in catalog.list_users()? {
+        if user.roles.remove(name) {
+            catalog.alter_user(user)?;
+        }
+    }
+    Ok(())
+}
+
+/// Drops a database, and revokes all privileges on it from users and roles
+/// such that a later database with the same name doesn't inherit them. Returns
+/// true if the database existed.
+pub fn drop_database(catalog: &impl Catalog, name: &str, if_exists: bool) -> Result<bool> {
+    if !catalog.drop_database(name, if_exists)? {
+        return Ok(false);
+    }
+    for mut user in catalog.list_users()? {
+        if user.privileges.remove_database(name) {
+            catalog.alter_user(user)?;
+        }
+    }
+    for mut role in catalog.list_roles()? {
+        if role.privileges.remove_database(name) {
+            catalog.alter_role(role)?;
+        }
+    }
+    Ok(true)
+}
+
+/// Grants privileges or a role to the grantee, or revokes them if revoke is
+/// true. Roles can only be granted to users, while privileges can be granted
+/// to both users and roles.
+pub fn grant(catalog: &impl Catalog, grant: Grant, grantee: &str, revoke: bool) -> Result<()> {
+    match grant {
+        Grant::Role(role) => {
+            if catalog.get_role(&role)?.is_none() {
+                return errinput!("role {role} does not exist");
+            }
+            let Some(mut user) = catalog.get_user(grantee)? else {
+                return errinput!("user {grantee} does not exist");
+            };
+            match revoke {
+                true => user.roles.remove(&role),
+                false => user.roles.insert(role),
+            };
+            catalog.alter_user(user)
+        }
+
+        Grant::Privileges { privileges, table } => {
+            let update = |granted: &mut Privileges| match revoke {
+                true => granted.revoke(catalog.database(), table.as_deref(), &privileges),
+                false => granted.grant(catalog.database(), table.as_deref(), &privileges),
+            };
+            if let Some(mut user) = catalog.get_user(grantee)? {
+                update(&mut user.privileges);
+                catalog.alter_user(user)
+            } else if let Some(mut role) = catalog.get_role(grantee)? {
+                update(&mut role.privileges);
+                catalog.alter_role(role)
+            } else {
+                errinput!("user or role {grantee} does not exist")
+            }
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use super::MemoryBudget;
 use crate::error::Result;
 use crate::sql::planner::Aggregate;
 use crate::sql::types::{Expression, Row, Rows, Value};
@@ -7,13 +8,15 @@ use std::collections::BTreeMap;
 
 /// Aggregates row values from the source according to the aggregates, using the
 /// group_by expressions as buckets. Emits rows with group_by buckets then
-/// aggregates in the given order.
+/// aggregates in the given order. Memory for the buckets is reserved from the
+/// budget.
 pub fn aggregate(
     mut source: Rows,
     group_by: Vec<Expression>,
     aggregates: Vec<Aggregate>,
+    memory: &MemoryBudget,
 ) -> Result<Rows> {
-    let mut aggregator = Aggregator::new(group_by, aggregates);
+    let mut aggregator = Aggregator::new(group_by, aggregates, memory.clone());
     while let Some(row) = source.next().transpose()? {
         aggregator.add(row)?;
     }
@@ -30,11 +33,13 @@ struct Aggregator {
     group_by: Vec<Expression>,
     /// Expressions to accumulate. Indexes map to accumulators.
     expressions: Vec<Expression>,
+    /// The memory budget, for new buckets.
+    memory: MemoryBudget,
 }
 
 impl Aggregator {
     /// Creates a new aggregator for the given GROUP BY buckets and aggregates.
-    fn new(group_by: Vec<Expression>, aggregates: Vec<Aggregate>) -> Self {
+    fn new(group_by: Vec<Expression>, aggregates: Vec<Aggregate>, memory: MemoryBudget) -> Self {
         use Aggregate::*;
         let accumulators = aggregates.iter().map(Accumulator::new).collect();
         let expressions = aggregates
@@ -43,7 +48,7 @@ impl Aggregator {
                 Average(expr) | Count(expr) | Max(expr) | Min(expr) | Sum(expr) => expr,
             })
             .collect();
-        Self { buckets: BTreeMap::new(), empty: accumulators, group_by, expressions }
+        Self { buckets: BTreeMap::new(), empty: accumulators, group_by, expressions, memory }
     }
 
     /// Adds a row to the aggregator.
@@ -52,6 +57,12 @@ impl Aggregator {
         let bucket: Vec<Value> =
             self.group_by.iter().map(|expr| expr.evaluate(Some(&row))).try_collect()?;
 
+        // Reserve memory for new buckets, including their accumulators.
+        if !self.buckets.contains_key(&bucket) {
+            let size = std::mem::size_of::<Accumulator>() * self.empty.len();
+            self.memory.reserve(MemoryBudget::row_size(&bucket) + size)?;
+        }
+
         // Compute and accumulate the input values.
         let accumulators = self.buckets.entry(bucket).or_insert_with(|| self.empty.clone());
         for (accumulator, expr) in accumulators.iter_mut().zip(&self.expressions) {
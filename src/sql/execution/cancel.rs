@@ -0,0 +1,121 @@
+use crate::error::{Error, Result};
+use crate::sql::types::Rows;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// A cancellation flag for a running statement. It is shared between the
+/// session executing the statement and whoever may want to cancel it, e.g. a
+/// server thread handling a cancel request from the client.
+///
+/// Executors check the flag as rows are pulled through the plan tree, and
+/// abort with Error::Cancelled once it is set. Statements that don't process
+/// rows (e.g. CREATE TABLE) can't be cancelled, but are typically fast.
+///
+/// The flag can also carry a statement deadline, after which executors abort
+/// with Error::Timeout. The deadline is local to each clone, unlike the flag.
+#[derive(Clone, Debug, Default)]
+pub struct CancelFlag {
+    cancelled: Arc<AtomicBool>,
+    deadline: Option<Instant>,
+}
+
+impl CancelFlag {
+    /// Creates a new, unset cancellation flag.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancels the running statement, if any.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed)
+    }
+
+    /// Returns true if the flag is set.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Clears the flag, e.g. before executing the next statement.
+    pub fn reset(&self) {
+        self.cancelled.store(false, Ordering::Relaxed)
+    }
+
+    /// Returns a clone of the flag with the given statement deadline.
+    pub fn with_deadline(&self, deadline: Instant) -> Self {
+        Self { cancelled: self.cancelled.clone(), deadline: Some(deadline) }
+    }
+
+    /// Returns Error::Cancelled if the flag is set, or Error::Timeout if the
+    /// deadline has passed.
+    pub fn check(&self) -> Result<()> {
+        if self.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+        if self.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Err(Error::Timeout);
+        }
+        Ok(())
+    }
+
+    /// Wraps a row iterator, checking the flag and deadline before pulling
+    /// each row. Once cancelled, it returns a single error and then ends.
+    pub fn wrap(&self, rows: Rows) -> Rows {
+        let flag = self.clone();
+        Box::new(rows.scan(false, move |failed, row| {
+            if *failed {
+                return None;
+            }
+            if let Err(err) = flag.check() {
+                *failed = true;
+                return Some(Err(err));
+            }
+            Some(row)
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::types::Value;
+
+    /// Wrapped rows are returned until the flag is set, after which a single
+    /// cancellation error is returned.
+    #[test]
+    fn wrap() {
+        let flag = CancelFlag::new();
+        let rows: Rows = Box::new((0..10).map(|i| Ok(vec![Value::Integer(i)])));
+        let mut rows = flag.wrap(rows);
+
+        assert_eq!(rows.next(), Some(Ok(vec![Value::Integer(0)])));
+        assert_eq!(rows.next(), Some(Ok(vec![Value::Integer(1)])));
+        flag.cancel();
+        assert_eq!(rows.next(), Some(Err(Error::Cancelled)));
+        assert_eq!(rows.next(), None);
+
+        // Resetting the flag doesn't resume the wrapped iterator, but allows
+        // new statements to run.
+        flag.reset();
+        assert_eq!(rows.next(), None);
+        assert_eq!(flag.check(), Ok(()));
+    }
+
+    /// A passed deadline times out, but only for the clone that carries it.
+    #[test]
+    fn deadline() {
+        let flag = CancelFlag::new();
+        let expired = flag.with_deadline(Instant::now());
+        assert_eq!(expired.check(), Err(Error::Timeout));
+        assert_eq!(flag.check(), Ok(()));
+
+        let future = flag.with_deadline(Instant::now() + std::time::Duration::from_secs(3600));
+        assert_eq!(future.check(), Ok(()));
+
+        // Cancellation takes precedence, and is shared with the clones.
+        flag.cancel();
+        assert_eq!(expired.check(), Err(Error::Cancelled));
+        assert_eq!(future.check(), Err(Error::Cancelled));
+    }
+}
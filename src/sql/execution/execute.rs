@@ -1,10 +1,13 @@
-use super::{aggregate, join, source, transform, write};
+use super::trigger::RowTriggers;
+use super::{access, aggregate, join, source, transform, write, CancelFlag, MemoryBudget};
 use crate::error::Result;
 use crate::sql::engine::{Catalog, Transaction};
-use crate::sql::planner::{Node, Plan};
-use crate::sql::types::{Label, Rows};
+use crate::sql::planner::{Aggregate, Node, Plan};
+use crate::sql::types::{Expression, Label, Rows, Statistics, Value};
 
-/// Executes a plan, returning an execution result.
+/// Executes a plan, returning an execution result. The cancellation flag is
+/// checked as rows are processed, see CancelFlag, and operators that buffer
+/// rows reserve memory from the budget, see MemoryBudget.
 ///
 /// Takes the transaction and catalog separately, even though Transaction must
 /// implement Catalog, to ensure the catalog is primarily used during planning.
@@ -12,8 +15,21 @@ pub fn execute_plan(
     plan: Plan,
     txn: &impl Transaction,
     catalog: &impl Catalog,
+    cancel: &CancelFlag,
+    memory: &MemoryBudget,
 ) -> Result<ExecutionResult> {
     Ok(match plan {
+        Plan::CreateDatabase { database } => {
+            let name = database.name.clone();
+            catalog.create_database(database)?;
+            ExecutionResult::CreateDatabase { name }
+        }
+
+        Plan::DropDatabase { name, if_exists } => {
+            let existed = access::drop_database(catalog, &name, if_exists)?;
+            ExecutionResult::DropDatabase { name, existed }
+        }
+
         Plan::CreateTable { schema } => {
             let name = schema.name.clone();
             catalog.create_table(schema)?;
@@ -25,27 +41,109 @@ pub fn execute_plan(
             ExecutionResult::DropTable { name: table, existed }
         }
 
-        Plan::Delete { table, primary_key, source } => {
-            let source = execute(source, txn)?;
-            let count = write::delete(txn, table, primary_key, source)?;
+        Plan::Analyze { tables } => {
+            let mut names = Vec::with_capacity(tables.len());
+            for table in tables {
+                let (name, columns) = (table.name.clone(), table.columns.len());
+                let scan = Node::Scan { table, alias: None, filter: None };
+                let rows = execute(scan, txn, cancel, memory)?
+                    .map(|r| r.and_then(|row| memory.reserve_row(&row).map(|()| row)));
+                catalog.set_statistics(&name, Statistics::build(columns, rows)?)?;
+                names.push(name);
+            }
+            ExecutionResult::Analyze { tables: names }
+        }
+
+        Plan::CreateUser { user } => {
+            let name = user.name.clone();
+            access::create_user(catalog, user)?;
+            ExecutionResult::CreateUser { name }
+        }
+
+        Plan::AlterUser { name, password_hash } => {
+            access::alter_user(catalog, &name, password_hash)?;
+            ExecutionResult::AlterUser { name }
+        }
+
+        Plan::CreateRole { role } => {
+            let name = role.name.clone();
+            access::create_role(catalog, role)?;
+            ExecutionResult::CreateRole { name }
+        }
+
+        Plan::DropRole { name } => {
+            access::drop_role(catalog, &name)?;
+            ExecutionResult::DropRole { name }
+        }
+
+        Plan::CreateChangefeed { changefeed } => {
+            let name = changefeed.name.clone();
+            catalog.create_changefeed(changefeed)?;
+            ExecutionResult::CreateChangefeed { name }
+        }
+
+        Plan::DropChangefeed { name } => {
+            catalog.drop_changefeed(&name)?;
+            ExecutionResult::DropChangefeed { name }
+        }
+
+        Plan::CreateTrigger { trigger } => {
+            let name = trigger.name.clone();
+            catalog.create_trigger(trigger)?;
+            ExecutionResult::CreateTrigger { name }
+        }
+
+        Plan::DropTrigger { table, name } => {
+            catalog.drop_trigger(&table, &name)?;
+            ExecutionResult::DropTrigger { name }
+        }
+
+        Plan::CreateFunction { function } => {
+            let name = function.name.clone();
+            catalog.create_function(function)?;
+            ExecutionResult::CreateFunction { name }
+        }
+
+        Plan::DropFunction { name } => {
+            catalog.drop_function(&name)?;
+            ExecutionResult::DropFunction { name }
+        }
+
+        Plan::Grant { grant, grantee } => {
+            access::grant(catalog, grant, &grantee, false)?;
+            ExecutionResult::Grant { grantee }
+        }
+
+        Plan::Revoke { grant, grantee } => {
+            access::grant(catalog, grant, &grantee, true)?;
+            ExecutionResult::Revoke { grantee }
+        }
+
+        Plan::Delete { table, primary_key, source, triggers } => {
+            let triggers = RowTriggers::new(&table, triggers, txn, catalog, cancel, memory)?;
+            let source = execute(source, txn, cancel, memory)?;
+            let count = write::delete(txn, table, primary_key, source, &triggers)?;
             ExecutionResult::Delete { count }
         }
 
-        Plan::Insert { table, column_map, source } => {
-            let source = execute(source, txn)?;
-            let count = write::insert(txn, table, column_map, source)?;
+        Plan::Insert { table, column_map, source, triggers } => {
+            let triggers = RowTriggers::new(&table.name, triggers, txn, catalog, cancel, memory)?;
+            let source = execute(source, txn, cancel, memory)?;
+            let count = write::insert(txn, table, column_map, source, &triggers)?;
             ExecutionResult::Insert { count }
         }
 
         Plan::Select(root) => {
             let columns = (0..root.columns()).map(|i| root.column_label(i)).collect();
-            let rows = execute(root, txn)?;
+            let rows = execute(root, txn, cancel, memory)?;
             ExecutionResult::Select { rows, columns }
         }
 
-        Plan::Update { table, primary_key, source, expressions } => {
-            let source = execute(source, txn)?;
-            let count = write::update(txn, table.name, primary_key, source, expressions)?;
+        Plan::Update { table, primary_key, source, expressions, triggers } => {
+            let triggers = RowTriggers::new(&table.name, triggers, txn, catalog, cancel, memory)?;
+            let source = execute(source, txn, cancel, memory)?;
+            let count =
+                write::update(txn, table.name, primary_key, source, expressions, &triggers)?;
             ExecutionResult::Update { count }
         }
     })
@@ -75,77 +173,131 @@ pub fn execute_plan(
 /// joins the rows from the two tables, then the Filter node discards old
 /// movies, the Projection node picks out the requested columns, and the Order
 /// node sorts them before emitting the rows to the client.
-pub fn execute(node: Node, txn: &impl Transaction) -> Result<Rows> {
-    Ok(match node {
+///
+/// Each node's output rows are wrapped to check the cancellation flag, such
+/// that a cancelled statement stops promptly at whichever node is processing
+/// rows, including blocking nodes like Order that buffer their input.
+pub fn execute(
+    node: Node,
+    txn: &impl Transaction,
+    cancel: &CancelFlag,
+    memory: &MemoryBudget,
+) -> Result<Rows> {
+    cancel.check()?;
+    let rows = match node {
+        // COUNT(*) and similar over an unfiltered table scan only need the row
+        // count, which the storage engine can compute from the keys. Tables
+        // with a TTL must read the rows to omit expired ones.
+        Node::Aggregate { source, group_by, aggregates }
+            if group_by.is_empty()
+                && !aggregates.is_empty()
+                && aggregates.iter().all(|aggregate| {
+                    matches!(aggregate, Aggregate::Count(Expression::Constant(value))
+                        if !matches!(value, Value::Null))
+                })
+                && matches!(&*source, Node::Scan { table, filter: None, .. }
+                    if table.ttl.is_none()) =>
+        {
+            let Node::Scan { table, .. } = *source else { unreachable!() };
+            source::count(txn, table, aggregates.len())?
+        }
+
         Node::Aggregate { source, group_by, aggregates } => {
-            let source = execute(*source, txn)?;
-            aggregate::aggregate(source, group_by, aggregates)?
+            let source = execute(*source, txn, cancel, memory)?;
+            aggregate::aggregate(source, group_by, aggregates, memory)?
         }
 
         Node::Filter { source, predicate } => {
-            let source = execute(*source, txn)?;
+            let source = execute(*source, txn, cancel, memory)?;
             transform::filter(source, predicate)
         }
 
         Node::HashJoin { left, left_column, right, right_column, outer } => {
             let right_size = right.columns();
-            let left = execute(*left, txn)?;
-            let right = execute(*right, txn)?;
-            join::hash(left, left_column, right, right_column, right_size, outer)?
+            let left = execute(*left, txn, cancel, memory)?;
+            let right = execute(*right, txn, cancel, memory)?;
+            join::hash(left, left_column, right, right_column, right_size, outer, memory)?
+        }
+
+        Node::FullTextLookup { table, column, query, alias: _ } => {
+            let column = table.columns[column].name.clone();
+            source::lookup_fulltext(txn, table, column, query)?
         }
 
         Node::IndexLookup { table, column, values, alias: _ } => {
-            let column = table.columns.into_iter().nth(column).expect("invalid column").name;
-            let table = table.name;
+            let column = table.columns[column].name.clone();
             source::lookup_index(txn, table, column, values)?
         }
 
-        Node::KeyLookup { table, keys, alias: _ } => source::lookup_key(txn, table.name, keys)?,
+        Node::IndexRange { table, column, range, alias: _ } => {
+            let column = table.columns[column].name.clone();
+            source::lookup_index_range(txn, table, column, range)?
+        }
+
+        Node::KeyLookup { table, keys, alias: _ } => source::lookup_key(txn, table, keys)?,
+
+        Node::KeyRange { table, range, alias: _ } => source::scan_range(txn, table, range)?,
 
         Node::Limit { source, limit } => {
-            let source = execute(*source, txn)?;
+            let source = execute(*source, txn, cancel, memory)?;
             transform::limit(source, limit)
         }
 
         Node::NestedLoopJoin { left, right, predicate, outer } => {
             let right_size = right.columns();
-            let left = execute(*left, txn)?;
-            let right = execute(*right, txn)?;
+            let left = execute(*left, txn, cancel, memory)?;
+            let right = execute(*right, txn, cancel, memory)?;
             join::nested_loop(left, right, right_size, predicate, outer)?
         }
 
         Node::Nothing { .. } => source::nothing(),
 
         Node::Offset { source, offset } => {
-            let source = execute(*source, txn)?;
+            let source = execute(*source, txn, cancel, memory)?;
             transform::offset(source, offset)
         }
 
         Node::Order { source, key: orders } => {
-            let source = execute(*source, txn)?;
-            transform::order(source, orders)?
+            let source = execute(*source, txn, cancel, memory)?;
+            transform::order(source, orders, memory)?
         }
 
         Node::Projection { source, expressions, aliases: _ } => {
-            let source = execute(*source, txn)?;
+            let source = execute(*source, txn, cancel, memory)?;
             transform::project(source, expressions)
         }
 
         Node::Remap { source, targets } => {
-            let source = execute(*source, txn)?;
+            let source = execute(*source, txn, cancel, memory)?;
             transform::remap(source, targets)
         }
 
         Node::Scan { table, filter, alias: _ } => source::scan(txn, table, filter)?,
 
         Node::Values { rows } => source::values(rows),
-    })
+    };
+    Ok(cancel.wrap(rows))
 }
 
 /// A plan execution result.
 pub enum ExecutionResult {
+    CreateDatabase { name: String },
+    DropDatabase { name: String, existed: bool },
     CreateTable { name: String },
     DropTable { name: String, existed: bool },
+    Analyze { tables: Vec<String> },
+    CreateUser { name: String },
+    AlterUser { name: String },
+    CreateRole { name: String },
+    DropRole { name: String },
+    CreateChangefeed { name: String },
+    DropChangefeed { name: String },
+    CreateTrigger { name: String },
+    DropTrigger { name: String },
+    CreateFunction { name: String },
+    DropFunction { name: String },
+    Grant { grantee: String },
+    Revoke { grantee: String },
     Delete { count: u64 },
     Insert { count: u64 },
     Update { count: u64 },
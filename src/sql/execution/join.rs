@@ -1,3 +1,4 @@
+use super::MemoryBudget;
 use crate::errinput;
 use crate::error::Result;
 use crate::sql::types::{Expression, Row, Rows, Value};
@@ -117,7 +118,8 @@ impl Iterator for NestedLoopIterator {
 /// keyed on the join value, then iterates over the left source and looks up
 /// matching rows in the hash table. If outer is true, and there is no match
 /// in the right source for a row in the left source, a row with NULL values
-/// for the right source is emitted instead.
+/// for the right source is emitted instead. Memory for the hash table is
+/// reserved from the budget.
 pub fn hash(
     left: Rows,
     left_column: usize,
@@ -125,6 +127,7 @@ pub fn hash(
     right_column: usize,
     right_size: usize,
     outer: bool,
+    memory: &MemoryBudget,
 ) -> Result<Rows> {
     // Build the hash table from the right source.
     let mut rows = right;
@@ -134,6 +137,7 @@ pub fn hash(
         if value.is_undefined() {
             continue; // NULL and NAN equality is always false
         }
+        memory.reserve_row(&row)?;
         right.entry(value).or_default().push(row);
     }
 
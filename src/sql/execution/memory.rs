@@ -0,0 +1,102 @@
+use crate::errinput;
+use crate::error::Result;
+use crate::sql::types::{Row, Value};
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// A statement's memory budget. Operators that buffer rows in memory (sorts,
+/// aggregates, and hash joins) reserve an estimate of their size here, and the
+/// statement errors if it exceeds the limit instead of exhausting the node's
+/// memory. Rows that stream through operators aren't accounted for.
+///
+/// Reservations are never released, since buffers are typically held until the
+/// statement completes. A new budget is used for each statement.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryBudget {
+    /// The maximum number of bytes, or None if unlimited.
+    limit: Option<usize>,
+    /// The number of bytes reserved so far.
+    used: Arc<AtomicUsize>,
+}
+
+impl MemoryBudget {
+    /// Creates a new memory budget with the given limit in bytes.
+    pub fn new(limit: Option<usize>) -> Self {
+        Self { limit, used: Arc::default() }
+    }
+
+    /// Returns the limit in bytes, if any.
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    /// Returns the number of reserved bytes. Since reservations aren't
+    /// released, this is also the peak memory usage.
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    /// Reserves the given number of bytes, erroring if the budget is exceeded.
+    pub fn reserve(&self, bytes: usize) -> Result<()> {
+        let add = |used: usize| Some(used.saturating_add(bytes));
+        let (Ok(used) | Err(used)) =
+            self.used.fetch_update(Ordering::Relaxed, Ordering::Relaxed, add);
+        let used = used.saturating_add(bytes);
+        match self.limit {
+            Some(limit) if used > limit => {
+                errinput!("statement exceeded the session memory budget of {limit} bytes")
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Reserves memory for a buffered row.
+    pub fn reserve_row(&self, row: &Row) -> Result<()> {
+        self.reserve(Self::row_size(row))
+    }
+
+    /// Estimates the in-memory size of a row, including heap allocations.
+    pub fn row_size(row: &Row) -> usize {
+        std::mem::size_of::<Row>() + row.iter().map(Self::value_size).sum::<usize>()
+    }
+
+    /// Estimates the in-memory size of a value, including heap allocations.
+    fn value_size(value: &Value) -> usize {
+        let heap = match value {
+            Value::String(s) => s.capacity(),
+            Value::Null | Value::Boolean(_) | Value::Integer(_) | Value::Float(_) => 0,
+            Value::Point(_, _) => 0,
+        };
+        std::mem::size_of::<Value>() + heap
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reservations accumulate until the limit is exceeded.
+    #[test]
+    fn reserve() {
+        let budget = MemoryBudget::new(Some(100));
+        assert_eq!(budget.reserve(60), Ok(()));
+        assert_eq!(budget.reserve(40), Ok(()));
+        assert_eq!(budget.used(), 100);
+        assert!(budget.reserve(1).is_err());
+
+        // Clones share the reservations.
+        let unlimited = MemoryBudget::new(None);
+        unlimited.clone().reserve(usize::MAX).expect("reserve failed");
+        unlimited.reserve(1).expect("reserve failed");
+        assert_eq!(unlimited.used(), usize::MAX);
+    }
+
+    /// Row sizes account for string contents.
+    #[test]
+    fn row_size() {
+        let row = vec![Value::Integer(1), Value::Null];
+        let with_string = vec![Value::Integer(1), Value::String("x".repeat(100))];
+        assert_eq!(MemoryBudget::row_size(&with_string), MemoryBudget::row_size(&row) + 100);
+    }
+}
@@ -1,10 +1,17 @@
 //! Executes a `Plan` against a [`crate::sql::engine::Engine`].
 
+mod access;
 mod aggregate;
+mod cancel;
 mod execute;
 mod join;
+mod memory;
 mod source;
 mod transform;
+mod trigger;
 mod write;
 
+pub use cancel::CancelFlag;
 pub use execute::{execute_plan, ExecutionResult};
+pub use memory::MemoryBudget;
+pub use write::purge_expired;
@@ -1,26 +1,79 @@
 use crate::error::Result;
 use crate::sql::engine::Transaction;
-use crate::sql::types::{Expression, Rows, Table, Value};
+use crate::sql::types::{Expression, Rows, Table, Value, ValueRange};
+
+use itertools::Itertools as _;
+use std::time::SystemTime;
 
 /// A table scan source.
 pub fn scan(txn: &impl Transaction, table: Table, filter: Option<Expression>) -> Result<Rows> {
-    Ok(Box::new(txn.scan(&table.name, filter)?))
+    let rows = Box::new(txn.scan(&table.name, filter)?);
+    Ok(unexpired(&table, rows))
+}
+
+/// A table row count source, emitting a single row with the count repeated in
+/// the given number of columns. Doesn't read the rows, nor omit expired ones.
+pub fn count(txn: &impl Transaction, table: Table, columns: usize) -> Result<Rows> {
+    let count = Value::Integer(txn.count(&table.name)? as i64);
+    Ok(Box::new(std::iter::once(Ok(vec![count; columns]))))
 }
 
 /// A primary key lookup source.
-pub fn lookup_key(txn: &impl Transaction, table: String, keys: Vec<Value>) -> Result<Rows> {
-    Ok(Box::new(txn.get(&table, &keys)?.into_iter().map(Ok)))
+pub fn lookup_key(txn: &impl Transaction, table: Table, keys: Vec<Value>) -> Result<Rows> {
+    let rows = Box::new(txn.get(&table.name, &keys)?.into_iter().map(Ok));
+    Ok(unexpired(&table, rows))
 }
 
 /// An index lookup source.
 pub fn lookup_index(
     txn: &impl Transaction,
-    table: String,
+    table: Table,
     column: String,
     values: Vec<Value>,
 ) -> Result<Rows> {
-    let ids: Vec<_> = txn.lookup_index(&table, &column, &values)?.into_iter().collect();
-    Ok(Box::new(txn.get(&table, &ids)?.into_iter().map(Ok)))
+    let ids: Vec<_> = txn.lookup_index(&table.name, &column, &values)?.into_iter().collect();
+    let rows = Box::new(txn.get(&table.name, &ids)?.into_iter().map(Ok));
+    Ok(unexpired(&table, rows))
+}
+
+/// A primary key range scan source.
+pub fn scan_range(txn: &impl Transaction, table: Table, range: ValueRange) -> Result<Rows> {
+    let rows = Box::new(txn.scan_range(&table.name, range)?);
+    Ok(unexpired(&table, rows))
+}
+
+/// An index range scan source. Rows are emitted in index order.
+pub fn lookup_index_range(
+    txn: &impl Transaction,
+    table: Table,
+    column: String,
+    range: ValueRange,
+) -> Result<Rows> {
+    let ids = txn.lookup_index_range(&table.name, &column, range)?;
+    let rows = Box::new(txn.get(&table.name, &ids)?.into_iter().map(Ok));
+    Ok(unexpired(&table, rows))
+}
+
+/// A full-text index lookup source.
+pub fn lookup_fulltext(
+    txn: &impl Transaction,
+    table: Table,
+    column: String,
+    query: String,
+) -> Result<Rows> {
+    let ids: Vec<_> = txn.lookup_fulltext(&table.name, &column, &query)?.into_iter().collect();
+    let rows = Box::new(txn.get(&table.name, &ids)?.into_iter().map(Ok));
+    Ok(unexpired(&table, rows))
+}
+
+/// Omits expired rows if the table has a TTL, using the current time. They
+/// remain in storage until purged by write::purge_expired().
+fn unexpired(table: &Table, rows: Rows) -> Rows {
+    let Some(ttl) = table.ttl.clone() else {
+        return rows;
+    };
+    let now = SystemTime::now();
+    Box::new(rows.filter_ok(move |row| !ttl.is_expired(row, now)))
 }
 
 /// Returns nothing. Used to short-circuit nodes that can't produce any rows.
@@ -1,3 +1,4 @@
+use super::MemoryBudget;
 use crate::errinput;
 use crate::error::Result;
 use crate::sql::planner::Direction;
@@ -28,13 +29,21 @@ pub fn offset(source: Rows, offset: usize) -> Rows {
     Box::new(source.skip(offset))
 }
 
-/// Sorts the rows (i.e. ORDER BY).
-pub fn order(source: Rows, order: Vec<(Expression, Direction)>) -> Result<Rows> {
+/// Sorts the rows (i.e. ORDER BY). Buffers all rows, reserving memory for them
+/// from the budget.
+pub fn order(
+    source: Rows,
+    order: Vec<(Expression, Direction)>,
+    memory: &MemoryBudget,
+) -> Result<Rows> {
     // We can't use sort_by_cached_key(), since expression evaluation is
     // fallible, and since we may have to vary the sort direction of each
     // expression. Precompute the sort values instead, and map them based on
     // the row index.
-    let mut irows: Vec<_> = source.enumerate().map(|(i, r)| r.map(|row| (i, row))).try_collect()?;
+    let mut irows: Vec<_> = source
+        .enumerate()
+        .map(|(i, r)| r.and_then(|row| memory.reserve_row(&row).map(|()| (i, row))))
+        .try_collect()?;
     let mut sort_values = Vec::with_capacity(irows.len());
     for (_, row) in &irows {
         let values: Vec<_> = order.iter().map(|(e, _)| e.evaluate(Some(row))).try_collect()?;
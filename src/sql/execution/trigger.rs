@@ -0,0 +1,65 @@
+use super::{execute_plan, CancelFlag, MemoryBudget};
+use crate::error::Result;
+use crate::sql::engine::{Catalog, Transaction};
+use crate::sql::planner::Plan;
+use crate::sql::types::{Row, Table, Trigger, TriggerTiming};
+
+/// Fires a write statement's row triggers. Each trigger's action is bound to
+/// the written row, then planned and executed in the statement's transaction.
+/// Actions don't fire triggers themselves, which prevents infinite recursion
+/// (e.g. a trigger inserting into its own table).
+pub struct RowTriggers<'a, T: Transaction, C: Catalog> {
+    /// The triggers to fire.
+    triggers: Vec<Trigger>,
+    /// The written table's schema, or None if there are no triggers.
+    table: Option<Table>,
+    txn: &'a T,
+    catalog: &'a C,
+    cancel: &'a CancelFlag,
+    memory: &'a MemoryBudget,
+}
+
+impl<'a, T: Transaction, C: Catalog> RowTriggers<'a, T, C> {
+    /// Creates a new set of row triggers for the given table. Only fetches the
+    /// table schema if there are any triggers.
+    pub fn new(
+        table: &str,
+        triggers: Vec<Trigger>,
+        txn: &'a T,
+        catalog: &'a C,
+        cancel: &'a CancelFlag,
+        memory: &'a MemoryBudget,
+    ) -> Result<Self> {
+        let table = match triggers.is_empty() {
+            true => None,
+            false => Some(catalog.must_get_table(table)?),
+        };
+        Ok(Self { triggers, table, txn, catalog, cancel, memory })
+    }
+
+    /// Returns true if there are no triggers to fire.
+    pub fn is_empty(&self) -> bool {
+        self.triggers.is_empty()
+    }
+
+    /// Fires the triggers with the given timing for a written row, in name
+    /// order. old is the row before the write (UPDATE and DELETE), and new is
+    /// the row after the write (INSERT and UPDATE).
+    pub fn fire(&self, timing: TriggerTiming, old: Option<&Row>, new: Option<&Row>) -> Result<()> {
+        let Some(table) = &self.table else {
+            return Ok(());
+        };
+        for trigger in self.triggers.iter().filter(|trigger| trigger.timing == timing) {
+            let statement = trigger.bind(table, old, new)?;
+            let mut plan = Plan::build(statement, self.catalog)?.optimize()?;
+            if let Plan::Delete { triggers, .. }
+            | Plan::Insert { triggers, .. }
+            | Plan::Update { triggers, .. } = &mut plan
+            {
+                triggers.clear();
+            }
+            execute_plan(plan, self.txn, self.catalog, self.cancel, self.memory)?;
+        }
+        Ok(())
+    }
+}
@@ -1,24 +1,44 @@
+use super::trigger::RowTriggers;
 use crate::errinput;
-use crate::error::Result;
-use crate::sql::engine::Transaction;
-use crate::sql::types::{Expression, Rows, Table, Value};
+use crate::error::{Error, Result};
+use crate::sql::engine::{Catalog, Transaction};
+use crate::sql::types::{Database, Expression, Row, Rows, Table, TriggerTiming, Ttl, Value};
 
 use itertools::Itertools as _;
 use std::collections::{BTreeMap, HashMap};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 /// Deletes rows, taking primary keys from the source (i.e. DELETE) using the
 /// primary_key column index. Returns the number of rows deleted.
-pub fn delete(
-    txn: &impl Transaction,
+///
+/// Triggers fire before and after all rows are deleted, with the deleted rows
+/// as OLD. The source rows must be complete table rows if there are triggers.
+pub fn delete<T: Transaction, C: Catalog>(
+    txn: &T,
     table: String,
     primary_key: usize,
     source: Rows,
+    triggers: &RowTriggers<T, C>,
 ) -> Result<u64> {
-    let ids: Vec<Value> =
-        source.map_ok(|row| row.into_iter().nth(primary_key).expect("short row")).try_collect()?;
-    let count = ids.len() as u64;
+    if triggers.is_empty() {
+        let ids: Vec<Value> = source
+            .map_ok(|row| row.into_iter().nth(primary_key).expect("short row"))
+            .try_collect()?;
+        let count = ids.len() as u64;
+        txn.delete(&table, &ids)?;
+        return Ok(count);
+    }
+
+    let rows: Vec<Row> = source.try_collect()?;
+    for row in &rows {
+        triggers.fire(TriggerTiming::Before, Some(row), None)?;
+    }
+    let ids = rows.iter().map(|row| row[primary_key].clone()).collect_vec();
     txn.delete(&table, &ids)?;
-    Ok(count)
+    for row in &rows {
+        triggers.fire(TriggerTiming::After, Some(row), None)?;
+    }
+    Ok(rows.len() as u64)
 }
 
 /// Inserts rows into a table (i.e. INSERT) from the given source.
@@ -27,11 +47,15 @@ pub fn delete(
 /// columns in source. Otherwise, every column in source is the corresponding
 /// column in table, but the source may not have all columns in table (there may
 /// be a missing tail).
-pub fn insert(
-    txn: &impl Transaction,
+///
+/// Triggers fire before and after all rows are inserted, with the complete
+/// inserted rows (including defaults) as NEW.
+pub fn insert<T: Transaction, C: Catalog>(
+    txn: &T,
     table: Table,
     column_map: Option<HashMap<usize, usize>>,
     mut source: Rows,
+    triggers: &RowTriggers<T, C>,
 ) -> Result<u64> {
     let mut rows = Vec::new();
     while let Some(values) = source.next().transpose()? {
@@ -68,29 +92,117 @@ pub fn insert(
         rows.push(row);
     }
     let count = rows.len() as u64;
-    txn.insert(&table.name, rows)?;
+    if triggers.is_empty() {
+        txn.insert(&table.name, rows)?;
+        return Ok(count);
+    }
+
+    for row in &rows {
+        triggers.fire(TriggerTiming::Before, None, Some(row))?;
+    }
+    txn.insert(&table.name, rows.clone())?;
+    for row in &rows {
+        triggers.fire(TriggerTiming::After, None, Some(row))?;
+    }
     Ok(count)
 }
 
 /// Updates rows passed in from the source (i.e. UPDATE). Returns the number of
 /// rows updated.
-pub fn update(
-    txn: &impl Transaction,
+///
+/// Triggers fire before and after all rows are updated, with the existing rows
+/// as OLD and the updated rows as NEW.
+pub fn update<T: Transaction, C: Catalog>(
+    txn: &T,
     table: String,
     primary_key: usize,
     mut source: Rows,
     expressions: Vec<(usize, Expression)>,
+    triggers: &RowTriggers<T, C>,
 ) -> Result<u64> {
     let mut updates = BTreeMap::new();
+    let mut olds = Vec::new();
     while let Some(row) = source.next().transpose()? {
         let mut update = row.clone();
         for (column, expr) in &expressions {
             update[*column] = expr.evaluate(Some(&row))?;
         }
-        let id = row.into_iter().nth(primary_key).expect("short row");
+        let id = row[primary_key].clone();
+        if !triggers.is_empty() {
+            olds.push(row);
+        }
         updates.insert(id, update);
     }
     let count = updates.len() as u64;
-    txn.update(&table, updates)?;
+    if triggers.is_empty() {
+        txn.update(&table, updates)?;
+        return Ok(count);
+    }
+
+    for old in &olds {
+        triggers.fire(TriggerTiming::Before, Some(old), Some(&updates[&old[primary_key]]))?;
+    }
+    txn.update(&table, updates.clone())?;
+    for old in &olds {
+        triggers.fire(TriggerTiming::After, Some(old), Some(&updates[&old[primary_key]]))?;
+    }
     Ok(count)
 }
+
+/// Deletes up to limit expired rows from tables with a TTL, in all databases,
+/// as of the given time. Returns the number of rows deleted. Triggers don't
+/// fire for purged rows.
+///
+/// Expired rows that are still referenced by foreign keys can't be deleted.
+/// They're skipped, and purged once the references are removed.
+pub fn purge_expired(
+    txn: &mut (impl Transaction + Catalog),
+    now: SystemTime,
+    limit: usize,
+) -> Result<u64> {
+    let database = txn.database().to_string();
+    let mut count = 0;
+    'databases: for Database { name } in txn.list_databases()? {
+        txn.use_database(&name);
+        for table in txn.list_tables()? {
+            let Some(ttl) = &table.ttl else {
+                continue;
+            };
+            let ids: Vec<Value> = txn
+                .scan(&table.name, Some(expired_filter(ttl, now)))?
+                .filter_ok(|row| ttl.is_expired(row, now))
+                .map_ok(|row| row.into_iter().nth(table.primary_key).expect("short row"))
+                .take(limit - count)
+                .try_collect()?;
+            // If the batch delete fails, some row is likely referenced by a
+            // foreign key. Delete the rows one by one, skipping those.
+            if txn.delete(&table.name, &ids).is_ok() {
+                count += ids.len();
+            } else {
+                for id in &ids {
+                    match txn.delete(&table.name, std::slice::from_ref(id)) {
+                        Ok(()) => count += 1,
+                        Err(Error::InvalidInput(_)) => {}
+                        Err(err) => return Err(err),
+                    }
+                }
+            }
+            if count >= limit {
+                break 'databases;
+            }
+        }
+    }
+    txn.use_database(&database);
+    Ok(count as u64)
+}
+
+/// Returns a scan filter for rows that have expired at the given time, i.e.
+/// time * 1000 + ttl <= now in milliseconds.
+fn expired_filter(ttl: &Ttl, now: SystemTime) -> Expression {
+    let now = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as i64;
+    let cutoff = now.saturating_sub(ttl.duration.as_millis() as i64).div_euclid(1000);
+    Expression::LessThan(
+        Expression::Column(ttl.column).into(),
+        Expression::Constant(Value::Integer(cutoff.saturating_add(1))).into(),
+    )
+}
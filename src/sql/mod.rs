@@ -75,11 +75,12 @@ pub mod types;
 /// SQL tests are implemented as goldenscripts under src/sql/testscripts.
 #[cfg(test)]
 mod tests {
-    use super::engine::{Catalog as _, Session};
+    use super::engine::{Catalog as _, Session, Transaction as _};
+    use super::execution::purge_expired;
     use super::parser::Parser;
     use super::planner::{Plan, OPTIMIZERS};
     use crate::encoding::format::{self, Formatter as _};
-    use crate::sql::engine::{Engine, Local, StatementResult};
+    use crate::sql::engine::{Engine, Local, QueryCache, StatementResult};
     use crate::sql::planner::{Planner, Scope};
     use crate::storage::engine::test as testengine;
     use crate::storage::{self, Engine as _};
@@ -90,6 +91,8 @@ mod tests {
     use std::error::Error;
     use std::fmt::Write as _;
     use std::result::Result;
+    use std::sync::Arc;
+    use std::time::SystemTime;
     use test_each_file::test_each_path;
 
     // Run goldenscript tests in src/sql/testscripts.
@@ -97,6 +100,7 @@ mod tests {
     test_each_path! { in "src/sql/testscripts/optimizers" as optimizers => test_goldenscript }
     test_each_path! { in "src/sql/testscripts/queries" as queries => test_goldenscript }
     test_each_path! { in "src/sql/testscripts/schema" as schema => test_goldenscript }
+    test_each_path! { in "src/sql/testscripts/session" as session => test_goldenscript }
     test_each_path! { in "src/sql/testscripts/transactions" as transactions => test_goldenscript }
     test_each_path! { in "src/sql/testscripts/writes" as writes => test_goldenscript }
 
@@ -127,6 +131,7 @@ mod tests {
         engine: &'a TestEngine,
         sessions: HashMap<String, Session<'a, TestEngine>>,
         op_rx: Receiver<testengine::Operation>,
+        query_cache: Option<Arc<QueryCache>>,
     }
 
     type TestEngine =
@@ -134,7 +139,7 @@ mod tests {
 
     impl<'a> SQLRunner<'a> {
         fn new(engine: &'a TestEngine, op_rx: Receiver<testengine::Operation>) -> Self {
-            Self { engine, sessions: HashMap::new(), op_rx }
+            Self { engine, sessions: HashMap::new(), op_rx, query_cache: None }
         }
     }
 
@@ -142,16 +147,39 @@ mod tests {
         fn run(&mut self, command: &goldenscript::Command) -> Result<String, Box<dyn Error>> {
             let mut output = String::new();
 
+            // query_cache [CAPACITY]
+            //
+            // Enables a query cache with the given capacity for all sessions,
+            // and outputs the cache status.
+            if command.name == "query_cache" {
+                let mut args = command.consume_args();
+                let capacity = args.next_pos().map(|arg| arg.parse()).transpose()?;
+                args.reject_rest()?;
+                if let Some(capacity) = capacity {
+                    self.query_cache = Some(Arc::new(QueryCache::new(capacity)));
+                    for session in self.sessions.values_mut() {
+                        session.set_query_cache(self.query_cache.clone());
+                    }
+                }
+                let cache = self.query_cache.as_ref().ok_or("query cache not enabled")?;
+                writeln!(output, "{:?}", cache.status()?)?;
+                return Ok(output);
+            }
+
             // Obtain a session based on the command prefix ("" if none).
             let prefix = command.prefix.clone().unwrap_or_default();
-            let session = self.sessions.entry(prefix).or_insert_with(|| self.engine.session());
+            let session = self.sessions.entry(prefix).or_insert_with(|| {
+                let mut session = self.engine.session();
+                session.set_query_cache(self.query_cache.clone());
+                session
+            });
 
             // Handle runner commands.
             match command.name.as_str() {
                 // dump
                 "dump" => {
                     command.consume_args().reject_rest()?;
-                    let mut engine = self.engine.mvcc.engine.lock().expect("mutex failed");
+                    let mut engine = self.engine.mvcc.engine.write().expect("lock failed");
                     let mut iter = engine.scan(..);
                     while let Some((key, value)) = iter.next().transpose()? {
                         let fmtkv = format::MVCC::<format::SQL>::key_value(&key, &value);
@@ -178,6 +206,126 @@ mod tests {
                     return Ok(schemas.into_iter().join("\n"));
                 }
 
+                // authenticate USER PASSWORD
+                "authenticate" => {
+                    let mut args = command.consume_args();
+                    let user = &args.next_pos().ok_or("user not given")?.value;
+                    let password = &args.next_pos().ok_or("password not given")?.value;
+                    args.reject_rest()?;
+                    session.authenticate(user, password)?;
+                    return Ok(output);
+                }
+
+                // users
+                "users" => {
+                    command.consume_args().reject_rest()?;
+                    let users = session.with_txn(true, |txn| txn.list_users())?;
+                    return Ok(users.into_iter().map(|user| user.name).join("\n"));
+                }
+
+                // memory_budget [BYTES]
+                "memory_budget" => {
+                    let mut args = command.consume_args();
+                    let bytes = args.next_pos().map(|arg| arg.parse()).transpose()?;
+                    args.reject_rest()?;
+                    session.set_memory_budget(bytes);
+                    return Ok(output);
+                }
+
+                // prepare STATEMENT
+                //
+                // Prepares a statement, and outputs its ID and parameter count.
+                "prepare" => {
+                    let mut args = command.consume_args();
+                    let statement = &args.next_pos().ok_or("statement not given")?.value;
+                    args.reject_rest()?;
+                    let (id, parameters) = session.prepare(statement)?;
+                    writeln!(output, "prepared {id} with {parameters} parameters")?;
+                    return Ok(output);
+                }
+
+                // execute ID [PARAM...]
+                //
+                // Executes a prepared statement with the given parameter
+                // values, as SQL constant expressions. Outputs the rows of
+                // SELECT queries, and the result of other statements.
+                "execute" => {
+                    let mut args = command.consume_args();
+                    let id = args.next_pos().ok_or("prepared statement ID not given")?.parse()?;
+                    let params = args
+                        .rest_pos()
+                        .into_iter()
+                        .map(|arg| {
+                            let ast = Parser::new(&arg.value).parse_expression()?;
+                            Planner::<Catalog>::build_expression(ast, &Scope::new())?.evaluate(None)
+                        })
+                        .try_collect()?;
+                    args.reject_rest()?;
+                    let result = session.execute_prepared(id, params)?;
+                    match result {
+                        StatementResult::Select { rows, .. } => {
+                            for row in rows {
+                                writeln!(output, "{}", row.into_iter().join(", "))?;
+                            }
+                        }
+                        result => writeln!(output, "{result:?}")?,
+                    }
+                    return Ok(output);
+                }
+
+                // changefeeds
+                "changefeeds" => {
+                    command.consume_args().reject_rest()?;
+                    let changefeeds = session.with_txn(true, |txn| txn.list_changefeeds())?;
+                    return Ok(changefeeds.into_iter().join("\n"));
+                }
+
+                // functions
+                "functions" => {
+                    command.consume_args().reject_rest()?;
+                    let functions = session.with_txn(true, |txn| txn.list_functions())?;
+                    return Ok(functions.into_iter().join("\n"));
+                }
+
+                // triggers TABLE
+                "triggers" => {
+                    let mut args = command.consume_args();
+                    let table = &args.next_pos().ok_or("table not given")?.value;
+                    args.reject_rest()?;
+                    let triggers = session.with_txn(true, |txn| txn.list_triggers(table))?;
+                    return Ok(triggers.into_iter().join("\n"));
+                }
+
+                // databases
+                "databases" => {
+                    command.consume_args().reject_rest()?;
+                    let databases = session.with_txn(true, |txn| txn.list_databases())?;
+                    return Ok(databases.into_iter().map(|db| db.name).join("\n"));
+                }
+
+                // purge [LIMIT]
+                //
+                // Purges up to LIMIT expired rows from tables with a TTL, in
+                // all databases, and outputs the number of purged rows.
+                "purge" => {
+                    let mut args = command.consume_args();
+                    let limit = args.next_pos().map(|arg| arg.parse()).transpose()?;
+                    args.reject_rest()?;
+                    let mut txn = self.engine.begin()?;
+                    let limit = limit.unwrap_or(usize::MAX);
+                    let count = purge_expired(&mut txn, SystemTime::now(), limit)?;
+                    txn.commit()?;
+                    writeln!(output, "purged {count} rows")?;
+                    return Ok(output);
+                }
+
+                // roles
+                "roles" => {
+                    command.consume_args().reject_rest()?;
+                    let roles = session.with_txn(true, |txn| txn.list_roles())?;
+                    return Ok(roles.into_iter().map(|role| role.name).join("\n"));
+                }
+
                 // Otherwise, fall through to SQL execution.
                 _ => {}
             }
@@ -249,6 +397,9 @@ mod tests {
                         writeln!(output, "{}", row.into_iter().join(", "))?;
                     }
                 }
+                StatementResult::Explain(plan, format, analysis) => {
+                    writeln!(output, "{}", plan.explain(format, analysis.as_ref()))?
+                }
                 result if tags.remove("result") => writeln!(output, "{result:?}")?,
                 _ => {}
             }
@@ -288,7 +439,7 @@ mod tests {
             // Parse and build the expression.
             let mut parser = Parser::new(input);
             let ast = parser.parse_expression()?;
-            if let Some(next) = parser.lexer.next().transpose()? {
+            if let Some(next) = parser.next_token()? {
                 return Err(format!("unconsumed token {next}").into());
             }
             let expr = Planner::<Catalog>::build_expression(ast, &Scope::new())?;
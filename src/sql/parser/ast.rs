@@ -1,12 +1,16 @@
-use crate::sql::types::DataType;
+use crate::errinput;
+use crate::error::Result;
+use crate::sql::types::{DataType, Grant, TriggerEvent, TriggerTiming};
 
+use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
+use std::time::Duration;
 
 /// The statement is the root node of the Abstract Syntax Tree, and describes
 /// the syntactic structure of a SQL query. It is built from a raw SQL string by
 /// the parser, and passed on to the planner which validates it and builds an
 /// execution plan from it.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum Statement {
     /// Begin a new transaction.
     Begin { read_only: bool, as_of: Option<u64> },
@@ -14,12 +18,72 @@ pub enum Statement {
     Commit,
     /// Roll back a transaction.
     Rollback,
-    /// Explain a statement.
-    Explain(Box<Statement>),
-    /// Create a new table.
-    CreateTable { name: String, columns: Vec<Column> },
+    /// Create a savepoint in a transaction.
+    Savepoint { name: String },
+    /// Roll back a transaction to a savepoint.
+    RollbackToSavepoint { name: String },
+    /// Release a savepoint in a transaction.
+    ReleaseSavepoint { name: String },
+    /// Explain a statement, in the given output format. With analyze, the
+    /// statement is also executed, and execution statistics are output.
+    Explain { statement: Box<Statement>, format: ExplainFormat, analyze: bool },
+    /// Set a session setting, or reset it to its default if None.
+    Set { name: String, value: Option<Literal> },
+    /// Declare a cursor for a SELECT query.
+    Declare { name: String, query: Box<Statement> },
+    /// Fetch the given number of rows from a cursor, or all rows if None.
+    Fetch { name: String, count: Option<u64> },
+    /// Close a cursor.
+    Close { name: String },
+    /// Switch the session to a different database.
+    Use { name: String },
+    /// Collect statistics for the given table, or all tables if None.
+    Analyze { table: Option<String> },
+    /// Create a new database.
+    CreateDatabase { name: String },
+    /// Drop a database.
+    DropDatabase { name: String, if_exists: bool },
+    /// Create a new table, with WITH (name = value, ...) table options.
+    CreateTable { name: String, columns: Vec<Column>, options: Vec<(String, Expression)> },
     /// Drop a table.
     DropTable { name: String, if_exists: bool },
+    /// Create a secondary index on a table column, built online.
+    CreateIndex { table: String, column: String },
+    /// Create a new user with the given plaintext password.
+    CreateUser { name: String, password: String, superuser: bool },
+    /// Change a user's password.
+    AlterUser { name: String, password: String },
+    /// Create a new role.
+    CreateRole { name: String },
+    /// Drop a role.
+    DropRole { name: String },
+    /// Create a changefeed emitting changes in the given tables to a sink.
+    CreateChangefeed { name: String, tables: Vec<String>, sink: String },
+    /// Drop a changefeed.
+    DropChangefeed { name: String },
+    /// Create a row trigger on a table, with the action statement as raw SQL.
+    CreateTrigger {
+        name: String,
+        table: String,
+        timing: TriggerTiming,
+        event: TriggerEvent,
+        action: String,
+    },
+    /// Drop a trigger from a table.
+    DropTrigger { name: String, table: String },
+    /// Create a scalar function, with the body expression as raw SQL.
+    CreateFunction {
+        name: String,
+        parameters: Vec<(String, DataType)>,
+        returns: DataType,
+        body: String,
+    },
+    /// Drop a function.
+    DropFunction { name: String },
+    /// Grant privileges or a role to a user or role.
+    Grant { grant: Grant, grantee: String },
+    /// Revoke privileges or a role from a user or role.
+    Revoke { grant: Grant, grantee: String },
     /// Delete matching rows.
     Delete { table: String, r#where: Option<Expression> },
     /// Insert new rows into a table.
@@ -34,10 +98,12 @@ pub enum Statement {
         set: BTreeMap<String, Option<Expression>>, // column → value, None for default value
         r#where: Option<Expression>,
     },
-    /// Select matching rows.
+    /// Select matching rows, optionally in a historical version of the
+    /// database given by AS OF SYSTEM TIME.
     Select {
         select: Vec<(Expression, Option<String>)>, // optional column aliases
         from: Vec<From>,
+        as_of: Option<u64>,
         r#where: Option<Expression>,
         group_by: Vec<Expression>,
         having: Option<Expression>,
@@ -48,7 +114,7 @@ pub enum Statement {
 }
 
 /// A FROM item.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum From {
     /// A table.
     Table { name: String, alias: Option<String> },
@@ -57,7 +123,7 @@ pub enum From {
 }
 
 /// A CREATE TABLE column definition.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Column {
     pub name: String,
     pub datatype: DataType,
@@ -67,10 +133,11 @@ pub struct Column {
     pub unique: bool,
     pub index: bool,
     pub references: Option<String>,
+    pub fulltext: bool,
 }
 
 /// JOIN types.
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum JoinType {
     Cross,
     Inner,
@@ -89,8 +156,17 @@ impl JoinType {
     }
 }
 
+/// EXPLAIN output formats.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ExplainFormat {
+    /// An indented plan tree. The default.
+    Text,
+    /// A Graphviz DOT graph, for visualizing the plan.
+    Dot,
+}
+
 /// ORDER BY direction.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum Direction {
     Ascending,
     Descending,
@@ -109,6 +185,12 @@ pub enum Expression {
     Function(String, Vec<Expression>),
     /// An operator.
     Operator(Operator),
+    /// A positional statement parameter, i.e. ?, with its 0-based index. It
+    /// must be bound to a value before planning, see Statement::bind().
+    Parameter(usize),
+    /// A row value, i.e. (a, b). Comparisons of row values are expanded into
+    /// comparisons of their elements during parsing, and other uses error.
+    Tuple(Vec<Expression>),
 }
 
 /// Expression literal values.
@@ -119,6 +201,9 @@ pub enum Literal {
     Integer(i64),
     Float(f64),
     String(String),
+    /// A point. There's no point literal syntax, but POINT(x, y) function
+    /// calls yield point values, and these can be bound as parameters.
+    Point(f64, f64),
 }
 
 /// To allow using Expressions and Literals in e.g. hashmaps, implement simple
@@ -133,6 +218,9 @@ impl PartialEq for Literal {
             // Implies NaN == NaN but -NaN != NaN. Similarly with +/-0.0.
             (Self::Float(l), Self::Float(r)) => l.to_bits() == r.to_bits(),
             (Self::String(l), Self::String(r)) => l == r,
+            (Self::Point(lx, ly), Self::Point(rx, ry)) => {
+                lx.to_bits() == rx.to_bits() && ly.to_bits() == ry.to_bits()
+            }
             (l, r) => core::mem::discriminant(l) == core::mem::discriminant(r),
         }
     }
@@ -150,10 +238,73 @@ impl std::hash::Hash for Literal {
             Self::Integer(v) => v.hash(state),
             Self::Float(v) => v.to_bits().hash(state),
             Self::String(v) => v.hash(state),
+            Self::Point(x, y) => {
+                x.to_bits().hash(state);
+                y.to_bits().hash(state);
+            }
         }
     }
 }
 
+impl Literal {
+    /// Converts the literal to a duration. Integers are given in milliseconds,
+    /// and strings as a number with an optional unit, e.g. '5s'.
+    pub fn to_duration(&self) -> Result<Duration> {
+        let (number, unit) = match self {
+            Self::Integer(ms) => (*ms, "ms"),
+            Self::String(s) => {
+                let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+                let (number, unit) = s.split_at(split);
+                let Ok(number) = number.parse() else {
+                    return errinput!("invalid duration {s}");
+                };
+                (number, unit.trim())
+            }
+            _ => return errinput!("duration must be an integer or string"),
+        };
+        let ms: u64 = match unit {
+            "" | "ms" => 1,
+            "s" => 1_000,
+            "min" => 60_000,
+            "h" => 3_600_000,
+            "d" => 86_400_000,
+            unit => return errinput!("invalid duration unit {unit}, expected ms, s, min, h, or d"),
+        };
+        let Ok(number) = u64::try_from(number) else {
+            return errinput!("duration can't be negative");
+        };
+        Ok(Duration::from_millis(number.saturating_mul(ms)))
+    }
+
+    /// Converts the literal to a number of bytes. Integers are given in bytes,
+    /// and strings as a number with an optional unit, e.g. '4MB'.
+    pub fn to_bytes(&self) -> Result<usize> {
+        let (number, unit) = match self {
+            Self::Integer(bytes) => (*bytes, "B"),
+            Self::String(s) => {
+                let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+                let (number, unit) = s.split_at(split);
+                let Ok(number) = number.parse() else {
+                    return errinput!("invalid size {s}");
+                };
+                (number, unit.trim())
+            }
+            _ => return errinput!("size must be an integer or string"),
+        };
+        let bytes: usize = match unit {
+            "" | "B" => 1,
+            "kB" => 1 << 10,
+            "MB" => 1 << 20,
+            "GB" => 1 << 30,
+            unit => return errinput!("invalid size unit {unit}, expected B, kB, MB, or GB"),
+        };
+        let Ok(number) = usize::try_from(number) else {
+            return errinput!("size can't be negative");
+        };
+        Ok(number.saturating_mul(bytes))
+    }
+}
+
 /// Expression operators.
 ///
 /// Since this is a recursive data structure, we have to box each child
@@ -183,7 +334,8 @@ pub enum Operator {
     Remainder(Box<Expression>, Box<Expression>),    // a % b
     Subtract(Box<Expression>, Box<Expression>),     // a - b
 
-    Like(Box<Expression>, Box<Expression>), // a LIKE b
+    Like(Box<Expression>, Box<Expression>),  // a LIKE b
+    Match(Box<Expression>, Box<Expression>), // a MATCH b, a @@ b
 }
 
 impl Expression {
@@ -206,6 +358,7 @@ impl Expression {
             | Self::Operator(LessThan(lhs, rhs))
             | Self::Operator(LessThanOrEqual(lhs, rhs))
             | Self::Operator(Like(lhs, rhs))
+            | Self::Operator(Match(lhs, rhs))
             | Self::Operator(Multiply(lhs, rhs))
             | Self::Operator(NotEqual(lhs, rhs))
             | Self::Operator(Or(lhs, rhs))
@@ -218,10 +371,54 @@ impl Expression {
             | Self::Operator(Negate(expr))
             | Self::Operator(Not(expr)) => expr.walk(visitor),
 
-            Self::Function(_, exprs) => exprs.iter().any(|expr| expr.walk(visitor)),
+            Self::Function(_, exprs) | Self::Tuple(exprs) => {
+                exprs.iter().any(|expr| expr.walk(visitor))
+            }
+
+            Self::All | Self::Column(_, _) | Self::Literal(_) | Self::Parameter(_) => true,
+        }
+    }
+
+    // noinspection DuplicatedCode
+    /// Walks the expression tree depth-first, calling a closure for every node
+    /// which may modify it in place. Children are visited before their parent,
+    /// so a node replaced by the closure isn't visited again.
+    pub fn visit_mut(&mut self, visitor: &mut impl FnMut(&mut Expression)) {
+        use Operator::*;
+        match self {
+            Self::Operator(Add(lhs, rhs))
+            | Self::Operator(And(lhs, rhs))
+            | Self::Operator(Divide(lhs, rhs))
+            | Self::Operator(Equal(lhs, rhs))
+            | Self::Operator(Exponentiate(lhs, rhs))
+            | Self::Operator(GreaterThan(lhs, rhs))
+            | Self::Operator(GreaterThanOrEqual(lhs, rhs))
+            | Self::Operator(LessThan(lhs, rhs))
+            | Self::Operator(LessThanOrEqual(lhs, rhs))
+            | Self::Operator(Like(lhs, rhs))
+            | Self::Operator(Match(lhs, rhs))
+            | Self::Operator(Multiply(lhs, rhs))
+            | Self::Operator(NotEqual(lhs, rhs))
+            | Self::Operator(Or(lhs, rhs))
+            | Self::Operator(Remainder(lhs, rhs))
+            | Self::Operator(Subtract(lhs, rhs)) => {
+                lhs.visit_mut(visitor);
+                rhs.visit_mut(visitor);
+            }
+
+            Self::Operator(Factorial(expr))
+            | Self::Operator(Identity(expr))
+            | Self::Operator(Is(expr, _))
+            | Self::Operator(Negate(expr))
+            | Self::Operator(Not(expr)) => expr.visit_mut(visitor),
+
+            Self::Function(_, exprs) | Self::Tuple(exprs) => {
+                exprs.iter_mut().for_each(|expr| expr.visit_mut(visitor))
+            }
 
-            Self::All | Self::Column(_, _) | Self::Literal(_) => true,
+            Self::All | Self::Column(_, _) | Self::Literal(_) | Self::Parameter(_) => {}
         }
+        visitor(self);
     }
 
     /// Walks the expression tree depth-first while calling a closure until it
@@ -250,6 +447,7 @@ impl Expression {
             | Self::Operator(LessThan(lhs, rhs))
             | Self::Operator(LessThanOrEqual(lhs, rhs))
             | Self::Operator(Like(lhs, rhs))
+            | Self::Operator(Match(lhs, rhs))
             | Self::Operator(Multiply(lhs, rhs))
             | Self::Operator(NotEqual(lhs, rhs))
             | Self::Operator(Or(lhs, rhs))
@@ -265,13 +463,155 @@ impl Expression {
             | Self::Operator(Negate(expr))
             | Self::Operator(Not(expr)) => expr.collect(visitor, c),
 
-            Self::Function(_, exprs) => exprs.iter().for_each(|expr| expr.collect(visitor, c)),
+            Self::Function(_, exprs) | Self::Tuple(exprs) => {
+                exprs.iter().for_each(|expr| expr.collect(visitor, c))
+            }
+
+            Self::All | Self::Column(_, _) | Self::Literal(_) | Self::Parameter(_) => {}
+        }
+    }
+
+    // noinspection DuplicatedCode
+    /// Binds parameters to the given values, replacing them with literals.
+    /// Parameters without a value are left as is.
+    pub fn bind(&mut self, params: &[Literal]) {
+        use Operator::*;
+        match self {
+            Self::Parameter(index) => {
+                if let Some(value) = params.get(*index) {
+                    *self = Self::Literal(value.clone());
+                }
+            }
+
+            Self::Operator(Add(lhs, rhs))
+            | Self::Operator(And(lhs, rhs))
+            | Self::Operator(Divide(lhs, rhs))
+            | Self::Operator(Equal(lhs, rhs))
+            | Self::Operator(Exponentiate(lhs, rhs))
+            | Self::Operator(GreaterThan(lhs, rhs))
+            | Self::Operator(GreaterThanOrEqual(lhs, rhs))
+            | Self::Operator(LessThan(lhs, rhs))
+            | Self::Operator(LessThanOrEqual(lhs, rhs))
+            | Self::Operator(Like(lhs, rhs))
+            | Self::Operator(Match(lhs, rhs))
+            | Self::Operator(Multiply(lhs, rhs))
+            | Self::Operator(NotEqual(lhs, rhs))
+            | Self::Operator(Or(lhs, rhs))
+            | Self::Operator(Remainder(lhs, rhs))
+            | Self::Operator(Subtract(lhs, rhs)) => {
+                lhs.bind(params);
+                rhs.bind(params);
+            }
+
+            Self::Operator(Factorial(expr))
+            | Self::Operator(Identity(expr))
+            | Self::Operator(Is(expr, _))
+            | Self::Operator(Negate(expr))
+            | Self::Operator(Not(expr)) => expr.bind(params),
+
+            Self::Function(_, exprs) | Self::Tuple(exprs) => {
+                exprs.iter_mut().for_each(|expr| expr.bind(params))
+            }
 
             Self::All | Self::Column(_, _) | Self::Literal(_) => {}
         }
     }
 }
 
+impl Statement {
+    /// Binds positional parameters (i.e. ?) in the statement's expressions to
+    /// the given values, e.g. for prepared statements. Parameters without a
+    /// value are left as is, and error during planning.
+    pub fn bind(&mut self, params: &[Literal]) {
+        self.visit_expressions_mut(&mut |expr| expr.bind(params))
+    }
+
+    /// Calls the closure for each top-level expression in the statement, which
+    /// may modify it in place. Use Expression::visit_mut() to visit nested
+    /// expressions.
+    pub fn visit_expressions_mut(&mut self, visitor: &mut impl FnMut(&mut Expression)) {
+        match self {
+            Self::Explain { statement, .. } | Self::Declare { query: statement, .. } => {
+                statement.visit_expressions_mut(visitor)
+            }
+            Self::CreateTable { columns, options, .. } => {
+                let defaults = columns.iter_mut().filter_map(|column| column.default.as_mut());
+                defaults.for_each(&mut *visitor);
+                options.iter_mut().map(|(_, value)| value).for_each(visitor);
+            }
+            Self::Delete { r#where, .. } => r#where.iter_mut().for_each(visitor),
+            Self::Insert { values, .. } => values.iter_mut().flatten().for_each(visitor),
+            Self::Update { set, r#where, .. } => {
+                set.values_mut().flatten().for_each(&mut *visitor);
+                r#where.iter_mut().for_each(visitor);
+            }
+            Self::Select {
+                select,
+                from,
+                as_of: _,
+                r#where,
+                group_by,
+                having,
+                order_by,
+                offset,
+                limit,
+            } => {
+                select.iter_mut().map(|(expr, _)| expr).for_each(&mut *visitor);
+                from.iter_mut().for_each(|from| from.visit_expressions_mut(visitor));
+                r#where.iter_mut().for_each(&mut *visitor);
+                group_by.iter_mut().for_each(&mut *visitor);
+                having.iter_mut().for_each(&mut *visitor);
+                order_by.iter_mut().map(|(expr, _)| expr).for_each(&mut *visitor);
+                offset.iter_mut().for_each(&mut *visitor);
+                limit.iter_mut().for_each(visitor);
+            }
+            Self::Begin { .. }
+            | Self::Commit
+            | Self::Rollback
+            | Self::Savepoint { .. }
+            | Self::RollbackToSavepoint { .. }
+            | Self::ReleaseSavepoint { .. }
+            | Self::Set { .. }
+            | Self::Fetch { .. }
+            | Self::Close { .. }
+            | Self::Use { .. }
+            | Self::Analyze { .. }
+            | Self::CreateDatabase { .. }
+            | Self::DropDatabase { .. }
+            | Self::DropTable { .. }
+            | Self::CreateIndex { .. }
+            | Self::CreateUser { .. }
+            | Self::AlterUser { .. }
+            | Self::CreateRole { .. }
+            | Self::DropRole { .. }
+            | Self::CreateChangefeed { .. }
+            | Self::DropChangefeed { .. }
+            | Self::CreateTrigger { .. }
+            | Self::DropTrigger { .. }
+            | Self::CreateFunction { .. }
+            | Self::DropFunction { .. }
+            | Self::Grant { .. }
+            | Self::Revoke { .. } => {}
+        }
+    }
+}
+
+impl From {
+    /// Visits join predicates, see Statement::visit_expressions_mut().
+    fn visit_expressions_mut(&mut self, visitor: &mut impl FnMut(&mut Expression)) {
+        match self {
+            Self::Table { .. } => {}
+            Self::Join { left, right, predicate, .. } => {
+                left.visit_expressions_mut(visitor);
+                right.visit_expressions_mut(visitor);
+                if let Some(predicate) = predicate {
+                    visitor(predicate);
+                }
+            }
+        }
+    }
+}
+
 impl core::convert::From<Literal> for Expression {
     fn from(literal: Literal) -> Self {
         Self::Literal(literal)
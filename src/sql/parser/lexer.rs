@@ -1,12 +1,20 @@
-use crate::errinput;
-use crate::error::Result;
+use crate::error::{Location, Result};
+use crate::errsyntax;
+
+use std::borrow::Cow;
 
 /// The lexer (lexical analyzer) preprocesses raw SQL strings into a sequence of
 /// lexical tokens (e.g. keyword, number, string, etc.), which are passed on to
 /// the SQL parser. In doing so, it strips away basic syntactic noise such as
 /// whitespace, case, and quotes, and performs initial symbol validation.
+#[derive(Clone)]
 pub struct Lexer<'a> {
+    input: &'a str,
     chars: std::iter::Peekable<std::str::Chars<'a>>,
+    /// The byte offset of the next character.
+    position: usize,
+    /// The byte offset of the last scanned token, or the end of the input.
+    start: usize,
 }
 
 /// A lexical token.
@@ -41,6 +49,7 @@ pub enum Token {
     Percent,            // %
     Exclamation,        // !
     Question,           // ?
+    AtAt,               // @@
     Comma,              // ,
     Semicolon,          // ;
     OpenParen,          // (
@@ -70,6 +79,7 @@ impl std::fmt::Display for Token {
             Self::Percent => "%",
             Self::Exclamation => "!",
             Self::Question => "?",
+            Self::AtAt => "@@",
             Self::Comma => ",",
             Self::Semicolon => ";",
             Self::OpenParen => "(",
@@ -87,16 +97,27 @@ impl From<Keyword> for Token {
 /// Reserved SQL keywords.
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Keyword {
+    After,
+    All,
+    Alter,
+    Analyze,
     And,
     As,
     Asc,
+    Before,
     Begin,
     Bool,
     Boolean,
     By,
+    Changefeed,
+    Close,
     Commit,
     Create,
     Cross,
+    Cursor,
+    Database,
+    Ddl,
+    Declare,
     Default,
     Delete,
     Desc,
@@ -105,8 +126,13 @@ pub enum Keyword {
     Exists,
     Explain,
     False,
+    Fetch,
     Float,
+    For,
     From,
+    Fulltext,
+    Function,
+    Grant,
     Group,
     Having,
     If,
@@ -123,7 +149,9 @@ pub enum Keyword {
     Left,
     Like,
     Limit,
+    Match,
     NaN,
+    Next,
     Not,
     Null,
     Of,
@@ -133,28 +161,146 @@ pub enum Keyword {
     Or,
     Order,
     Outer,
+    Password,
+    Point,
     Primary,
     Read,
     References,
+    Release,
+    Returns,
+    Revoke,
     Right,
+    Role,
     Rollback,
+    Savepoint,
     Select,
     Set,
+    Show,
     String,
+    Superuser,
     System,
     Table,
     Text,
     Time,
+    To,
     Transaction,
+    Trigger,
     True,
     Unique,
     Update,
+    Use,
+    User,
     Values,
     Varchar,
     Where,
+    With,
     Write,
 }
 
+impl Keyword {
+    /// All keywords, e.g. for tab completion.
+    pub const ALL: &'static [Keyword] = &[
+        Self::After,
+        Self::All,
+        Self::Alter,
+        Self::Analyze,
+        Self::And,
+        Self::As,
+        Self::Asc,
+        Self::Before,
+        Self::Begin,
+        Self::Bool,
+        Self::Boolean,
+        Self::By,
+        Self::Changefeed,
+        Self::Close,
+        Self::Commit,
+        Self::Create,
+        Self::Cross,
+        Self::Cursor,
+        Self::Database,
+        Self::Ddl,
+        Self::Declare,
+        Self::Default,
+        Self::Delete,
+        Self::Desc,
+        Self::Double,
+        Self::Drop,
+        Self::Exists,
+        Self::Explain,
+        Self::False,
+        Self::Fetch,
+        Self::Float,
+        Self::For,
+        Self::From,
+        Self::Fulltext,
+        Self::Function,
+        Self::Grant,
+        Self::Group,
+        Self::Having,
+        Self::If,
+        Self::Index,
+        Self::Infinity,
+        Self::Inner,
+        Self::Insert,
+        Self::Int,
+        Self::Integer,
+        Self::Into,
+        Self::Is,
+        Self::Join,
+        Self::Key,
+        Self::Left,
+        Self::Like,
+        Self::Limit,
+        Self::Match,
+        Self::NaN,
+        Self::Next,
+        Self::Not,
+        Self::Null,
+        Self::Of,
+        Self::Offset,
+        Self::On,
+        Self::Only,
+        Self::Or,
+        Self::Order,
+        Self::Outer,
+        Self::Password,
+        Self::Point,
+        Self::Primary,
+        Self::Read,
+        Self::References,
+        Self::Release,
+        Self::Returns,
+        Self::Revoke,
+        Self::Right,
+        Self::Role,
+        Self::Rollback,
+        Self::Savepoint,
+        Self::Select,
+        Self::Set,
+        Self::Show,
+        Self::String,
+        Self::Superuser,
+        Self::System,
+        Self::Table,
+        Self::Text,
+        Self::Time,
+        Self::To,
+        Self::Transaction,
+        Self::Trigger,
+        Self::True,
+        Self::Unique,
+        Self::Update,
+        Self::Use,
+        Self::User,
+        Self::Values,
+        Self::Varchar,
+        Self::Where,
+        Self::With,
+        Self::Write,
+    ];
+}
+
 impl TryFrom<&str> for Keyword {
     // Use a cheap static string, since this just indicates it's not a keyword.
     type Error = &'static str;
@@ -164,16 +310,27 @@ impl TryFrom<&str> for Keyword {
         // allocating a string to change the case. Assert this.
         debug_assert!(value.chars().all(|c| !c.is_uppercase()), "keyword must be lowercase");
         Ok(match value {
+            "after" => Self::After,
+            "all" => Self::All,
+            "alter" => Self::Alter,
+            "analyze" => Self::Analyze,
             "as" => Self::As,
             "asc" => Self::Asc,
             "and" => Self::And,
+            "before" => Self::Before,
             "begin" => Self::Begin,
             "bool" => Self::Bool,
             "boolean" => Self::Boolean,
             "by" => Self::By,
+            "changefeed" => Self::Changefeed,
+            "close" => Self::Close,
             "commit" => Self::Commit,
             "create" => Self::Create,
             "cross" => Self::Cross,
+            "cursor" => Self::Cursor,
+            "database" => Self::Database,
+            "ddl" => Self::Ddl,
+            "declare" => Self::Declare,
             "default" => Self::Default,
             "delete" => Self::Delete,
             "desc" => Self::Desc,
@@ -182,8 +339,13 @@ impl TryFrom<&str> for Keyword {
             "exists" => Self::Exists,
             "explain" => Self::Explain,
             "false" => Self::False,
+            "fetch" => Self::Fetch,
             "float" => Self::Float,
+            "for" => Self::For,
             "from" => Self::From,
+            "fulltext" => Self::Fulltext,
+            "function" => Self::Function,
+            "grant" => Self::Grant,
             "group" => Self::Group,
             "having" => Self::Having,
             "if" => Self::If,
@@ -200,7 +362,9 @@ impl TryFrom<&str> for Keyword {
             "left" => Self::Left,
             "like" => Self::Like,
             "limit" => Self::Limit,
+            "match" => Self::Match,
             "nan" => Self::NaN,
+            "next" => Self::Next,
             "not" => Self::Not,
             "null" => Self::Null,
             "of" => Self::Of,
@@ -210,25 +374,39 @@ impl TryFrom<&str> for Keyword {
             "or" => Self::Or,
             "order" => Self::Order,
             "outer" => Self::Outer,
+            "password" => Self::Password,
+            "point" => Self::Point,
             "primary" => Self::Primary,
             "read" => Self::Read,
             "references" => Self::References,
+            "release" => Self::Release,
+            "returns" => Self::Returns,
+            "revoke" => Self::Revoke,
             "right" => Self::Right,
+            "role" => Self::Role,
             "rollback" => Self::Rollback,
+            "savepoint" => Self::Savepoint,
             "select" => Self::Select,
             "set" => Self::Set,
+            "show" => Self::Show,
             "string" => Self::String,
+            "superuser" => Self::Superuser,
             "system" => Self::System,
             "table" => Self::Table,
             "text" => Self::Text,
             "time" => Self::Time,
+            "to" => Self::To,
             "transaction" => Self::Transaction,
+            "trigger" => Self::Trigger,
             "true" => Self::True,
             "unique" => Self::Unique,
             "update" => Self::Update,
+            "use" => Self::Use,
+            "user" => Self::User,
             "values" => Self::Values,
             "varchar" => Self::Varchar,
             "where" => Self::Where,
+            "with" => Self::With,
             "write" => Self::Write,
             _ => return Err("not a keyword"),
         })
@@ -239,16 +417,27 @@ impl std::fmt::Display for Keyword {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         // Display keywords as uppercase.
         f.write_str(match self {
+            Self::After => "AFTER",
+            Self::All => "ALL",
+            Self::Alter => "ALTER",
+            Self::Analyze => "ANALYZE",
             Self::As => "AS",
             Self::Asc => "ASC",
             Self::And => "AND",
+            Self::Before => "BEFORE",
             Self::Begin => "BEGIN",
             Self::Bool => "BOOL",
             Self::Boolean => "BOOLEAN",
             Self::By => "BY",
+            Self::Changefeed => "CHANGEFEED",
+            Self::Close => "CLOSE",
             Self::Commit => "COMMIT",
             Self::Create => "CREATE",
             Self::Cross => "CROSS",
+            Self::Cursor => "CURSOR",
+            Self::Database => "DATABASE",
+            Self::Ddl => "DDL",
+            Self::Declare => "DECLARE",
             Self::Default => "DEFAULT",
             Self::Delete => "DELETE",
             Self::Desc => "DESC",
@@ -257,8 +446,13 @@ impl std::fmt::Display for Keyword {
             Self::Exists => "EXISTS",
             Self::Explain => "EXPLAIN",
             Self::False => "FALSE",
+            Self::Fetch => "FETCH",
             Self::Float => "FLOAT",
+            Self::For => "FOR",
             Self::From => "FROM",
+            Self::Fulltext => "FULLTEXT",
+            Self::Function => "FUNCTION",
+            Self::Grant => "GRANT",
             Self::Group => "GROUP",
             Self::Having => "HAVING",
             Self::If => "IF",
@@ -275,7 +469,9 @@ impl std::fmt::Display for Keyword {
             Self::Left => "LEFT",
             Self::Like => "LIKE",
             Self::Limit => "LIMIT",
+            Self::Match => "MATCH",
             Self::NaN => "NAN",
+            Self::Next => "NEXT",
             Self::Not => "NOT",
             Self::Null => "NULL",
             Self::Of => "OF",
@@ -285,25 +481,39 @@ impl std::fmt::Display for Keyword {
             Self::Outer => "OUTER",
             Self::Or => "OR",
             Self::Order => "ORDER",
+            Self::Password => "PASSWORD",
+            Self::Point => "POINT",
             Self::Primary => "PRIMARY",
             Self::Read => "READ",
             Self::References => "REFERENCES",
+            Self::Release => "RELEASE",
+            Self::Returns => "RETURNS",
+            Self::Revoke => "REVOKE",
             Self::Right => "RIGHT",
+            Self::Role => "ROLE",
             Self::Rollback => "ROLLBACK",
+            Self::Savepoint => "SAVEPOINT",
             Self::Select => "SELECT",
             Self::Set => "SET",
+            Self::Show => "SHOW",
             Self::String => "STRING",
+            Self::Superuser => "SUPERUSER",
             Self::System => "SYSTEM",
             Self::Table => "TABLE",
             Self::Text => "TEXT",
             Self::Time => "TIME",
+            Self::To => "TO",
             Self::Transaction => "TRANSACTION",
+            Self::Trigger => "TRIGGER",
             Self::True => "TRUE",
             Self::Unique => "UNIQUE",
             Self::Update => "UPDATE",
+            Self::Use => "USE",
+            Self::User => "USER",
             Self::Values => "VALUES",
             Self::Varchar => "VARCHAR",
             Self::Where => "WHERE",
+            Self::With => "WITH",
             Self::Write => "WRITE",
         })
     }
@@ -314,33 +524,60 @@ impl Iterator for Lexer<'_> {
     type Item = Result<Token>;
 
     fn next(&mut self) -> Option<Result<Token>> {
-        match self.scan() {
+        // Ignore whitespace.
+        self.skip_whitespace();
+        self.start = self.position;
+        let result = match self.scan() {
             Ok(Some(token)) => Some(Ok(token)),
             // If there's any remaining chars, the lexer didn't recognize them.
             // Otherwise, we're done lexing.
-            Ok(None) => self.chars.peek().map(|c| errinput!("unexpected character `{c}`")),
+            Ok(None) => self.chars.peek().map(|c| errsyntax!("unexpected character `{c}`")),
             Err(err) => Some(Err(err)),
-        }
+        };
+        result.map(|r| r.map_err(|err| err.with_location(|| self.location(self.start))))
     }
 }
 
 impl<'a> Lexer<'a> {
     /// Creates a new lexer for the given string.
     pub fn new(input: &'a str) -> Lexer<'a> {
-        Lexer { chars: input.chars().peekable() }
+        Lexer { input, chars: input.chars().peekable(), position: 0, start: 0 }
+    }
+
+    /// Returns the byte offset of the last token returned by the iterator, or
+    /// the end of the input if there are no more tokens.
+    pub fn offset(&self) -> usize {
+        self.start
+    }
+
+    /// Returns the input string.
+    pub fn input(&self) -> &'a str {
+        self.input
+    }
+
+    /// Returns the location of the given byte offset in the input.
+    pub fn location(&self, offset: usize) -> Location {
+        Location::new(self.input, offset)
+    }
+
+    /// Consumes and returns the next character, tracking its position.
+    fn next_char(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        self.position += c.len_utf8();
+        Some(c)
     }
 
     /// Returns the next character if it satisfies the predicate.
     fn next_if(&mut self, predicate: impl Fn(char) -> bool) -> Option<char> {
         self.chars.peek().filter(|&&c| predicate(c))?;
-        self.chars.next()
+        self.next_char()
     }
 
     /// Applies a function to the next character, returning its result and
     /// consuming the next character if it's Some.
     fn next_if_map<T>(&mut self, map: impl Fn(char) -> Option<T>) -> Option<T> {
         let value = self.chars.peek().and_then(|&c| map(c))?;
-        self.chars.next();
+        self.next_char();
         Some(value)
     }
 
@@ -351,8 +588,6 @@ impl<'a> Lexer<'a> {
 
     /// Scans the next token, if any.
     fn scan(&mut self) -> Result<Option<Token>> {
-        // Ignore whitespace.
-        self.skip_whitespace();
         // The first character tells us the token type.
         match self.chars.peek() {
             Some('\'') => self.scan_string(),
@@ -386,12 +621,12 @@ impl<'a> Lexer<'a> {
         }
         let mut ident = String::new();
         loop {
-            match self.chars.next() {
+            match self.next_char() {
                 // "" is the escape sequence for ".
                 Some('"') if self.next_is('"') => ident.push('"'),
                 Some('"') => break,
                 Some(c) => ident.push(c),
-                None => return errinput!("unexpected end of quoted identifier"),
+                None => return errsyntax!("unexpected end of quoted identifier"),
             }
         }
         Ok(Some(Token::Ident(ident)))
@@ -431,12 +666,12 @@ impl<'a> Lexer<'a> {
         }
         let mut string = String::new();
         loop {
-            match self.chars.next() {
+            match self.next_char() {
                 // '' is the escape sequence for '.
                 Some('\'') if self.next_is('\'') => string.push('\''),
                 Some('\'') => break,
                 Some(c) => string.push(c),
-                None => return errinput!("unexpected end of string literal"),
+                None => return errsyntax!("unexpected end of string literal"),
             }
         }
         Ok(Some(Token::String(string)))
@@ -444,6 +679,12 @@ impl<'a> Lexer<'a> {
 
     /// Scans the next symbol token, if any.
     fn scan_symbol(&mut self) -> Option<Token> {
+        // @@ is the only token starting with @, so handle it separately.
+        if self.input[self.position..].starts_with("@@") {
+            self.next_char();
+            self.next_char();
+            return Some(Token::AtAt);
+        }
         let mut token = self.next_if_map(|c| {
             Some(match c {
                 '.' => Token::Period,
@@ -482,9 +723,65 @@ impl<'a> Lexer<'a> {
     }
 }
 
-/// Returns true if the entire given string is a single valid identifier.
+/// Returns true if the entire given string is a single valid identifier, which
+/// lexes to the same string (i.e. it's lowercase and doesn't need quoting).
 pub fn is_ident(ident: &str) -> bool {
     let mut lexer = Lexer::new(ident);
-    let Some(Ok(Token::Ident(_))) = lexer.next() else { return false };
-    lexer.next().is_none() // if further tokens, it's not a lone identifier
+    let Some(Ok(Token::Ident(lexed))) = lexer.next() else { return false };
+    lexed == ident && lexer.next().is_none() // if further tokens, it's not a lone identifier
+}
+
+/// Redacts passwords in a SQL statement, for use in logs, traces, and
+/// histories. String literals following the PASSWORD keyword (e.g. in CREATE
+/// USER and ALTER USER) are replaced by '***', as is the rest of the statement
+/// if it fails to lex after a PASSWORD keyword. Also returns true if the
+/// statement has a password parameter (i.e. PASSWORD ?), in which case the
+/// caller must redact the parameter values.
+pub fn redact(statement: &str) -> (Cow<'_, str>, bool) {
+    let mut lexer = Lexer::new(statement);
+    let mut redact = Vec::new();
+    let mut password = false;
+    let mut parameter = false;
+    while let Some(result) = lexer.next() {
+        match result {
+            Ok(Token::String(_)) if password => redact.push(lexer.offset()..lexer.position),
+            Ok(Token::Question) if password => parameter = true,
+            Ok(_) => {}
+            Err(_) if password => {
+                redact.push(lexer.offset()..statement.len());
+                break;
+            }
+            Err(_) => break,
+        }
+        password = matches!(result, Ok(Token::Keyword(Keyword::Password)));
+    }
+    if redact.is_empty() {
+        return (Cow::Borrowed(statement), parameter);
+    }
+    let mut redacted = String::with_capacity(statement.len());
+    let mut end = 0;
+    for range in redact {
+        redacted.push_str(&statement[end..range.start]);
+        redacted.push_str("'***'");
+        end = range.end;
+    }
+    redacted.push_str(&statement[end..]);
+    (Cow::Owned(redacted), parameter)
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    /// Password literals and parameters are redacted, other strings aren't.
+    #[test_case("SELECT 'secret'" => ("SELECT 'secret'".to_string(), false); "other string")]
+    #[test_case("CREATE USER alice PASSWORD 'secret'" => ("CREATE USER alice PASSWORD '***'".to_string(), false); "create user")]
+    #[test_case("ALTER USER alice PASSWORD 'it''s' ; SELECT 'a'" => ("ALTER USER alice PASSWORD '***' ; SELECT 'a'".to_string(), false); "alter user")]
+    #[test_case("alter user alice password ?" => ("alter user alice password ?".to_string(), true); "parameter")]
+    #[test_case("CREATE USER alice PASSWORD 'unterminated" => ("CREATE USER alice PASSWORD '***'".to_string(), false); "lex error")]
+    #[test_case("SELECT 'a' PASSWORD" => ("SELECT 'a' PASSWORD".to_string(), false); "trailing keyword")]
+    fn redact(statement: &str) -> (String, bool) {
+        let (redacted, parameter) = super::redact(statement);
+        (redacted.into_owned(), parameter)
+    }
 }
@@ -4,5 +4,5 @@ pub mod ast;
 mod lexer;
 mod parser;
 
-pub use lexer::{is_ident, Keyword, Lexer, Token};
-pub use parser::Parser;
+pub use lexer::{is_ident, redact, Keyword, Lexer, Token};
+pub use parser::{Parser, MAX_EXPRESSION_DEPTH};
@@ -1,9 +1,9 @@
 #![allow(clippy::module_inception)]
 
 use super::{ast, Keyword, Lexer, Token};
-use crate::errinput;
 use crate::error::Result;
-use crate::sql::types::DataType;
+use crate::errsyntax;
+use crate::sql::types::{DataType, Grant, Privilege, TriggerEvent, TriggerTiming};
 
 /// The SQL parser takes tokens from the lexer and parses the SQL syntax into an
 /// Abstract Syntax Tree (AST). This nested structure represents the syntactic
@@ -11,40 +11,93 @@ use crate::sql::types::DataType;
 /// arithmetic expressions, etc.). However, it only ensures the syntax is
 /// well-formed, and does not know whether e.g. a given table or column exists
 /// or which kind of join to use -- that is the job of the planner.
+///
+/// Syntax errors are located at the start of the last consumed token, such
+/// that e.g. clients can point out the offending token to the user.
 pub struct Parser<'a> {
-    pub lexer: std::iter::Peekable<Lexer<'a>>,
+    lexer: Lexer<'a>,
+    /// The peeked token and its byte offset, if any.
+    peeked: Option<(usize, Option<Result<Token>>)>,
+    /// The byte offset of the last consumed token.
+    offset: usize,
+    /// The number of positional parameters (i.e. ?) parsed so far.
+    parameters: usize,
+    /// The current expression recursion depth.
+    depth: usize,
 }
 
+/// The maximum expression depth, both in terms of nested parentheses and the
+/// height of the expression tree. Expressions are parsed, planned, and
+/// evaluated recursively, so deeply nested expressions (e.g. from malicious
+/// clients) could otherwise overflow the stack and crash the server.
+pub const MAX_EXPRESSION_DEPTH: usize = 64;
+
 impl Parser<'_> {
     /// Creates a new parser for the given raw SQL string.
     pub fn new(statement: &str) -> Parser {
-        Parser { lexer: Lexer::new(statement).peekable() }
+        Parser { lexer: Lexer::new(statement), peeked: None, offset: 0, parameters: 0, depth: 0 }
+    }
+
+    /// Returns the number of positional parameters (i.e. ?) parsed so far.
+    pub fn parameters(&self) -> usize {
+        self.parameters
     }
 
     /// Parses the input string into an AST statement. The whole string must be
     /// parsed as a single statement, ending with an optional semicolon.
     pub fn parse(&mut self) -> Result<ast::Statement> {
-        let statement = self.parse_statement()?;
-        self.next_is(Token::Semicolon);
-        if let Some(token) = self.lexer.next().transpose()? {
-            return errinput!("unexpected token {token}");
-        }
-        Ok(statement)
+        let result = self.parse_statement().and_then(|statement| {
+            self.next_is(Token::Semicolon);
+            if let Some(token) = self.next_token()? {
+                return errsyntax!("unexpected token {token}");
+            }
+            Ok(statement)
+        });
+        result.map_err(|err| err.with_location(|| self.lexer.location(self.offset)))
+    }
+
+    /// Fetches the next lexer token, if any. Can be used to check that the
+    /// entire input was parsed.
+    pub fn next_token(&mut self) -> Result<Option<Token>> {
+        let (offset, token) = self.peeked.take().unwrap_or_else(|| self.lex());
+        self.offset = offset;
+        token.transpose()
+    }
+
+    /// Fetches the next lexer token and its byte offset, bypassing the peeked
+    /// token. At the end of the input, the offset is the input length.
+    fn lex(&mut self) -> (usize, Option<Result<Token>>) {
+        let token = self.lexer.next();
+        (self.lexer.offset(), token)
     }
 
     /// Fetches the next lexer token, or errors if none is found.
     fn next(&mut self) -> Result<Token> {
-        self.lexer.next().transpose()?.ok_or_else(|| errinput!("unexpected end of input"))
+        self.next_token()?.ok_or_else(|| errsyntax!("unexpected end of input"))
     }
 
     /// Returns the next identifier, or errors if not found.
     fn next_ident(&mut self) -> Result<String> {
         match self.next()? {
             Token::Ident(ident) => Ok(ident),
-            token => errinput!("expected identifier, got {token}"),
+            token => errsyntax!("expected identifier, got {token}"),
         }
     }
 
+    /// Returns the next table name: an identifier, optionally qualified by a
+    /// schema, e.g. system.nodes. The system schema is also a keyword.
+    fn next_table_name(&mut self) -> Result<String> {
+        let name = match self.next_is(Keyword::System.into()) {
+            true => "system".to_string(),
+            false => self.next_ident()?,
+        };
+        if name == "system" || self.peek()? == Some(&Token::Period) {
+            self.expect(Token::Period)?;
+            return Ok(format!("{name}.{}", self.next_ident()?));
+        }
+        Ok(name)
+    }
+
     /// Returns the next lexer token if it satisfies the predicate.
     fn next_if(&mut self, predicate: impl Fn(&Token) -> bool) -> Option<Token> {
         self.peek().unwrap_or(None).filter(|t| predicate(t))?;
@@ -78,7 +131,7 @@ impl Parser<'_> {
     fn expect(&mut self, expect: Token) -> Result<()> {
         let token = self.next()?;
         if token != expect {
-            return errinput!("expected token {expect}, found {token}");
+            return errsyntax!("expected token {expect}, found {token}");
         }
         Ok(())
     }
@@ -91,29 +144,59 @@ impl Parser<'_> {
 
     /// Peeks the next lexer token if any, but transposes it for convenience.
     fn peek(&mut self) -> Result<Option<&Token>> {
-        self.lexer.peek().map(|r| r.as_ref().map_err(|err| err.clone())).transpose()
+        if self.peeked.is_none() {
+            self.peeked = Some(self.lex());
+        }
+        let token = self.peeked.as_ref().and_then(|(_, token)| token.as_ref());
+        token.map(|r| r.as_ref().map_err(|err| err.clone())).transpose()
+    }
+
+    /// Peeks the lexer token after the next one, if any. This is only needed to
+    /// disambiguate AS aliases from AS OF SYSTEM TIME clauses, see as_of_next().
+    fn peek_second(&mut self) -> Result<Option<Token>> {
+        self.peek()?;
+        self.lexer.clone().next().transpose()
+    }
+
+    /// Returns true if the next tokens begin an AS OF SYSTEM TIME clause.
+    fn as_of_next(&mut self) -> Result<bool> {
+        Ok(self.peek()? == Some(&Keyword::As.into())
+            && self.peek_second()? == Some(Keyword::Of.into()))
     }
 
     /// Parses a SQL statement.
     fn parse_statement(&mut self) -> Result<ast::Statement> {
         let Some(token) = self.peek()? else {
-            return errinput!("unexpected end of input");
+            return errsyntax!("unexpected end of input");
         };
         match token {
             Token::Keyword(Keyword::Begin) => self.parse_begin(),
             Token::Keyword(Keyword::Commit) => self.parse_commit(),
             Token::Keyword(Keyword::Rollback) => self.parse_rollback(),
+            Token::Keyword(Keyword::Savepoint) => self.parse_savepoint(),
+            Token::Keyword(Keyword::Release) => self.parse_release(),
             Token::Keyword(Keyword::Explain) => self.parse_explain(),
+            Token::Keyword(Keyword::Set) => self.parse_set(),
+            Token::Keyword(Keyword::Show) => self.parse_show(),
+            Token::Keyword(Keyword::Use) => self.parse_use(),
 
-            Token::Keyword(Keyword::Create) => self.parse_create_table(),
-            Token::Keyword(Keyword::Drop) => self.parse_drop_table(),
+            Token::Keyword(Keyword::Declare) => self.parse_declare(),
+            Token::Keyword(Keyword::Fetch) => self.parse_fetch(),
+            Token::Keyword(Keyword::Close) => self.parse_close(),
+
+            Token::Keyword(Keyword::Alter) => self.parse_alter_user(),
+            Token::Keyword(Keyword::Analyze) => self.parse_analyze(),
+            Token::Keyword(Keyword::Create) => self.parse_create(),
+            Token::Keyword(Keyword::Drop) => self.parse_drop(),
+            Token::Keyword(Keyword::Grant) => self.parse_grant(),
+            Token::Keyword(Keyword::Revoke) => self.parse_revoke(),
 
             Token::Keyword(Keyword::Delete) => self.parse_delete(),
             Token::Keyword(Keyword::Insert) => self.parse_insert(),
             Token::Keyword(Keyword::Select) => self.parse_select(),
             Token::Keyword(Keyword::Update) => self.parse_update(),
 
-            token => errinput!("unexpected token {token}"),
+            _ => errsyntax!("unexpected token {}", self.next()?),
         }
     }
 
@@ -127,49 +210,220 @@ impl Parser<'_> {
             match self.next()? {
                 Token::Keyword(Keyword::Only) => read_only = true,
                 Token::Keyword(Keyword::Write) => {}
-                token => return errinput!("unexpected token {token}"),
+                token => return errsyntax!("unexpected token {token}"),
             }
         }
 
-        let mut as_of = None;
-        if self.next_is(Keyword::As.into()) {
-            self.expect(Keyword::Of.into())?;
-            self.expect(Keyword::System.into())?;
-            self.expect(Keyword::Time.into())?;
-            match self.next()? {
-                Token::Number(n) => as_of = Some(n.parse()?),
-                token => return errinput!("unexpected token {token}, wanted number"),
-            }
-        }
+        let as_of = self.parse_as_of_clause()?;
         Ok(ast::Statement::Begin { read_only, as_of })
     }
 
+    /// Parses an AS OF SYSTEM TIME clause, if present, returning the version.
+    fn parse_as_of_clause(&mut self) -> Result<Option<u64>> {
+        if !self.next_is(Keyword::As.into()) {
+            return Ok(None);
+        }
+        self.expect(Keyword::Of.into())?;
+        self.expect(Keyword::System.into())?;
+        self.expect(Keyword::Time.into())?;
+        match self.next()? {
+            Token::Number(n) => Ok(Some(n.parse()?)),
+            token => errsyntax!("unexpected token {token}, wanted number"),
+        }
+    }
+
     /// Parses a COMMIT statement.
     fn parse_commit(&mut self) -> Result<ast::Statement> {
         self.expect(Keyword::Commit.into())?;
         Ok(ast::Statement::Commit)
     }
 
-    /// Parses a ROLLBACK statement.
+    /// Parses a ROLLBACK statement, or ROLLBACK TO [SAVEPOINT] name.
     fn parse_rollback(&mut self) -> Result<ast::Statement> {
         self.expect(Keyword::Rollback.into())?;
-        Ok(ast::Statement::Rollback)
+        if !self.next_is(Keyword::To.into()) {
+            return Ok(ast::Statement::Rollback);
+        }
+        self.skip(Keyword::Savepoint.into());
+        Ok(ast::Statement::RollbackToSavepoint { name: self.next_ident()? })
+    }
+
+    /// Parses a SAVEPOINT statement.
+    fn parse_savepoint(&mut self) -> Result<ast::Statement> {
+        self.expect(Keyword::Savepoint.into())?;
+        Ok(ast::Statement::Savepoint { name: self.next_ident()? })
     }
 
-    /// Parses an EXPLAIN statement.
+    /// Parses a RELEASE [SAVEPOINT] statement.
+    fn parse_release(&mut self) -> Result<ast::Statement> {
+        self.expect(Keyword::Release.into())?;
+        self.skip(Keyword::Savepoint.into());
+        Ok(ast::Statement::ReleaseSavepoint { name: self.next_ident()? })
+    }
+
+    /// Parses an EXPLAIN statement. ANALYZE can be given either directly after
+    /// EXPLAIN, or in the option list along with FORMAT TEXT | DOT.
     fn parse_explain(&mut self) -> Result<ast::Statement> {
         self.expect(Keyword::Explain.into())?;
+        let mut format = ast::ExplainFormat::Text;
+        let mut analyze = self.next_is(Keyword::Analyze.into());
+        if self.next_is(Token::OpenParen) {
+            loop {
+                match self.next()? {
+                    Token::Keyword(Keyword::Analyze) => analyze = true,
+                    Token::Ident(option) if option == "format" => {
+                        format = match self.next()? {
+                            Token::Keyword(Keyword::Text) => ast::ExplainFormat::Text,
+                            Token::Ident(format) if format == "dot" => ast::ExplainFormat::Dot,
+                            token => return errsyntax!("unknown EXPLAIN format {token}"),
+                        }
+                    }
+                    token => return errsyntax!("unknown EXPLAIN option {token}"),
+                }
+                if !self.next_is(Token::Comma) {
+                    break;
+                }
+            }
+            self.expect(Token::CloseParen)?;
+        }
         if self.next_is(Keyword::Explain.into()) {
-            return errinput!("cannot nest EXPLAIN statements");
+            return errsyntax!("cannot nest EXPLAIN statements");
         }
-        Ok(ast::Statement::Explain(Box::new(self.parse_statement()?)))
+        let statement = Box::new(self.parse_statement()?);
+        Ok(ast::Statement::Explain { statement, format, analyze })
     }
 
-    /// Parses a CREATE TABLE statement.
-    fn parse_create_table(&mut self) -> Result<ast::Statement> {
-        self.expect(Keyword::Create.into())?;
-        self.expect(Keyword::Table.into())?;
+    /// Parses a SET statement.
+    fn parse_set(&mut self) -> Result<ast::Statement> {
+        self.expect(Keyword::Set.into())?;
+        if self.next_is(Keyword::Transaction.into()) {
+            return self.parse_set_transaction();
+        }
+        let name = self.next_ident()?;
+        if !self.next_is(Token::Equal) {
+            self.expect(Keyword::To.into())?;
+        }
+        if self.next_is(Keyword::Default.into()) {
+            return Ok(ast::Statement::Set { name, value: None });
+        }
+        match self.parse_expression()? {
+            ast::Expression::Literal(value) => Ok(ast::Statement::Set { name, value: Some(value) }),
+            _ => errsyntax!("SET value must be a constant"),
+        }
+    }
+
+    /// Parses the remainder of a SET TRANSACTION ISOLATION LEVEL statement,
+    /// as the transaction_isolation setting. ISOLATION and LEVEL aren't
+    /// keywords, to avoid reserving them.
+    fn parse_set_transaction(&mut self) -> Result<ast::Statement> {
+        for word in ["isolation", "level"] {
+            match self.next()? {
+                Token::Ident(ident) if ident == word => {}
+                token => return errsyntax!("unexpected token {token}, wanted {word}"),
+            }
+        }
+        let level = self.next_ident()?;
+        let value = Some(ast::Literal::String(level));
+        Ok(ast::Statement::Set { name: "transaction_isolation".to_string(), value })
+    }
+
+    /// Parses a SHOW statement. This is shorthand for a SELECT from the
+    /// corresponding system table.
+    fn parse_show(&mut self) -> Result<ast::Statement> {
+        self.expect(Keyword::Show.into())?;
+        let table = match self.next_ident()?.as_str() {
+            "cluster" => "system.cluster",
+            "nodes" => "system.nodes",
+            name => return errsyntax!("unknown SHOW target {name}"),
+        };
+        Ok(ast::Statement::Select {
+            select: vec![(ast::Expression::All, None)],
+            from: vec![ast::From::Table { name: table.to_string(), alias: None }],
+            as_of: None,
+            r#where: None,
+            group_by: Vec::new(),
+            having: None,
+            order_by: Vec::new(),
+            offset: None,
+            limit: None,
+        })
+    }
+
+    /// Parses a USE statement.
+    fn parse_use(&mut self) -> Result<ast::Statement> {
+        self.expect(Keyword::Use.into())?;
+        Ok(ast::Statement::Use { name: self.next_ident()? })
+    }
+
+    /// Parses a DECLARE CURSOR statement.
+    fn parse_declare(&mut self) -> Result<ast::Statement> {
+        self.expect(Keyword::Declare.into())?;
         let name = self.next_ident()?;
+        self.expect(Keyword::Cursor.into())?;
+        self.expect(Keyword::For.into())?;
+        let query = self.parse_select()?;
+        Ok(ast::Statement::Declare { name, query: Box::new(query) })
+    }
+
+    /// Parses a FETCH statement: FETCH [NEXT | count | ALL] [FROM] cursor.
+    /// Fetches the next row by default.
+    fn parse_fetch(&mut self) -> Result<ast::Statement> {
+        self.expect(Keyword::Fetch.into())?;
+        let number = |token: &Token| match token {
+            Token::Number(n) => Some(n.clone()),
+            _ => None,
+        };
+        let count = if self.next_is(Keyword::All.into()) {
+            None
+        } else if let Some(n) = self.next_if_map(number) {
+            Some(n.parse().or_else(|_| errsyntax!("invalid FETCH count {n}"))?)
+        } else {
+            self.skip(Keyword::Next.into());
+            Some(1)
+        };
+        self.skip(Keyword::From.into());
+        let name = self.next_ident()?;
+        Ok(ast::Statement::Fetch { name, count })
+    }
+
+    /// Parses a CLOSE statement.
+    fn parse_close(&mut self) -> Result<ast::Statement> {
+        self.expect(Keyword::Close.into())?;
+        let name = self.next_ident()?;
+        Ok(ast::Statement::Close { name })
+    }
+
+    /// Parses an ANALYZE statement, for a given table or all tables.
+    fn parse_analyze(&mut self) -> Result<ast::Statement> {
+        self.expect(Keyword::Analyze.into())?;
+        let table = self.next_if_map(|token| match token {
+            Token::Ident(ident) => Some(ident.clone()),
+            _ => None,
+        });
+        Ok(ast::Statement::Analyze { table })
+    }
+
+    /// Parses a CREATE statement.
+    fn parse_create(&mut self) -> Result<ast::Statement> {
+        self.expect(Keyword::Create.into())?;
+        match self.next()? {
+            Token::Keyword(Keyword::Database) => {
+                Ok(ast::Statement::CreateDatabase { name: self.next_ident()? })
+            }
+            Token::Keyword(Keyword::Table) => self.parse_create_table(),
+            Token::Keyword(Keyword::Index) => self.parse_create_index(),
+            Token::Keyword(Keyword::User) => self.parse_create_user(),
+            Token::Keyword(Keyword::Role) => self.parse_create_role(),
+            Token::Keyword(Keyword::Changefeed) => self.parse_create_changefeed(),
+            Token::Keyword(Keyword::Trigger) => self.parse_create_trigger(),
+            Token::Keyword(Keyword::Function) => self.parse_create_function(),
+            token => errsyntax!("unexpected token {token}"),
+        }
+    }
+
+    /// Parses a CREATE TABLE statement, after CREATE TABLE.
+    fn parse_create_table(&mut self) -> Result<ast::Statement> {
+        let name = self.next_table_name()?;
         self.expect(Token::OpenParen)?;
         let mut columns = Vec::new();
         loop {
@@ -179,19 +433,26 @@ impl Parser<'_> {
             }
         }
         self.expect(Token::CloseParen)?;
-        Ok(ast::Statement::CreateTable { name, columns })
+        let mut options = Vec::new();
+        if self.next_is(Keyword::With.into()) {
+            self.expect(Token::OpenParen)?;
+            loop {
+                let name = self.next_ident()?;
+                self.expect(Token::Equal)?;
+                options.push((name, self.parse_expression()?));
+                if !self.next_is(Token::Comma) {
+                    break;
+                }
+            }
+            self.expect(Token::CloseParen)?;
+        }
+        Ok(ast::Statement::CreateTable { name, columns, options })
     }
 
     /// Parses a CREATE TABLE column definition.
     fn parse_create_table_column(&mut self) -> Result<ast::Column> {
         let name = self.next_ident()?;
-        let datatype = match self.next()? {
-            Token::Keyword(Keyword::Bool | Keyword::Boolean) => DataType::Boolean,
-            Token::Keyword(Keyword::Float | Keyword::Double) => DataType::Float,
-            Token::Keyword(Keyword::Int | Keyword::Integer) => DataType::Integer,
-            Token::Keyword(Keyword::String | Keyword::Text | Keyword::Varchar) => DataType::String,
-            token => return errinput!("unexpected token {token}"),
-        };
+        let datatype = self.parse_datatype()?;
         let mut column = ast::Column {
             name,
             datatype,
@@ -201,6 +462,7 @@ impl Parser<'_> {
             unique: false,
             index: false,
             references: None,
+            fulltext: false,
         };
         while let Some(keyword) = self.next_if_keyword() {
             match keyword {
@@ -210,14 +472,14 @@ impl Parser<'_> {
                 }
                 Keyword::Null => {
                     if column.nullable.is_some() {
-                        return errinput!("nullability already set for column {}", column.name);
+                        return errsyntax!("nullability already set for column {}", column.name);
                     }
                     column.nullable = Some(true)
                 }
                 Keyword::Not => {
                     self.expect(Keyword::Null.into())?;
                     if column.nullable.is_some() {
-                        return errinput!("nullability already set for column {}", column.name);
+                        return errsyntax!("nullability already set for column {}", column.name);
                     }
                     column.nullable = Some(false)
                 }
@@ -225,30 +487,245 @@ impl Parser<'_> {
                 Keyword::Unique => column.unique = true,
                 Keyword::Index => column.index = true,
                 Keyword::References => column.references = Some(self.next_ident()?),
-                keyword => return errinput!("unexpected keyword {keyword}"),
+                Keyword::Fulltext => column.fulltext = true,
+                keyword => return errsyntax!("unexpected keyword {keyword}"),
             }
         }
         Ok(column)
     }
 
-    /// Parses a DROP TABLE statement.
+    /// Parses a data type.
+    fn parse_datatype(&mut self) -> Result<DataType> {
+        Ok(match self.next()? {
+            Token::Keyword(Keyword::Bool | Keyword::Boolean) => DataType::Boolean,
+            Token::Keyword(Keyword::Float | Keyword::Double) => DataType::Float,
+            Token::Keyword(Keyword::Int | Keyword::Integer) => DataType::Integer,
+            Token::Keyword(Keyword::Point) => DataType::Point,
+            Token::Keyword(Keyword::String | Keyword::Text | Keyword::Varchar) => DataType::String,
+            token => return errsyntax!("unexpected token {token}"),
+        })
+    }
+
+    /// Parses a CREATE INDEX statement, after CREATE INDEX: ON table (column).
+    fn parse_create_index(&mut self) -> Result<ast::Statement> {
+        self.expect(Keyword::On.into())?;
+        let table = self.next_table_name()?;
+        self.expect(Token::OpenParen)?;
+        let column = self.next_ident()?;
+        self.expect(Token::CloseParen)?;
+        Ok(ast::Statement::CreateIndex { table, column })
+    }
+
+    /// Parses a CREATE USER statement, after CREATE USER.
+    fn parse_create_user(&mut self) -> Result<ast::Statement> {
+        let name = self.next_ident()?;
+        let password = self.parse_password()?;
+        let superuser = self.next_is(Keyword::Superuser.into());
+        Ok(ast::Statement::CreateUser { name, password, superuser })
+    }
+
+    /// Parses a CREATE ROLE statement, after CREATE ROLE.
+    fn parse_create_role(&mut self) -> Result<ast::Statement> {
+        Ok(ast::Statement::CreateRole { name: self.next_ident()? })
+    }
+
+    /// Parses a CREATE CHANGEFEED statement, after CREATE CHANGEFEED.
+    fn parse_create_changefeed(&mut self) -> Result<ast::Statement> {
+        let name = self.next_ident()?;
+        self.expect(Keyword::For.into())?;
+        let mut tables = vec![self.next_table_name()?];
+        while self.next_is(Token::Comma) {
+            tables.push(self.next_table_name()?);
+        }
+        self.expect(Keyword::Into.into())?;
+        let sink = match self.next()? {
+            Token::String(sink) => sink,
+            token => return errsyntax!("expected sink string, got {token}"),
+        };
+        Ok(ast::Statement::CreateChangefeed { name, tables, sink })
+    }
+
+    /// Parses a CREATE TRIGGER statement, after CREATE TRIGGER. The action is
+    /// parsed to check its syntax, but stored as SQL text, since NEW and OLD
+    /// references are bound separately for every written row.
+    fn parse_create_trigger(&mut self) -> Result<ast::Statement> {
+        let name = self.next_ident()?;
+        let timing = match self.next()? {
+            Token::Keyword(Keyword::Before) => TriggerTiming::Before,
+            Token::Keyword(Keyword::After) => TriggerTiming::After,
+            token => return errsyntax!("expected BEFORE or AFTER, got {token}"),
+        };
+        let event = match self.next()? {
+            Token::Keyword(Keyword::Insert) => TriggerEvent::Insert,
+            Token::Keyword(Keyword::Update) => TriggerEvent::Update,
+            Token::Keyword(Keyword::Delete) => TriggerEvent::Delete,
+            token => return errsyntax!("expected INSERT, UPDATE, or DELETE, got {token}"),
+        };
+        self.expect(Keyword::On.into())?;
+        let table = self.next_table_name()?;
+        let (_, action) = self.parse_source(|parser| match parser.peek()? {
+            Some(Token::Keyword(Keyword::Insert | Keyword::Update | Keyword::Delete)) => {
+                parser.parse_statement()
+            }
+            _ => errsyntax!("unexpected token {}", parser.next()?),
+        })?;
+        Ok(ast::Statement::CreateTrigger { name, table, timing, event, action })
+    }
+
+    /// Parses a CREATE FUNCTION statement, after CREATE FUNCTION. The body is
+    /// parsed to check its syntax, but stored as SQL text like trigger actions.
+    fn parse_create_function(&mut self) -> Result<ast::Statement> {
+        let name = self.next_ident()?;
+        self.expect(Token::OpenParen)?;
+        let mut parameters = Vec::new();
+        if !self.next_is(Token::CloseParen) {
+            loop {
+                parameters.push((self.next_ident()?, self.parse_datatype()?));
+                if !self.next_is(Token::Comma) {
+                    break;
+                }
+            }
+            self.expect(Token::CloseParen)?;
+        }
+        self.expect(Keyword::Returns.into())?;
+        let returns = self.parse_datatype()?;
+        self.expect(Keyword::As.into())?;
+        let (_, body) = self.parse_source(|parser| parser.parse_expression())?;
+        Ok(ast::Statement::CreateFunction { name, parameters, returns, body })
+    }
+
+    /// Parses an element with the given closure, and also returns its raw SQL
+    /// text from the input, without trailing whitespace.
+    fn parse_source<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<T>,
+    ) -> Result<(T, String)> {
+        let offset = |parser: &mut Self| -> Result<usize> {
+            parser.peek()?;
+            Ok(parser.peeked.as_ref().map(|(offset, _)| *offset).expect("no peeked token"))
+        };
+        let start = offset(self)?;
+        let result = parse(self)?;
+        let end = offset(self)?;
+        Ok((result, self.lexer.input()[start..end].trim_end().to_string()))
+    }
+
+    /// Parses an ALTER USER statement.
+    fn parse_alter_user(&mut self) -> Result<ast::Statement> {
+        self.expect(Keyword::Alter.into())?;
+        self.expect(Keyword::User.into())?;
+        let name = self.next_ident()?;
+        let password = self.parse_password()?;
+        Ok(ast::Statement::AlterUser { name, password })
+    }
+
+    /// Parses a WITH PASSWORD 'password' clause.
+    fn parse_password(&mut self) -> Result<String> {
+        self.expect(Keyword::With.into())?;
+        self.expect(Keyword::Password.into())?;
+        match self.next()? {
+            Token::String(password) => Ok(password),
+            token => errsyntax!("expected password string, got {token}"),
+        }
+    }
+
+    /// Parses a DROP statement.
+    fn parse_drop(&mut self) -> Result<ast::Statement> {
+        self.expect(Keyword::Drop.into())?;
+        match self.next()? {
+            Token::Keyword(Keyword::Database) => self.parse_drop_database(),
+            Token::Keyword(Keyword::Table) => self.parse_drop_table(),
+            Token::Keyword(Keyword::Role) => {
+                Ok(ast::Statement::DropRole { name: self.next_ident()? })
+            }
+            Token::Keyword(Keyword::Changefeed) => {
+                Ok(ast::Statement::DropChangefeed { name: self.next_ident()? })
+            }
+            Token::Keyword(Keyword::Function) => {
+                Ok(ast::Statement::DropFunction { name: self.next_ident()? })
+            }
+            Token::Keyword(Keyword::Trigger) => {
+                let name = self.next_ident()?;
+                self.expect(Keyword::On.into())?;
+                Ok(ast::Statement::DropTrigger { name, table: self.next_table_name()? })
+            }
+            token => errsyntax!("unexpected token {token}"),
+        }
+    }
+
+    /// Parses a DROP TABLE statement, after DROP TABLE.
     fn parse_drop_table(&mut self) -> Result<ast::Statement> {
-        self.expect(Token::Keyword(Keyword::Drop))?;
-        self.expect(Token::Keyword(Keyword::Table))?;
         let mut if_exists = false;
         if self.next_is(Keyword::If.into()) {
             self.expect(Token::Keyword(Keyword::Exists))?;
             if_exists = true;
         }
-        let name = self.next_ident()?;
+        let name = self.next_table_name()?;
         Ok(ast::Statement::DropTable { name, if_exists })
     }
 
+    /// Parses a DROP DATABASE statement, after DROP DATABASE.
+    fn parse_drop_database(&mut self) -> Result<ast::Statement> {
+        let mut if_exists = false;
+        if self.next_is(Keyword::If.into()) {
+            self.expect(Token::Keyword(Keyword::Exists))?;
+            if_exists = true;
+        }
+        let name = self.next_ident()?;
+        Ok(ast::Statement::DropDatabase { name, if_exists })
+    }
+
+    /// Parses a GRANT statement.
+    fn parse_grant(&mut self) -> Result<ast::Statement> {
+        self.expect(Keyword::Grant.into())?;
+        let grant = self.parse_grant_target()?;
+        self.expect(Keyword::To.into())?;
+        let grantee = self.next_ident()?;
+        Ok(ast::Statement::Grant { grant, grantee })
+    }
+
+    /// Parses a REVOKE statement.
+    fn parse_revoke(&mut self) -> Result<ast::Statement> {
+        self.expect(Keyword::Revoke.into())?;
+        let grant = self.parse_grant_target()?;
+        self.expect(Keyword::From.into())?;
+        let grantee = self.next_ident()?;
+        Ok(ast::Statement::Revoke { grant, grantee })
+    }
+
+    /// Parses the target of a GRANT or REVOKE statement: either a role name, or
+    /// a list of privileges ON a table (or * for all tables).
+    fn parse_grant_target(&mut self) -> Result<Grant> {
+        if let Some(Token::Ident(_)) = self.peek()? {
+            return Ok(Grant::Role(self.next_ident()?));
+        }
+        let mut privileges = Vec::new();
+        loop {
+            privileges.push(match self.next()? {
+                Token::Keyword(Keyword::Select) => Privilege::Select,
+                Token::Keyword(Keyword::Insert) => Privilege::Insert,
+                Token::Keyword(Keyword::Update) => Privilege::Update,
+                Token::Keyword(Keyword::Delete) => Privilege::Delete,
+                Token::Keyword(Keyword::Ddl) => Privilege::Ddl,
+                token => return errsyntax!("expected privilege, got {token}"),
+            });
+            if !self.next_is(Token::Comma) {
+                break;
+            }
+        }
+        self.expect(Keyword::On.into())?;
+        let table = match self.next_is(Token::Asterisk) {
+            true => None,
+            false => Some(self.next_table_name()?),
+        };
+        Ok(Grant::Privileges { privileges, table })
+    }
+
     /// Parses a DELETE statement.
     fn parse_delete(&mut self) -> Result<ast::Statement> {
         self.expect(Keyword::Delete.into())?;
         self.expect(Keyword::From.into())?;
-        let table = self.next_ident()?;
+        let table = self.next_table_name()?;
         Ok(ast::Statement::Delete { table, r#where: self.parse_where_clause()? })
     }
 
@@ -256,7 +733,7 @@ impl Parser<'_> {
     fn parse_insert(&mut self) -> Result<ast::Statement> {
         self.expect(Keyword::Insert.into())?;
         self.expect(Keyword::Into.into())?;
-        let table = self.next_ident()?;
+        let table = self.next_table_name()?;
 
         let mut columns = None;
         if self.next_is(Token::OpenParen) {
@@ -295,7 +772,7 @@ impl Parser<'_> {
     /// Parses an UPDATE statement.
     fn parse_update(&mut self) -> Result<ast::Statement> {
         self.expect(Keyword::Update.into())?;
-        let table = self.next_ident()?;
+        let table = self.next_table_name()?;
         self.expect(Keyword::Set.into())?;
         let mut set = std::collections::BTreeMap::new();
         loop {
@@ -305,7 +782,7 @@ impl Parser<'_> {
                 .then(|| self.parse_expression())
                 .transpose()?;
             if set.contains_key(&column) {
-                return errinput!("column {column} set multiple times");
+                return errsyntax!("column {column} set multiple times");
             }
             set.insert(column, expr);
             if !self.next_is(Token::Comma) {
@@ -320,6 +797,7 @@ impl Parser<'_> {
         Ok(ast::Statement::Select {
             select: self.parse_select_clause()?,
             from: self.parse_from_clause()?,
+            as_of: self.parse_as_of_clause()?,
             r#where: self.parse_where_clause()?,
             group_by: self.parse_group_by_clause()?,
             having: self.parse_having_clause()?,
@@ -344,9 +822,12 @@ impl Parser<'_> {
         loop {
             let expr = self.parse_expression()?;
             let mut label = None;
-            if self.next_is(Keyword::As.into()) || matches!(self.peek()?, Some(Token::Ident(_))) {
+            if !self.as_of_next()?
+                && (self.next_is(Keyword::As.into())
+                    || matches!(self.peek()?, Some(Token::Ident(_))))
+            {
                 if expr == ast::Expression::All {
-                    return errinput!("can't alias *");
+                    return errsyntax!("can't alias *");
                 }
                 label = Some(self.next_ident()?);
             }
@@ -386,9 +867,11 @@ impl Parser<'_> {
 
     // Parses a FROM table.
     fn parse_from_table(&mut self) -> Result<ast::From> {
-        let name = self.next_ident()?;
+        let name = self.next_table_name()?;
         let mut alias = None;
-        if self.next_is(Keyword::As.into()) || matches!(self.peek()?, Some(Token::Ident(_))) {
+        if !self.as_of_next()?
+            && (self.next_is(Keyword::As.into()) || matches!(self.peek()?, Some(Token::Ident(_))))
+        {
             alias = Some(self.next_ident()?)
         };
         Ok(ast::From::Table { name, alias })
@@ -482,35 +965,57 @@ impl Parser<'_> {
     ///
     /// TODO: write a description of the algorithm.
     pub fn parse_expression(&mut self) -> Result<ast::Expression> {
-        self.parse_expression_at(0)
+        let result = self.parse_expression_at(0).map(|(expr, _)| expr);
+        result.map_err(|err| err.with_location(|| self.lexer.location(self.offset)))
     }
 
-    /// Parses an expression at the given minimum precedence.
-    fn parse_expression_at(&mut self, min_precedence: Precedence) -> Result<ast::Expression> {
+    /// Parses an expression at the given minimum precedence. Returns the
+    /// expression and the height of its expression tree.
+    fn parse_expression_at(
+        &mut self,
+        min_precedence: Precedence,
+    ) -> Result<(ast::Expression, usize)> {
+        self.depth += 1;
+        Self::check_depth(self.depth)?;
         // If there is a prefix operator, parse it and its right-hand operand.
         // Otherwise, parse the left-hand atom.
-        let mut lhs = if let Some(prefix) = self.parse_prefix_operator(min_precedence) {
+        let (mut lhs, mut height) = if let Some(prefix) = self.parse_prefix_operator(min_precedence)
+        {
             let at_precedence = prefix.precedence() + prefix.associativity();
-            prefix.build(self.parse_expression_at(at_precedence)?)
+            let (rhs, height) = self.parse_expression_at(at_precedence)?;
+            (prefix.build(rhs), Self::check_depth(height + 1)?)
         } else {
             self.parse_expression_atom()?
         };
         // Apply any postfix operators for the left-hand atom.
         while let Some(postfix) = self.parse_postfix_operator(min_precedence)? {
-            lhs = postfix.build(lhs)
+            lhs = postfix.build(lhs);
+            height = Self::check_depth(height + 1)?;
         }
         // Apply any binary infix operators, parsing the right-hand operand.
         while let Some(infix) = self.parse_infix_operator(min_precedence) {
             let at_precedence = infix.precedence() + infix.associativity();
-            let rhs = self.parse_expression_at(at_precedence)?;
-            lhs = infix.build(lhs, rhs);
+            let (rhs, rhs_height) = self.parse_expression_at(at_precedence)?;
+            lhs = infix.build(lhs, rhs)?;
+            height = Self::check_depth(height.max(rhs_height) + 1)?;
         }
         // Apply any postfix operators after the binary operator. Consider e.g.
         // 1 + NULL IS NULL.
         while let Some(postfix) = self.parse_postfix_operator(min_precedence)? {
-            lhs = postfix.build(lhs)
+            lhs = postfix.build(lhs);
+            height = Self::check_depth(height + 1)?;
+        }
+        self.depth -= 1;
+        Ok((lhs, height))
+    }
+
+    /// Checks that an expression depth doesn't exceed MAX_EXPRESSION_DEPTH,
+    /// returning it.
+    fn check_depth(depth: usize) -> Result<usize> {
+        if depth > MAX_EXPRESSION_DEPTH {
+            return errsyntax!("expression exceeds maximum depth {MAX_EXPRESSION_DEPTH}");
         }
-        Ok(lhs)
+        Ok(depth)
     }
 
     /// Parses an expression atom. This is either:
@@ -519,52 +1024,87 @@ impl Parser<'_> {
     /// * A column name.
     /// * A function call.
     /// * A parenthesized expression.
-    fn parse_expression_atom(&mut self) -> Result<ast::Expression> {
+    ///
+    /// Returns the expression and the height of its expression tree.
+    fn parse_expression_atom(&mut self) -> Result<(ast::Expression, usize)> {
         Ok(match self.next()? {
             // All columns.
-            Token::Asterisk => ast::Expression::All,
+            Token::Asterisk => (ast::Expression::All, 1),
 
             // Literal value.
             Token::Number(n) if n.chars().all(|c| c.is_ascii_digit()) => {
-                ast::Literal::Integer(n.parse()?).into()
+                (ast::Literal::Integer(n.parse()?).into(), 1)
+            }
+            Token::Number(n) => (ast::Literal::Float(n.parse()?).into(), 1),
+            Token::String(s) => (ast::Literal::String(s).into(), 1),
+            Token::Keyword(Keyword::True) => (ast::Literal::Boolean(true).into(), 1),
+            Token::Keyword(Keyword::False) => (ast::Literal::Boolean(false).into(), 1),
+            Token::Keyword(Keyword::Infinity) => (ast::Literal::Float(f64::INFINITY).into(), 1),
+            Token::Keyword(Keyword::NaN) => (ast::Literal::Float(f64::NAN).into(), 1),
+            Token::Keyword(Keyword::Null) => (ast::Literal::Null.into(), 1),
+
+            // Positional parameter, numbered in order of appearance.
+            Token::Question => {
+                self.parameters += 1;
+                (ast::Expression::Parameter(self.parameters - 1), 1)
             }
-            Token::Number(n) => ast::Literal::Float(n.parse()?).into(),
-            Token::String(s) => ast::Literal::String(s).into(),
-            Token::Keyword(Keyword::True) => ast::Literal::Boolean(true).into(),
-            Token::Keyword(Keyword::False) => ast::Literal::Boolean(false).into(),
-            Token::Keyword(Keyword::Infinity) => ast::Literal::Float(f64::INFINITY).into(),
-            Token::Keyword(Keyword::NaN) => ast::Literal::Float(f64::NAN).into(),
-            Token::Keyword(Keyword::Null) => ast::Literal::Null.into(),
 
-            // Function call.
+            // Function call. POINT(x, y) is a keyword, since it's also a
+            // data type, but is otherwise a regular function call.
             Token::Ident(name) if self.next_is(Token::OpenParen) => {
-                let mut args = Vec::new();
-                while !self.next_is(Token::CloseParen) {
-                    if !args.is_empty() {
-                        self.expect(Token::Comma)?;
-                    }
-                    args.push(self.parse_expression()?);
-                }
-                ast::Expression::Function(name, args)
+                self.parse_function_call(name)?
+            }
+            Token::Keyword(Keyword::Point) if self.next_is(Token::OpenParen) => {
+                self.parse_function_call("point".to_string())?
             }
 
             // Column name, either qualified as table.column or unqualified.
             Token::Ident(table) if self.next_is(Token::Period) => {
-                ast::Expression::Column(Some(table), self.next_ident()?)
+                (ast::Expression::Column(Some(table), self.next_ident()?), 1)
             }
-            Token::Ident(column) => ast::Expression::Column(None, column),
+            Token::Ident(column) => (ast::Expression::Column(None, column), 1),
 
-            // Parenthesized expression.
+            // Parenthesized expression, or a row value if there are several
+            // comma-separated expressions.
             Token::OpenParen => {
-                let expr = self.parse_expression()?;
+                let (expr, mut height) = self.parse_expression_at(0)?;
+                if !self.next_is(Token::Comma) {
+                    self.expect(Token::CloseParen)?;
+                    return Ok((expr, height));
+                }
+                let mut exprs = vec![expr];
+                loop {
+                    let (expr, expr_height) = self.parse_expression_at(0)?;
+                    exprs.push(expr);
+                    height = height.max(expr_height);
+                    if !self.next_is(Token::Comma) {
+                        break;
+                    }
+                }
                 self.expect(Token::CloseParen)?;
-                expr
+                (ast::Expression::Tuple(exprs), Self::check_depth(height + 1)?)
             }
 
-            token => return errinput!("expected expression atom, found {token}"),
+            token => return errsyntax!("expected expression atom, found {token}"),
         })
     }
 
+    /// Parses a function call's arguments, after the function name and (.
+    /// Returns the expression and the height of its expression tree.
+    fn parse_function_call(&mut self, name: String) -> Result<(ast::Expression, usize)> {
+        let mut args = Vec::new();
+        let mut height = 0;
+        while !self.next_is(Token::CloseParen) {
+            if !args.is_empty() {
+                self.expect(Token::Comma)?;
+            }
+            let (arg, arg_height) = self.parse_expression_at(0)?;
+            args.push(arg);
+            height = height.max(arg_height);
+        }
+        Ok((ast::Expression::Function(name, args), Self::check_depth(height + 1)?))
+    }
+
     /// Parses a prefix operator, if there is one and its precedence is at least
     /// min_precedence.
     fn parse_prefix_operator(&mut self, min_precedence: Precedence) -> Option<PrefixOperator> {
@@ -584,6 +1124,7 @@ impl Parser<'_> {
     fn parse_infix_operator(&mut self, min_precedence: Precedence) -> Option<InfixOperator> {
         self.next_if_map(|token| {
             let operator = match token {
+                Token::AtAt => InfixOperator::Match,
                 Token::Asterisk => InfixOperator::Multiply,
                 Token::Caret => InfixOperator::Exponentiate,
                 Token::Equal => InfixOperator::Equal,
@@ -591,6 +1132,7 @@ impl Parser<'_> {
                 Token::GreaterThanOrEqual => InfixOperator::GreaterThanOrEqual,
                 Token::Keyword(Keyword::And) => InfixOperator::And,
                 Token::Keyword(Keyword::Like) => InfixOperator::Like,
+                Token::Keyword(Keyword::Match) => InfixOperator::Match,
                 Token::Keyword(Keyword::Or) => InfixOperator::Or,
                 Token::LessOrGreaterThan => InfixOperator::NotEqual,
                 Token::LessThan => InfixOperator::LessThan,
@@ -624,7 +1166,7 @@ impl Parser<'_> {
             let value = match self.next()? {
                 Token::Keyword(Keyword::NaN) => ast::Literal::Float(f64::NAN),
                 Token::Keyword(Keyword::Null) => ast::Literal::Null,
-                token => return errinput!("unexpected token {token}"),
+                token => return errsyntax!("unexpected token {token}"),
             };
             let operator = match not {
                 false => PostfixOperator::Is(value),
@@ -683,6 +1225,7 @@ impl PrefixOperator {
 }
 
 /// Infix operators.
+#[derive(Clone, Copy)]
 enum InfixOperator {
     Add,                // a + b
     And,                // a AND b
@@ -694,6 +1237,7 @@ enum InfixOperator {
     LessThan,           // a < b
     LessThanOrEqual,    // a <= b
     Like,               // a LIKE b
+    Match,              // a MATCH b, a @@ b
     Multiply,           // a * b
     NotEqual,           // a != b
     Or,                 // a OR b
@@ -704,14 +1248,15 @@ enum InfixOperator {
 impl InfixOperator {
     /// The operator precedence.
     ///
-    /// Mostly follows Postgres, except IS and LIKE having same precedence as =.
+    /// Mostly follows Postgres, except IS, LIKE, and MATCH having same precedence
+    /// as =.
     /// This is similar to SQLite and MySQL.
     fn precedence(&self) -> Precedence {
         match self {
             Self::Or => 1,
             Self::And => 2,
             // Self::Not => 3
-            Self::Equal | Self::NotEqual | Self::Like => 4, // and Self::Is
+            Self::Equal | Self::NotEqual | Self::Like | Self::Match => 4, // and Self::Is
             Self::GreaterThan
             | Self::GreaterThanOrEqual
             | Self::LessThan
@@ -730,10 +1275,17 @@ impl InfixOperator {
         }
     }
 
-    /// Builds an AST expression for the infix operator.
-    fn build(self, lhs: ast::Expression, rhs: ast::Expression) -> ast::Expression {
-        let (lhs, rhs) = (Box::new(lhs), Box::new(rhs));
-        match self {
+    /// Builds an AST expression for the infix operator. Comparisons of row
+    /// values are expanded into comparisons of their elements, e.g. (a, b) >
+    /// (1, 2) into a > 1 OR a = 1 AND b > 2.
+    fn build(self, lhs: ast::Expression, rhs: ast::Expression) -> Result<ast::Expression> {
+        let (lhs, rhs) = match (lhs, rhs) {
+            (ast::Expression::Tuple(lhs), ast::Expression::Tuple(rhs)) if self.is_comparison() => {
+                return self.build_row_comparison(lhs, rhs);
+            }
+            (lhs, rhs) => (Box::new(lhs), Box::new(rhs)),
+        };
+        Ok(match self {
             Self::Add => ast::Operator::Add(lhs, rhs).into(),
             Self::And => ast::Operator::And(lhs, rhs).into(),
             Self::Divide => ast::Operator::Divide(lhs, rhs).into(),
@@ -744,12 +1296,71 @@ impl InfixOperator {
             Self::LessThan => ast::Operator::LessThan(lhs, rhs).into(),
             Self::LessThanOrEqual => ast::Operator::LessThanOrEqual(lhs, rhs).into(),
             Self::Like => ast::Operator::Like(lhs, rhs).into(),
+            Self::Match => ast::Operator::Match(lhs, rhs).into(),
             Self::Multiply => ast::Operator::Multiply(lhs, rhs).into(),
             Self::NotEqual => ast::Operator::NotEqual(lhs, rhs).into(),
             Self::Or => ast::Operator::Or(lhs, rhs).into(),
             Self::Remainder => ast::Operator::Remainder(lhs, rhs).into(),
             Self::Subtract => ast::Operator::Subtract(lhs, rhs).into(),
+        })
+    }
+
+    /// Returns true if the operator is a comparison.
+    fn is_comparison(&self) -> bool {
+        matches!(
+            self,
+            Self::Equal
+                | Self::NotEqual
+                | Self::GreaterThan
+                | Self::GreaterThanOrEqual
+                | Self::LessThan
+                | Self::LessThanOrEqual
+        )
+    }
+
+    /// Builds a comparison of row values, which are compared element-wise
+    /// from left to right.
+    fn build_row_comparison(
+        self,
+        lhs: Vec<ast::Expression>,
+        rhs: Vec<ast::Expression>,
+    ) -> Result<ast::Expression> {
+        use ast::Operator::*;
+        if lhs.len() != rhs.len() {
+            return errsyntax!(
+                "can't compare row values of length {} and {}",
+                lhs.len(),
+                rhs.len()
+            );
         }
+        let mut pairs = lhs.into_iter().zip(rhs).rev().map(|(l, r)| (Box::new(l), Box::new(r)));
+        let (last_lhs, last_rhs) = pairs.next().expect("row value can't be empty");
+        Ok(match self {
+            // Equality requires all elements to be equal.
+            Self::Equal => pairs.fold(Equal(last_lhs, last_rhs).into(), |expr, (l, r)| {
+                And(Equal(l, r).into(), Box::new(expr)).into()
+            }),
+            Self::NotEqual => pairs.fold(NotEqual(last_lhs, last_rhs).into(), |expr, (l, r)| {
+                Or(NotEqual(l, r).into(), Box::new(expr)).into()
+            }),
+            // Ordering is decided by the first unequal element, and only the
+            // last element can be equal for >= and <=.
+            Self::GreaterThan
+            | Self::GreaterThanOrEqual
+            | Self::LessThan
+            | Self::LessThanOrEqual => {
+                let strict = |l, r| match self {
+                    Self::GreaterThan | Self::GreaterThanOrEqual => GreaterThan(l, r),
+                    _ => LessThan(l, r),
+                };
+                let last = Self::build(self, *last_lhs, *last_rhs)?;
+                pairs.fold(last, |expr, (l, r)| {
+                    let equal = And(Equal(l.clone(), r.clone()).into(), Box::new(expr));
+                    Or(strict(l, r).into(), equal.into()).into()
+                })
+            }
+            _ => panic!("not a comparison operator"),
+        })
     }
 }
 
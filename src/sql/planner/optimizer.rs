@@ -1,8 +1,10 @@
-use super::Node;
+use super::{Direction, Node};
 use crate::error::Result;
-use crate::sql::types::{Expression, Label, Value};
+use crate::sql::engine::{InformationSchema, SystemTable};
+use crate::sql::types::{DataType, Expression, Label, Table, Value};
 
 use std::collections::HashMap;
+use std::ops::Bound;
 
 /// A plan optimizer, which recursively transforms a plan node to make plan
 /// execution more efficient where possible.
@@ -13,6 +15,8 @@ pub static OPTIMIZERS: &[(&str, Optimizer)] = &[
     ("Constant folding", fold_constants),
     ("Filter pushdown", push_filters),
     ("Index lookup", index_lookup),
+    ("Index range", index_range),
+    ("Order elimination", eliminate_order),
     ("Join type", join_type),
     ("Short circuit", short_circuit),
 ];
@@ -219,7 +223,17 @@ pub fn push_filters(node: Node) -> Result<Node> {
     node.transform(&|node| Ok(xform(node)), &Ok)
 }
 
-/// Uses an index or primary key lookup for a filter when possible.
+/// Secondary index lookups that are estimated to match a larger fraction of
+/// rows than this use a table scan instead, since the index lookup would then
+/// read most of the table anyway, with an extra read per row.
+const INDEX_LOOKUP_MAX_SELECTIVITY: f64 = 0.25;
+
+/// Uses an index, primary key, or full-text index lookup for a filter when
+/// possible.
+///
+/// If the table has statistics (see ANALYZE), the lookup that's estimated to
+/// match the fewest rows is used, and secondary index lookups that match too
+/// many rows are skipped. Otherwise, the first lookup is used.
 pub fn index_lookup(node: Node) -> Result<Node> {
     let transform = |mut node| {
         // Only handle scan filters. filter_pushdown() must have pushed filters
@@ -229,24 +243,48 @@ pub fn index_lookup(node: Node) -> Result<Node> {
         // Convert the filter into conjunctive normal form (a list of ANDs).
         let mut cnf = filter.clone().into_cnf_vec();
 
-        // Find the first expression that's either a primary key or secondary
-        // index lookup. We could be more clever here, but this is fine.
-        let Some((i, column)) = cnf.iter().enumerate().find_map(|(i, expr)| {
+        // Find the expressions that are either a primary key or secondary
+        // index lookup, and pick the most selective one if there are table
+        // statistics. Otherwise, just pick the first one.
+        let mut lookups = cnf.iter().enumerate().filter_map(|(i, expr)| {
             expr.is_column_lookup()
                 .filter(|c| *c == table.primary_key || table.columns[*c].index)
                 .map(|column| (i, column))
-        }) else {
-            return Node::Scan { table, alias, filter: Some(filter) };
+        });
+        let lookup = match &table.statistics {
+            Some(statistics) => lookups
+                .map(|(i, column)| (i, column, statistics.selectivity(&cnf[i])))
+                .filter(|(_, column, selectivity)| {
+                    *column == table.primary_key || *selectivity <= INDEX_LOOKUP_MAX_SELECTIVITY
+                })
+                .min_by(|(_, _, a), (_, _, b)| a.total_cmp(b))
+                .map(|(i, column, _)| (i, column)),
+            None => lookups.next(),
         };
-
-        // Extract the lookup values and expression from the cnf vector.
-        let values = cnf.remove(i).into_column_values(column);
-
-        // Build the primary key or secondary index lookup node.
-        if column == table.primary_key {
-            node = Node::KeyLookup { table, keys: values, alias };
+        // Otherwise, find the first full-text search of a full-text index.
+        let search = cnf.iter().enumerate().find_map(|(i, expr)| {
+            expr.is_search_lookup()
+                .filter(|(c, _)| table.columns[*c].fulltext)
+                .map(|(column, query)| (i, column, query.to_string()))
+        });
+
+        if let Some((i, column)) = lookup {
+            // Extract the lookup values and expression from the cnf vector.
+            let values = cnf.remove(i).into_column_values(column);
+
+            // Build the primary key or secondary index lookup node.
+            if column == table.primary_key {
+                node = Node::KeyLookup { table, keys: values, alias };
+            } else {
+                node = Node::IndexLookup { table, column, values, alias };
+            }
+        } else if let Some((i, column, query)) = search {
+            // The full-text lookup yields exactly the matching rows, so the
+            // MATCH expression can be removed from the cnf vector.
+            cnf.remove(i);
+            node = Node::FullTextLookup { table, column, query, alias };
         } else {
-            node = Node::IndexLookup { table, column, values, alias };
+            return Node::Scan { table, alias, filter: Some(filter) };
         }
 
         // If there's any remaining CNF expressions, add a filter node for them.
@@ -259,6 +297,146 @@ pub fn index_lookup(node: Node) -> Result<Node> {
     node.transform(&Ok, &|n| Ok(transform(n)))
 }
 
+/// Uses a primary key or secondary index range scan for a filter that bounds
+/// the column with comparisons, e.g. id > 3 AND id <= 7, when index_lookup()
+/// couldn't use a lookup. The primary key is preferred, otherwise the first
+/// indexed column is used.
+///
+/// If the table has statistics (see ANALYZE), the range that's estimated to
+/// match the fewest rows is used, and secondary index ranges that match too
+/// many rows are skipped.
+pub fn index_range(node: Node) -> Result<Node> {
+    let transform = |node| {
+        let Node::Scan { table, alias, filter: Some(filter) } = node else { return node };
+        if is_system_table(&table) {
+            return Node::Scan { table, alias, filter: Some(filter) };
+        }
+
+        // Find the bounded ranges of the primary key and indexed columns. The
+        // bounds must have the column's datatype, since the storage order of
+        // mixed types differs from their comparison order.
+        let ranges = table.columns.iter().enumerate().filter_map(|(column, c)| {
+            if column != table.primary_key && !c.index {
+                return None;
+            }
+            let range = filter.column_range(column)?;
+            let values: Vec<_> = [&range.0, &range.1]
+                .into_iter()
+                .filter_map(|bound| match bound {
+                    Bound::Included(value) | Bound::Excluded(value) => Some(value),
+                    Bound::Unbounded => None,
+                })
+                .collect();
+            let valid =
+                !values.is_empty() && values.iter().all(|v| v.datatype() == Some(c.datatype));
+            valid.then_some((column, range))
+        });
+        let range = match &table.statistics {
+            Some(statistics) => ranges
+                .map(|(column, range)| {
+                    let selectivity = statistics.range_selectivity(column, &range);
+                    (column, range, selectivity)
+                })
+                .filter(|(column, _, selectivity)| {
+                    *column == table.primary_key || *selectivity <= INDEX_LOOKUP_MAX_SELECTIVITY
+                })
+                .min_by(|(_, _, a), (_, _, b)| a.total_cmp(b))
+                .map(|(column, range, _)| (column, range)),
+            None => {
+                let ranges: Vec<_> = ranges.collect();
+                let primary_key = ranges.iter().position(|(c, _)| *c == table.primary_key);
+                ranges.into_iter().nth(primary_key.unwrap_or(0))
+            }
+        };
+        let Some((column, range)) = range else {
+            return Node::Scan { table, alias, filter: Some(filter) };
+        };
+
+        // Comparisons that are fully covered by the range can be removed from
+        // the filter. Floats are always filtered, since the range may include
+        // NaNs.
+        let mut cnf = filter.into_cnf_vec();
+        if table.columns[column].datatype != DataType::Float {
+            cnf.retain(|expr| !expr.is_column_range(column));
+        }
+
+        let mut node = if column == table.primary_key {
+            Node::KeyRange { table, range, alias }
+        } else {
+            Node::IndexRange { table, column, range, alias }
+        };
+        if let Some(predicate) = Expression::and_vec(cnf) {
+            node = Node::Filter { source: Box::new(node), predicate };
+        }
+        node
+    };
+    node.transform(&Ok, &|n| Ok(transform(n)))
+}
+
+/// Removes Order nodes whose source already emits rows in the sort order,
+/// e.g. a primary key scan for ORDER BY id, or an index range scan for ORDER
+/// BY the indexed column. This allows ORDER BY ... LIMIT queries to stop
+/// reading rows once the limit is reached.
+pub fn eliminate_order(node: Node) -> Result<Node> {
+    /// Returns the column indexes that the node's rows are ordered by
+    /// (ascending), if any.
+    fn ordering(node: &Node) -> Vec<usize> {
+        // Floats aren't ordered by their storage order, e.g. for NaNs.
+        let ordered = |table: &Table, columns: Vec<usize>| -> Vec<usize> {
+            if is_system_table(table) {
+                return Vec::new();
+            }
+            columns
+                .into_iter()
+                .take_while(|c| table.columns[*c].datatype != DataType::Float)
+                .collect()
+        };
+        match node {
+            Node::Scan { table, .. } | Node::KeyRange { table, .. } => {
+                ordered(table, vec![table.primary_key])
+            }
+            Node::IndexRange { table, column, .. } => {
+                ordered(table, vec![*column, table.primary_key])
+            }
+            Node::Filter { source, .. }
+            | Node::Limit { source, .. }
+            | Node::Offset { source, .. } => ordering(source),
+            Node::Projection { source, expressions, .. } => ordering(source)
+                .into_iter()
+                .map_while(|c| expressions.iter().position(|e| *e == Expression::Column(c)))
+                .collect(),
+            Node::Remap { source, targets } => ordering(source)
+                .into_iter()
+                .map_while(|c| targets.get(c).copied().flatten())
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    let xform = |node| match node {
+        Node::Order { source, key } => {
+            let ordering = ordering(&source);
+            let sorted = key.len() <= ordering.len()
+                && key.iter().zip(&ordering).all(|((expr, direction), column)| {
+                    *expr == Expression::Column(*column) && *direction == Direction::Ascending
+                });
+            if sorted {
+                return *source;
+            }
+            Node::Order { source, key }
+        }
+        node => node,
+    };
+    node.transform(&Ok, &|node| Ok(xform(node)))
+}
+
+/// Returns true if the table is a system or information schema table. These
+/// aren't stored as regular table rows, so they can't be range scanned and
+/// aren't emitted in primary key order.
+fn is_system_table(table: &Table) -> bool {
+    SystemTable::lookup(&table.name).is_some() || InformationSchema::lookup(&table.name).is_some()
+}
+
 /// Uses a hash join instead of a nested loop join for single-column equijoins.
 pub fn join_type(node: Node) -> Result<Node> {
     let xform = |node| match node {
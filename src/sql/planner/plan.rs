@@ -1,14 +1,19 @@
 use super::optimizer::OPTIMIZERS;
 use super::planner::Planner;
+use crate::errinput;
 use crate::error::Result;
 use crate::sql::engine::{Catalog, Transaction};
-use crate::sql::execution::{self, ExecutionResult};
+use crate::sql::execution::{self, CancelFlag, ExecutionResult, MemoryBudget};
 use crate::sql::parser::ast;
-use crate::sql::types::{Expression, Label, Table, Value};
+use crate::sql::types::{
+    Changefeed, Database, Expression, Function, Grant, Label, Privilege, Role, Statistics, Table,
+    Trigger, User, Value, ValueRange,
+};
 
 use itertools::Itertools as _;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
+use std::ops::Bound;
 
 /// A statement execution plan. The root nodes can perform data modifications or
 /// schema changes, in addition to SELECT queries. Beyond the root, the plan is
@@ -33,26 +38,87 @@ use std::collections::HashMap;
 /// node sorts them before emitting the rows to the client.
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Plan {
+    /// A CREATE DATABASE plan. Creates a new, empty database. Errors if the
+    /// database already exists.
+    CreateDatabase { database: Database },
+    /// A DROP DATABASE plan. Drops the given database, including its tables,
+    /// and revokes all privileges on it. Errors if the database does not
+    /// exist, unless if_exists is true, or if it's the default or current
+    /// database.
+    DropDatabase { name: String, if_exists: bool },
     /// A CREATE TABLE plan. Creates a new table with the given schema. Errors
     /// if the table already exists or the schema is invalid.
     CreateTable { schema: Table },
     /// A DROP TABLE plan. Drops the given table. Errors if the table does not
     /// exist, unless if_exists is true.
     DropTable { table: String, if_exists: bool },
+    /// An ANALYZE plan. Scans the given tables and stores their statistics in
+    /// the table schemas, for use by the optimizer.
+    Analyze { tables: Vec<Table> },
+    /// A CREATE USER plan. Creates a new user. The password has already been
+    /// hashed. Errors if the user already exists.
+    CreateUser { user: User },
+    /// An ALTER USER plan. Replaces the user's password hash. Errors if the
+    /// user does not exist.
+    AlterUser { name: String, password_hash: String },
+    /// A CREATE ROLE plan. Creates a new role without privileges. Errors if
+    /// the role already exists.
+    CreateRole { role: Role },
+    /// A DROP ROLE plan. Drops the given role and revokes it from all users.
+    /// Errors if the role does not exist.
+    DropRole { name: String },
+    /// A CREATE CHANGEFEED plan. Creates a new changefeed. Errors if it already
+    /// exists, or a table does not exist.
+    CreateChangefeed { changefeed: Changefeed },
+    /// A DROP CHANGEFEED plan. Drops the given changefeed. Errors if it does
+    /// not exist.
+    DropChangefeed { name: String },
+    /// A CREATE TRIGGER plan. Creates a new trigger. Errors if it already
+    /// exists, the table does not exist, or the action is invalid.
+    CreateTrigger { trigger: Trigger },
+    /// A DROP TRIGGER plan. Drops the given table trigger. Errors if it does
+    /// not exist.
+    DropTrigger { table: String, name: String },
+    /// A CREATE FUNCTION plan. Creates a new function. Errors if it already
+    /// exists.
+    CreateFunction { function: Function },
+    /// A DROP FUNCTION plan. Drops the given function. Errors if it does not
+    /// exist.
+    DropFunction { name: String },
+    /// A GRANT plan. Grants privileges to a user or role, or a role to a user.
+    /// Errors if the grantee does not exist.
+    Grant { grant: Grant, grantee: String },
+    /// A REVOKE plan. Revokes privileges from a user or role, or a role from a
+    /// user. Errors if the grantee does not exist.
+    Revoke { grant: Grant, grantee: String },
     /// A DELETE plan. Deletes rows in table that match the rows from source.
     /// primary_key specifies the primary key column index in the source rows.
-    Delete { table: String, primary_key: usize, source: Node },
+    /// The table's DELETE triggers are fired for each deleted row.
+    Delete { table: String, primary_key: usize, source: Node, triggers: Vec<Trigger> },
     /// An INSERT plan. Inserts rows from source (typically a Values node) into
     /// table. If column_map is given, it maps table → source column indexes and
     /// must have one entry for every column in source. Table columns not
     /// present in source will get the column's default value if set, or error.
-    Insert { table: Table, column_map: Option<HashMap<usize, usize>>, source: Node },
+    /// The table's INSERT triggers are fired for each inserted row.
+    Insert {
+        table: Table,
+        column_map: Option<HashMap<usize, usize>>,
+        source: Node,
+        triggers: Vec<Trigger>,
+    },
     /// An UPDATE plan. Updates rows in table that match the rows from source,
     /// where primary_key specifies the primary key column index in the source
     /// rows. The given column/expression pairs specify the row updates to make,
     /// evaluated using the existing source row, which must be a complete row
-    /// from the update table.
-    Update { table: Table, primary_key: usize, source: Node, expressions: Vec<(usize, Expression)> },
+    /// from the update table. The table's UPDATE triggers are fired for each
+    /// updated row.
+    Update {
+        table: Table,
+        primary_key: usize,
+        source: Node,
+        expressions: Vec<(usize, Expression)>,
+        triggers: Vec<Trigger>,
+    },
     /// A SELECT plan. Recursively executes the query plan tree and returns the
     /// resulting rows.
     Select(Node),
@@ -64,9 +130,16 @@ impl Plan {
         Planner::new(catalog).build(statement)
     }
 
-    /// Executes the plan, consuming it.
-    pub fn execute(self, txn: &(impl Transaction + Catalog)) -> Result<ExecutionResult> {
-        execution::execute_plan(self, txn, txn)
+    /// Executes the plan, consuming it. Aborts with Error::Cancelled if the
+    /// cancellation flag is set during execution, or with an error if it
+    /// exceeds the memory budget.
+    pub fn execute(
+        self,
+        txn: &(impl Transaction + Catalog),
+        cancel: &CancelFlag,
+        memory: &MemoryBudget,
+    ) -> Result<ExecutionResult> {
+        execution::execute_plan(self, txn, txn, cancel, memory)
     }
 
     /// Optimizes the plan, consuming it. See OPTIMIZERS for the list of
@@ -74,19 +147,133 @@ impl Plan {
     pub fn optimize(self) -> Result<Self> {
         let optimize = |node| OPTIMIZERS.iter().try_fold(node, |node, (_, opt)| opt(node));
         Ok(match self {
-            Self::CreateTable { .. } | Self::DropTable { .. } => self,
-            Self::Delete { table, primary_key, source } => {
-                Self::Delete { table, primary_key, source: optimize(source)? }
+            Self::CreateDatabase { .. }
+            | Self::DropDatabase { .. }
+            | Self::CreateTable { .. }
+            | Self::DropTable { .. }
+            | Self::Analyze { .. }
+            | Self::CreateUser { .. }
+            | Self::AlterUser { .. }
+            | Self::CreateRole { .. }
+            | Self::DropRole { .. }
+            | Self::CreateChangefeed { .. }
+            | Self::DropChangefeed { .. }
+            | Self::CreateTrigger { .. }
+            | Self::DropTrigger { .. }
+            | Self::CreateFunction { .. }
+            | Self::DropFunction { .. }
+            | Self::Grant { .. }
+            | Self::Revoke { .. } => self,
+            Self::Delete { table, primary_key, source, triggers } => {
+                Self::Delete { table, primary_key, source: optimize(source)?, triggers }
             }
-            Self::Insert { table, column_map, source } => {
-                Self::Insert { table, column_map, source: optimize(source)? }
+            Self::Insert { table, column_map, source, triggers } => {
+                Self::Insert { table, column_map, source: optimize(source)?, triggers }
             }
-            Self::Update { table, primary_key, source, expressions } => {
-                Self::Update { table, primary_key, source: optimize(source)?, expressions }
+            Self::Update { table, primary_key, source, expressions, triggers } => {
+                let source = optimize(source)?;
+                Self::Update { table, primary_key, source, expressions, triggers }
             }
             Self::Select(root) => Self::Select(optimize(root)?),
         })
     }
+
+    /// Checks that the given user is allowed to execute the plan. Superusers
+    /// can execute any plan. Other users need the relevant table privileges,
+    /// granted either directly or via one of their roles, and can't manage
+    /// databases, users, roles, functions, or changefeeds (which write to
+    /// server files) except for changing their own password. Privileges apply
+    /// to the catalog's current database.
+    ///
+    /// UPDATE and DELETE only require the UPDATE and DELETE privilege, not
+    /// SELECT, even though they read the rows they modify. CREATE TRIGGER also
+    /// requires the privilege to write to the action's table, but the actions
+    /// themselves run without privilege checks when the trigger fires.
+    pub fn authorize(&self, user: &str, catalog: &impl Catalog) -> Result<()> {
+        let Some(user) = catalog.get_user(user)? else {
+            return errinput!("user {user} does not exist");
+        };
+        if user.superuser {
+            return Ok(());
+        }
+        let target;
+        let required = match self {
+            Self::CreateTable { schema } => vec![(schema.name.as_str(), Privilege::Ddl)],
+            Self::DropTable { table, .. } => vec![(table.as_str(), Privilege::Ddl)],
+            Self::Analyze { tables } => {
+                tables.iter().map(|t| (t.name.as_str(), Privilege::Ddl)).collect()
+            }
+            Self::CreateTrigger { trigger } => {
+                target = trigger.target()?;
+                vec![(trigger.table.as_str(), Privilege::Ddl), (target.0.as_str(), target.1)]
+            }
+            Self::DropTrigger { table, .. } => vec![(table.as_str(), Privilege::Ddl)],
+            Self::Delete { table, .. } => vec![(table.as_str(), Privilege::Delete)],
+            Self::Insert { table, .. } => vec![(table.name.as_str(), Privilege::Insert)],
+            Self::Update { table, .. } => vec![(table.name.as_str(), Privilege::Update)],
+            Self::Select(root) => {
+                root.tables().into_iter().map(|t| (t, Privilege::Select)).collect()
+            }
+            Self::AlterUser { name, .. } if *name == user.name => Vec::new(),
+            Self::CreateDatabase { .. }
+            | Self::DropDatabase { .. }
+            | Self::CreateUser { .. }
+            | Self::AlterUser { .. }
+            | Self::CreateRole { .. }
+            | Self::DropRole { .. }
+            | Self::CreateChangefeed { .. }
+            | Self::DropChangefeed { .. }
+            | Self::CreateFunction { .. }
+            | Self::DropFunction { .. }
+            | Self::Grant { .. }
+            | Self::Revoke { .. } => {
+                return errinput!("permission denied: user {} is not a superuser", user.name)
+            }
+        };
+        Self::check_privileges(&user, required, catalog)
+    }
+
+    /// Checks that the given user has the given table privileges, e.g. for
+    /// reading changes via Session::changes(). Superusers have all privileges.
+    pub fn authorize_tables(
+        user: &str,
+        required: Vec<(&str, Privilege)>,
+        catalog: &impl Catalog,
+    ) -> Result<()> {
+        let Some(user) = catalog.get_user(user)? else {
+            return errinput!("user {user} does not exist");
+        };
+        if user.superuser {
+            return Ok(());
+        }
+        Self::check_privileges(&user, required, catalog)
+    }
+
+    /// Checks that a user has the given table privileges, either directly or
+    /// via one of their roles.
+    fn check_privileges(
+        user: &User,
+        required: Vec<(&str, Privilege)>,
+        catalog: &impl Catalog,
+    ) -> Result<()> {
+        let roles: Vec<Role> = user
+            .roles
+            .iter()
+            .filter_map(|role| catalog.get_role(role).transpose())
+            .try_collect()?;
+        let database = catalog.database();
+        for (table, privilege) in required {
+            if !user.privileges.allows(database, table, privilege)
+                && !roles.iter().any(|role| role.privileges.allows(database, table, privilege))
+            {
+                return errinput!(
+                    "permission denied: user {} lacks {privilege} privilege on table {table}",
+                    user.name
+                );
+            }
+        }
+        Ok(())
+    }
 }
 
 /// A query plan node. Returns a row iterator, and can be nested.
@@ -110,12 +297,21 @@ pub enum Node {
         right_column: usize,
         outer: bool,
     },
+    /// Looks up the given search query in a column's full-text index and emits
+    /// matching rows.
+    FullTextLookup { table: Table, column: usize, query: String, alias: Option<String> },
     /// Looks up the given values in a secondary index and emits matching rows.
     /// NULL and NaN values are considered equal, to allow IS NULL and IS NAN
     /// index lookups, as is -0.0 and 0.0.
     IndexLookup { table: Table, column: usize, values: Vec<Value>, alias: Option<String> },
+    /// Scans a range of values in a secondary index and emits matching rows,
+    /// ordered by the column value and then the primary key. NULLs are never
+    /// in the range.
+    IndexRange { table: Table, column: usize, range: ValueRange, alias: Option<String> },
     /// Looks up the given primary keys and emits their rows.
     KeyLookup { table: Table, keys: Vec<Value>, alias: Option<String> },
+    /// Scans a range of primary keys and emits their rows in primary key order.
+    KeyRange { table: Table, range: ValueRange, alias: Option<String> },
     /// Only emits the first limit rows from the source, discards the rest.
     Limit { source: Box<Node>, limit: usize },
     /// Joins the left and right sources on the given predicate by buffering the
@@ -152,8 +348,11 @@ impl Node {
     pub fn columns(&self) -> usize {
         match self {
             // Source nodes emit all table columns.
-            Self::IndexLookup { table, .. }
+            Self::FullTextLookup { table, .. }
+            | Self::IndexLookup { table, .. }
+            | Self::IndexRange { table, .. }
             | Self::KeyLookup { table, .. }
+            | Self::KeyRange { table, .. }
             | Self::Scan { table, .. } => table.columns.len(),
 
             // Some nodes modify the column set.
@@ -180,14 +379,49 @@ impl Node {
         }
     }
 
+    /// Returns the names of all tables read by the node and its descendants.
+    pub fn tables(&self) -> BTreeSet<&str> {
+        self.schemas().into_iter().map(|table| table.name.as_str()).collect()
+    }
+
+    /// Returns the schemas of all tables read by the node and its descendants,
+    /// once for each read.
+    pub fn schemas(&self) -> Vec<&Table> {
+        match self {
+            Self::FullTextLookup { table, .. }
+            | Self::IndexLookup { table, .. }
+            | Self::IndexRange { table, .. }
+            | Self::KeyLookup { table, .. }
+            | Self::KeyRange { table, .. }
+            | Self::Scan { table, .. } => vec![table],
+
+            Self::HashJoin { left, right, .. } | Self::NestedLoopJoin { left, right, .. } => {
+                left.schemas().into_iter().chain(right.schemas()).collect()
+            }
+
+            Self::Aggregate { source, .. }
+            | Self::Filter { source, .. }
+            | Self::Limit { source, .. }
+            | Self::Offset { source, .. }
+            | Self::Order { source, .. }
+            | Self::Projection { source, .. }
+            | Self::Remap { source, .. } => source.schemas(),
+
+            Self::Nothing { .. } | Self::Values { .. } => Vec::new(),
+        }
+    }
+
     /// Returns a label for a column, if any, by tracing the column through the
     /// plan tree. Only used for query result headers and plan display purposes,
     /// not to look up expression columns (see Scope).
     pub fn column_label(&self, index: usize) -> Label {
         match self {
             // Source nodes use the table/column name.
-            Self::IndexLookup { table, alias, .. }
+            Self::FullTextLookup { table, alias, .. }
+            | Self::IndexLookup { table, alias, .. }
+            | Self::IndexRange { table, alias, .. }
             | Self::KeyLookup { table, alias, .. }
+            | Self::KeyRange { table, alias, .. }
             | Self::Scan { table, alias, .. } => Label::Qualified(
                 alias.as_ref().unwrap_or(&table.name).clone(),
                 table.columns[index].name.clone(),
@@ -238,6 +472,30 @@ impl Node {
         }
     }
 
+    /// Returns the node's child nodes, i.e. its inputs, from left to right.
+    pub fn children(&self) -> Vec<&Node> {
+        match self {
+            Self::Aggregate { source, .. }
+            | Self::Filter { source, .. }
+            | Self::Limit { source, .. }
+            | Self::Offset { source, .. }
+            | Self::Order { source, .. }
+            | Self::Projection { source, .. }
+            | Self::Remap { source, .. } => vec![source],
+            Self::HashJoin { left, right, .. } | Self::NestedLoopJoin { left, right, .. } => {
+                vec![left, right]
+            }
+            Self::FullTextLookup { .. }
+            | Self::IndexLookup { .. }
+            | Self::IndexRange { .. }
+            | Self::KeyLookup { .. }
+            | Self::KeyRange { .. }
+            | Self::Nothing { .. }
+            | Self::Scan { .. }
+            | Self::Values { .. } => Vec::new(),
+        }
+    }
+
     /// Recursively transforms query nodes depth-first by applying the given
     /// closures before and after descending.
     pub fn transform(
@@ -277,8 +535,11 @@ impl Node {
             }
             Self::Remap { source, targets } => Self::Remap { source: xform(source)?, targets },
 
-            Self::IndexLookup { .. }
+            Self::FullTextLookup { .. }
+            | Self::IndexLookup { .. }
+            | Self::IndexRange { .. }
             | Self::KeyLookup { .. }
+            | Self::KeyRange { .. }
             | Self::Nothing { .. }
             | Self::Scan { .. }
             | Self::Values { .. } => self,
@@ -330,9 +591,12 @@ impl Node {
             }
 
             Self::Aggregate { .. }
+            | Self::FullTextLookup { .. }
             | Self::HashJoin { .. }
             | Self::IndexLookup { .. }
+            | Self::IndexRange { .. }
             | Self::KeyLookup { .. }
+            | Self::KeyRange { .. }
             | Self::Limit { .. }
             | Self::NestedLoopJoin { predicate: None, .. }
             | Self::Nothing { .. }
@@ -391,32 +655,166 @@ impl From<ast::Direction> for Direction {
 }
 
 /// Formats the plan as an EXPLAIN tree.
+/// Execution statistics of a statement, output by EXPLAIN ANALYZE.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Analysis {
+    /// The number of rows returned or written.
+    pub rows: u64,
+    /// The peak memory usage in bytes, see MemoryBudget.
+    pub peak_memory: usize,
+    /// The statement's memory limit in bytes, if any.
+    pub memory_limit: Option<usize>,
+}
+
+impl std::fmt::Display for Analysis {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Rows: {}", self.rows)?;
+        write!(f, "Peak memory: {} bytes", self.peak_memory)?;
+        if let Some(limit) = self.memory_limit {
+            write!(f, " (limit {limit} bytes)")?;
+        }
+        Ok(())
+    }
+}
+
 impl std::fmt::Display for Plan {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
+            Self::Select(root) => root.format(f, "", true, true),
+            plan => {
+                plan.format_root(f)?;
+                match plan.source() {
+                    Some(source) => source.format(f, "", false, true),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+impl Plan {
+    /// Formats the plan in the given EXPLAIN format, along with the execution
+    /// statistics for EXPLAIN ANALYZE. These follow the text plan tree, and
+    /// label the DOT graph.
+    pub fn explain(&self, format: ast::ExplainFormat, analysis: Option<&Analysis>) -> String {
+        match (format, analysis) {
+            (ast::ExplainFormat::Text, None) => self.to_string(),
+            (ast::ExplainFormat::Text, Some(analysis)) => format!("{self}\n{analysis}"),
+            (ast::ExplainFormat::Dot, analysis) => self.to_dot(analysis),
+        }
+    }
+
+    /// Formats the plan as a Graphviz DOT graph, see DotGraph.
+    fn to_dot(&self, analysis: Option<&Analysis>) -> String {
+        let mut graph = DotGraph { label: analysis.map(|a| a.to_string()), ..Default::default() };
+        if let Self::Select(root) = self {
+            graph.add_node(root);
+            return graph.to_string();
+        }
+        let mut line = String::new();
+        self.format_root(&mut line).expect("formatting failed");
+        let id = graph.add(&line);
+        if let Some(source) = self.source() {
+            let child = graph.add_node(source);
+            graph.edges.push((child, id));
+        }
+        graph.to_string()
+    }
+
+    /// Returns the plan's source node, if any. For SELECT plans, this is the
+    /// root node.
+    fn source(&self) -> Option<&Node> {
+        match self {
+            Self::Delete { source, .. }
+            | Self::Insert { source, .. }
+            | Self::Update { source, .. }
+            | Self::Select(source) => Some(source),
+            _ => None,
+        }
+    }
+
+    /// Formats the plan's root as a single line, without its source node. For
+    /// SELECT plans, this formats the root node.
+    fn format_root(&self, f: &mut impl std::fmt::Write) -> std::fmt::Result {
+        match self {
+            Self::CreateDatabase { database } => write!(f, "CreateDatabase: {}", database.name),
+            Self::DropDatabase { name, .. } => write!(f, "DropDatabase: {name}"),
             Self::CreateTable { schema } => write!(f, "CreateTable: {}", schema.name),
             Self::DropTable { table, .. } => write!(f, "DropTable: {table}"),
-            Self::Delete { table, source, .. } => {
-                write!(f, "Delete: {table}")?;
-                source.format(f, "", false, true)
+            Self::Analyze { tables } => {
+                write!(f, "Analyze: {}", tables.iter().map(|t| t.name.as_str()).join(", "))
+            }
+            Self::CreateUser { user } => write!(f, "CreateUser: {}", user.name),
+            Self::AlterUser { name, .. } => write!(f, "AlterUser: {name}"),
+            Self::CreateRole { role } => write!(f, "CreateRole: {}", role.name),
+            Self::DropRole { name } => write!(f, "DropRole: {name}"),
+            Self::CreateChangefeed { changefeed } => {
+                write!(f, "CreateChangefeed: {}", changefeed.name)
             }
-            Self::Insert { table, source, .. } => {
-                write!(f, "Insert: {}", table.name)?;
-                source.format(f, "", false, true)
+            Self::DropChangefeed { name } => write!(f, "DropChangefeed: {name}"),
+            Self::CreateTrigger { trigger } => {
+                write!(f, "CreateTrigger: {} on {}", trigger.name, trigger.table)
             }
-            Self::Update { table, source, expressions, .. } => {
+            Self::DropTrigger { table, name } => write!(f, "DropTrigger: {name} on {table}"),
+            Self::CreateFunction { function } => write!(f, "CreateFunction: {}", function.name),
+            Self::DropFunction { name } => write!(f, "DropFunction: {name}"),
+            Self::Grant { grant, grantee } => write!(f, "Grant: {grant} TO {grantee}"),
+            Self::Revoke { grant, grantee } => write!(f, "Revoke: {grant} FROM {grantee}"),
+            Self::Delete { table, triggers, .. } => {
+                write!(f, "Delete: {table}{}", format_triggers(triggers))
+            }
+            Self::Insert { table, triggers, .. } => {
+                write!(f, "Insert: {}{}", table.name, format_triggers(triggers))
+            }
+            Self::Update { table, source, expressions, triggers, .. } => {
                 let expressions = expressions
                     .iter()
                     .map(|(i, expr)| format!("{}={}", table.columns[*i].name, expr.format(source)))
                     .join(", ");
-                write!(f, "Update: {} ({expressions})", table.name)?;
-                source.format(f, "", false, true)
+                write!(f, "Update: {} ({expressions}){}", table.name, format_triggers(triggers))
             }
-            Self::Select(root) => root.format(f, "", true, true),
+            Self::Select(root) => root.format_node(f),
         }
     }
 }
 
+/// Formats the estimated number of rows read from a table as an EXPLAIN
+/// suffix, if the table has statistics.
+fn format_estimate(table: &Table, selectivity: impl FnOnce(&Statistics) -> f64) -> String {
+    let Some(statistics) = &table.statistics else {
+        return String::new();
+    };
+    let rows = (statistics.rows as f64 * selectivity(statistics)).round();
+    format!(" (estimated rows: {rows})")
+}
+
+/// Formats a range of column values for EXPLAIN, e.g. id > 3 AND id <= 7.
+fn format_range(column: &str, (start, end): &ValueRange) -> String {
+    let start = match start {
+        Bound::Included(value) => Some(format!("{column} >= {value}")),
+        Bound::Excluded(value) => Some(format!("{column} > {value}")),
+        Bound::Unbounded => None,
+    };
+    let end = match end {
+        Bound::Included(value) => Some(format!("{column} <= {value}")),
+        Bound::Excluded(value) => Some(format!("{column} < {value}")),
+        Bound::Unbounded => None,
+    };
+    match (start, end) {
+        (Some(start), Some(end)) => format!("{start} AND {end}"),
+        (Some(bound), None) | (None, Some(bound)) => bound,
+        (None, None) => "all".to_string(),
+    }
+}
+
+/// Formats a write plan's triggers as an EXPLAIN suffix, if any.
+fn format_triggers(triggers: &[Trigger]) -> String {
+    if triggers.is_empty() {
+        return String::new();
+    }
+    format!(" (triggers: {})", triggers.iter().map(|t| t.name.as_str()).join(", "))
+}
+
 impl std::fmt::Display for Node {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         self.format(f, "", true, true)
@@ -453,7 +851,17 @@ impl Node {
             prefix.to_string()
         };
 
-        // Format the node.
+        // Format the node, and recurse into its children.
+        self.format_node(f)?;
+        let children = self.children();
+        for (i, child) in children.iter().enumerate() {
+            child.format(f, &prefix, false, i == children.len() - 1)?;
+        }
+        Ok(())
+    }
+
+    /// Formats the node itself as a single line, without its children.
+    fn format_node(&self, f: &mut impl std::fmt::Write) -> std::fmt::Result {
         match self {
             Self::Aggregate { source, aggregates, group_by } => {
                 let aggregates = group_by
@@ -462,11 +870,9 @@ impl Node {
                     .chain(aggregates.iter().map(|agg| agg.format(source)))
                     .join(", ");
                 write!(f, "Aggregate: {aggregates}")?;
-                source.format(f, &prefix, false, true)?;
             }
             Self::Filter { source, predicate } => {
-                write!(f, "Filter: {}", predicate.format(source))?;
-                source.format(f, &prefix, false, true)?;
+                write!(f, "Filter: {}", predicate.format(source))?
             }
             Self::HashJoin { left, left_column, right, right_column, outer } => {
                 let kind = if *outer { "outer" } else { "inner" };
@@ -479,11 +885,17 @@ impl Node {
                     label => format!("{label}"),
                 };
                 write!(f, "HashJoin: {kind} on {left_column} = {right_column}")?;
-                left.format(f, &prefix, false, false)?;
-                right.format(f, &prefix, false, true)?;
             }
-            Self::IndexLookup { table, column, alias, values } => {
+            Self::FullTextLookup { table, column, query, alias } => {
                 let column = &table.columns[*column].name;
+                write!(f, "FullTextLookup: {}.{column}", table.name)?;
+                if let Some(alias) = alias {
+                    write!(f, " as {alias}.{column}")?;
+                }
+                write!(f, " MATCH {}", Value::String(query.clone()))?;
+            }
+            Self::IndexLookup { table, column: index, alias, values } => {
+                let column = &table.columns[*index].name;
                 write!(f, "IndexLookup: {}.{column}", table.name)?;
                 if let Some(alias) = alias {
                     write!(f, " as {alias}.{column}")?;
@@ -493,6 +905,7 @@ impl Node {
                 } else {
                     write!(f, " ({} values)", values.len())?;
                 }
+                write!(f, "{}", format_estimate(table, |s| s.lookup_selectivity(*index, values)))?;
             }
             Self::KeyLookup { table, alias, keys } => {
                 write!(f, "KeyLookup: {}", table.name)?;
@@ -504,32 +917,50 @@ impl Node {
                 } else {
                     write!(f, " ({} keys)", keys.len())?;
                 }
+                write!(
+                    f,
+                    "{}",
+                    format_estimate(table, |s| s.lookup_selectivity(table.primary_key, keys))
+                )?;
             }
-            Self::Limit { source, limit } => {
-                write!(f, "Limit: {limit}")?;
-                source.format(f, &prefix, false, true)?;
+            Self::IndexRange { table, column: index, alias, range } => {
+                let column = &table.columns[*index].name;
+                write!(f, "IndexRange: {}.{column}", table.name)?;
+                if let Some(alias) = alias {
+                    write!(f, " as {alias}.{column}")?;
+                }
+                write!(f, " ({})", format_range(column, range))?;
+                write!(f, "{}", format_estimate(table, |s| s.range_selectivity(*index, range)))?;
             }
-            Self::NestedLoopJoin { left, right, predicate, outer, .. } => {
+            Self::KeyRange { table, alias, range } => {
+                write!(f, "KeyRange: {}", table.name)?;
+                if let Some(alias) = alias {
+                    write!(f, " as {alias}")?;
+                }
+                let column = &table.columns[table.primary_key].name;
+                write!(f, " ({})", format_range(column, range))?;
+                write!(
+                    f,
+                    "{}",
+                    format_estimate(table, |s| s.range_selectivity(table.primary_key, range))
+                )?;
+            }
+            Self::Limit { limit, .. } => write!(f, "Limit: {limit}")?,
+            Self::NestedLoopJoin { predicate, outer, .. } => {
                 let kind = if *outer { "outer" } else { "inner" };
                 write!(f, "NestedLoopJoin: {kind}")?;
                 if let Some(predicate) = predicate {
                     write!(f, " on {}", predicate.format(self))?;
                 }
-                left.format(f, &prefix, false, false)?;
-                right.format(f, &prefix, false, true)?;
             }
             Self::Nothing { .. } => write!(f, "Nothing")?,
-            Self::Offset { source, offset } => {
-                write!(f, "Offset: {offset}")?;
-                source.format(f, &prefix, false, true)?;
-            }
+            Self::Offset { offset, .. } => write!(f, "Offset: {offset}")?,
             Self::Order { source, key: orders } => {
                 let orders = orders
                     .iter()
                     .map(|(expr, dir)| format!("{} {dir}", expr.format(source)))
                     .join(", ");
                 write!(f, "Order: {orders}")?;
-                source.format(f, &prefix, false, true)?;
             }
             Self::Projection { source, expressions, aliases } => {
                 let expressions = expressions
@@ -541,7 +972,6 @@ impl Node {
                     })
                     .join(", ");
                 write!(f, "Projection: {expressions}")?;
-                source.format(f, &prefix, false, true)?;
             }
             Self::Remap { source, targets } => {
                 let remap = remap_sources(targets)
@@ -568,7 +998,6 @@ impl Node {
                 if !dropped.is_empty() {
                     write!(f, " (dropped: {dropped})")?;
                 }
-                source.format(f, &prefix, false, true)?;
             }
             Self::Scan { table, alias, filter } => {
                 write!(f, "Scan: {}", table.name)?;
@@ -578,6 +1007,11 @@ impl Node {
                 if let Some(filter) = filter {
                     write!(f, " ({})", filter.format(self))?;
                 }
+                write!(
+                    f,
+                    "{}",
+                    format_estimate(table, |s| filter.as_ref().map_or(1.0, |f| s.selectivity(f)))
+                )?;
             }
             Self::Values { rows, .. } => {
                 write!(f, "Values: ")?;
@@ -592,6 +1026,67 @@ impl Node {
     }
 }
 
+/// A Graphviz DOT graph of a plan, for EXPLAIN (FORMAT DOT). Each plan node is
+/// a graph node labeled with its operator and properties, as in the EXPLAIN
+/// tree, with edges from child to parent nodes in the direction rows flow.
+#[derive(Default)]
+struct DotGraph {
+    /// The graph label, if any.
+    label: Option<String>,
+    /// Node labels, by node ID.
+    nodes: Vec<String>,
+    /// Edges from child to parent node IDs.
+    edges: Vec<(usize, usize)>,
+}
+
+impl DotGraph {
+    /// Adds a node labeled by the given EXPLAIN line, returning its ID. The
+    /// operator and its properties are placed on separate lines.
+    fn add(&mut self, line: &str) -> usize {
+        let label = match line.split_once(": ") {
+            Some((operator, properties)) => format!("{operator}\n{properties}"),
+            None => line.to_string(),
+        };
+        self.nodes.push(label);
+        self.nodes.len() - 1
+    }
+
+    /// Recursively adds a plan node and its children, returning its ID.
+    fn add_node(&mut self, node: &Node) -> usize {
+        let mut line = String::new();
+        node.format_node(&mut line).expect("formatting failed");
+        let id = self.add(&line);
+        for child in node.children() {
+            let child = self.add_node(child);
+            self.edges.push((child, id));
+        }
+        id
+    }
+
+    /// Escapes a label as a DOT string.
+    fn escape(label: &str) -> String {
+        label.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+    }
+}
+
+impl std::fmt::Display for DotGraph {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "digraph plan {{")?;
+        writeln!(f, "  rankdir=BT;")?;
+        writeln!(f, "  node [shape=box];")?;
+        if let Some(label) = &self.label {
+            writeln!(f, "  label=\"{}\";", Self::escape(label))?;
+        }
+        for (id, label) in self.nodes.iter().enumerate() {
+            writeln!(f, "  n{id} [label=\"{}\"];", Self::escape(label))?;
+        }
+        for (from, to) in &self.edges {
+            writeln!(f, "  n{from} -> n{to};")?;
+        }
+        write!(f, "}}")
+    }
+}
+
 /// Inverts a Remap targets vector to a vector of source indexes, with None
 /// for columns that weren't targeted.
 pub fn remap_sources(targets: &[Option<usize>]) -> Vec<Option<usize>> {
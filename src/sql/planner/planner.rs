@@ -5,7 +5,10 @@ use crate::errinput;
 use crate::error::Result;
 use crate::sql::engine::Catalog;
 use crate::sql::parser::ast;
-use crate::sql::types::{Column, Expression, Label, Table, Value};
+use crate::sql::types::{
+    self, Changefeed, Column, Database, Expression, Function, Label, Role, Table, Trigger,
+    TriggerEvent, Ttl, User, Value,
+};
 
 use itertools::Itertools as _;
 use std::collections::{BTreeMap, HashMap, HashSet};
@@ -23,27 +26,96 @@ impl<'a, C: Catalog> Planner<'a, C> {
     }
 
     /// Builds a plan for an AST statement.
-    pub fn build(&mut self, statement: ast::Statement) -> Result<Plan> {
+    pub fn build(&mut self, mut statement: ast::Statement) -> Result<Plan> {
         use ast::Statement::*;
+        self.inline_functions(&mut statement)?;
         match statement {
-            CreateTable { name, columns } => self.build_create_table(name, columns),
+            CreateDatabase { name } => Ok(Plan::CreateDatabase { database: Database { name } }),
+            DropDatabase { name, if_exists } => Ok(Plan::DropDatabase { name, if_exists }),
+            CreateTable { name, columns, options } => {
+                self.build_create_table(name, columns, options)
+            }
             DropTable { name, if_exists } => Ok(Plan::DropTable { table: name, if_exists }),
+            Analyze { table: Some(table) } => {
+                Ok(Plan::Analyze { tables: vec![self.catalog.must_get_table(&table)?] })
+            }
+            Analyze { table: None } => Ok(Plan::Analyze { tables: self.catalog.list_tables()? }),
+            CreateUser { name, password, superuser } => {
+                Ok(Plan::CreateUser { user: User::new(name, &password, superuser)? })
+            }
+            AlterUser { name, password } => {
+                Ok(Plan::AlterUser { name, password_hash: User::hash_password(&password)? })
+            }
+            CreateRole { name } => Ok(Plan::CreateRole { role: Role::new(name)? }),
+            DropRole { name } => Ok(Plan::DropRole { name }),
+            CreateChangefeed { name, tables, sink } => {
+                // Changefeeds are run in the default database.
+                if self.catalog.database() != Database::DEFAULT {
+                    return errinput!(
+                        "changefeeds can only be created in database {}",
+                        Database::DEFAULT
+                    );
+                }
+                Ok(Plan::CreateChangefeed { changefeed: Changefeed { name, tables, sink } })
+            }
+            DropChangefeed { name } => Ok(Plan::DropChangefeed { name }),
+            CreateTrigger { name, table, timing, event, action } => {
+                Ok(Plan::CreateTrigger { trigger: Trigger { name, table, timing, event, action } })
+            }
+            DropTrigger { name, table } => Ok(Plan::DropTrigger { table, name }),
+            CreateFunction { name, parameters, returns, body } => {
+                self.build_create_function(Function { name, parameters, returns, body })
+            }
+            DropFunction { name } => Ok(Plan::DropFunction { name }),
+            Grant { grant, grantee } => {
+                if let types::Grant::Privileges { table: Some(table), .. } = &grant {
+                    self.catalog.must_get_table(table)?;
+                }
+                Ok(Plan::Grant { grant, grantee })
+            }
+            Revoke { grant, grantee } => Ok(Plan::Revoke { grant, grantee }),
             Delete { table, r#where } => self.build_delete(table, r#where),
             Insert { table, columns, values } => self.build_insert(table, columns, values),
             Update { table, set, r#where } => self.build_update(table, set, r#where),
-            Select { select, from, r#where, group_by, having, order_by, offset, limit } => {
+            Select {
+                select,
+                from,
+                as_of: _,
+                r#where,
+                group_by,
+                having,
+                order_by,
+                offset,
+                limit,
+            } => {
                 self.build_select(select, from, r#where, group_by, having, order_by, offset, limit)
             }
 
-            // Transaction and explain statements are handled by Session.
-            Begin { .. } | Commit | Rollback | Explain(_) => {
-                panic!("unexpected statement {statement:?}")
-            }
+            // Transaction, explain, set, use, cursor, and online index build
+            // statements are handled by Session.
+            Begin { .. }
+            | Commit
+            | Rollback
+            | Savepoint { .. }
+            | RollbackToSavepoint { .. }
+            | ReleaseSavepoint { .. }
+            | Explain { .. }
+            | Set { .. }
+            | Use { .. }
+            | CreateIndex { .. }
+            | Declare { .. }
+            | Fetch { .. }
+            | Close { .. } => panic!("unexpected statement {statement:?}"),
         }
     }
 
     /// Builds a CREATE TABLE plan.
-    fn build_create_table(&self, name: String, columns: Vec<ast::Column>) -> Result<Plan> {
+    fn build_create_table(
+        &self,
+        name: String,
+        columns: Vec<ast::Column>,
+        options: Vec<(String, ast::Expression)>,
+    ) -> Result<Plan> {
         // Most schema validation happens during execution via Table.validate().
         // However, the AST specifies the primary key as a column field, while
         // the schema stores it as a column index, so we have to map that here.
@@ -53,6 +125,7 @@ impl<'a, C: Catalog> Planner<'a, C> {
         if columns.iter().filter(|c| c.primary_key).count() > 1 {
             return errinput!("multiple primary keys for table {name}");
         }
+        let ttl = Self::build_ttl(&columns, options)?;
         let columns = columns
             .into_iter()
             .map(|c| {
@@ -69,10 +142,106 @@ impl<'a, C: Catalog> Planner<'a, C> {
                     unique: c.unique || c.primary_key,
                     index: (c.index || c.unique || c.references.is_some()) && !c.primary_key,
                     references: c.references,
+                    fulltext: c.fulltext,
                 })
             })
             .collect::<Result<_>>()?;
-        Ok(Plan::CreateTable { schema: Table { name, primary_key, columns } })
+        Ok(Plan::CreateTable {
+            schema: Table { name, primary_key, columns, ttl, statistics: None },
+        })
+    }
+
+    /// Builds a table TTL from CREATE TABLE options. The ttl option gives the
+    /// duration, and ttl_column the name of the TTL column, which is mapped to
+    /// a column index. The TTL is validated with the table.
+    fn build_ttl(
+        columns: &[ast::Column],
+        options: Vec<(String, ast::Expression)>,
+    ) -> Result<Option<Ttl>> {
+        let (mut duration, mut column) = (None, None);
+        for (name, value) in options {
+            match (name.as_str(), value) {
+                ("ttl", _) if duration.is_some() => return errinput!("ttl already set"),
+                ("ttl", ast::Expression::Literal(literal)) => {
+                    duration = Some(literal.to_duration()?)
+                }
+                ("ttl", _) => return errinput!("ttl must be a duration literal"),
+                ("ttl_column", _) if column.is_some() => {
+                    return errinput!("ttl_column already set")
+                }
+                ("ttl_column", ast::Expression::Column(None, name)) => {
+                    let Some(index) = columns.iter().position(|c| c.name == name) else {
+                        return errinput!("unknown TTL column {name}");
+                    };
+                    column = Some(index)
+                }
+                ("ttl_column", _) => return errinput!("ttl_column must be a column name"),
+                (name, _) => return errinput!("unknown table option {name}"),
+            }
+        }
+        match (duration, column) {
+            (Some(duration), Some(column)) => Ok(Some(Ttl { duration, column })),
+            (None, None) => Ok(None),
+            (Some(_), None) => errinput!("ttl requires ttl_column"),
+            (None, Some(_)) => errinput!("ttl_column requires ttl"),
+        }
+    }
+
+    /// Builds a CREATE FUNCTION plan. Checks that the body can be built, i.e.
+    /// that it only refers to the parameters and calls built-in functions.
+    fn build_create_function(&self, function: Function) -> Result<Plan> {
+        if Self::is_builtin_function(&function.name) {
+            return errinput!("can't replace built-in function {}", function.name);
+        }
+        function.validate()?;
+        let mut scope = Scope::new();
+        for (name, _) in &function.parameters {
+            scope.add_column(Label::Unqualified(name.clone()));
+        }
+        Self::build_expression(function.expression()?, &scope)?;
+        Ok(Plan::CreateFunction { function })
+    }
+
+    /// Inlines calls to user-defined functions in the statement, replacing them
+    /// with the function body. Calls to unknown functions are left as is, and
+    /// error when building the expression.
+    fn inline_functions(&self, statement: &mut ast::Statement) -> Result<()> {
+        let mut functions: Option<HashMap<String, Function>> = None;
+        let mut result = Ok(());
+        statement.visit_expressions_mut(&mut |expr| {
+            expr.visit_mut(&mut |expr| {
+                let ast::Expression::Function(name, args) = expr else {
+                    return;
+                };
+                if result.is_err() || Self::is_builtin_function(name) {
+                    return;
+                }
+                // Only fetch the functions once, when a call is found.
+                if functions.is_none() {
+                    match self.catalog.list_functions() {
+                        Ok(list) => {
+                            functions =
+                                Some(list.into_iter().map(|f| (f.name.clone(), f)).collect())
+                        }
+                        Err(err) => return result = Err(err),
+                    }
+                }
+                let Some(function) = functions.as_ref().and_then(|f| f.get(name.as_str())) else {
+                    return;
+                };
+                match function.inline(std::mem::take(args)) {
+                    Ok(body) => *expr = body,
+                    Err(err) => result = Err(err),
+                }
+            })
+        });
+        result
+    }
+
+    /// Checks whether a function name is a built-in function.
+    fn is_builtin_function(name: &str) -> bool {
+        ["avg", "count", "distance", "max", "min", "point", "sqrt", "sum", "within_box"]
+            .contains(&name)
     }
 
     /// Builds a DELETE plan.
@@ -83,6 +252,7 @@ impl<'a, C: Catalog> Planner<'a, C> {
         Ok(Plan::Delete {
             table: table.name.clone(),
             primary_key: table.primary_key,
+            triggers: self.triggers(&table.name, TriggerEvent::Delete)?,
             source: Node::Scan { table, alias: None, filter },
         })
     }
@@ -114,7 +284,8 @@ impl<'a, C: Catalog> Planner<'a, C> {
                 exprs.into_iter().map(|expr| Self::build_expression(expr, &scope)).collect()
             })
             .try_collect()?;
-        Ok(Plan::Insert { table, column_map, source: Node::Values { rows } })
+        let triggers = self.triggers(&table.name, TriggerEvent::Insert)?;
+        Ok(Plan::Insert { table, column_map, source: Node::Values { rows }, triggers })
     }
 
     /// Builds an UPDATE plan.
@@ -142,11 +313,18 @@ impl<'a, C: Catalog> Planner<'a, C> {
         Ok(Plan::Update {
             table: table.clone(),
             primary_key: table.primary_key,
+            triggers: self.triggers(&table.name, TriggerEvent::Update)?,
             source: Node::Scan { table, alias: None, filter },
             expressions,
         })
     }
 
+    /// Fetches the table's triggers for the given write event.
+    fn triggers(&self, table: &str, event: TriggerEvent) -> Result<Vec<Trigger>> {
+        let triggers = self.catalog.list_triggers(table)?;
+        Ok(triggers.into_iter().filter(|trigger| trigger.event == event).collect())
+    }
+
     /// Builds a SELECT plan.
     #[allow(clippy::too_many_arguments)]
     fn build_select(
@@ -504,21 +682,36 @@ impl<'a, C: Catalog> Planner<'a, C> {
             // values, not compound types like tuples. Support for * is
             // therefore special-cased in SELECT and COUNT(*).
             ast::Expression::All => return errinput!("unsupported use of *"),
+            ast::Expression::Parameter(index) => {
+                return errinput!("no value given for parameter {}", index + 1)
+            }
+            ast::Expression::Tuple(_) => {
+                return errinput!("row values can only be compared with row values")
+            }
             ast::Expression::Literal(l) => Constant(match l {
                 ast::Literal::Null => Value::Null,
                 ast::Literal::Boolean(b) => Value::Boolean(b),
                 ast::Literal::Integer(i) => Value::Integer(i),
                 ast::Literal::Float(f) => Value::Float(f),
                 ast::Literal::String(s) => Value::String(s),
+                ast::Literal::Point(x, y) => Value::Point(x, y),
             }),
             ast::Expression::Column(table, name) => {
                 Column(scope.lookup_column(table.as_deref(), &name)?)
             }
-            ast::Expression::Function(name, mut args) => match (name.as_str(), args.len()) {
-                // NB: aggregate functions are processed above.
-                ("sqrt", 1) => SquareRoot(build(Box::new(args.remove(0)))?),
-                (name, n) => return errinput!("unknown function {name} with {n} arguments"),
-            },
+            ast::Expression::Function(name, args) => {
+                let count = args.len();
+                let mut args = args.into_iter().map(|arg| build(Box::new(arg)));
+                let mut arg = || args.next().expect("missing argument");
+                match (name.as_str(), count) {
+                    // NB: aggregate functions are processed above.
+                    ("sqrt", 1) => SquareRoot(arg()?),
+                    ("point", 2) => Point(arg()?, arg()?),
+                    ("distance", 2) => Distance(arg()?, arg()?),
+                    ("within_box", 3) => WithinBox(arg()?, arg()?, arg()?),
+                    (name, n) => return errinput!("unknown function {name} with {n} arguments"),
+                }
+            }
             ast::Expression::Operator(op) => match op {
                 ast::Operator::And(lhs, rhs) => And(build(lhs)?, build(rhs)?),
                 ast::Operator::Not(expr) => Not(build(expr)?),
@@ -545,6 +738,7 @@ impl<'a, C: Catalog> Planner<'a, C> {
                     Equal(build(lhs)?, build(rhs)?).into(),
                 ),
                 ast::Operator::Like(lhs, rhs) => Like(build(lhs)?, build(rhs)?),
+                ast::Operator::Match(lhs, rhs) => Match(build(lhs)?, build(rhs)?),
                 ast::Operator::NotEqual(lhs, rhs) => Not(Equal(build(lhs)?, build(rhs)?).into()),
 
                 ast::Operator::Add(lhs, rhs) => Add(build(lhs)?, build(rhs)?),
@@ -629,7 +823,9 @@ impl Scope {
     /// Adds a table to the scope. The label is either the table's original name
     /// or an alias, and must be unique. All table columns are added, in order.
     fn add_table(&mut self, table: &Table, alias: Option<&str>) -> Result<()> {
-        let name = alias.unwrap_or(&table.name);
+        // Schema-qualified tables (e.g. system.nodes) are referenced by their
+        // unqualified name, unless aliased.
+        let name = alias.unwrap_or(table.name.rsplit('.').next().unwrap_or(&table.name));
         if self.tables.contains(name) {
             return errinput!("duplicate table name {name}");
         }
@@ -1,9 +1,11 @@
-use super::{Label, Row, Value};
+use super::{Label, Row, SearchQuery, Value, ValueRange, ValueRef};
 use crate::errinput;
 use crate::error::Result;
 use crate::sql::planner::Node;
 
 use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::ops::Bound;
 
 /// An expression, made up of nested operations and values. Values are either
 /// constants or dynamic column references. Evaluates to a final value during
@@ -58,6 +60,16 @@ pub enum Expression {
 
     // Checks if a string matches a pattern: a LIKE b.
     Like(Box<Expression>, Box<Expression>),
+    // Checks if a string matches a full-text search query: a MATCH b.
+    Match(Box<Expression>, Box<Expression>),
+
+    /// Builds a point from x and y coordinates: POINT(x, y).
+    Point(Box<Expression>, Box<Expression>),
+    /// The Euclidean distance between two points: distance(a, b).
+    Distance(Box<Expression>, Box<Expression>),
+    /// Checks if a point is within the bounding box given by two opposite
+    /// corner points, inclusive: within_box(p, a, b).
+    WithinBox(Box<Expression>, Box<Expression>, Box<Expression>),
 }
 
 impl Expression {
@@ -70,13 +82,14 @@ impl Expression {
         fn precedence(expr: &Expression) -> u8 {
             match expr {
                 Column(_) | Constant(_) | SquareRoot(_) => 11,
+                Point(_, _) | Distance(_, _) | WithinBox(_, _, _) => 11,
                 Identity(_) | Negate(_) => 10,
                 Factorial(_) => 9,
                 Exponentiate(_, _) => 8,
                 Multiply(_, _) | Divide(_, _) | Remainder(_, _) => 7,
                 Add(_, _) | Subtract(_, _) => 6,
                 GreaterThan(_, _) | LessThan(_, _) => 5,
-                Equal(_, _) | Like(_, _) | Is(_, _) => 4,
+                Equal(_, _) | Like(_, _) | Match(_, _) | Is(_, _) => 4,
                 Not(_) => 3,
                 And(_, _) => 2,
                 Or(_, _) => 1,
@@ -122,6 +135,13 @@ impl Expression {
             Subtract(lhs, rhs) => format!("{} - {}", format(lhs), format(rhs)),
 
             Like(lhs, rhs) => format!("{} LIKE {}", format(lhs), format(rhs)),
+            Match(lhs, rhs) => format!("{} MATCH {}", format(lhs), format(rhs)),
+
+            Point(x, y) => format!("POINT({}, {})", format(x), format(y)),
+            Distance(lhs, rhs) => format!("distance({}, {})", format(lhs), format(rhs)),
+            WithinBox(point, a, b) => {
+                format!("within_box({}, {}, {})", format(point), format(a), format(b))
+            }
         }
     }
 
@@ -133,6 +153,17 @@ impl Expression {
     /// Evaluates an expression, returning a value. Column references look up
     /// values in the given row. If None, any Column references will panic.
     pub fn evaluate(&self, row: Option<&Row>) -> Result<Value> {
+        self.eval(row)
+    }
+
+    /// Evaluates an expression against a borrowed row, e.g. one decoded
+    /// directly from storage. Only the referenced columns are copied.
+    pub fn evaluate_ref(&self, row: &[ValueRef]) -> Result<Value> {
+        self.eval(Some(row))
+    }
+
+    /// Evaluates an expression against an owned or borrowed row.
+    fn eval<R: Columns + ?Sized>(&self, row: Option<&R>) -> Result<Value> {
         use Value::*;
         Ok(match self {
             // Constant values return themselves.
@@ -141,13 +172,13 @@ impl Expression {
             // Column references look up a row value. The planner ensures that
             // only constant expressions are evaluated without a row.
             Self::Column(index) => match row {
-                Some(row) => row.get(*index).expect("short row").clone(),
+                Some(row) => row.column(*index),
                 None => panic!("can't reference column {index} with constant evaluation"),
             },
 
             // Logical AND. Inputs must be boolean or NULL. NULLs generally
             // yield NULL, except the special case NULL AND false == false.
-            Self::And(lhs, rhs) => match (lhs.evaluate(row)?, rhs.evaluate(row)?) {
+            Self::And(lhs, rhs) => match (lhs.eval(row)?, rhs.eval(row)?) {
                 (Boolean(lhs), Boolean(rhs)) => Boolean(lhs && rhs),
                 (Boolean(b), Null) | (Null, Boolean(b)) if !b => Boolean(false),
                 (Boolean(_), Null) | (Null, Boolean(_)) | (Null, Null) => Null,
@@ -156,7 +187,7 @@ impl Expression {
 
             // Logical OR. Inputs must be boolean or NULL. NULLs generally
             // yield NULL, except the special case NULL OR true == true.
-            Self::Or(lhs, rhs) => match (lhs.evaluate(row)?, rhs.evaluate(row)?) {
+            Self::Or(lhs, rhs) => match (lhs.eval(row)?, rhs.eval(row)?) {
                 (Boolean(lhs), Boolean(rhs)) => Boolean(lhs || rhs),
                 (Boolean(b), Null) | (Null, Boolean(b)) if b => Boolean(true),
                 (Boolean(_), Null) | (Null, Boolean(_)) | (Null, Null) => Null,
@@ -164,7 +195,7 @@ impl Expression {
             },
 
             // Logical NOT. Input must be boolean or NULL.
-            Self::Not(expr) => match expr.evaluate(row)? {
+            Self::Not(expr) => match expr.eval(row)? {
                 Boolean(b) => Boolean(!b),
                 Null => Null,
                 value => return errinput!("can't NOT {value}"),
@@ -172,22 +203,24 @@ impl Expression {
 
             // Comparisons. Must be of same type, except floats and integers
             // which are interchangeable. NULLs yield NULL, NaNs yield NaN.
+            // Points can only be compared for equality.
             //
             // Does not dispatch to Value.cmp() because sorting and comparisons
             // are different for f64 NaN and -0.0 values.
             #[allow(clippy::float_cmp)]
-            Self::Equal(lhs, rhs) => match (lhs.evaluate(row)?, rhs.evaluate(row)?) {
+            Self::Equal(lhs, rhs) => match (lhs.eval(row)?, rhs.eval(row)?) {
                 (Boolean(lhs), Boolean(rhs)) => Boolean(lhs == rhs),
                 (Integer(lhs), Integer(rhs)) => Boolean(lhs == rhs),
                 (Integer(lhs), Float(rhs)) => Boolean(lhs as f64 == rhs),
                 (Float(lhs), Integer(rhs)) => Boolean(lhs == rhs as f64),
                 (Float(lhs), Float(rhs)) => Boolean(lhs == rhs),
                 (String(lhs), String(rhs)) => Boolean(lhs == rhs),
+                (Point(lx, ly), Point(rx, ry)) => Boolean(lx == rx && ly == ry),
                 (Null, _) | (_, Null) => Null,
                 (lhs, rhs) => return errinput!("can't compare {lhs} and {rhs}"),
             },
 
-            Self::GreaterThan(lhs, rhs) => match (lhs.evaluate(row)?, rhs.evaluate(row)?) {
+            Self::GreaterThan(lhs, rhs) => match (lhs.eval(row)?, rhs.eval(row)?) {
                 #[allow(clippy::bool_comparison)]
                 (Boolean(lhs), Boolean(rhs)) => Boolean(lhs > rhs),
                 (Integer(lhs), Integer(rhs)) => Boolean(lhs > rhs),
@@ -199,7 +232,7 @@ impl Expression {
                 (lhs, rhs) => return errinput!("can't compare {lhs} and {rhs}"),
             },
 
-            Self::LessThan(lhs, rhs) => match (lhs.evaluate(row)?, rhs.evaluate(row)?) {
+            Self::LessThan(lhs, rhs) => match (lhs.eval(row)?, rhs.eval(row)?) {
                 #[allow(clippy::bool_comparison)]
                 (Boolean(lhs), Boolean(rhs)) => Boolean(lhs < rhs),
                 (Integer(lhs), Integer(rhs)) => Boolean(lhs < rhs),
@@ -211,8 +244,8 @@ impl Expression {
                 (lhs, rhs) => return errinput!("can't compare {lhs} and {rhs}"),
             },
 
-            Self::Is(expr, Null) => Boolean(expr.evaluate(row)? == Null),
-            Self::Is(expr, Float(f)) if f.is_nan() => match expr.evaluate(row)? {
+            Self::Is(expr, Null) => Boolean(expr.eval(row)? == Null),
+            Self::Is(expr, Float(f)) if f.is_nan() => match expr.eval(row)? {
                 Float(f) => Boolean(f.is_nan()),
                 Null => Null,
                 v => return errinput!("IS NAN can't be used with {}", v.datatype().unwrap()),
@@ -222,40 +255,40 @@ impl Expression {
             // Mathematical operations. Inputs must be numbers, but integers and
             // floats are interchangeable (float when mixed). NULLs yield NULL.
             // Errors on integer overflow, while floats yield infinity or NaN.
-            Self::Add(lhs, rhs) => lhs.evaluate(row)?.checked_add(&rhs.evaluate(row)?)?,
-            Self::Divide(lhs, rhs) => lhs.evaluate(row)?.checked_div(&rhs.evaluate(row)?)?,
-            Self::Exponentiate(lhs, rhs) => lhs.evaluate(row)?.checked_pow(&rhs.evaluate(row)?)?,
-            Self::Factorial(expr) => match expr.evaluate(row)? {
+            Self::Add(lhs, rhs) => lhs.eval(row)?.checked_add(&rhs.eval(row)?)?,
+            Self::Divide(lhs, rhs) => lhs.eval(row)?.checked_div(&rhs.eval(row)?)?,
+            Self::Exponentiate(lhs, rhs) => lhs.eval(row)?.checked_pow(&rhs.eval(row)?)?,
+            Self::Factorial(expr) => match expr.eval(row)? {
                 Integer(i) if i < 0 => return errinput!("can't take factorial of negative number"),
                 Integer(i) => (1..=i).try_fold(Integer(1), |p, i| p.checked_mul(&Integer(i)))?,
                 Null => Null,
                 value => return errinput!("can't take factorial of {value}"),
             },
-            Self::Identity(expr) => match expr.evaluate(row)? {
+            Self::Identity(expr) => match expr.eval(row)? {
                 v @ (Integer(_) | Float(_) | Null) => v,
                 expr => return errinput!("can't take the identity of {expr}"),
             },
-            Self::Multiply(lhs, rhs) => lhs.evaluate(row)?.checked_mul(&rhs.evaluate(row)?)?,
-            Self::Negate(expr) => match expr.evaluate(row)? {
+            Self::Multiply(lhs, rhs) => lhs.eval(row)?.checked_mul(&rhs.eval(row)?)?,
+            Self::Negate(expr) => match expr.eval(row)? {
                 Integer(i) => Integer(-i),
                 Float(f) => Float(-f),
                 Null => Null,
                 value => return errinput!("can't negate {value}"),
             },
-            Self::Remainder(lhs, rhs) => lhs.evaluate(row)?.checked_rem(&rhs.evaluate(row)?)?,
-            Self::SquareRoot(expr) => match expr.evaluate(row)? {
+            Self::Remainder(lhs, rhs) => lhs.eval(row)?.checked_rem(&rhs.eval(row)?)?,
+            Self::SquareRoot(expr) => match expr.eval(row)? {
                 Integer(i) if i < 0 => return errinput!("can't take negative square root"),
                 Integer(i) => Float((i as f64).sqrt()),
                 Float(f) => Float(f.sqrt()),
                 Null => Null,
                 value => return errinput!("can't take square root of {value}"),
             },
-            Self::Subtract(lhs, rhs) => lhs.evaluate(row)?.checked_sub(&rhs.evaluate(row)?)?,
+            Self::Subtract(lhs, rhs) => lhs.eval(row)?.checked_sub(&rhs.eval(row)?)?,
 
             // LIKE pattern matching, using _ and % as single- and
             // multi-character wildcards. Inputs must be strings. NULLs yield
             // NULL. There's no support for escaping an _ and %.
-            Self::Like(lhs, rhs) => match (lhs.evaluate(row)?, rhs.evaluate(row)?) {
+            Self::Like(lhs, rhs) => match (lhs.eval(row)?, rhs.eval(row)?) {
                 (String(lhs), String(rhs)) => {
                     // We could precompile the pattern if it's constant, instead
                     // of recompiling it for every row, but this is fine.
@@ -266,6 +299,42 @@ impl Expression {
                 (String(_), Null) | (Null, String(_)) | (Null, Null) => Null,
                 (lhs, rhs) => return errinput!("can't LIKE {lhs} and {rhs}"),
             },
+
+            // Full-text search, matching all of the query's terms against the
+            // string's terms. See SearchQuery. NULLs yield NULL.
+            Self::Match(lhs, rhs) => match (lhs.eval(row)?, rhs.eval(row)?) {
+                (String(lhs), String(rhs)) => Boolean(SearchQuery::parse(&rhs)?.matches(&lhs)),
+                (String(_), Null) | (Null, String(_)) | (Null, Null) => Null,
+                (lhs, rhs) => return errinput!("can't MATCH {lhs} and {rhs}"),
+            },
+
+            // Geospatial functions. Points are built from numbers, and
+            // coordinates can't be NaN. NULLs yield NULL.
+            Self::Point(x, y) => {
+                let coordinate = |value| match value {
+                    Integer(i) => Ok(Some(i as f64)),
+                    Float(f) if f.is_nan() => errinput!("point coordinates can't be NaN"),
+                    Float(f) => Ok(Some(f)),
+                    Null => Ok(None),
+                    value => errinput!("can't use {value} as point coordinate"),
+                };
+                match (coordinate(x.eval(row)?)?, coordinate(y.eval(row)?)?) {
+                    (Some(x), Some(y)) => Point(x, y),
+                    (None, _) | (_, None) => Null,
+                }
+            }
+            Self::Distance(lhs, rhs) => match (lhs.eval(row)?, rhs.eval(row)?) {
+                (Point(ax, ay), Point(bx, by)) => Float((ax - bx).hypot(ay - by)),
+                (Point(..) | Null, Null) | (Null, Point(..)) => Null,
+                (lhs, rhs) => return errinput!("can't take distance between {lhs} and {rhs}"),
+            },
+            Self::WithinBox(point, a, b) => match (point.eval(row)?, a.eval(row)?, b.eval(row)?) {
+                (Point(x, y), Point(ax, ay), Point(bx, by)) => Boolean(
+                    ax.min(bx) <= x && x <= ax.max(bx) && ay.min(by) <= y && y <= ay.max(by),
+                ),
+                (Point(..) | Null, Point(..) | Null, Point(..) | Null) => Null,
+                (p, a, b) => return errinput!("can't check if {p} is within box {a} and {b}"),
+            },
         })
     }
 
@@ -284,10 +353,17 @@ impl Expression {
             | Self::GreaterThan(lhs, rhs)
             | Self::LessThan(lhs, rhs)
             | Self::Like(lhs, rhs)
+            | Self::Match(lhs, rhs)
             | Self::Multiply(lhs, rhs)
             | Self::Or(lhs, rhs)
             | Self::Remainder(lhs, rhs)
-            | Self::Subtract(lhs, rhs) => lhs.walk(visitor) && rhs.walk(visitor),
+            | Self::Subtract(lhs, rhs)
+            | Self::Point(lhs, rhs)
+            | Self::Distance(lhs, rhs) => lhs.walk(visitor) && rhs.walk(visitor),
+
+            Self::WithinBox(point, a, b) => {
+                point.walk(visitor) && a.walk(visitor) && b.walk(visitor)
+            }
 
             Self::Factorial(expr)
             | Self::Identity(expr)
@@ -330,6 +406,7 @@ impl Expression {
             Self::GreaterThan(lhs, rhs) => Self::GreaterThan(xform(lhs)?, xform(rhs)?),
             Self::LessThan(lhs, rhs) => Self::LessThan(xform(lhs)?, xform(rhs)?),
             Self::Like(lhs, rhs) => Self::Like(xform(lhs)?, xform(rhs)?),
+            Self::Match(lhs, rhs) => Self::Match(xform(lhs)?, xform(rhs)?),
             Self::Multiply(lhs, rhs) => Self::Multiply(xform(lhs)?, xform(rhs)?),
             Self::Or(lhs, rhs) => Self::Or(xform(lhs)?, xform(rhs)?),
             Self::Remainder(lhs, rhs) => Self::Remainder(xform(lhs)?, xform(rhs)?),
@@ -342,6 +419,10 @@ impl Expression {
             Self::Negate(expr) => Self::Negate(xform(expr)?),
             Self::Not(expr) => Self::Not(xform(expr)?),
 
+            Self::Point(x, y) => Self::Point(xform(x)?, xform(y)?),
+            Self::Distance(lhs, rhs) => Self::Distance(xform(lhs)?, xform(rhs)?),
+            Self::WithinBox(point, a, b) => Self::WithinBox(xform(point)?, xform(a)?, xform(b)?),
+
             expr @ (Self::Constant(_) | Self::Column(_)) => expr,
         };
         self = after(self)?;
@@ -477,6 +558,108 @@ impl Expression {
         }
     }
 
+    /// Checks if an expression is a full-text search of a single column with a
+    /// valid constant query (i.e. column MATCH 'query'), returning the column
+    /// index and query.
+    pub fn is_search_lookup(&self) -> Option<(usize, &str)> {
+        use Expression::*;
+        let Match(lhs, rhs) = self else { return None };
+        match (lhs.as_ref(), rhs.as_ref()) {
+            (Column(c), Constant(Value::String(query))) if SearchQuery::parse(query).is_ok() => {
+                Some((*c, query))
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the range of values that the given column must be in for the
+    /// expression to be true, if it's bounded by comparisons of the column with
+    /// constants, e.g. id > 3 AND id <= 7. Rows outside the range can be skipped
+    /// by range scans, but rows within it must still be filtered by the
+    /// expression unless is_column_range() is true.
+    pub fn column_range(&self, column: usize) -> Option<ValueRange> {
+        use Bound::*;
+        use Expression::*;
+        match self {
+            Equal(lhs, rhs) | GreaterThan(lhs, rhs) | LessThan(lhs, rhs) => {
+                let (value, flipped) = match (lhs.as_ref(), rhs.as_ref()) {
+                    (Column(c), Constant(value)) if *c == column => (value, false),
+                    (Constant(value), Column(c)) if *c == column => (value, true),
+                    _ => return None,
+                };
+                if value.is_undefined() {
+                    return None;
+                }
+                let value = value.normalize_ref().into_owned();
+                Some(match (self, flipped) {
+                    (Equal(..), _) => (Included(value.clone()), Included(value)),
+                    (GreaterThan(..), false) | (LessThan(..), true) => (Excluded(value), Unbounded),
+                    _ => (Unbounded, Excluded(value)),
+                })
+            }
+            // An AND is in both ranges, or either range if only one is bounded.
+            And(lhs, rhs) => match (lhs.column_range(column), rhs.column_range(column)) {
+                (Some(lhs), Some(rhs)) => Some((
+                    std::cmp::max_by(lhs.0, rhs.0, |a, b| Self::cmp_bounds(a, b, true)),
+                    std::cmp::min_by(lhs.1, rhs.1, |a, b| Self::cmp_bounds(a, b, false)),
+                )),
+                (range, None) | (None, range) => range,
+            },
+            // An OR spans both ranges, which must both be bounded.
+            Or(lhs, rhs) => {
+                let (lhs, rhs) = (lhs.column_range(column)?, rhs.column_range(column)?);
+                Some((
+                    std::cmp::min_by(lhs.0, rhs.0, |a, b| Self::cmp_bounds(a, b, true)),
+                    std::cmp::max_by(lhs.1, rhs.1, |a, b| Self::cmp_bounds(a, b, false)),
+                ))
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns true if the expression is exactly the range returned by
+    /// column_range(), i.e. a single comparison of the column with a constant,
+    /// including >= and <= (which are planned as e.g. id > 3 OR id = 3).
+    pub fn is_column_range(&self, column: usize) -> bool {
+        use Expression::*;
+        let compares = |lhs: &Expression, rhs: &Expression| {
+            matches!((lhs, rhs), (Column(c), Constant(v)) | (Constant(v), Column(c))
+                if *c == column && !v.is_undefined())
+        };
+        match self {
+            Equal(lhs, rhs) | GreaterThan(lhs, rhs) | LessThan(lhs, rhs) => compares(lhs, rhs),
+            Or(lhs, rhs) => match (lhs.as_ref(), rhs.as_ref()) {
+                (GreaterThan(a, b) | LessThan(a, b), Equal(c, d)) => {
+                    compares(a, b) && a == c && b == d
+                }
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+
+    /// Compares the positions of two range bounds, either start or end bounds.
+    /// Unbounded starts come first and unbounded ends last, and excluded
+    /// bounds come after included starts and before included ends.
+    fn cmp_bounds(a: &Bound<Value>, b: &Bound<Value>, start: bool) -> Ordering {
+        use Bound::*;
+        let rank = |bound: &Bound<Value>| match bound {
+            Excluded(_) if start => 1,
+            Excluded(_) => -1,
+            Included(_) | Unbounded => 0,
+        };
+        match (a, b) {
+            (Unbounded, Unbounded) => Ordering::Equal,
+            (Unbounded, _) if start => Ordering::Less,
+            (Unbounded, _) => Ordering::Greater,
+            (_, Unbounded) if start => Ordering::Greater,
+            (_, Unbounded) => Ordering::Less,
+            (Included(a_value) | Excluded(a_value), Included(b_value) | Excluded(b_value)) => {
+                a_value.cmp(b_value).then_with(|| rank(a).cmp(&rank(b)))
+            }
+        }
+    }
+
     /// Replaces column references with the given column.
     pub fn replace_column(self, from: usize, to: usize) -> Self {
         let xform = |expr| match expr {
@@ -507,3 +690,21 @@ impl From<Value> for Box<Expression> {
         Box::new(value.into())
     }
 }
+
+/// Column access for expression evaluation, for owned and borrowed rows.
+trait Columns {
+    /// Returns the value of the given column. Panics if it doesn't exist.
+    fn column(&self, index: usize) -> Value;
+}
+
+impl Columns for Row {
+    fn column(&self, index: usize) -> Value {
+        self.get(index).expect("short row").clone()
+    }
+}
+
+impl Columns for [ValueRef<'_>] {
+    fn column(&self, index: usize) -> Value {
+        self.get(index).expect("short row").to_owned()
+    }
+}
@@ -1,9 +1,21 @@
-//! The SQL data model, including data types, expressions, and schema objects.
+//! The SQL data model, including data types, expressions, schema objects, and
+//! access control.
 
 mod expression;
+mod row;
 mod schema;
+mod statistics;
+mod text;
+mod user;
 mod value;
 
 pub use expression::Expression;
-pub use schema::{Column, Table};
-pub use value::{DataType, Label, Row, Rows, Value};
+pub use row::FromRow;
+pub use schema::{
+    format_duration, format_ident, Changefeed, Column, Database, Function, Table, Trigger,
+    TriggerEvent, TriggerTiming, Ttl,
+};
+pub use statistics::{ColumnStatistics, Statistics};
+pub use text::{terms, SearchQuery, SearchTerm};
+pub use user::{Grant, Privilege, Privileges, Role, User};
+pub use value::{DataType, Label, Row, Rows, Value, ValueRange, ValueRef};
@@ -0,0 +1,285 @@
+//! Typed row deserialization, mapping query result rows into Rust types.
+//!
+//! Any type implementing serde's Deserialize can be deserialized from a row
+//! via FromRow. Structs map result columns to fields by name, while tuples,
+//! arrays, and vectors map them by position. For example:
+//!
+//! ```no_run
+//! # use serde::Deserialize;
+//! # fn main() -> toydb::error::Result<()> {
+//! #[derive(Deserialize)]
+//! struct Movie {
+//!     id: i64,
+//!     title: String,
+//!     rating: Option<f64>,
+//! }
+//!
+//! let mut client = toydb::Client::connect("localhost:9601")?;
+//! let movies: Vec<Movie> = client.query_as("SELECT id, title, rating FROM movies")?;
+//! let pairs: Vec<(i64, String)> = client.query_as("SELECT id, title FROM movies")?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Struct fields are matched against unqualified column names, so columns
+//! from joins or expressions may need an alias (e.g. `SELECT a.id AS a_id`).
+//! Columns without a matching field are ignored, and missing fields error
+//! unless they're Options or have a default (i.e. `#[serde(default)]`).
+//!
+//! Values are type checked against the field types: an integer column can
+//! only be deserialized into an integer field that can hold its value, a
+//! string into a String, and so on. NULL can only be deserialized into an
+//! Option (as None) or a unit. As a convenience, integers can also be
+//! deserialized into floats.
+
+use super::{Label, Row, Value};
+use crate::errdata;
+use crate::error::{Error, Result};
+
+use serde::de::{self, DeserializeOwned};
+
+/// A type that can be constructed from a result row, given the column labels.
+pub trait FromRow: Sized {
+    /// Constructs the type from a row, with the given column labels.
+    fn from_row(columns: &[Label], row: Row) -> Result<Self>;
+}
+
+impl<T: DeserializeOwned> FromRow for T {
+    fn from_row(columns: &[Label], row: Row) -> Result<Self> {
+        if columns.len() != row.len() {
+            return errdata!("row has {} values, expected {} columns", row.len(), columns.len());
+        }
+        T::deserialize(RowDeserializer { columns, row })
+    }
+}
+
+/// Deserializes a row, as a map of column names to values or a sequence of
+/// values.
+struct RowDeserializer<'a> {
+    columns: &'a [Label],
+    row: Row,
+}
+
+impl<'de> de::Deserializer<'de> for RowDeserializer<'_> {
+    type Error = Error;
+
+    fn deserialize_any<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_seq<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_seq(de::value::SeqDeserializer::new(self.row.into_iter()))
+    }
+
+    fn deserialize_tuple<V: de::Visitor<'de>>(self, _: usize, visitor: V) -> Result<V::Value> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: de::Visitor<'de>>(
+        self,
+        _: &'static str,
+        _: usize,
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        // Unlabeled columns can't be matched by name, so skip them.
+        let columns = self.columns.iter().zip(self.row).filter_map(|(label, value)| {
+            let name = match label {
+                Label::None => return None,
+                Label::Unqualified(name) | Label::Qualified(_, name) => name.clone(),
+            };
+            Some((name, value))
+        });
+        visitor.visit_map(RowMapAccess { columns, value: None })
+    }
+
+    fn deserialize_struct<V: de::Visitor<'de>>(
+        self,
+        _: &'static str,
+        _: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: de::Visitor<'de>>(
+        self,
+        _: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct enum identifier ignored_any
+    }
+}
+
+/// Accesses a row's columns as a map of column names to values.
+struct RowMapAccess<I: Iterator<Item = (String, Value)>> {
+    columns: I,
+    /// The current column, whose value is next.
+    value: Option<(String, Value)>,
+}
+
+impl<'de, I: Iterator<Item = (String, Value)>> de::MapAccess<'de> for RowMapAccess<I> {
+    type Error = Error;
+
+    fn next_key_seed<K: de::DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+        let Some((name, value)) = self.columns.next() else {
+            return Ok(None);
+        };
+        let key = seed.deserialize(de::value::StrDeserializer::<Error>::new(&name))?;
+        self.value = Some((name, value));
+        Ok(Some(key))
+    }
+
+    fn next_value_seed<V: de::DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value> {
+        let (name, value) = self.value.take().expect("next_value_seed before next_key_seed");
+        seed.deserialize(value).map_err(|err| match err {
+            Error::InvalidData(msg) => Error::InvalidData(format!("column {name}: {msg}")),
+            err => err,
+        })
+    }
+}
+
+impl<'de> de::IntoDeserializer<'de, Error> for Value {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+/// Deserializes a single value into its corresponding Rust type.
+impl<'de> de::Deserializer<'de> for Value {
+    type Error = Error;
+
+    fn deserialize_any<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self {
+            Self::Null => visitor.visit_unit(),
+            Self::Boolean(b) => visitor.visit_bool(b),
+            Self::Integer(i) => visitor.visit_i64(i),
+            Self::Float(f) => visitor.visit_f64(f),
+            Self::String(s) => visitor.visit_string(s),
+            Self::Point(x, y) => {
+                visitor.visit_seq(de::value::SeqDeserializer::new([x, y].into_iter()))
+            }
+        }
+    }
+
+    fn deserialize_option<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self {
+            Self::Null => visitor.visit_none(),
+            value => visitor.visit_some(value),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: de::Visitor<'de>>(
+        self,
+        _: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct enum
+        identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Movie {
+        id: i64,
+        title: String,
+        rating: Option<f64>,
+        #[serde(default)]
+        released: bool,
+    }
+
+    fn labels(names: &[&str]) -> Vec<Label> {
+        names.iter().map(|name| Label::Unqualified(name.to_string())).collect()
+    }
+
+    /// Structs map columns to fields by name, in any order.
+    #[test]
+    fn from_row_struct() -> Result<()> {
+        let columns = vec![
+            Label::Unqualified("title".into()),
+            Label::Qualified("movies".into(), "id".into()),
+            Label::None,
+            Label::Unqualified("rating".into()),
+            Label::Unqualified("extra".into()),
+        ];
+        let row = vec!["Heat".into(), 1.into(), 7.into(), Value::Null, true.into()];
+        let movie = Movie::from_row(&columns, row)?;
+        assert_eq!(movie, Movie { id: 1, title: "Heat".into(), rating: None, released: false });
+
+        // Integers can be used as floats.
+        let row = vec![Value::Integer(2), "Alien".into(), Value::Integer(8)];
+        let movie = Movie::from_row(&labels(&["id", "title", "rating"]), row)?;
+        assert_eq!(movie.rating, Some(8.0));
+        Ok(())
+    }
+
+    /// Tuples and vectors map columns by position.
+    #[test]
+    fn from_row_tuple() -> Result<()> {
+        let columns = labels(&["a", "b", "c"]);
+        let row = vec![1.into(), "foo".into(), Value::Null];
+        let tuple = <(i64, String, Option<bool>)>::from_row(&columns, row.clone())?;
+        assert_eq!(tuple, (1, "foo".to_string(), None));
+
+        let row = vec![1.into(), 2.into(), 3.into()];
+        assert_eq!(Vec::<u8>::from_row(&columns, row)?, vec![1, 2, 3]);
+        Ok(())
+    }
+
+    /// Type mismatches, missing fields, and wrong column counts error.
+    #[test]
+    fn from_row_errors() {
+        let columns = labels(&["id", "title", "rating"]);
+        let row = vec!["1".into(), "Heat".into(), Value::Null];
+        assert_eq!(
+            Movie::from_row(&columns, row),
+            Err(Error::InvalidData(
+                "column id: invalid type: string \"1\", expected i64".to_string()
+            ))
+        );
+
+        let row = vec![1.into(), Value::Null, Value::Null];
+        assert_eq!(
+            Movie::from_row(&columns, row),
+            Err(Error::InvalidData(
+                "column title: invalid type: unit value, expected a string".to_string()
+            ))
+        );
+
+        let row = vec![1.into(), Value::Null];
+        assert_eq!(
+            Movie::from_row(&labels(&["id", "rating"]), row),
+            Err(Error::InvalidData("missing field `title`".to_string()))
+        );
+
+        let row = vec![Value::Integer(-1)];
+        assert_eq!(
+            <(u8,)>::from_row(&columns[..1], row),
+            Err(Error::InvalidData("invalid value: integer `-1`, expected u8".to_string()))
+        );
+
+        let row = vec![1.into()];
+        assert!(<(i64,)>::from_row(&columns, row).is_err());
+    }
+}
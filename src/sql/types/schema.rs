@@ -1,16 +1,20 @@
-use super::{DataType, Value};
+use super::{DataType, Privilege, Row, Statistics, Value};
 use crate::encoding;
-use crate::errinput;
-use crate::error::Result;
-use crate::sql::engine::{Catalog, Transaction};
+use crate::error::{Error, Result};
+use crate::sql::engine::{Catalog, InformationSchema, SystemTable, Transaction};
+use crate::sql::parser::{ast, Parser};
+use crate::sql::planner::Plan;
+use crate::{errdata, errinput};
 
 use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 /// A table schema, which specifies its data structure and constraints.
 ///
-/// Tables can't change after they are created. There is no ALTER TABLE nor
-/// CREATE/DROP INDEX -- only CREATE TABLE and DROP TABLE.
+/// Tables can't change after they are created, except for adding secondary
+/// indexes via CREATE INDEX. There is no ALTER TABLE nor DROP INDEX.
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct Table {
     /// The table name. Can't be empty.
@@ -20,10 +24,78 @@ pub struct Table {
     pub primary_key: usize,
     /// The table's columns. Must have at least one.
     pub columns: Vec<Column>,
+    /// The table's row time-to-live, if any.
+    pub ttl: Option<Ttl>,
+    /// The table's statistics, if collected by ANALYZE.
+    pub statistics: Option<Statistics>,
 }
 
 impl encoding::Value for Table {}
 
+impl encoding::Versioned for Table {
+    /// Version 2 added the row TTL, and version 3 the table statistics.
+    const VERSION: u8 = 3;
+
+    fn migrate(version: u8, bytes: &[u8]) -> Result<Self> {
+        /// A version 1 table schema, without a TTL.
+        #[derive(Deserialize)]
+        struct TableV1 {
+            name: String,
+            primary_key: usize,
+            columns: Vec<Column>,
+        }
+
+        /// A version 2 table schema, without statistics.
+        #[derive(Deserialize)]
+        struct TableV2 {
+            name: String,
+            primary_key: usize,
+            columns: Vec<Column>,
+            ttl: Option<Ttl>,
+        }
+
+        match version {
+            0 | 1 => {
+                let TableV1 { name, primary_key, columns } = encoding::bincode::deserialize(bytes)?;
+                Ok(Self { name, primary_key, columns, ttl: None, statistics: None })
+            }
+            2 => {
+                let TableV2 { name, primary_key, columns, ttl } =
+                    encoding::bincode::deserialize(bytes)?;
+                Ok(Self { name, primary_key, columns, ttl, statistics: None })
+            }
+            version => errdata!("no migration from format version {version}"),
+        }
+    }
+}
+
+/// A row time-to-live. Rows expire once the duration has passed since the
+/// time in the TTL column, given as Unix seconds. Expired rows are omitted
+/// from reads, and deleted by the ttl maintenance task (see
+/// `MaintenanceTask::ttl()`). Rows with a NULL time never expire.
+///
+/// Until they're purged, expired rows still occupy their primary key and
+/// unique values, and still count towards foreign key references.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Ttl {
+    /// How long rows live. Must be positive.
+    pub duration: Duration,
+    /// The TTL column index. Must be an integer column.
+    pub column: usize,
+}
+
+impl Ttl {
+    /// Returns whether the row has expired at the given time.
+    pub fn is_expired(&self, row: &[Value], now: SystemTime) -> bool {
+        let Some(Value::Integer(time)) = row.get(self.column) else {
+            return false;
+        };
+        let now = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+        let expires = (*time as i128) * 1000 + self.duration.as_millis() as i128;
+        expires <= now as i128
+    }
+}
+
 /// A table column.
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct Column {
@@ -47,6 +119,9 @@ pub struct Column {
     /// If set, this column is a foreign key reference to the given table's
     /// primary key. Must be of the same type as the target primary key.
     pub references: Option<String>,
+    /// Whether the column should have a full-text index, mapping the terms of
+    /// its text values to primary keys. Only valid for string columns.
+    pub fulltext: bool,
 }
 
 impl std::fmt::Display for Table {
@@ -60,7 +135,7 @@ impl std::fmt::Display for Table {
                 write!(f, " NOT NULL")?;
             }
             if let Some(default) = &column.default {
-                write!(f, " DEFAULT {default}")?;
+                write!(f, " DEFAULT {}", default.to_sql())?;
             }
             if i != self.primary_key {
                 if column.unique {
@@ -70,15 +145,23 @@ impl std::fmt::Display for Table {
                     write!(f, " INDEX")?;
                 }
             }
+            if column.fulltext {
+                write!(f, " FULLTEXT")?;
+            }
             if let Some(reference) = &column.references {
-                write!(f, " REFERENCES {reference}")?;
+                write!(f, " REFERENCES {}", format_ident(reference))?;
             }
             if i < self.columns.len() - 1 {
                 write!(f, ",")?;
             }
             writeln!(f)?;
         }
-        write!(f, ")")
+        write!(f, ")")?;
+        if let Some(ttl) = &self.ttl {
+            let column = format_ident(&self.columns[ttl.column].name);
+            write!(f, " WITH (ttl = '{}', ttl_column = {column})", format_duration(ttl.duration))?;
+        }
+        Ok(())
     }
 }
 
@@ -95,6 +178,17 @@ impl Table {
         if self.columns.get(self.primary_key).is_none() {
             return errinput!("invalid primary key index");
         }
+        if let Some(ttl) = &self.ttl {
+            let Some(column) = self.columns.get(ttl.column) else {
+                return errinput!("invalid TTL column index");
+            };
+            if column.datatype != DataType::Integer {
+                return errinput!("TTL column {} must be an integer column", column.name);
+            }
+            if ttl.duration.is_zero() {
+                return errinput!("TTL must be positive");
+            }
+        }
 
         for (i, column) in self.columns.iter().enumerate() {
             if column.name.is_empty() {
@@ -135,6 +229,11 @@ impl Table {
                 return errinput!("unique column {cname} must have a secondary index");
             }
 
+            // Validate full-text index.
+            if column.fulltext && column.datatype != DataType::String {
+                return errinput!("full-text index column {cname} must be a string column");
+            }
+
             // Validate references.
             if let Some(reference) = &column.references {
                 if !column.index && !is_primary_key {
@@ -173,10 +272,10 @@ impl Table {
         let id = &row[self.primary_key];
         let idslice = &row[self.primary_key..=self.primary_key];
         if id.is_undefined() {
-            return errinput!("invalid primary key {id}");
+            return errinput!("invalid primary key");
         }
         if !update && !txn.get(&self.name, idslice)?.is_empty() {
-            return errinput!("primary key {id} already exists");
+            return Err(Error::UniqueViolation("primary key already exists".into()));
         }
 
         for (i, (column, value)) in self.columns.iter().zip(row).enumerate() {
@@ -214,7 +313,8 @@ impl Table {
                     index.remove(id); // ignore existing version of this row
                 }
                 if !index.is_empty() {
-                    return errinput!("value {value} already in unique column {cname}");
+                    let msg = format!("value {value} already in unique column {cname}");
+                    return Err(Error::UniqueViolation(msg));
                 }
             }
         }
@@ -222,10 +322,375 @@ impl Table {
     }
 }
 
+/// A changefeed, which emits the row changes of the given tables as JSON events
+/// to a sink. Changefeeds are stored in the catalog, and emitted by the server's
+/// changefeeds maintenance task (see `MaintenanceTask::changefeeds()`).
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Changefeed {
+    /// The changefeed name. Can't be empty.
+    pub name: String,
+    /// The tables to emit changes for. Must have at least one.
+    pub tables: Vec<String>,
+    /// The sink URI. Only `file://PATH` sinks are supported.
+    pub sink: String,
+}
+
+impl encoding::Value for Changefeed {}
+impl encoding::Versioned for Changefeed {}
+
+impl Changefeed {
+    /// The URI scheme of file sinks.
+    const FILE_SCHEME: &'static str = "file://";
+
+    /// Validates the changefeed, using the catalog to check that the tables
+    /// exist.
+    pub fn validate(&self, catalog: &impl Catalog) -> Result<()> {
+        if self.name.is_empty() {
+            return errinput!("changefeed name can't be empty");
+        }
+        if self.tables.is_empty() {
+            return errinput!("changefeed has no tables");
+        }
+        for (i, table) in self.tables.iter().enumerate() {
+            if self.tables[..i].contains(table) {
+                return errinput!("duplicate table {table} in changefeed");
+            }
+            if InformationSchema::lookup(table).is_some() || SystemTable::lookup(table).is_some() {
+                return errinput!("can't emit changes for system table {table}");
+            }
+            catalog.must_get_table(table)?;
+        }
+        self.sink_path()?;
+        Ok(())
+    }
+
+    /// Returns the sink's file path. Relative paths are relative to the server's
+    /// working directory.
+    pub fn sink_path(&self) -> Result<&Path> {
+        match self.sink.strip_prefix(Self::FILE_SCHEME) {
+            Some(path) if !path.is_empty() => Ok(Path::new(path)),
+            Some(_) => errinput!("changefeed sink {} has no path", self.sink),
+            None => errinput!("unsupported changefeed sink {}, must be file://PATH", self.sink),
+        }
+    }
+}
+
+impl std::fmt::Display for Changefeed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let tables = self.tables.iter().map(|t| format_ident(t)).collect::<Vec<_>>().join(", ");
+        let sink = Value::String(self.sink.clone()).to_sql();
+        write!(f, "CREATE CHANGEFEED {} FOR {tables} INTO {sink}", format_ident(&self.name))
+    }
+}
+
+/// A row trigger, which executes an action statement for each row written to a
+/// table by the trigger's event, in the same transaction. The action is an
+/// INSERT, UPDATE, or DELETE statement stored as SQL text, which can refer to
+/// the written row's values via NEW.column (INSERT and UPDATE) and OLD.column
+/// (UPDATE and DELETE). Triggers are stored in the catalog and fired by the
+/// write executors. Trigger actions don't fire further triggers.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Trigger {
+    /// The trigger name, unique per table. Can't be empty.
+    pub name: String,
+    /// The table to fire the trigger for.
+    pub table: String,
+    /// Whether to fire the trigger before or after the rows are written.
+    pub timing: TriggerTiming,
+    /// The write event to fire the trigger for.
+    pub event: TriggerEvent,
+    /// The action statement, as SQL text.
+    pub action: String,
+}
+
+/// When a trigger fires, relative to the statement's row writes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum TriggerTiming {
+    /// Fire before any rows are written.
+    Before,
+    /// Fire after all rows are written.
+    After,
+}
+
+/// The write event that fires a trigger.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum TriggerEvent {
+    Insert,
+    Update,
+    Delete,
+}
+
+impl encoding::Value for Trigger {}
+impl encoding::Versioned for Trigger {}
+
+impl Trigger {
+    /// Validates the trigger, using the catalog to check that the table exists
+    /// and that the action can be planned against it.
+    pub fn validate(&self, catalog: &impl Catalog) -> Result<()> {
+        if self.name.is_empty() {
+            return errinput!("trigger name can't be empty");
+        }
+        if InformationSchema::lookup(&self.table).is_some()
+            || SystemTable::lookup(&self.table).is_some()
+        {
+            return errinput!("can't create trigger on system table {}", self.table);
+        }
+        let table = catalog.must_get_table(&self.table)?;
+        let (target, _) = self.target()?;
+        if InformationSchema::lookup(&target).is_some() || SystemTable::lookup(&target).is_some() {
+            return errinput!("trigger action can't write to system table {target}");
+        }
+
+        // Plan the action with NULL rows, to check the columns and tables.
+        let nulls = vec![Value::Null; table.columns.len()];
+        let (old, new) = match self.event {
+            TriggerEvent::Insert => (None, Some(&nulls)),
+            TriggerEvent::Update => (Some(&nulls), Some(&nulls)),
+            TriggerEvent::Delete => (Some(&nulls), None),
+        };
+        Plan::build(self.bind(&table, old, new)?, catalog)?;
+        Ok(())
+    }
+
+    /// Parses the action statement. It must be an INSERT, UPDATE, or DELETE
+    /// without parameters.
+    pub fn statement(&self) -> Result<ast::Statement> {
+        let mut parser = Parser::new(&self.action);
+        let statement = parser.parse()?;
+        if parser.parameters() > 0 {
+            return errinput!("trigger action can't have parameters");
+        }
+        match statement {
+            ast::Statement::Insert { .. }
+            | ast::Statement::Update { .. }
+            | ast::Statement::Delete { .. } => Ok(statement),
+            _ => errinput!("trigger action must be INSERT, UPDATE, or DELETE"),
+        }
+    }
+
+    /// Returns the action's target table and the privilege it requires.
+    pub fn target(&self) -> Result<(String, Privilege)> {
+        Ok(match self.statement()? {
+            ast::Statement::Insert { table, .. } => (table, Privilege::Insert),
+            ast::Statement::Update { table, .. } => (table, Privilege::Update),
+            ast::Statement::Delete { table, .. } => (table, Privilege::Delete),
+            statement => panic!("unexpected trigger statement {statement:?}"),
+        })
+    }
+
+    /// Returns the action statement for a written row of the given table, with
+    /// NEW and OLD column references replaced by the new and old row values.
+    /// Errors if the action refers to an unavailable row.
+    pub fn bind(
+        &self,
+        table: &Table,
+        old: Option<&Row>,
+        new: Option<&Row>,
+    ) -> Result<ast::Statement> {
+        let mut statement = self.statement()?;
+        let mut result = Ok(());
+        statement.visit_expressions_mut(&mut |expr| {
+            expr.visit_mut(&mut |expr| {
+                let ast::Expression::Column(Some(qualifier), column) = expr else {
+                    return;
+                };
+                let (name, row) = match qualifier.as_str() {
+                    "new" => ("NEW", new),
+                    "old" => ("OLD", old),
+                    _ => return,
+                };
+                let Some(row) = row else {
+                    if result.is_ok() {
+                        result = errinput!("{name} is not available in {} triggers", self.event);
+                    }
+                    return;
+                };
+                let Some(index) = table.columns.iter().position(|c| &c.name == column) else {
+                    if result.is_ok() {
+                        result = errinput!("unknown column {column} in table {}", table.name);
+                    }
+                    return;
+                };
+                *expr = ast::Expression::Literal(row[index].clone().into());
+            })
+        });
+        result.map(|_| statement)
+    }
+}
+
+impl std::fmt::Display for Trigger {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "CREATE TRIGGER {} {} {} ON {} {}",
+            format_ident(&self.name),
+            self.timing,
+            self.event,
+            format_ident(&self.table),
+            self.action
+        )
+    }
+}
+
+impl std::fmt::Display for TriggerTiming {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Before => "BEFORE",
+            Self::After => "AFTER",
+        })
+    }
+}
+
+impl std::fmt::Display for TriggerEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Insert => "INSERT",
+            Self::Update => "UPDATE",
+            Self::Delete => "DELETE",
+        })
+    }
+}
+
+/// A user-defined scalar function, given as a single SQL expression over its
+/// parameters, e.g. `CREATE FUNCTION double(x INT) RETURNS INT AS x * 2`.
+/// Functions are stored in the catalog, and the planner inlines calls by
+/// replacing them with the body expression. The parameter and return types are
+/// part of the function signature, but values aren't converted or checked.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Function {
+    /// The function name. Can't be empty, or a built-in function name.
+    pub name: String,
+    /// The parameter names and types. Names must be unique.
+    pub parameters: Vec<(String, DataType)>,
+    /// The return type.
+    pub returns: DataType,
+    /// The body expression, as SQL text. It can only refer to the parameters,
+    /// and only call built-in functions.
+    pub body: String,
+}
+
+impl encoding::Value for Function {}
+impl encoding::Versioned for Function {}
+
+impl Function {
+    /// Validates the function signature and body syntax. The planner checks
+    /// that the body can be built when creating the function.
+    pub fn validate(&self) -> Result<()> {
+        if self.name.is_empty() {
+            return errinput!("function name can't be empty");
+        }
+        for (i, (name, _)) in self.parameters.iter().enumerate() {
+            if self.parameters[..i].iter().any(|(n, _)| n == name) {
+                return errinput!("duplicate parameter {name} in function {}", self.name);
+            }
+        }
+        self.expression()?;
+        Ok(())
+    }
+
+    /// Parses the body expression. It can't have positional parameters (?).
+    pub fn expression(&self) -> Result<ast::Expression> {
+        let mut parser = Parser::new(&self.body);
+        let expr = parser.parse_expression()?;
+        if let Some(token) = parser.next_token()? {
+            return errinput!("unexpected token {token} in function body");
+        }
+        if parser.parameters() > 0 {
+            return errinput!("function body can't have parameters");
+        }
+        Ok(expr)
+    }
+
+    /// Inlines a call to the function, returning the body expression with
+    /// parameter references replaced by the given arguments.
+    pub fn inline(&self, args: Vec<ast::Expression>) -> Result<ast::Expression> {
+        if args.len() != self.parameters.len() {
+            return errinput!(
+                "function {} takes {} arguments, got {}",
+                self.name,
+                self.parameters.len(),
+                args.len()
+            );
+        }
+        let mut expr = self.expression()?;
+        expr.visit_mut(&mut |expr| {
+            let ast::Expression::Column(None, column) = expr else {
+                return;
+            };
+            if let Some(i) = self.parameters.iter().position(|(name, _)| name == column) {
+                *expr = args[i].clone();
+            }
+        });
+        Ok(expr)
+    }
+}
+
+impl std::fmt::Display for Function {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let parameters = self
+            .parameters
+            .iter()
+            .map(|(name, datatype)| format!("{} {datatype}", format_ident(name)))
+            .collect::<Vec<_>>()
+            .join(", ");
+        write!(
+            f,
+            "CREATE FUNCTION {}({parameters}) RETURNS {} AS {}",
+            format_ident(&self.name),
+            self.returns,
+            self.body
+        )
+    }
+}
+
+/// A database, i.e. an isolated namespace of tables, functions, triggers, and
+/// changefeeds, with its own privileges. Users and roles are shared by all
+/// databases. The default database always exists, and is used by sessions
+/// until they switch databases with USE.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Database {
+    /// The database name. Can't be empty.
+    pub name: String,
+}
+
+impl encoding::Value for Database {}
+impl encoding::Versioned for Database {}
+
+impl Database {
+    /// The default database.
+    pub const DEFAULT: &'static str = "main";
+
+    /// Validates the database.
+    pub fn validate(&self) -> Result<()> {
+        if self.name.is_empty() {
+            return errinput!("database name can't be empty");
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Database {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "CREATE DATABASE {}", format_ident(&self.name))
+    }
+}
+
 /// Formats an identifier as valid SQL, quoting it if necessary.
-fn format_ident(ident: &str) -> Cow<str> {
+pub fn format_ident(ident: &str) -> Cow<str> {
     if crate::sql::parser::is_ident(ident) {
         return ident.into();
     }
     format!("\"{}\"", ident.replace('\"', "\"\"")).into()
 }
+
+/// Formats a duration as a number with the largest unit that represents it
+/// exactly, e.g. 30d or 90min. Sub-millisecond precision is dropped.
+pub fn format_duration(duration: Duration) -> String {
+    let ms = duration.as_millis();
+    for (unit, size) in [("d", 86_400_000), ("h", 3_600_000), ("min", 60_000), ("s", 1_000)] {
+        if ms > 0 && ms.is_multiple_of(size) {
+            return format!("{}{unit}", ms / size);
+        }
+    }
+    format!("{ms}ms")
+}
@@ -0,0 +1,359 @@
+use super::{Expression, Row, Value, ValueRange};
+use crate::error::Result;
+
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::ops::Bound;
+
+/// The maximum number of most common values kept per column.
+const MOST_COMMON_VALUES: usize = 10;
+
+/// The number of equi-depth histogram buckets per column.
+const HISTOGRAM_BUCKETS: usize = 10;
+
+/// The selectivity of predicates that can't be estimated from statistics.
+const DEFAULT_SELECTIVITY: f64 = 0.5;
+
+/// Table statistics, collected by ANALYZE and stored in the table schema. The
+/// optimizer uses them to estimate the selectivity of predicates, i.e. the
+/// fraction of rows they match, e.g. to decide whether an index lookup is
+/// cheaper than a table scan.
+///
+/// Statistics aren't maintained by writes, so they're a snapshot of the table
+/// as of the last ANALYZE and become stale as the table changes.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Statistics {
+    /// The number of rows in the table.
+    pub rows: u64,
+    /// Per-column statistics, in column order.
+    pub columns: Vec<ColumnStatistics>,
+}
+
+/// Column statistics.
+///
+/// The most common values are tracked exactly, and the remaining non-NULL
+/// values are summarized by an equi-depth histogram: a list of bounds where
+/// each bucket between consecutive bounds holds roughly the same number of
+/// values. Values within a bucket are assumed to be uniformly distributed.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct ColumnStatistics {
+    /// The number of NULL values.
+    pub nulls: u64,
+    /// The number of distinct non-NULL values.
+    pub distinct: u64,
+    /// The most common values and their number of rows, most common first.
+    /// Only values that are more common than the average value are included.
+    pub most_common: Vec<(Value, u64)>,
+    /// The histogram bucket bounds of the remaining non-NULL values, in
+    /// ascending order. Empty if there are no remaining values.
+    pub histogram: Vec<Value>,
+}
+
+impl Statistics {
+    /// Builds statistics from a full scan of the table's rows.
+    pub fn build(columns: usize, rows: impl Iterator<Item = Result<Row>>) -> Result<Self> {
+        let mut values = vec![Vec::new(); columns];
+        let mut count = 0;
+        for row in rows {
+            for (column, value) in values.iter_mut().zip(row?) {
+                column.push(value);
+            }
+            count += 1;
+        }
+        let columns = values.into_iter().map(ColumnStatistics::build).collect();
+        Ok(Self { rows: count, columns })
+    }
+
+    /// Estimates the fraction of rows that match the given predicate, where
+    /// column references are table column indexes.
+    pub fn selectivity(&self, predicate: &Expression) -> f64 {
+        use Expression::*;
+
+        if self.rows == 0 {
+            return 0.0;
+        }
+        // Returns the column and constant of a column/constant comparison,
+        // and whether the column is on the left-hand side.
+        let operands = |lhs: &Expression, rhs: &Expression| match (lhs, rhs) {
+            (Column(c), Constant(v)) => self.columns.get(*c).map(|s| (s, v.clone(), true)),
+            (Constant(v), Column(c)) => self.columns.get(*c).map(|s| (s, v.clone(), false)),
+            _ => None,
+        };
+        let rows = match predicate {
+            Constant(Value::Boolean(true)) => return 1.0,
+            Constant(_) => return 0.0,
+            And(lhs, rhs) => return self.selectivity(lhs) * self.selectivity(rhs),
+            Or(lhs, rhs) => {
+                let (lhs, rhs) = (self.selectivity(lhs), self.selectivity(rhs));
+                return lhs + rhs - lhs * rhs;
+            }
+            Not(expr) => return 1.0 - self.selectivity(expr),
+            Equal(lhs, rhs) => match operands(lhs, rhs) {
+                Some((column, value, _)) => column.equal(&value, self.rows),
+                None => return DEFAULT_SELECTIVITY,
+            },
+            LessThan(lhs, rhs) => match operands(lhs, rhs) {
+                Some((column, value, true)) => column.less(&value, self.rows),
+                Some((column, value, false)) => column.greater(&value, self.rows),
+                None => return DEFAULT_SELECTIVITY,
+            },
+            GreaterThan(lhs, rhs) => match operands(lhs, rhs) {
+                Some((column, value, true)) => column.greater(&value, self.rows),
+                Some((column, value, false)) => column.less(&value, self.rows),
+                None => return DEFAULT_SELECTIVITY,
+            },
+            Is(expr, Value::Null) => match expr.as_ref() {
+                Column(c) => self.columns.get(*c).map_or(0.0, |s| s.nulls as f64),
+                _ => return DEFAULT_SELECTIVITY,
+            },
+            _ => return DEFAULT_SELECTIVITY,
+        };
+        (rows / self.rows as f64).clamp(0.0, 1.0)
+    }
+
+    /// Estimates the fraction of rows where the given column has any of the
+    /// given values, as for an index lookup. NULL values match NULLs.
+    pub fn lookup_selectivity(&self, column: usize, values: &[Value]) -> f64 {
+        let Some(stats) = self.columns.get(column).filter(|_| self.rows > 0) else {
+            return 0.0;
+        };
+        let rows: f64 = values
+            .iter()
+            .map(|v| match v {
+                Value::Null => stats.nulls as f64,
+                v => stats.equal(v, self.rows),
+            })
+            .sum();
+        (rows / self.rows as f64).clamp(0.0, 1.0)
+    }
+
+    /// Estimates the fraction of rows where the given column is in the given
+    /// range, as for a range scan. NULL values are never in the range.
+    pub fn range_selectivity(&self, column: usize, (start, end): &ValueRange) -> f64 {
+        let Some(stats) = self.columns.get(column).filter(|_| self.rows > 0) else {
+            return 0.0;
+        };
+        let below = match start {
+            Bound::Included(v) => stats.less(v, self.rows),
+            Bound::Excluded(v) => stats.less(v, self.rows) + stats.equal(v, self.rows),
+            Bound::Unbounded => 0.0,
+        };
+        let above = match end {
+            Bound::Included(v) => stats.greater(v, self.rows),
+            Bound::Excluded(v) => stats.greater(v, self.rows) + stats.equal(v, self.rows),
+            Bound::Unbounded => 0.0,
+        };
+        let rows = self.rows.saturating_sub(stats.nulls) as f64 - below - above;
+        (rows / self.rows as f64).clamp(0.0, 1.0)
+    }
+}
+
+impl ColumnStatistics {
+    /// Builds column statistics from all of the column's values.
+    fn build(mut values: Vec<Value>) -> Self {
+        values.sort();
+        let nulls = values.iter().take_while(|v| matches!(v, Value::Null)).count();
+
+        // Count the number of occurrences of each distinct value.
+        let mut counts: Vec<(Value, u64)> = Vec::new();
+        for value in values.into_iter().skip(nulls) {
+            match counts.last_mut() {
+                Some((last, count)) if (*last).cmp(&value) == Ordering::Equal => *count += 1,
+                _ => counts.push((value, 1)),
+            }
+        }
+        let distinct = counts.len() as u64;
+        let total: u64 = counts.iter().map(|(_, count)| count).sum();
+
+        // Pick the most common values, if they're more common than average.
+        let average = total as f64 / distinct.max(1) as f64;
+        let mut common: Vec<usize> = (0..counts.len())
+            .filter(|i| counts[*i].1 > 1 && counts[*i].1 as f64 > average)
+            .collect();
+        common.sort_by(|a, b| counts[*b].1.cmp(&counts[*a].1).then(a.cmp(b)));
+        common.truncate(MOST_COMMON_VALUES);
+        let most_common = common.iter().map(|i| counts[*i].clone()).collect();
+
+        // Build the histogram from the remaining values, picking the bounds
+        // at evenly spaced positions.
+        let rest: Vec<(Value, u64)> = counts
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| !common.contains(i))
+            .map(|(_, c)| c)
+            .collect();
+        let size: u64 = rest.iter().map(|(_, count)| count).sum();
+        let buckets = (HISTOGRAM_BUCKETS as u64).min(size);
+        let mut histogram = Vec::new();
+        if buckets > 0 {
+            let mut iter = rest.iter();
+            let (mut value, mut end) = iter.next().map(|(v, c)| (v, *c)).expect("no values");
+            for i in 0..=buckets {
+                let position = i * (size - 1) / buckets;
+                while position >= end {
+                    let (v, c) = iter.next().expect("position out of bounds");
+                    (value, end) = (v, end + c);
+                }
+                histogram.push(value.clone());
+            }
+        }
+
+        Self { nulls: nulls as u64, distinct, most_common, histogram }
+    }
+
+    /// Returns the number of non-NULL values summarized by the histogram.
+    fn histogram_rows(&self, rows: u64) -> f64 {
+        let common: u64 = self.most_common.iter().map(|(_, count)| count).sum();
+        rows.saturating_sub(self.nulls).saturating_sub(common) as f64
+    }
+
+    /// Estimates the number of rows equal to the given value. The histogram
+    /// values are assumed to be equally common.
+    fn equal(&self, value: &Value, rows: u64) -> f64 {
+        if value.is_undefined() {
+            return 0.0;
+        }
+        if let Some((_, count)) = self.most_common.iter().find(|(v, _)| v == value) {
+            return *count as f64;
+        }
+        let (Some(first), Some(last)) = (self.histogram.first(), self.histogram.last()) else {
+            return 0.0;
+        };
+        if value < first || value > last {
+            return 0.0;
+        }
+        let distinct = self.distinct.saturating_sub(self.most_common.len() as u64).max(1);
+        self.histogram_rows(rows) / distinct as f64
+    }
+
+    /// Estimates the number of rows less than the given value.
+    fn less(&self, value: &Value, rows: u64) -> f64 {
+        if value.is_undefined() {
+            return 0.0;
+        }
+        let common: u64 =
+            self.most_common.iter().filter(|(v, _)| v < value).map(|(_, count)| count).sum();
+        common as f64 + self.histogram_rows(rows) * self.histogram_fraction(value)
+    }
+
+    /// Estimates the number of rows greater than the given value.
+    fn greater(&self, value: &Value, rows: u64) -> f64 {
+        if value.is_undefined() {
+            return 0.0;
+        }
+        let common: u64 =
+            self.most_common.iter().filter(|(v, _)| v > value).map(|(_, count)| count).sum();
+        common as f64 + self.histogram_rows(rows) * (1.0 - self.histogram_fraction(value))
+    }
+
+    /// Estimates the fraction of histogram values less than the given value,
+    /// interpolating numeric values within a bucket.
+    fn histogram_fraction(&self, value: &Value) -> f64 {
+        let (Some(first), Some(last)) = (self.histogram.first(), self.histogram.last()) else {
+            return 0.0;
+        };
+        if value <= first {
+            return 0.0;
+        } else if value > last {
+            return 1.0;
+        }
+        let buckets = self.histogram.len() - 1;
+        let bucket = self.histogram.windows(2).position(|b| value <= &b[1]).unwrap_or(buckets);
+        let (lower, upper) = (&self.histogram[bucket], &self.histogram[bucket + 1]);
+        let within = match (numeric(lower), numeric(upper), numeric(value)) {
+            (Some(lower), Some(upper), Some(value)) if upper > lower => {
+                (value - lower) / (upper - lower)
+            }
+            _ => 0.5,
+        };
+        (bucket as f64 + within) / buckets as f64
+    }
+}
+
+/// Returns a numeric value as a float, for interpolation within histogram
+/// buckets.
+fn numeric(value: &Value) -> Option<f64> {
+    match value {
+        Value::Integer(i) => Some(*i as f64),
+        Value::Float(f) if f.is_finite() => Some(*f),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build(values: Vec<Value>) -> Statistics {
+        let rows = values.into_iter().map(|v| Ok(vec![v]));
+        Statistics::build(1, rows).expect("build failed")
+    }
+
+    /// Column statistics track NULLs, distinct values, the most common values,
+    /// and histogram bounds of the remaining values.
+    #[test]
+    fn build_column() {
+        let mut values = vec![Value::Null, Value::Null];
+        values.extend([1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12].map(Value::Integer));
+        values.extend([5, 5, 5, 9, 9].map(Value::Integer));
+        let stats = build(values);
+
+        assert_eq!(stats.rows, 19);
+        let column = &stats.columns[0];
+        assert_eq!(column.nulls, 2);
+        assert_eq!(column.distinct, 12);
+        assert_eq!(column.most_common, vec![(Value::Integer(5), 4), (Value::Integer(9), 3)]);
+        assert_eq!(
+            column.histogram,
+            [1, 1, 2, 3, 4, 6, 7, 8, 10, 11, 12].map(Value::Integer).to_vec(),
+        );
+    }
+
+    /// Empty tables and all-NULL columns have no histogram.
+    #[test]
+    fn build_empty() {
+        let stats = build(Vec::new());
+        assert_eq!(stats.rows, 0);
+        assert!(stats.columns[0].histogram.is_empty());
+        assert_eq!(stats.selectivity(&Expression::Constant(true.into())), 0.0);
+
+        let stats = build(vec![Value::Null, Value::Null]);
+        let column = &stats.columns[0];
+        assert_eq!((column.nulls, column.distinct), (2, 0));
+        assert!(column.most_common.is_empty() && column.histogram.is_empty());
+    }
+
+    /// Predicate selectivity is estimated from the column statistics.
+    #[test]
+    fn selectivity() {
+        use Expression::*;
+
+        let mut values: Vec<Value> = (1..=80).map(Value::Integer).collect();
+        values.extend(vec![Value::Integer(100); 10]);
+        values.extend(vec![Value::Null; 10]);
+        let stats = build(values);
+
+        let column = || Box::new(Column(0));
+        let constant = |i: i64| Box::new(Constant(Value::Integer(i)));
+        let estimate = |expr: Expression| (stats.selectivity(&expr) * 100.0).round() / 100.0;
+
+        assert_eq!(estimate(Equal(column(), constant(100))), 0.1);
+        assert_eq!(estimate(Equal(column(), constant(50))), 0.01);
+        assert_eq!(estimate(Equal(constant(200), column())), 0.0);
+        assert_eq!(estimate(Equal(column(), Box::new(Constant(Value::Null)))), 0.0);
+        assert_eq!(estimate(Is(column(), Value::Null)), 0.1);
+        assert_eq!(estimate(LessThan(column(), constant(41))), 0.41);
+        assert_eq!(estimate(GreaterThan(constant(41), column())), 0.41);
+        assert_eq!(estimate(GreaterThan(column(), constant(80))), 0.1);
+        assert_eq!(estimate(Not(Box::new(LessThan(column(), constant(41))))), 0.59);
+        assert_eq!(
+            estimate(And(
+                Box::new(LessThan(column(), constant(41))),
+                Box::new(Equal(column(), constant(100)))
+            )),
+            0.04
+        );
+        assert_eq!(estimate(Like(column(), Box::new(Constant("%".into())))), 0.5);
+        assert_eq!(stats.lookup_selectivity(0, &[Value::Null, Value::Integer(100)]), 0.2);
+    }
+}
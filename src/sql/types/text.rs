@@ -0,0 +1,110 @@
+use crate::errinput;
+use crate::error::Result;
+
+use std::collections::BTreeSet;
+
+/// Tokenizes a text value into a set of full-text search terms. Terms are
+/// lowercased runs of alphanumeric characters, and everything else is treated
+/// as a separator. Full-text indexes contain an entry for each term.
+pub fn terms(text: &str) -> BTreeSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|term| !term.is_empty())
+        .map(|term| term.to_lowercase())
+        .collect()
+}
+
+/// A full-text search term.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SearchTerm {
+    /// Matches the exact term.
+    Exact(String),
+    /// Matches any term with the given prefix, written as term*.
+    Prefix(String),
+}
+
+impl SearchTerm {
+    /// Returns the term or prefix string.
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Exact(term) | Self::Prefix(term) => term,
+        }
+    }
+
+    /// Returns true if the term matches any of the given text terms.
+    fn matches(&self, terms: &BTreeSet<String>) -> bool {
+        match self {
+            Self::Exact(term) => terms.contains(term),
+            Self::Prefix(prefix) => {
+                terms.range(prefix.clone()..).next().is_some_and(|t| t.starts_with(prefix))
+            }
+        }
+    }
+}
+
+/// A full-text search query, as used by MATCH. The query is tokenized like
+/// text values, and a term followed by * matches any term with that prefix.
+/// A text value matches the query if it matches all of the query's terms.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchQuery(Vec<SearchTerm>);
+
+impl SearchQuery {
+    /// Parses a search query. Errors if the query doesn't contain any terms.
+    pub fn parse(query: &str) -> Result<Self> {
+        let mut terms = Vec::new();
+        let mut chars = query.chars().peekable();
+        while chars.peek().is_some() {
+            let term: String = std::iter::from_fn(|| chars.next_if(|c| c.is_alphanumeric()))
+                .flat_map(char::to_lowercase)
+                .collect();
+            if term.is_empty() {
+                chars.next();
+            } else if chars.next_if_eq(&'*').is_some() {
+                terms.push(SearchTerm::Prefix(term));
+            } else {
+                terms.push(SearchTerm::Exact(term));
+            }
+        }
+        if terms.is_empty() {
+            return errinput!("empty full-text search query {query:?}");
+        }
+        Ok(Self(terms))
+    }
+
+    /// Returns the query terms.
+    pub fn terms(&self) -> &[SearchTerm] {
+        &self.0
+    }
+
+    /// Returns true if the given text matches the query.
+    pub fn matches(&self, text: &str) -> bool {
+        let terms = terms(text);
+        self.0.iter().all(|term| term.matches(&terms))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn terms() {
+        let terms = super::terms("The quick, brown fox -- the QUICK one!");
+        assert_eq!(terms.into_iter().collect::<Vec<_>>(), ["brown", "fox", "one", "quick", "the"]);
+        assert!(super::terms(" ,.- ").is_empty());
+    }
+
+    #[test]
+    fn query() -> Result<()> {
+        let query = SearchQuery::parse("Quick bro*")?;
+        assert_eq!(
+            query.terms(),
+            [SearchTerm::Exact("quick".into()), SearchTerm::Prefix("bro".into())]
+        );
+        assert!(query.matches("the quick brown fox"));
+        assert!(query.matches("BROWNIE is quick"));
+        assert!(!query.matches("quick fox"));
+        assert!(!query.matches("quickly brown"));
+        assert!(SearchQuery::parse(" * ").is_err());
+        Ok(())
+    }
+}
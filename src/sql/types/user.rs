@@ -0,0 +1,286 @@
+use crate::encoding;
+use crate::errinput;
+use crate::error::Result;
+
+use argon2::password_hash::{PasswordHash, PasswordHasher as _, PasswordVerifier as _, SaltString};
+use argon2::Argon2;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// A database user, which SQL clients can authenticate as. Users are stored in
+/// the catalog alongside table schemas.
+///
+/// Superusers can do anything. Other users can only access tables via
+/// privileges granted to them, either directly or via their roles.
+///
+/// Passwords are never stored, only their Argon2 hash. The hash is computed
+/// when planning the statement, above Raft, such that the plaintext password is
+/// not written to the Raft log and state machine application is deterministic
+/// (the hash uses a random salt).
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct User {
+    /// The user name. Can't be empty.
+    pub name: String,
+    /// The Argon2 password hash, as a PHC string (which includes the algorithm
+    /// parameters and salt).
+    pub password_hash: String,
+    /// Whether the user is a superuser, which bypasses privilege checks and can
+    /// manage users, roles, and privileges.
+    pub superuser: bool,
+    /// The roles granted to the user.
+    pub roles: BTreeSet<String>,
+    /// The privileges granted directly to the user.
+    pub privileges: Privileges,
+}
+
+impl encoding::Value for User {}
+impl encoding::Versioned for User {}
+
+impl User {
+    /// Creates a new user with the given name and plaintext password, hashing
+    /// the password with a random salt. The user has no roles or privileges.
+    pub fn new(name: String, password: &str, superuser: bool) -> Result<Self> {
+        if name.is_empty() {
+            return errinput!("user name can't be empty");
+        }
+        let password_hash = Self::hash_password(password)?;
+        Ok(Self {
+            name,
+            password_hash,
+            superuser,
+            roles: BTreeSet::new(),
+            privileges: Privileges::default(),
+        })
+    }
+
+    /// Hashes a plaintext password with a random salt, returning a PHC string.
+    pub fn hash_password(password: &str) -> Result<String> {
+        if password.is_empty() {
+            return errinput!("password can't be empty");
+        }
+        let salt = SaltString::encode_b64(&rand::random::<[u8; 16]>())
+            .or_else(|err| errinput!("invalid password salt: {err}"))?;
+        Ok(Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .or_else(|err| errinput!("password hashing failed: {err}"))?
+            .to_string())
+    }
+
+    /// Returns true if the given plaintext password matches the user's
+    /// password hash.
+    pub fn verify_password(&self, password: &str) -> bool {
+        Self::verify_hash(&self.password_hash, password)
+    }
+
+    /// Verifies a password against a dummy hash, always returning false. Used
+    /// when authenticating an unknown user, such that it takes as long as for
+    /// a known user and the response time doesn't reveal whether it exists.
+    pub fn verify_dummy_password(password: &str) -> bool {
+        static DUMMY_HASH: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+        let hash = DUMMY_HASH.get_or_init(|| {
+            Self::hash_password("toydb-dummy-password").expect("dummy password hashing failed")
+        });
+        Self::verify_hash(hash, password);
+        false
+    }
+
+    /// Returns true if the given plaintext password matches the PHC hash.
+    fn verify_hash(hash: &str, password: &str) -> bool {
+        let Ok(hash) = PasswordHash::new(hash) else {
+            return false;
+        };
+        Argon2::default().verify_password(password.as_bytes(), &hash).is_ok()
+    }
+}
+
+/// A role, i.e. a named set of privileges which can be granted to users.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Role {
+    /// The role name. Can't be empty.
+    pub name: String,
+    /// The privileges granted to the role.
+    pub privileges: Privileges,
+}
+
+impl encoding::Value for Role {}
+impl encoding::Versioned for Role {}
+
+impl Role {
+    /// Creates a new role without any privileges.
+    pub fn new(name: String) -> Result<Self> {
+        if name.is_empty() {
+            return errinput!("role name can't be empty");
+        }
+        Ok(Self { name, privileges: Privileges::default() })
+    }
+}
+
+/// A table privilege.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+pub enum Privilege {
+    /// Read rows with SELECT.
+    Select,
+    /// Write rows with INSERT.
+    Insert,
+    /// Modify rows with UPDATE.
+    Update,
+    /// Remove rows with DELETE.
+    Delete,
+    /// Create and drop the table.
+    Ddl,
+}
+
+impl std::fmt::Display for Privilege {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Select => "SELECT",
+            Self::Insert => "INSERT",
+            Self::Update => "UPDATE",
+            Self::Delete => "DELETE",
+            Self::Ddl => "DDL",
+        })
+    }
+}
+
+/// A set of table privileges, by database and table name. A None table applies
+/// to all tables in the database, including ones that don't exist yet.
+/// Privileges in one database don't apply to other databases.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct Privileges(BTreeMap<(String, Option<String>), BTreeSet<Privilege>>);
+
+impl Privileges {
+    /// Grants the given privileges on a table in a database, or all tables in
+    /// the database if None.
+    pub fn grant(&mut self, database: &str, table: Option<&str>, privileges: &[Privilege]) {
+        let key = (database.to_string(), table.map(str::to_string));
+        self.0.entry(key).or_default().extend(privileges);
+    }
+
+    /// Revokes the given privileges on a table in a database, or all tables in
+    /// the database if None. Only privileges granted for that exact target are
+    /// revoked, i.e. revoking a privilege on a table doesn't affect a privilege
+    /// on all tables.
+    pub fn revoke(&mut self, database: &str, table: Option<&str>, privileges: &[Privilege]) {
+        let key = (database.to_string(), table.map(str::to_string));
+        if let Some(granted) = self.0.get_mut(&key) {
+            granted.retain(|p| !privileges.contains(p));
+            if granted.is_empty() {
+                self.0.remove(&key);
+            }
+        }
+    }
+
+    /// Returns true if the privilege has been granted on the given table in a
+    /// database, either explicitly or via all tables in the database.
+    pub fn allows(&self, database: &str, table: &str, privilege: Privilege) -> bool {
+        [Some(table.to_string()), None].into_iter().any(|table| {
+            let key = (database.to_string(), table);
+            self.0.get(&key).is_some_and(|granted| granted.contains(&privilege))
+        })
+    }
+
+    /// Removes all privileges in the given database, e.g. when it's dropped.
+    /// Returns true if any privileges were removed.
+    pub fn remove_database(&mut self, database: &str) -> bool {
+        let len = self.0.len();
+        self.0.retain(|(db, _), _| db != database);
+        self.0.len() != len
+    }
+}
+
+/// A GRANT or REVOKE target: either table privileges or a role. Privileges
+/// apply to tables in the session's current database.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum Grant {
+    /// Privileges on a table, or all tables if None.
+    Privileges { privileges: Vec<Privilege>, table: Option<String> },
+    /// Membership of a role.
+    Role(String),
+}
+
+impl std::fmt::Display for Grant {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Privileges { privileges, table } => {
+                let privileges = privileges.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+                write!(f, "{} ON {}", privileges.join(", "), table.as_deref().unwrap_or("*"))
+            }
+            Self::Role(role) => write!(f, "{role}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Passwords are hashed with a random salt, and can be verified.
+    #[test]
+    fn password() -> Result<()> {
+        let user = User::new("alice".into(), "secret", false)?;
+        assert!(user.password_hash.starts_with("$argon2id$"));
+        assert!(user.verify_password("secret"));
+        assert!(!user.verify_password("wrong"));
+        assert!(!user.verify_password(""));
+
+        let other = User::new("alice".into(), "secret", false)?;
+        assert_ne!(user.password_hash, other.password_hash);
+
+        // The dummy password never verifies, not even with the dummy password.
+        assert!(!User::verify_dummy_password("secret"));
+        assert!(!User::verify_dummy_password("toydb-dummy-password"));
+        Ok(())
+    }
+
+    /// Empty names and passwords are rejected.
+    #[test]
+    fn empty() {
+        assert!(User::new("".into(), "secret", false).is_err());
+        assert!(User::new("alice".into(), "", false).is_err());
+    }
+
+    /// Privileges can be granted and revoked on individual tables or all
+    /// tables.
+    #[test]
+    fn privileges() {
+        let mut privileges = Privileges::default();
+        assert!(!privileges.allows("main", "movies", Privilege::Select));
+
+        privileges.grant("main", Some("movies"), &[Privilege::Select, Privilege::Insert]);
+        assert!(privileges.allows("main", "movies", Privilege::Select));
+        assert!(privileges.allows("main", "movies", Privilege::Insert));
+        assert!(!privileges.allows("main", "movies", Privilege::Delete));
+        assert!(!privileges.allows("main", "genres", Privilege::Select));
+
+        privileges.grant("main", None, &[Privilege::Select]);
+        assert!(privileges.allows("main", "genres", Privilege::Select));
+
+        // Revoking on a table doesn't revoke on all tables, and vice versa.
+        privileges.revoke("main", Some("movies"), &[Privilege::Select]);
+        assert!(privileges.allows("main", "movies", Privilege::Select));
+        privileges.revoke("main", None, &[Privilege::Select]);
+        assert!(!privileges.allows("main", "movies", Privilege::Select));
+        assert!(privileges.allows("main", "movies", Privilege::Insert));
+
+        privileges.revoke("main", Some("movies"), &[Privilege::Insert]);
+        assert_eq!(privileges, Privileges::default());
+    }
+
+    /// Privileges only apply to the database they were granted in, and can be
+    /// removed for an entire database.
+    #[test]
+    fn privileges_database() {
+        let mut privileges = Privileges::default();
+        privileges.grant("main", Some("movies"), &[Privilege::Select]);
+        privileges.grant("other", None, &[Privilege::Insert]);
+        assert!(privileges.allows("main", "movies", Privilege::Select));
+        assert!(!privileges.allows("other", "movies", Privilege::Select));
+        assert!(privileges.allows("other", "movies", Privilege::Insert));
+        assert!(!privileges.allows("main", "movies", Privilege::Insert));
+
+        assert!(privileges.remove_database("other"));
+        assert!(!privileges.remove_database("other"));
+        assert!(!privileges.allows("other", "movies", Privilege::Insert));
+        assert!(privileges.allows("main", "movies", Privilege::Select));
+    }
+}
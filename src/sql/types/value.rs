@@ -6,11 +6,13 @@ use crate::{errdata, errinput};
 use dyn_clone::DynClone;
 use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
+use std::ops::Bound;
 
 /// A primitive SQL value.
 ///
 /// For simplicity, only a handful of representative scalar types are supported,
-/// no compound types or more compact variants.
+/// no compound types or more compact variants. The exception is geospatial
+/// points, which are stored as a single value for simple location filtering.
 ///
 /// In SQL, neither Null nor floating point NaN are considered equal to
 /// themselves (they are unknown values). However, in code, we consider them
@@ -33,6 +35,9 @@ pub enum Value {
     Float(f64),
     /// A UTF-8 encoded string.
     String(String),
+    /// A 2D point with x and y coordinates, e.g. longitude and latitude. The
+    /// coordinates can't be NaN.
+    Point(f64, f64),
 }
 
 impl encoding::Value for Value {}
@@ -47,6 +52,7 @@ impl PartialEq for Value {
             (Self::Integer(l), Self::Integer(r)) => l == r,
             (Self::Float(l), Self::Float(r)) => l == r || l.is_nan() && r.is_nan(),
             (Self::String(l), Self::String(r)) => l == r,
+            (Self::Point(lx, ly), Self::Point(rx, ry)) => lx == rx && ly == ry,
             (l, r) => core::mem::discriminant(l) == core::mem::discriminant(r),
         }
     }
@@ -65,6 +71,10 @@ impl std::hash::Hash for Value {
             Self::Integer(v) => v.hash(state),
             Self::Float(v) => v.to_bits().hash(state),
             Self::String(v) => v.hash(state),
+            Self::Point(x, y) => {
+                x.to_bits().hash(state);
+                y.to_bits().hash(state);
+            }
         }
     }
 }
@@ -83,6 +93,7 @@ impl Ord for Value {
             (Float(a), Integer(b)) => a.total_cmp(&(*b as f64)),
             (Float(a), Float(b)) => a.total_cmp(b),
             (String(a), String(b)) => a.cmp(b),
+            (Point(ax, ay), Point(bx, by)) => ax.total_cmp(bx).then(ay.total_cmp(by)),
 
             (Null, _) => Less,
             (_, Null) => Greater,
@@ -92,7 +103,9 @@ impl Ord for Value {
             (_, Float(_)) => Greater,
             (Integer(_), _) => Less,
             (_, Integer(_)) => Greater,
-            // String is ordered last.
+            (String(_), _) => Less,
+            (_, String(_)) => Greater,
+            // Point is ordered last.
         }
     }
 }
@@ -218,6 +231,7 @@ impl Value {
             Self::Integer(_) => Some(DataType::Integer),
             Self::Float(_) => Some(DataType::Float),
             Self::String(_) => Some(DataType::String),
+            Self::Point(_, _) => Some(DataType::Point),
         }
     }
 
@@ -227,8 +241,8 @@ impl Value {
     }
 
     /// Normalizes a value in place. Currently, normalizes -0.0 and -NAN to 0.0
-    /// and NAN respectively, which is the canonical value used e.g. in primary
-    /// key and index lookups.
+    /// and NAN respectively (also in point coordinates), which is the canonical
+    /// value used e.g. in primary key and index lookups.
     pub fn normalize(&mut self) {
         if let Cow::Owned(normalized) = self.normalize_ref() {
             *self = normalized;
@@ -236,22 +250,52 @@ impl Value {
     }
 
     /// Normalizes a borrowed value. Currently, normalizes -0.0 and -NAN to 0.0
-    /// and NAN respectively, which is the canonical value used e.g. in primary
-    /// key and index lookups. Returns a Cow::Owned when changed, to avoid
-    /// allocating in the common case where the value doesn't change.
+    /// and NAN respectively (also in point coordinates), which is the canonical
+    /// value used e.g. in primary key and index lookups. Returns a Cow::Owned
+    /// when changed, to avoid allocating in the common case where the value
+    /// doesn't change.
     pub fn normalize_ref(&self) -> Cow<'_, Self> {
-        if let Self::Float(f) = self {
-            if (f.is_nan() || *f == -0.0) && f.is_sign_negative() {
-                return Cow::Owned(Self::Float(-f));
+        fn is_denormalized(f: f64) -> bool {
+            (f.is_nan() || f == -0.0) && f.is_sign_negative()
+        }
+        fn normalize(f: f64) -> f64 {
+            if is_denormalized(f) {
+                -f
+            } else {
+                f
             }
         }
-        Cow::Borrowed(self)
+        match self {
+            Self::Float(f) if is_denormalized(*f) => Cow::Owned(Self::Float(-f)),
+            Self::Point(x, y) if is_denormalized(*x) || is_denormalized(*y) => {
+                Cow::Owned(Self::Point(normalize(*x), normalize(*y)))
+            }
+            _ => Cow::Borrowed(self),
+        }
     }
 
     // Returns true if the value is already normalized.
     pub fn is_normalized(&self) -> bool {
         matches!(self.normalize_ref(), Cow::Borrowed(_))
     }
+
+    /// Formats the value as a SQL expression which evaluates to the same
+    /// value, e.g. for CREATE TABLE defaults or INSERT statements. Unlike the
+    /// Display implementation, this quotes strings as SQL string literals and
+    /// handles infinite floats and the minimum integer.
+    pub fn to_sql(&self) -> String {
+        match self {
+            Self::Integer(i64::MIN) => format!("({} - 1)", i64::MIN + 1),
+            Self::Float(f) if f.is_nan() => "NAN".to_string(),
+            Self::Float(f) if f.is_infinite() && *f > 0.0 => "INFINITY".to_string(),
+            Self::Float(f) if f.is_infinite() => "-INFINITY".to_string(),
+            Self::String(s) => format!("'{}'", s.replace('\'', "''")),
+            Self::Point(x, y) => {
+                format!("POINT({}, {})", Self::Float(*x).to_sql(), Self::Float(*y).to_sql())
+            }
+            value => value.to_string(),
+        }
+    }
 }
 
 impl std::fmt::Display for Value {
@@ -263,6 +307,7 @@ impl std::fmt::Display for Value {
             Self::Integer(integer) => integer.fmt(f),
             Self::Float(float) => write!(f, "{float:?}"),
             Self::String(string) => write!(f, "'{}'", string.escape_debug()),
+            Self::Point(x, y) => write!(f, "POINT({x:?}, {y:?})"),
         }
     }
 }
@@ -333,6 +378,20 @@ impl TryFrom<Value> for String {
     }
 }
 
+impl From<Value> for ast::Literal {
+    /// Builds an AST literal for a value, e.g. to bind statement parameters.
+    fn from(value: Value) -> Self {
+        match value {
+            Value::Null => Self::Null,
+            Value::Boolean(b) => Self::Boolean(b),
+            Value::Integer(i) => Self::Integer(i),
+            Value::Float(f) => Self::Float(f),
+            Value::String(s) => Self::String(s),
+            Value::Point(x, y) => Self::Point(x, y),
+        }
+    }
+}
+
 impl<'a> From<&'a Value> for Cow<'a, Value> {
     fn from(v: &'a Value) -> Self {
         Cow::Borrowed(v)
@@ -350,6 +409,8 @@ pub enum DataType {
     Float,
     /// A UTF-8 encoded string.
     String,
+    /// A 2D point with float coordinates.
+    Point,
 }
 
 impl std::fmt::Display for DataType {
@@ -359,6 +420,7 @@ impl std::fmt::Display for DataType {
             Self::Integer => write!(f, "INTEGER"),
             Self::Float => write!(f, "FLOAT"),
             Self::String => write!(f, "STRING"),
+            Self::Point => write!(f, "POINT"),
         }
     }
 }
@@ -366,6 +428,88 @@ impl std::fmt::Display for DataType {
 /// A row of values.
 pub type Row = Vec<Value>;
 
+/// Rows and index entries are stored with a format version.
+impl encoding::Versioned for Row {}
+impl encoding::Versioned for std::collections::BTreeSet<Value> {}
+
+/// A borrowed SQL value, which can be decoded from a buffer without copying
+/// strings. It has the same Bincode encoding as Value, and is used to avoid
+/// allocating every column value when decoding rows that may be discarded,
+/// e.g. rows rejected by a scan filter.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum ValueRef<'a> {
+    Null,
+    Boolean(bool),
+    Integer(i64),
+    Float(f64),
+    String(#[serde(borrow)] Cow<'a, str>),
+    Point(f64, f64),
+}
+
+impl ValueRef<'_> {
+    /// Decodes a stored row (see encoding::Versioned), borrowing strings from
+    /// the buffer. Rows stored with an older format version are migrated,
+    /// which requires decoding them into owned values.
+    pub fn decode_row(bytes: &[u8]) -> Result<Vec<ValueRef<'_>>> {
+        use encoding::Versioned as _;
+        match encoding::versioned::split(bytes)? {
+            (version, payload) if version == Row::VERSION => {
+                encoding::bincode::deserialize(payload)
+            }
+            _ => Ok(Row::decode_versioned(bytes)?.into_iter().map(ValueRef::from).collect()),
+        }
+    }
+
+    /// Copies the value into an owned value.
+    pub fn to_owned(&self) -> Value {
+        match self {
+            Self::Null => Value::Null,
+            Self::Boolean(b) => Value::Boolean(*b),
+            Self::Integer(i) => Value::Integer(*i),
+            Self::Float(f) => Value::Float(*f),
+            Self::String(s) => Value::String(s.to_string()),
+            Self::Point(x, y) => Value::Point(*x, *y),
+        }
+    }
+
+    /// Converts the value into an owned value, copying borrowed strings.
+    pub fn into_owned(self) -> Value {
+        match self {
+            Self::String(s) => Value::String(s.into_owned()),
+            value => value.to_owned(),
+        }
+    }
+}
+
+impl From<Value> for ValueRef<'_> {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::Null => Self::Null,
+            Value::Boolean(b) => Self::Boolean(b),
+            Value::Integer(i) => Self::Integer(i),
+            Value::Float(f) => Self::Float(f),
+            Value::String(s) => Self::String(Cow::Owned(s)),
+            Value::Point(x, y) => Self::Point(x, y),
+        }
+    }
+}
+
+impl<'a> From<&'a Value> for ValueRef<'a> {
+    fn from(value: &'a Value) -> Self {
+        match value {
+            Value::Null => Self::Null,
+            Value::Boolean(b) => Self::Boolean(*b),
+            Value::Integer(i) => Self::Integer(*i),
+            Value::Float(f) => Self::Float(*f),
+            Value::String(s) => Self::String(Cow::Borrowed(s)),
+            Value::Point(x, y) => Self::Point(*x, *y),
+        }
+    }
+}
+
+/// A range of values, as a start and end bound. Used for range scans.
+pub type ValueRange = (Bound<Value>, Bound<Value>);
+
 /// A row iterator.
 pub type Rows = Box<dyn RowIterator>;
 
@@ -424,3 +568,67 @@ impl From<Option<String>> for Label {
         name.map(Label::Unqualified).unwrap_or(Label::None)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::{bincode, Versioned as _};
+
+    /// Rows decode into borrowed values, which borrow strings from the buffer.
+    /// Legacy rows are decoded too.
+    #[test]
+    fn decode_row_ref() -> Result<()> {
+        let row: Row = vec![
+            Value::Null,
+            true.into(),
+            1.into(),
+            2.5.into(),
+            "foo".into(),
+            Value::Point(1.0, 2.0),
+        ];
+        let bytes = row.encode_versioned();
+        let decoded = ValueRef::decode_row(&bytes)?;
+        assert!(matches!(&decoded[4], ValueRef::String(Cow::Borrowed("foo"))));
+        assert_eq!(decoded.into_iter().map(|v| v.into_owned()).collect::<Row>(), row);
+
+        let legacy = bincode::serialize(&row);
+        let decoded = ValueRef::decode_row(&legacy)?;
+        assert_eq!(decoded.iter().map(|v| v.to_owned()).collect::<Row>(), row);
+        Ok(())
+    }
+
+    /// Values formatted as SQL evaluate to the same value.
+    #[test]
+    fn to_sql() -> Result<()> {
+        use crate::sql::engine::{Engine as _, Local, StatementResult};
+        use crate::storage::Memory;
+
+        let engine = Local::new(Memory::new());
+        let mut session = engine.session();
+        for value in [
+            Value::Null,
+            true.into(),
+            0.into(),
+            i64::MIN.into(),
+            i64::MAX.into(),
+            (-0.0).into(),
+            1e300.into(),
+            (-1.5e-7).into(),
+            f64::NAN.into(),
+            f64::INFINITY.into(),
+            f64::NEG_INFINITY.into(),
+            "it's a\nmulti-line \"string\"".into(),
+            Value::Point(1.5, -2.0),
+            Value::Point(f64::INFINITY, 0.0),
+        ] {
+            let result = session.execute(&format!("SELECT {}", value.to_sql()))?;
+            let StatementResult::Select { mut rows, .. } = result else { panic!("expected rows") };
+            let result = rows.remove(0).remove(0);
+            assert_eq!(result, value);
+            if let (Value::Float(result), Value::Float(value)) = (result, &value) {
+                assert_eq!(result.is_sign_negative(), value.is_sign_negative());
+            }
+        }
+        Ok(())
+    }
+}
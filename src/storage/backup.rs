@@ -0,0 +1,252 @@
+//! Storage engine backup files, see Engine::backup() and Engine::restore().
+//!
+//! A backup file contains a point-in-time copy of every key/value pair in an
+//! engine, in key order. It is independent of the engine's on-disk format, so
+//! a backup taken from one engine can be restored into another. The format is:
+//!
+//! * The magic bytes `toydbbk1`.
+//! * For each key/value pair: the key length and value length as big-endian
+//!   u32s, followed by the key and value bytes.
+//! * The end marker u32::MAX, the number of key/value pairs as a big-endian
+//!   u64, and a big-endian CRC32 checksum of all preceding bytes.
+//!
+//! The file is written to a temporary file and then renamed into place, so an
+//! interrupted backup never leaves behind a partial file at the destination.
+
+use super::{ScanIterator, WriteBatch};
+use crate::errdata;
+use crate::error::Result;
+
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+/// The magic bytes at the start of a backup file.
+const MAGIC: &[u8; 8] = b"toydbbk1";
+
+/// The length marker that ends the key/value pairs.
+const END_MARKER: u32 = u32::MAX;
+
+/// The number of key/value pairs to restore per write batch.
+pub const BATCH_SIZE: usize = 1024;
+
+/// Writes the key/value pairs of the given iterator to a backup file at dest,
+/// replacing any existing file. Returns the number of key/value pairs written.
+pub fn write(scan: impl ScanIterator, dest: &Path) -> Result<u64> {
+    let mut tmp_path = dest.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let file = std::fs::File::create(&tmp_path)?;
+    let mut writer = ChecksumWriter::new(BufWriter::new(file));
+    writer.write_all(MAGIC)?;
+    let mut count = 0u64;
+    for item in scan {
+        let (key, value) = item?;
+        let (Ok(key_len), Ok(value_len)) = (u32::try_from(key.len()), u32::try_from(value.len()))
+        else {
+            return errdata!("key/value pair too large for backup");
+        };
+        if key_len == END_MARKER {
+            return errdata!("key too large for backup");
+        }
+        writer.write_all(&key_len.to_be_bytes())?;
+        writer.write_all(&value_len.to_be_bytes())?;
+        writer.write_all(&key)?;
+        writer.write_all(&value)?;
+        count += 1;
+    }
+    writer.write_all(&END_MARKER.to_be_bytes())?;
+    writer.write_all(&count.to_be_bytes())?;
+    let (mut writer, checksum) = writer.finish();
+    writer.write_all(&checksum.to_be_bytes())?;
+    let file = writer.into_inner().map_err(|err| err.into_error())?;
+    file.sync_all()?;
+    std::fs::rename(tmp_path, dest)?;
+    Ok(count)
+}
+
+/// Validates a backup file, returning the number of key/value pairs in it.
+pub fn validate(src: &Path) -> Result<u64> {
+    read_pairs(src, |_, _| Ok(()))
+}
+
+/// Reads a backup file, calling the given closure with write batches of its
+/// key/value pairs in key order. The file is validated as it's read, so a
+/// corrupt file errors after some batches may have been applied; use
+/// validate() first to avoid this. Returns the number of key/value pairs read.
+pub fn read(src: &Path, mut apply: impl FnMut(WriteBatch) -> Result<()>) -> Result<u64> {
+    let mut batch = WriteBatch::new();
+    let count = read_pairs(src, |key, value| {
+        batch.set(&key, value);
+        if batch.len() >= BATCH_SIZE {
+            apply(std::mem::take(&mut batch))?;
+        }
+        Ok(())
+    })?;
+    if !batch.is_empty() {
+        apply(batch)?;
+    }
+    Ok(count)
+}
+
+/// Reads and validates a backup file, calling the given closure with each
+/// key/value pair. Returns the number of key/value pairs.
+fn read_pairs(src: &Path, mut f: impl FnMut(Vec<u8>, Vec<u8>) -> Result<()>) -> Result<u64> {
+    let mut reader = ChecksumReader::new(BufReader::new(std::fs::File::open(src)?));
+    let mut magic = [0; MAGIC.len()];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return errdata!("not a backup file: {}", src.display());
+    }
+    let mut count = 0u64;
+    loop {
+        let key_len = reader.read_u32()?;
+        if key_len == END_MARKER {
+            break;
+        }
+        let value_len = reader.read_u32()?;
+        let key = reader.read_bytes(key_len)?;
+        let value = reader.read_bytes(value_len)?;
+        f(key, value)?;
+        count += 1;
+    }
+    let mut buf = [0; 8];
+    reader.read_exact(&mut buf)?;
+    if u64::from_be_bytes(buf) != count {
+        return errdata!(
+            "backup file has {count} key/value pairs, expected {}",
+            u64::from_be_bytes(buf)
+        );
+    }
+    let (mut reader, checksum) = reader.finish();
+    let mut buf = [0; 4];
+    reader.read_exact(&mut buf)?;
+    if u32::from_be_bytes(buf) != checksum {
+        return errdata!("backup file checksum mismatch");
+    }
+    if reader.read(&mut buf)? != 0 {
+        return errdata!("unexpected data after end of backup file");
+    }
+    Ok(count)
+}
+
+/// A writer which computes a CRC32 checksum of the written bytes.
+struct ChecksumWriter<W: Write> {
+    inner: W,
+    hasher: crc32fast::Hasher,
+}
+
+impl<W: Write> ChecksumWriter<W> {
+    fn new(inner: W) -> Self {
+        Self { inner, hasher: crc32fast::Hasher::new() }
+    }
+
+    /// Returns the inner writer and the checksum of the bytes written so far.
+    fn finish(self) -> (W, u32) {
+        (self.inner, self.hasher.finalize())
+    }
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// A reader which computes a CRC32 checksum of the read bytes.
+struct ChecksumReader<R: Read> {
+    inner: R,
+    hasher: crc32fast::Hasher,
+}
+
+impl<R: Read> ChecksumReader<R> {
+    fn new(inner: R) -> Self {
+        Self { inner, hasher: crc32fast::Hasher::new() }
+    }
+
+    /// Reads a big-endian u32.
+    fn read_u32(&mut self) -> Result<u32> {
+        let mut buf = [0; 4];
+        self.read_exact(&mut buf)?;
+        Ok(u32::from_be_bytes(buf))
+    }
+
+    /// Reads the given number of bytes. Doesn't allocate them up front, in
+    /// case a corrupt length is very large.
+    fn read_bytes(&mut self, len: u32) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        self.by_ref().take(len as u64).read_to_end(&mut buf)?;
+        if buf.len() != len as usize {
+            return errdata!("unexpected end of backup file");
+        }
+        Ok(buf)
+    }
+
+    /// Returns the inner reader and the checksum of the bytes read so far.
+    fn finish(self) -> (R, u32) {
+        (self.inner, self.hasher.finalize())
+    }
+}
+
+impl<R: Read> Read for ChecksumReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Engine as _, Memory};
+    use super::*;
+
+    /// Tests that corrupt backup files are rejected by restore() without
+    /// modifying the engine.
+    #[test]
+    fn corrupt() -> Result<()> {
+        let dir = tempfile::TempDir::with_prefix("toydb")?;
+        let path = dir.path().join("backup");
+        let mut engine = Memory::new();
+        engine.set(b"a", vec![1])?;
+        engine.set(b"b", vec![2, 3])?;
+        engine.backup(&path)?;
+        let data = std::fs::read(&path)?;
+        assert_eq!(validate(&path)?, 2);
+
+        let mut corruptions: Vec<(&str, Vec<u8>)> = vec![
+            ("empty", Vec::new()),
+            ("magic", [b"toydbbk2", &data[8..]].concat()),
+            ("truncated", data[..data.len() - 1].to_vec()),
+            ("trailing", [&data[..], &[0]].concat()),
+        ];
+        let mut flipped = data.clone();
+        flipped[18] ^= 0xff; // second key length
+        corruptions.push(("length", flipped.clone()));
+        flipped[18] ^= 0xff;
+        flipped[26] ^= 0xff; // second key
+        corruptions.push(("flipped", flipped));
+        let mut count = data.clone();
+        let count_pos = data.len() - 12;
+        count[count_pos + 7] += 1;
+        corruptions.push(("count", count));
+
+        let mut target = Memory::new();
+        target.set(b"x", vec![9])?;
+        for (name, corrupt) in corruptions {
+            std::fs::write(&path, corrupt)?;
+            assert!(target.restore(&path).is_err(), "{name} backup restored");
+            assert_eq!(target.get(b"x")?, Some(vec![9]), "{name} backup modified engine");
+        }
+
+        std::fs::write(&path, data)?;
+        target.restore(&path)?;
+        assert_eq!(target.get(b"x")?, None);
+        assert_eq!(target.get(b"b")?, Some(vec![2, 3]));
+        Ok(())
+    }
+}
@@ -1,32 +1,51 @@
-use super::{Engine, Status};
+use super::{Engine, ReadEngine, Status, WriteBatch};
 use crate::error::Result;
+use crate::{errdata, errinput};
 
 use fs4::fs_std::FileExt;
-use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
-use std::path::PathBuf;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+#[cfg(not(feature = "mmap"))]
+use std::os::unix::fs::FileExt as _;
+use std::path::{Path, PathBuf};
 
 /// A very simple variant of BitCask, itself a very simple log-structured
 /// key-value engine used e.g. by the Riak database. It is not compatible with
 /// BitCask databases generated by other implementations. See:
 /// https://riak.com/assets/bitcask-intro.pdf
 ///
-/// BitCask writes key-value pairs to an append-only log file, and keeps a
+/// BitCask writes key-value pairs to append-only log files, and keeps a
 /// mapping of keys to file positions in memory. All live keys must fit in
-/// memory. Deletes write a tombstone value to the log file. To remove old
-/// garbage, logs can be compacted by writing new logs containing only live
-/// data, skipping replaced values and tombstones.
+/// memory, so the keydir's estimated memory use is tracked and can be capped,
+/// see set_max_keydir_size(). Deletes write a tombstone value to the log file.
+/// To remove old garbage, logs can be compacted by writing new logs containing
+/// only live data, skipping replaced values and tombstones.
+///
+/// The log is split into segments. Writes are appended to the last (active)
+/// segment, and once it would exceed the maximum segment size a new active
+/// segment is started. Older segments are immutable, except for compaction.
+/// Compaction can either rewrite all segments, or only rewrite the segments
+/// that have accumulated enough garbage, leaving cold segments with mostly
+/// live data alone. A rewritten segment keeps its position in the segment
+/// order, so it must retain tombstones that may shadow values in older
+/// segments, unless it's the oldest segment.
+///
+/// Given a database path such as data/sql, the following files are used:
+///
+/// - data/sql: an empty lock file, exclusively locked while the database is
+///   open. Databases written before log segments were added store a single
+///   log here, which is migrated to a segment when opened.
+/// - data/sql.manifest: the segment IDs in order, one per line. It is
+///   atomically replaced when segments are added or removed.
+/// - data/sql.000001 etc: the log segments, named by segment ID.
 ///
 /// This implementation makes several significant simplifications over
 /// standard BitCask:
 ///
-/// - Instead of writing multiple fixed-size log files, it uses a single
-///   append-only log file of arbitrary size. This increases the compaction
-///   volume, since the entire log file must be rewritten on every compaction,
-///   and can exceed the filesystem's file size limit, but ToyDB databases are
-///   expected to be small.
-///
-/// - Compactions lock the database for reads and writes. This is ok since ToyDB
-///   only compacts during node startup and files are expected to be small.
+/// - Compactions run in the caller's thread rather than a background thread.
+///   Full compactions lock the database for reads and writes, but segment
+///   compactions can be run incrementally in bounded steps interleaved with
+///   reads and writes, see set_compact_step_size().
 ///
 /// - Hint files are not used, the log itself is scanned when opened to
 ///   build the keydir. Hint files only omit values, and ToyDB values are
@@ -41,24 +60,144 @@ use std::path::PathBuf;
 /// - Value length as big-endian i32, or -1 for tombstones.
 /// - Key as raw bytes (max 2 GB).
 /// - Value as raw bytes (max 2 GB).
+///
+/// Write batches with multiple entries are prefixed by a header entry, with the
+/// length of the batch entries in place of the key length and -2 in place of
+/// the value length. If the batch is incomplete when the log is opened, it's
+/// truncated along with its header, so batches are applied atomically.
 pub struct BitCask {
-    /// The active append-only log file.
-    log: Log,
-    /// Maps keys to a value position and length in the log file.
+    /// The database path, see above.
+    path: PathBuf,
+    /// The lock file at the database path, held for its exclusive lock.
+    _lock: std::fs::File,
+    /// The log segments by ID, in write order. The last is the active segment.
+    segments: BTreeMap<SegmentID, Log>,
+    /// Maps keys to a segment, value position, and value length.
     keydir: KeyDir,
+    /// The estimated memory size of the keydir, see keydir_entry_size().
+    keydir_size: u64,
+    /// The maximum estimated memory size of the keydir, or 0 for no limit.
+    /// See set_max_keydir_size().
+    max_keydir_size: u64,
+    /// The size in bytes beyond which a new active segment is started.
+    max_segment_size: u64,
+    /// The number of bytes to compact per step, or 0 to compact segments in
+    /// one go. See set_compact_step_size().
+    compact_step_size: u64,
+    /// Segments queued for compaction, after the in-progress one.
+    compact_queue: VecDeque<SegmentID>,
+    /// The in-progress segment compaction, if any.
+    compaction: Option<Compaction>,
+    /// Whether the active segment has writes that haven't been fsynced yet.
+    /// flush() is a noop otherwise, so redundant flushes are cheap.
+    unsynced: bool,
+}
+
+/// An in-progress compaction of a single segment, see compact_step().
+struct Compaction {
+    /// The segment being compacted.
+    id: SegmentID,
+    /// Whether this is the oldest segment, in which case tombstones are dropped.
+    is_oldest: bool,
+    /// The position of the next entry to copy from the segment.
+    pos: u64,
+    /// The new segment file, which replaces the segment once complete.
+    new_log: Log,
+    /// Copied values, as keys with their old and new value positions and value
+    /// lengths. The keydir is updated once the new segment replaces the old.
+    moved: Vec<(Vec<u8>, u64, u64, u32)>,
+    /// Retained tombstones, to avoid writing duplicates.
+    tombstones: BTreeSet<Vec<u8>>,
 }
 
-/// Maps keys to a value position and length in the log file.
-type KeyDir = std::collections::BTreeMap<Vec<u8>, (u64, u32)>;
+/// A log segment ID. IDs increase as segments are added.
+type SegmentID = u64;
+
+/// Maps keys to a segment, value position, and value length.
+type KeyDir = BTreeMap<Vec<u8>, (SegmentID, u64, u32)>;
 
 impl BitCask {
-    /// Opens or creates a BitCask database in the given file.
+    /// The default maximum size of a log segment, in bytes.
+    pub const DEFAULT_MAX_SEGMENT_SIZE: u64 = 64 * 1024 * 1024;
+
+    /// The estimated memory overhead of a keydir entry, in addition to the key
+    /// bytes: the key vector, the value position, and B-tree node overhead.
+    const KEYDIR_ENTRY_OVERHEAD: u64 = 64;
+
+    /// Opens or creates a BitCask database at the given path.
     pub fn new(path: PathBuf) -> Result<Self> {
         log::info!("Opening database {}", path.display());
-        let mut log = Log::new(path.clone())?;
-        let keydir = log.build_keydir()?;
-        log::info!("Indexed {} live keys in {}", keydir.len(), path.display());
-        Ok(Self { log, keydir })
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?
+        }
+        let lock = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        lock.try_lock_exclusive()?;
+
+        let mut ids = match Self::read_manifest(&path)? {
+            Some(ids) => ids,
+            // A legacy log in the lock file is copied to the first segment. The
+            // lock file is truncated below, once the manifest is written.
+            None if lock.metadata()?.len() > 0 => {
+                log::info!("Migrating {} to log segments", path.display());
+                let segment = Self::segment_path(&path, 1);
+                std::fs::copy(&path, &segment)?;
+                #[cfg(not(test))]
+                std::fs::File::open(&segment)?.sync_all()?;
+                Self::write_manifest(&path, [1])?;
+                vec![1]
+            }
+            None => Vec::new(),
+        };
+        if lock.metadata()?.len() > 0 {
+            lock.set_len(0)?;
+        }
+        Self::remove_orphans(&path, &ids)?;
+
+        // Replay the segments in order to build the keydir.
+        let mut segments = BTreeMap::new();
+        let mut keydir = KeyDir::new();
+        for id in ids.iter().copied() {
+            let segment = Self::segment_path(&path, id);
+            if !segment.exists() {
+                return errdata!("log segment {} not found", segment.display());
+            }
+            let mut log = Log::new(segment)?;
+            log.build_keydir(id, &mut keydir)?;
+            segments.insert(id, log);
+        }
+        let keydir_size = keydir.keys().map(|key| Self::keydir_entry_size(key)).sum();
+        log::info!(
+            "Indexed {} live keys in {} using {} MB of memory",
+            keydir.len(),
+            path.display(),
+            keydir_size / 1024 / 1024
+        );
+
+        // Create an initial segment for new databases.
+        if ids.is_empty() {
+            ids.push(1);
+            segments.insert(1, Log::create(Self::segment_path(&path, 1))?);
+            Self::write_manifest(&path, ids)?;
+        }
+
+        Ok(Self {
+            path,
+            _lock: lock,
+            segments,
+            keydir,
+            keydir_size,
+            max_keydir_size: 0,
+            max_segment_size: Self::DEFAULT_MAX_SEGMENT_SIZE,
+            compact_step_size: 0,
+            compact_queue: VecDeque::new(),
+            compaction: None,
+            unsynced: false,
+        })
     }
 
     /// Opens a BitCask database, and automatically compacts it if the amount
@@ -69,30 +208,75 @@ impl BitCask {
         garbage_min_bytes: u64,
     ) -> Result<Self> {
         let mut s = Self::new(path)?;
+        s.maybe_compact(garbage_min_fraction, garbage_min_bytes)?;
+        Ok(s)
+    }
 
-        let status = s.status()?;
-        if Self::should_compact(
-            status.garbage_disk_size,
-            status.total_disk_size,
-            garbage_min_fraction,
-            garbage_min_bytes,
-        ) {
-            log::info!(
-                "Compacting {} to remove {:.0}% garbage ({} MB out of {} MB)",
-                s.log.path.display(),
-                status.garbage_percent(),
-                status.garbage_disk_size / 1024 / 1024,
-                status.total_disk_size / 1024 / 1024
-            );
-            s.compact()?;
-            log::info!(
-                "Compacted {} to size {} MB",
-                s.log.path.display(),
-                (status.total_disk_size - status.garbage_disk_size) / 1024 / 1024
+    /// Sets the size in bytes beyond which a new active segment is started.
+    /// Entries larger than this get a segment of their own.
+    pub fn set_max_segment_size(&mut self, size: u64) {
+        self.max_segment_size = size;
+    }
+
+    /// Sets the number of bytes to compact per step. If 0 (the default),
+    /// maybe_compact() compacts all segments with enough garbage before
+    /// returning. Otherwise, segment compaction is incremental: each
+    /// maybe_compact() call and each write copies roughly this many bytes of
+    /// segment entries, until the compaction is complete. This bounds the time
+    /// the database is locked for, but leaves compaction garbage around for
+    /// longer. The active segment is rotated out before it's compacted, so
+    /// writes never go to the segment being compacted.
+    pub fn set_compact_step_size(&mut self, size: u64) {
+        self.compact_step_size = size;
+    }
+
+    /// Sets the maximum estimated memory size of the keydir in bytes, or 0 for
+    /// no limit (the default). Once reached, writes that would add keys to the
+    /// keydir are rejected with an error, instead of exhausting memory. Deletes
+    /// and writes to existing keys are still allowed.
+    ///
+    /// When the limit is lowered below the current keydir size, e.g. on
+    /// restart, existing keys remain readable. Note that MVCC writes, including
+    /// SQL deletes, add new version keys, so the limit must be raised to make
+    /// further SQL writes once it's reached.
+    pub fn set_max_keydir_size(&mut self, size: u64) {
+        self.max_keydir_size = size;
+    }
+
+    /// Returns the estimated memory size of a keydir entry for the given key.
+    fn keydir_entry_size(key: &[u8]) -> u64 {
+        key.len() as u64 + Self::KEYDIR_ENTRY_OVERHEAD
+    }
+
+    /// Checks that writing the given keys (with None for deletes) won't make
+    /// the keydir exceed the maximum size. Returns the keydir size afterwards.
+    fn check_keydir_size<'a>(
+        &self,
+        writes: impl IntoIterator<Item = (&'a [u8], bool)>,
+    ) -> Result<u64> {
+        // Track the final presence of each written key, in case a batch writes
+        // the same key several times.
+        let mut present: HashMap<&[u8], bool> = HashMap::new();
+        for (key, is_set) in writes {
+            present.insert(key, is_set);
+        }
+        let (mut added, mut removed) = (0, 0);
+        for (key, is_set) in present {
+            match (is_set, self.keydir.contains_key(key)) {
+                (true, false) => added += Self::keydir_entry_size(key),
+                (false, true) => removed += Self::keydir_entry_size(key),
+                _ => {}
+            }
+        }
+        let size = self.keydir_size + added - removed;
+        if self.max_keydir_size > 0 && added > removed && size > self.max_keydir_size {
+            return errinput!(
+                "keydir memory limit exceeded: {} of {} bytes used",
+                self.keydir_size,
+                self.max_keydir_size
             );
         }
-
-        Ok(s)
+        Ok(size)
     }
 
     /// Returns true if the log file should be compacted.
@@ -105,36 +289,233 @@ impl BitCask {
         let garbage_fraction = garbage_size as f64 / total_size as f64;
         garbage_size > 0 && garbage_size >= min_bytes && garbage_fraction >= min_fraction
     }
+
+    /// Returns the path of a file next to the database path, with the given
+    /// suffix appended to the file name.
+    fn path_with_suffix(path: &Path, suffix: &str) -> PathBuf {
+        let mut path = path.as_os_str().to_owned();
+        path.push(".");
+        path.push(suffix);
+        path.into()
+    }
+
+    /// Returns the path of the given segment.
+    fn segment_path(path: &Path, id: SegmentID) -> PathBuf {
+        Self::path_with_suffix(path, &format!("{id:06}"))
+    }
+
+    /// Reads the segment IDs from the manifest, or None if there isn't one.
+    fn read_manifest(path: &Path) -> Result<Option<Vec<SegmentID>>> {
+        let manifest = match std::fs::read_to_string(Self::path_with_suffix(path, "manifest")) {
+            Ok(manifest) => manifest,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        let ids = manifest
+            .lines()
+            .map(|line| line.parse())
+            .collect::<std::result::Result<Vec<SegmentID>, _>>()?;
+        if !ids.is_sorted_by(|a, b| a < b) {
+            return errdata!("segment IDs out of order in manifest: {ids:?}");
+        }
+        Ok(Some(ids))
+    }
+
+    /// Writes the segment IDs to the manifest, atomically replacing it.
+    fn write_manifest(path: &Path, ids: impl IntoIterator<Item = SegmentID>) -> Result<()> {
+        let tmp_path = Self::path_with_suffix(path, "manifest.new");
+        let mut file = std::fs::File::create(&tmp_path)?;
+        for id in ids {
+            writeln!(file, "{id}")?;
+        }
+        #[cfg(not(test))]
+        file.sync_all()?;
+        std::fs::rename(tmp_path, Self::path_with_suffix(path, "manifest"))?;
+        Ok(())
+    }
+
+    /// Removes segment files that aren't in the manifest, e.g. ones left
+    /// behind by an interrupted compaction.
+    fn remove_orphans(path: &Path, ids: &[SegmentID]) -> Result<()> {
+        let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+            return Ok(());
+        };
+        let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+        let prefix = format!("{}.", name.to_string_lossy());
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let file_name = entry.file_name();
+            let Some(suffix) = file_name.to_str().and_then(|n| n.strip_prefix(&prefix)) else {
+                continue;
+            };
+            let id = suffix.strip_suffix(".new").unwrap_or(suffix);
+            if id.is_empty() || !id.bytes().all(|b| b.is_ascii_digit()) {
+                continue;
+            }
+            if id == suffix && ids.contains(&id.parse()?) {
+                continue;
+            }
+            log::info!("Removing orphaned log segment {}", entry.path().display());
+            std::fs::remove_file(entry.path())?;
+        }
+        Ok(())
+    }
+
+    /// Returns the active segment.
+    fn active_segment(&mut self) -> (SegmentID, &mut Log) {
+        let (id, log) = self.segments.iter_mut().next_back().expect("no active segment");
+        (*id, log)
+    }
+
+    /// Writes an entry to the active segment, see append(). Returns the
+    /// segment ID, position, and length of the entry.
+    fn write_entry(&mut self, key: &[u8], value: Option<&[u8]>) -> Result<(SegmentID, u64, u32)> {
+        let mut buf = Vec::new();
+        Log::encode_entry(&mut buf, key, value);
+        let (id, pos) = self.append(&buf)?;
+        Ok((id, pos, buf.len() as u32))
+    }
+
+    /// Appends encoded entries to the active segment, first starting a new
+    /// active segment if they would make it exceed the maximum segment size.
+    /// Returns the segment ID and position of the entries.
+    fn append(&mut self, buf: &[u8]) -> Result<(SegmentID, u64)> {
+        let size = self.active_segment().1.len()?;
+        if size > 0 && size + buf.len() as u64 > self.max_segment_size {
+            self.rotate()?;
+        }
+        self.unsynced = true;
+        let (id, active) = self.active_segment();
+        Ok((id, active.append(buf)?))
+    }
+
+    /// Starts a new active segment.
+    fn rotate(&mut self) -> Result<()> {
+        // Sync the full segment, since flush() only syncs the active one.
+        #[cfg(not(test))]
+        self.active_segment().1.file.sync_all()?;
+        let id = self.active_segment().0;
+        self.segments.insert(id + 1, Log::create(Self::segment_path(&self.path, id + 1))?);
+        Self::write_manifest(&self.path, self.segments.keys().copied())
+    }
+
+    /// Returns the live and total disk size of each segment.
+    fn segment_sizes(&self) -> Result<BTreeMap<SegmentID, (u64, u64)>> {
+        let mut live: HashMap<SegmentID, u64> = HashMap::new();
+        for (key, (id, _, value_len)) in &self.keydir {
+            *live.entry(*id).or_default() += 8 + key.len() as u64 + *value_len as u64;
+        }
+        self.segments
+            .iter()
+            .map(|(id, log)| Ok((*id, (live.get(id).copied().unwrap_or(0), log.len()?))))
+            .collect()
+    }
+}
+
+impl ReadEngine for BitCask {
+    fn get_shared(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        if let Some((id, value_pos, value_len)) = self.keydir.get(key) {
+            let log = self.segments.get(id).expect("unknown segment");
+            Ok(Some(log.read_value(*value_pos, *value_len)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn scan_shared(
+        &self,
+        range: (std::ops::Bound<Vec<u8>>, std::ops::Bound<Vec<u8>>),
+    ) -> Box<dyn super::ScanIterator + '_> {
+        Box::new(ScanIterator { inner: self.keydir.range(range), segments: &self.segments })
+    }
+
+    /// Scans the keydir, without reading values from the log files.
+    fn scan_keys_shared(
+        &self,
+        range: (std::ops::Bound<Vec<u8>>, std::ops::Bound<Vec<u8>>),
+    ) -> Box<dyn super::KeyScanIterator + '_> {
+        Box::new(
+            self.keydir
+                .range(range)
+                .map(|(key, (_, _, value_len))| Ok((key.clone(), *value_len as usize))),
+        )
+    }
 }
 
 impl Engine for BitCask {
     type ScanIterator<'a> = ScanIterator<'a>;
 
     fn delete(&mut self, key: &[u8]) -> Result<()> {
-        self.log.write_entry(key, None)?;
-        self.keydir.remove(key);
-        Ok(())
+        self.write_entry(key, None)?;
+        if self.keydir.remove(key).is_some() {
+            self.keydir_size -= Self::keydir_entry_size(key);
+        }
+        self.compact_step(self.compact_step_size)
     }
 
     fn flush(&mut self) -> Result<()> {
         // Don't fsync in tests, to speed them up. We disable this here, instead
         // of setting raft::Log::fsync = false in tests, because we want to
         // assert that the Raft log flushes to disk even if the flush is a noop.
+        // Only the active segment has unsynced writes, see write_entry().
+        if !self.unsynced {
+            return Ok(());
+        }
         #[cfg(not(test))]
-        self.log.file.sync_all()?;
+        self.active_segment().1.file.sync_all()?;
+        self.unsynced = false;
         Ok(())
     }
 
     fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
-        if let Some((value_pos, value_len)) = self.keydir.get(key) {
-            Ok(Some(self.log.read_value(*value_pos, *value_len)?))
-        } else {
-            Ok(None)
+        self.get_shared(key)
+    }
+
+    fn maybe_compact(&mut self, garbage_min_fraction: f64, garbage_min_bytes: u64) -> Result<bool> {
+        // Continue an in-progress incremental compaction, if any. Otherwise,
+        // check the thresholds and queue the segments to compact.
+        if self.compaction.is_none() && self.compact_queue.is_empty() {
+            let status = self.status()?;
+            if !Self::should_compact(
+                status.garbage_disk_size,
+                status.total_disk_size,
+                garbage_min_fraction,
+                garbage_min_bytes,
+            ) {
+                return Ok(false);
+            }
+            log::info!(
+                "Compacting {} to remove {:.0}% garbage ({} MB out of {} MB)",
+                self.path.display(),
+                status.garbage_percent(),
+                status.garbage_disk_size / 1024 / 1024,
+                status.total_disk_size / 1024 / 1024
+            );
+            // Only rewrite segments whose own garbage exceeds the fraction. At
+            // least one does, since the total garbage fraction exceeds it.
+            for (id, (live_size, total_size)) in self.segment_sizes()? {
+                if Self::should_compact(total_size - live_size, total_size, garbage_min_fraction, 0)
+                {
+                    self.compact_queue.push_back(id);
+                }
+            }
+        }
+        let step_size = if self.compact_step_size == 0 { u64::MAX } else { self.compact_step_size };
+        self.compact_step(step_size)?;
+        if self.compaction.is_some() || !self.compact_queue.is_empty() {
+            return Ok(true);
         }
+        let total_disk_size = self.status()?.total_disk_size;
+        log::info!(
+            "Compacted {} to size {} MB",
+            self.path.display(),
+            total_disk_size / 1024 / 1024
+        );
+        Ok(true)
     }
 
     fn scan(&mut self, range: impl std::ops::RangeBounds<Vec<u8>>) -> Self::ScanIterator<'_> {
-        ScanIterator { inner: self.keydir.range(range), log: &mut self.log }
+        ScanIterator { inner: self.keydir.range(range), segments: &self.segments }
     }
 
     // noinspection DuplicatedCode
@@ -145,11 +526,25 @@ impl Engine for BitCask {
         Box::new(self.scan(range))
     }
 
+    /// Serves keys and value lengths from the keydir, without reading the log.
+    fn scan_keys(
+        &mut self,
+        range: (std::ops::Bound<Vec<u8>>, std::ops::Bound<Vec<u8>>),
+    ) -> Box<dyn super::KeyScanIterator + '_> {
+        Box::new(
+            self.keydir
+                .range(range)
+                .map(|(key, (_, _, value_len))| Ok((key.clone(), *value_len as usize))),
+        )
+    }
+
     fn set(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
-        let (pos, len) = self.log.write_entry(key, Some(&*value))?;
+        let keydir_size = self.check_keydir_size([(key, true)])?;
+        let (id, pos, len) = self.write_entry(key, Some(&*value))?;
         let value_len = value.len() as u32;
-        self.keydir.insert(key.to_vec(), (pos + len as u64 - value_len as u64, value_len));
-        Ok(())
+        self.keydir.insert(key.to_vec(), (id, pos + len as u64 - value_len as u64, value_len));
+        self.keydir_size = keydir_size;
+        self.compact_step(self.compact_step_size)
     }
 
     fn status(&mut self) -> Result<Status> {
@@ -157,8 +552,8 @@ impl Engine for BitCask {
         let size = self
             .keydir
             .iter()
-            .fold(0, |size, (key, (_, value_len))| size + key.len() as u64 + *value_len as u64);
-        let total_disk_size = self.log.file.metadata()?.len();
+            .fold(0, |size, (key, (_, _, value_len))| size + key.len() as u64 + *value_len as u64);
+        let total_disk_size = self.segments.values().map(|log| log.len()).sum::<Result<u64>>()?;
         let live_disk_size = size + 8 * keys; // account for length prefixes
         let garbage_disk_size = total_disk_size - live_disk_size;
         Ok(Status {
@@ -168,19 +563,62 @@ impl Engine for BitCask {
             total_disk_size,
             live_disk_size,
             garbage_disk_size,
+            index_memory_size: self.keydir_size,
         })
     }
+
+    fn write_batch(&mut self, batch: WriteBatch) -> Result<()> {
+        if batch.len() <= 1 {
+            for (key, value) in batch {
+                match value {
+                    Some(value) => self.set(&key, value)?,
+                    None => self.delete(&key)?,
+                }
+            }
+            return Ok(());
+        }
+
+        // Encode the batch entries after a batch header, and append them.
+        let keydir_size = self.check_keydir_size(
+            (&batch).into_iter().map(|(key, value)| (&key[..], value.is_some())),
+        )?;
+        let mut buf = vec![0; 8];
+        for (key, value) in &batch {
+            Log::encode_entry(&mut buf, key, value.as_deref());
+        }
+        let Ok(batch_len) = u32::try_from(buf.len() - 8) else {
+            return errinput!("write batch too large");
+        };
+        buf[0..4].copy_from_slice(&batch_len.to_be_bytes());
+        buf[4..8].copy_from_slice(&Log::BATCH_HEADER.to_be_bytes());
+        let (id, mut pos) = self.append(&buf)?;
+
+        // Update the keydir with the entry positions.
+        pos += 8;
+        for (key, value) in batch {
+            let value_len = value.as_ref().map_or(0, |v| v.len() as u32);
+            let value_pos = pos + 8 + key.len() as u64;
+            pos = value_pos + value_len as u64;
+            match value {
+                Some(_) => self.keydir.insert(key, (id, value_pos, value_len)),
+                None => self.keydir.remove(&key),
+            };
+        }
+        self.keydir_size = keydir_size;
+        self.compact_step(self.compact_step_size)
+    }
 }
 
 pub struct ScanIterator<'a> {
-    inner: std::collections::btree_map::Range<'a, Vec<u8>, (u64, u32)>,
-    log: &'a mut Log,
+    inner: std::collections::btree_map::Range<'a, Vec<u8>, (SegmentID, u64, u32)>,
+    segments: &'a BTreeMap<SegmentID, Log>,
 }
 
 impl ScanIterator<'_> {
-    fn map(&mut self, item: (&Vec<u8>, &(u64, u32))) -> <Self as Iterator>::Item {
-        let (key, (value_pos, value_len)) = item;
-        Ok((key.clone(), self.log.read_value(*value_pos, *value_len)?))
+    fn map(&mut self, item: (&Vec<u8>, &(SegmentID, u64, u32))) -> <Self as Iterator>::Item {
+        let (key, (id, value_pos, value_len)) = item;
+        let log = self.segments.get(id).expect("unknown segment");
+        Ok((key.clone(), log.read_value(*value_pos, *value_len)?))
     }
 }
 
@@ -199,33 +637,175 @@ impl DoubleEndedIterator for ScanIterator<'_> {
 }
 
 impl BitCask {
-    /// Compacts the current log file by writing out a new log file containing
-    /// only live keys and replacing the current file with it.
+    /// Compacts the entire log by writing out new segments containing only
+    /// live keys in key order, and replacing all current segments with them.
     pub fn compact(&mut self) -> Result<()> {
-        let mut tmp_path = self.log.path.clone();
-        tmp_path.set_extension("new");
-        let (mut new_log, new_keydir) = self.write_log(tmp_path)?;
+        // Abort any in-progress segment compaction, since it's superseded.
+        self.compact_queue.clear();
+        if let Some(compaction) = self.compaction.take() {
+            std::fs::remove_file(&compaction.new_log.path)?;
+        }
 
-        std::fs::rename(&new_log.path, &self.log.path)?;
-        new_log.path = self.log.path.clone();
+        let mut id = *self.segments.keys().next_back().expect("no active segment");
+        let mut new_segments = BTreeMap::new();
+        let mut new_keydir = KeyDir::new();
+        let mut new_log: Option<Log> = None;
+
+        for (key, (old_id, value_pos, value_len)) in &self.keydir {
+            let old_log = self.segments.get_mut(old_id).expect("unknown segment");
+            let value = old_log.read_value(*value_pos, *value_len)?;
+            let entry_len = 8 + key.len() as u64 + *value_len as u64;
+            let size = new_log.as_ref().map(|log| log.len()).transpose()?;
+            if size.is_none_or(|size| size > 0 && size + entry_len > self.max_segment_size) {
+                if let Some(log) = new_log.take() {
+                    new_segments.insert(id, log);
+                }
+                id += 1;
+                new_log = Some(Log::create(Self::segment_path(&self.path, id))?);
+            }
+            let log = new_log.as_mut().expect("no segment");
+            let (pos, len) = log.write_entry(key, Some(&value))?;
+            new_keydir.insert(key.clone(), (id, pos + len as u64 - *value_len as u64, *value_len));
+        }
+        let new_log = match new_log {
+            Some(log) => log,
+            None => {
+                id += 1;
+                Log::create(Self::segment_path(&self.path, id))?
+            }
+        };
+        new_segments.insert(id, new_log);
 
-        self.log = new_log;
+        #[cfg(not(test))]
+        for log in new_segments.values() {
+            log.file.sync_all()?;
+        }
+        Self::write_manifest(&self.path, new_segments.keys().copied())?;
+        let old_segments = std::mem::replace(&mut self.segments, new_segments);
+        for (_, log) in old_segments {
+            std::fs::remove_file(&log.path)?;
+        }
         self.keydir = new_keydir;
         Ok(())
     }
 
-    /// Writes out a new log file with the live entries of the current log file
-    /// and returns it along with its keydir. Entries are written in key order.
-    fn write_log(&mut self, path: PathBuf) -> Result<(Log, KeyDir)> {
-        let mut new_keydir = KeyDir::new();
-        let mut new_log = Log::new(path)?;
-        new_log.file.set_len(0)?; // truncate file if it exists
-        for (key, (value_pos, value_len)) in self.keydir.iter() {
-            let value = self.log.read_value(*value_pos, *value_len)?;
-            let (pos, len) = new_log.write_entry(key, Some(&value))?;
-            new_keydir.insert(key.clone(), (pos + len as u64 - *value_len as u64, *value_len));
+    /// Runs queued segment compactions, copying roughly the given number of
+    /// bytes of segment entries before returning. A segment is compacted in
+    /// place, by writing out a new segment file containing only its live
+    /// entries and replacing the segment file with it once complete.
+    /// Tombstones are retained if the key doesn't exist, since they may shadow
+    /// values in older segments, unless this is the oldest segment. Empty
+    /// segments are removed, except for the active segment.
+    fn compact_step(&mut self, max_bytes: u64) -> Result<()> {
+        let mut budget = max_bytes;
+        while budget > 0 {
+            let Some(compaction) = self.compaction.as_mut() else {
+                let Some(id) = self.compact_queue.pop_front() else {
+                    return Ok(());
+                };
+                // The segment may have been removed since it was queued.
+                if self.segments.contains_key(&id) {
+                    self.start_compaction(id)?;
+                }
+                continue;
+            };
+            let id = compaction.id;
+            let log = self.segments.get_mut(&id).expect("unknown segment");
+
+            // Find the live entries in the next chunk, as keys and value
+            // positions and lengths, or None for retained tombstones.
+            let mut entries = Vec::new();
+            let pos =
+                log.scan_entries(
+                    compaction.pos,
+                    budget,
+                    |key, value_pos, value_len| match value_len {
+                        Some(value_len)
+                            if self.keydir.get(&key) == Some(&(id, value_pos, value_len)) =>
+                        {
+                            entries.push((key, Some((value_pos, value_len))))
+                        }
+                        None if !compaction.is_oldest && !self.keydir.contains_key(&key) => {
+                            if compaction.tombstones.insert(key.clone()) {
+                                entries.push((key, None))
+                            }
+                        }
+                        Some(_) | None => {}
+                    },
+                )?;
+            budget = budget.saturating_sub(pos - compaction.pos);
+            compaction.pos = pos;
+
+            for (key, value_pos) in entries {
+                let Some((value_pos, value_len)) = value_pos else {
+                    compaction.new_log.write_entry(&key, None)?;
+                    continue;
+                };
+                let value = log.read_value(value_pos, value_len)?;
+                let (pos, len) = compaction.new_log.write_entry(&key, Some(&value))?;
+                let new_value_pos = pos + len as u64 - value_len as u64;
+                compaction.moved.push((key, value_pos, new_value_pos, value_len));
+            }
+
+            if pos >= log.len()? {
+                self.finish_compaction()?;
+            }
         }
-        Ok((new_log, new_keydir))
+        Ok(())
+    }
+
+    /// Starts compacting the given segment. In incremental mode, the active
+    /// segment is rotated out first, so that writes go to a different segment.
+    fn start_compaction(&mut self, id: SegmentID) -> Result<()> {
+        if self.compact_step_size > 0 && self.active_segment().0 == id {
+            self.rotate()?;
+        }
+        let is_oldest = self.segments.keys().next() == Some(&id);
+        let log = self.segments.get(&id).expect("unknown segment");
+        let new_log = Log::create(Self::path_with_suffix(&log.path, "new"))?;
+        let (moved, tombstones) = (Vec::new(), BTreeSet::new());
+        self.compaction = Some(Compaction { id, is_oldest, pos: 0, new_log, moved, tombstones });
+        Ok(())
+    }
+
+    /// Completes the in-progress segment compaction, replacing the segment
+    /// with the new segment file and pointing the keydir at it.
+    fn finish_compaction(&mut self) -> Result<()> {
+        let Compaction { id, mut new_log, moved, .. } =
+            self.compaction.take().expect("no compaction in progress");
+        let is_active = self.active_segment().0 == id;
+        let log = self.segments.get_mut(&id).expect("unknown segment");
+
+        if new_log.len()? == 0 && !is_active {
+            let log = self.segments.remove(&id).expect("unknown segment");
+            Self::write_manifest(&self.path, self.segments.keys().copied())?;
+            std::fs::remove_file(log.path)?;
+            std::fs::remove_file(new_log.path)?;
+            return Ok(());
+        }
+
+        // If only retained tombstones counted as garbage, nothing changed.
+        if new_log.len()? == log.len()? {
+            std::fs::remove_file(new_log.path)?;
+            return Ok(());
+        }
+
+        #[cfg(not(test))]
+        new_log.file.sync_all()?;
+        std::fs::rename(&new_log.path, &log.path)?;
+        new_log.path = log.path.clone();
+        *log = new_log;
+
+        // Point the keydir at the copied values, unless they were replaced or
+        // deleted in the meanwhile.
+        for (key, value_pos, new_value_pos, value_len) in moved {
+            if let Some(entry) = self.keydir.get_mut(&key) {
+                if *entry == (id, value_pos, value_len) {
+                    *entry = (id, new_value_pos, value_len);
+                }
+            }
+        }
+        Ok(())
     }
 }
 
@@ -238,21 +818,31 @@ impl Drop for BitCask {
     }
 }
 
-/// A BitCask append-only log file, containing a sequence of key/value
+/// A BitCask append-only log segment file, containing a sequence of key/value
 /// entries encoded as follows;
 ///
 /// - Key length as big-endian u32.
 /// - Value length as big-endian i32, or -1 for tombstones.
 /// - Key as raw bytes (max 2 GB).
 /// - Value as raw bytes (max 2 GB).
+///
+/// Multi-entry write batches are prefixed by a header, see BitCask.
 struct Log {
     /// Path to the log file.
     path: PathBuf,
     /// The opened file containing the log.
     file: std::fs::File,
+    /// A read-only memory map of the file, used to read values without
+    /// syscalls. It's remapped when reading beyond its end, since the file may
+    /// have grown. The lock allows concurrent readers to remap it.
+    #[cfg(feature = "mmap")]
+    mmap: std::sync::RwLock<Option<memmap2::Mmap>>,
 }
 
 impl Log {
+    /// The value length of batch headers.
+    const BATCH_HEADER: i32 = -2;
+
     /// Opens a log file, or creates one if it does not exist. Takes out an
     /// exclusive lock on the file until it is closed, or errors if the lock is
     /// already held.
@@ -267,27 +857,80 @@ impl Log {
             .truncate(false)
             .open(&path)?;
         file.try_lock_exclusive()?;
-        Ok(Self { path, file })
+        Ok(Self {
+            path,
+            file,
+            #[cfg(feature = "mmap")]
+            mmap: std::sync::RwLock::new(None),
+        })
     }
 
-    /// Builds a keydir by scanning the log file. If an incomplete entry is
-    /// encountered, it is assumed to be caused by an incomplete write operation
-    /// and the remainder of the file is truncated.
-    fn build_keydir(&mut self) -> Result<KeyDir> {
+    /// Creates a new empty log file, truncating it if it exists.
+    fn create(path: PathBuf) -> Result<Self> {
+        let log = Self::new(path)?;
+        log.file.set_len(0)?;
+        Ok(log)
+    }
+
+    /// Returns the size of the log file.
+    fn len(&self) -> Result<u64> {
+        Ok(self.file.metadata()?.len())
+    }
+
+    /// Adds the log file's entries to a keydir, as the given segment. If an
+    /// incomplete entry is encountered, it is assumed to be caused by an
+    /// incomplete write operation and the remainder of the file is truncated.
+    fn build_keydir(&mut self, id: SegmentID, keydir: &mut KeyDir) -> Result<()> {
+        self.scan_entries(0, u64::MAX, |key, value_pos, value_len| match value_len {
+            // Populate the keydir with the entry, or remove it on tombstones.
+            Some(value_len) => {
+                keydir.insert(key, (id, value_pos, value_len));
+            }
+            None => {
+                keydir.remove(&key);
+            }
+        })?;
+        Ok(())
+    }
+
+    /// Scans the log file's entries in order from the given entry position,
+    /// calling the given closure with each key, value position, and value
+    /// length or None for tombstones. Stops once at least max_bytes have been
+    /// scanned, returning the position of the next entry. If an incomplete
+    /// entry is encountered, it is assumed to be caused by an incomplete write
+    /// operation and the remainder of the file is truncated.
+    fn scan_entries(
+        &mut self,
+        start: u64,
+        max_bytes: u64,
+        mut f: impl FnMut(Vec<u8>, u64, Option<u32>),
+    ) -> Result<u64> {
         let mut len_buf = [0u8; 4];
-        let mut keydir = KeyDir::new();
         let file_len = self.file.metadata()?.len();
         let mut r = BufReader::new(&mut self.file);
-        let mut pos = r.seek(SeekFrom::Start(0))?;
+        let mut pos = r.seek(SeekFrom::Start(start))?;
 
-        while pos < file_len {
+        while pos < file_len && pos - start < max_bytes {
             // Read the next entry from the file, returning the key, value
-            // position, and value length or None for tombstones.
-            let result = || -> std::result::Result<(Vec<u8>, u64, Option<u32>), std::io::Error> {
+            // position, and value length or None for tombstones. Returns None
+            // for batch headers.
+            type Entry = Option<(Vec<u8>, u64, Option<u32>)>;
+            let result = || -> std::result::Result<Entry, std::io::Error> {
                 r.read_exact(&mut len_buf)?;
                 let key_len = u32::from_be_bytes(len_buf);
                 r.read_exact(&mut len_buf)?;
                 let value_len_or_tombstone = match i32::from_be_bytes(len_buf) {
+                    Self::BATCH_HEADER => {
+                        // The key length is the batch length. An incomplete
+                        // batch is truncated along with its header.
+                        if pos + 8 + key_len as u64 > file_len {
+                            return Err(std::io::Error::new(
+                                std::io::ErrorKind::UnexpectedEof,
+                                "batch extends beyond end of file",
+                            ));
+                        }
+                        return Ok(None);
+                    }
                     l if l >= 0 => Some(l as u32),
                     _ => None, // -1 for tombstones
                 };
@@ -306,23 +949,23 @@ impl Log {
                     r.seek_relative(value_len as i64)?; // avoids discarding buffer
                 }
 
-                Ok((key, value_pos, value_len_or_tombstone))
+                Ok(Some((key, value_pos, value_len_or_tombstone)))
             }();
 
             match result {
-                // Populate the keydir with the entry, or remove it on tombstones.
-                Ok((key, value_pos, Some(value_len))) => {
-                    keydir.insert(key, (value_pos, value_len));
-                    pos = value_pos + value_len as u64;
-                }
-                Ok((key, value_pos, None)) => {
-                    keydir.remove(&key);
-                    pos = value_pos;
+                Ok(Some((key, value_pos, value_len))) => {
+                    pos = value_pos + value_len.unwrap_or(0) as u64;
+                    f(key, value_pos, value_len);
                 }
+                Ok(None) => pos += 8,
                 // If an incomplete entry was found at the end of the file, assume an
                 // incomplete write and truncate the file.
                 Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => {
                     log::error!("Found incomplete entry at offset {}, truncating file", pos);
+                    #[cfg(feature = "mmap")]
+                    {
+                        *self.mmap.get_mut()? = None; // accessing truncated pages would fault
+                    }
                     self.file.set_len(pos)?;
                     break;
                 }
@@ -330,36 +973,74 @@ impl Log {
             }
         }
 
-        Ok(keydir)
+        Ok(pos)
     }
 
-    /// Reads a value from the log file.
-    fn read_value(&mut self, value_pos: u64, value_len: u32) -> Result<Vec<u8>> {
+    /// Reads a value from the log file. Uses a positional read, which doesn't
+    /// move the file cursor, so concurrent reads don't interfere.
+    #[cfg(not(feature = "mmap"))]
+    fn read_value(&self, value_pos: u64, value_len: u32) -> Result<Vec<u8>> {
         let mut value = vec![0; value_len as usize];
-        self.file.seek(SeekFrom::Start(value_pos))?;
-        self.file.read_exact(&mut value)?;
+        self.file.read_exact_at(&mut value, value_pos)?;
         Ok(value)
     }
 
+    /// Reads a value from the memory-mapped log file, remapping it if the value
+    /// is beyond the end of the current map.
+    #[cfg(feature = "mmap")]
+    fn read_value(&self, value_pos: u64, value_len: u32) -> Result<Vec<u8>> {
+        let (start, end) = (value_pos as usize, value_pos as usize + value_len as usize);
+        if value_len == 0 {
+            return Ok(Vec::new());
+        }
+        let mut mmap = self.mmap.read()?;
+        if mmap.as_ref().is_none_or(|mmap| mmap.len() < end) {
+            drop(mmap);
+            let mut mmap_mut = self.mmap.write()?;
+            // Another reader may have remapped it while we waited for the lock.
+            if mmap_mut.as_ref().is_none_or(|mmap| mmap.len() < end) {
+                // SAFETY: the file is exclusively locked while the database is
+                // open, and is only appended to, so mapped bytes don't change.
+                // It's only truncated when opened, and the map is then
+                // discarded.
+                *mmap_mut = Some(unsafe { memmap2::Mmap::map(&self.file)? });
+            }
+            drop(mmap_mut);
+            mmap = self.mmap.read()?;
+        }
+        match mmap.as_ref().and_then(|mmap| mmap.get(start..end)) {
+            Some(value) => Ok(value.to_vec()),
+            None => errdata!("value at {value_pos} beyond end of file {}", self.path.display()),
+        }
+    }
+
     /// Appends a key/value entry to the log file, using a None value for
     /// tombstones. It returns the position and length of the entry.
     fn write_entry(&mut self, key: &[u8], value: Option<&[u8]>) -> Result<(u64, u32)> {
-        let key_len = key.len() as u32;
-        let value_len = value.map_or(0, |v| v.len() as u32);
-        let value_len_or_tombstone = value.map_or(-1, |v| v.len() as i32);
-        let len = 4 + 4 + key_len + value_len;
+        let mut buf = Vec::new();
+        Self::encode_entry(&mut buf, key, value);
+        Ok((self.append(&buf)?, buf.len() as u32))
+    }
 
+    /// Appends encoded entries to the log file, returning their position.
+    fn append(&mut self, buf: &[u8]) -> Result<u64> {
         let pos = self.file.seek(SeekFrom::End(0))?;
-        let mut w = BufWriter::with_capacity(len as usize, &mut self.file);
-        w.write_all(&key_len.to_be_bytes())?;
-        w.write_all(&value_len_or_tombstone.to_be_bytes())?;
-        w.write_all(key)?;
+        self.file.write_all(buf)?;
+        Ok(pos)
+    }
+
+    /// Encodes a key/value entry into the buffer, using a None value for
+    /// tombstones.
+    fn encode_entry(buf: &mut Vec<u8>, key: &[u8], value: Option<&[u8]>) {
+        let key_len = key.len() as u32;
+        let value_len_or_tombstone = value.map_or(-1, |v| v.len() as i32);
+        buf.reserve(8 + key.len() + value.map_or(0, |v| v.len()));
+        buf.extend_from_slice(&key_len.to_be_bytes());
+        buf.extend_from_slice(&value_len_or_tombstone.to_be_bytes());
+        buf.extend_from_slice(key);
         if let Some(value) = value {
-            w.write_all(value)?;
+            buf.extend_from_slice(value);
         }
-        w.flush()?;
-
-        Ok((pos, len))
     }
 }
 
@@ -402,7 +1083,8 @@ mod tests {
     }
 
     /// Tests that a log with an incomplete write at the end can be recovered by
-    /// discarding the last entry.
+    /// discarding the last entry. The log is written as a legacy single-file
+    /// log, which is migrated to a segment when opened.
     #[test]
     fn recovery() -> Result<()> {
         // Create an initial log file with a few entries. Keep track of where
@@ -424,9 +1106,9 @@ mod tests {
 
         // Copy the file, and truncate it at each byte, then try to open it
         // and assert that we always retain a prefix of entries.
-        let truncpath = dir.path().join("truncated");
         let size = std::fs::metadata(&path)?.len();
         for pos in 0..=size {
+            let truncpath = dir.path().join(format!("truncated{pos}"));
             std::fs::copy(&path, &truncpath)?;
             let f = std::fs::OpenOptions::new().write(true).open(&truncpath)?;
             f.set_len(pos)?;
@@ -452,6 +1134,102 @@ mod tests {
         Ok(())
     }
 
+    /// Tests that a write batch is atomic: if the log is truncated anywhere in
+    /// the batch, none of its writes are visible when reopened.
+    #[test]
+    fn recovery_batch() -> Result<()> {
+        let dir = tempfile::TempDir::with_prefix("toydb")?;
+        let path = dir.path().join("complete");
+        let mut engine = BitCask::new(path.clone())?;
+        engine.set(b"a", vec![1])?;
+        let mut batch = WriteBatch::new();
+        batch.set(b"a", vec![2]).delete(b"b").set(b"c", vec![3]);
+        engine.write_batch(batch)?;
+        drop(engine);
+
+        // Truncate the segment at each byte after the first entry.
+        let segment = BitCask::segment_path(&path, 1);
+        let size = std::fs::metadata(&segment)?.len();
+        for pos in 10..=size {
+            let truncpath = dir.path().join(format!("truncated{pos}"));
+            let manifest = BitCask::path_with_suffix(&path, "manifest");
+            std::fs::copy(manifest, BitCask::path_with_suffix(&truncpath, "manifest"))?;
+            let truncsegment = BitCask::segment_path(&truncpath, 1);
+            std::fs::copy(&segment, &truncsegment)?;
+            std::fs::OpenOptions::new().write(true).open(&truncsegment)?.set_len(pos)?;
+
+            let expect = match pos {
+                pos if pos < size => vec![(b"a".to_vec(), vec![1])],
+                _ => vec![(b"a".to_vec(), vec![2]), (b"c".to_vec(), vec![3])],
+            };
+            let mut engine = BitCask::new(truncpath)?;
+            assert_eq!(expect, engine.scan(..).collect::<Result<Vec<_>>>()?);
+        }
+        Ok(())
+    }
+
+    /// Tests that segment files that aren't in the manifest are removed when
+    /// the database is opened, and that missing segments error.
+    #[test]
+    fn orphans() -> Result<()> {
+        let dir = tempfile::TempDir::with_prefix("toydb")?;
+        let path = dir.path().join("bitcask");
+        let mut engine = BitCask::new(path.clone())?;
+        engine.set(b"a", vec![1])?;
+        drop(engine);
+
+        // Orphaned segments are removed, but unrelated files aren't.
+        let orphans =
+            [BitCask::segment_path(&path, 2), BitCask::path_with_suffix(&path, "000001.new")];
+        let other = BitCask::path_with_suffix(&path, "bak");
+        for file in orphans.iter().chain([&other]) {
+            std::fs::write(file, b"foo")?;
+        }
+        let mut engine = BitCask::new(path.clone())?;
+        assert_eq!(engine.get(b"a")?, Some(vec![1]));
+        assert!(orphans.iter().all(|file| !file.exists()));
+        assert!(other.exists());
+        drop(engine);
+
+        // Missing segments error.
+        std::fs::remove_file(BitCask::segment_path(&path, 1))?;
+        assert!(BitCask::new(path).is_err());
+        Ok(())
+    }
+
+    /// Tests that shared reads can run concurrently from multiple threads.
+    #[test]
+    fn concurrent_reads() -> Result<()> {
+        let path = tempfile::TempDir::with_prefix("toydb")?.path().join("bitcask");
+        let mut engine = BitCask::new(path)?;
+        engine.set_max_segment_size(100);
+        for i in 0..100u8 {
+            engine.set(&[i], vec![i; 10])?;
+        }
+
+        let engine = &engine;
+        std::thread::scope(|s| {
+            let threads: Vec<_> = (0..4u8)
+                .map(|t| {
+                    s.spawn(move || -> Result<()> {
+                        for i in (t..100).step_by(4) {
+                            assert_eq!(engine.get_shared(&[i])?, Some(vec![i; 10]));
+                        }
+                        let range = (
+                            std::ops::Bound::Included(vec![t * 10]),
+                            std::ops::Bound::Excluded(vec![50]),
+                        );
+                        let scan: Vec<_> = engine.scan_shared(range).collect::<Result<_>>()?;
+                        let expect: Vec<_> = (t * 10..50).map(|i| (vec![i], vec![i; 10])).collect();
+                        assert_eq!(scan, expect);
+                        Ok(())
+                    })
+                })
+                .collect();
+            threads.into_iter().try_for_each(|t| t.join().expect("thread panicked"))
+        })
+    }
+
     /// Tests key/value sizes up to 64 MB.
     #[test]
     fn point_ops_sizes() -> Result<()> {
@@ -472,6 +1250,57 @@ mod tests {
         Ok(())
     }
 
+    /// Tests that maybe_compact() only compacts a running database once the
+    /// garbage exceeds the thresholds, preserving its contents.
+    #[test]
+    fn maybe_compact() -> Result<()> {
+        let path = tempfile::TempDir::with_prefix("toydb")?.path().join("bitcask");
+        let mut engine = BitCask::new(path)?;
+        for i in 0..10u8 {
+            engine.set(&[i], vec![i; 10])?;
+            engine.set(&[i], vec![i; 10])?;
+        }
+        assert!(!engine.maybe_compact(0.2, 1000)?);
+        assert!(!engine.maybe_compact(0.6, 0)?);
+        assert_eq!(engine.status()?.garbage_disk_size, 190);
+
+        assert!(engine.maybe_compact(0.2, 0)?);
+        assert_eq!(engine.status()?.garbage_disk_size, 0);
+        assert!(!engine.maybe_compact(0.0, 0)?);
+        for i in 0..10u8 {
+            assert_eq!(engine.get(&[i])?, Some(vec![i; 10]));
+        }
+        Ok(())
+    }
+
+    /// Tests that incremental compaction preserves the database contents while
+    /// it's interleaved with writes, and eventually completes.
+    #[test]
+    fn maybe_compact_incremental() -> Result<()> {
+        let path = tempfile::TempDir::with_prefix("toydb")?.path().join("bitcask");
+        let mut engine = BitCask::new(path)?;
+        engine.set_max_segment_size(100);
+        engine.set_compact_step_size(15);
+        for i in 0..100u8 {
+            engine.set(&[i % 10], vec![i])?;
+        }
+
+        assert!(engine.maybe_compact(0.2, 0)?);
+        for i in 0..5u8 {
+            engine.delete(&[i * 2])?;
+            assert_eq!(engine.get(&[i * 2 + 1])?, Some(vec![90 + i * 2 + 1]));
+        }
+        while engine.compaction.is_some() || !engine.compact_queue.is_empty() {
+            assert!(engine.maybe_compact(0.2, 0)?);
+        }
+        // Only the deletes remain as garbage, since the last segment was live
+        // when the compaction started.
+        assert_eq!(engine.status()?.garbage_disk_size, 5 * 10 + 5 * 9);
+        let expect: Vec<_> = (0..5u8).map(|i| (vec![i * 2 + 1], vec![90 + i * 2 + 1])).collect();
+        assert_eq!(engine.scan(..).collect::<Result<Vec<_>>>()?, expect);
+        Ok(())
+    }
+
     /// Tests that should_compact() handles parameters correctly.
     #[test_case(100, 100, -01.0, 0 => true; "ratio negative all garbage")]
     #[test_case(100, 100, 0.0, 0 => true; "ratio 0 all garbage")]
@@ -512,6 +1341,15 @@ mod tests {
                     self.inner.engine.compact()?;
                 }
 
+                // compact_step_size SIZE
+                // Sets the number of bytes to compact per step.
+                "compact_step_size" => {
+                    let mut args = command.consume_args();
+                    let size = args.next_pos().ok_or("size not given")?.parse()?;
+                    args.reject_rest()?;
+                    self.inner.engine.set_compact_step_size(size);
+                }
+
                 // dump
                 // Dumps the full BitCask entry log.
                 "dump" => {
@@ -519,6 +1357,50 @@ mod tests {
                     self.dump(&mut output)?;
                 }
 
+                // max_keydir_size SIZE
+                // Sets the maximum estimated keydir memory size in bytes.
+                "max_keydir_size" => {
+                    let mut args = command.consume_args();
+                    let size = args.next_pos().ok_or("size not given")?.parse()?;
+                    args.reject_rest()?;
+                    self.inner.engine.set_max_keydir_size(size);
+                }
+
+                // max_segment_size SIZE
+                // Sets the maximum segment size in bytes.
+                "max_segment_size" => {
+                    let mut args = command.consume_args();
+                    let size = args.next_pos().ok_or("size not given")?.parse()?;
+                    args.reject_rest()?;
+                    self.inner.engine.set_max_segment_size(size);
+                }
+
+                // maybe_compact [fraction=FLOAT] [min_bytes=INT]
+                // Compacts segments if the garbage exceeds the given fraction
+                // (default 0) and bytes (default 0).
+                "maybe_compact" => {
+                    let mut args = command.consume_args();
+                    let fraction = args.lookup_parse("fraction")?.unwrap_or(0.0);
+                    let min_bytes = args.lookup_parse("min_bytes")?.unwrap_or(0);
+                    args.reject_rest()?;
+                    let compacted = self.inner.engine.maybe_compact(fraction, min_bytes)?;
+                    writeln!(output, "{}", if compacted { "compacted" } else { "not compacted" })?;
+                }
+
+                // segments
+                // Lists the log segments in the manifest, with their live and
+                // total sizes.
+                "segments" => {
+                    command.consume_args().reject_rest()?;
+                    let engine = &self.inner.engine;
+                    let manifest = BitCask::read_manifest(&engine.path)?.unwrap_or_default();
+                    let sizes = engine.segment_sizes()?;
+                    assert_eq!(manifest, sizes.keys().copied().collect::<Vec<_>>());
+                    for (id, (live_size, total_size)) in sizes {
+                        writeln!(output, "{id}: {total_size} bytes, {live_size} live")?;
+                    }
+                }
+
                 // reopen [compact_fraction=FLOAT]
                 // Closes and reopens the BitCask database. If compact_ratio is
                 // given, it specifies a garbage ratio beyond which the log
@@ -530,13 +1412,19 @@ mod tests {
                     // We need to close the file before we can reopen it, which
                     // happens when the database is dropped. Replace the engine
                     // with a temporary empty engine then reopen the file.
-                    let path = self.inner.engine.log.path.clone();
+                    let path = self.inner.engine.path.clone();
+                    let max_keydir_size = self.inner.engine.max_keydir_size;
+                    let max_segment_size = self.inner.engine.max_segment_size;
+                    let compact_step_size = self.inner.engine.compact_step_size;
                     self.inner.engine = BitCask::new(self.tempdir.path().join("empty"))?;
                     if let Some(garbage_fraction) = compact_fraction {
                         self.inner.engine = BitCask::new_compact(path, garbage_fraction, 0)?;
                     } else {
                         self.inner.engine = BitCask::new(path)?;
                     }
+                    self.inner.engine.set_max_keydir_size(max_keydir_size);
+                    self.inner.engine.set_max_segment_size(max_segment_size);
+                    self.inner.engine.set_compact_step_size(compact_step_size);
                 }
 
                 // Pass other commands to the standard engine runner.
@@ -554,9 +1442,20 @@ mod tests {
             Self { inner, tempdir }
         }
 
-        /// Dumps the full BitCask entry log.
+        /// Dumps the full BitCask entry log, segment by segment.
         fn dump(&mut self, output: &mut String) -> StdResult<(), Box<dyn StdError>> {
-            let file = &mut self.inner.engine.log.file;
+            for (id, log) in self.inner.engine.segments.iter_mut() {
+                writeln!(output, "segment {id}")?;
+                Self::dump_segment(&mut log.file, output)?;
+            }
+            Ok(())
+        }
+
+        /// Dumps a BitCask log segment.
+        fn dump_segment(
+            file: &mut std::fs::File,
+            output: &mut String,
+        ) -> StdResult<(), Box<dyn StdError>> {
             let file_len = file.metadata()?.len();
             let mut r = BufReader::new(file);
             let mut pos = r.seek(SeekFrom::Start(0))?;
@@ -578,6 +1477,14 @@ mod tests {
                 let value_len = value_len_or_tombstone.max(0) as u32;
                 writeln!(output, " valuelen={value_len_or_tombstone} [{}]", hex::encode(len_buf))?;
 
+                // For batch headers, the key length is the batch length.
+                if value_len_or_tombstone == Log::BATCH_HEADER {
+                    writeln!(output, "{:<7} batch of {key_len} bytes", "8b")?;
+                    pos += 8;
+                    idx += 1;
+                    continue;
+                }
+
                 let mut key = vec![0; key_len as usize];
                 r.read_exact(&mut key)?;
                 let mut value = vec![0; value_len as usize];
@@ -0,0 +1,404 @@
+use super::{Engine, ReadEngine, Status, WriteBatch};
+use crate::error::Result;
+use crate::{errdata, errinput};
+
+use base64::Engine as _;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom as _, SystemRandom};
+use std::collections::HashMap;
+use std::ops::Bound;
+
+/// The maximum number of values to re-encrypt per write batch.
+const REENCRYPT_BATCH_SIZE: usize = 1000;
+
+/// The length of a key ID.
+const KEY_ID_LEN: usize = 4;
+
+/// The length of the authentication tag appended to the ciphertext.
+const TAG_LEN: usize = 16;
+
+/// An engine that wraps another engine and encrypts values at rest with
+/// AES-256-GCM, such that the inner engine (e.g. a BitCask log) doesn't contain
+/// plaintext values. Keys are not encrypted, since engines must store keys in
+/// order for range scans. SQL keys contain primary key and index values, which
+/// are thus not protected.
+///
+/// Each value is encrypted with a random nonce, and the key is used as
+/// associated data such that values can't be moved between keys. The stored
+/// value is encoded as:
+///
+/// - Key ID: the first 4 bytes of the SHA-256 hash of the encryption key.
+/// - Nonce: 12 random bytes.
+/// - Ciphertext: the encrypted value, with the same length as the value.
+/// - Tag: the 16-byte authentication tag.
+///
+/// Encryption keys can be rotated by configuring a new key and passing the
+/// previous keys as old keys. Values encrypted with old keys can still be read,
+/// and are re-encrypted with the new key during the next maybe_compact() call,
+/// which then lets compaction remove the old ciphertexts. Old keys can be
+/// dropped once the re-encryption and compaction have completed.
+///
+/// Encryption must be enabled when the engine is created: existing plaintext
+/// values can't be read, since they can't be told apart from ciphertexts.
+pub struct Encrypted<E: Engine> {
+    /// The wrapped engine, containing encrypted values.
+    inner: E,
+    /// The encryption keys.
+    keys: Keys,
+    /// If true, values may be encrypted with old keys and should be
+    /// re-encrypted by maybe_compact().
+    reencrypt: bool,
+}
+
+/// Encryption keys, by key ID.
+struct Keys {
+    /// The current key, used to encrypt new values.
+    current: ([u8; KEY_ID_LEN], LessSafeKey),
+    /// Old keys, only used to decrypt values.
+    old: HashMap<[u8; KEY_ID_LEN], LessSafeKey>,
+    /// A random number generator for nonces.
+    rng: SystemRandom,
+}
+
+impl<E: Engine> Encrypted<E> {
+    /// Wraps an engine, encrypting values with the given 256-bit key. Values
+    /// encrypted with the given old keys can also be read, and are re-encrypted
+    /// with the current key by maybe_compact().
+    pub fn new(inner: E, key: [u8; 32], old_keys: Vec<[u8; 32]>) -> Result<Self> {
+        let reencrypt = !old_keys.is_empty();
+        let current = Self::load_key(&key)?;
+        let mut old = HashMap::new();
+        for key in old_keys {
+            let (id, key) = Self::load_key(&key)?;
+            if id != current.0 {
+                old.insert(id, key);
+            }
+        }
+        let keys = Keys { current, old, rng: SystemRandom::new() };
+        Ok(Self { inner, keys, reencrypt })
+    }
+
+    /// Parses a base64-encoded 256-bit key, e.g. generated with `openssl rand
+    /// -base64 32`.
+    pub fn parse_key(key: &str) -> Result<[u8; 32]> {
+        let Ok(bytes) = base64::engine::general_purpose::STANDARD.decode(key.trim()) else {
+            return errinput!("invalid base64 encryption key");
+        };
+        let Ok(key) = bytes.try_into() else {
+            return errinput!("encryption key must be 32 bytes");
+        };
+        Ok(key)
+    }
+
+    /// Loads an AES-256-GCM key, returning its key ID.
+    fn load_key(key: &[u8; 32]) -> Result<([u8; KEY_ID_LEN], LessSafeKey)> {
+        let digest = ring::digest::digest(&ring::digest::SHA256, key);
+        let id = digest.as_ref()[..KEY_ID_LEN].try_into()?;
+        let Ok(key) = UnboundKey::new(&AES_256_GCM, key) else {
+            return errinput!("invalid encryption key");
+        };
+        Ok((id, LessSafeKey::new(key)))
+    }
+
+    /// Re-encrypts values that were encrypted with old keys, in batches of
+    /// REENCRYPT_BATCH_SIZE. Returns the number of re-encrypted values.
+    fn reencrypt(&mut self) -> Result<u64> {
+        let mut count = 0;
+        let mut from = Bound::Unbounded;
+        loop {
+            let mut batch = WriteBatch::new();
+            let mut scan = self.inner.scan((from.clone(), Bound::Unbounded));
+            while batch.len() < REENCRYPT_BATCH_SIZE {
+                let Some((key, value)) = scan.next().transpose()? else {
+                    break;
+                };
+                if value.get(..KEY_ID_LEN) != Some(&self.keys.current.0) {
+                    let value = self.keys.decrypt(&key, value)?;
+                    batch.set(&key, self.keys.encrypt(&key, value)?);
+                }
+                from = Bound::Excluded(key);
+            }
+            drop(scan);
+            if batch.is_empty() {
+                return Ok(count);
+            }
+            count += batch.len() as u64;
+            self.inner.write_batch(batch)?;
+        }
+    }
+}
+
+impl Keys {
+    /// Encrypts a value for the given key with the current encryption key.
+    fn encrypt(&self, key: &[u8], mut value: Vec<u8>) -> Result<Vec<u8>> {
+        let (id, cipher) = &self.current;
+        let mut nonce = [0; NONCE_LEN];
+        if self.rng.fill(&mut nonce).is_err() {
+            return errdata!("failed to generate nonce");
+        }
+        let nonce_value = Nonce::assume_unique_for_key(nonce);
+        if cipher.seal_in_place_append_tag(nonce_value, Aad::from(key), &mut value).is_err() {
+            return errdata!("failed to encrypt value");
+        }
+        let mut encrypted = Vec::with_capacity(KEY_ID_LEN + NONCE_LEN + value.len());
+        encrypted.extend_from_slice(id);
+        encrypted.extend_from_slice(&nonce);
+        encrypted.extend_from_slice(&value);
+        Ok(encrypted)
+    }
+
+    /// Decrypts a value for the given key, with the key it was encrypted with.
+    fn decrypt(&self, key: &[u8], mut value: Vec<u8>) -> Result<Vec<u8>> {
+        if value.len() < KEY_ID_LEN + NONCE_LEN + TAG_LEN {
+            return errdata!("encrypted value too short");
+        }
+        let id: [u8; KEY_ID_LEN] = value[..KEY_ID_LEN].try_into()?;
+        let cipher = if id == self.current.0 {
+            &self.current.1
+        } else if let Some(cipher) = self.old.get(&id) {
+            cipher
+        } else {
+            return errdata!("unknown encryption key ID {:08x}", u32::from_be_bytes(id));
+        };
+        let nonce = Nonce::try_assume_unique_for_key(&value[KEY_ID_LEN..KEY_ID_LEN + NONCE_LEN])
+            .expect("invalid nonce length");
+        let Ok(plaintext) =
+            cipher.open_in_place(nonce, Aad::from(key), &mut value[KEY_ID_LEN + NONCE_LEN..])
+        else {
+            return errdata!("failed to decrypt value");
+        };
+        let len = plaintext.len();
+        value.drain(..KEY_ID_LEN + NONCE_LEN);
+        value.truncate(len);
+        Ok(value)
+    }
+}
+
+impl<E: Engine> ReadEngine for Encrypted<E> {
+    fn get_shared(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.inner.get_shared(key)?.map(|value| self.keys.decrypt(key, value)).transpose()
+    }
+
+    fn scan_shared(
+        &self,
+        range: (std::ops::Bound<Vec<u8>>, std::ops::Bound<Vec<u8>>),
+    ) -> Box<dyn super::ScanIterator + '_> {
+        Box::new(self.inner.scan_shared(range).map(|item| {
+            let (key, value) = item?;
+            let value = self.keys.decrypt(&key, value)?;
+            Ok((key, value))
+        }))
+    }
+
+    fn scan_keys_shared(
+        &self,
+        range: (std::ops::Bound<Vec<u8>>, std::ops::Bound<Vec<u8>>),
+    ) -> Box<dyn super::KeyScanIterator + '_> {
+        Box::new(self.inner.scan_keys_shared(range).map(|item| {
+            let (key, len) = item?;
+            match len.checked_sub(KEY_ID_LEN + NONCE_LEN + TAG_LEN) {
+                Some(len) => Ok((key, len)),
+                None => errdata!("encrypted value too short"),
+            }
+        }))
+    }
+}
+
+impl<E: Engine> Engine for Encrypted<E> {
+    type ScanIterator<'a>
+        = ScanIterator<'a, E>
+    where
+        E: 'a;
+
+    /// Backs up the inner engine, so the backup contains encrypted values and
+    /// can only be restored with the same encryption keys.
+    fn backup(&mut self, dest: &std::path::Path) -> Result<()> {
+        self.inner.backup(dest)
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<()> {
+        self.inner.delete(key)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+
+    fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.inner.get(key)?.map(|value| self.keys.decrypt(key, value)).transpose()
+    }
+
+    fn maybe_compact(&mut self, garbage_min_fraction: f64, garbage_min_bytes: u64) -> Result<bool> {
+        let mut compacted = false;
+        if self.reencrypt {
+            let count = self.reencrypt()?;
+            if count > 0 {
+                log::info!("Re-encrypted {count} values with the current encryption key");
+                compacted = true;
+            }
+            self.reencrypt = false;
+        }
+        Ok(self.inner.maybe_compact(garbage_min_fraction, garbage_min_bytes)? || compacted)
+    }
+
+    fn scan(&mut self, range: impl std::ops::RangeBounds<Vec<u8>>) -> Self::ScanIterator<'_> {
+        ScanIterator { inner: self.inner.scan(range), keys: &self.keys }
+    }
+
+    // noinspection DuplicatedCode
+    fn scan_dyn(
+        &mut self,
+        range: (std::ops::Bound<Vec<u8>>, std::ops::Bound<Vec<u8>>),
+    ) -> Box<dyn super::ScanIterator + '_> {
+        Box::new(self.scan(range))
+    }
+
+    /// Uses the inner engine's key scan, without decrypting values. Value
+    /// lengths are those of the plaintext values.
+    fn scan_keys(
+        &mut self,
+        range: (std::ops::Bound<Vec<u8>>, std::ops::Bound<Vec<u8>>),
+    ) -> Box<dyn super::KeyScanIterator + '_> {
+        Box::new(self.inner.scan_keys(range).map(|item| {
+            let (key, len) = item?;
+            match len.checked_sub(KEY_ID_LEN + NONCE_LEN + TAG_LEN) {
+                Some(len) => Ok((key, len)),
+                None => errdata!("encrypted value too short"),
+            }
+        }))
+    }
+
+    fn set(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        let value = self.keys.encrypt(key, value)?;
+        self.inner.set(key, value)
+    }
+
+    /// Restores encrypted values into the inner engine. They may have been
+    /// encrypted with old keys, so re-encrypt them during the next compaction.
+    fn restore(&mut self, src: &std::path::Path) -> Result<()> {
+        self.inner.restore(src)?;
+        self.reencrypt = !self.keys.old.is_empty();
+        Ok(())
+    }
+
+    fn status(&mut self) -> Result<Status> {
+        let mut status = self.inner.status()?;
+        status.name = format!("{} (encrypted)", status.name);
+        Ok(status)
+    }
+
+    fn write_batch(&mut self, batch: WriteBatch) -> Result<()> {
+        let mut encrypted = WriteBatch::new();
+        for (key, value) in batch {
+            match value {
+                Some(value) => encrypted.set(&key, self.keys.encrypt(&key, value)?),
+                None => encrypted.delete(&key),
+            };
+        }
+        self.inner.write_batch(encrypted)
+    }
+}
+
+pub struct ScanIterator<'a, E: Engine + 'a> {
+    inner: E::ScanIterator<'a>,
+    keys: &'a Keys,
+}
+
+impl<E: Engine> ScanIterator<'_, E> {
+    fn decrypt(&self, item: <Self as Iterator>::Item) -> <Self as Iterator>::Item {
+        let (key, value) = item?;
+        let value = self.keys.decrypt(&key, value)?;
+        Ok((key, value))
+    }
+}
+
+impl<E: Engine> Iterator for ScanIterator<'_, E> {
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|item| self.decrypt(item))
+    }
+}
+
+impl<E: Engine> DoubleEndedIterator for ScanIterator<'_, E> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back().map(|item| self.decrypt(item))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::engine::test::Runner;
+    use super::super::Memory;
+    use super::*;
+
+    use test_each_file::test_each_path;
+
+    // Run common goldenscript tests in src/storage/testscripts/engine.
+    test_each_path! { in "src/storage/testscripts/engine" as engine => test_goldenscript }
+
+    fn test_goldenscript(path: &std::path::Path) {
+        let engine = Encrypted::new(Memory::new(), [1; 32], Vec::new()).expect("encrypted failed");
+        goldenscript::run(&mut Runner::new(engine), path).expect("goldenscript failed")
+    }
+
+    /// Tests that the inner engine doesn't contain plaintext values, and that
+    /// values are bound to their key and encryption key.
+    #[test]
+    fn encrypts() -> Result<()> {
+        let mut engine = Encrypted::new(Memory::new(), [1; 32], Vec::new())?;
+        engine.set(b"a", b"plaintext".to_vec())?;
+        engine.set(b"b", b"plaintext".to_vec())?;
+        assert_eq!(engine.get(b"a")?, Some(b"plaintext".to_vec()));
+
+        // The values are encrypted with different nonces.
+        let a = engine.inner.get(b"a")?.expect("no value");
+        let b = engine.inner.get(b"b")?.expect("no value");
+        assert_eq!(a.len(), KEY_ID_LEN + NONCE_LEN + 9 + TAG_LEN);
+        assert!(!a.windows(9).any(|w| w == b"plaintext"));
+        assert_ne!(a[KEY_ID_LEN..], b[KEY_ID_LEN..]);
+
+        // A value moved to a different key can't be decrypted.
+        engine.inner.set(b"b", a.clone())?;
+        assert!(engine.get(b"b").is_err());
+
+        // A different key can't decrypt the values.
+        let mut engine = Encrypted::new(engine.inner, [2; 32], Vec::new())?;
+        assert!(engine.get(b"a").is_err());
+        Ok(())
+    }
+
+    /// Tests that rotated keys can read old values, and that they're
+    /// re-encrypted with the new key by maybe_compact().
+    #[test]
+    fn rotate() -> Result<()> {
+        let mut engine = Encrypted::new(Memory::new(), [1; 32], Vec::new())?;
+        for i in 0..10u8 {
+            engine.set(&[i], vec![i])?;
+        }
+        let old = engine.inner.get(&[0])?.expect("no value");
+
+        // The new key can read the old values, but not without the old key.
+        let mut engine = Encrypted::new(engine.inner, [2; 32], vec![[1; 32]])?;
+        assert_eq!(engine.get(&[0])?, Some(vec![0]));
+        engine.set(&[1], vec![11])?;
+        assert!(engine.maybe_compact(0.2, 0)?);
+        assert!(!engine.maybe_compact(0.2, 0)?);
+        assert_ne!(engine.inner.get(&[0])?, Some(old));
+
+        let mut engine = Encrypted::new(engine.inner, [2; 32], Vec::new())?;
+        let expect: Vec<_> =
+            (0..10u8).map(|i| (vec![i], vec![if i == 1 { 11 } else { i }])).collect();
+        assert_eq!(engine.scan(..).collect::<Result<Vec<_>>>()?, expect);
+        Ok(())
+    }
+
+    /// Tests key parsing.
+    #[test]
+    fn parse_key() {
+        let key = base64::engine::general_purpose::STANDARD.encode([7; 32]);
+        assert_eq!(Encrypted::<Memory>::parse_key(&key).ok(), Some([7; 32]));
+        assert!(Encrypted::<Memory>::parse_key("not base64!").is_err());
+        assert!(Encrypted::<Memory>::parse_key("AAAA").is_err());
+    }
+}
@@ -1,7 +1,10 @@
+use super::backup;
 use crate::encoding::keycode;
 use crate::error::Result;
 
+use itertools::Itertools as _;
 use serde::{Deserialize, Serialize};
+use std::ops::Bound;
 
 /// A key/value storage engine storing arbitrary byte strings in lexicographical
 /// key order. Storing keys in order allows for efficient range scans, which is
@@ -10,15 +13,26 @@ use serde::{Deserialize, Serialize};
 /// order-preserving encoding, see src/encoding/keycode. Writes are only
 /// guaranteed durable after calling flush().
 ///
-/// Only supports single-threaded use since all methods (including reads) take a
-/// mutable reference -- serialized access can't be avoided anyway, since both
-/// Raft execution and file access is serial.
-pub trait Engine: Send {
+/// Writes and other methods take a mutable reference, and thus require
+/// exclusive access -- serialized writes can't be avoided anyway, since Raft
+/// execution is serial. Reads can also be done concurrently from multiple
+/// threads via a shared reference, using the ReadEngine supertrait.
+pub trait Engine: ReadEngine + Send {
     /// The iterator returned by scan().
     type ScanIterator<'a>: ScanIterator + 'a
     where
         Self: Sized + 'a; // omit in trait objects, for object safety
 
+    /// Writes a consistent point-in-time copy of all key/value pairs to a
+    /// backup file at dest, replacing any existing file. The backup is
+    /// independent of the engine's on-disk format (see the `backup` module),
+    /// and can be restored into any engine with restore(). Writes are blocked
+    /// while the backup is taken, since it requires exclusive access.
+    fn backup(&mut self, dest: &std::path::Path) -> Result<()> {
+        backup::write(self.scan_dyn((Bound::Unbounded, Bound::Unbounded)), dest)?;
+        Ok(())
+    }
+
     /// Deletes a key, or does nothing if it does not exist.
     fn delete(&mut self, key: &[u8]) -> Result<()>;
 
@@ -28,6 +42,20 @@ pub trait Engine: Send {
     /// Gets a value for a key, if it exists.
     fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>>;
 
+    /// Compacts the storage if its garbage exceeds both the given fraction of
+    /// the total disk size and the given number of bytes, reclaiming disk
+    /// space. Returns true if it was compacted. Engines may also compact
+    /// incrementally, continuing an in-progress compaction on each call
+    /// regardless of the thresholds and returning true while it's in progress.
+    /// Defaults to a noop, for engines that don't accumulate garbage.
+    fn maybe_compact(
+        &mut self,
+        _garbage_min_fraction: f64,
+        _garbage_min_bytes: u64,
+    ) -> Result<bool> {
+        Ok(false)
+    }
+
     /// Iterates over an ordered range of key/value pairs.
     fn scan(&mut self, range: impl std::ops::RangeBounds<Vec<u8>>) -> Self::ScanIterator<'_>
     where
@@ -40,6 +68,16 @@ pub trait Engine: Send {
         range: (std::ops::Bound<Vec<u8>>, std::ops::Bound<Vec<u8>>),
     ) -> Box<dyn ScanIterator + '_>;
 
+    /// Iterates over an ordered range of keys and their value lengths, for
+    /// callers that don't need the values. Engines should override this if
+    /// they can avoid reading values, e.g. from an in-memory key index.
+    fn scan_keys(
+        &mut self,
+        range: (std::ops::Bound<Vec<u8>>, std::ops::Bound<Vec<u8>>),
+    ) -> Box<dyn KeyScanIterator + '_> {
+        Box::new(self.scan_dyn(range).map_ok(|(key, value)| (key, value.len())))
+    }
+
     /// Iterates over all key/value pairs starting with prefix.
     fn scan_prefix(&mut self, prefix: &[u8]) -> Self::ScanIterator<'_>
     where
@@ -51,8 +89,112 @@ pub trait Engine: Send {
     /// Sets a value for a key, replacing the existing value if any.
     fn set(&mut self, key: &[u8], value: Vec<u8>) -> Result<()>;
 
+    /// Replaces all key/value pairs with the contents of a backup file written
+    /// by backup(), and flushes them to durable storage. The backup file is
+    /// validated before any changes are made, but the restore itself is not
+    /// atomic: if it fails partway, the engine contains a mix of old and
+    /// restored data, and the restore should be retried.
+    fn restore(&mut self, src: &std::path::Path) -> Result<()> {
+        backup::validate(src)?;
+        let keys: Vec<Vec<u8>> = self
+            .scan_keys((Bound::Unbounded, Bound::Unbounded))
+            .map_ok(|(key, _)| key)
+            .try_collect()?;
+        for chunk in keys.chunks(backup::BATCH_SIZE) {
+            let mut batch = WriteBatch::new();
+            chunk.iter().for_each(|key| _ = batch.delete(key));
+            self.write_batch(batch)?;
+        }
+        backup::read(src, |batch| self.write_batch(batch))?;
+        self.flush()
+    }
+
     /// Returns engine status.
     fn status(&mut self) -> Result<Status>;
+
+    /// Atomically applies a batch of writes, in order. Either all or none of
+    /// the writes will be visible after a crash, and a single flush() makes
+    /// them all durable.
+    fn write_batch(&mut self, batch: WriteBatch) -> Result<()>;
+}
+
+/// Shared read access to a storage engine. Unlike Engine methods, these take a
+/// shared reference, so they can run concurrently from multiple threads, e.g.
+/// while holding the read lock of an RwLock. They must see the same data as
+/// the corresponding Engine methods.
+pub trait ReadEngine: Sync {
+    /// Gets a value for a key, if it exists.
+    fn get_shared(&self, key: &[u8]) -> Result<Option<Vec<u8>>>;
+
+    /// Iterates over an ordered range of key/value pairs.
+    fn scan_shared(
+        &self,
+        range: (std::ops::Bound<Vec<u8>>, std::ops::Bound<Vec<u8>>),
+    ) -> Box<dyn ScanIterator + '_>;
+
+    /// Iterates over an ordered range of keys and their value lengths, like
+    /// Engine::scan_keys(). Engines should override this if they can avoid
+    /// reading values.
+    fn scan_keys_shared(
+        &self,
+        range: (std::ops::Bound<Vec<u8>>, std::ops::Bound<Vec<u8>>),
+    ) -> Box<dyn KeyScanIterator + '_> {
+        Box::new(self.scan_shared(range).map_ok(|(key, value)| (key, value.len())))
+    }
+}
+
+/// A batch of writes, applied atomically by Engine::write_batch().
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WriteBatch {
+    /// The writes, as keys and values, or None for deletes.
+    writes: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+}
+
+impl WriteBatch {
+    /// Creates a new, empty write batch.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a delete of a key to the batch.
+    pub fn delete(&mut self, key: &[u8]) -> &mut Self {
+        self.writes.push((key.to_vec(), None));
+        self
+    }
+
+    /// Adds a set of a key to the batch.
+    pub fn set(&mut self, key: &[u8], value: Vec<u8>) -> &mut Self {
+        self.writes.push((key.to_vec(), Some(value)));
+        self
+    }
+
+    /// Returns true if the batch is empty.
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty()
+    }
+
+    /// Returns the number of writes in the batch.
+    pub fn len(&self) -> usize {
+        self.writes.len()
+    }
+}
+
+impl IntoIterator for WriteBatch {
+    type Item = (Vec<u8>, Option<Vec<u8>>);
+    type IntoIter = std::vec::IntoIter<Self::Item>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.writes.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a WriteBatch {
+    type Item = &'a (Vec<u8>, Option<Vec<u8>>);
+    type IntoIter = std::slice::Iter<'a, (Vec<u8>, Option<Vec<u8>>)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.writes.iter()
+    }
 }
 
 /// A scan iterator, with a blanket implementation (in lieu of trait aliases).
@@ -60,6 +202,12 @@ pub trait ScanIterator: DoubleEndedIterator<Item = Result<(Vec<u8>, Vec<u8>)>> {
 
 impl<I: DoubleEndedIterator<Item = Result<(Vec<u8>, Vec<u8>)>>> ScanIterator for I {}
 
+/// A key scan iterator over keys and value lengths, with a blanket
+/// implementation (in lieu of trait aliases).
+pub trait KeyScanIterator: DoubleEndedIterator<Item = Result<(Vec<u8>, usize)>> {}
+
+impl<I: DoubleEndedIterator<Item = Result<(Vec<u8>, usize)>>> KeyScanIterator for I {}
+
 /// Engine status.
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Status {
@@ -75,6 +223,9 @@ pub struct Status {
     pub live_disk_size: u64,
     /// The on-disk size of garbage data.
     pub garbage_disk_size: u64,
+    /// The estimated memory used by the in-memory key index, if any, e.g. the
+    /// BitCask keydir or the LSM table indexes.
+    pub index_memory_size: u64,
 }
 
 impl Status {
@@ -93,7 +244,6 @@ pub mod test {
     use crate::encoding::format::{self, Formatter as _};
 
     use crossbeam::channel::Sender;
-    use itertools::Itertools as _;
     use regex::Regex;
     use std::fmt::Write as _;
     use std::{error::Error as StdError, result::Result as StdResult};
@@ -103,11 +253,13 @@ pub mod test {
     /// engine-specific tests.
     pub struct Runner<E: Engine> {
         pub engine: E,
+        /// A temporary directory for backup files.
+        backup_dir: tempfile::TempDir,
     }
 
     impl<E: Engine> Runner<E> {
         pub fn new(engine: E) -> Self {
-            Self { engine }
+            Self { engine, backup_dir: tempfile::TempDir::with_prefix("toydb").unwrap() }
         }
     }
 
@@ -115,6 +267,14 @@ pub mod test {
         fn run(&mut self, command: &goldenscript::Command) -> StdResult<String, Box<dyn StdError>> {
             let mut output = String::new();
             match command.name.as_str() {
+                // backup FILE
+                "backup" => {
+                    let mut args = command.consume_args();
+                    let file = &args.next_pos().ok_or("file not given")?.value;
+                    args.reject_rest()?;
+                    self.engine.backup(&self.backup_dir.path().join(file))?;
+                }
+
                 // delete KEY
                 "delete" => {
                     let mut args = command.consume_args();
@@ -129,9 +289,18 @@ pub mod test {
                     let key = decode_binary(&args.next_pos().ok_or("key not given")?.value);
                     args.reject_rest()?;
                     let value = self.engine.get(&key)?;
+                    assert_eq!(value, self.engine.get_shared(&key)?, "get_shared mismatch");
                     writeln!(output, "{}", format::Raw::key_maybe_value(&key, value.as_deref()))?;
                 }
 
+                // restore FILE
+                "restore" => {
+                    let mut args = command.consume_args();
+                    let file = &args.next_pos().ok_or("file not given")?.value;
+                    args.reject_rest()?;
+                    self.engine.restore(&self.backup_dir.path().join(file))?;
+                }
+
                 // scan [reverse=BOOL] RANGE
                 "scan" => {
                     let mut args = command.consume_args();
@@ -139,17 +308,51 @@ pub mod test {
                     let range =
                         parse_key_range(args.next_pos().map(|a| a.value.as_str()).unwrap_or(".."))?;
                     args.reject_rest()?;
+                    use std::ops::RangeBounds as _;
+                    let range = (range.start_bound().cloned(), range.end_bound().cloned());
                     let items: Vec<_> = if reverse {
-                        self.engine.scan(range).rev().try_collect()?
+                        self.engine.scan(range.clone()).rev().try_collect()?
                     } else {
-                        self.engine.scan(range).try_collect()?
+                        self.engine.scan(range.clone()).try_collect()?
                     };
+                    let shared: Vec<_> = if reverse {
+                        self.engine.scan_shared(range).rev().try_collect()?
+                    } else {
+                        self.engine.scan_shared(range).try_collect()?
+                    };
+                    assert_eq!(items, shared, "scan_shared mismatch");
                     for (key, value) in items {
                         let fmtkv = format::Raw::key_value(&key, &value);
                         writeln!(output, "{fmtkv}")?;
                     }
                 }
 
+                // scan_keys [reverse=BOOL] RANGE
+                "scan_keys" => {
+                    let mut args = command.consume_args();
+                    let reverse = args.lookup_parse("reverse")?.unwrap_or(false);
+                    let range =
+                        parse_key_range(args.next_pos().map(|a| a.value.as_str()).unwrap_or(".."))?;
+                    args.reject_rest()?;
+                    use std::ops::RangeBounds as _;
+                    let range = (range.start_bound().cloned(), range.end_bound().cloned());
+                    let items: Vec<_> = if reverse {
+                        self.engine.scan_keys(range.clone()).rev().try_collect()?
+                    } else {
+                        self.engine.scan_keys(range.clone()).try_collect()?
+                    };
+                    let shared: Vec<_> = if reverse {
+                        self.engine.scan_keys_shared(range).rev().try_collect()?
+                    } else {
+                        self.engine.scan_keys_shared(range).try_collect()?
+                    };
+                    assert_eq!(items, shared, "scan_keys_shared mismatch");
+                    for (key, value_len) in items {
+                        let fmtkey = format::Raw::key(&key);
+                        writeln!(output, "{fmtkey} ({value_len} bytes)")?;
+                    }
+                }
+
                 // scan_prefix PREFIX
                 "scan_prefix" => {
                     let mut args = command.consume_args();
@@ -178,6 +381,19 @@ pub mod test {
                     writeln!(output, "{:#?}", self.engine.status()?)?;
                 }
 
+                // write_batch [KEY=VALUE | KEY]...
+                // Atomically writes a batch of sets and deletes (bare keys).
+                "write_batch" => {
+                    let mut batch = WriteBatch::new();
+                    for arg in command.consume_args().rest() {
+                        match &arg.key {
+                            Some(key) => batch.set(&decode_binary(key), decode_binary(&arg.value)),
+                            None => batch.delete(&decode_binary(&arg.value)),
+                        };
+                    }
+                    self.engine.write_batch(batch)?;
+                }
+
                 name => return Err(format!("invalid command {name}").into()),
             }
             Ok(output)
@@ -247,8 +463,31 @@ pub mod test {
         }
     }
 
+    impl<E: Engine> ReadEngine for Emit<E> {
+        fn get_shared(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+            self.inner.get_shared(key)
+        }
+
+        fn scan_shared(
+            &self,
+            range: (std::ops::Bound<Vec<u8>>, std::ops::Bound<Vec<u8>>),
+        ) -> Box<dyn ScanIterator + '_> {
+            self.inner.scan_shared(range)
+        }
+
+        fn scan_keys_shared(
+            &self,
+            range: (std::ops::Bound<Vec<u8>>, std::ops::Bound<Vec<u8>>),
+        ) -> Box<dyn KeyScanIterator + '_> {
+            self.inner.scan_keys_shared(range)
+        }
+    }
+
     impl<E: Engine> Engine for Emit<E> {
-        type ScanIterator<'a> = E::ScanIterator<'a> where E: 'a;
+        type ScanIterator<'a>
+            = E::ScanIterator<'a>
+        where
+            E: 'a;
 
         fn delete(&mut self, key: &[u8]) -> Result<()> {
             self.inner.delete(key)?;
@@ -277,6 +516,13 @@ pub mod test {
             Box::new(self.scan(range))
         }
 
+        fn scan_keys(
+            &mut self,
+            range: (std::ops::Bound<Vec<u8>>, std::ops::Bound<Vec<u8>>),
+        ) -> Box<dyn KeyScanIterator + '_> {
+            self.inner.scan_keys(range)
+        }
+
         fn set(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
             self.inner.set(key, value.clone())?;
             self.tx.send(Operation::Set { key: key.to_vec(), value })?;
@@ -286,6 +532,17 @@ pub mod test {
         fn status(&mut self) -> Result<Status> {
             self.inner.status()
         }
+
+        fn write_batch(&mut self, batch: WriteBatch) -> Result<()> {
+            self.inner.write_batch(batch.clone())?;
+            for (key, value) in batch {
+                match value {
+                    Some(value) => self.tx.send(Operation::Set { key, value })?,
+                    None => self.tx.send(Operation::Delete { key })?,
+                }
+            }
+            Ok(())
+        }
     }
 
     /// An engine that wraps two others and mirrors operations across them,
@@ -302,8 +559,40 @@ pub mod test {
         }
     }
 
+    impl<A: Engine, B: Engine> ReadEngine for Mirror<A, B> {
+        fn get_shared(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+            let a = self.a.get_shared(key)?;
+            let b = self.b.get_shared(key)?;
+            assert_eq!(a, b);
+            Ok(a)
+        }
+
+        fn scan_shared(
+            &self,
+            range: (std::ops::Bound<Vec<u8>>, std::ops::Bound<Vec<u8>>),
+        ) -> Box<dyn ScanIterator + '_> {
+            // For simplicity, buffer both scans and compare them.
+            let a: Vec<_> = self.a.scan_shared(range.clone()).collect();
+            let b: Vec<_> = self.b.scan_shared(range).collect();
+            assert_eq!(a, b);
+            Box::new(a.into_iter())
+        }
+
+        fn scan_keys_shared(
+            &self,
+            range: (std::ops::Bound<Vec<u8>>, std::ops::Bound<Vec<u8>>),
+        ) -> Box<dyn KeyScanIterator + '_> {
+            // For simplicity, buffer both scans and compare them.
+            let a: Vec<_> = self.a.scan_keys_shared(range.clone()).collect();
+            let b: Vec<_> = self.b.scan_keys_shared(range).collect();
+            assert_eq!(a, b);
+            Box::new(a.into_iter())
+        }
+    }
+
     impl<A: Engine, B: Engine> Engine for Mirror<A, B> {
-        type ScanIterator<'a> = MirrorIterator<'a, A, B>
+        type ScanIterator<'a>
+            = MirrorIterator<'a, A, B>
         where
             Self: Sized,
             A: 'a,
@@ -344,6 +633,17 @@ pub mod test {
             Box::new(MirrorIterator::<A, B> { a, b })
         }
 
+        fn scan_keys(
+            &mut self,
+            range: (std::ops::Bound<Vec<u8>>, std::ops::Bound<Vec<u8>>),
+        ) -> Box<dyn KeyScanIterator + '_> {
+            // For simplicity, buffer both scans and compare them.
+            let a: Vec<_> = self.a.scan_keys(range.clone()).collect();
+            let b: Vec<_> = self.b.scan_keys(range).collect();
+            assert_eq!(a, b);
+            Box::new(a.into_iter())
+        }
+
         fn set(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
             self.a.set(key, value.clone())?;
             self.b.set(key, value)
@@ -357,6 +657,11 @@ pub mod test {
             assert_eq!(a.size, b.size);
             Ok(a)
         }
+
+        fn write_batch(&mut self, batch: WriteBatch) -> Result<()> {
+            self.a.write_batch(batch.clone())?;
+            self.b.write_batch(batch)
+        }
     }
 
     pub struct MirrorIterator<'a, A: Engine + 'a, B: Engine + 'a> {
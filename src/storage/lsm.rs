@@ -0,0 +1,1370 @@
+use super::{Engine, ReadEngine, Status, WriteBatch};
+use crate::errdata;
+use crate::error::Result;
+
+use fs4::fs_std::FileExt;
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::ops::{Bound, RangeBounds};
+use std::os::unix::fs::FileExt as _;
+use std::path::{Path, PathBuf};
+
+/// A simple log-structured merge-tree (LSM) key-value engine. Unlike BitCask,
+/// it does not keep all keys in memory, so it can store datasets whose key set
+/// is larger than memory. See e.g.:
+/// https://www.cs.umb.edu/~poneil/lsmtree.pdf
+///
+/// Writes are appended to a write-ahead log (WAL) and applied to an in-memory
+/// memtable. Once the WAL exceeds the maximum memtable size, the memtable is
+/// flushed to an immutable sorted string table (SSTable) file in level 0, and
+/// the WAL is truncated. Deletes write tombstones, which shadow values in older
+/// tables until they're merged away.
+///
+/// Tables are organized in levels. Level 0 contains flushed memtables, whose
+/// key ranges may overlap. Once level 0 has L0_MAX_TABLES tables, they're
+/// merged with the overlapping tables in level 1. Each level from 1 and down
+/// contains non-overlapping tables, and has a maximum size that's 10 times
+/// larger than the previous level. Once a level exceeds its maximum size, its
+/// oldest table is merged with the overlapping tables in the next level.
+/// Tombstones are dropped when merged into the bottom level.
+///
+/// Reads check the memtable, then level 0 tables from newest to oldest, then
+/// the lower levels in order, and use the first version found. Scans merge the
+/// memtable and all overlapping tables. Each table keeps a sparse index of its
+/// blocks in memory, with one key per block of about BLOCK_SIZE bytes, and
+/// only reads the relevant blocks from disk.
+///
+/// Given a database path such as data/sql, the following files are used:
+///
+/// - data/sql: an empty lock file, exclusively locked while the database is
+///   open.
+/// - data/sql.wal: the write-ahead log of memtable writes.
+/// - data/sql.levels: the tables in each level, one "level id" pair per line.
+///   It is atomically replaced when tables are added or removed.
+/// - data/sql.000001.sst etc: the tables, named by table ID.
+///
+/// This implementation makes several simplifications over standard LSM trees:
+///
+/// - Flushes and compactions run in the caller's thread rather than a
+///   background thread, blocking reads and writes while they run.
+///
+/// - Tables don't have Bloom filters, so reads of missing keys may read a
+///   block from each level.
+///
+/// - Log entries and tables don't contain checksums.
+///
+/// WAL entries and table entries use the same encoding as BitCask:
+///
+/// - Key length as big-endian u32.
+/// - Value length as big-endian i32, or -1 for tombstones.
+/// - Key as raw bytes (max 2 GB).
+/// - Value as raw bytes (max 2 GB).
+///
+/// Write batches with multiple entries are prefixed by a header entry in the
+/// WAL, with the length of the batch entries in place of the key length and -2
+/// in place of the value length. If the batch is incomplete when the WAL is
+/// replayed, it's truncated along with its header.
+pub struct Lsm {
+    /// The database path, see above.
+    path: PathBuf,
+    /// The lock file at the database path, held for its exclusive lock.
+    _lock: std::fs::File,
+    /// The write-ahead log, opened for appends.
+    wal: std::fs::File,
+    /// The size of the write-ahead log.
+    wal_size: u64,
+    /// The memtable, containing the writes in the WAL. None is a tombstone.
+    memtable: Memtable,
+    /// The WAL size in bytes beyond which the memtable is flushed.
+    max_memtable_size: u64,
+    /// The size in bytes beyond which compactions start a new table.
+    max_table_size: u64,
+    /// The tables in each level. Level 0 tables are ordered from oldest to
+    /// newest, and tables in other levels by key.
+    levels: Vec<Vec<Table>>,
+    /// The next table ID.
+    next_id: TableID,
+}
+
+/// An in-memory table of recent writes, with None for tombstones.
+type Memtable = BTreeMap<Vec<u8>, Option<Vec<u8>>>;
+
+/// A key/value entry, with None for tombstones.
+type Entry = (Vec<u8>, Option<Vec<u8>>);
+
+/// A table ID. IDs increase as tables are written.
+type TableID = u64;
+
+/// The value length of WAL batch headers.
+const BATCH_HEADER: i32 = -2;
+
+/// The approximate size of table blocks, in bytes.
+const BLOCK_SIZE: u64 = 4096;
+
+/// The number of level 0 tables that triggers a compaction into level 1.
+const L0_MAX_TABLES: usize = 4;
+
+/// The size ratio between adjacent levels.
+const LEVEL_SIZE_MULTIPLIER: u64 = 10;
+
+impl Lsm {
+    /// The default maximum memtable size, in bytes of WAL.
+    pub const DEFAULT_MAX_MEMTABLE_SIZE: u64 = 4 * 1024 * 1024;
+
+    /// The default maximum table size, in bytes.
+    pub const DEFAULT_MAX_TABLE_SIZE: u64 = 8 * 1024 * 1024;
+
+    /// Opens or creates an LSM database at the given path.
+    pub fn new(path: PathBuf) -> Result<Self> {
+        log::info!("Opening database {}", path.display());
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?
+        }
+        let lock = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        lock.try_lock_exclusive()?;
+
+        // Open the tables in the manifest, and remove any others.
+        let manifest = Self::read_manifest(&path)?;
+        Self::remove_orphans(&path, &manifest)?;
+        let mut levels: Vec<Vec<Table>> = Vec::new();
+        for (level, id) in manifest.iter().copied() {
+            if levels.len() <= level {
+                levels.resize_with(level + 1, Vec::new);
+            }
+            levels[level].push(Table::open(id, Self::table_path(&path, id))?);
+        }
+        for tables in levels.iter_mut().skip(1) {
+            tables.sort_by(|a, b| a.first_key().cmp(b.first_key()));
+        }
+        if levels.is_empty() {
+            levels.push(Vec::new());
+        }
+        let next_id = manifest.iter().map(|(_, id)| id + 1).max().unwrap_or(1);
+
+        // Replay the WAL into the memtable.
+        let wal_path = Self::path_with_suffix(&path, "wal");
+        let mut wal =
+            std::fs::OpenOptions::new().read(true).append(true).create(true).open(&wal_path)?;
+        let mut memtable = Memtable::new();
+        let wal_size = Self::replay_wal(&mut wal, &mut memtable)?;
+        log::info!(
+            "Opened {} tables and {} memtable keys in {}",
+            manifest.len(),
+            memtable.len(),
+            path.display()
+        );
+
+        Ok(Self {
+            path,
+            _lock: lock,
+            wal,
+            wal_size,
+            memtable,
+            max_memtable_size: Self::DEFAULT_MAX_MEMTABLE_SIZE,
+            max_table_size: Self::DEFAULT_MAX_TABLE_SIZE,
+            levels,
+            next_id,
+        })
+    }
+
+    /// Sets the WAL size in bytes beyond which the memtable is flushed to a
+    /// level 0 table.
+    pub fn set_max_memtable_size(&mut self, size: u64) {
+        self.max_memtable_size = size;
+    }
+
+    /// Sets the size in bytes beyond which compactions start a new table.
+    /// Level sizes are multiples of this.
+    pub fn set_max_table_size(&mut self, size: u64) {
+        self.max_table_size = size;
+    }
+
+    /// Returns the path of a file next to the database path, with the given
+    /// suffix appended to the file name.
+    fn path_with_suffix(path: &Path, suffix: &str) -> PathBuf {
+        let mut path = path.as_os_str().to_owned();
+        path.push(".");
+        path.push(suffix);
+        path.into()
+    }
+
+    /// Returns the path of the given table.
+    fn table_path(path: &Path, id: TableID) -> PathBuf {
+        Self::path_with_suffix(path, &format!("{id:06}.sst"))
+    }
+
+    /// Reads the level and ID of each table from the manifest, or an empty
+    /// list if there isn't one.
+    fn read_manifest(path: &Path) -> Result<Vec<(usize, TableID)>> {
+        let manifest = match std::fs::read_to_string(Self::path_with_suffix(path, "levels")) {
+            Ok(manifest) => manifest,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+        let mut tables = Vec::new();
+        for line in manifest.lines() {
+            let Some((level, id)) = line.split_once(' ') else {
+                return errdata!("invalid manifest line {line:?}");
+            };
+            tables.push((level.parse()?, id.parse()?));
+        }
+        Ok(tables)
+    }
+
+    /// Writes the levels to the manifest, atomically replacing it.
+    fn write_manifest(&self) -> Result<()> {
+        let tmp_path = Self::path_with_suffix(&self.path, "levels.new");
+        let mut file = std::fs::File::create(&tmp_path)?;
+        for (level, tables) in self.levels.iter().enumerate() {
+            for table in tables {
+                writeln!(file, "{level} {}", table.id)?;
+            }
+        }
+        #[cfg(not(test))]
+        file.sync_all()?;
+        std::fs::rename(tmp_path, Self::path_with_suffix(&self.path, "levels"))?;
+        Ok(())
+    }
+
+    /// Removes table files that aren't in the manifest, e.g. ones left behind
+    /// by an interrupted flush or compaction.
+    fn remove_orphans(path: &Path, manifest: &[(usize, TableID)]) -> Result<()> {
+        let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+            return Ok(());
+        };
+        let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+        let prefix = format!("{}.", name.to_string_lossy());
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let file_name = entry.file_name();
+            let Some(id) = file_name
+                .to_str()
+                .and_then(|n| n.strip_prefix(&prefix))
+                .and_then(|n| n.strip_suffix(".sst"))
+            else {
+                continue;
+            };
+            if id.is_empty() || !id.bytes().all(|b| b.is_ascii_digit()) {
+                continue;
+            }
+            let id: TableID = id.parse()?;
+            if manifest.iter().any(|(_, table_id)| *table_id == id) {
+                continue;
+            }
+            log::info!("Removing orphaned table {}", entry.path().display());
+            std::fs::remove_file(entry.path())?;
+        }
+        Ok(())
+    }
+
+    /// Replays the WAL into the memtable, returning the WAL size. If an
+    /// incomplete entry or batch is encountered, it is assumed to be caused by
+    /// an incomplete write and the remainder of the WAL is truncated.
+    fn replay_wal(wal: &mut std::fs::File, memtable: &mut Memtable) -> Result<u64> {
+        let file_len = wal.metadata()?.len();
+        let mut r = BufReader::new(&mut *wal);
+        let mut pos = r.seek(SeekFrom::Start(0))?;
+        while pos < file_len {
+            let result = || -> std::io::Result<(Vec<Entry>, u64)> {
+                let (key_len, value_len) = read_entry_header(&mut r)?;
+                if value_len != BATCH_HEADER {
+                    let entry = read_entry(&mut r, key_len, value_len)?;
+                    return Ok((vec![entry], 8 + key_len as u64 + value_len.max(0) as u64));
+                }
+                // The key length is the batch length. An incomplete batch is
+                // truncated along with its header.
+                if pos + 8 + key_len as u64 > file_len {
+                    return Err(std::io::ErrorKind::UnexpectedEof.into());
+                }
+                let mut batch = vec![0; key_len as usize];
+                r.read_exact(&mut batch)?;
+                Ok((decode_entries(&batch)?, 8 + key_len as u64))
+            }();
+            match result {
+                Ok((entries, len)) => {
+                    memtable.extend(entries);
+                    pos += len;
+                }
+                Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => {
+                    log::error!("Found incomplete WAL entry at offset {pos}, truncating WAL");
+                    drop(r);
+                    wal.set_len(pos)?;
+                    break;
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(pos)
+    }
+
+    /// Appends encoded entries to the WAL, and applies them to the memtable.
+    /// Flushes the memtable if the WAL exceeds the maximum memtable size.
+    fn append(&mut self, buf: &[u8], entries: impl IntoIterator<Item = Entry>) -> Result<()> {
+        self.wal.write_all(buf)?;
+        self.wal_size += buf.len() as u64;
+        self.memtable.extend(entries);
+        if self.wal_size >= self.max_memtable_size {
+            self.flush_memtable()?;
+        }
+        Ok(())
+    }
+
+    /// Flushes the memtable to a new level 0 table, truncates the WAL, and
+    /// compacts levels that exceed their size limits.
+    pub fn flush_memtable(&mut self) -> Result<()> {
+        if !self.memtable.is_empty() {
+            let id = self.next_id;
+            self.next_id += 1;
+            let mut builder = TableBuilder::new(id, Self::table_path(&self.path, id))?;
+            for (key, value) in &self.memtable {
+                builder.add(key, value.as_deref())?;
+            }
+            self.levels[0].push(builder.finish()?);
+            self.write_manifest()?;
+        }
+        self.wal.set_len(0)?;
+        self.wal_size = 0;
+        self.memtable.clear();
+        self.compact_levels()
+    }
+
+    /// Returns the maximum size of the given level, in bytes.
+    fn max_level_size(&self, level: usize) -> u64 {
+        self.max_table_size.saturating_mul(LEVEL_SIZE_MULTIPLIER.saturating_pow(level as u32))
+    }
+
+    /// Compacts levels until level 0 has fewer than L0_MAX_TABLES tables and
+    /// all other levels are within their maximum size.
+    fn compact_levels(&mut self) -> Result<()> {
+        loop {
+            if self.levels[0].len() >= L0_MAX_TABLES {
+                self.compact_level(0)?;
+                continue;
+            }
+            let Some(level) = (1..self.levels.len()).find(|level| {
+                self.levels[*level].iter().map(|t| t.size).sum::<u64>()
+                    > self.max_level_size(*level)
+            }) else {
+                return Ok(());
+            };
+            self.compact_level(level)?;
+        }
+    }
+
+    /// Merges tables from the given level into the next level. For level 0,
+    /// all tables are merged, otherwise the oldest table is. They're merged
+    /// with the overlapping tables in the next level, replacing them.
+    fn compact_level(&mut self, level: usize) -> Result<()> {
+        if self.levels.len() <= level + 1 {
+            self.levels.push(Vec::new());
+        }
+        let (upper, lower) = self.levels.split_at(level + 1);
+        let (upper, lower) = (&upper[level], &lower[0]);
+
+        // Pick the input tables, and the overlapping tables in the next level.
+        let inputs: Vec<&Table> = match level {
+            0 => upper.iter().rev().collect(), // newest first
+            _ => upper.iter().min_by_key(|t| t.id).into_iter().collect(),
+        };
+        let start = inputs.iter().map(|t| t.first_key()).min().expect("no input tables").to_vec();
+        let end = inputs.iter().map(|t| t.last_key.as_slice()).max().expect("no tables").to_vec();
+        let range = (Bound::Included(start), Bound::Included(end));
+        let overlapping: Vec<&Table> = lower.iter().filter(|t| t.overlaps(&range)).collect();
+        let bottom = self.levels[level + 2..].iter().all(|tables| tables.is_empty());
+
+        // Merge the tables, newest first, and write out the new tables.
+        let ids: Vec<TableID> = inputs.iter().chain(&overlapping).map(|t| t.id).collect();
+        let sources = inputs
+            .iter()
+            .chain(&overlapping)
+            .map(|t| Box::new(t.scan(..)) as EntryIterator)
+            .collect();
+        let merged = MergeIterator::new(sources, !bottom);
+        let mut next_id = self.next_id;
+        let tables = Self::write_tables(&self.path, &mut next_id, self.max_table_size, merged)?;
+        self.next_id = next_id;
+
+        // Install the new tables and remove the old ones.
+        let mut removed = Vec::new();
+        for tables in &mut self.levels[level..=level + 1] {
+            let (remove, keep) =
+                std::mem::take(tables).into_iter().partition(|t| ids.contains(&t.id));
+            *tables = keep;
+            removed.extend::<Vec<Table>>(remove);
+        }
+        self.levels[level + 1].extend(tables);
+        self.levels[level + 1].sort_by(|a, b| a.first_key().cmp(b.first_key()));
+        self.write_manifest()?;
+        for table in removed {
+            std::fs::remove_file(&table.path)?;
+        }
+        Ok(())
+    }
+
+    /// Writes merged entries to new tables, starting a new table once one
+    /// exceeds the maximum table size. Returns the new tables.
+    fn write_tables<'a>(
+        path: &Path,
+        next_id: &mut TableID,
+        max_table_size: u64,
+        entries: impl Iterator<Item = Result<Entry>> + 'a,
+    ) -> Result<Vec<Table>> {
+        let mut tables = Vec::new();
+        let mut builder: Option<TableBuilder> = None;
+        for entry in entries {
+            let (key, value) = entry?;
+            if builder.as_ref().is_some_and(|b| b.pos >= max_table_size) {
+                tables.push(builder.take().expect("no builder").finish()?);
+            }
+            if builder.is_none() {
+                builder = Some(TableBuilder::new(*next_id, Self::table_path(path, *next_id))?);
+                *next_id += 1;
+            }
+            builder.as_mut().expect("no builder").add(&key, value.as_deref())?;
+        }
+        if let Some(builder) = builder {
+            tables.push(builder.finish()?);
+        }
+        Ok(tables)
+    }
+
+    /// Compacts the entire database by flushing the memtable and merging all
+    /// tables into the bottom level, removing replaced values and tombstones.
+    pub fn compact(&mut self) -> Result<()> {
+        self.flush_memtable()?;
+        let sources = self
+            .levels
+            .iter()
+            .enumerate()
+            .flat_map(|(level, tables)| -> Box<dyn Iterator<Item = &Table>> {
+                match level {
+                    0 => Box::new(tables.iter().rev()), // newest first
+                    _ => Box::new(tables.iter()),
+                }
+            })
+            .map(|t| Box::new(t.scan(..)) as EntryIterator)
+            .collect();
+        let merged = MergeIterator::new(sources, false);
+        let mut next_id = self.next_id;
+        let tables = Self::write_tables(&self.path, &mut next_id, self.max_table_size, merged)?;
+        self.next_id = next_id;
+
+        let bottom = self.levels.len().max(2) - 1;
+        let mut levels = Vec::new();
+        levels.resize_with(bottom + 1, Vec::new);
+        levels[bottom] = tables;
+        let removed = std::mem::replace(&mut self.levels, levels);
+        self.write_manifest()?;
+        for table in removed.into_iter().flatten() {
+            std::fs::remove_file(&table.path)?;
+        }
+        Ok(())
+    }
+
+    /// Returns entry iterators for the memtable and all tables overlapping the
+    /// range, newest first.
+    fn sources(&self, range: (Bound<Vec<u8>>, Bound<Vec<u8>>)) -> Vec<EntryIterator<'_>> {
+        let memtable = self.memtable.range(range.clone()).map(|(k, v)| Ok((k.clone(), v.clone())));
+        let mut sources: Vec<EntryIterator> = vec![Box::new(memtable)];
+        for (level, tables) in self.levels.iter().enumerate() {
+            let tables: Box<dyn Iterator<Item = &Table>> = match level {
+                0 => Box::new(tables.iter().rev()), // newest first
+                _ => Box::new(tables.iter()),
+            };
+            for table in tables.filter(|t| t.overlaps(&range)) {
+                sources.push(Box::new(table.scan(range.clone())));
+            }
+        }
+        sources
+    }
+}
+
+impl ReadEngine for Lsm {
+    fn get_shared(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        if let Some(value) = self.memtable.get(key) {
+            return Ok(value.clone());
+        }
+        for table in self.levels[0].iter().rev() {
+            if let Some(value) = table.get(key)? {
+                return Ok(value);
+            }
+        }
+        // Lower levels don't overlap, so only one table can contain the key.
+        for tables in &self.levels[1..] {
+            let i = tables.partition_point(|t| t.first_key() <= key);
+            if let Some(table) = i.checked_sub(1).map(|i| &tables[i]) {
+                if let Some(value) = table.get(key)? {
+                    return Ok(value);
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    fn scan_shared(
+        &self,
+        range: (Bound<Vec<u8>>, Bound<Vec<u8>>),
+    ) -> Box<dyn super::ScanIterator + '_> {
+        Box::new(ScanIterator { inner: MergeIterator::new(self.sources(range), false) })
+    }
+}
+
+impl Engine for Lsm {
+    type ScanIterator<'a> = ScanIterator<'a>;
+
+    fn delete(&mut self, key: &[u8]) -> Result<()> {
+        let mut buf = Vec::new();
+        encode_entry(&mut buf, key, None);
+        self.append(&buf, [(key.to_vec(), None)])
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        // Don't fsync in tests, to speed them up. Tables are synced when
+        // they're written, so only the WAL has unsynced writes.
+        #[cfg(not(test))]
+        self.wal.sync_all()?;
+        Ok(())
+    }
+
+    fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.get_shared(key)
+    }
+
+    fn maybe_compact(&mut self, garbage_min_fraction: f64, garbage_min_bytes: u64) -> Result<bool> {
+        let status = self.status()?;
+        let garbage_fraction = status.garbage_disk_size as f64 / status.total_disk_size as f64;
+        if status.garbage_disk_size == 0
+            || status.garbage_disk_size < garbage_min_bytes
+            || garbage_fraction < garbage_min_fraction
+        {
+            return Ok(false);
+        }
+        log::info!(
+            "Compacting {} to remove {:.0}% garbage ({} MB out of {} MB)",
+            self.path.display(),
+            status.garbage_percent(),
+            status.garbage_disk_size / 1024 / 1024,
+            status.total_disk_size / 1024 / 1024
+        );
+        self.compact()?;
+        let total_disk_size = self.status()?.total_disk_size;
+        log::info!(
+            "Compacted {} to size {} MB",
+            self.path.display(),
+            total_disk_size / 1024 / 1024
+        );
+        Ok(true)
+    }
+
+    fn scan(&mut self, range: impl RangeBounds<Vec<u8>>) -> Self::ScanIterator<'_> {
+        let range = (range.start_bound().cloned(), range.end_bound().cloned());
+        ScanIterator { inner: MergeIterator::new(self.sources(range), false) }
+    }
+
+    fn scan_dyn(
+        &mut self,
+        range: (Bound<Vec<u8>>, Bound<Vec<u8>>),
+    ) -> Box<dyn super::ScanIterator + '_> {
+        Box::new(self.scan(range))
+    }
+
+    fn set(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        let mut buf = Vec::new();
+        encode_entry(&mut buf, key, Some(&value));
+        self.append(&buf, [(key.to_vec(), Some(value))])
+    }
+
+    fn status(&mut self) -> Result<Status> {
+        let (mut keys, mut size) = (0, 0);
+        for item in self.scan(..) {
+            let (key, value) = item?;
+            keys += 1;
+            size += key.len() as u64 + value.len() as u64;
+        }
+        let tables = self.levels.iter().flatten();
+        let tables_size = tables.clone().map(|t| t.size).sum::<u64>();
+        let tables_index_size = tables.clone().map(|t| t.size - t.blocks_end).sum::<u64>();
+        let index_memory_size = tables
+            .flat_map(|t| &t.index)
+            .map(|(key, _)| key.len() as u64 + Table::INDEX_ENTRY_OVERHEAD)
+            .sum();
+        let total_disk_size = self.wal_size + tables_size;
+        // Account for length prefixes, and consider table indexes live.
+        let live_disk_size = size + 8 * keys + tables_index_size;
+        let garbage_disk_size = total_disk_size.saturating_sub(live_disk_size);
+        Ok(Status {
+            name: "lsm".to_string(),
+            keys,
+            size,
+            total_disk_size,
+            live_disk_size,
+            garbage_disk_size,
+            index_memory_size,
+        })
+    }
+
+    fn write_batch(&mut self, batch: WriteBatch) -> Result<()> {
+        if batch.len() <= 1 {
+            for (key, value) in batch {
+                match value {
+                    Some(value) => self.set(&key, value)?,
+                    None => self.delete(&key)?,
+                }
+            }
+            return Ok(());
+        }
+        let mut buf = vec![0; 8];
+        for (key, value) in &batch {
+            encode_entry(&mut buf, key, value.as_deref());
+        }
+        let Ok(batch_len) = u32::try_from(buf.len() - 8) else {
+            return crate::errinput!("write batch too large");
+        };
+        buf[0..4].copy_from_slice(&batch_len.to_be_bytes());
+        buf[4..8].copy_from_slice(&BATCH_HEADER.to_be_bytes());
+        self.append(&buf, batch)
+    }
+}
+
+/// Attempt to flush the WAL when the database is closed.
+impl Drop for Lsm {
+    fn drop(&mut self) {
+        if let Err(error) = self.flush() {
+            log::error!("failed to flush WAL: {}", error)
+        }
+    }
+}
+
+/// An LSM scan iterator, merging the memtable and tables.
+pub struct ScanIterator<'a> {
+    inner: MergeIterator<'a>,
+}
+
+impl Iterator for ScanIterator<'_> {
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|r| r.map(|(key, value)| (key, value.unwrap_or_default())))
+    }
+}
+
+impl DoubleEndedIterator for ScanIterator<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back().map(|r| r.map(|(key, value)| (key, value.unwrap_or_default())))
+    }
+}
+
+/// An iterator over entries, including tombstones.
+type EntryIterator<'a> = Box<dyn DoubleEndedIterator<Item = Result<Entry>> + 'a>;
+
+/// Merges ordered entry iterators into a single ordered iterator. Sources are
+/// given newest first, and an entry shadows entries with the same key in later
+/// sources. Tombstones are omitted, unless they're kept for compactions that
+/// don't write to the bottom level.
+struct MergeIterator<'a> {
+    /// The sources, newest first.
+    sources: Vec<MergeSource<'a>>,
+    /// If true, emit tombstones.
+    keep_tombstones: bool,
+}
+
+/// A merge source, with its next entries from the front and back.
+struct MergeSource<'a> {
+    iter: EntryIterator<'a>,
+    front: Option<Entry>,
+    back: Option<Entry>,
+}
+
+impl<'a> MergeIterator<'a> {
+    fn new(sources: Vec<EntryIterator<'a>>, keep_tombstones: bool) -> Self {
+        let sources =
+            sources.into_iter().map(|iter| MergeSource { iter, front: None, back: None }).collect();
+        Self { sources, keep_tombstones }
+    }
+
+    /// Returns the next entry from the front (or back, if reverse is true).
+    fn try_next(&mut self, reverse: bool) -> Result<Option<Entry>> {
+        loop {
+            // Peek at each source's next entry. Once a source is exhausted from
+            // one end, its last entry may be buffered at the other end.
+            for source in &mut self.sources {
+                let (next, other) = match reverse {
+                    false => (&mut source.front, &mut source.back),
+                    true => (&mut source.back, &mut source.front),
+                };
+                if next.is_none() {
+                    let entry = match reverse {
+                        false => source.iter.next(),
+                        true => source.iter.next_back(),
+                    };
+                    *next = entry.transpose()?.or_else(|| other.take());
+                }
+            }
+
+            // Pick the first (or last) key, preferring the newest source, and
+            // skip that key in older sources.
+            let mut pick: Option<usize> = None;
+            for (i, source) in self.sources.iter().enumerate() {
+                let Some(key) = source.peek(reverse) else { continue };
+                if pick.is_none_or(|p| {
+                    let pick_key = self.sources[p].peek(reverse).expect("no key");
+                    if reverse {
+                        key > pick_key
+                    } else {
+                        key < pick_key
+                    }
+                }) {
+                    pick = Some(i);
+                }
+            }
+            let Some(pick) = pick else {
+                return Ok(None);
+            };
+            let (key, value) = self.sources[pick].take(reverse).expect("no entry");
+            for source in &mut self.sources[pick + 1..] {
+                if source.peek(reverse) == Some(key.as_slice()) {
+                    source.take(reverse);
+                }
+            }
+            if value.is_some() || self.keep_tombstones {
+                return Ok(Some((key, value)));
+            }
+        }
+    }
+}
+
+impl MergeSource<'_> {
+    /// Returns the buffered key at the front (or back, if reverse is true).
+    fn peek(&self, reverse: bool) -> Option<&[u8]> {
+        match reverse {
+            false => self.front.as_ref().map(|(key, _)| key.as_slice()),
+            true => self.back.as_ref().map(|(key, _)| key.as_slice()),
+        }
+    }
+
+    /// Takes the buffered entry at the front (or back, if reverse is true).
+    fn take(&mut self, reverse: bool) -> Option<Entry> {
+        match reverse {
+            false => self.front.take(),
+            true => self.back.take(),
+        }
+    }
+}
+
+impl Iterator for MergeIterator<'_> {
+    type Item = Result<Entry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.try_next(false).transpose()
+    }
+}
+
+impl DoubleEndedIterator for MergeIterator<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.try_next(true).transpose()
+    }
+}
+
+/// An immutable sorted string table (SSTable) file, containing key-ordered
+/// entries in blocks, followed by a block index and footer:
+///
+/// - Blocks: entries in key order, using the WAL entry encoding. A new block
+///   is started once a block exceeds BLOCK_SIZE.
+/// - Index: for each block, its first key length as big-endian u32, the first
+///   key as raw bytes, and the block position as big-endian u64.
+/// - Last key: the last key length as big-endian u32, and the last key.
+/// - Footer: the index position and block count, as big-endian u64.
+struct Table {
+    /// The table ID.
+    id: TableID,
+    /// The table file path.
+    path: PathBuf,
+    /// The table file, read with positional reads.
+    file: std::fs::File,
+    /// The first key and position of each block.
+    index: Vec<(Vec<u8>, u64)>,
+    /// The last key in the table.
+    last_key: Vec<u8>,
+    /// The end of the blocks, i.e. the position of the index.
+    blocks_end: u64,
+    /// The file size.
+    size: u64,
+}
+
+impl Table {
+    /// The estimated in-memory overhead of an index entry, in addition to the
+    /// key bytes: the key vector and block position.
+    const INDEX_ENTRY_OVERHEAD: u64 = 32;
+
+    /// Opens a table file, reading its index.
+    fn open(id: TableID, path: PathBuf) -> Result<Self> {
+        let file = std::fs::File::open(&path)?;
+        let size = file.metadata()?.len();
+        if size < 16 {
+            return errdata!("table {} too short", path.display());
+        }
+        let mut footer = [0; 16];
+        file.read_exact_at(&mut footer, size - 16)?;
+        let blocks_end = u64::from_be_bytes(footer[0..8].try_into()?);
+        let block_count = u64::from_be_bytes(footer[8..16].try_into()?);
+        if blocks_end > size - 16 {
+            return errdata!("invalid index position in table {}", path.display());
+        }
+
+        let mut buf = vec![0; (size - 16 - blocks_end) as usize];
+        file.read_exact_at(&mut buf, blocks_end)?;
+        let mut r = buf.as_slice();
+        let read_key = |r: &mut &[u8]| -> std::io::Result<Vec<u8>> {
+            let mut len = [0; 4];
+            r.read_exact(&mut len)?;
+            let mut key = vec![0; u32::from_be_bytes(len) as usize];
+            r.read_exact(&mut key)?;
+            Ok(key)
+        };
+        let mut index = Vec::with_capacity(block_count as usize);
+        for _ in 0..block_count {
+            let key = read_key(&mut r)?;
+            let mut pos = [0; 8];
+            r.read_exact(&mut pos)?;
+            index.push((key, u64::from_be_bytes(pos)));
+        }
+        let last_key = read_key(&mut r)?;
+        if index.is_empty() {
+            return errdata!("table {} has no blocks", path.display());
+        }
+        Ok(Self { id, path, file, index, last_key, blocks_end, size })
+    }
+
+    /// Returns the first key in the table.
+    fn first_key(&self) -> &[u8] {
+        &self.index[0].0
+    }
+
+    /// Returns true if the table's key range overlaps the given range.
+    fn overlaps(&self, range: &(Bound<Vec<u8>>, Bound<Vec<u8>>)) -> bool {
+        let after_start = match &range.0 {
+            Bound::Included(start) => self.last_key >= *start,
+            Bound::Excluded(start) => self.last_key > *start,
+            Bound::Unbounded => true,
+        };
+        let before_end = match &range.1 {
+            Bound::Included(end) => self.first_key() <= end.as_slice(),
+            Bound::Excluded(end) => self.first_key() < end.as_slice(),
+            Bound::Unbounded => true,
+        };
+        after_start && before_end
+    }
+
+    /// Reads a block's entries.
+    fn read_block(&self, block: usize) -> Result<Vec<Entry>> {
+        let start = self.index[block].1;
+        let end = self.index.get(block + 1).map(|(_, pos)| *pos).unwrap_or(self.blocks_end);
+        let mut buf = vec![0; (end - start) as usize];
+        self.file.read_exact_at(&mut buf, start)?;
+        Ok(decode_entries(&buf)?)
+    }
+
+    /// Fetches a key's entry, if the table contains it. Returns Some(None) for
+    /// tombstones.
+    fn get(&self, key: &[u8]) -> Result<Option<Option<Vec<u8>>>> {
+        if key < self.first_key() || key > self.last_key.as_slice() {
+            return Ok(None);
+        }
+        let block = self.index.partition_point(|(first, _)| first.as_slice() <= key) - 1;
+        let entry = self.read_block(block)?.into_iter().find(|(k, _)| k == key);
+        Ok(entry.map(|(_, value)| value))
+    }
+
+    /// Iterates over the table's entries in the given range.
+    fn scan(&self, range: impl RangeBounds<Vec<u8>>) -> TableIterator<'_> {
+        let range = (range.start_bound().cloned(), range.end_bound().cloned());
+        let start = match &range.0 {
+            Bound::Included(key) | Bound::Excluded(key) => {
+                self.index.partition_point(|(first, _)| first <= key).saturating_sub(1)
+            }
+            Bound::Unbounded => 0,
+        };
+        let end = match &range.1 {
+            Bound::Included(key) | Bound::Excluded(key) => {
+                self.index.partition_point(|(first, _)| first <= key)
+            }
+            Bound::Unbounded => self.index.len(),
+        };
+        let blocks = start..end.max(start);
+        TableIterator { table: self, range, blocks, front: VecDeque::new(), back: VecDeque::new() }
+    }
+}
+
+/// An iterator over a table's entries in a range. It reads blocks as needed
+/// from either end.
+struct TableIterator<'a> {
+    table: &'a Table,
+    range: (Bound<Vec<u8>>, Bound<Vec<u8>>),
+    /// The blocks that haven't been read yet.
+    blocks: std::ops::Range<usize>,
+    /// Entries read from the front.
+    front: VecDeque<Entry>,
+    /// Entries read from the back.
+    back: VecDeque<Entry>,
+}
+
+impl TableIterator<'_> {
+    /// Reads a block's entries in the range.
+    fn read_block(&self, block: usize) -> Result<VecDeque<Entry>> {
+        let entries = self.table.read_block(block)?;
+        Ok(entries.into_iter().filter(|(key, _)| self.range.contains(key)).collect())
+    }
+}
+
+impl Iterator for TableIterator<'_> {
+    type Item = Result<Entry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.front.is_empty() {
+            let Some(block) = self.blocks.next() else {
+                return self.back.pop_front().map(Ok);
+            };
+            match self.read_block(block) {
+                Ok(entries) => self.front = entries,
+                Err(err) => return Some(Err(err)),
+            }
+        }
+        self.front.pop_front().map(Ok)
+    }
+}
+
+impl DoubleEndedIterator for TableIterator<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        while self.back.is_empty() {
+            let Some(block) = self.blocks.next_back() else {
+                return self.front.pop_back().map(Ok);
+            };
+            match self.read_block(block) {
+                Ok(entries) => self.back = entries,
+                Err(err) => return Some(Err(err)),
+            }
+        }
+        self.back.pop_back().map(Ok)
+    }
+}
+
+/// Writes a new table file. Entries must be added in key order.
+struct TableBuilder {
+    id: TableID,
+    path: PathBuf,
+    writer: BufWriter<std::fs::File>,
+    /// The first key and position of each block.
+    index: Vec<(Vec<u8>, u64)>,
+    /// The last key written.
+    last_key: Vec<u8>,
+    /// The position of the current block.
+    block_pos: u64,
+    /// The position of the next entry.
+    pos: u64,
+}
+
+impl TableBuilder {
+    /// Creates a new table file, truncating it if it exists.
+    fn new(id: TableID, path: PathBuf) -> Result<Self> {
+        let writer = BufWriter::new(std::fs::File::create(&path)?);
+        Ok(Self { id, path, writer, index: Vec::new(), last_key: Vec::new(), block_pos: 0, pos: 0 })
+    }
+
+    /// Adds an entry, using None for tombstones.
+    fn add(&mut self, key: &[u8], value: Option<&[u8]>) -> Result<()> {
+        if self.index.is_empty() || self.pos - self.block_pos >= BLOCK_SIZE {
+            self.index.push((key.to_vec(), self.pos));
+            self.block_pos = self.pos;
+        }
+        let mut buf = Vec::new();
+        encode_entry(&mut buf, key, value);
+        self.writer.write_all(&buf)?;
+        self.pos += buf.len() as u64;
+        self.last_key = key.to_vec();
+        Ok(())
+    }
+
+    /// Writes the index and footer, and opens the finished table.
+    fn finish(mut self) -> Result<Table> {
+        let blocks_end = self.pos;
+        for (key, pos) in &self.index {
+            self.writer.write_all(&(key.len() as u32).to_be_bytes())?;
+            self.writer.write_all(key)?;
+            self.writer.write_all(&pos.to_be_bytes())?;
+        }
+        self.writer.write_all(&(self.last_key.len() as u32).to_be_bytes())?;
+        self.writer.write_all(&self.last_key)?;
+        self.writer.write_all(&blocks_end.to_be_bytes())?;
+        self.writer.write_all(&(self.index.len() as u64).to_be_bytes())?;
+        let file = self.writer.into_inner().map_err(|err| err.into_error())?;
+        #[cfg(not(test))]
+        file.sync_all()?;
+        drop(file);
+        Table::open(self.id, self.path)
+    }
+}
+
+/// Encodes a key/value entry into the buffer, using a None value for
+/// tombstones.
+fn encode_entry(buf: &mut Vec<u8>, key: &[u8], value: Option<&[u8]>) {
+    let key_len = key.len() as u32;
+    let value_len_or_tombstone = value.map_or(-1, |v| v.len() as i32);
+    buf.reserve(8 + key.len() + value.map_or(0, |v| v.len()));
+    buf.extend_from_slice(&key_len.to_be_bytes());
+    buf.extend_from_slice(&value_len_or_tombstone.to_be_bytes());
+    buf.extend_from_slice(key);
+    if let Some(value) = value {
+        buf.extend_from_slice(value);
+    }
+}
+
+/// Reads an entry header, returning the key length and value length (-1 for
+/// tombstones, -2 for batch headers).
+fn read_entry_header(r: &mut impl Read) -> std::io::Result<(u32, i32)> {
+    let mut header = [0; 8];
+    r.read_exact(&mut header)?;
+    let key_len = u32::from_be_bytes(header[0..4].try_into().expect("invalid length"));
+    let value_len = i32::from_be_bytes(header[4..8].try_into().expect("invalid length"));
+    Ok((key_len, value_len))
+}
+
+/// Reads an entry's key and value, given its header.
+fn read_entry(r: &mut impl Read, key_len: u32, value_len: i32) -> std::io::Result<Entry> {
+    let mut key = vec![0; key_len as usize];
+    r.read_exact(&mut key)?;
+    let value = match value_len {
+        -1 => None,
+        len if len >= 0 => {
+            let mut value = vec![0; len as usize];
+            r.read_exact(&mut value)?;
+            Some(value)
+        }
+        len => {
+            let msg = format!("invalid value length {len}");
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, msg));
+        }
+    };
+    Ok((key, value))
+}
+
+/// Decodes a buffer of complete entries, e.g. a table block or WAL batch.
+fn decode_entries(mut buf: &[u8]) -> std::io::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    while !buf.is_empty() {
+        let (key_len, value_len) = read_entry_header(&mut buf)?;
+        entries.push(read_entry(&mut buf, key_len, value_len)?);
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::engine::test::Runner;
+    use super::*;
+    use crate::encoding::format::{self, Formatter as _};
+
+    use std::fmt::Write as _;
+    use std::{error::Error as StdError, result::Result as StdResult};
+    use test_each_file::test_each_path;
+
+    // Run common goldenscript tests in src/storage/testscripts/engine. These
+    // use small memtables and tables, to exercise flushes and compactions.
+    test_each_path! { in "src/storage/testscripts/engine" as engine => test_goldenscript }
+
+    // Also run LSM-specific tests in src/storage/testscripts/lsm.
+    test_each_path! { in "src/storage/testscripts/lsm" as scripts => test_goldenscript }
+
+    fn test_goldenscript(path: &std::path::Path) {
+        goldenscript::run(&mut LsmRunner::new(), path).expect("goldenscript failed")
+    }
+
+    /// Tests that exclusive locks are taken out on the lock file, erroring if
+    /// held, and released when the database is closed.
+    #[test]
+    fn lock() -> Result<()> {
+        let path = tempfile::TempDir::with_prefix("toydb")?.path().join("lsm");
+        let engine = Lsm::new(path.clone()).expect("lsm failed");
+
+        // Opening another database with the same file should error.
+        assert!(Lsm::new(path.clone()).is_err());
+
+        // Opening another database after the current is closed works.
+        drop(engine);
+        assert!(Lsm::new(path).is_ok());
+        Ok(())
+    }
+
+    /// Tests that a WAL with an incomplete write at the end can be recovered
+    /// by discarding the last entry or batch.
+    #[test]
+    fn recovery() -> Result<()> {
+        let dir = tempfile::TempDir::with_prefix("toydb")?;
+        let path = dir.path().join("complete");
+        let mut engine = Lsm::new(path.clone())?;
+        engine.set(b"deleted", vec![1, 2, 3])?;
+        engine.delete(b"deleted")?;
+        engine.set(b"", vec![])?;
+        let mut batch = WriteBatch::new();
+        batch.set(b"a", vec![1]).delete(b"").set(b"b", vec![2]);
+        engine.write_batch(batch)?;
+        drop(engine);
+
+        // The entries end at these WAL positions.
+        let ends = [18, 33, 41, 41 + 8 + 10 + 8 + 10];
+
+        // Truncate the WAL at each byte, then open it and assert that we
+        // always retain a prefix of entries.
+        let wal = Lsm::path_with_suffix(&path, "wal");
+        let size = std::fs::metadata(&wal)?.len();
+        assert_eq!(size, ends[3]);
+        for pos in 0..=size {
+            let truncpath = dir.path().join(format!("truncated{pos}"));
+            let truncwal = Lsm::path_with_suffix(&truncpath, "wal");
+            std::fs::copy(&wal, &truncwal)?;
+            std::fs::OpenOptions::new().write(true).open(&truncwal)?.set_len(pos)?;
+
+            let mut expect = vec![];
+            if pos >= ends[0] {
+                expect.push((b"deleted".to_vec(), vec![1, 2, 3]))
+            }
+            if pos >= ends[1] {
+                expect.pop(); // "deleted" key removed
+            }
+            if pos >= ends[2] {
+                expect.push((b"".to_vec(), vec![]))
+            }
+            if pos >= ends[3] {
+                expect = vec![(b"a".to_vec(), vec![1]), (b"b".to_vec(), vec![2])];
+            }
+            let mut engine = Lsm::new(truncpath)?;
+            assert_eq!(expect, engine.scan(..).collect::<Result<Vec<_>>>()?);
+            assert_eq!(
+                engine.wal_size,
+                ends.iter().copied().filter(|end| *end <= pos).max().unwrap_or(0)
+            );
+        }
+        Ok(())
+    }
+
+    /// Tests that table files that aren't in the manifest are removed when
+    /// the database is opened, and that missing tables error.
+    #[test]
+    fn orphans() -> Result<()> {
+        let dir = tempfile::TempDir::with_prefix("toydb")?;
+        let path = dir.path().join("lsm");
+        let mut engine = Lsm::new(path.clone())?;
+        engine.set(b"a", vec![1])?;
+        engine.flush_memtable()?;
+        drop(engine);
+
+        // Orphaned tables are removed, but unrelated files aren't.
+        let orphan = Lsm::table_path(&path, 2);
+        let other = Lsm::path_with_suffix(&path, "bak.sst");
+        for file in [&orphan, &other] {
+            std::fs::write(file, b"foo")?;
+        }
+        let mut engine = Lsm::new(path.clone())?;
+        assert_eq!(engine.get(b"a")?, Some(vec![1]));
+        assert!(!orphan.exists());
+        assert!(other.exists());
+        drop(engine);
+
+        // Missing tables error.
+        std::fs::remove_file(Lsm::table_path(&path, 1))?;
+        assert!(Lsm::new(path).is_err());
+        Ok(())
+    }
+
+    /// Tests key/value sizes up to 16 MB, across flushes and compactions.
+    #[test]
+    fn point_ops_sizes() -> Result<()> {
+        let path = tempfile::TempDir::with_prefix("toydb")?.path().join("lsm");
+        let mut engine = Lsm::new(path.clone()).expect("lsm failed");
+        engine.set_max_memtable_size(1024 * 1024);
+        engine.set_max_table_size(1024 * 1024);
+
+        // Generate keys/values for increasing powers of two.
+        for size in (1..=24).map(|i| 1 << i) {
+            let value = vec![b'x'; size];
+            let key = value.as_slice();
+
+            assert_eq!(engine.get(key)?, None);
+            engine.set(key, value.clone())?;
+            assert_eq!(engine.get(key)?.as_ref(), Some(&value));
+            engine.flush_memtable()?;
+            assert_eq!(engine.get(key)?.as_ref(), Some(&value));
+            engine.delete(key)?;
+            assert_eq!(engine.get(key)?, None);
+        }
+        engine.compact()?;
+        assert_eq!(engine.scan(..).count(), 0);
+        Ok(())
+    }
+
+    /// Tests that many writes across flushes and level compactions, in both
+    /// directions, match a BTreeMap.
+    #[test]
+    fn random_ops() -> Result<()> {
+        use rand::{Rng as _, SeedableRng as _};
+
+        let path = tempfile::TempDir::with_prefix("toydb")?.path().join("lsm");
+        let mut engine = Lsm::new(path.clone())?;
+        engine.set_max_memtable_size(200);
+        engine.set_max_table_size(100);
+        let mut expect = BTreeMap::new();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+        for i in 0..5000u32 {
+            let key = vec![rng.gen_range(0..200u8)];
+            if rng.gen_bool(0.3) {
+                engine.delete(&key)?;
+                expect.remove(&key);
+            } else {
+                engine.set(&key, i.to_be_bytes().to_vec())?;
+                expect.insert(key, i.to_be_bytes().to_vec());
+            }
+        }
+        assert!(engine.levels.len() > 2, "expected multiple levels");
+        let expect: Vec<_> = expect.into_iter().collect();
+        assert_eq!(engine.scan(..).collect::<Result<Vec<_>>>()?, expect);
+        let reverse: Vec<_> = expect.iter().rev().cloned().collect();
+        assert_eq!(engine.scan(..).rev().collect::<Result<Vec<_>>>()?, reverse);
+        for (key, value) in &expect {
+            assert_eq!(engine.get(key)?.as_ref(), Some(value));
+        }
+
+        // Reopening the database retains the data.
+        drop(engine);
+        let mut engine = Lsm::new(path)?;
+        assert_eq!(engine.scan(..).collect::<Result<Vec<_>>>()?, expect);
+        Ok(())
+    }
+
+    /// An LSM-specific goldenscript runner, which dispatches through to the
+    /// standard Engine runner.
+    struct LsmRunner {
+        inner: Runner<Lsm>,
+        tempdir: tempfile::TempDir,
+    }
+
+    impl goldenscript::Runner for LsmRunner {
+        fn run(&mut self, command: &goldenscript::Command) -> StdResult<String, Box<dyn StdError>> {
+            let mut output = String::new();
+            match command.name.as_str() {
+                // compact
+                // Fully compacts the database into the bottom level.
+                "compact" => {
+                    command.consume_args().reject_rest()?;
+                    self.inner.engine.compact()?;
+                }
+
+                // flush_memtable
+                // Flushes the memtable to a level 0 table.
+                "flush_memtable" => {
+                    command.consume_args().reject_rest()?;
+                    self.inner.engine.flush_memtable()?;
+                }
+
+                // levels
+                // Lists the memtable and the tables in each level, with their
+                // key ranges and sizes.
+                "levels" => {
+                    command.consume_args().reject_rest()?;
+                    let engine = &self.inner.engine;
+                    let manifest = Lsm::read_manifest(&engine.path)?;
+                    let tables: Vec<_> = engine
+                        .levels
+                        .iter()
+                        .enumerate()
+                        .flat_map(|(level, tables)| tables.iter().map(move |t| (level, t.id)))
+                        .collect();
+                    assert_eq!(manifest, tables);
+                    writeln!(
+                        output,
+                        "memtable: {} keys, {} bytes WAL",
+                        engine.memtable.len(),
+                        engine.wal_size
+                    )?;
+                    for (level, tables) in engine.levels.iter().enumerate() {
+                        for table in tables {
+                            writeln!(
+                                output,
+                                "L{level}: table {} {}..{} {} bytes, {} blocks",
+                                table.id,
+                                format::Raw::key(table.first_key()),
+                                format::Raw::key(&table.last_key),
+                                table.size,
+                                table.index.len(),
+                            )?;
+                        }
+                    }
+                }
+
+                // max_memtable_size SIZE
+                // Sets the maximum memtable size in bytes of WAL.
+                "max_memtable_size" => {
+                    let mut args = command.consume_args();
+                    let size = args.next_pos().ok_or("size not given")?.parse()?;
+                    args.reject_rest()?;
+                    self.inner.engine.set_max_memtable_size(size);
+                }
+
+                // max_table_size SIZE
+                // Sets the maximum table size in bytes.
+                "max_table_size" => {
+                    let mut args = command.consume_args();
+                    let size = args.next_pos().ok_or("size not given")?.parse()?;
+                    args.reject_rest()?;
+                    self.inner.engine.set_max_table_size(size);
+                }
+
+                // maybe_compact [fraction=FLOAT] [min_bytes=INT]
+                // Compacts the database if the garbage exceeds the given
+                // fraction (default 0) and bytes (default 0).
+                "maybe_compact" => {
+                    let mut args = command.consume_args();
+                    let fraction = args.lookup_parse("fraction")?.unwrap_or(0.0);
+                    let min_bytes = args.lookup_parse("min_bytes")?.unwrap_or(0);
+                    args.reject_rest()?;
+                    let compacted = self.inner.engine.maybe_compact(fraction, min_bytes)?;
+                    writeln!(output, "{}", if compacted { "compacted" } else { "not compacted" })?;
+                }
+
+                // reopen
+                // Closes and reopens the database.
+                "reopen" => {
+                    command.consume_args().reject_rest()?;
+                    // We need to close the files before we can reopen them,
+                    // which happens when the database is dropped. Replace the
+                    // engine with a temporary empty engine then reopen it.
+                    let path = self.inner.engine.path.clone();
+                    let max_memtable_size = self.inner.engine.max_memtable_size;
+                    let max_table_size = self.inner.engine.max_table_size;
+                    self.inner.engine = Lsm::new(self.tempdir.path().join("empty"))?;
+                    self.inner.engine = Lsm::new(path)?;
+                    self.inner.engine.set_max_memtable_size(max_memtable_size);
+                    self.inner.engine.set_max_table_size(max_table_size);
+                }
+
+                // Pass other commands to the standard engine runner.
+                _ => return self.inner.run(command),
+            }
+            Ok(output)
+        }
+    }
+
+    impl LsmRunner {
+        fn new() -> Self {
+            let tempdir = tempfile::TempDir::with_prefix("toydb").expect("tempdir failed");
+            let mut engine = Lsm::new(tempdir.path().join("lsm")).expect("lsm failed");
+            engine.set_max_memtable_size(50);
+            engine.set_max_table_size(100);
+            let inner = Runner::new(engine);
+            Self { inner, tempdir }
+        }
+    }
+}
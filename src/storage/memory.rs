@@ -1,4 +1,4 @@
-use super::{Engine, Status};
+use super::{Engine, ReadEngine, Status, WriteBatch};
 use crate::error::Result;
 
 /// An in-memory key/value storage engine using the Rust standard library B-tree
@@ -14,6 +14,26 @@ impl Memory {
     }
 }
 
+impl ReadEngine for Memory {
+    fn get_shared(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.data.get(key).cloned())
+    }
+
+    fn scan_shared(
+        &self,
+        range: (std::ops::Bound<Vec<u8>>, std::ops::Bound<Vec<u8>>),
+    ) -> Box<dyn super::ScanIterator + '_> {
+        Box::new(ScanIterator { inner: self.data.range(range) })
+    }
+
+    fn scan_keys_shared(
+        &self,
+        range: (std::ops::Bound<Vec<u8>>, std::ops::Bound<Vec<u8>>),
+    ) -> Box<dyn super::KeyScanIterator + '_> {
+        Box::new(self.data.range(range).map(|(key, value)| Ok((key.clone(), value.len()))))
+    }
+}
+
 impl Engine for Memory {
     type ScanIterator<'a> = ScanIterator<'a>;
 
@@ -27,7 +47,7 @@ impl Engine for Memory {
     }
 
     fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
-        Ok(self.data.get(key).cloned())
+        self.get_shared(key)
     }
 
     fn scan(&mut self, range: impl std::ops::RangeBounds<Vec<u8>>) -> Self::ScanIterator<'_> {
@@ -42,6 +62,13 @@ impl Engine for Memory {
         Box::new(self.scan(range))
     }
 
+    fn scan_keys(
+        &mut self,
+        range: (std::ops::Bound<Vec<u8>>, std::ops::Bound<Vec<u8>>),
+    ) -> Box<dyn super::KeyScanIterator + '_> {
+        Box::new(self.data.range(range).map(|(key, value)| Ok((key.clone(), value.len()))))
+    }
+
     fn set(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
         self.data.insert(key.to_vec(), value);
         Ok(())
@@ -55,8 +82,19 @@ impl Engine for Memory {
             total_disk_size: 0,
             live_disk_size: 0,
             garbage_disk_size: 0,
+            index_memory_size: 0,
         })
     }
+
+    fn write_batch(&mut self, batch: WriteBatch) -> Result<()> {
+        for (key, value) in batch {
+            match value {
+                Some(value) => self.data.insert(key, value),
+                None => self.data.remove(&key),
+            };
+        }
+        Ok(())
+    }
 }
 
 pub struct ScanIterator<'a> {
@@ -1,11 +1,17 @@
 //! Key/value storage engines, including an MVCC transaction layer. For
-//! details, see the `engine`, `bitcask`, and `mvcc` module documentation.
+//! details, see the `engine`, `bitcask`, `lsm`, and `mvcc` module
+//! documentation.
 
+mod backup;
 mod bitcask;
+mod encrypted;
 pub mod engine;
+mod lsm;
 mod memory;
 pub mod mvcc;
 
 pub use bitcask::BitCask;
-pub use engine::{Engine, ScanIterator, Status};
+pub use encrypted::Encrypted;
+pub use engine::{Engine, KeyScanIterator, ReadEngine, ScanIterator, Status, WriteBatch};
+pub use lsm::Lsm;
 pub use memory::Memory;
@@ -88,6 +88,44 @@
 //! not see any of t2's writes, because it's still in its local snapshot of the
 //! active set at the time it began.
 //!
+//! SAVEPOINTS
+//! ==========
+//!
+//! A read-write transaction can create named savepoints, and later roll back
+//! to a savepoint to undo only the writes made after it. Since a transaction
+//! writes all keys at its own version, a later write replaces an earlier write
+//! of the same key in place. To undo it, the first write to each key after a
+//! savepoint records the key's previous value at the transaction's version (if
+//! any) as Key::TxnUndo(version, depth, key), where depth is the number of
+//! savepoints at the time. Rolling back to a savepoint restores these values,
+//! innermost savepoint first. The savepoint names are stored as a stack in
+//! Key::TxnSavepoints(version), since transactions are resumed from their
+//! TransactionState for each Raft request and can't keep them in memory.
+//!
+//! SERIALIZABILITY
+//! ===============
+//!
+//! Snapshot isolation permits write skew, where two concurrent transactions
+//! read overlapping data and then write to disjoint keys based on what they
+//! read (see the anomaly_write_skew test script). Read-write transactions can
+//! instead run with serializable isolation, which prevents this by detecting
+//! read-write conflicts when committing.
+//!
+//! A serializable transaction records the keys it reads as Key::TxnRead and the
+//! key ranges it scans as Key::TxnScan. When it commits, it checks whether any
+//! concurrent transaction has already committed a write to a key it read or
+//! to a range it scanned, i.e. whether a version that's invisible to it but no
+//! longer in the active set exists there. If so, its reads are stale and it
+//! rolls back with a serialization error instead of committing. Otherwise, it
+//! could have run at its commit time, so transactions are serializable in
+//! commit order. This is a conservative first-committer-wins variant of
+//! serializable snapshot isolation: it doesn't track full dependency graphs,
+//! so it can abort transactions that would have been serializable.
+//!
+//! Only serializable transactions record and check their reads. Snapshot
+//! isolation transactions can still cause anomalies with serializable ones,
+//! and read-only transactions always use snapshot isolation.
+//!
 //! READ-ONLY AND TIME TRAVEL QUERIES
 //! =================================
 //!
@@ -130,6 +168,17 @@
 //! current active set, storing the snapshot in memory only. Read-only queries
 //! do not increment the version sequence number in Key::NextVersion.
 //!
+//! WATCHES
+//! =======
+//!
+//! Callers can watch a key range for committed changes via MVCC::watch(), e.g.
+//! for change data capture or cache invalidation. When a transaction commits,
+//! its writes to watched keys are sent to the watchers' channels, along with
+//! its version. This happens while the commit holds the engine's write lock,
+//! so each transaction's changes are delivered atomically and in commit order,
+//! and a watch sees all commits after it was registered. Rolled back writes are
+//! never delivered.
+//!
 //! GARBAGE COLLECTION
 //! ==================
 //!
@@ -139,17 +188,18 @@
 //! forever, both out of laziness and also because it allows unlimited time
 //! travel queries (it's a feature, not a bug!).
 
-use super::engine::{self, Engine};
+use super::engine::{self, Engine, WriteBatch};
 use crate::encoding::{self, bincode, keycode, Key as _, Value as _};
 use crate::error::{Error, Result};
 use crate::{errdata, errinput};
 
+use crossbeam::channel::{Receiver, Sender};
 use itertools::Itertools as _;
 use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
 use std::collections::{BTreeSet, VecDeque};
 use std::ops::{Bound, RangeBounds};
-use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::{Arc, Mutex, RwLock};
 
 /// An MVCC version represents a logical timestamp. The latest version
 /// is incremented when beginning each read-write transaction.
@@ -193,6 +243,40 @@ pub enum Key<'a> {
         #[serde(borrow)]
         Cow<'a, [u8]>,
     ),
+    /// The savepoint names of an active transaction, innermost last. Only
+    /// written once the transaction creates a savepoint.
+    TxnSavepoints(Version),
+    /// The value of a key at an active transaction's version before it was
+    /// first written after the savepoint at the given depth (1-based), or None
+    /// if the transaction hadn't written the key. Used to roll back to the
+    /// savepoint.
+    TxnUndo(
+        Version,
+        u32,
+        #[serde(with = "serde_bytes")]
+        #[serde(borrow)]
+        Cow<'a, [u8]>,
+    ),
+    /// A key read by an active serializable transaction, for read-write
+    /// conflict detection when it commits.
+    TxnRead(
+        Version,
+        #[serde(with = "serde_bytes")]
+        #[serde(borrow)]
+        Cow<'a, [u8]>,
+    ),
+    /// A key range [start, end) scanned by an active serializable transaction,
+    /// for read-write conflict detection when it commits. An empty end is
+    /// unbounded.
+    TxnScan(
+        Version,
+        #[serde(with = "serde_bytes")]
+        #[serde(borrow)]
+        Cow<'a, [u8]>,
+        #[serde(with = "serde_bytes")]
+        #[serde(borrow)]
+        Cow<'a, [u8]>,
+    ),
 }
 
 impl<'a> encoding::Key<'a> for Key<'a> {}
@@ -211,6 +295,10 @@ enum KeyPrefix<'a> {
         Cow<'a, [u8]>,
     ),
     Unversioned,
+    TxnSavepoints,
+    TxnUndo(Version),
+    TxnRead(Version),
+    TxnScan(Version),
 }
 
 impl<'a> encoding::Key<'a> for KeyPrefix<'a> {}
@@ -218,60 +306,110 @@ impl<'a> encoding::Key<'a> for KeyPrefix<'a> {}
 /// An MVCC-based transactional key-value engine. It wraps an underlying storage
 /// engine that's used for raw key/value storage.
 ///
-/// While it supports any number of concurrent transactions, individual read or
-/// write operations are executed sequentially, serialized via a mutex. There
-/// are two reasons for this: the storage engine itself is not thread-safe,
-/// requiring serialized access, and the Raft state machine that manages the
+/// While it supports any number of concurrent transactions, individual write
+/// operations are executed sequentially, serialized via the write lock of an
+/// RwLock. There are two reasons for this: the storage engine requires
+/// exclusive access for writes, and the Raft state machine that manages the
 /// MVCC engine applies commands one at a time from the Raft log, which will
-/// serialize them anyway.
+/// serialize them anyway. Reads only take the read lock, and use the engine's
+/// shared reads (see ReadEngine), so they can run concurrently with each other.
 pub struct MVCC<E: Engine> {
-    pub engine: Arc<Mutex<E>>,
+    pub engine: Arc<RwLock<E>>,
+    /// Key range watches, notified when transactions commit.
+    watchers: Watchers,
+}
+
+/// Watches registered via MVCC::watch(), shared by all transactions.
+type Watchers = Arc<Mutex<Vec<Watcher>>>;
+
+/// A key range watch, see MVCC::watch().
+struct Watcher {
+    /// The watched key range.
+    range: (Bound<Vec<u8>>, Bound<Vec<u8>>),
+    /// The channel to send committed changes to.
+    tx: Sender<Change>,
+}
+
+/// A committed change to a key, delivered to watches, see MVCC::watch().
+#[derive(Clone, Debug, PartialEq)]
+pub struct Change {
+    /// The version of the transaction that committed the change.
+    pub version: Version,
+    /// The changed key.
+    pub key: Vec<u8>,
+    /// The new value, or None if the key was deleted.
+    pub value: Option<Vec<u8>>,
+}
+
+impl<E: Engine> Clone for MVCC<E> {
+    fn clone(&self) -> Self {
+        Self { engine: self.engine.clone(), watchers: self.watchers.clone() }
+    }
 }
 
 impl<E: Engine> MVCC<E> {
     /// Creates a new MVCC engine with the given storage engine.
     pub fn new(engine: E) -> Self {
-        Self { engine: Arc::new(Mutex::new(engine)) }
+        Self { engine: Arc::new(RwLock::new(engine)), watchers: Watchers::default() }
     }
 
     /// Begins a new read-write transaction.
     pub fn begin(&self) -> Result<Transaction<E>> {
-        Transaction::begin(self.engine.clone())
+        Transaction::begin(self.engine.clone(), self.watchers.clone(), false)
+    }
+
+    /// Begins a new read-write transaction with serializable isolation.
+    pub fn begin_serializable(&self) -> Result<Transaction<E>> {
+        Transaction::begin(self.engine.clone(), self.watchers.clone(), true)
     }
 
     /// Begins a new read-only transaction at the latest version.
     pub fn begin_read_only(&self) -> Result<Transaction<E>> {
-        Transaction::begin_read_only(self.engine.clone(), None)
+        Transaction::begin_read_only(self.engine.clone(), self.watchers.clone(), None)
     }
 
     /// Begins a new read-only transaction as of the given version.
     pub fn begin_as_of(&self, version: Version) -> Result<Transaction<E>> {
-        Transaction::begin_read_only(self.engine.clone(), Some(version))
+        Transaction::begin_read_only(self.engine.clone(), self.watchers.clone(), Some(version))
     }
 
     /// Resumes a transaction from the given transaction state.
     pub fn resume(&self, state: TransactionState) -> Result<Transaction<E>> {
-        Transaction::resume(self.engine.clone(), state)
+        Transaction::resume(self.engine.clone(), self.watchers.clone(), state)
+    }
+
+    /// Watches the given key range for committed changes. Returns a channel
+    /// that receives the changes of every transaction that commits after this
+    /// call, in commit order, see the module documentation for details. The
+    /// watch is removed once the receiver is dropped.
+    pub fn watch(&self, range: impl RangeBounds<Vec<u8>>) -> Result<Receiver<Change>> {
+        let (tx, rx) = crossbeam::channel::unbounded();
+        let range = (range.start_bound().cloned(), range.end_bound().cloned());
+        // Hold the engine lock while registering, to avoid racing with commits.
+        let _engine = self.engine.read()?;
+        self.watchers.lock()?.push(Watcher { range, tx });
+        Ok(rx)
     }
 
     /// Fetches the value of an unversioned key.
     pub fn get_unversioned(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
-        self.engine.lock()?.get(&Key::Unversioned(key.into()).encode())
+        self.engine.read()?.get_shared(&Key::Unversioned(key.into()).encode())
     }
 
     /// Sets the value of an unversioned key.
     pub fn set_unversioned(&self, key: &[u8], value: Vec<u8>) -> Result<()> {
-        self.engine.lock()?.set(&Key::Unversioned(key.into()).encode(), value)
+        self.engine.write()?.set(&Key::Unversioned(key.into()).encode(), value)
     }
 
     /// Returns the status of the MVCC and storage engines.
     pub fn status(&self) -> Result<Status> {
-        let mut engine = self.engine.lock()?;
+        let mut engine = self.engine.write()?;
         let versions = match engine.get(&Key::NextVersion.encode())? {
             Some(ref v) => Version::decode(v)? - 1,
             None => 0,
         };
-        let active_txns = engine.scan_prefix(&KeyPrefix::TxnActive.encode()).count() as u64;
+        let active_txns =
+            engine.scan_keys(keycode::prefix_range(&KeyPrefix::TxnActive.encode())).count() as u64;
         Ok(Status { versions, active_txns, storage: engine.status()? })
     }
 }
@@ -292,7 +430,9 @@ impl encoding::Value for Status {}
 /// An MVCC transaction.
 pub struct Transaction<E: Engine> {
     /// The underlying engine, shared by all transactions.
-    engine: Arc<Mutex<E>>,
+    engine: Arc<RwLock<E>>,
+    /// Key range watches, notified on commit.
+    watchers: Watchers,
     /// The transaction state.
     st: TransactionState,
 }
@@ -317,6 +457,9 @@ pub struct TransactionState {
     pub version: Version,
     /// If true, the transaction is read only.
     pub read_only: bool,
+    /// If true, the transaction is serializable rather than snapshot isolated.
+    /// Only applies to read-write transactions.
+    pub serializable: bool,
     /// The set of concurrent active (uncommitted) transactions, as of the start
     /// of this transaction. Their writes should be invisible to this
     /// transaction even if they're writing at a lower version, since they're
@@ -348,6 +491,13 @@ impl TransactionState {
             version <= self.version
         }
     }
+
+    /// Returns the resolved version: all versions below it are either visible
+    /// to this transaction or were rolled back, so no further writes can
+    /// appear below it. Active versions may still commit later.
+    pub fn resolved(&self) -> Version {
+        self.active.first().copied().unwrap_or(self.version)
+    }
 }
 
 impl From<TransactionState> for Cow<'_, TransactionState> {
@@ -366,8 +516,8 @@ impl<E: Engine> Transaction<E> {
     /// Begins a new transaction in read-write mode. This will allocate a new
     /// version that the transaction can write at, add it to the active set, and
     /// record its active snapshot for time-travel queries.
-    fn begin(engine: Arc<Mutex<E>>) -> Result<Self> {
-        let mut session = engine.lock()?;
+    fn begin(engine: Arc<RwLock<E>>, watchers: Watchers, serializable: bool) -> Result<Self> {
+        let mut session = engine.write()?;
 
         // Allocate a new version to write at.
         let version = match session.get(&Key::NextVersion.encode())? {
@@ -378,25 +528,30 @@ impl<E: Engine> Transaction<E> {
 
         // Fetch the current set of active transactions, persist it for
         // time-travel queries if non-empty, then add this txn to it.
-        let active = Self::scan_active(&mut session)?;
+        let active = Self::scan_active(&*session)?;
         if !active.is_empty() {
             session.set(&Key::TxnActiveSnapshot(version).encode(), active.encode())?
         }
         session.set(&Key::TxnActive(version).encode(), vec![])?;
         drop(session);
 
-        Ok(Self { engine, st: TransactionState { version, read_only: false, active } })
+        let st = TransactionState { version, read_only: false, serializable, active };
+        Ok(Self { engine, watchers, st })
     }
 
     /// Begins a new read-only transaction. If version is given it will see the
     /// state as of the beginning of that version (ignoring writes at that
     /// version). In other words, it sees the same state as the read-write
     /// transaction at that version saw when it began.
-    fn begin_read_only(engine: Arc<Mutex<E>>, as_of: Option<Version>) -> Result<Self> {
-        let mut session = engine.lock()?;
+    fn begin_read_only(
+        engine: Arc<RwLock<E>>,
+        watchers: Watchers,
+        as_of: Option<Version>,
+    ) -> Result<Self> {
+        let session = engine.read()?;
 
         // Fetch the latest version.
-        let mut version = match session.get(&Key::NextVersion.encode())? {
+        let mut version = match session.get_shared(&Key::NextVersion.encode())? {
             Some(ref v) => Version::decode(v)?,
             None => 1,
         };
@@ -410,32 +565,36 @@ impl<E: Engine> Transaction<E> {
                 return errinput!("version {as_of} does not exist");
             }
             version = as_of;
-            if let Some(value) = session.get(&Key::TxnActiveSnapshot(version).encode())? {
+            if let Some(value) = session.get_shared(&Key::TxnActiveSnapshot(version).encode())? {
                 active = BTreeSet::<Version>::decode(&value)?;
             }
         } else {
-            active = Self::scan_active(&mut session)?;
+            active = Self::scan_active(&*session)?;
         }
 
         drop(session);
 
-        Ok(Self { engine, st: TransactionState { version, read_only: true, active } })
+        let st = TransactionState { version, read_only: true, serializable: false, active };
+        Ok(Self { engine, watchers, st })
     }
 
     /// Resumes a transaction from the given state.
-    fn resume(engine: Arc<Mutex<E>>, s: TransactionState) -> Result<Self> {
+    fn resume(engine: Arc<RwLock<E>>, watchers: Watchers, s: TransactionState) -> Result<Self> {
         // For read-write transactions, verify that the transaction is still
         // active before making further writes.
-        if !s.read_only && engine.lock()?.get(&Key::TxnActive(s.version).encode())?.is_none() {
-            return errinput!("no active transaction at version {}", s.version);
+        if !s.read_only {
+            let key = Key::TxnActive(s.version).encode();
+            if engine.read()?.get_shared(&key)?.is_none() {
+                return errinput!("no active transaction at version {}", s.version);
+            }
         }
-        Ok(Self { engine, st: s })
+        Ok(Self { engine, watchers, st: s })
     }
 
     /// Fetches the set of currently active transactions.
-    fn scan_active(session: &mut MutexGuard<E>) -> Result<BTreeSet<Version>> {
+    fn scan_active(engine: &E) -> Result<BTreeSet<Version>> {
         let mut active = BTreeSet::new();
-        let mut scan = session.scan_prefix(&KeyPrefix::TxnActive.encode());
+        let mut scan = engine.scan_shared(keycode::prefix_range(&KeyPrefix::TxnActive.encode()));
         while let Some((key, _)) = scan.next().transpose()? {
             match Key::decode(&key)? {
                 Key::TxnActive(version) => active.insert(version),
@@ -463,7 +622,11 @@ impl<E: Engine> Transaction<E> {
 
     /// Commits the transaction, by removing it from the active set. This will
     /// immediately make its writes visible to subsequent transactions. Also
-    /// removes its TxnWrite records, which are no longer needed.
+    /// removes its TxnWrite records and savepoints, which are no longer needed,
+    /// and sends its writes to any watches of them.
+    ///
+    /// Serializable transactions first check for read-write conflicts, and if
+    /// any are found they roll back and return a serialization error.
     ///
     /// NB: commit does not flush writes to durable storage, since we rely on
     /// the Raft log for persistence.
@@ -471,15 +634,63 @@ impl<E: Engine> Transaction<E> {
         if self.st.read_only {
             return Ok(());
         }
-        let mut engine = self.engine.lock()?;
+        let mut engine = self.engine.write()?;
+        if self.st.serializable && Self::has_read_conflict(&mut *engine, &self.st)? {
+            drop(engine);
+            self.rollback()?;
+            return Err(Error::Serialization);
+        }
         let remove: Vec<_> = engine
-            .scan_prefix(&KeyPrefix::TxnWrite(self.st.version).encode())
+            .scan_keys(keycode::prefix_range(&KeyPrefix::TxnWrite(self.st.version).encode()))
             .map_ok(|(k, _)| k)
             .try_collect()?;
+        let mut watchers = self.watchers.lock()?;
+        let changes = Self::watched_changes(&mut *engine, &watchers, self.st.version, &remove)?;
+        let mut batch = WriteBatch::new();
         for key in remove {
-            engine.delete(&key)?
+            batch.delete(&key);
         }
-        engine.delete(&Key::TxnActive(self.st.version).encode())
+        Self::remove_savepoints(&mut *engine, self.st.version, &mut batch)?;
+        Self::remove_reads(&mut *engine, &self.st, &mut batch)?;
+        batch.delete(&Key::TxnActive(self.st.version).encode());
+        engine.write_batch(batch)?;
+
+        // Notify watches while still holding the engine lock, so changes are
+        // delivered in commit order. Watches with dropped receivers are removed.
+        for change in changes {
+            watchers
+                .retain(|w| !w.range.contains(&change.key) || w.tx.send(change.clone()).is_ok());
+        }
+        Ok(())
+    }
+
+    /// Returns the committed changes for the given TxnWrite records that are
+    /// covered by a watch, for commit().
+    fn watched_changes(
+        engine: &mut E,
+        watchers: &[Watcher],
+        version: Version,
+        writes: &[Vec<u8>],
+    ) -> Result<Vec<Change>> {
+        let mut changes = Vec::new();
+        if watchers.is_empty() {
+            return Ok(changes);
+        }
+        for write in writes {
+            let key = match Key::decode(write)? {
+                Key::TxnWrite(_, key) => key.into_owned(),
+                key => return errdata!("expected TxnWrite, got {key:?}"),
+            };
+            if !watchers.iter().any(|w| w.range.contains(&key)) {
+                continue;
+            }
+            let version_key = Key::Version(key.as_slice().into(), version).encode();
+            let Some(value) = engine.get(&version_key)? else {
+                return errdata!("missing version {version} of written key {key:?}");
+            };
+            changes.push(Change { version, key, value: bincode::deserialize(&value)? });
+        }
+        Ok(changes)
     }
 
     /// Rolls back the transaction, by undoing all written versions and removing
@@ -489,23 +700,268 @@ impl<E: Engine> Transaction<E> {
         if self.st.read_only {
             return Ok(());
         }
-        let mut engine = self.engine.lock()?;
-        let mut rollback = Vec::new();
-        let mut scan = engine.scan_prefix(&KeyPrefix::TxnWrite(self.st.version).encode());
+        let mut engine = self.engine.write()?;
+        let mut batch = WriteBatch::new();
+        let prefix = KeyPrefix::TxnWrite(self.st.version).encode();
+        let mut scan = engine.scan_keys(keycode::prefix_range(&prefix));
         while let Some((key, _)) = scan.next().transpose()? {
             match Key::decode(&key)? {
                 Key::TxnWrite(_, key) => {
-                    rollback.push(Key::Version(key, self.st.version).encode()) // the version
+                    batch.delete(&Key::Version(key, self.st.version).encode()) // the version
                 }
                 key => return errdata!("expected TxnWrite, got {key:?}"),
             };
-            rollback.push(key); // the TxnWrite record
+            batch.delete(&key); // the TxnWrite record
         }
         drop(scan);
-        for key in rollback.into_iter() {
-            engine.delete(&key)?;
+        Self::remove_savepoints(&mut *engine, self.st.version, &mut batch)?;
+        Self::remove_reads(&mut *engine, &self.st, &mut batch)?;
+        batch.delete(&Key::TxnActive(self.st.version).encode()); // remove from active set
+        engine.write_batch(batch)
+    }
+
+    /// Adds deletes of a transaction's savepoints and undo records to a batch.
+    fn remove_savepoints(engine: &mut E, version: Version, batch: &mut WriteBatch) -> Result<()> {
+        let prefix = KeyPrefix::TxnUndo(version).encode();
+        let mut scan = engine.scan_keys(keycode::prefix_range(&prefix));
+        while let Some((key, _)) = scan.next().transpose()? {
+            batch.delete(&key);
         }
-        engine.delete(&Key::TxnActive(self.st.version).encode()) // remove from active set
+        drop(scan);
+        let key = Key::TxnSavepoints(version).encode();
+        if engine.get(&key)?.is_some() {
+            batch.delete(&key);
+        }
+        Ok(())
+    }
+
+    /// Adds deletes of a serializable transaction's read records to a batch.
+    fn remove_reads(engine: &mut E, st: &TransactionState, batch: &mut WriteBatch) -> Result<()> {
+        if !st.serializable {
+            return Ok(());
+        }
+        for prefix in [KeyPrefix::TxnRead(st.version), KeyPrefix::TxnScan(st.version)] {
+            let mut scan = engine.scan_keys(keycode::prefix_range(&prefix.encode()));
+            while let Some((key, _)) = scan.next().transpose()? {
+                batch.delete(&key);
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks whether a serializable transaction has a read-write conflict,
+    /// i.e. whether a concurrent transaction has committed a write to a key it
+    /// read or a range it scanned. See the module documentation for details.
+    fn has_read_conflict(engine: &mut E, st: &TransactionState) -> Result<bool> {
+        // Collect the engine key ranges of the transaction's reads.
+        let mut ranges = Vec::new();
+        let prefix = KeyPrefix::TxnRead(st.version).encode();
+        let mut scan = engine.scan_keys(keycode::prefix_range(&prefix));
+        while let Some((key, _)) = scan.next().transpose()? {
+            let Key::TxnRead(_, key) = Key::decode(&key)? else {
+                return errdata!("expected TxnRead, got {key:?}");
+            };
+            ranges.push(Self::version_range(Bound::Included(&key), Bound::Included(&key)));
+        }
+        drop(scan);
+        let prefix = KeyPrefix::TxnScan(st.version).encode();
+        let mut scan = engine.scan_keys(keycode::prefix_range(&prefix));
+        while let Some((key, _)) = scan.next().transpose()? {
+            let Key::TxnScan(_, start, end) = Key::decode(&key)? else {
+                return errdata!("expected TxnScan, got {key:?}");
+            };
+            let end = match end.is_empty() {
+                true => Bound::Unbounded,
+                false => Bound::Excluded(end.as_ref()),
+            };
+            ranges.push(Self::version_range(Bound::Included(&start), end));
+        }
+        drop(scan);
+
+        // Look for versions that are invisible to us, but have committed.
+        let active = Self::scan_active(engine)?;
+        for range in ranges {
+            let mut scan = engine.scan_keys(range);
+            while let Some((key, _)) = scan.next().transpose()? {
+                let Key::Version(_, version) = Key::decode(&key)? else {
+                    return errdata!("expected Key::Version got {key:?}");
+                };
+                if !st.is_visible(version) && !active.contains(&version) {
+                    return Ok(true);
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    /// Returns the engine key range of all versions of the user keys in the
+    /// given range.
+    fn version_range(start: Bound<&[u8]>, end: Bound<&[u8]>) -> (Bound<Vec<u8>>, Bound<Vec<u8>>) {
+        let start = match start {
+            Bound::Excluded(k) => Bound::Excluded(Key::Version(k.into(), u64::MAX).encode()),
+            Bound::Included(k) => Bound::Included(Key::Version(k.into(), 0).encode()),
+            Bound::Unbounded => Bound::Included(Key::Version(vec![].into(), 0).encode()),
+        };
+        let end = match end {
+            Bound::Excluded(k) => Bound::Excluded(Key::Version(k.into(), 0).encode()),
+            Bound::Included(k) => Bound::Included(Key::Version(k.into(), u64::MAX).encode()),
+            Bound::Unbounded => Bound::Excluded(KeyPrefix::Unversioned.encode()),
+        };
+        (start, end)
+    }
+
+    /// Records a read for conflict detection, if the transaction is a
+    /// serializable read-write transaction.
+    fn record_read(&self, key: Key) -> Result<()> {
+        if self.st.serializable && !self.st.read_only {
+            self.engine.write()?.set(&key.encode(), Vec::new())?;
+        }
+        Ok(())
+    }
+
+    /// Returns the TxnScan key to record for a scan of the given user key
+    /// range, if the transaction is a serializable read-write transaction.
+    fn scan_record(&self, range: impl RangeBounds<Vec<u8>>) -> Option<Vec<u8>> {
+        if !self.st.serializable || self.st.read_only {
+            return None;
+        }
+        // Convert the range to a half-open range. Appending a 0 byte to a key
+        // yields the smallest key after it.
+        let start = match range.start_bound() {
+            Bound::Included(k) => k.clone(),
+            Bound::Excluded(k) => [k.as_slice(), &[0]].concat(),
+            Bound::Unbounded => Vec::new(),
+        };
+        let end = match range.end_bound() {
+            Bound::Included(k) => [k.as_slice(), &[0]].concat(),
+            Bound::Excluded(k) => k.clone(),
+            Bound::Unbounded => Vec::new(),
+        };
+        Some(Key::TxnScan(self.st.version, start.into(), end.into()).encode())
+    }
+
+    /// Fetches the transaction's savepoint names, innermost last.
+    fn get_savepoints(engine: &mut E, version: Version) -> Result<Vec<String>> {
+        match engine.get(&Key::TxnSavepoints(version).encode())? {
+            Some(value) => bincode::deserialize(&value),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Creates a savepoint with the given name, which the transaction can
+    /// later roll back to with rollback_to_savepoint(). A savepoint with the
+    /// same name as an existing one shadows it, until it's released.
+    pub fn savepoint(&self, name: &str) -> Result<()> {
+        if self.st.read_only {
+            return Err(Error::ReadOnly);
+        }
+        let mut engine = self.engine.write()?;
+        let mut savepoints = Self::get_savepoints(&mut engine, self.st.version)?;
+        if savepoints.len() >= u32::MAX as usize {
+            return errinput!("too many savepoints");
+        }
+        savepoints.push(name.to_string());
+        engine.set(&Key::TxnSavepoints(self.st.version).encode(), bincode::serialize(&savepoints))
+    }
+
+    /// Rolls back all writes made since the given savepoint was created, and
+    /// removes any savepoints created after it. The savepoint itself remains,
+    /// and can be rolled back to again.
+    pub fn rollback_to_savepoint(&self, name: &str) -> Result<()> {
+        if self.st.read_only {
+            return Err(Error::ReadOnly);
+        }
+        let mut engine = self.engine.write()?;
+        let version = self.st.version;
+        let mut savepoints = Self::get_savepoints(&mut engine, version)?;
+        let Some(index) = savepoints.iter().rposition(|s| s == name) else {
+            return errinput!("savepoint {name} does not exist");
+        };
+        let depth = index as u32 + 1;
+
+        // Collect the undo records at or below the savepoint, and apply them
+        // innermost first, such that each key ends up with its value as of
+        // the savepoint.
+        let mut undo = Vec::new();
+        let prefix = KeyPrefix::TxnUndo(version).encode();
+        let mut scan = engine.scan(keycode::prefix_range(&prefix));
+        while let Some((undo_key, value)) = scan.next().transpose()? {
+            match Key::decode(&undo_key)? {
+                Key::TxnUndo(_, d, key) if d >= depth => {
+                    let value: Option<Vec<u8>> = bincode::deserialize(&value)?;
+                    undo.push((d, key.into_owned(), value, undo_key));
+                }
+                Key::TxnUndo(..) => {}
+                key => return errdata!("expected TxnUndo, got {key:?}"),
+            }
+        }
+        drop(scan);
+        undo.sort_by_key(|(depth, ..)| std::cmp::Reverse(*depth)); // stable, retains key order
+
+        let mut batch = WriteBatch::new();
+        for (_, key, value, undo_key) in undo {
+            let version_key = Key::Version(key.as_slice().into(), version).encode();
+            match value {
+                Some(value) => batch.set(&version_key, value),
+                None => {
+                    batch.delete(&Key::TxnWrite(version, key.as_slice().into()).encode());
+                    batch.delete(&version_key)
+                }
+            };
+            batch.delete(&undo_key);
+        }
+        savepoints.truncate(index + 1);
+        batch.set(&Key::TxnSavepoints(version).encode(), bincode::serialize(&savepoints));
+        engine.write_batch(batch)
+    }
+
+    /// Releases the given savepoint and any savepoints created after it. Their
+    /// writes are retained, and can still be rolled back via an earlier
+    /// savepoint or the transaction itself.
+    pub fn release_savepoint(&self, name: &str) -> Result<()> {
+        if self.st.read_only {
+            return Err(Error::ReadOnly);
+        }
+        let mut engine = self.engine.write()?;
+        let version = self.st.version;
+        let mut savepoints = Self::get_savepoints(&mut engine, version)?;
+        let Some(index) = savepoints.iter().rposition(|s| s == name) else {
+            return errinput!("savepoint {name} does not exist");
+        };
+        let depth = index as u32 + 1;
+
+        // Move the released undo records to the enclosing savepoint (if any),
+        // unless it already has a record for the key. The scan is ordered by
+        // depth, so the outermost record for each key is kept, which has the
+        // value as of the enclosing savepoint.
+        let mut batch = WriteBatch::new();
+        let mut enclosing = std::collections::HashSet::new();
+        let prefix = KeyPrefix::TxnUndo(version).encode();
+        let mut scan = engine.scan(keycode::prefix_range(&prefix));
+        while let Some((undo_key, value)) = scan.next().transpose()? {
+            match Key::decode(&undo_key)? {
+                Key::TxnUndo(_, d, key) if d == depth - 1 => {
+                    enclosing.insert(key.into_owned());
+                }
+                Key::TxnUndo(_, d, key) if d >= depth => {
+                    batch.delete(&undo_key);
+                    if depth > 1 && enclosing.insert(key.to_vec()) {
+                        batch.set(&Key::TxnUndo(version, depth - 1, key).encode(), value);
+                    }
+                }
+                Key::TxnUndo(..) => {}
+                key => return errdata!("expected TxnUndo, got {key:?}"),
+            }
+        }
+        drop(scan);
+        savepoints.truncate(index);
+        match savepoints.is_empty() {
+            true => batch.delete(&Key::TxnSavepoints(version).encode()),
+            false => {
+                batch.set(&Key::TxnSavepoints(version).encode(), bincode::serialize(&savepoints))
+            }
+        };
+        engine.write_batch(batch)
     }
 
     /// Deletes a key.
@@ -526,7 +982,7 @@ impl<E: Engine> Transaction<E> {
         if self.st.read_only {
             return Err(Error::ReadOnly);
         }
-        let mut engine = self.engine.lock()?;
+        let mut engine = self.engine.write()?;
 
         // Check for write conflicts, i.e. if the latest key is invisible to us
         // (either a newer version, or an uncommitted version in our past). We
@@ -538,7 +994,8 @@ impl<E: Engine> Transaction<E> {
         )
         .encode();
         let to = Key::Version(key.into(), u64::MAX).encode();
-        if let Some((key, _)) = engine.scan(from..=to).last().transpose()? {
+        let range = (Bound::Included(from), Bound::Included(to));
+        if let Some((key, _)) = engine.scan_keys(range).next_back().transpose()? {
             match Key::decode(&key)? {
                 Key::Version(_, version) => {
                     if !self.st.is_visible(version) {
@@ -549,20 +1006,36 @@ impl<E: Engine> Transaction<E> {
             }
         }
 
+        // If the transaction has savepoints, and this is the first write to
+        // the key since the innermost one, record the key's current value at
+        // our version so rollback_to_savepoint() can restore it.
+        let mut batch = WriteBatch::new();
+        let savepoints = Self::get_savepoints(&mut engine, self.st.version)?;
+        if !savepoints.is_empty() {
+            let depth = savepoints.len() as u32;
+            let undo_key = Key::TxnUndo(self.st.version, depth, key.into()).encode();
+            if engine.get(&undo_key)?.is_none() {
+                let value = engine.get(&Key::Version(key.into(), self.st.version).encode())?;
+                batch.set(&undo_key, bincode::serialize(&value));
+            }
+        }
+
         // Write the new version and its write record.
         //
         // NB: TxnWrite contains the provided user key, not the encoded engine
         // key, since we can construct the engine key using the version.
-        engine.set(&Key::TxnWrite(self.st.version, key.into()).encode(), vec![])?;
-        engine.set(&Key::Version(key.into(), self.st.version).encode(), bincode::serialize(&value))
+        batch.set(&Key::TxnWrite(self.st.version, key.into()).encode(), vec![]);
+        batch.set(&Key::Version(key.into(), self.st.version).encode(), bincode::serialize(&value));
+        engine.write_batch(batch)
     }
 
     /// Fetches a key's value, or None if it does not exist.
     pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
-        let mut engine = self.engine.lock()?;
+        self.record_read(Key::TxnRead(self.st.version, key.into()))?;
+        let engine = self.engine.read()?;
         let from = Key::Version(key.into(), 0).encode();
         let to = Key::Version(key.into(), self.st.version).encode();
-        let mut scan = engine.scan(from..=to).rev();
+        let mut scan = engine.scan_shared((Bound::Included(from), Bound::Included(to))).rev();
         while let Some((key, value)) = scan.next().transpose()? {
             match Key::decode(&key)? {
                 Key::Version(_, version) => {
@@ -579,17 +1052,11 @@ impl<E: Engine> Transaction<E> {
     /// Returns an iterator over the latest visible key/value pairs at the
     /// transaction's version.
     pub fn scan(&self, range: impl RangeBounds<Vec<u8>>) -> ScanIterator<E> {
-        let start = match range.start_bound() {
-            Bound::Excluded(k) => Bound::Excluded(Key::Version(k.into(), u64::MAX).encode()),
-            Bound::Included(k) => Bound::Included(Key::Version(k.into(), 0).encode()),
-            Bound::Unbounded => Bound::Included(Key::Version(vec![].into(), 0).encode()),
-        };
-        let end = match range.end_bound() {
-            Bound::Excluded(k) => Bound::Excluded(Key::Version(k.into(), 0).encode()),
-            Bound::Included(k) => Bound::Included(Key::Version(k.into(), u64::MAX).encode()),
-            Bound::Unbounded => Bound::Excluded(KeyPrefix::Unversioned.encode()),
-        };
-        ScanIterator::new(self.engine.clone(), self.state().clone(), (start, end))
+        let bounds = (range.start_bound(), range.end_bound());
+        let range =
+            Self::version_range(bounds.0.map(|k| k.as_slice()), bounds.1.map(|k| k.as_slice()));
+        let record = self.scan_record(bounds);
+        ScanIterator::new(self.engine.clone(), self.state().clone(), range, record)
     }
 
     /// Scans keys under a given prefix.
@@ -597,32 +1064,120 @@ impl<E: Engine> Transaction<E> {
         // Normally, KeyPrefix::Version will only match all versions of the
         // exact given key. We want all keys maching the prefix, so we chop off
         // the KeyCode byte slice terminator 0x0000 at the end.
+        let record = self.scan_record(keycode::prefix_range(prefix));
         let mut prefix = KeyPrefix::Version(prefix.into()).encode();
         prefix.truncate(prefix.len() - 2);
         let range = keycode::prefix_range(&prefix);
-        ScanIterator::new(self.engine.clone(), self.state().clone(), range)
+        ScanIterator::new(self.engine.clone(), self.state().clone(), range, record)
+    }
+
+    /// Counts the live keys under a given prefix, like scan_prefix().count().
+    /// This uses an engine key scan, which doesn't have to read the values:
+    /// tombstones are recognized by their length, since a bincode-encoded None
+    /// is a single byte while Some is at least two (the tag and length).
+    ///
+    /// The key scan only needs shared engine access, so it can run concurrently
+    /// with other reads.
+    pub fn count_prefix(&self, prefix: &[u8]) -> Result<u64> {
+        if let Some(record) = self.scan_record(keycode::prefix_range(prefix)) {
+            self.engine.write()?.set(&record, Vec::new())?;
+        }
+        let mut prefix = KeyPrefix::Version(prefix.into()).encode();
+        prefix.truncate(prefix.len() - 2); // see scan_prefix()
+        let range = keycode::prefix_range(&prefix);
+        let engine = self.engine.read()?;
+        let scan = engine.scan_keys_shared(range);
+        self.count_versions(scan)
+    }
+
+    /// Counts the live keys among the given engine key versions and their
+    /// value lengths, for count_prefix().
+    fn count_versions(&self, scan: impl Iterator<Item = Result<(Vec<u8>, usize)>>) -> Result<u64> {
+        let tombstone_len = bincode::serialize(&None::<Vec<u8>>).len();
+        let mut count = 0;
+        let mut last: Option<(Vec<u8>, usize)> = None;
+        for item in scan {
+            let (key, value_len) = item?;
+            let Key::Version(key, version) = Key::decode(&key)? else {
+                return errdata!("expected Key::Version got {key:?}");
+            };
+            if !self.st.is_visible(version) {
+                continue;
+            }
+            // Versions are ordered by key then version, so a key's latest
+            // visible version is the last one before the key changes.
+            if let Some((last_key, last_len)) = last.take() {
+                if last_key != *key && last_len != tombstone_len {
+                    count += 1;
+                }
+            }
+            last = Some((key.into_owned(), value_len));
+        }
+        if let Some((_, last_len)) = last {
+            if last_len != tombstone_len {
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    /// Returns all key versions under the given prefix written at or after the
+    /// given version and below the resolved version (see
+    /// TransactionState::resolved), as key/version/value tuples ordered by
+    /// version and key. Deleted keys have a None value. This can be used to
+    /// follow changes, using the resolved version as the next from version.
+    ///
+    /// This scans all versions of all keys under the prefix, since there is no
+    /// index of versions. It's fine for ToyDB, but a real database would need
+    /// one, or garbage collection to limit the history.
+    #[allow(clippy::type_complexity)]
+    pub fn scan_changes(
+        &self,
+        prefix: &[u8],
+        from: Version,
+    ) -> Result<Vec<(Vec<u8>, Version, Option<Vec<u8>>)>> {
+        let resolved = self.st.resolved();
+        let mut prefix = KeyPrefix::Version(prefix.into()).encode();
+        prefix.truncate(prefix.len() - 2); // see scan_prefix()
+        let engine = self.engine.read()?;
+        let mut changes = Vec::new();
+        let scan = engine.scan_shared(keycode::prefix_range(&prefix));
+        let mut iter = VersionIterator::new(&self.st, scan);
+        while let Some((key, version, value)) = iter.next().transpose()? {
+            if (from..resolved).contains(&version) {
+                changes.push((key, version, bincode::deserialize(&value)?));
+            }
+        }
+        // The scan is ordered by key and version, so a stable sort by version
+        // retains the key order.
+        changes.sort_by_key(|(_, version, _)| *version);
+        Ok(changes)
     }
 }
 
 /// An iterator over the latest live and visible key/value pairs for the txn.
 ///
-/// The (single-threaded) engine is protected by a mutex, and holding the mutex
-/// for the duration of the iteration can cause deadlocks (e.g. when the local
-/// SQL engine pulls from two tables concurrently during a join). Instead, we
-/// pull and buffer a batch of rows at a time, and release the mutex in between.
+/// The engine is protected by an RwLock, and holding the read lock for the
+/// duration of the iteration can block writers indefinitely or cause deadlocks
+/// (e.g. when the local SQL engine pulls from two tables concurrently during a
+/// join, and a writer queues up between them). Instead, we pull and buffer a
+/// batch of rows at a time, and release the lock in between.
 ///
 /// This does not implement DoubleEndedIterator (reverse scans), since the SQL
 /// layer doesn't currently need it.
 #[allow(clippy::type_complexity)]
 pub struct ScanIterator<E: Engine> {
     /// The engine.
-    engine: Arc<Mutex<E>>,
+    engine: Arc<RwLock<E>>,
     /// The transaction state.
     txn: TransactionState,
     /// A buffer of live and visible key/value pairs to emit.
     buffer: VecDeque<(Vec<u8>, Vec<u8>)>,
     /// The remaining range after the buffer.
     remainder: Option<(Bound<Vec<u8>>, Bound<Vec<u8>>)>,
+    /// A TxnScan key to record before the first read, for serializable
+    /// transactions.
+    record: Option<Vec<u8>>,
 }
 
 /// Implement Clone manually. Deriving it requires Engine: Clone.
@@ -633,6 +1188,7 @@ impl<E: Engine> Clone for ScanIterator<E> {
             txn: self.txn.clone(),
             buffer: self.buffer.clone(),
             remainder: self.remainder.clone(),
+            record: self.record.clone(),
         }
     }
 }
@@ -647,12 +1203,13 @@ impl<E: Engine> ScanIterator<E> {
 
     /// Creates a new scan iterator.
     fn new(
-        engine: Arc<Mutex<E>>,
+        engine: Arc<RwLock<E>>,
         txn: TransactionState,
         range: (Bound<Vec<u8>>, Bound<Vec<u8>>),
+        record: Option<Vec<u8>>,
     ) -> Self {
         let buffer = VecDeque::with_capacity(Self::BUFFER_SIZE);
-        Self { engine, txn, buffer, remainder: Some(range) }
+        Self { engine, txn, buffer, remainder: Some(range), record }
     }
 
     /// Fills the buffer, if there's any pending items.
@@ -663,9 +1220,12 @@ impl<E: Engine> ScanIterator<E> {
         }
         let Some(range) = self.remainder.take() else { return Ok(()) };
         let range_end = range.1.clone();
+        if let Some(record) = self.record.take() {
+            self.engine.write()?.set(&record, Vec::new())?;
+        }
 
-        let mut engine = self.engine.lock()?;
-        let mut iter = VersionIterator::new(&self.txn, engine.scan(range)).peekable();
+        let engine = self.engine.read()?;
+        let mut iter = VersionIterator::new(&self.txn, engine.scan_shared(range)).peekable();
         while let Some((key, _, value)) = iter.next().transpose()? {
             // If the next key equals this one, we're not at the latest version.
             match iter.peek() {
@@ -771,16 +1331,57 @@ pub mod tests {
     #[test_case(KeyPrefix::TxnWrite(1), Key::TxnWrite(1, b"foo".as_slice().into()); "TxnWrite")]
     #[test_case(KeyPrefix::Version(b"foo".as_slice().into()), Key::Version(b"foo".as_slice().into(), 1); "Version")]
     #[test_case(KeyPrefix::Unversioned, Key::Unversioned(b"foo".as_slice().into()); "Unversioned")]
+    #[test_case(KeyPrefix::TxnSavepoints, Key::TxnSavepoints(1); "TxnSavepoints")]
+    #[test_case(KeyPrefix::TxnUndo(1), Key::TxnUndo(1, 1, b"foo".as_slice().into()); "TxnUndo")]
+    #[test_case(KeyPrefix::TxnRead(1), Key::TxnRead(1, b"foo".as_slice().into()); "TxnRead")]
+    #[test_case(KeyPrefix::TxnScan(1), Key::TxnScan(1, b"foo".as_slice().into(), b"fop".as_slice().into()); "TxnScan")]
     fn key_prefix(prefix: KeyPrefix, key: Key) {
         let prefix = prefix.encode();
         let key = key.encode();
         assert_eq!(prefix, key[..prefix.len()])
     }
 
+    /// Tests that read-only transactions can read while another thread holds
+    /// the engine's read lock, i.e. that reads don't serialize on the engine.
+    #[test]
+    fn concurrent_reads() -> crate::error::Result<()> {
+        let mvcc = MVCC::new(Memory::new());
+        let txn = mvcc.begin()?;
+        txn.set(b"a", vec![1])?;
+        txn.set(b"b", vec![2])?;
+        txn.commit()?;
+
+        let guard = mvcc.engine.read()?;
+        let (tx, rx) = crossbeam::channel::unbounded();
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                let (mvcc, tx) = (&mvcc, tx.clone());
+                s.spawn(move || {
+                    let result = || -> crate::error::Result<_> {
+                        let txn = mvcc.begin_read_only()?;
+                        let value = txn.get(b"a")?;
+                        let scan: Vec<_> = txn.scan(..).try_collect()?;
+                        Ok((value, scan))
+                    }();
+                    tx.send(result).expect("send failed");
+                });
+            }
+            for _ in 0..4 {
+                let result = rx.recv_timeout(std::time::Duration::from_secs(10));
+                let (value, scan) = result.expect("read blocked").expect("read failed");
+                assert_eq!(value, Some(vec![1]));
+                assert_eq!(scan, vec![(b"a".to_vec(), vec![1]), (b"b".to_vec(), vec![2])]);
+            }
+            drop(guard);
+        });
+        Ok(())
+    }
+
     /// Runs MVCC goldenscript tests.
     pub struct MVCCRunner {
         mvcc: MVCC<TestEngine>,
         txns: HashMap<String, Transaction<TestEngine>>,
+        watches: HashMap<String, Receiver<Change>>,
         op_rx: Receiver<Operation>,
         #[allow(dead_code)]
         tempdir: tempfile::TempDir,
@@ -798,7 +1399,7 @@ pub mod tests {
             let memory = Memory::new();
             let engine = Emit::new(Mirror::new(bitcask, memory), op_tx);
             let mvcc = MVCC::new(engine);
-            Self { mvcc, op_rx, txns: HashMap::new(), tempdir }
+            Self { mvcc, op_rx, txns: HashMap::new(), watches: HashMap::new(), tempdir }
         }
 
         /// Fetches the named transaction from a command prefix.
@@ -830,21 +1431,23 @@ pub mod tests {
             let mut tags = command.tags.clone();
 
             match command.name.as_str() {
-                // txn: begin [readonly] [as_of=VERSION]
+                // txn: begin [readonly|serializable] [as_of=VERSION]
                 "begin" => {
                     let name = Self::txn_name(&command.prefix)?;
                     if self.txns.contains_key(name) {
                         return Err(format!("txn {name} already exists").into());
                     }
                     let mut args = command.consume_args();
-                    let readonly = match args.next_pos().map(|a| a.value.as_str()) {
-                        Some("readonly") => true,
-                        None => false,
+                    let (readonly, serializable) = match args.next_pos().map(|a| a.value.as_str()) {
+                        Some("readonly") => (true, false),
+                        Some("serializable") => (false, true),
+                        None => (false, false),
                         Some(v) => return Err(format!("invalid argument {v}").into()),
                     };
                     let as_of = args.lookup_parse("as_of")?;
                     args.reject_rest()?;
                     let txn = match (readonly, as_of) {
+                        (false, None) if serializable => self.mvcc.begin_serializable()?,
                         (false, None) => self.mvcc.begin()?,
                         (true, None) => self.mvcc.begin_read_only()?,
                         (true, Some(v)) => self.mvcc.begin_as_of(v)?,
@@ -875,7 +1478,7 @@ pub mod tests {
                 // dump
                 "dump" => {
                     command.consume_args().reject_rest()?;
-                    let mut engine = self.mvcc.engine.lock().unwrap();
+                    let mut engine = self.mvcc.engine.write().unwrap();
                     let mut scan = engine.scan(..);
                     while let Some((key, value)) = scan.next().transpose()? {
                         let fmtkv = format::MVCC::<format::Raw>::key_value(&key, &value);
@@ -937,6 +1540,17 @@ pub mod tests {
                     txn.commit()?;
                 }
 
+                // watch: poll
+                "poll" => {
+                    let name = Self::txn_name(&command.prefix)?;
+                    command.consume_args().reject_rest()?;
+                    let rx = self.watches.get(name).ok_or(format!("unknown watch {name}"))?;
+                    while let Ok(Change { version, key, value }) = rx.try_recv() {
+                        let fmtkv = format::Raw::key_maybe_value(&key, value.as_deref());
+                        writeln!(output, "v{version} {fmtkv}")?;
+                    }
+                }
+
                 // txn: resume JSON
                 "resume" => {
                     let name = Self::txn_name(&command.prefix)?;
@@ -948,6 +1562,15 @@ pub mod tests {
                     self.txns.insert(name.to_string(), txn);
                 }
 
+                // txn: release NAME
+                "release" => {
+                    let txn = self.get_txn(&command.prefix)?;
+                    let mut args = command.consume_args();
+                    let name = &args.next_pos().ok_or("name not given")?.value;
+                    args.reject_rest()?;
+                    txn.release_savepoint(name)?;
+                }
+
                 // txn: rollback
                 "rollback" => {
                     let name = Self::txn_name(&command.prefix)?;
@@ -956,6 +1579,24 @@ pub mod tests {
                     txn.rollback()?;
                 }
 
+                // txn: rollback_to NAME
+                "rollback_to" => {
+                    let txn = self.get_txn(&command.prefix)?;
+                    let mut args = command.consume_args();
+                    let name = &args.next_pos().ok_or("name not given")?.value;
+                    args.reject_rest()?;
+                    txn.rollback_to_savepoint(name)?;
+                }
+
+                // txn: savepoint NAME
+                "savepoint" => {
+                    let txn = self.get_txn(&command.prefix)?;
+                    let mut args = command.consume_args();
+                    let name = &args.next_pos().ok_or("name not given")?.value;
+                    args.reject_rest()?;
+                    txn.savepoint(name)?;
+                }
+
                 // txn: scan [RANGE]
                 "scan" => {
                     let txn = self.get_txn(&command.prefix)?;
@@ -983,6 +1624,30 @@ pub mod tests {
                     }
                 }
 
+                // txn: count_prefix PREFIX
+                "count_prefix" => {
+                    let txn = self.get_txn(&command.prefix)?;
+                    let mut args = command.consume_args();
+                    let prefix = decode_binary(&args.next_pos().ok_or("prefix not given")?.value);
+                    args.reject_rest()?;
+                    writeln!(output, "{}", txn.count_prefix(&prefix)?)?;
+                }
+
+                // txn: scan_changes PREFIX [from=VERSION]
+                "scan_changes" => {
+                    let txn = self.get_txn(&command.prefix)?;
+                    let mut args = command.consume_args();
+                    let prefix = decode_binary(&args.next_pos().ok_or("prefix not given")?.value);
+                    let from = args.lookup_parse("from")?.unwrap_or(0);
+                    args.reject_rest()?;
+
+                    for (key, version, value) in txn.scan_changes(&prefix, from)? {
+                        let fmtkv = format::Raw::key_maybe_value(&key, value.as_deref());
+                        writeln!(output, "v{version} {fmtkv}")?;
+                    }
+                    writeln!(output, "resolved={}", txn.state().resolved())?;
+                }
+
                 // txn: set KEY=VALUE...
                 "set" => {
                     let txn = self.get_txn(&command.prefix)?;
@@ -1014,9 +1679,10 @@ pub mod tests {
                     let state = txn.state();
                     write!(
                         output,
-                        "v{} {} active={{{}}}",
+                        "v{} {}{} active={{{}}}",
                         state.version,
                         if state.read_only { "ro" } else { "rw" },
+                        if state.serializable { " serializable" } else { "" },
                         state.active.iter().sorted().join(",")
                     )?;
                 }
@@ -1024,6 +1690,26 @@ pub mod tests {
                 // status
                 "status" => writeln!(output, "{:#?}", self.mvcc.status()?)?,
 
+                // watch: unwatch
+                "unwatch" => {
+                    let name = Self::txn_name(&command.prefix)?;
+                    command.consume_args().reject_rest()?;
+                    self.watches.remove(name).ok_or(format!("unknown watch {name}"))?;
+                }
+
+                // watch: watch [RANGE]
+                "watch" => {
+                    let name = Self::txn_name(&command.prefix)?;
+                    if self.watches.contains_key(name) {
+                        return Err(format!("watch {name} already exists").into());
+                    }
+                    let mut args = command.consume_args();
+                    let range =
+                        parse_key_range(args.next_pos().map(|a| a.value.as_str()).unwrap_or(".."))?;
+                    args.reject_rest()?;
+                    self.watches.insert(name.to_string(), self.mvcc.watch(range)?);
+                }
+
                 name => return Err(format!("invalid command {name}").into()),
             }
 
@@ -0,0 +1,295 @@
+//! A goldenscript test harness for end-to-end SQL tests, which runs scripts of
+//! SQL statements and harness commands against an in-process toyDB cluster and
+//! compares the output with the expected output in the script. Only built for
+//! tests or with the `testing` feature, e.g. for downstream forks and
+//! extensions to test their changes:
+//!
+//! ```toml
+//! [dev-dependencies]
+//! goldenscript = "0.7.0"
+//! toydb = { version = "1.0.0", features = ["testing"] }
+//! ```
+//!
+//! ```ignore
+//! #[test]
+//! fn sql() {
+//!     goldenscript::run(&mut toydb::testing::Runner::new(), "tests/scripts/sql")
+//!         .expect("goldenscript failed")
+//! }
+//! ```
+//!
+//! See the goldenscript crate for the script syntax, and `Runner::run()` for
+//! the available commands. For example:
+//!
+//! ```text
+//! cluster nodes=3
+//! ---
+//! ok
+//!
+//! > CREATE TABLE t (id INTEGER PRIMARY KEY, value STRING)
+//! > INSERT INTO t VALUES (1, 'a'), (2, 'b')
+//! [header]> SELECT * FROM t
+//! ---
+//! t.id, t.value
+//! 1, 'a'
+//! 2, 'b'
+//!
+//! c2: connect node=2
+//! c2:> SELECT value FROM t WHERE id = 2
+//! ---
+//! c2: 'b'
+//! ```
+//!
+//! Nodes use in-memory storage and communicate via a `MemoryNetwork`, and are
+//! shut down in the background when the runner is dropped. Scripts can use `UPDATE_GOLDENFILES=1`
+//! to update the expected output.
+
+use crate::raft;
+use crate::server::{MemoryNetwork, ServerBuilder, ServerHandle};
+use crate::{Client, StatementResult};
+
+use itertools::Itertools as _;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt::Write as _;
+use std::time::{Duration, Instant};
+
+/// The Raft tick interval of test cluster nodes. This is shorter than the
+/// default, to speed up leader elections.
+const TICK_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How long to wait for a test cluster to become ready.
+const READY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A goldenscript runner for SQL sessions, see the module documentation.
+pub struct Runner {
+    /// The cluster. Started with a single node on first use, unless the
+    /// script starts one via the cluster command.
+    cluster: Option<Cluster>,
+    /// Clients by command prefix ("" if none).
+    clients: HashMap<String, Client>,
+}
+
+impl Runner {
+    /// Creates a new runner.
+    pub fn new() -> Self {
+        Self { cluster: None, clients: HashMap::new() }
+    }
+
+    /// Returns the cluster, starting a single-node cluster if none is running.
+    fn cluster(&mut self) -> Result<&mut Cluster, Box<dyn Error>> {
+        if self.cluster.is_none() {
+            self.cluster = Some(Cluster::run(1)?);
+        }
+        Ok(self.cluster.as_mut().expect("no cluster"))
+    }
+
+    /// Fetches the client for the given prefix, or connects a new one to the
+    /// first running node.
+    fn client(&mut self, prefix: &Option<String>) -> Result<&mut Client, Box<dyn Error>> {
+        let name = prefix.clone().unwrap_or_default();
+        if !self.clients.contains_key(&name) {
+            let (_, handle) = self.cluster()?.nodes.first_key_value().ok_or("no running nodes")?;
+            let client = handle.connect()?;
+            self.clients.insert(name.clone(), client);
+        }
+        Ok(self.clients.get_mut(&name).expect("no client"))
+    }
+}
+
+impl Default for Runner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl goldenscript::Runner for Runner {
+    /// Runs a goldenscript command. Commands that aren't listed below are
+    /// executed as SQL statements. SELECT results are output as rows of
+    /// comma-separated values, with column labels if the `[header]` tag is
+    /// given, and EXPLAIN plans are output as-is. Other statement results
+    /// are only output if the `[result]` tag is given.
+    ///
+    /// Each command prefix uses a separate client session, connected to the
+    /// first running node unless connected elsewhere via the connect command.
+    fn run(&mut self, command: &goldenscript::Command) -> Result<String, Box<dyn Error>> {
+        let mut output = String::new();
+        let mut tags = command.tags.clone();
+
+        match command.name.as_str() {
+            // authenticate USER PASSWORD
+            "authenticate" => {
+                let mut args = command.consume_args();
+                let user = &args.next_pos().ok_or("user not given")?.value;
+                let password = &args.next_pos().ok_or("password not given")?.value;
+                args.reject_rest()?;
+                self.client(&command.prefix)?.authenticate(user, password)?;
+                return Ok(output);
+            }
+
+            // close
+            //
+            // Closes the prefix's client.
+            "close" => {
+                command.consume_args().reject_rest()?;
+                let name = command.prefix.clone().unwrap_or_default();
+                self.clients.remove(&name).ok_or("no client to close")?;
+                return Ok(output);
+            }
+
+            // cluster nodes=N
+            //
+            // Starts a cluster with the given number of nodes, and waits for
+            // it to elect a leader. Must be the first command using a cluster.
+            "cluster" => {
+                let mut args = command.consume_args();
+                let nodes = args.lookup_parse("nodes")?.unwrap_or(1);
+                args.reject_rest()?;
+                if self.cluster.is_some() {
+                    return Err("cluster already exists".into());
+                }
+                self.cluster = Some(Cluster::run(nodes)?);
+                return Ok(output);
+            }
+
+            // connect [node=ID]
+            //
+            // Connects the prefix's client to the given node (the first running
+            // node by default), replacing any existing client.
+            "connect" => {
+                let mut args = command.consume_args();
+                let node: Option<raft::NodeID> = args.lookup_parse("node")?;
+                args.reject_rest()?;
+                let nodes = &self.cluster()?.nodes;
+                let handle = match node {
+                    Some(id) => nodes.get(&id).ok_or(format!("node {id} not running"))?,
+                    None => nodes.values().next().ok_or("no running nodes")?,
+                };
+                let client = handle.connect()?;
+                self.clients.insert(command.prefix.clone().unwrap_or_default(), client);
+                return Ok(output);
+            }
+
+            // stop ID
+            //
+            // Shuts down the given node. Its clients will error.
+            "stop" => {
+                let mut args = command.consume_args();
+                let id: raft::NodeID = args.next_pos().ok_or("node ID not given")?.parse()?;
+                args.reject_rest()?;
+                let handle = self.cluster()?.nodes.remove(&id).ok_or("node not running")?;
+                handle.shutdown()?;
+                return Ok(output);
+            }
+
+            // table NAME
+            //
+            // Outputs the table's schema.
+            "table" => {
+                let mut args = command.consume_args();
+                let name = &args.next_pos().ok_or("table not given")?.value;
+                args.reject_rest()?;
+                write!(output, "{}", self.client(&command.prefix)?.get_table(name)?)?;
+                return Ok(output);
+            }
+
+            // tables
+            //
+            // Outputs the names of all tables.
+            "tables" => {
+                command.consume_args().reject_rest()?;
+                for table in self.client(&command.prefix)?.list_tables()? {
+                    writeln!(output, "{table}")?;
+                }
+                return Ok(output);
+            }
+
+            _ => {}
+        }
+
+        // Otherwise, execute the command as a SQL statement.
+        if !command.args.is_empty() {
+            return Err("statements should be given as a command with no args".into());
+        }
+        match self.client(&command.prefix)?.execute(&command.name)? {
+            StatementResult::Select { columns, rows } => {
+                if tags.remove("header") {
+                    writeln!(output, "{}", columns.into_iter().join(", "))?;
+                }
+                for row in rows {
+                    writeln!(output, "{}", row.into_iter().join(", "))?;
+                }
+            }
+            StatementResult::Explain(plan, format, analysis) => {
+                writeln!(output, "{}", plan.explain(format, analysis.as_ref()))?
+            }
+            result if tags.remove("result") => writeln!(output, "{result:?}")?,
+            _ => {}
+        }
+        if let Some(tag) = tags.iter().next() {
+            return Err(format!("invalid tag {tag}").into());
+        }
+        Ok(output)
+    }
+}
+
+/// An in-process test cluster.
+struct Cluster {
+    /// Running nodes by ID.
+    nodes: BTreeMap<raft::NodeID, ServerHandle>,
+}
+
+impl Cluster {
+    /// Starts a cluster with the given number of nodes, and waits for the nodes
+    /// to be ready by fetching their status, which requires a leader.
+    fn run(nodes: raft::NodeID) -> Result<Self, Box<dyn Error>> {
+        if nodes == 0 {
+            return Err("cluster must have at least 1 node".into());
+        }
+        let network = MemoryNetwork::new();
+        let mut cluster = Self { nodes: BTreeMap::new() };
+        for id in 1..=nodes {
+            let peers = (1..=nodes).filter(|peer| *peer != id);
+            let mut server =
+                ServerBuilder::new(id).peers(peers).durability(raft::Durability::Never).build()?;
+            server.set_raft_tick_interval(TICK_INTERVAL)?;
+            cluster.nodes.insert(id, server.start(network.transport(id), "127.0.0.1:0")?);
+        }
+
+        let started = Instant::now();
+        for handle in cluster.nodes.values() {
+            while let Err(error) = handle.connect().and_then(|mut client| client.status()) {
+                if started.elapsed() >= READY_TIMEOUT {
+                    return Err(error.into());
+                }
+                std::thread::sleep(TICK_INTERVAL);
+            }
+        }
+        Ok(cluster)
+    }
+}
+
+impl Drop for Cluster {
+    /// Shuts down the nodes in the background, ignoring errors. Waiting for
+    /// them could take a while, since the last leader can't transfer
+    /// leadership.
+    fn drop(&mut self) {
+        for handle in std::mem::take(&mut self.nodes).into_values() {
+            std::thread::spawn(move || handle.shutdown());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use test_each_file::test_each_path;
+
+    // Run goldenscript tests in src/testing/testscripts.
+    test_each_path! { in "src/testing/testscripts" => test_goldenscript }
+
+    fn test_goldenscript(path: &std::path::Path) {
+        goldenscript::run(&mut Runner::new(), path).expect("goldenscript failed")
+    }
+}
@@ -1,11 +1,13 @@
+use base64::Engine as _;
 use rand::Rng;
 use std::collections::BTreeMap;
 use std::error::Error;
 use std::fmt::Write as _;
-use std::path::Path;
+use std::io::{Read as _, Write as _};
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 use toydb::raft::NodeID;
-use toydb::Client;
+use toydb::{Balance, BalancedClient, Client};
 
 /// Timeout for node readiness.
 const TIMEOUT: Duration = Duration::from_secs(5);
@@ -16,6 +18,9 @@ const SQL_BASE_PORT: u16 = 19600;
 /// The base Raft port (+id).
 const RAFT_BASE_PORT: u16 = 19700;
 
+/// The base HTTP port (+id).
+const HTTP_BASE_PORT: u16 = 19800;
+
 /// Runs a toyDB cluster using the built binary in a temporary directory. The
 /// cluster will be killed and removed when dropped.
 ///
@@ -26,51 +31,138 @@ const RAFT_BASE_PORT: u16 = 19700;
 /// server (and eventually the toySQL client) end-to-end.
 pub struct TestCluster {
     servers: BTreeMap<NodeID, TestServer>,
-    #[allow(dead_code)]
+    ports: NodePorts,
+    config: BTreeMap<String, String>,
     dir: tempfile::TempDir, // deleted when dropped
 }
 
-type NodePorts = BTreeMap<NodeID, (u16, u16)>; // raft,sql on localhost
+type NodePorts = BTreeMap<NodeID, (u16, u16, u16)>; // raft,sql,http on localhost
 
 impl TestCluster {
-    /// Runs and returns a test cluster. It keeps running until dropped.
-    pub fn run(nodes: u8) -> Result<Self, Box<dyn Error>> {
+    /// Runs and returns a test cluster. It keeps running until dropped. The
+    /// given config settings are added to each node's config file.
+    pub fn run(nodes: u8, config: &BTreeMap<String, String>) -> Result<Self, Box<dyn Error>> {
         // Create temporary directory.
         let dir = tempfile::TempDir::with_prefix("toydb")?;
 
         // Allocate port numbers for nodes.
         let ports: NodePorts = (1..=nodes)
-            .map(|id| (id, (RAFT_BASE_PORT + id as u16, SQL_BASE_PORT + id as u16)))
+            .map(|id| {
+                let id_port = id as u16;
+                (id, (RAFT_BASE_PORT + id_port, SQL_BASE_PORT + id_port, HTTP_BASE_PORT + id_port))
+            })
             .collect();
 
         // Start nodes.
         let mut servers = BTreeMap::new();
         for id in 1..=nodes {
             let dir = dir.path().join(format!("toydb{id}"));
-            servers.insert(id, TestServer::run(id, &dir, &ports)?);
+            servers.insert(id, TestServer::run(id, &dir, &ports, config, &[])?);
         }
 
-        // Wait for the nodes to be ready, by fetching the server status.
+        // Wait for the nodes to be ready.
         let started = std::time::Instant::now();
         for server in servers.values_mut() {
-            while let Err(error) = server.connect().and_then(|mut c| Ok(c.status()?)) {
-                server.assert_alive();
-                if started.elapsed() >= TIMEOUT {
-                    return Err(error);
-                }
-                std::thread::sleep(Duration::from_millis(200));
-            }
+            server.wait_ready(started)?;
+        }
+
+        Ok(Self { servers, ports, config: config.clone(), dir })
+    }
+
+    /// Returns the cluster's temporary directory.
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// Starts a stopped node with its existing data and the given extra
+    /// command-line arguments, and waits for it to be ready.
+    pub fn start(&mut self, id: NodeID, args: &[String]) -> Result<(), Box<dyn Error>> {
+        if self.servers.contains_key(&id) {
+            return Err(format!("node {id} already running").into());
         }
+        if !self.ports.contains_key(&id) {
+            return Err(format!("unknown node {id}").into());
+        }
+        let dir = self.dir.path().join(format!("toydb{id}"));
+        let mut server = TestServer::run(id, &dir, &self.ports, &self.config, args)?;
+        server.wait_ready(std::time::Instant::now())?;
+        self.servers.insert(id, server);
+        Ok(())
+    }
 
-        Ok(Self { servers, dir })
+    /// Returns the IDs of the running nodes, in order.
+    pub fn ids(&self) -> Vec<NodeID> {
+        self.servers.keys().copied().collect()
     }
 
-    /// Connects to a random cluster node using a Rust client. Testing with
-    /// toysql is too annoying, since we have to deal with rustyline, PTYs,
+    /// Connects to a random running cluster node using a Rust client. Testing
+    /// with toysql is too annoying, since we have to deal with rustyline, PTYs,
     /// echoing, multiline editing, etc.
     pub fn connect(&self) -> Result<Client, Box<dyn Error>> {
-        let id = rand::thread_rng().gen_range(1..=self.servers.len()) as NodeID;
-        self.servers.get(&id).unwrap().connect()
+        let index = rand::thread_rng().gen_range(0..self.servers.len());
+        self.servers.values().nth(index).ok_or("no running nodes")?.connect()
+    }
+
+    /// Connects to the given node with the addresses of all running nodes, such
+    /// that the client fails over to the next node (by ID) if it fails.
+    pub fn connect_failover(&self, id: NodeID) -> Result<Client, Box<dyn Error>> {
+        let (before, after): (Vec<_>, Vec<_>) =
+            self.servers.values().partition(|server| server.id < id);
+        let addrs = after.into_iter().chain(before).map(|server| ("localhost", server.sql_port));
+        Ok(Client::connect_cluster(addrs)?)
+    }
+
+    /// Connects a balanced client to all running nodes, see BalancedClient.
+    pub fn connect_balanced(
+        &self,
+        balance: Balance,
+        max_staleness: Option<u8>,
+    ) -> Result<BalancedClient, Box<dyn Error>> {
+        let addrs = self.servers.values().map(|server| ("localhost", server.sql_port));
+        Ok(BalancedClient::connect(addrs, balance, max_staleness)?)
+    }
+
+    /// Connects to the given node via its Unix domain socket.
+    pub fn connect_unix(&self, id: NodeID) -> Result<Client, Box<dyn Error>> {
+        let server = self.servers.get(&id).ok_or(format!("unknown node {id}"))?;
+        Ok(Client::connect_unix(&server.socket)?)
+    }
+
+    /// Shuts down the given node gracefully via SIGTERM, and waits for it to
+    /// exit. Returns its exit status.
+    pub fn stop(&mut self, id: NodeID) -> Result<std::process::ExitStatus, Box<dyn Error>> {
+        let server = self.servers.remove(&id).ok_or(format!("unknown node {id}"))?;
+        server.stop()
+    }
+
+    /// Sends an HTTP request to the given node, using basic authentication if
+    /// credentials are given. Returns the status line and body.
+    pub fn http(
+        &self,
+        id: NodeID,
+        method: &str,
+        path: &str,
+        body: &str,
+        credentials: Option<(&str, &str)>,
+    ) -> Result<(String, String), Box<dyn Error>> {
+        let server = self.servers.get(&id).ok_or(format!("unknown node {id}"))?;
+        let mut stream = std::net::TcpStream::connect(("localhost", server.http_port))?;
+        let mut request = format!("{method} {path} HTTP/1.1\r\nHost: localhost\r\n");
+        if let Some((user, password)) = credentials {
+            let encoded =
+                base64::engine::general_purpose::STANDARD.encode(format!("{user}:{password}"));
+            write!(request, "Authorization: Basic {encoded}\r\n")?;
+        }
+        write!(request, "Content-Length: {}\r\n\r\n{body}", body.len())?;
+        stream.write_all(request.as_bytes())?;
+
+        // The server closes the connection after the response.
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        let (head, body) = response.split_once("\r\n\r\n").ok_or("invalid HTTP response")?;
+        let status = head.lines().next().unwrap_or_default();
+        let status = status.strip_prefix("HTTP/1.1 ").ok_or("invalid HTTP status")?;
+        Ok((status.to_string(), body.to_string()))
     }
 }
 
@@ -79,15 +171,23 @@ pub struct TestServer {
     id: NodeID,
     child: std::process::Child,
     sql_port: u16,
+    http_port: u16,
+    socket: PathBuf,
 }
 
 impl TestServer {
     /// Runs a toyDB server.
-    fn run(id: NodeID, dir: &Path, ports: &NodePorts) -> Result<Self, Box<dyn Error>> {
+    fn run(
+        id: NodeID,
+        dir: &Path,
+        ports: &NodePorts,
+        config: &BTreeMap<String, String>,
+        args: &[String],
+    ) -> Result<Self, Box<dyn Error>> {
         // Build and write the configuration file.
         let configfile = dir.join("toydb.yaml");
         std::fs::create_dir_all(dir)?;
-        std::fs::write(&configfile, Self::build_config(id, dir, ports)?)?;
+        std::fs::write(&configfile, Self::build_config(id, dir, ports, config)?)?;
 
         // Build the binary.
         //
@@ -98,30 +198,81 @@ impl TestServer {
         let child = build
             .command()
             .args(["-c", &configfile.to_string_lossy()])
+            .args(args)
             .stdout(std::process::Stdio::null())
             .stderr(std::process::Stdio::null())
             .spawn()?;
 
-        let (_, sql_port) = ports.get(&id).copied().expect("node not in ports");
-        Ok(Self { id, child, sql_port })
+        let (_, sql_port, http_port) = ports.get(&id).copied().expect("node not in ports");
+        let socket = Self::socket_path(dir);
+        Ok(Self { id, child, sql_port, http_port, socket })
     }
 
     /// Generates a config file for the given node.
-    fn build_config(id: NodeID, dir: &Path, ports: &NodePorts) -> Result<String, Box<dyn Error>> {
-        let (raft_port, sql_port) = ports.get(&id).expect("node not in ports");
+    fn build_config(
+        id: NodeID,
+        dir: &Path,
+        ports: &NodePorts,
+        config: &BTreeMap<String, String>,
+    ) -> Result<String, Box<dyn Error>> {
+        let (raft_port, sql_port, http_port) = ports.get(&id).expect("node not in ports");
         let mut cfg = String::new();
         writeln!(cfg, "id: {id}")?;
         writeln!(cfg, "data_dir: {}", dir.to_string_lossy())?;
         writeln!(cfg, "listen_raft: localhost:{raft_port}")?;
         writeln!(cfg, "listen_sql: localhost:{sql_port}")?;
+        writeln!(cfg, "listen_http: localhost:{http_port}")?;
+        writeln!(cfg, "listen_unix: {}", Self::socket_path(dir).to_string_lossy())?;
+        for (key, value) in config {
+            writeln!(cfg, "{key}: {value}")?;
+        }
         writeln!(cfg, "peers: {{")?;
-        for (peer_id, (peer_raft_port, _)) in ports.iter().filter(|(peer, _)| **peer != id) {
+        for (peer_id, (peer_raft_port, _, _)) in ports.iter().filter(|(peer, _)| **peer != id) {
             writeln!(cfg, "  '{peer_id}': localhost:{peer_raft_port},")?;
         }
         writeln!(cfg, "}}")?;
         Ok(cfg)
     }
 
+    /// Returns the Unix domain socket path for a node directory.
+    fn socket_path(dir: &Path) -> PathBuf {
+        dir.join("toydb.sock")
+    }
+
+    /// Sends SIGTERM to the server and waits for it to exit.
+    fn stop(mut self) -> Result<std::process::ExitStatus, Box<dyn Error>> {
+        let pid = self.child.id().to_string();
+        std::process::Command::new("kill").args(["-TERM", &pid]).status()?;
+        let started = std::time::Instant::now();
+        loop {
+            if let Some(status) = self.child.try_wait()? {
+                return Ok(status);
+            }
+            if started.elapsed() >= TIMEOUT {
+                return Err(format!("node {} did not shut down", self.id).into());
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+    }
+
+    /// Waits for the server to be ready, by fetching the server status, until
+    /// TIMEOUT has passed since the given start time. If the server requires
+    /// authentication, e.g. when restarted with existing users, an
+    /// authentication error means it's ready.
+    fn wait_ready(&mut self, started: std::time::Instant) -> Result<(), Box<dyn Error>> {
+        while let Err(error) = self.connect().and_then(|mut c| Ok(c.status()?)) {
+            if error.to_string().contains("authentication required") {
+                return Ok(());
+            }
+            self.assert_alive();
+            if started.elapsed() >= TIMEOUT {
+                return Err(error);
+            }
+            std::thread::sleep(Duration::from_millis(200));
+        }
+        Ok(())
+    }
+
     /// Asserts that the server is still running.
     fn assert_alive(&mut self) {
         if let Some(status) = self.child.try_wait().expect("failed to check exit status") {
@@ -136,8 +287,11 @@ impl TestServer {
 }
 
 impl Drop for TestServer {
-    // Kills the child process when dropped.
+    // Kills the child process when dropped, unless it already exited.
     fn drop(&mut self) {
+        if let Ok(Some(_)) = self.child.try_wait() {
+            return;
+        }
         self.child.kill().expect("failed to kill node");
         self.child.wait().expect("failed to wait for node to terminate");
     }
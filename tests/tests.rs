@@ -10,11 +10,13 @@
 mod testcluster;
 
 use itertools::Itertools as _;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
 use std::fmt::Write as _;
-use std::{collections::HashMap, error::Error};
 use test_each_file::test_each_path;
 use testcluster::TestCluster;
-use toydb::{Client, StatementResult};
+use toydb::sql::types::Value;
+use toydb::{Balance, BalancedClient, Client, PreparedStatement, StatementResult};
 
 // Run goldenscript tests in tests/scripts.
 test_each_path! { in "tests/scripts" => test_goldenscript }
@@ -35,11 +37,20 @@ fn test_goldenscript(path: &std::path::Path) {
 struct Runner {
     cluster: Option<TestCluster>,
     clients: HashMap<String, Client>,
+    balanced: HashMap<String, BalancedClient>,
+    prepared: HashMap<String, PreparedStatement>,
+    page_token: Option<String>,
 }
 
 impl Runner {
     fn new() -> Self {
-        Self { cluster: None, clients: HashMap::new() }
+        Self {
+            cluster: None,
+            clients: HashMap::new(),
+            balanced: HashMap::new(),
+            prepared: HashMap::new(),
+            page_token: None,
+        }
     }
 
     /// Fetches a client for the given prefix, or creates a new one.
@@ -59,6 +70,20 @@ impl Runner {
     fn client_name(prefix: &Option<String>) -> &str {
         prefix.as_deref().unwrap_or_default()
     }
+
+    /// Parses a parameter value: NULL, TRUE, FALSE, a number, or a string.
+    fn parse_value(value: &str) -> Value {
+        match value {
+            "NULL" => Value::Null,
+            "TRUE" => Value::Boolean(true),
+            "FALSE" => Value::Boolean(false),
+            value => value
+                .parse()
+                .map(Value::Integer)
+                .or_else(|_| value.parse().map(Value::Float))
+                .unwrap_or_else(|_| Value::String(value.to_string())),
+        }
+    }
 }
 
 impl goldenscript::Runner for Runner {
@@ -73,21 +98,281 @@ impl goldenscript::Runner for Runner {
             "close" => {
                 command.consume_args().reject_rest()?;
                 let name = Self::client_name(&command.prefix);
-                if self.clients.remove(name).is_none() {
+                if self.clients.remove(name).is_none() && self.balanced.remove(name).is_none() {
                     return Err("no client to close".into());
                 }
                 return Ok(output);
             }
 
-            // cluster nodes=N
+            // authenticate USER PASSWORD
+            "authenticate" => {
+                let mut args = command.consume_args();
+                let user = &args.next_pos().ok_or("user not given")?.value;
+                let password = &args.next_pos().ok_or("password not given")?.value;
+                args.reject_rest()?;
+                self.get_client(&command.prefix)?.authenticate(user, password)?;
+                return Ok(output);
+            }
+
+            // backup DIR
+            //
+            // Backs up the client's node to the given directory, relative to
+            // the cluster's temporary directory.
+            "backup" => {
+                let mut args = command.consume_args();
+                let dir = &args.next_pos().ok_or("directory not given")?.value;
+                args.reject_rest()?;
+                let Some(cluster) = self.cluster.as_ref() else {
+                    return Err("no cluster".into());
+                };
+                let path = cluster.path().join(dir).to_string_lossy().to_string();
+                self.get_client(&command.prefix)?.backup(&path)?;
+                return Ok(output);
+            }
+
+            // cancel [STATEMENT] [after=MS]
+            //
+            // Executes the statement and cancels it after the given delay
+            // (default 100 ms) from a separate thread. Without a statement,
+            // sends a cancel request for the idle session.
+            "cancel" => {
+                let mut args = command.consume_args();
+                let statement = args.next_pos().map(|arg| arg.value.clone());
+                let after = args.lookup_parse("after")?.unwrap_or(100);
+                args.reject_rest()?;
+                let client = self.get_client(&command.prefix)?;
+                let canceller = client.canceller()?;
+                let Some(statement) = statement else {
+                    canceller.cancel()?;
+                    return Ok(output);
+                };
+                let handle = std::thread::spawn(move || {
+                    std::thread::sleep(std::time::Duration::from_millis(after));
+                    canceller.cancel()
+                });
+                let result = client.execute(&statement);
+                handle.join().expect("canceller panicked")?;
+                writeln!(output, "{:?}", result?)?;
+                return Ok(output);
+            }
+
+            // concurrent STATEMENT CLIENT=STATEMENT [after=MS]
+            //
+            // Executes the statement, while the other client executes its
+            // statement after the given delay (default 20 ms) from a separate
+            // thread. Outputs the result of the first statement, e.g. to test
+            // conflicts that are resolved while the statement is retried.
+            "concurrent" => {
+                let mut args = command.consume_args();
+                let statement = args.next_pos().ok_or("statement not given")?.value.clone();
+                let after = args.lookup_parse("after")?.unwrap_or(20);
+                let other = args.next_key().ok_or("concurrent client not given")?;
+                let other_name = other.key.clone().expect("no key");
+                let other_statement = other.value.clone();
+                args.reject_rest()?;
+                if other_name == Self::client_name(&command.prefix) {
+                    return Err("concurrent client must differ".into());
+                }
+                self.get_client(&Some(other_name.clone()))?;
+                let mut other_client = self.clients.remove(&other_name).expect("no client");
+                let handle = std::thread::spawn(move || {
+                    std::thread::sleep(std::time::Duration::from_millis(after));
+                    let result = other_client.execute(&other_statement);
+                    (other_client, result)
+                });
+                let result = self.get_client(&command.prefix)?.execute(&statement);
+                let (other_client, other_result) = handle.join().expect("client panicked");
+                self.clients.insert(other_name, other_client);
+                other_result?;
+                writeln!(output, "{:?}", result?)?;
+                return Ok(output);
+            }
+
+            // changes TABLE... [from=VERSION] [limit=N]
+            //
+            // Fetches the row changes of the given tables via Client::changes(),
+            // and outputs them followed by the resolved version. With a limit,
+            // instead streams the given number of changes via
+            // Client::change_stream(), waiting for them if necessary.
+            "changes" => {
+                let mut args = command.consume_args();
+                let tables = args.rest_pos().into_iter().map(|a| a.value.as_str()).collect_vec();
+                let from = args.lookup_parse("from")?.unwrap_or(0);
+                let limit: Option<usize> = args.lookup_parse("limit")?;
+                args.reject_rest()?;
+                let client = self.get_client(&command.prefix)?;
+                let (changes, resolved) = match limit {
+                    Some(limit) => {
+                        let poll_interval = std::time::Duration::from_millis(10);
+                        let mut stream = client.change_stream(&tables, from, poll_interval);
+                        let changes: Vec<_> = stream.by_ref().take(limit).try_collect()?;
+                        (changes, stream.resolved())
+                    }
+                    None => {
+                        let changes = client.changes(&tables, from)?;
+                        (changes.changes, changes.resolved)
+                    }
+                };
+                for change in changes {
+                    let row = match change.row {
+                        Some(row) => row.into_iter().join(", "),
+                        None => "deleted".to_string(),
+                    };
+                    writeln!(output, "v{} {}[{}] {row}", change.version, change.table, change.id)?;
+                }
+                writeln!(output, "resolved={resolved}")?;
+                return Ok(output);
+            }
+
+            // connect [node=ID] [unix=BOOL] [failover=BOOL]
+            // connect balance=round-robin|latency [max_staleness=N]
+            //
+            // Connects a new client, replacing any existing client with the
+            // same prefix. With unix=true, connects via the node's Unix domain
+            // socket instead of TCP. With failover=true, connects to the node
+            // with the addresses of all nodes, to fail over to. With balance,
+            // connects a BalancedClient to all nodes, and SQL statements can
+            // use the [route] tag to output the role of the executing node.
+            "connect" => {
+                let mut args = command.consume_args();
+                let node = args.lookup_parse("node")?.unwrap_or(1);
+                let unix = args.lookup_parse("unix")?.unwrap_or(false);
+                let failover = args.lookup_parse("failover")?.unwrap_or(false);
+                let balance = args.lookup("balance").map(|arg| arg.value.clone());
+                let max_staleness = args.lookup_parse("max_staleness")?;
+                args.reject_rest()?;
+                let Some(cluster) = self.cluster.as_ref() else {
+                    return Err("no cluster".into());
+                };
+                let name = Self::client_name(&command.prefix).to_string();
+                self.clients.remove(&name);
+                self.balanced.remove(&name);
+                if let Some(balance) = balance {
+                    let balance = match balance.as_str() {
+                        "round-robin" => Balance::RoundRobin,
+                        "latency" => Balance::Latency,
+                        balance => return Err(format!("invalid balance {balance}").into()),
+                    };
+                    let client = cluster.connect_balanced(balance, max_staleness)?;
+                    self.balanced.insert(name, client);
+                    return Ok(output);
+                }
+                let client = match (unix, failover) {
+                    (true, _) => cluster.connect_unix(node)?,
+                    (false, true) => cluster.connect_failover(node)?,
+                    (false, false) => cluster.connect()?,
+                };
+                self.clients.insert(name, client);
+                return Ok(output);
+            }
+
+            // cluster nodes=N [SETTING=VALUE...]
+            //
+            // Any other settings are added to the node config files.
             "cluster" => {
                 let mut args = command.consume_args();
                 let nodes = args.lookup_parse("nodes")?.unwrap_or(0);
+                let config: BTreeMap<String, String> = args
+                    .rest_key()
+                    .into_iter()
+                    .map(|arg| (arg.name().to_string(), arg.value.clone()))
+                    .collect();
                 args.reject_rest()?;
                 if self.cluster.is_some() {
                     return Err("cluster already exists".into());
                 }
-                self.cluster = Some(TestCluster::run(nodes)?);
+                self.cluster = Some(TestCluster::run(nodes, &config)?);
+                return Ok(output);
+            }
+
+            // execute NAME [PARAM...]
+            //
+            // Executes a prepared statement with the given parameter values,
+            // see parse_value(). Outputs the rows of SELECT queries, and the
+            // result of other statements.
+            "execute" => {
+                let mut args = command.consume_args();
+                let name = &args.next_pos().ok_or("prepared statement not given")?.value;
+                let params = args
+                    .rest_pos()
+                    .into_iter()
+                    .map(|arg| Self::parse_value(&arg.value))
+                    .collect_vec();
+                args.reject_rest()?;
+                let statement =
+                    self.prepared.get(name).ok_or("unknown prepared statement")?.clone();
+                match statement.execute(self.get_client(&command.prefix)?, &params)? {
+                    StatementResult::Select { rows, .. } => {
+                        for row in rows {
+                            writeln!(output, "{}", row.into_iter().join(", "))?;
+                        }
+                    }
+                    result => writeln!(output, "{result:?}")?,
+                }
+                return Ok(output);
+            }
+
+            // http METHOD PATH [BODY] [node=ID] [user=USER password=PASSWORD]
+            "http" => {
+                let mut args = command.consume_args();
+                let method = &args.next_pos().ok_or("method not given")?.value;
+                let path = &args.next_pos().ok_or("path not given")?.value;
+                let body = args.next_pos().map(|arg| arg.value.as_str()).unwrap_or_default();
+                let node = args.lookup_parse("node")?.unwrap_or(1);
+                let user = args.lookup("user").map(|arg| arg.value.as_str());
+                let password = args.lookup("password").map(|arg| arg.value.as_str());
+                args.reject_rest()?;
+                let Some(cluster) = self.cluster.as_ref() else {
+                    return Err("no cluster".into());
+                };
+                let credentials = user.zip(password);
+                let (status, body) = cluster.http(node, method, path, body, credentials)?;
+                let body: serde_json::Value = serde_json::from_str(&body)?;
+                writeln!(output, "{status}")?;
+                write!(output, "{}", serde_json::to_string_pretty(&body)?)?;
+                return Ok(output);
+            }
+
+            // page TABLE KEY... [limit=N] [next=BOOL] [token=TOKEN]
+            //
+            // Fetches a page of table rows ordered by the given keys via
+            // Client::page(), and outputs the rows and next page token. With
+            // next=true, fetches the page after the previously fetched page.
+            "page" => {
+                let mut args = command.consume_args();
+                let limit = args.lookup_parse("limit")?.unwrap_or(10);
+                let next = args.lookup_parse("next")?.unwrap_or(false);
+                let mut token = args.lookup("token").map(|arg| arg.value.clone());
+                let table = args.next_pos().ok_or("table not given")?.value.clone();
+                let keys: Vec<String> = args.rest_pos().iter().map(|a| a.value.clone()).collect();
+                args.reject_rest()?;
+                if next {
+                    token = self.page_token.clone();
+                }
+                let keys: Vec<&str> = keys.iter().map(|key| key.as_str()).collect();
+                let page = self.get_client(&command.prefix)?.page(
+                    &table,
+                    &keys,
+                    limit,
+                    token.as_deref(),
+                )?;
+                for row in page.rows {
+                    writeln!(output, "{}", row.into_iter().join(", "))?;
+                }
+                writeln!(output, "next: {}", page.next.as_deref().unwrap_or("none"))?;
+                self.page_token = page.next;
+                return Ok(output);
+            }
+
+            // prepare NAME STATEMENT
+            "prepare" => {
+                let mut args = command.consume_args();
+                let name = args.next_pos().ok_or("name not given")?.value.clone();
+                let statement = &args.next_pos().ok_or("statement not given")?.value;
+                args.reject_rest()?;
+                let prepared = self.get_client(&command.prefix)?.prepare(statement)?;
+                writeln!(output, "{} parameters", prepared.parameters())?;
+                self.prepared.insert(name, prepared);
                 return Ok(output);
             }
 
@@ -99,6 +384,62 @@ impl goldenscript::Runner for Runner {
                 return Ok(output);
             }
 
+            // start ID [restore=DIR]
+            //
+            // Starts a stopped node, optionally restoring it from a backup
+            // directory written by the backup command.
+            "start" => {
+                let mut args = command.consume_args();
+                let id = args.next_pos().ok_or("node ID not given")?.parse()?;
+                let restore = args.lookup("restore").map(|arg| arg.value.clone());
+                args.reject_rest()?;
+                let Some(cluster) = self.cluster.as_mut() else {
+                    return Err("no cluster".into());
+                };
+                let mut extra = Vec::new();
+                if let Some(dir) = restore {
+                    extra.push("--restore".to_string());
+                    extra.push(cluster.path().join(dir).to_string_lossy().to_string());
+                }
+                cluster.start(id, &extra)?;
+                return Ok(output);
+            }
+
+            // stop ID
+            //
+            // Shuts down the given node gracefully, and outputs its exit status.
+            "stop" => {
+                let mut args = command.consume_args();
+                let id = args.next_pos().ok_or("node ID not given")?.parse()?;
+                args.reject_rest()?;
+                let Some(cluster) = self.cluster.as_mut() else {
+                    return Err("no cluster".into());
+                };
+                let status = cluster.stop(id)?;
+                writeln!(output, "n{id} {status}")?;
+                return Ok(output);
+            }
+
+            // stream QUERY [limit=N] [header=BOOL]
+            //
+            // Executes a query via Client::query_iter(), streaming the rows.
+            // With a limit, stops reading after the given number of rows.
+            "stream" => {
+                let mut args = command.consume_args();
+                let query = &args.next_pos().ok_or("query not given")?.value;
+                let limit = args.lookup_parse("limit")?.unwrap_or(usize::MAX);
+                let header = args.lookup_parse("header")?.unwrap_or(false);
+                args.reject_rest()?;
+                let rows = self.get_client(&command.prefix)?.query_iter(query)?;
+                if header {
+                    writeln!(output, "{}", rows.columns().iter().join(", "))?;
+                }
+                for row in rows.take(limit) {
+                    writeln!(output, "{}", row?.into_iter().join(", "))?;
+                }
+                return Ok(output);
+            }
+
             // table [TABLE]
             "table" => {
                 let mut args = command.consume_args();
@@ -114,6 +455,43 @@ impl goldenscript::Runner for Runner {
                 return Ok(output);
             }
 
+            // transfer ID
+            //
+            // Transfers leadership from the current leader to the given node,
+            // and waits for all nodes to route requests to it.
+            "transfer" => {
+                let mut args = command.consume_args();
+                let to = args.next_pos().ok_or("node ID not given")?.parse()?;
+                args.reject_rest()?;
+                let Some(cluster) = self.cluster.as_ref() else {
+                    return Err("no cluster".into());
+                };
+                let leader = cluster.connect()?.status()?.raft.leader;
+                if leader != to {
+                    let mut client = cluster.connect_failover(leader)?;
+                    if client.transfer_leadership(Some(to))? != Some(to) {
+                        return Err(format!("failed to transfer leadership to n{to}").into());
+                    }
+                }
+                // Status requests are aborted while the new leader is elected,
+                // or if the node hasn't heard from it yet.
+                let started = std::time::Instant::now();
+                for id in cluster.ids() {
+                    loop {
+                        let status = cluster.connect_failover(id)?.status();
+                        if status.is_ok_and(|status| status.raft.leader == to) {
+                            break;
+                        }
+                        if started.elapsed() > std::time::Duration::from_secs(10) {
+                            return Err(format!("n{to} did not become leader").into());
+                        }
+                        std::thread::sleep(std::time::Duration::from_millis(100));
+                    }
+                }
+                writeln!(output, "n{to} is leader")?;
+                return Ok(output);
+            }
+
             // tables
             "tables" => {
                 command.consume_args().reject_rest()?;
@@ -131,12 +509,21 @@ impl goldenscript::Runner for Runner {
         if !command.args.is_empty() {
             return Err("statements should be given as a command with no args".into());
         }
-        let client = self.get_client(&command.prefix)?;
         let input = &command.name;
 
         // Execute the command and display the result if requested.
-        // SELECT and EXPLAIN results are always output.
-        let result = client.execute(input)?;
+        // SELECT and EXPLAIN results are always output. Balanced clients
+        // output the role of the executing node with the route tag.
+        let result = match self.balanced.get_mut(Self::client_name(&command.prefix)) {
+            Some(client) => {
+                let result = client.execute(input)?;
+                if tags.remove("route") {
+                    writeln!(output, "{}", client.last_role().expect("no role"))?;
+                }
+                result
+            }
+            None => self.get_client(&command.prefix)?.execute(input)?,
+        };
 
         match result {
             StatementResult::Select { columns, rows } => {
@@ -147,7 +534,9 @@ impl goldenscript::Runner for Runner {
                     writeln!(output, "{}", row.into_iter().join(", "))?;
                 }
             }
-            StatementResult::Explain(root) => writeln!(output, "{root}")?,
+            StatementResult::Explain(plan, format, analysis) => {
+                writeln!(output, "{}", plan.explain(format, analysis.as_ref()))?
+            }
             result if tags.remove("result") => writeln!(output, "{result:?}")?,
             _ => {}
         }